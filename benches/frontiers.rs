@@ -0,0 +1,119 @@
+// Preconsensus with ledger-sized frontier sets, to find where construction, hashing, reconciliation and transfer
+// stop being negligible: cargo bench --bench frontiers
+use std::{hint::black_box, sync::mpsc::channel};
+use arquipelago::{decode_frontiers, Message, PreProposal, Proposal};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+
+const SIZES: [u64; 3] = [10_000, 50_000, 100_000];
+
+fn frontiers(count: u64, offset: u64) -> Vec<BlockHash> {
+    (offset..offset + count).map(|value| BlockHash::from(value.wrapping_mul(0x9E37_79B9_7F4A_7C15))).collect()
+}
+
+// Hashes spread over the whole range like the frontiers of a real ledger, which the multiples above are not
+fn ledger_frontiers(count: u64) -> Vec<BlockHash> {
+    (0..count).map(|value| Blake2HashBuilder::new().update(value.to_le_bytes()).build()).collect()
+}
+
+// 2f+1 preproposals of a committee of 4, each sharing half of its frontiers with the next one
+fn quorum(size: u64) -> Vec<PreProposal> {
+    (0..3).map(|id| PreProposal::new(frontiers(size, id as u64 * size / 2), id)).collect()
+}
+
+fn construction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("preproposal");
+
+    for size in SIZES {
+        group.throughput(Throughput::Elements(size));
+
+        // Sorting and hashing the whole set
+        group.bench_with_input(BenchmarkId::new("new", size), &size, |b, &size| {
+            b.iter_batched(|| frontiers(size, 0), |frontiers| PreProposal::new(frontiers, 0).hash(), BatchSize::LargeInput)
+        });
+
+        // Inserting is a tree insertion, the set is rehashed once when its hash is next asked for
+        group.bench_with_input(BenchmarkId::new("insert", size), &size, |b, &size| {
+            b.iter_batched(|| PreProposal::new(frontiers(size, 0), 0), |mut preproposal| preproposal.insert(BlockHash::from(u64::MAX)), BatchSize::LargeInput)
+        });
+
+        group.bench_with_input(BenchmarkId::new("extend_10_percent", size), &size, |b, &size| {
+            b.iter_batched(
+                || (PreProposal::new(frontiers(size, 0), 0), frontiers(size / 10, size)),
+                |(mut preproposal, new)| {
+                    preproposal.extend(new);
+                    preproposal.hash()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn reconciliation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("proposal");
+
+    for size in SIZES {
+        let preproposals = quorum(size);
+        let proposal = Proposal::create_proposal(preproposals.clone(), 0);
+        group.throughput(Throughput::Elements(size * 3));
+
+        group.bench_with_input(BenchmarkId::new("create", size), &preproposals, |b, preproposals| {
+            b.iter_batched(|| preproposals.clone(), |preproposals| Proposal::create_proposal(preproposals, 0), BatchSize::LargeInput)
+        });
+
+        // Union of the frontiers of the decided proposal, which every node computes once it commits
+        group.bench_with_input(BenchmarkId::new("frontiers", size), &preproposals, |b, preproposals| {
+            b.iter(|| proposal.frontiers(black_box(preproposals), 1))
+        });
+    }
+    group.finish();
+}
+
+// Preproposals are cloned once per peer by the outbox, before being handed to the link
+fn transfer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transfer");
+
+    for size in SIZES {
+        let preproposal = PreProposal::new(frontiers(size, 0), 0);
+        group.throughput(Throughput::Elements(size));
+
+        group.bench_with_input(BenchmarkId::new("send_to_3_peers", size), &preproposal, |b, preproposal| {
+            let links: Vec<_> = (0..3).map(|_| channel()).collect();
+
+            b.iter(|| {
+                for (sender, receiver) in &links {
+                    sender.send(Message::PreProposal(preproposal.clone())).unwrap();
+                    black_box(receiver.recv().unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// Prefix-compressed frontiers, the sizes are printed once per set since criterion only reports timings
+fn encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encoding");
+
+    for size in SIZES {
+        for (name, preproposal) in [("ledger", PreProposal::new(ledger_frontiers(size), 0)), ("clustered", PreProposal::new(frontiers(size, 0), 0))] {
+            let encoded = preproposal.encoded_frontiers();
+            let raw = preproposal.len() * 32;
+            println!("encoding/{}/{}: {} bytes raw, {} encoded ({:.1}%)", name, size, raw, encoded.len(), 100.0 * encoded.len() as f64 / raw as f64);
+            group.throughput(Throughput::Elements(size));
+
+            group.bench_with_input(BenchmarkId::new(format!("encode_{}", name), size), &preproposal, |b, preproposal| {
+                b.iter(|| preproposal.encoded_frontiers())
+            });
+            group.bench_with_input(BenchmarkId::new(format!("decode_{}", name), size), &encoded, |b, encoded| {
+                b.iter(|| decode_frontiers(black_box(encoded)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, construction, reconciliation, transfer, encoding);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+use arquipelago::{encode_vectors, vectors};
+
+// Prints the conformance vectors, redirect the output to vectors/conformance.txt to regenerate them
+fn main() {
+    print!("{}", encode_vectors(&vectors()));
+}
@@ -0,0 +1,17 @@
+// Measures how latency, message counts and memory grow with the size of the committee, on the simulator
+// cargo run --release --features scalability --example scalability -- [seeds] [sizes...] > scalability.csv
+use arquipelago::{sweep, SimConfig, CSV_HEADER};
+
+fn main() {
+    let mut args = std::env::args().skip(1).map(|arg| arg.parse::<usize>().expect("arguments must be numbers"));
+    let seeds = args.next().unwrap_or(3) as u64;
+    let sizes: Vec<usize> = args.collect();
+    let sizes = if sizes.is_empty() { vec![4, 10, 20, 40, 70, 100] } else { sizes };
+
+    println!("{}", CSV_HEADER);
+    for &nodes in &sizes {
+        for row in sweep(&[nodes], seeds, SimConfig::default()) {
+            println!("{}", row.csv());
+        }
+    }
+}
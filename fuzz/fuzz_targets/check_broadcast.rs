@@ -0,0 +1,67 @@
+#![no_main]
+
+mod messages;
+
+use std::sync::{mpsc::channel, Arc};
+use arbitrary::{Result, Unstructured};
+use arquipelago::{Broadcast, Config, Core, Message, Outbox, PeerQueues, Response, State, Step};
+use libfuzzer_sys::fuzz_target;
+
+// Lines 73-87: builds broadcasts whose certificates answer the previous step, with arbitrary states and
+// arbitrary corruptions, so that certificate validation runs on inputs that are almost valid
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let Ok(f) = u.int_in_range(1..=2usize) else {
+        return;
+    };
+
+    let (senders, _receivers): (Vec<_>, Vec<_>) = (0..3 * f + 1).map(|_| channel()).unzip();
+    let mut outbox = Outbox::new(PeerQueues::direct(senders), Config::default().batch);
+    let mut core = Core::new(0, f, false, Config::default());
+
+    while let Ok(broadcast) = justified_broadcast(&mut u, f) {
+        // The same broadcast may arrive several times, which exercises the count of responses that already answered it
+        let repeat = u.int_in_range(1..=2).unwrap_or(1);
+
+        for _ in 0..repeat {
+            core.handle(Message::Broadcast(broadcast.clone()), &mut outbox);
+        }
+
+        if u.is_empty() {
+            break;
+        }
+    }
+    outbox.flush();
+});
+
+fn justified_broadcast(u: &mut Unstructured, f: usize) -> Result<Broadcast> {
+    let step = messages::step(u)?;
+    let rank = messages::rank(u)?;
+    let (previous_step, previous_rank) = match step {
+        Step::R => (Step::B, rank.wrapping_sub(1)),
+        Step::A => (Step::R, rank),
+        Step::B => (Step::A, rank),
+    };
+
+    // Usually 2f+1 responses, sometimes one too few or one too many
+    let len = (2 * f + 1).saturating_add_signed(u.int_in_range(-1..=1)?);
+    let mut responses = Vec::with_capacity(len);
+
+    for sender in 0..len {
+        let answered = Arc::new(Broadcast::new(messages::id(u)?, previous_step, messages::value(u)?, u.arbitrary()?, previous_rank, None));
+        let states = (0..u.int_in_range(1..=2)?)
+            .map(|_| Ok(State::new(messages::state_value(u, previous_step)?, answered.clone())))
+            .collect::<Result<Vec<_>>>()?;
+        let mut response = Response::new(sender as i64, previous_step, previous_rank, states);
+
+        if u.ratio(1, 16)? {
+            response = messages::response(u, 1)?;
+        }
+        responses.push(response);
+    }
+
+    let flag = if step == Step::B { Some(u.arbitrary()?) } else { None };
+    let flag = if u.ratio(1, 16)? { u.arbitrary()? } else { flag };
+
+    Ok(Broadcast::new(messages::id(u)?, step, messages::value(u)?, flag, rank, Some(responses.into())))
+}
@@ -0,0 +1,11 @@
+#![no_main]
+
+use arquipelago::{decode_message, encode_message};
+use libfuzzer_sys::fuzz_target;
+
+// Arbitrary bytes must decode or fail without panicking, and whatever decodes must survive encoding again
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = decode_message(data) {
+        assert_eq!(decode_message(&encode_message(&message)), Ok(message));
+    }
+});
@@ -0,0 +1,40 @@
+#![no_main]
+
+mod messages;
+
+use std::sync::mpsc::channel;
+use arbitrary::Unstructured;
+use arquipelago::{Config, Core, Outbox, PeerQueues};
+use libfuzzer_sys::fuzz_target;
+
+const NODES: usize = 4;
+
+// Feeds a sequence of arbitrary messages to the run loop of a process, none of them may panic it
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let Ok(config) = config(&mut u) else {
+        return;
+    };
+
+    let (senders, _receivers): (Vec<_>, Vec<_>) = (0..NODES).map(|_| channel()).unzip();
+    let mut outbox = Outbox::new(PeerQueues::direct(senders), config.batch);
+    let mut core = Core::new(0, 1, false, config);
+
+    while let Ok(message) = messages::message(&mut u, 2) {
+        core.handle(message, &mut outbox);
+
+        if u.is_empty() {
+            break;
+        }
+    }
+    outbox.flush();
+});
+
+fn config(u: &mut Unstructured) -> arbitrary::Result<Config> {
+    Ok(Config {
+        certificates_by_reference: u.arbitrary()?,
+        // Small budgets make the run loop evict state while messages keep arriving
+        memory_budget: u.arbitrary::<Option<u16>>()?.map(usize::from),
+        ..Config::default()
+    })
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use arbitrary::{Result, Unstructured};
+use arquipelago::{AValue, BValue, Broadcast, CertificateResponses, CommitRecord, Id, Message, PreProposal, Proposal, ProposalHash, RValue, Rank, Response, State, Step, Value, Vote};
+use rsnano_core::BlockHash;
+
+// Senders, ranks and values are mostly drawn from small ranges, so that arbitrary certificates regularly
+// agree with the broadcasts they justify and reach the tallies instead of failing the first check
+pub fn id(u: &mut Unstructured) -> Result<Id> {
+    u.int_in_range(-1..=4)
+}
+
+pub fn rank(u: &mut Unstructured) -> Result<Rank> {
+    if u.ratio(1, 16)? {
+        u.arbitrary()
+    } else {
+        u.int_in_range(0..=3)
+    }
+}
+
+pub fn value(u: &mut Unstructured) -> Result<ProposalHash> {
+    Ok(BlockHash::from(u.int_in_range(0..=3u64)?))
+}
+
+pub fn step(u: &mut Unstructured) -> Result<Step> {
+    u.choose(&[Step::R, Step::A, Step::B]).copied()
+}
+
+pub fn state_value(u: &mut Unstructured, step: Step) -> Result<Value> {
+    Ok(match step {
+        Step::R => Value::RValue(RValue::new(rank(u)?, value(u)?)),
+        Step::A => Value::AValue(AValue(value(u)?)),
+        Step::B => Value::BValue(BValue::new(value(u)?, u.arbitrary()?)),
+    })
+}
+
+// Certificates nest broadcasts, `depth` bounds the recursion
+pub fn broadcast(u: &mut Unstructured, depth: usize) -> Result<Broadcast> {
+    let certificate = if depth > 0 && u.arbitrary()? {
+        Some(certificate(u, depth - 1)?)
+    } else {
+        None
+    };
+    let broadcast = Broadcast::new(id(u)?, step(u)?, value(u)?, u.arbitrary()?, rank(u)?, certificate);
+
+    if u.ratio(1, 8)? {
+        Ok(broadcast.into_reference())
+    } else {
+        Ok(broadcast)
+    }
+}
+
+pub fn certificate(u: &mut Unstructured, depth: usize) -> Result<CertificateResponses> {
+    let len = u.int_in_range(0..=14)?;
+    (0..len).map(|_| response(u, depth)).collect()
+}
+
+pub fn response(u: &mut Unstructured, depth: usize) -> Result<Response> {
+    let len = u.int_in_range(0..=3)?;
+    let states: Vec<State> = (0..len)
+        .map(|_| {
+            let step = step(u)?;
+            let value = state_value(u, step)?;
+            Ok(State::new(value, Arc::new(broadcast(u, depth)?)))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Response::new(id(u)?, step(u)?, rank(u)?, states))
+}
+
+fn values(u: &mut Unstructured) -> Result<Vec<BlockHash>> {
+    let len = u.int_in_range(0..=4)?;
+    (0..len).map(|_| value(u)).collect()
+}
+
+pub fn message(u: &mut Unstructured, depth: usize) -> Result<Message> {
+    Ok(match u.int_in_range(0..=10)? {
+        0 => Message::Broadcast(broadcast(u, depth)?),
+        1 => Message::Response(response(u, depth)?),
+        2 => Message::PreProposal(PreProposal::new(values(u)?, id(u)?)),
+        3 => Message::Proposal(Proposal::new(values(u)?, id(u)?)),
+        4 => {
+            let len = u.int_in_range(0..=4)?;
+            Message::Batch((0..len).map(|_| message(u, 0)).collect::<Result<_>>()?)
+        }
+        5 => Message::GetResponses(id(u)?, u.arbitrary()?),
+        6 => Message::Vote(Vote { voter: id(u)?, key: u.arbitrary()?, timestamp: u.arbitrary()?, hashes: values(u)?, signature: u.arbitrary()? }),
+        7 => Message::Announce(id(u)?, u.arbitrary()?),
+        8 => {
+            let len = u.int_in_range(0..=2)?;
+            let commits = (0..len)
+                .map(|_| Ok(CommitRecord { instance: u.int_in_range(0..=2)?, rank: rank(u)?, value: value(u)?, certificate: certificate(u, depth)? }))
+                .collect::<Result<_>>()?;
+            Message::Commits(id(u)?, commits)
+        }
+        9 => Message::Instance(u.int_in_range(0..=3)?, Box::new(message(u, depth)?)),
+        10 => Message::VoteHashes(id(u)?, values(u)?),
+        11 => Message::GetVotes(id(u)?, values(u)?),
+        12 => Message::PreProposalDigest(id(u)?, values(u)?),
+        13 => Message::GetPreProposals(id(u)?, values(u)?),
+        _ => {
+            let len = u.int_in_range(0..=4)?;
+            Message::Responses(id(u)?, (0..len).map(|_| response(u, depth)).collect::<Result<_>>()?)
+        }
+    })
+}
@@ -0,0 +1,65 @@
+#![no_main]
+
+use arbitrary::Unstructured;
+use arquipelago::{max_faults, BatchConfig, Config, Id, PreProposal, SimConfig, Simulation};
+use libfuzzer_sys::fuzz_target;
+use rsnano_core::BlockHash;
+
+// Runs a committee on the simulator with the fuzzer choosing which message is delivered next, so that every
+// asynchronous schedule is reachable from the input. Built with debug assertions, the simulator checks after
+// each delivery that correct nodes never decide different values and that proposers never go back to a lower rank
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let Ok(config) = config(&mut u) else {
+        return;
+    };
+    let Ok(preproposals) = preproposals(&mut u, config.nodes) else {
+        return;
+    };
+
+    let mut simulation = Simulation::new(config, preproposals);
+
+    while simulation.pending() > 0 && !u.is_empty() {
+        let Ok(index) = u.choose_index(simulation.pending()) else {
+            break;
+        };
+        simulation.step_pending(index);
+    }
+
+    // Once the input runs out the remaining messages are delivered in latency order
+    let outcome = simulation.run();
+    assert!(outcome.agreement(), "{:?}", outcome);
+
+    let proposals = simulation.proposals();
+    assert!(outcome.decisions.iter().flatten().all(|decision| proposals.contains(decision)), "{:?}", outcome);
+});
+
+fn config(u: &mut Unstructured) -> arbitrary::Result<SimConfig> {
+    let nodes = u.int_in_range(4..=7)?;
+
+    Ok(SimConfig {
+        nodes,
+        f: max_faults(nodes),
+        seed: u.arbitrary()?,
+        time_limit: 1_000_000,
+        config: Config {
+            batch: BatchConfig::disabled(),
+            certificates_by_reference: u.arbitrary()?,
+            forced_adopt_ranks: u.int_in_range(0..=2)?,
+            ..Config::default()
+        },
+        ..SimConfig::default()
+    })
+}
+
+// A few distinct frontiers, so that nodes propose overlapping but different sets
+fn preproposals(u: &mut Unstructured, nodes: usize) -> arbitrary::Result<Vec<PreProposal>> {
+    (0..nodes)
+        .map(|id| {
+            let frontiers = (0..u.int_in_range(1..=3)?)
+                .map(|_| Ok(BlockHash::from(u.int_in_range(0..=7u64)?)))
+                .collect::<arbitrary::Result<_>>()?;
+            Ok(PreProposal::new(frontiers, id as Id))
+        })
+        .collect()
+}
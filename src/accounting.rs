@@ -0,0 +1,130 @@
+use std::{collections::BTreeMap, ops::AddAssign, sync::{Arc, Mutex}};
+use crate::{Id, Offense, Priority, Suspicion};
+
+// Traffic exchanged with one peer, as seen by a process
+// Received messages are attributed to their sender field, which nothing authenticates yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PeerStats {
+    pub sent_messages: usize,
+    pub sent_bytes: usize,
+    pub received_messages: usize,
+    pub received_bytes: usize,
+    // Messages that failed validation, including broadcasts whose certificate does not check
+    pub invalid: usize,
+    // Preproposals, proposals, broadcasts and responses received again, which are ignored
+    pub duplicates: usize,
+    // Messages to the peer dropped because its outbound queue was full, the only limit on a peer's rate so far
+    pub rate_limited: usize,
+    // The same, per priority of the dropped message (Priority as index)
+    pub shed: [usize; 4],
+    // Pending responses of the peer evicted because too many were waiting for a quorum, mostly its own
+    pub pending_evicted: usize,
+}
+
+impl AddAssign for PeerStats {
+    fn add_assign(&mut self, other: PeerStats) {
+        self.sent_messages += other.sent_messages;
+        self.sent_bytes += other.sent_bytes;
+        self.received_messages += other.received_messages;
+        self.received_bytes += other.received_bytes;
+        self.invalid += other.invalid;
+        self.duplicates += other.duplicates;
+        self.rate_limited += other.rate_limited;
+        for (shed, other) in self.shed.iter_mut().zip(other.shed) {
+            *shed += other;
+        }
+        self.pending_evicted += other.pending_evicted;
+    }
+}
+
+// Per-peer counters of a process, updated by its run loop, validation workers and outbound queues
+#[derive(Debug, Default)]
+pub struct PeerAccounting {
+    peers: Mutex<BTreeMap<Id, PeerStats>>,
+    // Invalid messages count against the sender there too
+    suspicion: Option<Arc<Suspicion>>,
+}
+
+impl PeerAccounting {
+    pub fn suspecting(suspicion: Arc<Suspicion>) -> PeerAccounting {
+        PeerAccounting { suspicion: Some(suspicion), ..PeerAccounting::default() }
+    }
+
+    fn update(&self, peer: Id, update: impl FnOnce(&mut PeerStats)) {
+        update(self.peers.lock().unwrap().entry(peer).or_default());
+    }
+
+    pub fn sent(&self, peer: Id, bytes: usize) {
+        self.update(peer, |stats| {
+            stats.sent_messages += 1;
+            stats.sent_bytes += bytes;
+        });
+    }
+
+    pub fn received(&self, peer: Id, bytes: usize) {
+        self.update(peer, |stats| {
+            stats.received_messages += 1;
+            stats.received_bytes += bytes;
+        });
+    }
+
+    pub fn invalid(&self, peer: Id) {
+        self.update(peer, |stats| stats.invalid += 1);
+        if let Some(suspicion) = &self.suspicion {
+            suspicion.record(peer, Offense::Invalid);
+        }
+    }
+
+    pub fn duplicate(&self, peer: Id) {
+        self.update(peer, |stats| stats.duplicates += 1);
+    }
+
+    pub fn rate_limited(&self, peer: Id) {
+        self.update(peer, |stats| stats.rate_limited += 1);
+    }
+
+    // A message to the peer was dropped by the overflow policy of its queue
+    pub fn shed(&self, peer: Id, priority: Priority) {
+        self.update(peer, |stats| {
+            stats.rate_limited += 1;
+            stats.shed[priority as usize] += 1;
+        });
+    }
+
+    pub fn pending_evicted(&self, peer: Id) {
+        self.update(peer, |stats| stats.pending_evicted += 1);
+    }
+
+    pub fn peer(&self, peer: Id) -> PeerStats {
+        self.peers.lock().unwrap().get(&peer).copied().unwrap_or_default()
+    }
+
+    pub fn stats(&self) -> BTreeMap<Id, PeerStats> {
+        self.peers.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_peer() {
+        let accounting = PeerAccounting::default();
+
+        accounting.sent(1, 10);
+        accounting.sent(1, 5);
+        accounting.received(2, 7);
+        accounting.invalid(2);
+        accounting.duplicate(2);
+        accounting.rate_limited(1);
+
+        assert_eq!(accounting.peer(1), PeerStats { sent_messages: 2, sent_bytes: 15, rate_limited: 1, ..PeerStats::default() });
+        assert_eq!(
+            accounting.peer(2),
+            PeerStats { received_messages: 1, received_bytes: 7, invalid: 1, duplicates: 1, ..PeerStats::default() }
+        );
+        assert_eq!(accounting.peer(3), PeerStats::default());
+        assert_eq!(accounting.stats().keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+    }
+}
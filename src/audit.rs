@@ -0,0 +1,236 @@
+use std::{fmt, io, path::Path, time::{SystemTime, UNIX_EPOCH}};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{FileSink, ProposalHash, Rank, Response, Sink, Value};
+
+// Append-only audit log of the commits of a process, one JSON object per line:
+//   {"instance":0,"value":"<hex>","rank":2,"certificate":"<hex>","time_ms":<unix time>,"previous":"<hex>","hash":"<hex>"}
+// Each entry hashes its fields together with the hash of the entry before it (zero for the first one),
+// so editing, removing or reordering entries breaks the chain from there on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    // Position in the log, the n-th commit of the process
+    pub instance: u64,
+    pub value: ProposalHash,
+    pub rank: Rank,
+    // Digest of the B answers the value was committed on
+    pub certificate: BlockHash,
+    pub time_ms: u64,
+    pub previous: BlockHash,
+    pub hash: BlockHash,
+}
+
+// Senders and B pairs of the answers, in the order of the certificate
+pub fn certificate_digest(responses: &[Response]) -> BlockHash {
+    let mut hasher = Blake2HashBuilder::new();
+
+    for response in responses {
+        hasher = hasher.update(response.sender.to_le_bytes()).update(response.rank.to_le_bytes());
+
+        for state in &response.state {
+            if let Value::BValue(pair) = state.value {
+                hasher = hasher.update([pair.flag as u8]).update(pair.value.as_bytes());
+            }
+        }
+    }
+    hasher.build()
+}
+
+impl AuditEntry {
+    pub fn new(previous: Option<&AuditEntry>, value: ProposalHash, rank: Rank, certificate: BlockHash, time_ms: u64) -> AuditEntry {
+        let mut entry = AuditEntry {
+            instance: previous.map_or(0, |previous| previous.instance + 1),
+            value,
+            rank,
+            certificate,
+            time_ms,
+            previous: previous.map_or(BlockHash::zero(), |previous| previous.hash),
+            hash: BlockHash::zero(),
+        };
+        entry.hash = entry.chained_hash();
+        entry
+    }
+
+    fn chained_hash(&self) -> BlockHash {
+        Blake2HashBuilder::new()
+            .update(self.previous.as_bytes())
+            .update(self.instance.to_le_bytes())
+            .update(self.value.as_bytes())
+            .update(self.rank.to_le_bytes())
+            .update(self.certificate.as_bytes())
+            .update(self.time_ms.to_le_bytes())
+            .build()
+    }
+
+    // Only reads the flat objects written by Display, not JSON in general
+    pub fn parse(line: &str) -> Result<AuditEntry, String> {
+        let object = line.trim().strip_prefix('{').and_then(|line| line.strip_suffix('}')).ok_or("not an object")?;
+        let fields: Vec<(&str, &str)> = object
+            .split(',')
+            .map(|field| {
+                let (key, value) = field.split_once(':').ok_or_else(|| format!("invalid field {:?}", field))?;
+                Ok((key.trim().trim_matches('"'), value.trim().trim_matches('"')))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let field = |name: &str| fields.iter().find(|(key, _)| *key == name).map(|(_, value)| *value).ok_or_else(|| format!("missing {}", name));
+        let number = |name: &str| field(name)?.parse().map_err(|_| format!("invalid {}", name));
+        let hash = |name: &str| BlockHash::decode_hex(field(name)?).map_err(|_| format!("invalid {}", name));
+
+        Ok(AuditEntry {
+            instance: number("instance")?,
+            value: hash("value")?,
+            rank: field("rank")?.parse().map_err(|_| "invalid rank")?,
+            certificate: hash("certificate")?,
+            time_ms: number("time_ms")?,
+            previous: hash("previous")?,
+            hash: hash("hash")?,
+        })
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            r#"{{"instance":{},"value":"{}","rank":{},"certificate":"{}","time_ms":{},"previous":"{}","hash":"{}"}}"#,
+            self.instance,
+            self.value.encode_hex(),
+            self.rank,
+            self.certificate.encode_hex(),
+            self.time_ms,
+            self.previous.encode_hex(),
+            self.hash.encode_hex()
+        )
+    }
+}
+
+// Entries of an audit log, or the first line where the chain is broken
+pub fn verify_audit_log(text: &str) -> Result<Vec<AuditEntry>, String> {
+    let mut entries: Vec<AuditEntry> = Vec::new();
+
+    for (line, text) in text.lines().enumerate().filter(|(_, text)| !text.trim().is_empty()) {
+        let entry = AuditEntry::parse(text).map_err(|error| format!("line {}: {}", line + 1, error))?;
+        let expected = AuditEntry::new(entries.last(), entry.value, entry.rank, entry.certificate, entry.time_ms);
+
+        if entry.instance != expected.instance {
+            return Err(format!("line {}: instance {} follows instance {:?}", line + 1, entry.instance, entries.last().map(|last| last.instance)));
+        }
+        if entry.previous != expected.previous {
+            return Err(format!("line {}: does not chain to the entry before it", line + 1));
+        }
+        if entry.hash != expected.hash {
+            return Err(format!("line {}: hash does not match the entry", line + 1));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Writes the entries to every sink it has, a file for `open`
+#[derive(Debug)]
+pub struct AuditLog {
+    sinks: Vec<Box<dyn Sink>>,
+    last: Option<AuditEntry>,
+}
+
+impl AuditLog {
+    // Continues the chain from the last entry written before, if any, which sinks that cannot be read back (syslog, a
+    // collector) leave to the caller to keep
+    pub fn new(last: Option<AuditEntry>) -> AuditLog {
+        AuditLog { sinks: Vec::new(), last }
+    }
+
+    // The entries also go to the sink, e.g. a QueuedSink in front of a collector
+    pub fn with_sink(mut self, sink: impl Sink + 'static) -> AuditLog {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    // Continues the chain of an existing log, which must verify
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        let last = match std::fs::read_to_string(path) {
+            Ok(text) => verify_audit_log(&text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?.pop(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+
+        Ok(AuditLog::new(last).with_sink(FileSink::append(path)?))
+    }
+
+    // Last entry of the log, e.g. the last commit before a restart
+    pub fn last(&self) -> Option<&AuditEntry> {
+        self.last.as_ref()
+    }
+
+    // Every sink gets the entry even if one fails, the first error is returned
+    pub fn append(&mut self, value: ProposalHash, rank: Rank, certificate: &[Response]) -> io::Result<&AuditEntry> {
+        let time_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis() as u64);
+        let entry = AuditEntry::new(self.last.as_ref(), value, rank, certificate_digest(certificate), time_ms);
+
+        let line = entry.to_string();
+        let written = self.sinks.iter_mut().map(|sink| sink.write_line(&line).and_then(|_| sink.flush())).fold(Ok(()), Result::and);
+        let entry = self.last.insert(entry);
+        written.map(|_| &*entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::{Arc, Mutex}};
+    use super::*;
+    use crate::{BValue, Broadcast, State, Step};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("archipelago-audit-{}-{}.jsonl", name, std::process::id()))
+    }
+
+    fn b_answers(value: u64) -> Vec<Response> {
+        let broadcast = Arc::new(Broadcast::new(0, Step::B, BlockHash::from(value), Some(true), 0, None));
+        let pair = BValue::new(BlockHash::from(value), true);
+
+        (0..3).map(|sender| Response::new(sender, Step::B, 0, vec![State::new(Value::BValue(pair), broadcast.clone())])).collect()
+    }
+
+    #[test]
+    fn reopened_log_continues_the_chain() {
+        let path = temp_path("reopened");
+
+        AuditLog::open(&path).unwrap().append(BlockHash::from(1), 0, &b_answers(1)).unwrap();
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.append(BlockHash::from(2), 3, &b_answers(2)).unwrap().instance, 1);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let entries = verify_audit_log(&text).unwrap();
+        assert_eq!(entries.iter().map(|entry| (entry.value, entry.rank)).collect::<Vec<_>>(), vec![(BlockHash::from(1), 0), (BlockHash::from(2), 3)]);
+        assert_eq!(entries[1].previous, entries[0].hash);
+        assert_eq!(entries[0].certificate, certificate_digest(&b_answers(1)));
+        assert_ne!(entries[0].certificate, entries[1].certificate);
+
+        // A sink that cannot be read back continues from the last entry it is given
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut log = AuditLog::new(entries.last().cloned()).with_sink(lines.clone());
+        assert_eq!(log.append(BlockHash::from(3), 0, &b_answers(3)).unwrap().previous, entries[1].hash);
+        assert_eq!(verify_audit_log(&format!("{}{}\n", text, lines.lock().unwrap()[0])).map(|entries| entries.len()), Ok(3));
+    }
+
+    #[test]
+    fn tampering_breaks_the_chain() {
+        let first = AuditEntry::new(None, BlockHash::from(1), 0, BlockHash::from(9), 1_000);
+        let second = AuditEntry::new(Some(&first), BlockHash::from(2), 1, BlockHash::from(9), 2_000);
+        let third = AuditEntry::new(Some(&second), BlockHash::from(3), 0, BlockHash::from(9), 3_000);
+        let log = |entries: &[&AuditEntry]| entries.iter().map(|entry| format!("{}\n", entry)).collect::<String>();
+
+        assert_eq!(verify_audit_log(&log(&[&first, &second, &third])).map(|entries| entries.len()), Ok(3));
+
+        let edited = AuditEntry { value: BlockHash::from(7), ..second.clone() };
+        assert_eq!(verify_audit_log(&log(&[&first, &edited, &third])), Err("line 2: hash does not match the entry".to_string()));
+
+        let rehashed = AuditEntry::new(Some(&first), BlockHash::from(7), 1, BlockHash::from(9), 2_000);
+        assert_eq!(verify_audit_log(&log(&[&first, &rehashed, &third])), Err("line 3: does not chain to the entry before it".to_string()));
+
+        assert!(verify_audit_log(&log(&[&first, &third])).unwrap_err().starts_with("line 2: instance 2 follows"));
+        assert!(verify_audit_log("{\"instance\":0}").unwrap_err().starts_with("line 1: missing value"));
+    }
+}
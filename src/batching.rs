@@ -0,0 +1,371 @@
+use std::{collections::{BTreeSet, VecDeque}, time::{Duration, Instant}};
+use crate::{Id, Message, PeerQueues, Probe, Profiler, MESSAGE_BUFFERS};
+
+// Sequenced batches held per peer until acknowledged, the oldest is given up on past it
+pub const MAX_UNACKED_BATCHES: usize = 1024;
+
+// Messages destined to the same peer are coalesced into a single Message::Batch
+// until either max_batch_size messages are queued or max_delay has elapsed since the oldest one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchConfig {
+    pub max_batch_size: usize,
+    pub max_delay: Duration,
+    // Batches are numbered per peer and acknowledged cumulatively, the ones no ack covers within this long are sent
+    // again. A receiver that sees a gap acknowledges right away, and the missing batch is sent again on the spot.
+    // Unnumbered if None
+    pub ack_timeout: Option<Duration>,
+}
+
+impl BatchConfig {
+    pub fn new(max_batch_size: usize, max_delay: Duration) -> BatchConfig {
+        BatchConfig { max_batch_size: max_batch_size.max(1), max_delay, ack_timeout: None }
+    }
+
+    pub fn acknowledged(mut self, ack_timeout: Duration) -> BatchConfig {
+        self.ack_timeout = Some(ack_timeout);
+        self
+    }
+
+    // Every message is sent as soon as it is queued
+    pub fn disabled() -> BatchConfig {
+        BatchConfig::new(1, Duration::ZERO)
+    }
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig::new(16, Duration::from_millis(1))
+    }
+}
+
+// Sequence numbers of the batches exchanged with one peer, starting from 1
+#[derive(Debug, Default)]
+struct Link {
+    // Sequence number of the last batch sent to the peer
+    sent: u64,
+    // Highest sequence number the peer acknowledged
+    acked: u64,
+    // Batches sent to the peer that no ack covers yet, with when they last went out
+    unacked: VecDeque<(u64, Instant, Vec<Message>)>,
+    // Every batch of the peer up to this one was received
+    received: u64,
+    // Batches of the peer received past a gap
+    ahead: BTreeSet<u64>,
+    ack_due: bool,
+}
+
+impl Link {
+    // Whether the batch was not received before
+    fn receive(&mut self, sequence: u64) -> bool {
+        if sequence <= self.received || !self.ahead.insert(sequence) {
+            return false;
+        }
+
+        // The sender no longer holds the batches that far back, so they are not coming
+        let oldest_held = sequence.saturating_sub(MAX_UNACKED_BATCHES as u64);
+        if self.received < oldest_held {
+            self.received = oldest_held;
+            self.ahead.retain(|ahead| *ahead > oldest_held);
+        }
+
+        while self.ahead.remove(&(self.received + 1)) {
+            self.received += 1;
+        }
+        true
+    }
+
+    // The batches the ack covers are dropped, and an ack that covers nothing new while some are outstanding tells
+    // that the next one was lost
+    fn ack(&mut self, sequence: u64) -> Option<&mut (u64, Instant, Vec<Message>)> {
+        let gap = sequence == self.acked;
+        self.acked = self.acked.max(sequence);
+
+        while self.unacked.front().is_some_and(|(sent, ..)| *sent <= sequence) {
+            self.unacked.pop_front();
+        }
+        self.unacked.front_mut().filter(|_| gap)
+    }
+}
+
+#[derive(Debug)]
+pub struct Outbox {
+    peers: PeerQueues,
+    pending: Vec<Vec<Message>>,
+    oldest: Option<Instant>,
+    config: BatchConfig,
+    profiler: Profiler,
+    // Batches are only numbered once the outbox knows the process it sends for
+    id: Option<Id>,
+    links: Vec<Link>,
+}
+
+impl Outbox {
+    pub fn new(peers: PeerQueues, config: BatchConfig) -> Outbox {
+        let pending = vec![Vec::new(); peers.len()];
+        let links = (0..peers.len()).map(|_| Link::default()).collect();
+        Outbox { peers, pending, oldest: None, config, profiler: Profiler::default(), id: None, links }
+    }
+
+    // Names the process in the sequenced batches and acks, see BatchConfig::ack_timeout
+    pub fn with_id(mut self, id: Id) -> Outbox {
+        self.id = Some(id);
+        self
+    }
+
+    // Records how long the oldest queued message waited once it goes out
+    pub fn with_profiler(mut self, profiler: Profiler) -> Outbox {
+        self.profiler = profiler;
+        self
+    }
+
+    // Messages already queued go out under the new limits
+    pub fn set_config(&mut self, config: BatchConfig) {
+        self.config = config;
+    }
+
+    pub fn peers(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Queues the message for every peer
+    pub fn push(&mut self, message: Message) {
+        for pending in self.pending.iter_mut() {
+            pending.push(message.clone());
+        }
+        self.oldest.get_or_insert_with(Instant::now);
+
+        if self.pending.iter().any(|pending| pending.len() >= self.config.max_batch_size) {
+            self.flush();
+        }
+    }
+
+    // Queues the message for a single peer
+    pub fn push_to(&mut self, peer: Id, message: Message) {
+        let Some(pending) = usize::try_from(peer).ok().and_then(|peer| self.pending.get_mut(peer)) else {
+            return;
+        };
+
+        pending.push(message);
+        self.oldest.get_or_insert_with(Instant::now);
+
+        if pending.len() >= self.config.max_batch_size {
+            self.flush();
+        }
+    }
+
+    pub fn flush_if_due(&mut self) {
+        if let Some(oldest) = self.oldest {
+            if oldest.elapsed() >= self.config.max_delay {
+                self.flush();
+            }
+        }
+        self.retransmit_due();
+    }
+
+    pub fn flush(&mut self) {
+        let sequencing = self.id.zip(self.config.ack_timeout);
+
+        for (peer, (pending, link)) in self.pending.iter_mut().zip(self.links.iter_mut()).enumerate() {
+            // Acks are never numbered themselves, so that acknowledging does not call for acks in turn
+            if let Some((id, _)) = sequencing.filter(|_| link.ack_due) {
+                self.peers.send(peer, Message::Ack(id, link.received));
+                link.ack_due = false;
+            }
+
+            let message = match (pending.len(), sequencing) {
+                (0, _) => continue,
+                (_, Some((id, _))) => {
+                    let messages = std::mem::replace(pending, MESSAGE_BUFFERS.take());
+                    link.sent += 1;
+                    if link.unacked.len() >= MAX_UNACKED_BATCHES {
+                        link.unacked.pop_front();
+                    }
+                    link.unacked.push_back((link.sent, Instant::now(), messages.clone()));
+                    Message::Sequenced(id, link.sent, messages)
+                }
+                (1, None) => pending.pop().unwrap(),
+                (_, None) => Message::Batch(std::mem::replace(pending, MESSAGE_BUFFERS.take())),
+            };
+
+            self.peers.send(peer, message);
+        }
+        if let Some(oldest) = self.oldest.take() {
+            self.profiler.record(Probe::ChannelLatency, oldest.elapsed());
+        }
+    }
+
+    // Records a sequenced batch of the peer, acknowledged with the next flush, or right away if it shows that an earlier
+    // one is missing or that the peer did not get the last ack
+    pub fn received(&mut self, peer: Id, sequence: u64) {
+        let (Some(id), Some(link)) = (self.id, usize::try_from(peer).ok().and_then(|peer| self.links.get_mut(peer))) else {
+            return;
+        };
+
+        if link.receive(sequence) && link.ahead.is_empty() {
+            link.ack_due = true;
+        } else {
+            self.peers.send_to(peer, Message::Ack(id, link.received));
+            link.ack_due = false;
+        }
+    }
+
+    pub fn acked(&mut self, peer: Id, sequence: u64) {
+        let (Some(id), Some(link)) = (self.id, usize::try_from(peer).ok().and_then(|peer| self.links.get_mut(peer))) else {
+            return;
+        };
+
+        if let Some((sequence, sent, messages)) = link.ack(sequence) {
+            *sent = Instant::now();
+            self.peers.send_to(peer, Message::Sequenced(id, *sequence, messages.clone()));
+        }
+    }
+
+    // Sends again the batches no ack covered within the timeout
+    fn retransmit_due(&mut self) {
+        let (Some(id), Some(timeout)) = (self.id, self.config.ack_timeout) else {
+            return;
+        };
+
+        for (peer, link) in self.links.iter_mut().enumerate() {
+            for (sequence, sent, messages) in link.unacked.iter_mut().filter(|(_, sent, _)| sent.elapsed() >= timeout) {
+                *sent = Instant::now();
+                self.peers.send(peer, Message::Sequenced(id, *sequence, messages.clone()));
+            }
+        }
+    }
+
+    // Batches sent that no ack covers yet, over every peer
+    pub fn unacked(&self) -> usize {
+        self.links.iter().map(|link| link.unacked.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.oldest.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Broadcast, Step};
+
+    fn message(rank: i64) -> Message {
+        Message::Broadcast(Broadcast::new(0, Step::R, BlockHash::from(1), None, rank, None))
+    }
+
+    #[test]
+    fn flushes_when_batch_is_full() {
+        let (sender, receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::new(2, Duration::from_secs(60)));
+
+        outbox.push(message(0));
+        assert!(receiver.try_recv().is_err());
+
+        outbox.push(message(1));
+        assert_eq!(receiver.try_recv().unwrap(), Message::Batch(vec![message(0), message(1)]));
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn pushes_to_single_peer() {
+        let (sender1, receiver1) = channel();
+        let (sender2, receiver2) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender1, sender2]), BatchConfig::disabled());
+
+        outbox.push_to(1, message(0));
+        outbox.push_to(7, message(1));
+
+        assert!(receiver1.try_recv().is_err());
+        assert_eq!(receiver2.try_recv().unwrap(), message(0));
+        assert!(receiver2.try_recv().is_err());
+    }
+
+    #[test]
+    fn flushes_single_message_unbatched() {
+        let (sender, receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::new(8, Duration::ZERO));
+
+        outbox.push(message(0));
+        outbox.flush_if_due();
+
+        assert_eq!(receiver.try_recv().unwrap(), message(0));
+    }
+
+    #[test]
+    fn holds_messages_within_window() {
+        let (sender1, receiver1) = channel();
+        let (sender2, receiver2) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender1, sender2]), BatchConfig::new(8, Duration::from_secs(60)));
+
+        outbox.push(message(0));
+        outbox.push(message(1));
+        outbox.flush_if_due();
+        assert!(receiver1.try_recv().is_err());
+
+        outbox.flush();
+        assert_eq!(receiver1.try_recv().unwrap(), Message::Batch(vec![message(0), message(1)]));
+        assert_eq!(receiver2.try_recv().unwrap(), Message::Batch(vec![message(0), message(1)]));
+    }
+
+    #[test]
+    fn resends_batch_missing_from_ack() {
+        let (sender0, _receiver0) = channel();
+        let (sender1, receiver1) = channel();
+        let config = BatchConfig::disabled().acknowledged(Duration::from_secs(60));
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender0, sender1]), config).with_id(0);
+
+        outbox.push_to(1, message(0));
+        outbox.push_to(1, message(1));
+        assert_eq!(receiver1.try_recv().unwrap(), Message::Sequenced(0, 1, vec![message(0)]));
+        assert_eq!(receiver1.try_recv().unwrap(), Message::Sequenced(0, 2, vec![message(1)]));
+
+        // The first ack covering nothing tells that batch 1 was lost
+        outbox.acked(1, 0);
+        assert_eq!(receiver1.try_recv().unwrap(), Message::Sequenced(0, 1, vec![message(0)]));
+
+        outbox.acked(1, 2);
+        outbox.flush_if_due();
+        assert!(receiver1.try_recv().is_err());
+        assert_eq!(outbox.unacked(), 0);
+    }
+
+    #[test]
+    fn acknowledges_gap_right_away() {
+        let (sender0, receiver0) = channel();
+        let (sender1, _receiver1) = channel();
+        let config = BatchConfig::disabled().acknowledged(Duration::from_secs(60));
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender0, sender1]), config).with_id(1);
+
+        outbox.received(0, 2);
+        assert_eq!(receiver0.try_recv().unwrap(), Message::Ack(1, 0));
+
+        // Batches in order are acknowledged with the next flush
+        outbox.received(0, 1);
+        assert!(receiver0.try_recv().is_err());
+        outbox.flush();
+        assert_eq!(receiver0.try_recv().unwrap(), Message::Ack(1, 2));
+
+        outbox.received(0, 2);
+        assert_eq!(receiver0.try_recv().unwrap(), Message::Ack(1, 2));
+    }
+
+    #[test]
+    fn resends_unacknowledged_batches_after_timeout() {
+        let (sender, receiver) = channel();
+        let config = BatchConfig::disabled().acknowledged(Duration::ZERO);
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), config).with_id(0);
+
+        outbox.push(message(0));
+        outbox.flush_if_due();
+        assert_eq!(receiver.try_recv().unwrap(), Message::Sequenced(0, 1, vec![message(0)]));
+        assert_eq!(receiver.try_recv().unwrap(), Message::Sequenced(0, 1, vec![message(0)]));
+    }
+
+    #[test]
+    fn unbatching_keeps_sequence_header() {
+        let messages = Message::Sequenced(2, 5, vec![message(0), message(1)]).into_messages();
+        assert_eq!(messages, vec![Message::Sequenced(2, 5, vec![]), message(0), message(1)]);
+    }
+}
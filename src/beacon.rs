@@ -0,0 +1,135 @@
+use std::{fmt::Debug, sync::{Arc, RwLock}, time::Duration};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{bottom_value, Id, Jitter, RValue, Rank};
+
+// Shared randomness, e.g. the output of a threshold beacon, only known once a rank starts
+// Every member of the committee must get the same randomness for a rank: the max() of Lines 21, 40, 45, 60 and 63 is
+// taken in the order it gives, and processes ordering values differently would answer and check certificates differently
+pub trait RandomnessBeacon: Send + Sync + Debug {
+    fn randomness(&self, rank: Rank) -> [u8; 32];
+}
+
+// Order of the values of a rank. Without a beacon it is the order of their hashes, which lets a byzantine proposer
+// grind a preproposal set whose proposal hash wins every tie. With one, values are ordered by the hash of the rank's
+// randomness and the value, which nobody can predict before the rank starts
+// Clones share the beacon, so the one set on a process applies to its proposer, run loop and validation workers
+#[derive(Debug, Clone, Default)]
+pub struct ValueOrder {
+    beacon: Arc<RwLock<Option<Arc<dyn RandomnessBeacon>>>>,
+}
+
+impl ValueOrder {
+    pub fn new(beacon: Arc<dyn RandomnessBeacon>) -> ValueOrder {
+        let order = ValueOrder::default();
+        order.set_beacon(beacon);
+        order
+    }
+
+    pub fn set_beacon(&self, beacon: Arc<dyn RandomnessBeacon>) {
+        *self.beacon.write().unwrap() = Some(beacon);
+    }
+
+    fn randomness(&self, rank: Rank) -> Option<[u8; 32]> {
+        self.beacon.read().unwrap().as_ref().map(|beacon| beacon.randomness(rank))
+    }
+
+    // The bottom value keys lowest, so that a process never prefers it to a value somebody proposed
+    fn keyed(randomness: Option<[u8; 32]>, bottom: BlockHash, value: BlockHash) -> BlockHash {
+        match randomness {
+            _ if value == bottom => BlockHash::zero(),
+            Some(randomness) => Blake2HashBuilder::new().update(randomness).update(value.as_bytes()).build(),
+            None => value,
+        }
+    }
+
+    // Values of the rank compare as their keys do
+    pub fn key(&self, rank: Rank, value: BlockHash) -> BlockHash {
+        ValueOrder::keyed(self.randomness(rank), bottom_value(), value)
+    }
+
+    // R values compare by rank first, then by the key of their value
+    pub fn r_key(&self, r_value: RValue) -> (Rank, BlockHash) {
+        (r_value.rank, self.key(r_value.rank, r_value.value))
+    }
+
+    pub fn greater(&self, rank: Rank, value: BlockHash, other: BlockHash) -> bool {
+        let (randomness, bottom) = (self.randomness(rank), bottom_value());
+        ValueOrder::keyed(randomness, bottom, value) > ValueOrder::keyed(randomness, bottom, other)
+    }
+
+    // Greatest of values of the same rank
+    pub fn max<T>(&self, rank: Rank, values: impl IntoIterator<Item = T>, value: impl Fn(&T) -> BlockHash) -> Option<T> {
+        let (randomness, bottom) = (self.randomness(rank), bottom_value());
+        values.into_iter().max_by_key(|item| ValueOrder::keyed(randomness, bottom, value(item)))
+    }
+
+    // Delay below `bound` for a process to back off in a rank: drawn from the beacon and the process id if there is
+    // one, so that it differs per process but cannot be predicted before the rank, from the jitter source otherwise
+    pub fn jitter(&self, rank: Rank, id: Id, bound: Duration, source: &Jitter) -> Duration {
+        let bound = bound.as_nanos().max(1) as u64;
+        let nanos = match self.randomness(rank) {
+            Some(randomness) => {
+                let hash = Blake2HashBuilder::new().update(randomness).update(id.to_le_bytes()).build();
+                u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap()) % bound
+            }
+            None => return source.below(Duration::from_nanos(bound)),
+        };
+
+        Duration::from_nanos(nanos)
+    }
+}
+
+// Randomness derived from a seed known to the whole committee, for tests and simulations: unlike a threshold beacon,
+// anyone knowing the seed can predict it
+#[derive(Debug, Clone, Copy)]
+pub struct SeededBeacon(pub u64);
+
+impl RandomnessBeacon for SeededBeacon {
+    fn randomness(&self, rank: Rank) -> [u8; 32] {
+        *Blake2HashBuilder::new().update(self.0.to_le_bytes()).update(rank.to_le_bytes()).build().as_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_beacon_reorders_values_per_rank() {
+        let values: Vec<BlockHash> = (1..=8).map(BlockHash::from).collect();
+        assert_eq!(ValueOrder::default().max(0, values.iter().copied(), |value| *value), Some(BlockHash::from(8)));
+
+        let order = ValueOrder::new(Arc::new(SeededBeacon(7)));
+        let maxima: Vec<_> = (0..16).map(|rank| order.max(rank, values.iter().copied(), |value| *value).unwrap()).collect();
+        assert!(maxima.iter().any(|max| *max != BlockHash::from(8)));
+        assert!(maxima.iter().any(|max| *max != maxima[0]));
+
+        // The same on every process given the same beacon
+        let other = ValueOrder::new(Arc::new(SeededBeacon(7)));
+        assert!((0..16).all(|rank| other.max(rank, values.iter().copied(), |value| *value).unwrap() == maxima[rank as usize]));
+        assert_eq!(order.greater(3, maxima[3], BlockHash::from(1)), maxima[3] != BlockHash::from(1));
+    }
+
+    #[test]
+    fn the_bottom_value_loses_to_any_other() {
+        let values = [BlockHash::from(1), bottom_value()];
+        assert_eq!(ValueOrder::default().max(0, values, |value| *value), Some(BlockHash::from(1)));
+
+        let order = ValueOrder::new(Arc::new(SeededBeacon(7)));
+        assert!((0..16).all(|rank| order.max(rank, values, |value| *value) == Some(BlockHash::from(1))));
+        assert!(!order.greater(3, bottom_value(), BlockHash::from(1)));
+    }
+
+    #[test]
+    fn jitter_stays_below_the_bound() {
+        let bound = Duration::from_millis(50);
+        let order = ValueOrder::new(Arc::new(SeededBeacon(7)));
+
+        let source = Jitter::seeded(1);
+
+        assert!((0..4).all(|id| order.jitter(1, id, bound, &source) < bound));
+        assert_eq!(order.jitter(1, 2, bound, &source), order.jitter(1, 2, bound, &Jitter::seeded(2)));
+        assert!(ValueOrder::default().jitter(1, 2, bound, &source) < bound);
+        assert_eq!(ValueOrder::default().jitter(1, 2, bound, &Jitter::seeded(3)), ValueOrder::default().jitter(1, 2, bound, &Jitter::seeded(3)));
+    }
+}
@@ -1,205 +1,772 @@
-use std::{cmp::max, collections::{BTreeSet, HashMap, HashSet}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc, RwLock}, thread};
-use crate::{AValue, BValue, Broadcast, BroadcastHash, Decision, Id, Message, PreProposal, Proposal, ProposalHash, RValue, Rank, Response, State, Step, Value};
+use std::{cmp::max, io, ops::Range, path::Path, collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver, Sender, TryRecvError}, Arc, Mutex, RwLock}, thread, time::{Duration, Instant}};
+use crate::{bottom_value, only_true, traced_answer, ATally, Alert, Alerts, AuditLog, BroadcastDump, AValue, BTally, BValue, Broadcast, BroadcastHash, BroadcastStore, CertificateResolver, CertificateResponses, Config, CommitLog, CommitRecord, Committee, ConsensusMetrics, ConsensusStats, Missing, DecidedWatch, Decision, Equivocation, Features, Hello, FrontierPage, Id, Identities, InstanceTrace, Jitter, Latencies, LiveConfig, LogSampler, MemoryBreakdown, MemoryMetrics, MemorySize, MemoryTracker, MemoryUsage, Message, MetricsSink, Mismatch, Offense, Outbox, Pacemaker, Probe, ProfileReport, Profiler, PreProposalCache, Priority, MESSAGE_BUFFERS, PeerAccounting, PeerState, PeerTable, PendingDump, PendingResponses, PeerQueues, PeerStats, PreProposal, Proposal, ProposalHash, Progress, RandomnessBeacon, RTally, RValue, Rank, Registers, Rejection, RejectionStage, Rejoin, RejoinGrounds, Reload, Response, ResponseGroup, ResponseStore, Stage, StallReport, State, StateDump, StatsdSink, States, Step, TranscriptRecorder, ValidationPool, ValidationRules, Validity, ValidityPredicate, Value, ValueOrder, Vote, VoteCache, VoteError, VoteGossip, Wakeup, MAX_VOTE_HASHES};
+#[cfg(debug_assertions)]
+use crate::Invariants;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use log::{debug, warn, Level};
 use rand::{self, Rng};
-use rsnano_core::BlockHash;
+use rayon::prelude::*;
+use rsnano_core::{BlockHash, PrivateKey, PublicKey};
+use smallvec::smallvec;
 
 // Each process receives 2f+1 responses per step and rank 
-type Responses = Arc<RwLock<HashMap<(Step, Rank), HashMap<Id, Response>>>>;
+type Responses = Arc<ResponseStore>;
 
-type PreProposals = Arc<RwLock<HashMap<Id, PreProposal>>>;
+type PreProposals = Arc<RwLock<PreProposalCache>>;
 
 type Proposals = Arc<RwLock<HashMap<Id, Proposal>>>;
+type PreVotes = Arc<RwLock<HashMap<Id, ProposalHash>>>;
+
+type Votes = Arc<Mutex<VoteCache>>;
+
+type SharedPacemaker = Arc<Mutex<Pacemaker>>;
+
+type Commits = Arc<Mutex<CommitLog>>;
+
+type Transcripts = Arc<Mutex<TranscriptRecorder>>;
+
+static REJECTED_BROADCASTS: LogSampler = LogSampler::new(1000);
+
+static EQUIVOCATIONS: LogSampler = LogSampler::new(1000);
+
+// The run loop wakes up at least this often while no message arrives, to answer state dumps and notice the stop flag
+const IDLE_WAKEUP: Duration = Duration::from_millis(100);
+
+// Certificates with at least this many responses have their entries verified in parallel
+const PARALLEL_VERIFICATION_THRESHOLD: usize = 16;
 
-// In the first step of rank i, each process: 
-// 1) Broadcasts its rank i, value v and an optional certificate containing responses of step B and rank i-1 from 2f+1 processes (if i > 0)
-// 2) Waits valid responses from 2f+1 processes 
-// 3) Keeps the maximum RValue v'
-// 4) Returns (i, v')
-type R = Arc<RwLock<RValue>>;
-
-// In the second step of rank i, each process: 
-// 1) Broadcasts its rank i, value v and a certificate containing responses of step R and rank i from 2f+1 processes 
-// 2) Waits valid responses from 2f+1 processes 
-// 3) Keeps the the greatest AValue or the two greatest if there are different values
-// 4) According to the received AResponses, it returns:
-// - (true, v) if there is only one Avalue v 
-// - (false, max(v)), otherwise
-type A = Arc<RwLock<Vec<AValue>>>;
-
-// In the third step of rank i, each process: 
-// 1) Broadcasts its rank i, value v, a boolean flag and a certificate containing responses of step R and rank i from 2f+1 processes 
-// 2) Waits valid responses from 2f+1 processes
-// 3) Keeps the (true, v) pair and the highest (false, pair), if there is one
-// 4) According to the received BResponses, it returns:
-// - (commit, v) if there are at least 2f+1 (commit, v)
-// - (adopt, v) if there is at least 1 (commit, v)
-// - (adopt, max(v)) otherwise
-type B = Arc<RwLock<Vec<BValue>>>;
-
-// Maps broadcasts to their count
-type Broadcasts = HashMap<Broadcast, i64>;
-
-// Maps responses to their states
-type PendingResponses = HashMap<BTreeSet<BroadcastHash>, HashSet<Response>>;
 
 #[derive(Debug, Clone)]
 pub struct Process {
     id: Id,
+    f: usize,
     responses: Responses,
-    senders: Vec<Sender<Message>>,
+    peers: PeerQueues,
     stop_flag: Arc<AtomicBool>,
     byzantine: bool,
     preproposals: PreProposals,
     proposals: Proposals,
+    pre_votes: PreVotes,
+    // Notified by the run loop when it stores a preproposal, proposal, pre-vote or backfilled commits
+    wakeup: Arc<Wakeup>,
+    votes: Votes,
+    pacemaker: SharedPacemaker,
+    order: ValueOrder,
+    validity: Validity,
+    identities: Identities,
+    jitter: Jitter,
+    profiler: Profiler,
+    // Signs the final votes of this process, if it votes
+    voting_key: Option<PrivateKey>,
+    // The broadcasts this process sent, which Core::handle does not see before they come back to it
+    #[cfg(debug_assertions)]
+    invariants: Arc<Mutex<Invariants>>,
+    memory: Arc<MemoryMetrics>,
+    accounting: Arc<PeerAccounting>,
+    consensus: Arc<ConsensusMetrics>,
+    progress: Arc<Progress>,
+    alerts: Arc<Alerts>,
+    commits: Commits,
+    transcript: Transcripts,
+    // Requests for a dump of the run loop's state, answered on the given channel
+    dumps: Sender<Sender<StateDump>>,
+    trace: InstanceTrace,
+    config: Config,
+    // Settings reloaded since the process started, read by the run loop and the watchdog
+    live: LiveConfig,
 }
 
 impl Process {
     pub fn new(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, byzantine: bool) -> Self {   
-        let responses = Arc::new(RwLock::new(HashMap::new()));
-        let responses_clone = responses.clone();
-        let senders_clone = senders.clone();
+        Process::new_with_config(id, f, senders, receiver, byzantine, Config::default())
+    }
+
+    pub fn new_with_config(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, byzantine: bool, config: Config) -> Self {
+        Process::new_with_peers(id, f, PeerQueues::spawn(senders, config.outbound_queue_capacity, config.overflow_policy, config.prioritize_traffic), receiver, byzantine, config)
+    }
+
+    // Sends over queues that may be shared with other processes, e.g. the other instances of an Instances
+    pub fn new_with_peers(id: Id, f: usize, peers: PeerQueues, receiver: Receiver<Message>, byzantine: bool, config: Config) -> Self {
+        let mut core = Core::new(id, f, byzantine, config);
+        if config.pin_committee {
+            core.identities.pin(core.identities.configuration(f, peers.len()));
+        }
+        core.peer_table = peers.table();
+        core.accounting = Arc::new(PeerAccounting::suspecting(core.peer_table.suspicion()));
+        let responses = Arc::clone(&core.responses);
+        let peers = peers.with_accounting(core.peer_accounting());
+        let peers_clone = peers.clone();
         let stop_flag = Arc::new(AtomicBool::new(false));
         let stop_flag_clone = Arc::clone(&stop_flag);
-        let preproposals: PreProposals = Arc::new(RwLock::new(HashMap::new()));
-        let preproposals_clone = Arc::clone(&preproposals);
-        let proposals: Proposals = Arc::new(RwLock::new(HashMap::new()));
-        let proposals_clone = Arc::clone(&proposals);
+        let (dumps, dump_requests) = channel();
+        let live = LiveConfig::new(config);
+        let live_clone = live.clone();
 
         let state = Process {
             id,
+            f,
             responses,
-            senders,
+            peers,
             stop_flag,
             byzantine,
-            preproposals,
-            proposals
+            preproposals: Arc::clone(&core.preproposals),
+            proposals: Arc::clone(&core.proposals),
+            pre_votes: Arc::clone(&core.pre_votes),
+            wakeup: Arc::clone(&core.wakeup),
+            votes: Arc::clone(&core.votes),
+            pacemaker: Arc::clone(&core.pacemaker),
+            order: core.value_order(),
+            validity: core.validity.clone(),
+            identities: core.identities(),
+            jitter: core.jitter.clone(),
+            profiler: core.profiler.clone(),
+            voting_key: None,
+            #[cfg(debug_assertions)]
+            invariants: Arc::default(),
+            memory: Arc::clone(&core.memory_metrics),
+            accounting: core.peer_accounting(),
+            consensus: core.consensus_metrics(),
+            progress: Arc::new(Progress::new()),
+            alerts: Arc::new(Alerts::default()),
+            commits: Arc::clone(&core.commits),
+            transcript: Arc::clone(&core.transcript),
+            dumps,
+            trace: InstanceTrace::default(),
+            config,
+            live,
         };
+
+        let watched = state.clone();
+        thread::spawn(move || watched.watch());
+
+        if let Some(statsd) = config.statsd {
+            match StatsdSink::connect(statsd.address, &format!("archipelago.{}", id)) {
+                Ok(sink) => {
+                    let reported = state.clone();
+                    thread::spawn(move || reported.push_metrics(sink, statsd.interval));
+                }
+                Err(error) => warn!("{}: cannot push metrics to {}: {}", id, statsd.address, error),
+            }
+        }
                 
         // Start message handling in a background thread
         thread::spawn(move || {
             Process::run(
-                id,
-                f,
-                responses_clone,
-                senders_clone,
+                core,
+                peers_clone,
                 stop_flag_clone,
                 receiver,
-                byzantine,
-                preproposals_clone,
-                proposals_clone
+                dump_requests,
+                live_clone
             );
         });
-        
+
+        state.hello();
         state
     }
 
     fn run(
-        id: Id,
-        f: usize,
-        responses: Responses,
-        senders: Vec<Sender<Message>>,
+        mut core: Core,
+        peers: PeerQueues,
         stop_flag: Arc<AtomicBool>,
         receiver: Receiver<Message>,
-        byzantine: bool,
-        preproposals: PreProposals,
-        proposals: Proposals,
+        dump_requests: Receiver<Sender<StateDump>>,
+        live: LiveConfig,
     ) {
-        let r_set = Arc::new(RwLock::new(RValue::default()));
-        let a_sets = Arc::new(RwLock::new(Vec::new()));
-        let b_sets = Arc::new(RwLock::new(Vec::new()));
-        let mut broadcasts: Broadcasts = HashMap::new();
-        let mut pending_responses: PendingResponses = HashMap::new();
+        let config = live.get();
+        let mut reloaded = 0;
+        let table = peers.table();
+        let mut outbox = Outbox::new(peers, config.batch).with_profiler(core.profiler.clone()).with_id(core.id);
+        let announce_after = |core: &Core| config.preproposal_digest_interval.map(|interval| core.jitter.spread(interval, config.timer_jitter));
+        let mut announced = (Instant::now(), announce_after(&core));
+
+        let receiver = if core.verified {
+            ValidationPool::spawn(config.validation_workers, core.validation_rules(), core.peer_accounting(), core.consensus_metrics(), core.profiler.clone(), receiver)
+        } else {
+            receiver
+        };
 
         loop {
             if stop_flag.load(Ordering::Relaxed) {
                 break;
             }
 
-            if let Ok(msg) = receiver.recv() {
-                match msg {
-                    Message::PreProposal(preproposal) => {
-                        //if valid {
-                            let mut preproposals= preproposals.write().unwrap();
-                            preproposals.entry(preproposal.sender).or_insert(preproposal.clone());
-                        //}
-                    }
-                    Message::Proposal(proposal) => {
-                        //if valid {
-                            let mut proposals= proposals.write().unwrap();
-                            proposals.entry(proposal.sender).or_insert(proposal.clone());
-                        //}
-                    }
-                    Message::Broadcast(broadcast) => {                        
-                        // Lines 26, 42, 62
-                        let is_reliable = Process::reliably_check_broadcast(&broadcast, &broadcasts, f);
-
-                        if is_reliable {
-                            broadcasts.entry(broadcast.clone()).or_insert(0);
-
-                            match broadcast.step {
-                                Step::R => {
-                                    Process::answer_r_broadcast(
-                                        id,
-                                        &broadcast,
-                                        &senders,
-                                        &r_set,
-                                        &broadcasts,
-                                        byzantine
-                                    );
-                                }
-                                Step::A => {
-                                    Process::answer_a_broadcast(
-                                        id,
-                                        &broadcast,
-                                        &senders,
-                                        &a_sets,
-                                        &broadcasts,
-                                        byzantine
-                                    );
-                                }
-                                Step::B => {
-                                    Process::answer_b_broadcast(
-                                        id,
-                                        &broadcast,
-                                        &senders,
-                                        &b_sets,
-                                        &broadcasts,
-                                        byzantine
-                                    );
-                                }
-                            }
-                        }          
-                    }
-                    Message::Response(response) => {
-                        Process::reliably_check_response(
-                            response,
-                            &responses,
-                            &mut pending_responses,
-                            2 * f + 1
-                        );
+            for reply in dump_requests.try_iter() {
+                let _ = reply.send(core.dump_state());
+            }
+
+            if let Some(config) = live.changed(&mut reloaded) {
+                outbox.set_config(config.batch);
+            }
+
+            // Queued responses are only held back while more messages are waiting to be processed
+            let received = match receiver.try_recv() {
+                Err(TryRecvError::Empty) => {
+                    outbox.flush();
+                    receiver.recv_timeout(IDLE_WAKEUP).ok()
+                }
+                received => received.ok(),
+            };
+
+            // Banned peers are not heard, their requests to be taken back are held for the commit that decides them
+            match received {
+                Some(Message::Rejoin(rejoin)) => core.hold_rejoin(rejoin),
+                Some(msg) if msg.sender().is_none_or(|sender| table.admits(sender)) => core.handle(msg, &mut outbox),
+                _ => (),
+            }
+
+            core.check_relays(&mut outbox);
+
+            // Spread anew each time, so that the announcements do not tell when the next one goes out
+            if announced.1.is_some_and(|interval| announced.0.elapsed() >= interval) {
+                core.announce_preproposals(&mut outbox);
+                announced = (Instant::now(), announce_after(&core));
+            }
+
+            outbox.flush_if_due();
+        }
+    }
+
+    // Evicts stale ranks (more than one rank behind the latest quorum) first, then the pending responses furthest from a quorum
+    fn enforce_memory_budget(
+        memory: &mut MemoryTracker,
+        broadcasts: &mut BroadcastStore,
+        pending_responses: &mut PendingResponses,
+        resolver: &mut CertificateResolver,
+        responses: &Responses,
+        evicted_below: &mut Rank,
+        threshold: usize
+    ) {
+        if let Some(current_rank) = responses.latest_quorum_rank(threshold) {
+            while memory.over_budget() {
+                match memory.lowest_rank() {
+                    Some(rank) if rank < current_rank - 1 => {
+                        broadcasts.evict_rank(rank, |_| false);
+                        pending_responses.evict_rank(rank);
+                        responses.evict_rank(rank);
+                        resolver.evict_rank(rank);
+                        memory.evict_rank(rank);
+                        *evicted_below = max(*evicted_below, rank + 1);
                     }
+                    _ => break,
                 }
             }
         }
+
+        while memory.over_budget() {
+            let Some(pending) = pending_responses.evict_smallest() else {
+                break;
+            };
+
+            for response in pending {
+                memory.evict(response.rank, response.memory_size());
+            }
+        }
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory.usage()
+    }
+
+    // Approximate, the stores of the run loop as of the last message it handled
+    pub fn memory_breakdown(&self) -> MemoryBreakdown {
+        let (broadcasts, pending_responses) = self.memory.run_loop();
+        MemoryBreakdown {
+            broadcasts,
+            responses: self.responses.memory_size(),
+            pending_responses,
+            preproposals: self.preproposals.read().unwrap().memory_size(),
+            commit_log: self.commits.lock().unwrap().memory_size(),
+        }
+    }
+
+    // Time spent in the R, A and B steps and from proposing to committing, over every instance proposed so far
+    pub fn latencies(&self) -> Latencies {
+        self.progress.latencies()
+    }
+
+    // Messages dropped per peer because its outbound queue was full
+    pub fn dropped_messages(&self) -> Vec<usize> {
+        self.peers.dropped()
+    }
+
+    // Peers disconnected or banned by the operator, and the equivocations to ban them for
+    pub fn peer_table(&self) -> Arc<PeerTable> {
+        self.peers.table()
+    }
+
+    // Traffic, invalid messages and duplicates per peer, to tell which peer is noisy or broken
+    pub fn peer_stats(&self) -> BTreeMap<Id, PeerStats> {
+        self.accounting.stats()
+    }
+
+    // Instances decided, ranks and adopts they took, rejected broadcasts and equivocations, since the process started
+    pub fn stats(&self) -> ConsensusStats {
+        self.consensus.stats()
+    }
+
+    // Latest commit of this process, for consumers that do not need every one
+    pub fn decided_watch(&self) -> DecidedWatch {
+        self.commits.lock().unwrap().decided().subscribe()
+    }
+
+    // Applies the reloaded settings from the next message handled on, the instance in flight goes on
+    pub fn reload(&self, reload: &Reload) {
+        self.live.reload(reload);
+        if let Some(capacity) = reload.outbound_queue_capacity {
+            self.peers.set_capacity(capacity);
+        }
+    }
+
+    // Config the process started with, with the reloads applied since
+    pub fn config(&self) -> Config {
+        self.live.get()
     }
 
     pub fn stop(&mut self) {
         self.stop_flag.store(true, Ordering::Relaxed);
+        self.wakeup.notify();
+        self.responses.close();
+    }
+
+    // What the proposer is waiting for, once it has not moved to another step for the stall timeout
+    pub fn stall_report(&self) -> Option<StallReport> {
+        let (stage, elapsed) = self.progress.stalled(self.live.get().stall_timeout?)?;
+
+        let received = match stage {
+            Stage::Step(step, rank) => self.responses.senders(step, rank),
+            _ => self.preproposals.read().unwrap().senders().collect(),
+        };
+
+        Some(StallReport::new(self.id, stage, elapsed, 2 * self.f + 1, received, self.peers.len()))
+    }
+
+    // Commits are appended to the log, numbered on from its last entry
+    pub fn with_audit_log(self, log: AuditLog) -> Process {
+        self.commits.lock().unwrap().resume(log);
+        self
+    }
+
+    // Records the transcript of the instance this process commits next, written to the path once it commits and signed
+    // under the identity of the process, see `verify_transcript`
+    pub fn with_transcript(self, path: &Path) -> Process {
+        self.transcript.lock().unwrap().start(path.to_path_buf());
+        self
+    }
+
+    // A process coming back after a restart, under the identity and with the audit log it had before: it announces
+    // itself to the committee, which sends it the commits it missed
+    pub fn restart(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config, identity: (SigningKey, HashMap<Id, VerifyingKey>), audit: &Path) -> io::Result<Process> {
+        let (key, committee) = identity;
+        let process = Process::new_with_config(id, f, senders, receiver, false, config)
+            .with_identity(key, committee)
+            .with_audit_log(AuditLog::open(audit)?);
+
+        process.announce();
+        Ok(process)
+    }
+
+    // A member that was just added to the committee, which starts from the latest checkpoint the committee sends it
+    pub fn join(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config, identity: (SigningKey, HashMap<Id, VerifyingKey>)) -> Process {
+        let (key, committee) = identity;
+        let process = Process::new_with_config(id, f, senders, receiver, false, config).with_identity(key, committee);
+
+        process.announce();
+        process
+    }
+
+    // A member banned for equivocation coming back under a fresh key or the key on record, which the committee takes
+    // it back under once it commits the reconfiguration value of the grounds (Rejoin::value). The committee given holds
+    // the key it comes back under. It starts from the latest checkpoint like a new member, and neither answers nor
+    // votes before the resume instance
+    #[allow(clippy::too_many_arguments)]
+    pub fn rejoin(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config, identity: (SigningKey, HashMap<Id, VerifyingKey>), grounds: RejoinGrounds, resume: u64) -> Process {
+        let (key, committee) = identity;
+        let rejoin = Rejoin::new(id, grounds, resume, &key);
+        let process = Process::new_with_config(id, f, senders, receiver, false, config).with_identity(key, committee);
+        process.commits.lock().unwrap().silent_until(resume);
+
+        Process::send_message(&process.peers, &mut Message::Rejoin(rejoin), false);
+        process.announce();
+        process
+    }
+
+    // Commits of the instances this process kept, with their certificates
+    pub fn certificates(&self, instances: Range<u64>) -> Vec<CommitRecord> {
+        self.commits.lock().unwrap().range(instances)
+    }
+
+    // Commits of the instances with their certificates, e.g. for auditors and observers that did not witness them: the
+    // ones this process kept, and the others as f+1 processes of the committee send them within the timeout, each with
+    // a certificate of 2f+1 signed B answers committing its value. Instances nobody kept any longer are left out
+    pub fn backfill(&self, instances: Range<u64>, timeout: Duration) -> Vec<CommitRecord> {
+        let mut found: BTreeMap<u64, CommitRecord> = self.certificates(instances.clone()).into_iter().map(|commit| (commit.instance, commit)).collect();
+        let wanted = instances.end.saturating_sub(instances.start) as usize;
+        if found.len() >= wanted {
+            return found.into_values().collect();
+        }
+
+        self.commits.lock().unwrap().want(instances.clone());
+        Process::send_message(&self.peers, &mut Message::GetCertificates(self.id, instances), self.byzantine);
+
+        self.wakeup.wait_until(Some(Instant::now() + timeout), || {
+            let log = self.commits.lock().unwrap();
+            found.len() + log.backfilled().filter(|instance| !found.contains_key(instance)).count() >= wanted || self.stop_flag.load(Ordering::Relaxed)
+        });
+
+        for commit in self.commits.lock().unwrap().finish_backfill() {
+            found.entry(commit.instance).or_insert(commit);
+        }
+        found.into_values().collect()
+    }
+
+    // Instance of the next commit, the one this process takes part in once it caught up
+    pub fn next_instance(&self) -> u64 {
+        self.commits.lock().unwrap().next_instance()
+    }
+
+    // Asks the committee for the commits from the next instance this process has not seen
+    pub fn announce(&self) {
+        let next = self.commits.lock().unwrap().next_instance();
+        Process::send_message(&self.peers, &mut Message::Announce(self.id, next), self.byzantine);
+    }
+
+    // Tells the committee the version, features and committee of this process, unless every peer already heard them
+    pub fn hello(&self) {
+        let own = Hello::new(self.id, Features::of(&self.config), Hello::committee_hash(self.f, self.peers.len(), &self.identities));
+        if self.peers.table().say_hello(&own, self.peers.len()) {
+            Process::send_message(&self.peers, &mut Message::Hello(own), self.byzantine);
+        }
+    }
+
+    // Keys of the committee members whose final votes count
+    pub fn with_voters(self, voters: HashMap<Id, PublicKey>) -> Process {
+        self.votes.lock().unwrap().set_voters(voters);
+        self
+    }
+
+    // Randomness the values of each rank are ordered by, must be the same beacon on the whole committee
+    pub fn with_beacon(self, beacon: Arc<dyn RandomnessBeacon>) -> Process {
+        self.order.set_beacon(beacon);
+        self
+    }
+
+    // Only values the predicate holds for are answered, adopted and committed from now on
+    pub fn with_validity(self, predicate: Arc<dyn ValidityPredicate>) -> Process {
+        self.validity.set(predicate);
+        self
+    }
+
+    // Shares the predicate of the process, for the application to report Validity::changed
+    pub fn validity(&self) -> Validity {
+        self.validity.clone()
+    }
+
+    // Draws the delays of the timers from the seed instead of OS entropy, e.g. to replay a test
+    pub fn with_jitter_seed(self, seed: u64) -> Process {
+        self.jitter.reseed(seed);
+        self
+    }
+
+    // Timings of the instance, None unless built with the `profiling` feature or once the report was dropped for
+    // newer ones
+    pub fn profile(&self, instance: u64) -> Option<ProfileReport> {
+        self.profiler.report(instance)
+    }
+
+    // Reports of the latest instances, oldest first
+    pub fn profiles(&self) -> Vec<ProfileReport> {
+        self.profiler.reports()
+    }
+
+    // Delay below the bound to back off in the rank before acting again, see `ValueOrder::jitter`
+    pub fn backoff(&self, rank: Rank, bound: Duration) -> Duration {
+        self.order.jitter(rank, self.id, bound, &self.jitter)
+    }
+
+    // Signs the responses of this process with the key, and only counts responses signed by the key of their sender
+    pub fn with_identity(self, key: SigningKey, committee: HashMap<Id, VerifyingKey>) -> Process {
+        self.identities.set(key, committee);
+        // The keys are part of the configuration
+        if self.config.pin_committee {
+            self.identities.pin(self.identities.configuration(self.f, self.peers.len()));
+        }
+        self.hello();
+        self
+    }
+
+    // The rsnano account key of the representative this process votes as
+    pub fn with_voting_key(mut self, key: PrivateKey) -> Process {
+        self.voting_key = Some(key);
+        self
+    }
+
+    // Sends final votes for the frontiers to the whole committee, this process included, MAX_VOTE_HASHES per vote
+    // Returns false if the process has no voting key, or rejoined and has not reached its resume instance
+    pub fn vote(&self, frontiers: Vec<BlockHash>) -> bool {
+        let Some(key) = &self.voting_key else {
+            return false;
+        };
+        if self.commits.lock().unwrap().is_silent() {
+            return false;
+        }
+
+        for hashes in frontiers.chunks(MAX_VOTE_HASHES) {
+            Process::send_message(&self.peers, &mut Message::Vote(Vote::new_final(self.id, key, hashes.to_vec())), self.byzantine);
+        }
+        true
+    }
+
+    // Adds a vote received outside the messages of the committee, e.g. from the vote processor of a node
+    // Returns the frontiers it brought to 2f+1 final votes
+    pub fn add_vote(&self, vote: &Vote) -> Result<Vec<BlockHash>, VoteError> {
+        let mut votes = self.votes.lock().unwrap();
+        let new = votes.add(vote, false)?;
+        Ok(new.into_iter().filter(|frontier| votes.votes(frontier) == 2 * self.f + 1).collect())
+    }
+
+    // Frontiers final voted by 2f+1 members of the committee, in order
+    pub fn confirmed_frontiers(&self) -> Vec<BlockHash> {
+        self.votes.lock().unwrap().confirmed(2 * self.f + 1)
+    }
+
+    // Preproposal of the confirmed frontiers, for a committee running preconsensus on its own votes
+    pub fn preproposal(&self) -> PreProposal {
+        PreProposal::new(self.confirmed_frontiers(), self.id)
+    }
+
+    // A page of the frontiers of the preproposal received from the sender, None if there is none
+    pub fn preproposal_frontiers(&self, sender: Id, after: Option<BlockHash>, limit: usize) -> Option<FrontierPage> {
+        self.preproposals.write().unwrap().get(sender).map(|preproposal| preproposal.frontier_page(after, limit))
+    }
+
+    // A page of the frontiers of a received or decided proposal, None if it is unknown
+    pub fn proposal_frontiers(&self, hash: ProposalHash, after: Option<BlockHash>, limit: usize) -> Option<FrontierPage> {
+        let proposal = self.proposals.read().unwrap().values().find(|proposal| proposal.hash == hash).cloned()?;
+        let preproposals: Vec<PreProposal> = self.preproposals.read().unwrap().values().cloned().collect();
+        Some(proposal.frontier_page(&preproposals, after, limit))
+    }
+
+    // Alerts of this process from now on, stalls included
+    pub fn subscribe_alerts(&self) -> Receiver<Alert> {
+        self.alerts.subscribe()
+    }
+
+    // Snapshot of the run loop's state, None if it does not answer within the timeout (it is stopped, or stuck handling a message)
+    // Responses of the instance the process decides next, grouped by step and rank in canonical order, see ResponseGroup
+    // Read from the response store without going through the run loop, so it answers even when the run loop is stuck
+    pub fn snapshot_responses(&self) -> Vec<ResponseGroup> {
+        self.response_groups(self.next_instance())
+    }
+
+    pub(crate) fn response_groups(&self, instance: u64) -> Vec<ResponseGroup> {
+        let mut groups: Vec<ResponseGroup> = self
+            .responses
+            .snapshot()
+            .into_iter()
+            .map(|(step, rank, responses)| ResponseGroup::new(instance, step, rank, &responses))
+            .collect();
+        ResponseGroup::sort(&mut groups);
+        groups
+    }
+
+    pub fn dump_state(&self, timeout: Duration) -> Option<StateDump> {
+        let (reply, dump) = channel();
+        self.dumps.send(reply).ok()?;
+        dump.recv_timeout(timeout).ok()
+    }
+
+    pub fn ranks_since_commit(&self) -> Rank {
+        self.progress.ranks_since_commit()
+    }
+
+    // Raises an alert once the instance has gone through more ranks than the threshold without committing
+    fn check_convergence(&self, rank: Rank) -> bool {
+        let ranks = self.progress.ranks_since_commit();
+
+        match self.config.stall_ranks {
+            Some(threshold) if ranks > threshold => {
+                self.alerts.emit(Alert::RanksWithoutCommit { id: self.id, rank, ranks, threshold });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Alerts every stalled stage once, until the process is stopped. Checks the stall timeout as reloaded each time
+    fn watch(self) {
+        let mut reported = None;
+        let suspicion = self.peer_table().suspicion();
+        let mut suspected = BTreeSet::new();
+
+        while !self.stop_flag.load(Ordering::Relaxed) {
+            let timeout = self.live.get().stall_timeout.unwrap_or(Duration::MAX);
+            thread::sleep((timeout / 4).min(Duration::from_millis(100)));
+
+            match self.stall_report() {
+                Some(report) if reported != Some(report.stage) => {
+                    reported = Some(report.stage);
+                    for peer in &report.missing {
+                        suspicion.record(*peer, Offense::Silent);
+                    }
+                    self.alerts.emit(Alert::Stalled(report));
+                }
+                _ => (),
+            }
+
+            // Alerted once per peer until its score decays below the threshold again
+            let now: BTreeSet<Id> = suspicion.suspected().into_iter().collect();
+            for peer in now.difference(&suspected) {
+                self.alerts.emit(Alert::Suspected { id: self.id, peer: *peer, score: suspicion.score(*peer) as u64 });
+            }
+            suspected = now;
+        }
+    }
+
+    fn push_metrics(self, mut sink: StatsdSink, interval: Duration) {
+        while !self.stop_flag.load(Ordering::Relaxed) {
+            thread::sleep(interval);
+
+            // The daemon may not be up yet, the next interval tries again
+            if let Err(error) = self.report_metrics(&mut sink) {
+                debug!("{}: cannot push metrics: {}", self.id, error);
+            }
+        }
+    }
+
+    // Every metric of the process, as gauges named after the fields they come from
+    pub fn report_metrics(&self, sink: &mut dyn MetricsSink) -> io::Result<()> {
+        let stats = self.stats();
+        sink.gauge("instances_decided", stats.instances_decided as f64);
+        sink.gauge("ranks_to_commit", stats.ranks_to_commit as f64);
+        sink.gauge("adopts", stats.adopts as f64);
+        sink.gauge("fast_commits", stats.fast_commits as f64);
+        sink.gauge("equivocations", stats.equivocations as f64);
+        for (reason, count) in &stats.rejected {
+            sink.gauge(&format!("rejected.{}", reason.name()), *count as f64);
+        }
+        for (stage, count) in &stats.dropped {
+            sink.gauge(&format!("dropped.{}", stage.name()), *count as f64);
+        }
+        sink.gauge("ranks_since_commit", self.ranks_since_commit() as f64);
+
+        let latencies = self.latencies();
+        for step in [Step::R, Step::A, Step::B] {
+            latencies.step(step).report(&format!("latency.{:?}", step).to_lowercase(), sink);
+        }
+        latencies.commit.report("latency.commit", sink);
+
+        let memory = self.memory_usage();
+        sink.gauge("memory.held_bytes", memory.held_bytes as f64);
+        sink.gauge("memory.evictions", memory.evictions as f64);
+        sink.gauge("memory.evicted_bytes", memory.evicted_bytes as f64);
+        for (subsystem, bytes) in self.memory_breakdown().subsystems() {
+            sink.gauge(&format!("memory.{}_bytes", subsystem), bytes as f64);
+        }
+
+        for (peer, traffic) in self.peer_stats() {
+            sink.gauge(&format!("peer.{}.sent_messages", peer), traffic.sent_messages as f64);
+            sink.gauge(&format!("peer.{}.sent_bytes", peer), traffic.sent_bytes as f64);
+            sink.gauge(&format!("peer.{}.received_messages", peer), traffic.received_messages as f64);
+            sink.gauge(&format!("peer.{}.received_bytes", peer), traffic.received_bytes as f64);
+            sink.gauge(&format!("peer.{}.invalid", peer), traffic.invalid as f64);
+            sink.gauge(&format!("peer.{}.duplicates", peer), traffic.duplicates as f64);
+            sink.gauge(&format!("peer.{}.rate_limited", peer), traffic.rate_limited as f64);
+            sink.gauge(&format!("peer.{}.pending_evicted", peer), traffic.pending_evicted as f64);
+        }
+
+        // Peers whose latest hello differs from this process, so that operators see the outdated or misconfigured ones
+        for (peer, score) in self.peer_table().suspicion().scores() {
+            sink.gauge(&format!("suspicion.{}", peer), score);
+        }
+        let telemetry = self.peer_table().telemetry();
+        for mismatch in Mismatch::ALL {
+            let peers = telemetry.values().filter(|peer| peer.mismatches.contains(&mismatch)).count();
+            sink.gauge(&format!("mismatched_peers.{}", mismatch.name()), peers as f64);
+        }
+
+        // What the overflow policy dropped, so that operators see what was shed under pressure
+        let policy = self.config.overflow_policy.name();
+        for priority in Priority::ALL {
+            let shed: usize = self.peer_stats().values().map(|traffic| traffic.shed[priority as usize]).sum();
+            sink.gauge(&format!("shed.{}.{}", policy, priority.name()), shed as f64);
+        }
+
+        sink.flush()
     }
 
-    fn send_message(senders: &[Sender<Message>], message: &mut Message, byzantine: bool) {   
+    // Each broadcast starts the span of its step
+    fn send_broadcast(&mut self, broadcast: Broadcast) {
+        let trace = self.trace.step(self.id, broadcast.step, broadcast.rank);
+        let broadcast = self.identities.sign_broadcast(broadcast.with_trace(trace).pinned(self.identities.pinned()));
+        let broadcast = if self.config.certificates_by_reference {
+            self.profiler.time(Probe::Hashing, || broadcast.into_reference())
+        } else {
+            broadcast
+        };
+
+        #[cfg(debug_assertions)]
+        {
+            let sent = self.invariants.lock().unwrap().sent(&broadcast);
+            if let Err(violation) = sent {
+                panic!("{}: invariant violated: {:?}", self.id, violation);
+            }
+        }
+
+        // Only this process and the coordinator get it, unless this process coordinates the step
+        let coordinator = Process::coordinator(broadcast.step, broadcast.rank, self.peers.len());
+        let recipients: Vec<usize> = match self.config.coordinated_broadcasts {
+            Some(_) if coordinator != self.id => vec![self.id as usize, coordinator as usize],
+            _ => (0..self.peers.len()).collect(),
+        };
+
+        // The run loop of this process gets the certificate, to serve it to the processes that ask for it
+        if self.config.lazy_certificates && broadcast.previous_step_responses.is_some() {
+            let mut lazy = Message::Broadcast(self.profiler.time(Probe::Hashing, || broadcast.clone().into_lazy()));
+            if self.byzantine {
+                Process::apply_byzantine_behavior(&mut lazy);
+            }
+
+            for peer in recipients {
+                let message = if peer as Id == self.id { Message::Broadcast(broadcast.clone()) } else { lazy.clone() };
+                self.peers.send(peer, message);
+            }
+            return;
+        }
+
+        let mut message = Message::Broadcast(broadcast);
+        if self.byzantine {
+            Process::apply_byzantine_behavior(&mut message);
+        }
+        for peer in recipients {
+            self.peers.send(peer, message.clone());
+        }
+    }
+
+    // Relays the broadcasts of the step and rank to the committee in coordinated mode, a different process for each
+    // step of each rank so that a faulty coordinator only delays the steps it coordinates
+    pub fn coordinator(step: Step, rank: Rank, n: usize) -> Id {
+        (rank * 3 + step as Rank).rem_euclid(n.max(1) as Rank) as Id
+    }
+
+    fn send_message(peers: &PeerQueues, message: &mut Message, byzantine: bool) {   
         if byzantine {
             Process::apply_byzantine_behavior(message);
         }
             
-        for sender in senders {
-            sender.send(message.clone()).unwrap_or_else(|e| {
-                eprintln!("Failed to send message: {}", e);
-            });
+        peers.send_all(message);
+    }
+
+    // Responses are queued in the outbox of the run loop, broadcasts are sent right away since they mark a step boundary
+    fn queue_message(outbox: &mut Outbox, mut message: Message, byzantine: bool) {
+        if byzantine {
+            Process::apply_byzantine_behavior(&mut message);
         }
+
+        outbox.push(message);
     }
 
-    fn apply_byzantine_behavior(message: &mut Message) {
+    pub(crate) fn apply_byzantine_behavior(message: &mut Message) {
         let mut rng = rand::thread_rng();
         match message {
             Message::Broadcast(broadcast) => {
@@ -213,6 +780,7 @@ impl Process {
                     2 => broadcast.flag = Some(rng.gen_bool(0.5)),
                     _ => unreachable!(),
                 }
+                broadcast.rehash();
             }
        
             Message::Response(response) => {
@@ -226,54 +794,173 @@ impl Process {
                     _ => unreachable!(),
                 }
             }
+            // A value nobody proposed, which honest processes do not count
+            Message::PreVote(_, value) => *value = BlockHash::from(rng.gen::<u64>()),
             _ => ()
         }
     }
 
     pub fn propose(&mut self, threshold: usize, value: PreProposal, rank: Rank) -> Proposal {
+        self.trace.start(self.id);
+        self.profiler.start(self.next_instance());
+        let Some(proposal) = self.preproposal_step(threshold, value) else {
+            return self.stopped();
+        };
+        let value = match self.config.pre_vote_timeout {
+            Some(timeout) => self.pre_vote(threshold, rank, proposal.hash, timeout),
+            None => proposal.hash,
+        };
+
+        let Some((mut r_value, mut r_certificate)) = self.r_step(threshold, RValue::new(rank, value), None) else {
+            return self.stopped();
+        };
+        let mut alerted = false;
+
         loop {
             if self.stop_flag.load(Ordering::Relaxed) {
-                return Proposal::default();
+                return self.stopped();
             }
 
-            let proposal = self.preproposal_step(threshold, value.clone());
-
-            let r_value = self.r_step(threshold, RValue::new(rank, proposal.hash));
-
-            let (flag, a_value) = self.a_step(threshold, r_value);
+            let Some((flag, a_value, a_certificate)) = self.a_step(threshold, r_value, r_certificate) else {
+                return self.stopped();
+            };
 
-            let decision = self.b_step(threshold, r_value.rank, flag, a_value);
+            let Some((decision, b_certificate)) = self.b_step(threshold, r_value.rank, flag, a_value, a_certificate) else {
+                return self.stopped();
+            };
+            debug!("{}: {:?} at rank {}", self.id, decision, r_value.rank);
             
             match decision {
                 Decision::Commit(val) => {
-                    let proposals = self.proposals.read().unwrap();
-                    let proposal = proposals.iter().find(|(_, proposal)| proposal.hash == val).unwrap().1;
-                    
-                    return proposal.clone()
+                    if !self.await_validity(val) {
+                        return self.stopped();
+                    }
+                    self.progress.enter(Stage::Decided(r_value.rank));
+                    self.consensus.decided(rank, r_value.rank);
+                    self.commit(val, r_value.rank, b_certificate);
+                    self.trace.finish(Some(r_value.rank));
+
+                    // Every empty proposal is the same, whoever sent it
+                    if val == bottom_value() {
+                        return Proposal::empty(self.id);
+                    }
+
+                    // Prioritized peer queues send broadcasts ahead of proposals, so the decided one may still be on
+                    // its way. It is waited for until it arrives or the process is stopped
+                    let mut decided = None;
+                    self.wakeup.wait_until(None, || {
+                        decided = self.proposals.read().unwrap().values().find(|proposal| proposal.hash == val).cloned();
+                        decided.is_some() || self.stop_flag.load(Ordering::Relaxed)
+                    });
+                    return decided.unwrap_or_default();
                 },
-                Decision::Adopt(val) => self.r_step(threshold, RValue::new(rank + 1, val))
+                // Line 17: the next rank starts from the adopted value, with the B answers as its certificate
+                Decision::Adopt(val) => {
+                    self.consensus.adopted();
+                    // A rank whose certificate the pacemaker knows is started right away, instead of the next one
+                    let caught_up = self.pacemaker.lock().unwrap().catch_up(r_value.rank + 1);
+                    let next = match caught_up {
+                        Some((highest, certificate)) => self.r_step(threshold, highest, Some(certificate)),
+                        None => self.r_step(threshold, RValue::new(r_value.rank + 1, val), None),
+                    };
+                    let Some(next) = next else {
+                        return self.stopped();
+                    };
+                    (r_value, r_certificate) = next;
+                    alerted = alerted || self.check_convergence(r_value.rank);
+                }
             };
         }
     }
 
-    fn preproposal_step(&self, threshold: usize, value: PreProposal) -> Proposal {
-        Process::send_message(&self.senders, &mut Message::PreProposal(value), self.byzantine);
+    // A stopped proposer returns the default proposal
+    fn stopped(&mut self) -> Proposal {
+        self.trace.finish(None);
+        Proposal::default()
+    }
+
+    // A value is only committed once the validity predicate holds for it, which it may not yet if this process has not
+    // validated it when 2f+1 others did. False if the process is stopped first
+    fn await_validity(&self, value: ProposalHash) -> bool {
+        if !self.validity.holds(value) {
+            warn!("{}: decided a value the validity predicate does not hold for yet, committing once it does", self.id);
+        }
+        self.wakeup.wait_until(None, || self.validity.holds(value) || self.stop_flag.load(Ordering::Relaxed));
+        !self.stop_flag.load(Ordering::Relaxed)
+    }
+
+    fn commit(&self, value: ProposalHash, rank: Rank, certificate: CertificateResponses) {
+        self.preproposals.write().unwrap().unpin_all();
+        // Pre-votes are of the instance too, a later one counts fresh ones
+        self.pre_votes.write().unwrap().clear();
+        let mut commits = self.profiler.lock(&self.commits);
+        let mut transcript = self.profiler.lock(&self.transcript);
+        let recorded = transcript.recording().then(|| CommitRecord { instance: commits.next_instance(), rank, value, certificate: certificate.clone(), committee: None });
 
-        loop {
+        if let Err(error) = commits.record(rank, value, certificate) {
+            warn!("{}: cannot append the commit of rank {} to the audit log: {}", self.id, rank, error);
+        }
+        if let Some(member) = Rejoin::reconfigure(value, &self.peers.table(), &self.identities, self.f) {
+            debug!("{}: took {} back at instance {}", self.id, member, commits.next_instance() - 1);
+        }
+        if let Some(commit) = recorded {
+            let finished = self.profiler.time(Probe::Serialization, || transcript.finish(self.id, self.f, self.config.forced_adopt_ranks(), &self.identities, commit));
+            if let Err(error) = finished {
+                warn!("{}: cannot write the transcript of rank {}: {}", self.id, rank, error);
+            }
+        }
+    }
+
+    // None if the process is stopped first
+    fn preproposal_step(&self, threshold: usize, value: PreProposal) -> Option<Proposal> {
+        self.progress.enter(Stage::PreProposal);
+        Process::send_message(&self.peers, &mut Message::PreProposal(value), self.byzantine);
+
+        let mut proposal = None;
+        self.wakeup.wait_until(None, || {
             let preproposals = self.preproposals.read().unwrap();
 
             if preproposals.len() >= threshold {
-                let proposal = Proposal::new(preproposals.values().cloned().map(|x| x.hash).collect(), self.id);
+                // Nothing to propose anywhere, the proposal is the bottom value
+                proposal = Some(match preproposals.values().all(PreProposal::is_empty) {
+                    true => Proposal::empty(self.id),
+                    false => Proposal::new(preproposals.values().map(|x| x.hash()).collect(), self.id),
+                });
+            }
+            proposal.is_some() || self.stop_flag.load(Ordering::Relaxed)
+        });
+
+        let proposal = proposal?;
+        Process::send_message(&self.peers, &mut Message::Proposal(proposal.clone()), self.byzantine);
+        Some(proposal)
+    }
 
-                Process::send_message(&self.senders, &mut Message::Proposal(proposal.clone()), self.byzantine);
+    // Advertises the value and returns the most frequent of the 2f+1 pre-votes received within the timeout, or of the
+    // ones received by then. Only values whose proposal the process holds and the validity predicate holds for count,
+    // ties go to the greatest value of the rank. Any value is safe to start with, this only saves ranks
+    fn pre_vote(&self, threshold: usize, rank: Rank, value: ProposalHash, timeout: Duration) -> ProposalHash {
+        Process::send_message(&self.peers, &mut Message::PreVote(self.id, value), self.byzantine);
 
-                return proposal;
+        self.wakeup.wait_until(Some(Instant::now() + timeout), || self.pre_votes.read().unwrap().len() >= threshold || self.stop_flag.load(Ordering::Relaxed));
+
+        let mut counts: HashMap<ProposalHash, usize> = HashMap::from([(value, 0)]);
+        {
+            let proposals = self.proposals.read().unwrap();
+            let known = |pre_vote: &ProposalHash| *pre_vote == value || proposals.values().any(|proposal| proposal.hash == *pre_vote);
+            for pre_vote in self.pre_votes.read().unwrap().values().filter(|pre_vote| known(pre_vote) && self.validity.holds(**pre_vote)) {
+                *counts.entry(*pre_vote).or_insert(0) += 1;
             }
         }
+
+        let most = counts.values().copied().max().unwrap_or(0);
+        self.order.max(rank, counts.into_iter().filter(|(_, count)| *count == most), |(value, _)| *value).map_or(value, |(value, _)| value)
     }
 
     // Line 15: procedure R-Step(v)
-    fn r_step(&mut self, threshold: usize, r_value: RValue) -> RValue {
+    // Returns the R value together with the certificate of the A-Step
+    // Starts from the given certificate of B answers of the rank before, if any, instead of waiting for one
+    // None if the process is stopped first, as for the other steps
+    fn r_step(&mut self, threshold: usize, r_value: RValue, certificate: Option<CertificateResponses>) -> Option<(RValue, CertificateResponses)> {
         let rank = r_value.rank;
         let value = r_value.value;
 
@@ -281,229 +968,120 @@ impl Process {
         // Line 90: To compile a broadcast certificate, list all 2f + 1 answers to the previous step broadcast received during the previous step.
         // Line 17: broadcast(R, i, v, C) 
         if rank > 0 {
-            let key = (Step::B, rank - 1);
-
-            loop {
-                let responses = self.responses.read().unwrap();
-                if responses.get(&key).map(|m| m.len()).unwrap_or(0) == threshold {
-                    break;
-                }
-            }
-                                
-            let responses = self.responses.read().unwrap().get(&key).unwrap().values().cloned().collect();
+            let responses = match certificate {
+                Some(certificate) => certificate,
+                None => self.responses.wait_for_quorum(Step::B, rank - 1, threshold)?,
+            };
                     
             let broadcast = Broadcast::new(self.id, Step::R, value, None, rank, Some(responses));
             
-            Process::send_message(&self.senders, &mut Message::Broadcast(broadcast), self.byzantine);
+            self.send_broadcast(broadcast);
         }
         else {
             let broadcast = Broadcast::new(self.id, Step::R, r_value.value, None, r_value.rank, None);
                 
-            Process::send_message(&self.senders, &mut Message::Broadcast(broadcast), self.byzantine);
+            self.send_broadcast(broadcast);
         }
 
-        let key = (Step::R, rank);
-
         // Line 18/19: wait until (receive valid (Rresp, i, R, C) from 2f + 1 processes)
-        loop {
-            let responses = self.responses.read().unwrap();
-            if responses.get(&key).map(|m| m.len()).unwrap_or(0) == threshold {
-                let response_vec = responses.get(&key)
-                    .unwrap()
-                    .values()
-                    .cloned()
-                    .collect::<Vec<Response>>();
-
-                let max_value = Self::process_r_responses(&response_vec);
-                
-                // Line 22: R ← max(R)
-                return max_value;
-            }
-        }
+        self.progress.enter(Stage::Step(Step::R, rank));
+        // Lines 20/21 are folded in as the responses arrive
+        let mut tally = RTally::default().ordered(self.order.clone());
+        let certificate = self.responses.wait_for_quorum_with(Step::R, rank, threshold, |response| tally.add(response))?;
+        
+        // Line 22: R ← max(R)
+        Some((tally.result(), certificate))
     }
 
-    fn process_r_responses(responses: &[Response]) -> RValue {
-        // Line 20: R ← union of all valid Rs received in previous line (the paper has a typo?)
-        let r_values: Vec<RValue> = responses
-            .iter()
-            .filter_map(|response| {
-                for state in &response.state {
-                    if let Value::RValue(r_value) = &state.value {
-                        return Some(r_value);
-                    }
-                }
-                None
-            })
-            .cloned()
-            .collect();
-
-        // Line 21: ⟨i’,v’⟩ ← max(R)
-        *r_values.iter().max().unwrap()
+    fn process_r_responses(responses: &[Response], order: &ValueOrder) -> Option<RValue> {
+        let mut tally = RTally::default().ordered(order.clone());
+        responses.iter().for_each(|response| tally.add(response));
+        tally.value()
     }
 
     // Line 25: Upon delivering (R, j, v, C) from p
     fn answer_r_broadcast(
         id: Id,
         broadcast: &Broadcast,
-        senders: &[Sender<Message>],
-        r_set: &R,
-        broadcasts: &Broadcasts,
+        outbox: &mut Outbox,
+        registers: &Registers,
+        broadcasts: &BroadcastStore,
+        identities: &Identities,
         byzantine: bool
     ) {
-        let broadcast_r_value = RValue::new(broadcast.rank, broadcast.value);
-
         // Line 27: R ← max(⟨j, v⟩, R)
-        let max_r_value = max(broadcast_r_value, *r_set.read().unwrap());
-        
-        {
-            *r_set.write().unwrap() = max_r_value;
-        }
+        let max_r_value = registers.update_r(RValue::new(broadcast.rank, broadcast.value));
         
         // Line 28: b ← bcast responsible for R’s value (the paper has a typo?)
-        let response_broadcast = {
-            broadcasts
-                .iter()
-                .find(|(b, _)| matches!(b, rb if rb.value == max_r_value.value && rb.rank == max_r_value.rank && rb.step == broadcast.step))
-                .map(|(b, _)| b.clone())
-                .unwrap()
-        };
+        let response_broadcast = broadcasts.find(broadcast.step, max_r_value.rank, max_r_value.value, None).unwrap();
 
         // Page 9: A broadcast from pi justifies a response from pj for an R-Step if it contains the highest value encountered that appears in pj response.
         let response = Response::new(
             id,
             Step::R,
             broadcast.rank,
-            vec![State::new(Value::RValue(max_r_value), response_broadcast)],
-        );
+            smallvec![State::new(Value::RValue(max_r_value), response_broadcast)],
+        ).answering(broadcast.hash_value());
 
         // Line 29: send(Rresp, j, R, sig, b) to all
-        Process::send_message(senders, &mut Message::Response(response), byzantine);
+        Process::queue_message(outbox, Message::Response(identities.sign(response)), byzantine);
     }
 
     // Line 31: Procedure A-Step(i, v)
-    fn a_step(&mut self, threshold: usize, r_value: RValue) -> (bool, ProposalHash) {
+    fn a_step(&mut self, threshold: usize, r_value: RValue, certificate: CertificateResponses) -> Option<(bool, ProposalHash, CertificateResponses)> {
         let value = r_value.value;
         let rank = r_value.rank;
 
-        let key = (Step::R, rank);
-
         // Line 32: compile certificate C
-        let responses = self.responses.read().unwrap().get(&key).unwrap().values().cloned().collect();
-        
-        let broadcast = Broadcast::new(self.id, Step::A, value, None, rank, Some(responses));
+        let broadcast = Broadcast::new(self.id, Step::A, value, None, rank, Some(certificate));
 
         // Line 33: broadcast(A, i, v, C)
-        Process::send_message(&self.senders, &mut Message::Broadcast(broadcast), self.byzantine);
+        self.send_broadcast(broadcast);
         
-        let key = (Step::A, rank);
-
         // Lines 34/35:  wait until receive valid (Aresp, i, A[i]) 35: from 2f + 1 processes
-        loop {
-            let responses = self.responses.read().unwrap();
-            if responses.get(&key).map(|m| m.len()).unwrap_or(0) == threshold {
-                let response_vec = responses.get(&key)
-                    .unwrap()
-                    .values()
-                    .cloned()
-                    .collect::<Vec<Response>>();
-                
-                return Self::process_a_responses(&response_vec, threshold);
-            }
-        }
-    }
-
-    fn process_a_responses(responses: &[Response], threshold: usize) -> (bool, ProposalHash) {
-        // Line 36: S ← union of all A[i]s received
-        let a_values: Vec<AValue> = responses
-            .iter()
-            .filter_map(|response| {
-                for state in &response.state {
-                    if let Value::AValue(a_value) = state.value {
-                        return Some(a_value);
-                    }
-                }
-                None
-            })
-            .collect();
+        self.progress.enter(Stage::Step(Step::A, rank));
+        let mut tally = ATally::new(threshold).forcing_adopt(rank < self.config.forced_adopt_ranks()).ordered(self.order.clone());
+        let certificate = self.responses.wait_for_quorum_with(Step::A, rank, threshold, |response| tally.add(response))?;
+        let (flag, value) = tally.result();
         
-        let mut value_counts: HashMap<AValue, usize> = HashMap::new();
-        let mut max_value = a_values.first().map_or(AValue(BlockHash::zero()), |v| *v);
-
-        for a_value in &a_values {
-            *value_counts.entry(*a_value).or_insert(0) += 1;            
-            if a_value.0 > max_value.0 {
-                max_value = *a_value;
-            }
-        }
-
-        // Line 37/38: if (S contains at least 2f+1 A-answers containing only val)
-        for (val, count) in value_counts.iter() {
-            if *count >= threshold {
-                // Line 39: return ⟨true, val⟩
-                return (true, val.0);
-            }
-        }
+        Some((flag, value, certificate))
+    }
 
-        // Line 40: else return ⟨false, max(S)⟩
-        (false, max_value.0)
+    fn process_a_responses(responses: &[Response], threshold: usize, forced_adopt: bool, order: &ValueOrder) -> (bool, ProposalHash) {
+        let mut tally = ATally::new(threshold).forcing_adopt(forced_adopt).ordered(order.clone());
+        responses.iter().for_each(|response| tally.add(response));
+        tally.result()
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn answer_a_broadcast(
         id: Id,
         broadcast: &Broadcast,
-        senders: &[Sender<Message>],
-        a_sets: &A,
-        broadcasts: &Broadcasts,
-        byzantine: bool
+        outbox: &mut Outbox,
+        registers: &Registers,
+        broadcasts: &BroadcastStore,
+        identities: &Identities,
+        byzantine: bool,
+        aggregate: bool
     ) {
-        let j = broadcast.rank as usize;
-        let broadcast_value = AValue(broadcast.value);
-        
-        {
-            let mut a_sets_write = a_sets.write().unwrap();
-            if a_sets_write.len() > j {
-                // Line 43: if v /∈ A[j] and |A[j]| < 2
-                if !a_sets_write.contains(&broadcast_value) && a_sets_write.len() < 2 {
-                    // Line 44: add v to A[j]
-                    a_sets_write.push(broadcast_value);
-                // Line 45: v > max(A[j])
-                } else if broadcast_value > *a_sets_write.iter().max().unwrap() {
-                    let min = a_sets_write.iter().min().unwrap();
-                    if let Some(index) = a_sets_write.iter().position(|&x| x == *min) {
-                        // Line 46: min(A[j]) ← v
-                        a_sets_write[index] = broadcast_value;
-                    }
-                }
-            }
-            else {
-                a_sets_write.push(broadcast_value);
-            }
-        }
+        // Lines 43-46
+        let current_a_sets = registers.update_a(broadcast.rank, AValue(broadcast.value));
 
         let mut sent_values = HashSet::new();
-        
-        let current_a_sets = {
-            a_sets.read().unwrap().clone().clone()
-        };
 
         /* Page 9: A broadcast from pi justifies a response from pj for an A-Step, if it contains 
         the highest value v and, if possible, 
         any value from the response different from v. */
 
-        let mut a_states = Vec::new();
+        let mut a_states = States::new();
         
         for a_state in current_a_sets.iter() {
             if sent_values.insert(a_state.0) {
                 // Line 47: b ← bcast responsible for A[j]’s value
-                let response_broadcast = {
-                    broadcasts
-                        .iter()
-                        .find(|(b, _)| matches!(b, rb if rb.value == a_state.0 && rb.rank == broadcast.rank && rb.step == broadcast.step))
-                        .map(|(b, _)| b.clone())
-                };
+                let response_broadcast = broadcasts.find(broadcast.step, broadcast.rank, a_state.0, None);
 
                 if let Some(response_broadcast) = response_broadcast {
-                    a_states.push(State::new(Value::AValue(*a_state), response_broadcast.clone()));
+                    a_states.push(State::new(Value::AValue(*a_state), Process::justifying(response_broadcast, aggregate)));
                 }
             }
         }
@@ -513,136 +1091,64 @@ impl Process {
             Step::A,
             broadcast.rank,
             a_states,
-        );
+        ).answering(broadcast.hash_value());
         
         // Line 48: send(Aresp, j, A[j], sig, b) to all
-        Process::send_message(senders, &mut Message::Response(response), byzantine);
+        Process::queue_message(outbox, Message::Response(identities.sign(response)), byzantine);
     }
 
-    fn b_step(&mut self, threshold: usize, rank: Rank, flag: bool, value: ProposalHash) -> Decision {
+    // Returns the decision together with the B answers it was taken on
+    fn b_step(&mut self, threshold: usize, rank: Rank, flag: bool, value: ProposalHash, certificate: CertificateResponses) -> Option<(Decision, CertificateResponses)> {
         // Line 51: compile certificate C
-        let key = (Step::A, rank);
-                
-        let responses = self.responses.read().unwrap().get(&key).unwrap().values().cloned().collect();
-        
-        let broadcast = Broadcast::new(self.id, Step::B, value, Some(flag), rank, Some(responses));
+        let broadcast = Broadcast::new(self.id, Step::B, value, Some(flag), rank, Some(certificate));
         
         // Line 52: broadcast(B, i, , v, C)
-        Process::send_message(&self.senders, &mut Message::Broadcast(broadcast), self.byzantine);
+        self.send_broadcast(broadcast);
         
-        let key = (Step::B, rank);
-
         // Line 53/54:  wait until receive valid (Bresp, i, B[i]) from 2f + 1 proc.
-        loop {
-            let responses = self.responses.read().unwrap();
-            if responses.get(&key).map(|m| m.len()).unwrap_or(0) == threshold {
-                let response_vec = responses.get(&key)
-                    .unwrap()
-                    .values()
-                    .cloned()
-                    .collect::<Vec<Response>>();
-                
-                return Self::process_b_responses(&response_vec, threshold);
+        self.progress.enter(Stage::Step(Step::B, rank));
+        let mut tally = BTally::new(threshold).ordered(self.order.clone());
+        let certificate = self.responses.wait_for_quorum_with(Step::B, rank, threshold, |response| tally.add(response))?;
+
+        // The adopt stands if no commit comes, justified by the first 2f+1 answers
+        let decision = tally.result();
+        if let (Decision::Adopt(_), Some(window)) = (&decision, self.config.fast_commit_window) {
+            if let Some(fast_certificate) = Process::await_commit(&self.responses, rank, &mut tally, &certificate, window) {
+                self.consensus.fast_committed();
+                return Some((tally.result(), fast_certificate));
             }
-        } 
+        }
+        Some((decision, certificate))
     }
 
-    fn process_b_responses(responses: &[Response], threshold: usize) -> Decision {
-        // Line 55: S ← array with all B[i]s received
-        let b_values: Vec<BValue> = responses
-            .iter()
-            .filter_map(|response| {
-                for state in &response.state {
-                    if let Value::BValue(b_value) = state.value {
-                        return Some(b_value);
-                    }
-                }
-                None
-            })
-            .collect();
-
-        let true_values: Vec<&BValue> = b_values.iter()
-            .filter(|&b_value| b_value.flag)
-            .collect();
-        
-        // Line 56: if |{⟨true, val⟩ ∈ S}| ≥ 2f + 1
-        if true_values.len() >= threshold {
-            let value = true_values.first().unwrap().value;
-
-            // Line 57: return ⟨commit, val⟩
-            Decision::Commit(value)
-        }
-        // Line 58: else if |{⟨true, val⟩ ∈ S}| ≥ 1 then
-        else if !true_values.is_empty() {
-            let value = true_values.first().unwrap().value;
-            
-            // Line 59: return ⟨adopt, val⟩
-            Decision::Adopt(value)
-        }
-        else {
-            let max_value = b_values.iter()
-                .max_by_key(|b_value| b_value.value)
-                .map(|b_value| b_value.value)
-                .unwrap();
+    // Tallies the B answers that come after the ones seen until they commit, 2f+1 of them holding only the same true
+    // pair, or the window passes. Returns the answers that commit
+    fn await_commit(responses: &ResponseStore, rank: Rank, tally: &mut BTally, seen: &CertificateResponses, window: Duration) -> Option<CertificateResponses> {
+        responses.wait_for_more(Step::B, rank, seen, window, |response| {
+            tally.add(response);
+            matches!(tally.result(), Decision::Commit(_))
+        })
+    }
 
-            // Line 60: else return ⟨adopt, max(S)⟩
-            Decision::Adopt(max_value)
-        }
+    fn process_b_responses(responses: &[Response], threshold: usize, order: &ValueOrder) -> Decision {
+        let mut tally = BTally::new(threshold).ordered(order.clone());
+        responses.iter().for_each(|response| tally.add(response));
+        tally.result()
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn answer_b_broadcast(
         id: Id,
         broadcast: &Broadcast,
-        senders: &[Sender<Message>],
-        b_sets: &B,
-        broadcasts: &Broadcasts,
-        byzantine: bool
+        outbox: &mut Outbox,
+        registers: &Registers,
+        broadcasts: &BroadcastStore,
+        identities: &Identities,
+        byzantine: bool,
+        aggregate: bool
     ) {
-        let len = {
-            b_sets.read().unwrap().len()
-        };
-        let j = broadcast.rank as usize;
-
-        let value = broadcast.value;
-        let flag = broadcast.flag.unwrap();
-        let b_value = BValue::new(value, flag);
-        let mut b_values = b_sets.write().unwrap();
-        
-        if len > j {
-            let len = b_values.len();
-
-            // Line 63: m ← max(B[j][0].v, B[j][1].v)
-            let m = match len {
-                0 => BlockHash::zero(),
-                1 => b_values[0].value,
-                _ => max(b_values[0].value, b_values[1].value)
-            };
-
-            if len < 2 {
-                {
-                    // Line 64: if |B[j]| < 2 then add ⟨bool, v⟩ to B[j]
-                    b_values.push(b_value);
-                }
-            }
-            else {
-                let contains_flag_value = {
-                    b_values.iter().any(|value| value == &b_value)
-                };
-                
-                // Lines 65/66: else if(flag ∧ ⟨flag, v⟩ ∈/ B[j] ∨ ¬flag ∧ v > m) then
-                if (flag && !contains_flag_value) || (!flag && value > m) {
-                    // Line 67: B[j][0] ← ⟨flag, v⟩
-                    {
-                        b_values[0] = b_value;
-                    }
-                }
-            }
-        }
-        else {
-            {
-                b_values.push(b_value);
-            }
-        }
+        // Lines 63-67
+        let b_values = registers.update_b(broadcast.rank, BValue::new(broadcast.value, broadcast.flag.unwrap()));
 
         /* Page 9: For a broadcast from pi to justify a response from pj for a B-Step, it must ensures the following: 
         if the response contains only true, then the broadcast should contain true; 
@@ -660,82 +1166,103 @@ impl Process {
         if !true_pairs.is_empty() && false_pairs.is_empty() {
             let b_value = *true_pairs[0];
 
-            let response_broadcast = broadcasts
-                .iter()
-                .find(|(b, _)| matches!(b, rb if rb.value == b_value.value && rb.rank == broadcast.rank && rb.step == broadcast.step && rb.flag == broadcast.flag))
-                .unwrap()
-                .0
-                .clone();
+            let response_broadcast = broadcasts.find(broadcast.step, broadcast.rank, b_value.value, Some(b_value.flag)).unwrap();
 
             let response = Response::new(
                 id, 
                 Step::B,
                 broadcast.rank, 
-                vec![State::new(Value::BValue(b_value), response_broadcast)], 
-            );
+                smallvec![State::new(Value::BValue(b_value), Process::justifying(response_broadcast, aggregate))], 
+            ).answering(broadcast.hash_value());
 
-            Process::send_message(senders, &mut Message::Response(response), byzantine);
+            Process::queue_message(outbox, Message::Response(identities.sign(response)), byzantine);
         }
         else if !true_pairs.is_empty() && !false_pairs.is_empty() {
             let mut b_state = Vec::new();
 
             let b_value_true = *true_pairs[0];
 
-            let response_broadcast_true = broadcasts
-                .iter()
-                .find(|(b, _)| matches!(b, rb if rb.value == b_value_true.value && rb.rank == broadcast.rank && rb.step == broadcast.step && rb.flag == broadcast.flag))
-                .unwrap()
-                .0
-                .clone();
+            let response_broadcast_true = broadcasts.find(broadcast.step, broadcast.rank, b_value_true.value, Some(b_value_true.flag)).unwrap();
 
-            b_state.push(State::new(Value::BValue(b_value_true), response_broadcast_true));
+            b_state.push(State::new(Value::BValue(b_value_true), Process::justifying(response_broadcast_true, aggregate)));
         
             let b_value_false = *false_pairs.iter()
-                .max_by_key(|b_state| b_state.value)
+                .max_by_key(|b_state| registers.order().key(broadcast.rank, b_state.value))
                 .unwrap();
 
-            let response_broadcast_false = broadcasts
-                .iter()
-                .find(|(b, _)| matches!(b, rb if rb.value == b_value_false.value && rb.rank == broadcast.rank && rb.step == broadcast.step && rb.flag == broadcast.flag))
-                .unwrap()
-                .0
-                .clone();
+            let response_broadcast_false = broadcasts.find(broadcast.step, broadcast.rank, b_value_false.value, Some(b_value_false.flag)).unwrap();
 
-            b_state.push(State::new(Value::BValue(*b_value_false), response_broadcast_false));
+            b_state.push(State::new(Value::BValue(*b_value_false), Process::justifying(response_broadcast_false, aggregate)));
 
             let response = Response::new(
                 id, 
                 Step::B,
                 broadcast.rank, 
                 b_state, 
-            );
+            ).answering(broadcast.hash_value());
 
-            Process::send_message(senders, &mut Message::Response(response), byzantine);
+            Process::queue_message(outbox, Message::Response(identities.sign(response)), byzantine);
         }
         else if true_pairs.is_empty() && !false_pairs.is_empty() {                                        
             let highest_false = false_pairs.iter()
-                .max_by_key(|b_state| b_state.value)
+                .max_by_key(|b_state| registers.order().key(broadcast.rank, b_state.value))
                 .unwrap();
 
-            let response_broadcast = broadcasts
-                .iter()
-                .find(|(b, _)| matches!(b, rb if rb.value == highest_false.value && rb.rank == broadcast.rank && rb.step == broadcast.step && rb.flag == broadcast.flag))
-                .unwrap()
-                .0
-                .clone();
+            let response_broadcast = broadcasts.find(broadcast.step, broadcast.rank, highest_false.value, Some(highest_false.flag)).unwrap();
             
             let response = Response::new(
                 id, 
                 Step::B,
                 broadcast.rank, 
-                vec![State::new(Value::BValue(**highest_false), response_broadcast)], 
-            );
+                smallvec![State::new(Value::BValue(**highest_false), Process::justifying(response_broadcast, aggregate))], 
+            ).answering(broadcast.hash_value());
+
+            Process::queue_message(outbox, Message::Response(identities.sign(response)), byzantine);
+        }
+    }
+
+    // Only the header of the broadcast when aggregating, the response then does not nest its certificate
+    fn justifying(broadcast: Arc<Broadcast>, aggregate: bool) -> Arc<Broadcast> {
+        if aggregate { Arc::new(broadcast.header()) } else { broadcast }
+    }
 
-            Process::send_message(senders, &mut Message::Response(response), byzantine);
+    // Stateless part of the checks performed by the run loop, used by the validation workers
+    pub(crate) fn validate_message(message: &Message, f: usize, forced_adopt_ranks: Rank, order: &ValueOrder, identities: &Identities) -> bool {
+        Process::message_rejection(message, f, forced_adopt_ranks, order, identities).is_none()
+    }
+
+    // Stage that drops the message, None if it is valid
+    pub(crate) fn message_rejection(message: &Message, f: usize, forced_adopt_ranks: Rank, order: &ValueOrder, identities: &Identities) -> Option<RejectionStage> {
+        match message {
+            Message::Broadcast(broadcast) => Process::broadcast_rejection(broadcast, f, forced_adopt_ranks, order, identities).map(|reason| reason.stage()),
+            Message::Response(response) if !Process::validate_response(response) || !identities.accepts(response.committee) => Some(RejectionStage::Structure),
+            Message::Response(response) => (!identities.verify(response)).then_some(RejectionStage::Signature),
+            Message::Vote(vote) if vote.hashes.len() > MAX_VOTE_HASHES => Some(RejectionStage::Structure),
+            Message::Vote(vote) => (!vote.verify()).then_some(RejectionStage::Signature),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn broadcast_rejection(broadcast: &Broadcast, f: usize, forced_adopt_ranks: Rank, order: &ValueOrder, identities: &Identities) -> Option<Rejection> {
+        if !identities.accepts(broadcast.committee) {
+            return Some(Rejection::ForeignCommittee);
+        }
+        if !Process::check_flag(broadcast) {
+            return Some(Rejection::InvalidFlag);
+        }
+        if broadcast.step == Step::R && broadcast.rank == 0 {
+            return None;
+        }
+
+        match &broadcast.previous_step_responses {
+            Some(responses) => Process::certificate_rejection(broadcast, responses, f, forced_adopt_ranks, order, identities),
+            // Checked by the run loop once the referenced responses or the certificate are resolved
+            None if broadcast.certificate_refs.is_some() || broadcast.certificate_hash.is_some() => None,
+            None => Some(Rejection::MissingCertificate),
         }
     }
 
-    fn validate_response(response: &Response) -> bool {
+    pub(crate) fn validate_response(response: &Response) -> bool {
         for state in &response.state {
             let broadcast = &state.broadcast;
 
@@ -771,236 +1298,2080 @@ impl Process {
     }
 
     // Line 91: To reliably check response (check if a response is valid), check if, for the broadcast(s) originating its value we have received 2f + 1 responses to that broadcast
+    // Stores up to `limit` responses per step and rank. Returns false for a response that was already pending
     fn reliably_check_response(
         response: Response,
         responses: &Responses,
         pending_responses: &mut PendingResponses,
-        threshold: usize
-    ) {
-        if !Process::validate_response(&response) {
-            return;
-        }
-              
+        memory: &mut MemoryTracker,
+        threshold: usize,
+        limit: usize
+    ) -> bool {
         let broadcast_hashes: BTreeSet<BroadcastHash> = response.state.iter()
             .map(|r| r.broadcast.hash_value())
             .collect();
 
-        if !pending_responses.contains_key(&broadcast_hashes) {
-            let mut responses_set = HashSet::new();
-            responses_set.insert(response);
-            pending_responses.insert(broadcast_hashes.clone(), responses_set);
-        } else {
-            pending_responses.get_mut(&broadcast_hashes).unwrap().insert(response);
+        let (rank, size) = (response.rank, response.memory_size());
+
+        let new = pending_responses.insert(broadcast_hashes.clone(), response);
+        if new {
+            memory.add(rank, size);
         }
 
-        if let Some(received_responses) = pending_responses.get(&broadcast_hashes) {                
-            if received_responses.len() >= threshold {                    
+        if let Some(received_responses) = pending_responses.get(&broadcast_hashes) {
+            // A sender answers every broadcast, with responses that only differ in the broadcast they answer
+            if received_responses.iter().map(|resp| resp.sender).collect::<HashSet<Id>>().len() >= threshold {
+                // Stored in sender order, so that which responses make it into the store does not depend on the hash set
+                let mut received_responses: Vec<&Response> = received_responses.iter().collect();
+                received_responses.sort_by_key(|resp| resp.sender);
+
                 for resp in received_responses {
-                    let key = (resp.step, resp.rank);
-                    let mut responses_map = responses.write().unwrap();
-                    let entry = responses_map.entry(key).or_default();
-                    
-                    if !entry.contains_key(&resp.sender) && entry.len() < threshold {
-                        entry.insert(resp.sender, resp.clone());
+                    if responses.insert(resp.clone(), limit) {
+                        memory.add(resp.rank, resp.memory_size());
+                    } else if limit > threshold && only_true(resp).is_some() {
+                        // With fast commits, an answer holding only a true pair that a sender sent before the one stored,
+                        // but that became reliable after it, counts for the sender, see Config::fast_commit_window
+                        if let Some(replaced) = responses.replace(resp.clone(), |stored| only_true(stored).is_none()) {
+                            memory.evict(rank, replaced.memory_size());
+                            memory.add(rank, resp.memory_size());
+                        }
                     }
                 }
             }
         }
+        new
     }
 
+    // None if the broadcast is reliable, otherwise why it is not
     fn reliably_check_broadcast(
         broadcast: &Broadcast,
-        broadcasts: &Broadcasts,
+        broadcasts: &BroadcastStore,
         f: usize,
-    ) -> bool {
+        forced_adopt_ranks: Rank,
+        order: &ValueOrder,
+        identities: &Identities,
+        validity: &Validity,
+    ) -> Option<Rejection> {
+        if !identities.accepts(broadcast.committee) {
+            return Some(Rejection::ForeignCommittee);
+        }
+        if !Process::check_flag(broadcast) {
+            return Some(Rejection::InvalidFlag);
+        }
+        // Whatever answered it, so that no value the application rejects is adopted, even at rank 0
+        if !validity.holds(broadcast.value) {
+            return Some(Rejection::InvalidValue);
+        }
         if broadcast.step == Step::R && broadcast.rank == 0 {
-            return true;
+            return None;
         }
 
-        if broadcast.previous_step_responses.is_none() {
-            return false;
+        // Lines 74/75: if |{bcast-answers ∈ C}| > f then return true
+        // If at least f+1 responses contain this broadcast, it means that at least one of those response comes from a correct process, 
+        // which reliably checked the broadcast, so we don't have to check it, nor to have its certificate
+        if broadcasts.count(broadcast) > f {
+            return None;
         }
 
+        let Some(responses) = broadcast.previous_step_responses.as_ref() else {
+            return Some(Rejection::MissingCertificate);
+        };
+
+        Process::certificate_rejection(broadcast, responses, f, forced_adopt_ranks, order, identities)
+    }
+
+    // Entries of the certificate must answer a broadcast of their own step and rank. Only the broadcasts the process
+    // already has can be checked, the others may still be on their way
+    pub(crate) fn answers_rejection(broadcast: &Broadcast, broadcasts: &BroadcastStore) -> Option<Rejection> {
+        let responses = broadcast.previous_step_responses.as_ref()?;
+        responses.iter().any(|response| Process::answers_other_broadcast(response, broadcasts)).then_some(Rejection::InvalidEntry)
+    }
+
+    pub(crate) fn answers_other_broadcast(response: &Response, broadcasts: &BroadcastStore) -> bool {
+        broadcasts.get(response.answers).is_some_and(|answered| answered.step != response.step || answered.rank != response.rank)
+    }
+
+    // Lines 76-87, which only depend on the broadcast itself and can therefore run outside of the run loop
+    fn certificate_rejection(
+        broadcast: &Broadcast,
+        responses: &[Response],
+        f: usize,
+        forced_adopt_ranks: Rank,
+        order: &ValueOrder,
+        identities: &Identities,
+    ) -> Option<Rejection> {
         let threshold = 2 * f + 1;
-        let responses = broadcast.previous_step_responses.as_ref().unwrap();
 
-        // Lines 74/75: if |{bcast-answers ∈ C}| > f then return true
-        // If at least f+1 responses contain this broadcast, it means that at least one of those response comes from a correct process, 
-        // which reliably checked the broadcast, so we don't have to check itå
-        if *broadcasts.get(broadcast).unwrap_or(&0) as usize > f {
-            return true;
+        // Structural checks come first, then signatures, then the values the certificate carries, so that a bogus
+        // broadcast costs as little as possible. A certificate holds one response per member at most
+        if responses.len() > 3 * f + 1 {
+            return Some(Rejection::OversizedCertificate);
         }
 
-        // Line 76: check that |C| ≥ 2f + 1 messages 
-        if responses.len() < threshold {
-            return false;
+        // Line 76: check that |C| ≥ 2f + 1 messages, of as many senders
+        if responses.iter().map(|response| response.sender).collect::<HashSet<Id>>().len() < threshold {
+            return Some(Rejection::SmallCertificate);
         }
-        
-        // Missing
-        // Line 77: check signatures of those messages 
+
         // Line 78: check if |{bcast-answers }| > f
+        // Every entry must be a valid answer to the previous step
+        if !Process::check_certificate_entries(broadcast, responses) {
+            return Some(Rejection::InvalidEntry);
+        }
+
+        // Responses of another committee would otherwise only be caught by their signatures, if there are keys
+        if !responses.iter().all(|response| identities.accepts(response.committee)) {
+            return Some(Rejection::ForeignCommittee);
+        }
+
+        // Line 77: check signatures of those messages, if the process has the keys of the committee
+        if !Process::check_certificate_signatures(responses, identities) {
+            return Some(Rejection::InvalidSignature);
+        }
 
-        match broadcast.step {
+        let justified = match broadcast.step {
             // Lines 79/80/81: If X = R then check (i, v) is correct according to signed B-answers received and step B
-            Step::R => {
-                if broadcast.flag.is_some() {
-                    false
-                }
-                else if broadcast.rank == 0 {
-                    true
-                } else {
-                    Process::process_b_responses(responses, threshold) == Decision::Adopt(broadcast.value)
-                }
-            }
+            Step::R => broadcast.rank == 0 || Process::process_b_responses(responses, threshold, order) == Decision::Adopt(broadcast.value),
             // Lines 82/83/84: else if X=A then	check (i, v) is correct according to signed R-answers received and step R
-            Step::A => {
-                if broadcast.flag.is_some() {
-                    false
-                }
-                else {
-                    Process::process_r_responses(responses).value == broadcast.value
-                }
-            }
+            // A certificate whose responses carry no R value cannot justify any value
+            Step::A => Process::process_r_responses(responses, order).is_some_and(|r_value| r_value.value == broadcast.value),
             // Lines 85/86/87: else if X= B then check (i, bool, v) is correct according to signed A-answers received and step A
             Step::B => {
-                if broadcast.flag.is_none() {
-                    false
-                }
-                else {
-                    Process::process_a_responses(responses, threshold) == (broadcast.flag.unwrap(), broadcast.value)
-                }
+                let forced_adopt = broadcast.rank < forced_adopt_ranks;
+                Process::process_a_responses(responses, threshold, forced_adopt, order) == (broadcast.flag.unwrap(), broadcast.value)
             }
+        };
+
+        (!justified).then_some(Rejection::UnjustifiedValue)
+    }
+
+    // No flag on an R or A broadcast, one on a B broadcast. Checked before anything else, rank 0 R broadcasts included:
+    // a flagged R broadcast would otherwise stand for its value when answering
+    fn check_flag(broadcast: &Broadcast) -> bool {
+        broadcast.flag.is_some() == (broadcast.step == Step::B)
+    }
+
+    // Stops at the first invalid entry, large certificates are verified in parallel
+    fn check_certificate_entries(broadcast: &Broadcast, responses: &[Response]) -> bool {
+        let (step, rank) = match broadcast.step {
+            Step::R if broadcast.rank == 0 => return true,
+            Step::R => (Step::B, broadcast.rank - 1),
+            Step::A => (Step::R, broadcast.rank),
+            Step::B => (Step::A, broadcast.rank),
+        };
+
+        responses.iter().all(|response| response.step == step && response.rank == rank && Process::validate_response(response))
+    }
+
+    // The same certificate attached to several broadcasts is only verified once
+    fn check_certificate_signatures(responses: &[Response], identities: &Identities) -> bool {
+        if !identities.enabled() {
+            return true;
+        }
+        let digest = Identities::certificate_digest(responses);
+        if identities.verified_certificate(&digest) {
+            return true;
+        }
+
+        let verified = if responses.len() >= PARALLEL_VERIFICATION_THRESHOLD {
+            responses.par_iter().all(|response| identities.verify(response))
+        } else {
+            responses.iter().all(|response| identities.verify(response))
+        };
+        if verified {
+            identities.remember_certificate(digest);
         }
+        verified
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{sync::mpsc::channel, thread};
-    use super::*;
-    use std::sync::Once;
+// Everything a process does upon receiving a message, without threads or clocks
+// The run loop feeds it from the network, the simulator from its event queue
+#[derive(Debug)]
+pub struct Core {
+    id: Id,
+    f: usize,
+    byzantine: bool,
+    // Certificates of broadcasts coming out of the validation pool have already been checked
+    verified: bool,
+    certificates_by_reference: bool,
+    // Certificates of the broadcasts of this process are kept, to serve them to the processes it sent their hash to
+    lazy_certificates: bool,
+    aggregated_responses: bool,
+    coordinated_broadcasts: Option<Duration>,
+    // Responses are kept up to n-f per step and rank instead of 2f+1, for the proposer to fast commit on
+    fast_commit: bool,
+    // Told to the peers in the hellos of this process
+    features: Features,
+    // Broadcasts of this process sent to the coordinator of their step, by when they were sent
+    awaiting_relay: VecDeque<(Instant, Broadcast)>,
+    forced_adopt_ranks: Rank,
+    responses: Responses,
+    preproposals: PreProposals,
+    proposals: Proposals,
+    pre_votes: PreVotes,
+    wakeup: Arc<Wakeup>,
+    votes: Votes,
+    vote_gossip: VoteGossip,
+    pacemaker: SharedPacemaker,
+    pacemaker_gap: Option<Rank>,
+    commits: Commits,
+    transcript: Transcripts,
+    order: ValueOrder,
+    validity: Validity,
+    identities: Identities,
+    jitter: Jitter,
+    profiler: Profiler,
+    #[cfg(debug_assertions)]
+    invariants: Invariants,
+    memory_metrics: Arc<MemoryMetrics>,
+    accounting: Arc<PeerAccounting>,
+    consensus: Arc<ConsensusMetrics>,
+    registers: Registers,
+    broadcasts: BroadcastStore,
+    // Hash of the first reliable broadcast of each sender, step and rank, to notice equivocating senders
+    sent_broadcasts: HashMap<(Id, Step, Rank), BroadcastHash>,
+    // Of the transport, which keeps the equivocations noticed as evidence to ban their senders
+    peer_table: Arc<PeerTable>,
+    pending_responses: PendingResponses,
+    // Checkpoints offered to a process without commits, with the senders that offered each one
+    checkpoints: HashMap<(u64, ProposalHash, Committee), HashSet<Id>>,
+    memory: MemoryTracker,
+    resolver: CertificateResolver,
+    // Broadcasts below this rank arrive after their state was evicted and are no longer answered
+    evicted_below: Rank,
+    // Highest rank with a quorum of responses of some step, the horizon is counted from it
+    quorum_rank: Rank,
+    broadcast_horizon: Option<Rank>,
+}
 
-    static INIT: Once = Once::new();
+impl Core {
+    pub fn new(id: Id, f: usize, byzantine: bool, config: Config) -> Core {
+        let memory_metrics = Arc::new(MemoryMetrics::default());
+        let order = ValueOrder::default();
+        let identities = Identities::default();
+        let wakeup = Arc::new(Wakeup::default());
 
-    fn setup_logger() {
-        INIT.call_once(|| {
-            env_logger::Builder::from_default_env()
-                .format_timestamp_secs()
-                .init();
-        });
+        Core {
+            id,
+            f,
+            byzantine,
+            verified: config.validation_workers > 0,
+            certificates_by_reference: config.certificates_by_reference,
+            lazy_certificates: config.lazy_certificates,
+            aggregated_responses: config.aggregated_responses,
+            coordinated_broadcasts: config.coordinated_broadcasts,
+            fast_commit: config.fast_commit_window.is_some(),
+            features: Features::of(&config),
+            awaiting_relay: VecDeque::new(),
+            forced_adopt_ranks: config.forced_adopt_ranks(),
+            responses: Arc::new(ResponseStore::new()),
+            preproposals: Arc::new(RwLock::new(PreProposalCache::new(config.preproposal_capacity))),
+            proposals: Arc::new(RwLock::new(HashMap::new())),
+            pre_votes: Arc::default(),
+            validity: Validity::notifying(Arc::clone(&wakeup)),
+            wakeup,
+            votes: Arc::default(),
+            vote_gossip: config.vote_gossip,
+            pacemaker: Arc::default(),
+            pacemaker_gap: config.pacemaker_gap,
+            commits: Arc::new(Mutex::new(CommitLog::new(config.retained_commits).with_committee(f, identities.clone()))),
+            transcript: Arc::default(),
+            registers: Registers::ordered(order.clone()),
+            order,
+            identities,
+            jitter: Jitter::default(),
+            profiler: Profiler::default(),
+            #[cfg(debug_assertions)]
+            invariants: Invariants::default(),
+            memory: MemoryTracker::new(config.memory_budget, Arc::clone(&memory_metrics)),
+            memory_metrics,
+            accounting: Arc::default(),
+            consensus: Arc::default(),
+            broadcasts: BroadcastStore::new(),
+            sent_broadcasts: HashMap::new(),
+            peer_table: Arc::default(),
+            pending_responses: PendingResponses::new(config.max_pending_responses),
+            checkpoints: HashMap::new(),
+            resolver: CertificateResolver::new(),
+            evicted_below: 0,
+            quorum_rank: 0,
+            broadcast_horizon: config.broadcast_horizon,
+        }
     }
 
-    #[test]
-    fn test_consensus() {
-        setup_logger();
+    pub fn id(&self) -> Id {
+        self.id
+    }
 
-        for instance in 1..1000 {
-            let (sender1, receiver1) = channel();
-            let (sender2, receiver2) = channel();
-            let (sender3, receiver3) = channel();
-            let (sender4, receiver4) = channel();
+    // Responses that have been reliably checked, per step and rank
+    pub fn responses(&self) -> &ResponseStore {
+        &self.responses
+    }
 
-            let f: usize = 1;
-            let threshold = 2 * f + 1;
-            
-            let mut process1 = Process::new(0, f, vec![sender1.clone(), sender2.clone(), sender3.clone(), sender4.clone()], receiver1, false);
-            let mut process1_clone = process1.clone();
-            
-            let mut process2 = Process::new(1, f, vec![sender1.clone(), sender2.clone(), sender3.clone(), sender4.clone()], receiver2, false);
-            let mut process2_clone = process2.clone();
-            
-            let mut process3 = Process::new(2, f, vec![sender1.clone(), sender2.clone(), sender3.clone(), sender4.clone()], receiver3, false);
-            let mut process3_clone = process3.clone();
-            
-            let mut process4 = Process::new(3, f, vec![sender1.clone(), sender2.clone(), sender3.clone(), sender4.clone()], receiver4, true);
-            let mut process4_clone = process4.clone();
+    // Value and certificate of the highest rank above the given one this process knows a certificate for
+    pub fn catch_up(&self, rank: Rank) -> Option<(RValue, CertificateResponses)> {
+        self.pacemaker.lock().unwrap().catch_up(rank)
+    }
 
-            let block1 = BlockHash::from(instance + 0);
-            let block2 = BlockHash::from(instance + 1);
-            let block3 = BlockHash::from(instance + 2);
-            let block4 = BlockHash::from(instance + 3);
+    // Received preproposals, ordered by sender
+    pub fn preproposals(&self) -> Vec<PreProposal> {
+        let mut preproposals: Vec<PreProposal> = self.preproposals.read().unwrap().values().cloned().collect();
+        preproposals.sort_by_key(|preproposal| preproposal.sender);
+        preproposals
+    }
 
-            let preproposal1 = PreProposal::new(vec![block1, block2], 0);
-            let preproposal2 = PreProposal::new(vec![block2], 1);
-            let preproposal3 = PreProposal::new(vec![block3, block2], 2);
-            let preproposal4 = PreProposal::new(vec![block4], 3);
+    // A coordinator relays the broadcasts of its steps to every process but their sender, and a process keeps its own
+    // broadcasts until they are answered. Relayed broadcasts keep their certificates, which every receiver checks, so a
+    // coordinator can hold them back but not change them
+    fn coordinate(&mut self, broadcast: &Broadcast, outbox: &mut Outbox) {
+        let coordinator = Process::coordinator(broadcast.step, broadcast.rank, outbox.peers());
 
-            let mut preproposals = Vec::new();
+        if broadcast.sender == self.id {
+            if coordinator != self.id {
+                self.awaiting_relay.push_back((Instant::now(), broadcast.clone()));
+            }
+        } else if coordinator == self.id {
+            let mut relayed = Message::Broadcast(broadcast.clone());
+            if self.byzantine {
+                Process::apply_byzantine_behavior(&mut relayed);
+            }
+            for peer in (0..outbox.peers() as Id).filter(|peer| *peer != self.id && *peer != broadcast.sender) {
+                outbox.push_to(peer, relayed.clone());
+            }
+        }
+    }
 
-            preproposals.push(preproposal1.clone());
-            preproposals.push(preproposal2.clone());
-            preproposals.push(preproposal3.clone());
-            preproposals.push(preproposal4.clone());
+    // Sends every broadcast of this process that no quorum answered within the timeout to every process directly,
+    // in case its coordinator dropped or altered it
+    pub fn check_relays(&mut self, outbox: &mut Outbox) {
+        let Some(timeout) = self.coordinated_broadcasts else {
+            return;
+        };
 
-            let p1 = thread::spawn(move || {
-                process1.propose(threshold, preproposal1, 0)
-            });
-            
-            let p2 = thread::spawn(move || {
-                process2.propose(threshold, preproposal2, 0)
-            });
-            
-            let p3 = thread::spawn(move || {
-                process3.propose(threshold, preproposal3, 0)
-            });
+        while let Some((_, broadcast)) = self.awaiting_relay.front().filter(|(sent, _)| sent.elapsed() >= timeout) {
+            if self.responses.senders(broadcast.step, broadcast.rank).len() < 2 * self.f + 1 {
+                debug!("{}: {:?} broadcast of rank {} was not relayed, sending it directly", self.id, broadcast.step, broadcast.rank);
+                let coordinator = Process::coordinator(broadcast.step, broadcast.rank, outbox.peers());
+                if coordinator != self.id {
+                    self.peer_table.suspicion().record(coordinator, Offense::Timeout);
+                }
+                Process::queue_message(outbox, Message::Broadcast(broadcast.clone()), self.byzantine);
+            }
+            self.awaiting_relay.pop_front();
+        }
+    }
 
-            let p4 = thread::spawn(move || {
-                process4.propose(threshold, preproposal4, 0)
-            });
-            
-            let p1_value = p1.join().unwrap();
-            let p2_value = p2.join().unwrap();
-            let p3_value = p3.join().unwrap();
-            let _ = p4.join().unwrap();
+    // Sends the hashes of the preproposals held to every peer, the ones missing some ask for them
+    pub fn announce_preproposals(&self, outbox: &mut Outbox) {
+        let hashes: Vec<_> = self.preproposals.read().unwrap().values().map(PreProposal::hash).collect();
 
-            process1_clone.stop();
-            process2_clone.stop();
-            process3_clone.stop();
-            process4_clone.stop();
+        if !hashes.is_empty() {
+            outbox.push(Message::PreProposalDigest(self.id, hashes));
+        }
+    }
 
-            assert_eq!(p1_value.hash, p2_value.hash);
-            assert_eq!(p1_value.hash, p3_value.hash);
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory_metrics.usage()
+    }
 
-            let agreed_preproposals = p1_value.preproposals;
-            let mut confirmed_blocks = Vec::new();
+    // The one of the peer queues of its process, if it runs in one
+    pub fn peer_table(&self) -> Arc<PeerTable> {
+        Arc::clone(&self.peer_table)
+    }
 
-            let mut block_counts: HashMap<BlockHash, usize> = HashMap::new();
+    // Shared with the outbound queues and validation workers of the process, which count what they send and reject
+    pub fn peer_accounting(&self) -> Arc<PeerAccounting> {
+        Arc::clone(&self.accounting)
+    }
 
-            for preproposal in &preproposals {
-                for block in &preproposal.frontiers {
-                    *block_counts.entry(block.clone()).or_insert(0) += 1;
-                }
-            }
+    // Commits of the process, with the ones received from the committee after a restart
+    pub fn commits(&self) -> Arc<Mutex<CommitLog>> {
+        Arc::clone(&self.commits)
+    }
 
-            for (block, count) in block_counts {
-                if count >= threshold {
-                    confirmed_blocks.push(block);
-                }
-            }
+    // Final votes of the committee, shared with the process
+    pub fn votes(&self) -> Arc<Mutex<VoteCache>> {
+        Arc::clone(&self.votes)
+    }
 
-            let mut preproposal_map: HashMap<_, _> = HashMap::new();
-            for preproposal in &preproposals {
-                preproposal_map.insert(preproposal.hash, preproposal.clone());
-            }
+    pub fn consensus_metrics(&self) -> Arc<ConsensusMetrics> {
+        Arc::clone(&self.consensus)
+    }
 
-            let mut agreed_blocks = HashSet::new();
-            for preproposal_hash in &agreed_preproposals {
-                if let Some(preproposal) = preproposal_map.get(preproposal_hash) {
-                    for block in &preproposal.frontiers {
-                        agreed_blocks.insert(block.clone());
-                    }
-                } else {
-                    panic!("Agreed preproposal hash {:?} not found in original preproposals", preproposal_hash);
-                }
+    // Shared with the proposer and validation workers, setting a beacon on it applies to all of them
+    pub fn value_order(&self) -> ValueOrder {
+        self.order.clone()
+    }
+
+    // Shared with the validation workers, like the value order
+    pub fn identities(&self) -> Identities {
+        self.identities.clone()
+    }
+
+    pub fn validation_rules(&self) -> ValidationRules {
+        ValidationRules { f: self.f, forced_adopt_ranks: self.forced_adopt_ranks, order: self.value_order(), identities: self.identities() }
+    }
+
+    // Keeps the highest R certificate, and sends it to the sender of the broadcast if it lags too far behind
+    fn pace(&mut self, broadcast: &Broadcast, outbox: &mut Outbox) {
+        let mut pacemaker = self.pacemaker.lock().unwrap();
+
+        if !pacemaker.observe(broadcast) && broadcast.sender != self.id && self.pacemaker_gap.is_some_and(|gap| pacemaker.lagging(broadcast.rank, gap)) {
+            if let Some(highest) = pacemaker.highest() {
+                outbox.push_to(broadcast.sender, Message::Broadcast(Broadcast::clone(highest)));
             }
+        }
+    }
+
+    fn check_equivocation(&mut self, broadcast: &Broadcast) {
+        let hash = *self.sent_broadcasts.entry((broadcast.sender, broadcast.step, broadcast.rank)).or_insert(broadcast.hash_value());
 
-            for block in &confirmed_blocks {
-                assert!(
-                    agreed_blocks.contains(block),
-                    "Confirmed block {:?} not found in agreed preproposals",
-                    block
-                );
+        if hash != broadcast.hash_value() {
+            self.consensus.equivocation();
+            if let Some(evidence) = self.broadcasts.get(hash).and_then(|first| Equivocation::new(first, broadcast, &self.identities)) {
+                self.peer_table.equivocation(evidence);
             }
+            crate::sampled!(EQUIVOCATIONS, Level::Warn, "{}: {} sent two {:?} broadcasts of rank {}", self.id, broadcast.sender, broadcast.step, broadcast.rank);
+        }
+    }
+
+    // The broadcast the R register holds the value of, which every R answer is justified by (Line 28)
+    fn holds_register(broadcast: &Broadcast, r: RValue) -> bool {
+        broadcast.step == Step::R && broadcast.rank == r.rank && broadcast.value == r.value
+    }
+
+    // Ranks more than the horizon below the highest quorum are no longer answered: their broadcasts are dropped,
+    // except the ones pending responses or the R register still refer to, with the pending responses that already
+    // made it into the response store
+    fn expire_ranks(&mut self) {
+        let Some(horizon) = self.broadcast_horizon else {
+            return;
+        };
+        let below = self.quorum_rank.saturating_sub(horizon);
+        if below <= self.evicted_below {
+            return;
+        }
+
+        for response in self.pending_responses.evict_quorums_below(below, 2 * self.f + 1) {
+            self.memory.evict(response.rank, response.memory_size());
+        }
+
+        let r = self.registers.r();
+        let pending = &self.pending_responses;
+        for broadcast in self.broadcasts.evict_below(below, |broadcast| Core::holds_register(broadcast, r) || pending.is_referenced(broadcast.hash_value())) {
+            self.memory.evict(broadcast.rank, broadcast.memory_size());
+        }
+
+        self.evicted_below = below;
+        self.sent_broadcasts.retain(|(_, _, rank), _| *rank >= below);
+    }
+
+    // Lines 56/57: the certificate holds 2f+1 B answers of the rank, of as many senders, committing the value
+    // The certificate of a checkpoint is checked against the committee it comes with rather than the one this process
+    // holds, which may have been reconfigured since. Every response carries the configuration of that committee, and
+    // a configuration is the one of its f and members
+    pub(crate) fn check_checkpoint(commit: &CommitRecord, committee: &Committee, order: &ValueOrder) -> bool {
+        let keys: Option<HashMap<Id, VerifyingKey>> = committee.members.iter().map(|(member, key)| VerifyingKey::from_bytes(key).ok().map(|key| (*member, key))).collect();
+        let Some(keys) = keys else {
+            return false;
+        };
+        let configured = committee.configuration.is_none_or(|configuration| {
+            keys.is_empty() || configuration == Identities::configuration_hash(committee.f, committee.members.iter().map(|(member, key)| (*member, Some(*key))))
+        });
+
+        configured
+            && commit.certificate.iter().all(|response| response.committee == committee.configuration)
+            && Core::check_commit(commit, committee.f, order, &Identities::verifying(keys))
+    }
+
+    pub(crate) fn check_commit(commit: &CommitRecord, f: usize, order: &ValueOrder, identities: &Identities) -> bool {
+        let threshold = 2 * f + 1;
+        let certificate = &commit.certificate;
+
+        certificate.iter().map(|response| response.sender).collect::<HashSet<Id>>().len() >= threshold
+            && certificate.iter().all(|response| {
+                response.step == Step::B && response.rank == commit.rank && Process::validate_response(response) && identities.verify(response)
+            })
+            && Process::process_b_responses(certificate, threshold, order) == Decision::Commit(commit.value)
+    }
+
+    // Holds the request of a banned member to be taken back until the committee commits its reconfiguration value,
+    // or takes the member back now if it already did, the request coming after the commit
+    pub fn hold_rejoin(&mut self, rejoin: Rejoin) {
+        let member = rejoin.sender;
+        if !matches!(self.peer_table.state(member), PeerState::Banned { .. }) {
+            return;
+        }
+        if let Err(error) = rejoin.verify(self.identities.key(member).as_ref()) {
+            self.accounting.invalid(member);
+            warn!("{}: refused to take {} back: {:?}", self.id, member, error);
+            return;
+        }
+
+        let decided = self.commits.lock().unwrap().instance_of(&rejoin.reconfiguration());
+        match decided {
+            Some(instance) => {
+                rejoin.apply(&self.peer_table, &self.identities, self.f);
+                debug!("{}: took {} back at instance {}, it resumes at instance {}", self.id, member, instance, rejoin.resume);
+            }
+            None => {
+                self.peer_table.hold(rejoin);
+            }
+        }
+    }
+
+    // Commits are recorded in instance order, the ones this process already has or that leave a gap are ignored
+    // A process without commits installs the first checkpoint f+1 senders offer with the same committee instead, since
+    // at least one of them is correct, and records the ones after it
+    fn catch_up_commits(&mut self, sender: Id, mut commits: Vec<CommitRecord>) {
+        commits.sort_by_key(|commit| commit.instance);
+        let mut log = self.commits.lock().unwrap();
+
+        for commit in commits {
+            if let Some(committee) = commit.committee.clone().filter(|_| log.next_instance() == 0) {
+                if !Core::check_checkpoint(&commit, &committee, &self.order) {
+                    self.accounting.invalid(sender);
+                    break;
+                }
+
+                let offered = self.checkpoints.entry((commit.instance, commit.value, committee)).or_default();
+                offered.insert(sender);
+                if offered.len() > self.f {
+                    debug!("{}: installed the checkpoint of instance {}", self.id, commit.instance);
+                    log.install(commit);
+                    self.checkpoints.clear();
+                }
+                continue;
+            }
+            if commit.instance < log.next_instance() {
+                continue;
+            }
+            if commit.instance > log.next_instance() {
+                break;
+            }
+            if !Core::check_commit(&commit, self.f, &self.order, &self.identities) {
+                self.accounting.invalid(sender);
+                break;
+            }
+
+            debug!("{}: caught up on instance {} from {}", self.id, commit.instance, sender);
+            if let Err(error) = log.record(commit.rank, commit.value, commit.certificate) {
+                warn!("{}: cannot append the commit of rank {} to the audit log: {}", self.id, commit.rank, error);
+            }
+            if let Some(member) = Rejoin::reconfigure(commit.value, &self.peer_table, &self.identities, self.f) {
+                debug!("{}: took {} back at instance {}", self.id, member, commit.instance);
+            }
+        }
+    }
+
+    // Commits of a backfill, only the instances asked for are checked
+    fn backfill_commits(&mut self, sender: Id, commits: Vec<CommitRecord>) {
+        let mut log = self.commits.lock().unwrap();
+
+        for commit in commits {
+            if !log.wants(commit.instance) {
+                continue;
+            }
+            if !Core::check_commit(&commit, self.f, &self.order, &self.identities) {
+                self.accounting.invalid(sender);
+                continue;
+            }
+            log.offer(sender, commit, self.f);
+        }
+        self.wakeup.notify();
+    }
+
+    pub fn dump_state(&self) -> StateDump {
+        let broadcasts = self.broadcasts
+            .iter()
+            .map(|broadcast| BroadcastDump {
+                sender: broadcast.sender,
+                step: broadcast.step,
+                rank: broadcast.rank,
+                value: broadcast.value,
+                flag: broadcast.flag,
+                hash: broadcast.hash_value(),
+            })
+            .collect();
+        let pending = self.pending_responses
+            .iter()
+            .map(|(broadcasts, responses)| PendingDump {
+                broadcasts: broadcasts.iter().copied().collect(),
+                responses: responses.iter().map(|response| (response.sender, response.step, response.rank)).collect(),
+            })
+            .collect();
+
+        StateDump {
+            id: self.id,
+            evicted_below: self.evicted_below,
+            preproposals: self.preproposals.read().unwrap().senders().collect(),
+            proposals: self.proposals.read().unwrap().keys().copied().collect(),
+            broadcasts,
+            responses: self.responses.all_senders(),
+            pending,
+            r: self.registers.r(),
+            a: self.registers.a_sets(),
+            b: self.registers.b_sets(),
+        }
+        .sorted()
+    }
+
+    pub fn proposal(&self, hash: ProposalHash) -> Option<Proposal> {
+        self.proposals.read().unwrap().values().find(|proposal| proposal.hash == hash).cloned()
+    }
+
+    pub fn handle(&mut self, msg: Message, outbox: &mut Outbox) {
+        let id = self.id;
+        let f = self.f;
+        let mut queue = VecDeque::from(msg.into_messages());
+
+        // Broadcasts queued again once their certificate is resolved are only counted once
+        for msg in &queue {
+            if let Some(sender) = msg.sender() {
+                self.accounting.received(sender, msg.memory_size());
+            }
+        }
+
+        while let Some(msg) = queue.pop_front() {
+            match msg {
+                Message::PreProposal(preproposal) => {
+                    //if valid {
+                        let mut preproposals= self.preproposals.write().unwrap();
+                        if let Some(known) = preproposals.peek(preproposal.sender) {
+                            self.accounting.duplicate(preproposal.sender);
+                            if *known != preproposal {
+                                self.consensus.equivocation();
+                            }
+                        }
+                        preproposals.insert(preproposal);
+                        self.wakeup.notify();
+                    //}
+                }
+                Message::Proposal(proposal) => {
+                    //if valid {
+                        let mut proposals= self.proposals.write().unwrap();
+                        if let Some(known) = proposals.get(&proposal.sender) {
+                            self.accounting.duplicate(proposal.sender);
+                            if *known != proposal {
+                                self.consensus.equivocation();
+                            }
+                        }
+                        // The preproposals of a proposal are kept until a value is decided
+                        self.preproposals.write().unwrap().pin(&proposal.preproposals);
+                        proposals.entry(proposal.sender).or_insert(proposal.clone());
+                        self.wakeup.notify();
+                    //}
+                }
+                Message::PreVote(sender, value) => {
+                    let mut pre_votes = self.pre_votes.write().unwrap();
+                    if let Some(known) = pre_votes.get(&sender) {
+                        self.accounting.duplicate(sender);
+                        if *known != value {
+                            self.consensus.equivocation();
+                        }
+                    }
+                    pre_votes.entry(sender).or_insert(value);
+                    self.wakeup.notify();
+                }
+                Message::Broadcast(broadcast) => {                        
+                    // Lines 26, 42, 62
+                    if broadcast.rank < self.evicted_below {
+                        self.consensus.rejected(Rejection::Stale);
+                        continue;
+                    }
+
+                    // A broadcast of the same step, rank and value was already checked: its certificate is neither
+                    // resolved nor checked again
+                    let known = self.broadcasts.contains(&broadcast);
+                    // Lines 74/75: the certificate of a broadcast f+1 processes answered is not needed, nor fetched
+                    let answered = self.broadcasts.count(&broadcast) > f;
+                    // The pool could not check a certificate sent by reference or by hash, even once it is resolved
+                    let by_reference = broadcast.certificate_refs.is_some() || broadcast.certificate_hash.is_some();
+                    let sender = broadcast.sender;
+
+                    let broadcast = if known || answered {
+                        broadcast
+                    } else {
+                        match self.resolver.resolve(broadcast) {
+                            Ok(broadcast) => broadcast,
+                            Err(Missing::Responses(missing)) => {
+                                outbox.push_to(sender, Message::GetResponses(id, missing));
+                                continue;
+                            }
+                            Err(Missing::Certificate(hash)) => {
+                                outbox.push_to(sender, Message::GetCertificate(id, hash));
+                                continue;
+                            }
+                        }
+                    };
+
+                    let rejection = if known {
+                        None
+                    } else {
+                        // The validation pool does not know the predicate, which is checked here either way
+                        let checked = if self.verified && !by_reference {
+                            (!self.validity.holds(broadcast.value)).then_some(Rejection::InvalidValue)
+                        } else {
+                            self.profiler.time(Probe::Validation, || {
+                                Process::reliably_check_broadcast(&broadcast, &self.broadcasts, f, self.forced_adopt_ranks, &self.order, &self.identities, &self.validity)
+                            })
+                        };
+                        // The pool does not have the broadcasts the entries answer
+                        checked.or_else(|| self.profiler.time(Probe::Validation, || Process::answers_rejection(&broadcast, &self.broadcasts)))
+                    };
+
+                    if rejection.is_none() {
+                        if !known {
+                            self.profiler.lock(&self.transcript).broadcast(&broadcast);
+                        }
+                        if self.lazy_certificates && broadcast.sender == id {
+                            self.profiler.time(Probe::Hashing, || self.resolver.keep(&broadcast));
+                        }
+                        self.check_equivocation(&broadcast);
+                        self.pace(&broadcast, outbox);
+                        if !known && self.coordinated_broadcasts.is_some() {
+                            self.coordinate(&broadcast, outbox);
+                        }
+
+                        if self.broadcasts.insert(broadcast.clone()) {
+                            self.memory.add(broadcast.rank, broadcast.memory_size());
+                        } else {
+                            self.accounting.duplicate(broadcast.sender);
+                        }
+
+                        // A rejoining process keeps the broadcasts but answers none before its resume instance
+                        if self.commits.lock().unwrap().is_silent() {
+                            continue;
+                        }
+
+                        match broadcast.step {
+                            Step::R => {
+                                traced_answer(id, &broadcast, || Process::answer_r_broadcast(
+                                    id,
+                                    &broadcast,
+                                    outbox,
+                                    &self.registers,
+                                    &self.broadcasts,
+                                    &self.identities,
+                                    self.byzantine
+                                ));
+                            }
+                            Step::A => {
+                                traced_answer(id, &broadcast, || Process::answer_a_broadcast(
+                                    id,
+                                    &broadcast,
+                                    outbox,
+                                    &self.registers,
+                                    &self.broadcasts,
+                                    &self.identities,
+                                    self.byzantine,
+                                    self.aggregated_responses
+                                ));
+                            }
+                            Step::B => {
+                                traced_answer(id, &broadcast, || Process::answer_b_broadcast(
+                                    id,
+                                    &broadcast,
+                                    outbox,
+                                    &self.registers,
+                                    &self.broadcasts,
+                                    &self.identities,
+                                    self.byzantine,
+                                    self.aggregated_responses
+                                ));
+                            }
+                        }
+                    }
+                    else if let Some(reason) = rejection {
+                        self.accounting.invalid(broadcast.sender);
+                        self.consensus.rejected(reason);
+                        crate::sampled!(REJECTED_BROADCASTS, Level::Trace, "{}: rejected {:?} broadcast of rank {} from {}: {:?}", id, broadcast.step, broadcast.rank, broadcast.sender, reason);
+                    }
+                }
+                Message::Response(response) => {
+                    if self.certificates_by_reference && self.resolver.record(&response) {
+                        self.memory.add(response.rank, response.memory_size());
+                    }
+
+                    let (sender, step, rank) = (response.sender, response.step, response.rank);
+
+                    // Signatures were already verified by the validation pool. Responses bound to a broadcast of another
+                    // step or rank are dropped here, so that they never make it into a certificate of this process
+                    let rejection = self.profiler.time(Probe::Validation, || {
+                        if !Process::validate_response(&response) || !self.identities.accepts(response.committee) || Process::answers_other_broadcast(&response, &self.broadcasts) {
+                            Some(RejectionStage::Structure)
+                        } else {
+                            (!self.verified && !self.identities.verify(&response)).then_some(RejectionStage::Signature)
+                        }
+                    });
+                    if let Some(stage) = rejection {
+                        self.accounting.invalid(sender);
+                        self.consensus.dropped(stage);
+                        continue;
+                    }
+                    self.profiler.lock(&self.transcript).response(&response);
+
+                    // A broadcast waiting for its certificate goes on once f+1 processes answered it
+                    for state in &response.state {
+                        if self.broadcasts.answered(&state.broadcast, sender) > f {
+                            if let Some(broadcast) = self.resolver.unpark(state.broadcast.hash_value()) {
+                                queue.push_back(Message::Broadcast(broadcast));
+                            }
+                        }
+                    }
+
+                    let new = Process::reliably_check_response(
+                        response,
+                        &self.responses,
+                        &mut self.pending_responses,
+                        &mut self.memory,
+                        2 * f + 1,
+                        if self.fast_commit { outbox.peers().saturating_sub(f).max(2 * f + 1) } else { 2 * f + 1 }
+                    );
+
+                    if !new {
+                        self.accounting.duplicate(sender);
+                    }
+                    if self.responses.count(step, rank) > 2 * f {
+                        self.quorum_rank = max(self.quorum_rank, rank);
+                    }
+
+                    for evicted in self.pending_responses.evict_over_capacity() {
+                        self.memory.evict(evicted.rank, evicted.memory_size());
+                        self.accounting.pending_evicted(evicted.sender);
+                    }
+                }
+                Message::GetResponses(requester, hashes) => {
+                    let found = self.resolver.serve(&hashes);
+
+                    if !found.is_empty() {
+                        outbox.push_to(requester, Message::Responses(id, found));
+                    }
+                }
+                Message::GetCertificate(requester, hash) => {
+                    if let Some(certificate) = self.resolver.certificate(hash) {
+                        outbox.push_to(requester, Message::Certificate(id, certificate));
+                    }
+                }
+                Message::Certificate(_, certificate) => {
+                    for broadcast in self.profiler.time(Probe::Hashing, || self.resolver.receive_certificate(certificate)) {
+                        queue.push_back(Message::Broadcast(broadcast));
+                    }
+                }
+                Message::Responses(_, fetched) => {
+                    for response in &fetched {
+                        self.memory.add(response.rank, response.memory_size());
+                    }
+
+                    for broadcast in self.resolver.receive(fetched) {
+                        queue.push_back(Message::Broadcast(broadcast));
+                    }
+                }
+                Message::Vote(vote) => {
+                    match self.votes.lock().unwrap().add(&vote, self.verified) {
+                        Ok(new) if new.is_empty() && vote.is_final() => self.accounting.duplicate(vote.voter),
+                        // Relayed only the first time, so that relays stop at the processes that already have the vote
+                        Ok(new) if !new.is_empty() => match self.vote_gossip {
+                            VoteGossip::Direct => (),
+                            VoteGossip::Relay => outbox.push(Message::Vote(vote)),
+                            VoteGossip::RelayByHash => outbox.push(Message::VoteHashes(id, vec![vote.hash()])),
+                        },
+                        Ok(_) => (),
+                        Err(error) => {
+                            self.accounting.invalid(vote.voter);
+                            self.consensus.dropped(if error == VoteError::InvalidSignature { RejectionStage::Signature } else { RejectionStage::Structure });
+                            debug!("{}: rejected vote from {}: {:?}", id, vote.voter, error);
+                        }
+                    }
+                }
+                Message::PreProposalDigest(sender, hashes) => {
+                    let preproposals = self.preproposals.read().unwrap();
+                    let missing: Vec<_> = hashes.into_iter().filter(|hash| preproposals.find(hash).is_none()).collect();
+
+                    if !missing.is_empty() && sender != id {
+                        outbox.push_to(sender, Message::GetPreProposals(id, missing));
+                    }
+                }
+                Message::GetPreProposals(requester, hashes) => {
+                    let preproposals = self.preproposals.read().unwrap();
+
+                    for preproposal in hashes.iter().filter_map(|hash| preproposals.find(hash)) {
+                        outbox.push_to(requester, Message::PreProposal(preproposal.clone()));
+                    }
+                }
+                Message::VoteHashes(sender, hashes) => {
+                    let votes = self.votes.lock().unwrap();
+                    let missing: Vec<_> = hashes.into_iter().filter(|hash| !votes.contains(hash)).collect();
+
+                    if !missing.is_empty() && sender != id {
+                        outbox.push_to(sender, Message::GetVotes(id, missing));
+                    }
+                }
+                Message::GetVotes(requester, hashes) => {
+                    for vote in self.votes.lock().unwrap().get(&hashes) {
+                        outbox.push_to(requester, Message::Vote(vote));
+                    }
+                }
+                Message::Announce(requester, next) => {
+                    // A process without commits starts from the latest one instead of replaying the log
+                    let commits = match next {
+                        0 => self.commits.lock().unwrap().checkpoint().into_iter().collect(),
+                        next => self.commits.lock().unwrap().since(next),
+                    };
+
+                    if !commits.is_empty() && requester != id {
+                        outbox.push_to(requester, Message::Commits(id, commits));
+                    }
+                }
+                Message::Commits(sender, commits) => self.catch_up_commits(sender, commits),
+                Message::GetCertificates(requester, instances) => {
+                    let commits = self.commits.lock().unwrap().range(instances);
+
+                    if !commits.is_empty() && requester != id {
+                        outbox.push_to(requester, Message::Certificates(id, commits));
+                    }
+                }
+                Message::Certificates(sender, commits) => self.backfill_commits(sender, commits),
+                Message::Hello(hello) if hello.sender != id => {
+                    let own = Hello::new(id, self.features, Hello::committee_hash(f, outbox.peers(), &self.identities));
+                    let mismatches = self.peer_table.hello(&hello, &own);
+                    if !mismatches.is_empty() {
+                        warn!("{}: {} runs version {} with {:?}, which differs in {:?}", id, hello.sender, hello.version, hello.features.names(), mismatches);
+                    }
+                    if hello.ask {
+                        outbox.push_to(hello.sender, Message::Hello(own.answer()));
+                    }
+                }
+                Message::Hello(_) => (),
+                Message::Rejoin(rejoin) => self.hold_rejoin(rejoin),
+                // Unwrapped by Instances before they reach the process of their instance
+                Message::Instance(..) => (),
+                // Unbatched by into_messages, which leaves the header of sequenced batches
+                Message::Sequenced(sender, sequence, _) => outbox.received(sender, sequence),
+                Message::Ack(sender, sequence) => outbox.acked(sender, sequence),
+                // The outbox never nests batches
+                Message::Batch(_) => (),
+            }
+        }
+
+        MESSAGE_BUFFERS.give(Vec::from(queue));
+        self.expire_ranks();
+
+        if self.memory.over_budget() {
+            Process::enforce_memory_budget(
+                &mut self.memory,
+                &mut self.broadcasts,
+                &mut self.pending_responses,
+                &mut self.resolver,
+                &self.responses,
+                &mut self.evicted_below,
+                2 * f + 1
+            );
+            self.sent_broadcasts.retain(|(_, _, rank), _| *rank >= self.evicted_below);
+        }
+        self.memory_metrics.held_by_run_loop(self.broadcasts.memory_size(), self.pending_responses.memory_size());
+
+        #[cfg(debug_assertions)]
+        self.check_invariants();
+    }
+
+    // Panics at the first violation, so that fuzzed message sequences stop where the state went wrong
+    #[cfg(debug_assertions)]
+    fn check_invariants(&mut self) {
+        let commits = self.commits.lock().unwrap().since(self.invariants.next_instance());
+        let checked = self.invariants
+            .registers(&self.registers)
+            .and_then(|_| commits.iter().try_for_each(|commit| self.invariants.committed(commit.instance, commit.value)));
+
+        if let Err(violation) = checked {
+            panic!("{}: invariant violated: {:?}", self.id, violation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, sync::{atomic::AtomicUsize, mpsc::channel}, thread};
+    use super::*;
+    use rsnano_core::RawKey;
+    use crate::{BatchConfig, Decided, StatsdConfig};
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+
+    fn setup_logger() {
+        INIT.call_once(|| {
+            env_logger::Builder::from_default_env()
+                .format_timestamp_secs()
+                .init();
+        });
+    }
+
+    fn r_certificate(senders: i64, value: ProposalHash) -> Vec<Response> {
+        let broadcast = Arc::new(Broadcast::new(0, Step::R, value, None, 0, None));
+
+        (0..senders)
+            .map(|sender| Response::new(sender, Step::R, 0, vec![State::new(Value::RValue(RValue::new(0, value)), broadcast.clone())]))
+            .collect()
+    }
+
+    #[test]
+    fn certificate_with_wrong_step_entry_is_rejected() {
+        let value = BlockHash::from(1);
+        let mut responses = r_certificate(3, value);
+        responses[1].step = Step::B;
+
+        let broadcast = Broadcast::new(0, Step::A, value, None, 0, Some(responses.clone().into()));
+        assert_eq!(Process::certificate_rejection(&broadcast, &responses, 1, 0, &ValueOrder::default(), &Identities::default()), Some(Rejection::InvalidEntry));
+    }
+
+    #[test]
+    fn certificate_entries_answer_a_broadcast_of_their_step() {
+        let value = BlockHash::from(1);
+        let (r, a) = (Broadcast::new(1, Step::R, value, None, 0, None), Broadcast::new(1, Step::A, value, None, 0, None));
+        let mut broadcasts = BroadcastStore::new();
+        broadcasts.insert(r.clone());
+        broadcasts.insert(a.clone());
+
+        let answering = |answered: &Broadcast| {
+            let certificate: Vec<Response> = r_certificate(3, value).into_iter().map(|response| response.answering(answered.hash_value())).collect();
+            Broadcast::new(0, Step::A, value, None, 0, Some(certificate.into()))
+        };
+        assert_eq!(Process::answers_rejection(&answering(&r), &broadcasts), None);
+        assert_eq!(Process::answers_rejection(&answering(&a), &broadcasts), Some(Rejection::InvalidEntry));
+        // Answers to broadcasts the process does not have yet are not held against the certificate
+        assert_eq!(Process::answers_rejection(&answering(&Broadcast::new(1, Step::B, value, Some(true), 0, None)), &BroadcastStore::new()), None);
+    }
+
+    #[test]
+    fn certificate_without_r_values_is_rejected() {
+        let value = BlockHash::from(1);
+        let mut responses = r_certificate(3, value);
+        responses.iter_mut().for_each(|response| response.state.clear());
+
+        let broadcast = Broadcast::new(0, Step::A, value, None, 0, Some(responses.clone().into()));
+        assert_eq!(Process::certificate_rejection(&broadcast, &responses, 1, 0, &ValueOrder::default(), &Identities::default()), Some(Rejection::UnjustifiedValue));
+    }
+
+    #[test]
+    fn large_certificate_is_verified() {
+        let value = BlockHash::from(1);
+        let mut responses = r_certificate(31, value);
+
+        let broadcast = Broadcast::new(0, Step::A, value, None, 0, Some(responses.clone().into()));
+        assert_eq!(Process::certificate_rejection(&broadcast, &responses, 10, 0, &ValueOrder::default(), &Identities::default()), None);
+
+        responses[30].rank = 1;
+        assert_eq!(Process::certificate_rejection(&broadcast, &responses, 10, 0, &ValueOrder::default(), &Identities::default()), Some(Rejection::InvalidEntry));
+    }
+
+    #[test]
+    fn structure_is_checked_before_signatures() {
+        let key = |id: Id| SigningKey::from_bytes(&[id as u8 + 1; 32]);
+        let identities = Identities::default();
+        identities.set(key(0), (0..4).map(|id| (id, key(id).verifying_key())).collect());
+        let value = BlockHash::from(1);
+
+        // None of the responses is signed, yet the flag and the size are what reject these
+        let flagged = Broadcast::new(0, Step::A, value, Some(true), 0, Some(r_certificate(3, value).into()));
+        assert_eq!(Process::broadcast_rejection(&flagged, 1, 0, &ValueOrder::default(), &identities), Some(Rejection::InvalidFlag));
+        let oversized = Broadcast::new(0, Step::A, value, None, 0, Some(r_certificate(5, value).into()));
+        assert_eq!(Process::broadcast_rejection(&oversized, 1, 0, &ValueOrder::default(), &identities), Some(Rejection::OversizedCertificate));
+
+        let unsigned = Broadcast::new(0, Step::A, value, None, 0, Some(r_certificate(3, value).into()));
+        assert_eq!(Process::broadcast_rejection(&unsigned, 1, 0, &ValueOrder::default(), &identities).map(|reason| reason.stage()), Some(RejectionStage::Signature));
+    }
+
+    #[test]
+    fn flooded_pending_responses_evict_the_flooding_sender() {
+        let (sender, _receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::disabled());
+        let mut core = Core::new(0, 1, false, Config { max_pending_responses: Some(8), ..Config::default() });
+        let honest = r_certificate(3, BlockHash::from(1));
+
+        for response in &honest[..2] {
+            core.handle(Message::Response(response.clone()), &mut outbox);
+        }
+        // Each response of the flood is justified by a broadcast of its own, so none ever reaches a quorum
+        for value in 0..20 {
+            let flood = r_certificate(1, BlockHash::from(100 + value)).pop().unwrap();
+            core.handle(Message::Response(Response { sender: 3, ..flood }), &mut outbox);
+        }
+
+        let accounting = core.peer_accounting();
+        assert_eq!((accounting.peer(3).pending_evicted, accounting.peer(0).pending_evicted), (14, 0));
+
+        core.handle(Message::Response(honest[2].clone()), &mut outbox);
+        assert_eq!(core.responses().count(Step::R, 0), 3);
+    }
+
+    #[test]
+    fn spoofed_senders_cannot_form_a_quorum() {
+        let key = |id: Id| SigningKey::from_bytes(&[id as u8 + 1; 32]);
+        let committee: HashMap<Id, VerifyingKey> = (0..4).map(|id| (id, key(id).verifying_key())).collect();
+        let (sender, _receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::disabled());
+        let mut core = Core::new(0, 1, false, Config::default());
+        core.identities().set(key(0), committee.clone());
+
+        // Process 3 answers under every id of the committee, signing with its own key
+        let forger = Identities::default();
+        forger.set(key(3), committee.clone());
+        for response in r_certificate(4, BlockHash::from(1)) {
+            core.handle(Message::Response(forger.sign(response)), &mut outbox);
+        }
+        assert_eq!(core.responses().count(Step::R, 0), 0);
+        assert_eq!(core.peer_accounting().peer(1).invalid, 1);
+
+        // Nor can it make up a certificate out of them
+        let certificate: Vec<Response> = r_certificate(3, BlockHash::from(1)).into_iter().map(|response| forger.sign(response)).collect();
+        let broadcast = Broadcast::new(3, Step::A, BlockHash::from(1), None, 0, Some(certificate.into()));
+        assert_eq!(Process::broadcast_rejection(&broadcast, 1, 0, &ValueOrder::default(), &core.identities()), Some(Rejection::InvalidSignature));
+
+        for response in r_certificate(3, BlockHash::from(1)) {
+            let signer = Identities::default();
+            signer.set(key(response.sender), committee.clone());
+            core.handle(Message::Response(signer.sign(response)), &mut outbox);
+        }
+        assert_eq!(core.responses().count(Step::R, 0), 3);
+    }
+
+    #[test]
+    fn preconsensus_runs_on_the_votes_of_the_committee() {
+        let key = PrivateKey::from(RawKey::from_bytes([7; 32]));
+        let (sender, receiver) = channel();
+        let mut process = Process::new(0, 0, vec![sender], receiver, false)
+            .with_voters(HashMap::from([(0, key.public_key())]))
+            .with_voting_key(key);
+
+        let frontiers = vec![BlockHash::from(1), BlockHash::from(2)];
+        assert!(process.vote(frontiers.clone()));
+        while process.confirmed_frontiers().is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let preproposal = process.preproposal();
+        assert_eq!(preproposal.frontiers().iter().copied().collect::<Vec<_>>(), frontiers);
+        let proposal = process.propose(1, preproposal.clone(), 0);
+        assert_eq!(proposal.preproposals, vec![preproposal.hash()]);
+
+        let page = process.proposal_frontiers(proposal.hash, None, 1).unwrap();
+        assert_eq!((page.frontiers, page.next), (vec![frontiers[0]], Some(frontiers[0])));
+        assert_eq!(process.preproposal_frontiers(0, page.next, 1).unwrap().frontiers, vec![frontiers[1]]);
+        process.stop();
+    }
+
+    #[test]
+    fn votes_are_relayed_by_hash() {
+        let key = |id: Id| PrivateKey::from(RawKey::from_bytes([id as u8 + 1; 32]));
+        let voters: HashMap<Id, PublicKey> = (0..3).map(|id| (id, key(id).public_key())).collect();
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| channel()).unzip();
+        let config = Config { vote_gossip: VoteGossip::RelayByHash, ..Config::default() };
+        let mut cores: Vec<Core> = (0..3).map(|id| Core::new(id, 0, false, config)).collect();
+        for core in &cores {
+            core.votes().lock().unwrap().set_voters(voters.clone());
+        }
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        let received = |peer: usize| -> Vec<Message> { receivers[peer].try_iter().collect() };
+
+        // Process 1 relays only the hash of the vote it received from 0, and only the first time
+        let vote = Vote::new_final(0, &key(0), vec![BlockHash::from(1)]);
+        cores[1].handle(Message::Vote(vote.clone()), &mut outbox);
+        cores[1].handle(Message::Vote(vote.clone()), &mut outbox);
+        assert_eq!(received(2), vec![Message::VoteHashes(1, vec![vote.hash()])]);
+        cores[0].handle(Message::Vote(vote.clone()), &mut outbox);
+        (0..3).for_each(|peer| drop(received(peer)));
+
+        // Process 0 has the vote already, process 2 asks for it
+        cores[0].handle(Message::VoteHashes(1, vec![vote.hash()]), &mut outbox);
+        cores[2].handle(Message::VoteHashes(1, vec![vote.hash()]), &mut outbox);
+        assert_eq!(received(1), vec![Message::GetVotes(2, vec![vote.hash()])]);
+
+        cores[1].handle(Message::GetVotes(2, vec![vote.hash()]), &mut outbox);
+        assert_eq!(received(2), vec![Message::Vote(vote)]);
+    }
+
+    #[test]
+    fn late_joiners_fetch_the_preproposals_they_missed() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..2).map(|_| channel()).unzip();
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        let mut held = Core::new(0, 0, false, Config::default());
+        let mut late = Core::new(1, 0, false, Config::default());
+        let preproposals: Vec<PreProposal> = (0..2).map(|sender| PreProposal::new(vec![BlockHash::from(sender as u64)], sender)).collect();
+        for preproposal in &preproposals {
+            held.handle(Message::PreProposal(preproposal.clone()), &mut outbox);
+        }
+
+        // The digest reaches the late process, which asks the other one for both preproposals
+        held.announce_preproposals(&mut outbox);
+        receivers[0].try_iter().for_each(drop);
+        late.handle(receivers[1].try_recv().unwrap(), &mut outbox);
+        let request = receivers[0].try_recv().unwrap();
+        assert!(matches!(&request, Message::GetPreProposals(1, hashes) if hashes.len() == 2));
+
+        held.handle(request, &mut outbox);
+        for msg in receivers[1].try_iter() {
+            late.handle(msg, &mut outbox);
+        }
+        assert_eq!(late.preproposals(), preproposals);
+
+        // Nothing is asked once it holds them
+        held.announce_preproposals(&mut outbox);
+        late.handle(receivers[1].try_recv().unwrap(), &mut outbox);
+        assert!(receivers[0].try_iter().all(|msg| matches!(msg, Message::PreProposalDigest(..))));
+    }
+
+    #[test]
+    fn decided_watch_holds_the_latest_commit() {
+        let (sender, receiver) = channel();
+        let mut process = Process::new(0, 0, vec![sender], receiver, false);
+        let mut watch = process.decided_watch();
+
+        let mut proposer = process.clone();
+        let handle = thread::spawn(move || proposer.propose(1, PreProposal::new(vec![BlockHash::from(1)], 0), 0));
+        let decided = watch.changed(Duration::from_secs(10)).unwrap();
+        let proposal = handle.join().unwrap();
+
+        assert_eq!(decided, Decided { instance: 1, rank: 0, value: proposal.hash });
+        assert!(!watch.has_changed());
+        process.stop();
+    }
+
+    fn b_certificate(senders: i64, value: ProposalHash) -> CertificateResponses {
+        let broadcast = Arc::new(Broadcast::new(0, Step::B, value, Some(true), 0, None));
+        (0..senders)
+            .map(|sender| Response::new(sender, Step::B, 0, vec![State::new(Value::BValue(BValue::new(value, true)), broadcast.clone())]))
+            .collect()
+    }
+
+    #[test]
+    fn announced_processes_catch_up_on_missed_commits() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..2).map(|_| channel()).unzip();
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        let mut core = Core::new(0, 1, false, Config::default());
+        for value in 1..=3 {
+            core.commits().lock().unwrap().record(0, BlockHash::from(value), b_certificate(3, BlockHash::from(value))).unwrap();
+        }
+
+        core.handle(Message::Announce(1, 1), &mut outbox);
+        outbox.flush();
+        let Ok(Message::Commits(0, commits)) = receivers[1].try_recv() else {
+            panic!("no commits sent");
+        };
+        assert_eq!(commits.iter().map(|commit| commit.instance).collect::<Vec<_>>(), vec![1, 2]);
+
+        // A restarted process that has the first commit checks the others, and stops at a certificate that does not commit
+        let mut restarted = Core::new(1, 1, false, Config::default());
+        restarted.commits().lock().unwrap().record(0, BlockHash::from(1), b_certificate(3, BlockHash::from(1))).unwrap();
+        let mut forged = commits.clone();
+        forged[1].certificate = b_certificate(2, BlockHash::from(3));
+        restarted.handle(Message::Commits(0, forged), &mut outbox);
+        assert_eq!(restarted.commits().lock().unwrap().next_instance(), 2);
+        assert_eq!(restarted.peer_accounting().peer(0).invalid, 1);
+
+        restarted.handle(Message::Commits(0, commits), &mut outbox);
+        let log = restarted.commits();
+        let log = log.lock().unwrap();
+        assert_eq!(log.next_instance(), 3);
+        assert_eq!(log.decided().subscribe().borrow().map(|decided| decided.value), Some(BlockHash::from(3)));
+    }
+
+    #[test]
+    fn certificates_of_past_instances_are_backfilled() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..2).map(|_| channel()).unzip();
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        let mut core = Core::new(0, 1, false, Config::default());
+        for value in 1..=3 {
+            core.commits().lock().unwrap().record(0, BlockHash::from(value), b_certificate(3, BlockHash::from(value))).unwrap();
+        }
+
+        core.handle(Message::GetCertificates(1, 1..5), &mut outbox);
+        outbox.flush();
+        let Ok(Message::Certificates(0, commits)) = receivers[1].try_recv() else {
+            panic!("no certificates sent");
+        };
+        assert_eq!(commits.iter().map(|commit| commit.instance).collect::<Vec<_>>(), vec![1, 2]);
+
+        // An observer takes the commits f+1 processes sent it, and none whose certificate does not commit
+        let mut observer = Core::new(1, 1, false, Config::default());
+        observer.commits().lock().unwrap().want(0..3);
+        let mut forged = commits.clone();
+        forged[0].certificate = b_certificate(2, BlockHash::from(2));
+        observer.handle(Message::Certificates(0, commits.clone()), &mut outbox);
+        observer.handle(Message::Certificates(2, forged), &mut outbox);
+        assert_eq!(observer.peer_accounting().peer(2).invalid, 1);
+
+        observer.handle(Message::Certificates(3, commits.clone()), &mut outbox);
+        let log = observer.commits();
+        let mut log = log.lock().unwrap();
+        assert_eq!(log.next_instance(), 0);
+        assert_eq!(log.finish_backfill(), commits);
+    }
+
+    #[test]
+    fn new_members_start_from_a_checkpoint() {
+        let commit = |instance: u64, value: u64| CommitRecord { instance, rank: 0, value: BlockHash::from(value), certificate: b_certificate(3, BlockHash::from(value)), committee: None };
+        let checkpoint = |instance: u64, value: u64| CommitRecord { committee: Some(Committee { f: 1, members: BTreeMap::new(), configuration: None }), ..commit(instance, value) };
+        let (sender, _receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::disabled());
+        let mut joining = Core::new(4, 1, false, Config::default());
+
+        // Commits without their committee are no checkpoints
+        joining.handle(Message::Commits(1, vec![commit(5, 5)]), &mut outbox);
+        joining.handle(Message::Commits(2, vec![commit(5, 5)]), &mut outbox);
+        assert_eq!(joining.commits().lock().unwrap().next_instance(), 0);
+
+        // A single sender, or senders offering different commits for the instance, are not enough
+        joining.handle(Message::Commits(3, vec![checkpoint(5, 9)]), &mut outbox);
+        joining.handle(Message::Commits(0, vec![checkpoint(5, 5), commit(6, 6)]), &mut outbox);
+        assert_eq!(joining.commits().lock().unwrap().next_instance(), 0);
+
+        joining.handle(Message::Commits(1, vec![checkpoint(5, 5), commit(6, 6), commit(7, 7)]), &mut outbox);
+        let log = joining.commits();
+        let log = log.lock().unwrap();
+        assert_eq!(log.next_instance(), 8);
+        assert_eq!(log.since(0).iter().map(|commit| commit.value).collect::<Vec<_>>(), (5..=7).map(BlockHash::from).collect::<Vec<_>>());
+        assert_eq!(log.checkpoint().map(|checkpoint| (checkpoint.instance, checkpoint.committee.is_some())), Some((7, true)));
+    }
+
+    #[test]
+    fn new_members_catch_up_across_a_reconfiguration() {
+        let key = |id: Id| SigningKey::from_bytes(&[id as u8 + 1; 32]);
+        let configure = |identities: &Identities, id: Id, members: [Id; 4]| {
+            identities.set(key(id), members.into_iter().map(|member| (member, key(member).verifying_key())).collect());
+            identities.pin(identities.configuration(1, members.len()));
+        };
+        let certificate = |members: [Id; 4], value: u64| -> CertificateResponses {
+            b_certificate(3, BlockHash::from(value))
+                .into_iter()
+                .zip(members)
+                .map(|(response, member)| {
+                    let identities = Identities::default();
+                    configure(&identities, member, members);
+                    identities.sign(Response { sender: member, ..response })
+                })
+                .collect()
+        };
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..5).map(|_| channel()).unzip();
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        let (old, new) = ([0, 1, 2, 3], [0, 1, 2, 4]);
+
+        // Members commit under the old committee, then 3 is replaced by 4 before the next commit
+        for id in 0..2 {
+            let mut member = Core::new(id, 1, false, Config::default());
+            configure(&member.identities, id, old);
+            member.commits().lock().unwrap().record(0, BlockHash::from(1), certificate(old, 1)).unwrap();
+            configure(&member.identities, id, new);
+            member.handle(Message::Announce(4, 0), &mut outbox);
+        }
+        outbox.flush();
+        let offers: Vec<Message> = receivers[4].try_iter().flat_map(Message::into_messages).collect();
+        assert_eq!(offers.len(), 2);
+
+        // The certificate of the checkpoint does not verify against the new committee, only against the one it came with
+        let mut joining = Core::new(4, 1, false, Config::default());
+        configure(&joining.identities, 4, new);
+        let Message::Commits(_, commits) = &offers[0] else {
+            panic!("no checkpoint offered");
+        };
+        assert!(!Core::check_commit(&commits[0], 1, &ValueOrder::default(), &joining.identities));
+
+        // A committee that is not the one of its configuration is refused
+        let mut forged = commits.clone();
+        if let Some(committee) = &mut forged[0].committee {
+            committee.members.remove(&3);
+        }
+        joining.handle(Message::Commits(2, forged), &mut outbox);
+        assert_eq!(joining.peer_accounting().peer(2).invalid, 1);
+
+        for offer in offers {
+            joining.handle(offer, &mut outbox);
+        }
+        joining.handle(Message::Commits(0, vec![CommitRecord { instance: 1, rank: 0, value: BlockHash::from(2), certificate: certificate(new, 2), committee: None }]), &mut outbox);
+        let log = joining.commits();
+        let log = log.lock().unwrap();
+        assert_eq!(log.since(0).iter().map(|commit| commit.value).collect::<Vec<_>>(), vec![BlockHash::from(1), BlockHash::from(2)]);
+        assert_eq!(log.checkpoint().and_then(|checkpoint| checkpoint.committee).and_then(|committee| committee.configuration), Some(joining.identities.configuration(1, new.len())));
+    }
+
+    #[test]
+    fn banned_members_are_taken_back_at_the_commit_of_their_reconfiguration() {
+        let key = |seed: u8| SigningKey::from_bytes(&[seed; 32]);
+        let fresh = |seed: u8| RejoinGrounds::Rekey(key(seed).verifying_key().to_bytes());
+        let (sender, _receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::disabled());
+        let mut core = Core::new(0, 1, false, Config::default());
+        core.identities.set(key(1), (0..4).map(|id| (id, key(id as u8 + 1).verifying_key())).collect());
+        core.identities.pin(core.identities.configuration(1, 4));
+        for member in [2, 3] {
+            let signing = Identities::default();
+            signing.set(key(member as u8 + 1), core.identities.committee());
+            let broadcast = |value| signing.sign_broadcast(Broadcast::new(member, Step::R, BlockHash::from(value), None, 1, None));
+            let evidence = Equivocation::new(&broadcast(1), &broadcast(2), &core.identities).unwrap();
+            core.peer_table.ban(member, Duration::from_secs(60), &evidence).unwrap();
+        }
+        let commit = |core: &Core, value: ProposalHash| {
+            core.commits().lock().unwrap().record(0, value, b_certificate(3, value)).unwrap();
+            Rejoin::reconfigure(value, &core.peer_table, &core.identities, 1)
+        };
+
+        // Members that are not banned keep their key, banned ones need another than the one on record
+        core.handle(Message::Rejoin(Rejoin::new(1, fresh(9), 5, &key(9))), &mut outbox);
+        assert_eq!(core.identities.key(1), Some(key(2).verifying_key()));
+        core.hold_rejoin(Rejoin::new(3, fresh(4), 5, &key(4)));
+        assert_eq!(commit(&core, Rejoin::value(3, fresh(4))), None);
+
+        // The fresh key only counts once the committee commits it, and the pinned configuration moves with it
+        let configuration = core.identities.pinned();
+        core.hold_rejoin(Rejoin::new(3, fresh(9), 5, &key(9)));
+        assert!(!core.peer_table.admits(3));
+        assert_eq!(core.identities.key(3), Some(key(4).verifying_key()));
+        assert_eq!(commit(&core, Rejoin::value(3, fresh(9))), Some(3));
+        assert!(core.peer_table.admits(3));
+        assert_eq!(core.identities.key(3), Some(key(9).verifying_key()));
+        assert_ne!(core.identities.pinned(), configuration);
+        assert_eq!(core.identities.pinned(), Some(core.identities.configuration(1, 4)));
+
+        // An unban committed before the request arrives takes the member back as it does, under the key on record only
+        assert_eq!(commit(&core, Rejoin::value(2, RejoinGrounds::Unban)), None);
+        core.hold_rejoin(Rejoin::new(2, RejoinGrounds::Unban, 5, &key(9)));
+        assert!(!core.peer_table.admits(2));
+        core.hold_rejoin(Rejoin::new(2, RejoinGrounds::Unban, 5, &key(3)));
+        assert!(core.peer_table.admits(2));
+        assert_eq!((core.peer_accounting().peer(2).invalid, core.peer_accounting().peer(3).invalid), (1, 1));
+    }
+
+    #[test]
+    fn rejoining_processes_answer_from_their_resume_instance() {
+        let (sender, receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::disabled());
+        let mut rejoining = Core::new(0, 1, false, Config::default());
+        rejoining.commits().lock().unwrap().silent_until(1);
+        let answered = |receiver: &Receiver<Message>| receiver.try_iter().flat_map(Message::into_messages).any(|message| matches!(message, Message::Response(_)));
+
+        rejoining.handle(Message::Broadcast(Broadcast::new(1, Step::R, BlockHash::from(1), None, 0, None)), &mut outbox);
+        outbox.flush();
+        assert!(!answered(&receiver));
+
+        rejoining.commits().lock().unwrap().record(0, BlockHash::from(1), b_certificate(3, BlockHash::from(1))).unwrap();
+        rejoining.handle(Message::Broadcast(Broadcast::new(2, Step::R, BlockHash::from(1), None, 0, None)), &mut outbox);
+        outbox.flush();
+        assert!(answered(&receiver));
+    }
+
+    #[derive(Debug)]
+    struct OnlyValue(ProposalHash);
+
+    impl ValidityPredicate for OnlyValue {
+        fn valid(&self, value: ProposalHash) -> bool {
+            value == self.0
+        }
+    }
+
+    #[test]
+    fn broadcasts_of_invalid_values_are_not_answered() {
+        let (sender, receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::disabled());
+        let mut core = Core::new(0, 1, false, Config::default());
+        core.validity.set(Arc::new(OnlyValue(BlockHash::from(1))));
+        let answered = |receiver: &Receiver<Message>| receiver.try_iter().flat_map(Message::into_messages).any(|message| matches!(message, Message::Response(_)));
+
+        core.handle(Message::Broadcast(Broadcast::new(1, Step::R, BlockHash::from(2), None, 0, None)), &mut outbox);
+        outbox.flush();
+        assert!(!answered(&receiver));
+        assert_eq!(core.consensus_metrics().stats().rejected, [(Rejection::InvalidValue, 1)].into());
+
+        core.handle(Message::Broadcast(Broadcast::new(2, Step::R, BlockHash::from(1), None, 0, None)), &mut outbox);
+        outbox.flush();
+        assert!(answered(&receiver));
+    }
+
+    #[test]
+    fn coordinators_relay_the_broadcasts_of_their_steps() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        let config = Config { coordinated_broadcasts: Some(Duration::from_millis(50)), ..Config::default() };
+        let mut core = Core::new(0, 1, false, config);
+        let relayed = |receiver: &Receiver<Message>| -> Vec<Broadcast> {
+            receiver.try_iter().flat_map(Message::into_messages).filter_map(|message| match message {
+                Message::Broadcast(broadcast) => Some(broadcast),
+                _ => None,
+            }).collect()
+        };
+
+        // 0 coordinates the R step of rank 0, and relays the broadcast to every process but itself and its sender
+        let broadcast = Broadcast::new(1, Step::R, BlockHash::from(1), None, 0, None);
+        assert_eq!(Process::coordinator(broadcast.step, broadcast.rank, 4), 0);
+        core.handle(Message::Broadcast(broadcast.clone()), &mut outbox);
+        outbox.flush();
+        let relayed: Vec<Vec<Broadcast>> = receivers.iter().map(relayed).collect();
+        assert_eq!(relayed, vec![Vec::new(), Vec::new(), vec![broadcast.clone()], vec![broadcast]]);
+    }
+
+    #[test]
+    fn tampered_relayed_broadcasts_are_rejected() {
+        let (sender, receiver) = channel();
+        let mut outbox = Outbox::new(PeerQueues::direct(vec![sender]), BatchConfig::disabled());
+        let config = Config { coordinated_broadcasts: Some(Duration::from_millis(50)), ..Config::default() };
+        let mut core = Core::new(0, 1, false, config);
+        let answered = |receiver: &Receiver<Message>| receiver.try_iter().flat_map(Message::into_messages).any(|message| matches!(message, Message::Response(_)));
+
+        // A coordinator that changes the value of a broadcast it relays leaves it with a certificate for another one
+        let value = BlockHash::from(1);
+        let broadcast = Broadcast::new(2, Step::A, value, None, 0, Some(r_certificate(3, value).into()));
+        let mut tampered = broadcast.clone();
+        tampered.value = BlockHash::from(2);
+        tampered.rehash();
+
+        core.handle(Message::Broadcast(tampered), &mut outbox);
+        outbox.flush();
+        assert!(!answered(&receiver));
+        assert_eq!(core.consensus_metrics().stats().rejected, [(Rejection::UnjustifiedValue, 1)].into());
+
+        core.handle(Message::Broadcast(broadcast), &mut outbox);
+        outbox.flush();
+        assert!(answered(&receiver));
+    }
+
+    #[test]
+    fn lagging_senders_are_sent_the_highest_certificate() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        // Certificates are left to the validation pool, which is not running here
+        let mut core = Core::new(0, 1, false, Config { validation_workers: 1, ..Config::default() });
+
+        let highest = Broadcast::new(1, Step::R, BlockHash::from(1), None, 5, Some(CertificateResponses::new()));
+        core.handle(Message::Broadcast(highest.clone()), &mut outbox);
+        core.handle(Message::Broadcast(Broadcast::new(2, Step::R, BlockHash::from(2), None, 3, Some(CertificateResponses::new()))), &mut outbox);
+        core.handle(Message::Broadcast(Broadcast::new(3, Step::R, BlockHash::from(3), None, 0, None)), &mut outbox);
+        outbox.flush();
+
+        let relayed = |peer: usize| receivers[peer].try_iter().flat_map(Message::into_messages).filter(|message| *message == Message::Broadcast(highest.clone())).count();
+        assert_eq!((relayed(1), relayed(2), relayed(3)), (0, 0, 1));
+        assert_eq!(core.catch_up(2).map(|(r_value, _)| r_value.rank), Some(5));
+    }
+
+    #[test]
+    fn aggregated_responses_leave_out_nested_certificates() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..2).map(|_| channel()).unzip();
+        let mut outbox = Outbox::new(PeerQueues::direct(senders), BatchConfig::disabled());
+        // Certificates are left to the validation pool, which is not running here
+        let mut core = Core::new(0, 1, false, Config { validation_workers: 1, aggregated_responses: true, ..Config::default() });
+
+        let broadcast = Broadcast::new(1, Step::A, BlockHash::from(1), None, 0, Some(r_certificate(3, BlockHash::from(1)).into()));
+        core.handle(Message::Broadcast(broadcast.clone()), &mut outbox);
+        outbox.flush();
+
+        let Some(Message::Response(response)) = receivers[1].try_iter().flat_map(Message::into_messages).next() else {
+            panic!("no response sent");
+        };
+        assert_eq!(response.answers, broadcast.hash_value());
+        assert_eq!(response.state.len(), 1);
+        assert_eq!(*response.state[0].broadcast, broadcast);
+        assert!(response.state[0].broadcast.previous_step_responses.is_none());
+    }
+
+    #[test]
+    fn idle_run_loop_answers_state_dumps() {
+        let (sender, receiver) = channel();
+        let mut process = Process::new(0, 0, vec![sender], receiver, false);
+
+        let dump = process.dump_state(Duration::from_secs(5)).unwrap();
+        assert_eq!((dump.id, dump.broadcasts.len(), dump.r), (0, 0, RValue::default()));
+
+        process.stop();
+        thread::sleep(2 * IDLE_WAKEUP);
+        assert_eq!(process.dump_state(Duration::from_secs(5)), None);
+    }
+
+    #[test]
+    fn response_snapshots_are_in_canonical_order() {
+        let (sender, receiver) = channel();
+        let mut process = Process::new(0, 0, vec![sender], receiver, false);
+
+        let broadcast = Arc::new(Broadcast::new(2, Step::B, BlockHash::from(1), Some(true), 1, None));
+        let answer = |sender, step, rank| Response::new(sender, step, rank, vec![State::new(Value::BValue(BValue::new(BlockHash::from(1), true)), broadcast.clone())]);
+        for (sender, step, rank) in [(3, Step::B, 1), (1, Step::B, 1), (2, Step::R, 1), (0, Step::A, 0)] {
+            process.responses.insert(answer(sender, step, rank), 4);
+        }
+
+        let groups = process.snapshot_responses();
+        let keys: Vec<_> = groups.iter().map(|group| (group.step, group.rank, group.responses.iter().map(|response| response.sender).collect::<Vec<_>>())).collect();
+        assert_eq!(keys, vec![(Step::A, 0, vec![0]), (Step::R, 1, vec![2]), (Step::B, 1, vec![1, 3])]);
+        assert_eq!(groups[2].responses[0].states[0].broadcast_sender, 2);
+        assert_eq!(groups, process.snapshot_responses());
+        assert!(groups[2].to_string().starts_with("instance 0 B rank=1 responses=2"));
+        process.stop();
+    }
+
+    #[test]
+    fn metrics_are_pushed_to_statsd() {
+        let daemon = UdpSocket::bind("127.0.0.1:0").unwrap();
+        daemon.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let statsd = StatsdConfig { address: daemon.local_addr().unwrap(), interval: Duration::from_millis(20) };
+
+        let (sender, receiver) = channel();
+        let config = Config { statsd: Some(statsd), ..Config::default() };
+        let mut process = Process::new_with_config(0, 0, vec![sender], receiver, false, config);
+        process.propose(1, PreProposal::new(vec![BlockHash::from(1)], 0), 0);
+
+        let mut gauges = Vec::new();
+        process.report_metrics(&mut gauges).unwrap();
+        assert!(gauges.contains(&("instances_decided".to_string(), 1.0)));
+        assert!(gauges.contains(&("latency.commit.count".to_string(), 1.0)));
+        // Hello, preproposal, proposal, then a broadcast and its response per step
+        assert!(gauges.contains(&("peer.0.sent_messages".to_string(), 9.0)), "{gauges:?}");
+
+        // Pushes that happened before the commit report nothing decided yet
+        let mut buffer = [0; 1024];
+        let decided = (0..10).any(|_| {
+            let length = daemon.recv(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..length]).lines().any(|line| line == "archipelago.0.instances_decided:1|g")
+        });
+        assert!(decided);
+        process.stop();
+    }
+
+    #[test]
+    fn memory_is_broken_down_once_an_instance_ran() {
+        let (sender, receiver) = channel();
+        let mut process = Process::new(0, 0, vec![sender], receiver, false);
+        process.propose(1, PreProposal::new(vec![BlockHash::from(1)], 0), 0);
+
+        let breakdown = process.memory_breakdown();
+        assert!(breakdown.subsystems().iter().all(|(_, bytes)| *bytes > 0), "{breakdown:?}");
+        let mut gauges = Vec::new();
+        process.report_metrics(&mut gauges).unwrap();
+        for (subsystem, _) in breakdown.subsystems() {
+            let name = format!("memory.{}_bytes", subsystem);
+            assert!(gauges.iter().any(|(gauge, value)| *gauge == name && *value > 0.0), "{name}: {gauges:?}");
+        }
+        assert!(gauges.iter().any(|(gauge, value)| gauge == "memory.held_bytes" && *value > 0.0), "{gauges:?}");
+        process.stop();
+    }
+
+    #[test]
+    fn test_consensus() {
+        run_consensus(Config::default(), 1..1000, |_, _, _| false);
+    }
+
+    #[test]
+    fn test_consensus_with_certificates_by_reference() {
+        let config = Config { certificates_by_reference: true, validation_workers: 2, ..Config::default() };
+
+        let by_reference = run_consensus(config, 1..50, |_, _, message| matches!(message, Message::Broadcast(broadcast)
+            if broadcast.certificate_refs.is_some() && broadcast.previous_step_responses.is_none()));
+        assert!(by_reference > 0);
+    }
+
+    #[test]
+    fn test_consensus_with_lazy_certificates() {
+        let config = Config { lazy_certificates: true, ..Config::default() };
+
+        let by_hash = run_consensus(config, 1..50, |_, _, message| matches!(message, Message::Broadcast(broadcast)
+            if broadcast.certificate_hash.is_some() && broadcast.previous_step_responses.is_none()));
+        assert!(by_hash > 0);
+    }
+
+    #[test]
+    fn test_consensus_with_aggregated_responses() {
+        let config = Config { aggregated_responses: true, ..Config::default() };
+
+        // Answers to A and B broadcasts otherwise hold the broadcast with its certificate
+        let aggregated = run_consensus(config, 1..50, |_, _, message| matches!(message, Message::Response(response)
+            if response.step != Step::R && response.state.iter().all(|state| state.broadcast.previous_step_responses.is_none())));
+        assert!(aggregated > 0);
+    }
+
+    #[test]
+    fn test_consensus_with_acknowledged_batches() {
+        let config = Config { batch: BatchConfig::default().acknowledged(Duration::from_millis(50)), ..Config::default() };
+
+        let acks = run_consensus(config, 1..50, |_, _, message| matches!(message, Message::Ack(..)));
+        assert!(acks > 0);
+    }
+
+    #[test]
+    fn test_consensus_with_coordinated_broadcasts() {
+        // The faulty process coordinates some steps, whose broadcasts go out directly after the timeout
+        let config = Config { coordinated_broadcasts: Some(Duration::from_millis(50)), ..Config::default() };
+
+        let relayed = run_consensus(config, 1..50, |from, to, message| matches!(message, Message::Broadcast(broadcast)
+            if from != broadcast.sender && to != broadcast.sender));
+        assert!(relayed > 0);
+    }
+
+    #[test]
+    fn adopting_b_steps_commit_once_2f_plus_1_answers_hold_only_the_true_pair() {
+        // n = 5, f = 1: the first 2f+1 answers hold a mixed one, and a single true-only answer after them commits
+        let broadcast = Arc::new(Broadcast::new(0, Step::B, BlockHash::zero(), Some(true), 0, None));
+        let pair = |value: u64, flag: bool| State::new(Value::BValue(BValue::new(BlockHash::from(value), flag)), broadcast.clone());
+        let only_true = |sender: Id| Response::new(sender, Step::B, 0, vec![pair(1, true)]);
+        let store = ResponseStore::new();
+        store.insert(Response::new(0, Step::B, 0, vec![pair(1, true), pair(2, false)]), 4);
+        (1..=2).for_each(|sender| { store.insert(only_true(sender), 4); });
+
+        let mut tally = BTally::new(3);
+        let seen = store.wait_for_quorum_with(Step::B, 0, 3, |response| tally.add(response)).unwrap();
+        assert_eq!(tally.result(), Decision::Adopt(BlockHash::from(1)));
+        assert_eq!(Process::await_commit(&store, 0, &mut tally, &seen, Duration::from_millis(10)), None);
+        assert_eq!(tally.result(), Decision::Adopt(BlockHash::from(1)));
+
+        // The window is left as soon as the answers commit, n-f true-only ones or not
+        store.insert(only_true(3), 4);
+        let started = Instant::now();
+        let certificate = Process::await_commit(&store, 0, &mut tally, &seen, Duration::from_secs(60)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert_eq!(certificate.len(), 4);
+        assert_eq!(tally.result(), Decision::Commit(BlockHash::from(1)));
+    }
+
+    #[test]
+    fn test_consensus_with_fast_commits() {
+        setup_logger();
+
+        // Five members, so that n-f = 4 answers are kept, one more than the 2f+1 = 3 a commit takes. The faulty member 4 makes 0 and 1 hold a false pair once they answered with the true one, and answers 0 with both
+        // pairs, so that the first 2f+1 answers 0 gets to its B broadcast hold both and adopt. The B answers of 2 and 3
+        // reach 0 late, and those of 0 and 1 holding only the true pair count once they do, as a late answer of 4 does
+        let f = 1;
+        let config = Config { fast_commit_window: Some(Duration::from_secs(1)), ..Config::default() };
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..5).map(|_| channel()).unzip();
+        let mut receivers = receivers.into_iter();
+        let processes: Vec<Process> = (0..4)
+            .map(|id| {
+                let mut peers = senders.clone();
+                if id >= 2 {
+                    peers[0] = delayed(senders[0].clone(), Duration::from_millis(50), |message| matches!(message, Message::Response(response) if response.step == Step::B));
+                }
+                Process::new_with_config(id, f, peers, receivers.next().unwrap(), false, config)
+            })
+            .collect();
+
+        // A B broadcast of a false pair, on A answers that are not unanimous. Its value is above the true one, so
+        // that it takes the place of a true pair in B registers that hold it twice
+        let a_answer = |sender: Id, value: BlockHash| {
+            let broadcast = Arc::new(Broadcast::new(sender, Step::A, value, None, 0, None));
+            Response::new(sender, Step::A, 0, vec![State::new(Value::AValue(AValue(value)), broadcast)])
+        };
+        let highest = BlockHash::from_bytes([u8::MAX; 32]);
+        let certificate = vec![a_answer(1, BlockHash::from(100)), a_answer(2, highest), a_answer(4, highest)];
+        let (flag, value) = Process::process_a_responses(&certificate, 2 * f + 1, false, &ValueOrder::default());
+        let misleading = Arc::new(Broadcast::new(4, Step::B, value, Some(flag), 0, Some(certificate.into())));
+        let faulty = receivers.next().unwrap();
+        thread::spawn(move || {
+            let mut misled = HashSet::new();
+            for message in faulty.iter().flat_map(Message::into_messages) {
+                match message {
+                    Message::Broadcast(broadcast) if broadcast.sender == 0 && broadcast.step == Step::B => {
+                        let true_pair = State::new(Value::BValue(BValue::new(broadcast.value, true)), Arc::new(broadcast.clone()));
+                        let false_pair = State::new(Value::BValue(BValue::new(value, false)), misleading.clone());
+                        let both = Response::new(4, Step::B, 0, vec![false_pair, true_pair.clone()]).answering(broadcast.hash_value());
+                        let _ = senders[0].send(Message::Response(both));
+
+                        let only_true = Response::new(4, Step::B, 0, vec![true_pair]).answering(broadcast.hash_value());
+                        let sender = senders[0].clone();
+                        thread::spawn(move || {
+                            thread::sleep(Duration::from_millis(100));
+                            let _ = sender.send(Message::Response(only_true));
+                        });
+                    }
+                    Message::Response(response) if response.step == Step::B && response.sender < 2 && only_true(&response).is_some()
+                        && misled.insert(response.sender) => {
+                        let _ = senders[response.sender as usize].send(Message::Broadcast((*misleading).clone()));
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let proposers: Vec<_> = processes
+            .iter()
+            .map(|process| {
+                let mut process = process.clone();
+                let preproposal = PreProposal::new(vec![BlockHash::from(process.id as u64 + 1)], process.id);
+                thread::spawn(move || process.propose(2 * f + 1, preproposal, 0))
+            })
+            .collect();
+        let decisions: Vec<Proposal> = proposers.into_iter().zip(&processes).map(|(proposer, process)| join_or_report(proposer, process)).collect();
+
+        assert!(decisions.windows(2).all(|pair| pair[0].hash == pair[1].hash), "{decisions:?}");
+        assert!(processes[0].stats().fast_commits > 0, "{:?}", processes[0].stats());
+        for mut process in processes {
+            process.stop();
+        }
+    }
+
+    // Forwards the messages `delays` holds the delay after they were sent, and the others right away
+    fn delayed(sender: Sender<Message>, delay: Duration, delays: fn(&Message) -> bool) -> Sender<Message> {
+        let (delayed, receiver) = channel::<Message>();
+        let (stamped, due) = channel();
+        let direct = sender.clone();
+        thread::spawn(move || {
+            for message in receiver.iter().flat_map(Message::into_messages) {
+                if delays(&message) {
+                    let _ = stamped.send((Instant::now() + delay, message));
+                } else {
+                    let _ = direct.send(message);
+                }
+            }
+        });
+        thread::spawn(move || {
+            for (due, message) in due {
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+                let _ = sender.send(message);
+            }
+        });
+        delayed
+    }
+
+    #[test]
+    fn test_consensus_with_pre_votes() {
+        let config = Config { pre_vote_timeout: Some(Duration::from_millis(50)), ..Config::default() };
+
+        let pre_votes = run_consensus(config, 1..50, |_, _, message| matches!(message, Message::PreVote(..)));
+        assert!(pre_votes > 0);
+    }
+
+    #[test]
+    fn pre_votes_converge_on_the_most_voted_value() {
+        // Three members start from one value and the fourth from another, which it gives up for the value the others
+        // pre-voted, so that the R step of rank 0 is unanimous
+        let f = 1;
+        let config = Config { pre_vote_timeout: Some(Duration::from_secs(10)), ..Config::default() };
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let processes: Vec<Process> = receivers.into_iter().enumerate()
+            .map(|(id, receiver)| Process::new_with_config(id as Id, f, senders.clone(), receiver, false, config))
+            .collect();
+        let proposals: Vec<Proposal> = [1, 1, 1, 2].into_iter().zip(0..).map(|(value, id)| Proposal::new(vec![BlockHash::from(value)], id)).collect();
+        for sender in &senders {
+            for proposal in &proposals {
+                sender.send(Message::Proposal(proposal.clone())).unwrap();
+            }
+        }
+
+        let voters: Vec<_> = processes.iter().zip(&proposals)
+            .map(|(process, proposal)| {
+                let (process, value) = (process.clone(), proposal.hash);
+                thread::spawn(move || process.pre_vote(2 * f + 1, 0, value, config.pre_vote_timeout.unwrap()))
+            })
+            .collect();
+        let values: Vec<ProposalHash> = voters.into_iter().map(|voter| voter.join().unwrap()).collect();
+
+        assert_eq!(values, vec![proposals[0].hash; 4]);
+        for mut process in processes {
+            process.stop();
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn conflicting_broadcasts_of_a_process_are_caught_where_they_are_sent() {
+        let (sender, receiver) = channel();
+        let mut process = Process::new(0, 0, vec![sender], receiver, false);
+        process.send_broadcast(Broadcast::new(0, Step::R, BlockHash::from(1), None, 0, None));
+
+        let mut conflicting = process.clone();
+        let sent = thread::spawn(move || conflicting.send_broadcast(Broadcast::new(0, Step::R, BlockHash::from(2), None, 0, None))).join();
+        assert!(sent.is_err());
+        process.stop();
+    }
+
+    #[test]
+    fn pre_votes_are_cleared_once_decided() {
+        let config = Config { pre_vote_timeout: Some(Duration::from_secs(10)), ..Config::default() };
+        let (sender, receiver) = channel();
+        let mut process = Process::new_with_config(0, 0, vec![sender], receiver, false, config);
+        process.propose(1, PreProposal::new(vec![BlockHash::from(1)], 0), 0);
+
+        assert!(process.pre_votes.read().unwrap().is_empty());
+        process.stop();
+    }
+
+    #[test]
+    fn test_consensus_with_pinned_committee() {
+        let config = Config { pin_committee: true, ..Config::default() };
+
+        let unpinned = run_consensus(config, 1..50, |_, _, message| match message {
+            Message::Broadcast(broadcast) => broadcast.committee.is_none(),
+            Message::Response(response) => response.committee.is_none(),
+            _ => false,
+        });
+        assert_eq!(unpinned, 0);
+    }
+
+    #[test]
+    fn test_consensus_with_forced_adopt() {
+        let config = Config { forced_adopt_ranks: 2, stall_ranks: Some(1), ..Config::default() };
+
+        // Every instance goes through the forced ranks, so each one broadcasts at rank 2
+        let forced_past = run_consensus(config, 1..50, |_, _, message| matches!(message, Message::Broadcast(broadcast)
+            if broadcast.rank == 2 && broadcast.step == Step::R));
+        assert!(forced_past >= 49);
+    }
+
+    // A proposer that never returns fails the test with what it was waiting for, instead of hanging it
+    fn join_or_report(proposer: thread::JoinHandle<Proposal>, process: &Process) -> Proposal {
+        while !proposer.is_finished() {
+            if let Some(report) = process.stall_report() {
+                panic!("{}", report);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        proposer.join().unwrap()
+    }
+
+    // Runs the instances one after the other, and counts the messages `counted` holds given their sender and receiver
+    fn run_consensus(config: Config, instances: Range<u64>, counted: fn(Id, Id, &Message) -> bool) -> usize {
+        setup_logger();
+
+        instances.map(|instance| run_consensus_instance(instance, config, counted)).sum()
+    }
+
+    // Peers of `from` that count the messages `counted` holds on their way to each of them, the batched ones one by one
+    fn tapped(from: Id, peers: &[Sender<Message>], counted: fn(Id, Id, &Message) -> bool, count: &Arc<AtomicUsize>) -> Vec<Sender<Message>> {
+        peers.iter().zip(0..)
+            .map(|(peer, to)| {
+                let (tap, receiver) = channel::<Message>();
+                let (peer, count) = (peer.clone(), count.clone());
+                thread::spawn(move || {
+                    for message in receiver {
+                        let batched = match &message {
+                            Message::Batch(messages) | Message::Sequenced(_, _, messages) => messages.as_slice(),
+                            _ => &[],
+                        };
+                        let matching = std::iter::once(&message).chain(batched).filter(|message| counted(from, to, message)).count();
+                        count.fetch_add(matching, Ordering::Relaxed);
+                        let _ = peer.send(message);
+                    }
+                });
+                tap
+            })
+            .collect()
+    }
+
+    fn run_consensus_instance(instance: u64, config: Config, counted: fn(Id, Id, &Message) -> bool) -> usize {
+        let (sender1, receiver1) = channel();
+        let (sender2, receiver2) = channel();
+        let (sender3, receiver3) = channel();
+        let (sender4, receiver4) = channel();
+        let senders = [sender1, sender2, sender3, sender4];
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let f: usize = 1;
+        let threshold = 2 * f + 1;
+        
+        let mut process1 = Process::new_with_config(0, f, tapped(0, &senders, counted, &count), receiver1, false, config);
+        let mut process1_clone = process1.clone();
+        let alerts = process1.subscribe_alerts();
+        
+        let mut process2 = Process::new_with_config(1, f, tapped(1, &senders, counted, &count), receiver2, false, config);
+        let mut process2_clone = process2.clone();
+        
+        let mut process3 = Process::new_with_config(2, f, tapped(2, &senders, counted, &count), receiver3, false, config);
+        let mut process3_clone = process3.clone();
+        
+        let mut process4 = Process::new_with_config(3, f, tapped(3, &senders, counted, &count), receiver4, true, config);
+        let mut process4_clone = process4.clone();
+
+        let block1 = BlockHash::from(instance);
+        let block2 = BlockHash::from(instance + 1);
+        let block3 = BlockHash::from(instance + 2);
+        let block4 = BlockHash::from(instance + 3);
+
+        let preproposal1 = PreProposal::new(vec![block1, block2], 0);
+        let preproposal2 = PreProposal::new(vec![block2], 1);
+        let preproposal3 = PreProposal::new(vec![block3, block2], 2);
+        let preproposal4 = PreProposal::new(vec![block4], 3);
+
+        let preproposals = vec![preproposal1.clone(), preproposal2.clone(), preproposal3.clone(), preproposal4.clone()];
+
+        let p1 = thread::spawn(move || {
+            process1.propose(threshold, preproposal1, 0)
+        });
+        
+        let p2 = thread::spawn(move || {
+            process2.propose(threshold, preproposal2, 0)
+        });
+        
+        let p3 = thread::spawn(move || {
+            process3.propose(threshold, preproposal3, 0)
+        });
+
+        let p4 = thread::spawn(move || {
+            process4.propose(threshold, preproposal4, 0)
+        });
+        
+        let p1_value = join_or_report(p1, &process1_clone);
+        let p2_value = join_or_report(p2, &process2_clone);
+        let p3_value = join_or_report(p3, &process3_clone);
+        let _ = join_or_report(p4, &process4_clone);
+
+        for process in [&process1_clone, &process2_clone, &process3_clone] {
+            assert!(matches!(process.progress.stage(), Stage::Decided(rank) if rank >= config.forced_adopt_ranks));
+            assert_eq!(process.latencies().commit.count, 1);
+            assert_eq!(process.ranks_since_commit(), 0);
+
+            let stats = process.stats();
+            assert_eq!(stats.instances_decided, 1);
+            // Ranks skipped by the pacemaker are not adopted in
+            assert!(stats.adopts < stats.ranks_to_commit);
+            assert!(stats.ranks_to_commit > config.forced_adopt_ranks as u64);
+        }
+
+        // Committing at rank forced_adopt_ranks or later takes more ranks than a lower threshold
+        if config.stall_ranks.is_some_and(|ranks| ranks < config.forced_adopt_ranks) {
+            assert!(alerts.try_iter().any(|alert| matches!(alert, Alert::RanksWithoutCommit { id: 0, .. })));
+        }
+
+        process1_clone.stop();
+        process2_clone.stop();
+        process3_clone.stop();
+        process4_clone.stop();
+
+        assert_eq!(p1_value.hash, p2_value.hash);
+        assert_eq!(p1_value.hash, p3_value.hash);
+
+        let agreed_preproposals = p1_value.preproposals;
+        let mut confirmed_blocks = Vec::new();
+
+        let mut block_counts: HashMap<BlockHash, usize> = HashMap::new();
+
+        for preproposal in &preproposals {
+            for block in preproposal.frontiers() {
+                *block_counts.entry(*block).or_insert(0) += 1;
+            }
+        }
+
+        for (block, count) in block_counts {
+            if count >= threshold {
+                confirmed_blocks.push(block);
+            }
+        }
+
+        let mut preproposal_map: HashMap<_, _> = HashMap::new();
+        for preproposal in &preproposals {
+            preproposal_map.insert(preproposal.hash(), preproposal.clone());
+        }
+
+        let mut agreed_blocks = HashSet::new();
+        for preproposal_hash in &agreed_preproposals {
+            if let Some(preproposal) = preproposal_map.get(preproposal_hash) {
+                for block in preproposal.frontiers() {
+                    agreed_blocks.insert(*block);
+                }
+            } else {
+                panic!("Agreed preproposal hash {:?} not found in original preproposals", preproposal_hash);
+            }
+        }
+
+        for block in &confirmed_blocks {
+            assert!(
+                agreed_blocks.contains(block),
+                "Confirmed block {:?} not found in agreed preproposals",
+                block
+            );
         }
+        count.load(Ordering::Relaxed)
     }
 }
\ No newline at end of file
@@ -0,0 +1,27 @@
+// Verifies the hash chain of audit logs written by Process::with_audit_log, and fails on the first broken link
+// archipelago-audit <audit log>...
+use std::{fs, process::ExitCode};
+use arquipelago::verify_audit_log;
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: archipelago-audit <audit log>...");
+        return ExitCode::FAILURE;
+    }
+
+    let mut broken = 0;
+    for path in &paths {
+        let text = fs::read_to_string(path).unwrap_or_else(|error| panic!("cannot read {}: {}", path, error));
+
+        match verify_audit_log(&text) {
+            Ok(entries) => println!("{}: {} entries, chain intact", path, entries.len()),
+            Err(error) => {
+                println!("{}: {}", path, error);
+                broken += 1;
+            }
+        }
+    }
+
+    if broken == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
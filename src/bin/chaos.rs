@@ -0,0 +1,112 @@
+// Starts a committee of archipelago-daemon member processes on this machine and kills, restarts, pauses (SIGSTOP) and
+// partitions them while values keep being submitted, failing as soon as two members disagree on an entry of their
+// decided logs, see Cluster. The archipelago-daemon binary is the one built next to this one (`cargo build --bins`)
+// archipelago-chaos [seconds] [nodes] [seed] [directory for the decided logs]
+// With --simulated, runs the same kind of disruptions on the simulator instead, where every run replays from its seed
+// and a diverging slot is recorded in the regression corpus, see ChaosEvent
+// archipelago-chaos --simulated [seed] [slots] [nodes] [history file]
+use std::{fs, process::ExitCode};
+#[cfg(unix)]
+use std::{path::PathBuf, time::Duration};
+use arquipelago::{corpus_path, max_faults, run_chaos, ChaosConfig, SimConfig};
+#[cfg(unix)]
+use arquipelago::{Cluster, ClusterConfig};
+
+fn number(args: &[String], index: usize, default: u64) -> u64 {
+    args.get(index).map_or(default, |arg| arg.parse().expect("arguments must be numbers"))
+}
+
+#[cfg(unix)]
+fn processes(args: &[String]) -> ExitCode {
+    let daemon = match std::env::current_exe() {
+        Ok(exe) => exe.with_file_name("archipelago-daemon"),
+        Err(error) => {
+            eprintln!("cannot find this executable: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+    if !daemon.exists() {
+        eprintln!("{} not found, build it with `cargo build --bins`", daemon.display());
+        return ExitCode::FAILURE;
+    }
+    let dir = args.get(3).map_or_else(|| std::env::temp_dir().join(format!("archipelago-chaos-{}", std::process::id())), PathBuf::from);
+
+    let config = ClusterConfig {
+        duration: Duration::from_secs(number(args, 0, 60)),
+        nodes: number(args, 1, 4) as usize,
+        seed: number(args, 2, 0),
+        ..ClusterConfig::new(daemon, dir.clone())
+    };
+    let report = match Cluster::launch(config).and_then(|mut cluster| cluster.run()) {
+        Ok(report) => report,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for (at, disruption) in &report.disruptions {
+        println!("{:>8.3}s {:?}", at.as_secs_f64(), disruption);
+    }
+    let decided: Vec<usize> = report.logs.iter().map(|log| log.len()).collect();
+    println!(
+        "{} values submitted, {} lagging members restarted, instances decided by each member: {:?}, logs in {}",
+        report.submitted, report.lagging_restarts, decided, dir.display()
+    );
+
+    match report.divergence {
+        Some(instance) => {
+            let values: Vec<_> = report.logs.iter().map(|log| log.get(&instance)).collect();
+            eprintln!("decided logs diverge at instance {}: {:?}", instance, values);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::SUCCESS,
+    }
+}
+
+// Members are paused with signals
+#[cfg(not(unix))]
+fn processes(_: &[String]) -> ExitCode {
+    eprintln!("only the simulated cluster runs on this platform, see --simulated");
+    ExitCode::FAILURE
+}
+
+fn simulated(args: &[String]) -> ExitCode {
+    let seed = number(args, 0, 0);
+    let slots = number(args, 1, 100) as usize;
+    let nodes = number(args, 2, 4) as usize;
+
+    let sim = SimConfig { nodes, f: max_faults(nodes), seed, ..SimConfig::default() };
+    let report = run_chaos(ChaosConfig { sim, slots, ..ChaosConfig::default() });
+
+    for (slot, events) in report.events.iter().enumerate() {
+        let decisions: Vec<_> = report.logs.iter().map(|log| log[slot]).collect();
+        println!("slot {}: {:?} -> {:?}", slot, events, decisions);
+    }
+    println!("{} slots, {} with a node that did not decide", slots, report.undecided());
+
+    // Checked with archipelago-check
+    if let Some(path) = args.get(3) {
+        fs::write(path, report.history.to_string()).expect("cannot write the history");
+    }
+
+    match report.divergence() {
+        Some(slot) => {
+            eprintln!("seed {}: decided logs diverge at slot {}", seed, slot);
+            match report.slots[slot].record(corpus_path()) {
+                Ok(()) => eprintln!("recorded as {} in {}", report.slots[slot].name, corpus_path().display()),
+                Err(error) => eprintln!("cannot record the slot: {}", error),
+            }
+            ExitCode::FAILURE
+        }
+        None => ExitCode::SUCCESS,
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.split_first() {
+        Some((flag, rest)) if flag == "--simulated" => simulated(rest),
+        _ => processes(&args),
+    }
+}
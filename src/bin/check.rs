@@ -0,0 +1,35 @@
+// Checks agreement, validity and the commit certificates of a recorded run, from the histories of its nodes
+// (written by archipelago-chaos, or logged by the nodes of a deployment), and fails on the first violated property
+// archipelago-check <history file>...
+use std::{fs, process::ExitCode};
+use arquipelago::History;
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: archipelago-check <history file>...");
+        return ExitCode::FAILURE;
+    }
+
+    let text: String = paths
+        .iter()
+        .map(|path| fs::read_to_string(path).unwrap_or_else(|error| panic!("cannot read {}: {}", path, error)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let history = match History::parse(&text) {
+        Ok(history) => history,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let violations = history.check();
+    for violation in &violations {
+        println!("{}", violation);
+    }
+    println!("{} entries, {} violations", history.entries.len(), violations.len());
+
+    if violations.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -0,0 +1,115 @@
+// Runs a committee in this process, with a control socket on its first member for supervision tooling, or a single
+// member of a committee whose members talk over TCP
+// archipelago-daemon <control address> [nodes] [settings file], the address being host:port or unix:<path>
+// archipelago-daemon member <id> <member addresses> <key file> <control address> <decided log> [resume instance]
+// [settings file], the member addresses being the host:port of every member by id, separated by commas, and the key file
+// holding the secret key of the member and the public keys of the committee (see key_file), which the connections
+// between members are authenticated with. The decisions are appended to
+// the log, and a member restarted on the same log goes on from the instance after its last decision, or from the resume
+// instance if the committee is further, see Daemon::spawn_logged
+// The settings file holds the reloadable settings (see Reload), applied at start and again on SIGHUP
+use std::{fs, net::SocketAddr, path::Path, process::ExitCode, sync::mpsc::channel};
+use arquipelago::{max_faults, parse_key_file, Config, ControlAddress, ControlServer, Daemon, DecidedLog, Id, Identities, Reload, TcpTransport};
+
+fn read_settings(path: &str) -> Result<Reload, String> {
+    fs::read_to_string(path).map_err(|error| format!("cannot read {}: {}", path, error)).and_then(|text| Reload::parse(&text))
+}
+
+// The daemons of the committee run in this process
+fn committee(args: &[String]) -> Result<(ControlAddress, Vec<Daemon>, Option<String>), String> {
+    let address: ControlAddress = args.first().ok_or("usage: archipelago-daemon <control address> [nodes] [settings file]")?.parse()?;
+    let nodes: usize = args.get(1).map_or(Ok(4), |nodes| nodes.parse().map_err(|_| "nodes must be a number"))?;
+    let settings = args.get(2).cloned();
+    let config = config(settings.as_deref())?;
+
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..nodes).map(|_| channel()).unzip();
+    let daemons = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(id, receiver)| Daemon::spawn(id as Id, max_faults(nodes), senders.clone(), receiver, config))
+        .collect();
+    Ok((address, daemons, settings))
+}
+
+// Only this member runs in this process, the others are reached at their addresses
+fn member(args: &[String]) -> Result<(ControlAddress, Vec<Daemon>, Option<String>), String> {
+    let usage = "usage: archipelago-daemon member <id> <member addresses> <key file> <control address> <decided log> [resume instance] [settings file]";
+    let [id, members, keys, address, log, rest @ ..] = args else {
+        return Err(usage.to_string());
+    };
+    let id: Id = id.parse().map_err(|_| "the id must be a number")?;
+    let members: Vec<SocketAddr> = members.split(',').map(|member| member.parse().map_err(|_| format!("invalid member address {:?}", member))).collect::<Result<_, _>>()?;
+    if id < 0 || id as usize >= members.len() {
+        return Err(format!("no address for member {}", id));
+    }
+    let (key, committee) = fs::read_to_string(keys).map_err(|error| format!("cannot read {}: {}", keys, error)).and_then(|text| parse_key_file(&text))?;
+    if committee.len() != members.len() {
+        return Err(format!("{} holds {} public keys for {} members", keys, committee.len(), members.len()));
+    }
+    let identities = Identities::default();
+    identities.set(key, committee);
+    let address: ControlAddress = address.parse()?;
+    let resume: u64 = rest.first().map_or(Ok(0), |resume| resume.parse().map_err(|_| "the resume instance must be a number"))?;
+    let settings = rest.get(1).cloned();
+    let config = config(settings.as_deref())?;
+
+    let (_, senders, receiver) = TcpTransport::bind(id, &members, &identities).map_err(|error| format!("cannot listen on {}: {}", members[id as usize], error))?;
+    let log = DecidedLog::open(Path::new(log)).map_err(|error| format!("cannot open {}: {}", log, error))?;
+    let daemon = Daemon::spawn_logged(id, max_faults(members.len()), senders, receiver, config, log, resume);
+    Ok((address, vec![daemon], settings))
+}
+
+fn config(settings: Option<&str>) -> Result<Config, String> {
+    let mut config = Config::default();
+    if let Some(path) = settings {
+        read_settings(path)?.apply(&mut config);
+    }
+    Ok(config)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let started = match args.split_first() {
+        Some((mode, rest)) if mode == "member" => member(rest),
+        _ => committee(&args),
+    };
+    let (address, daemons, settings) = match started {
+        Ok(started) => started,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let server = match ControlServer::bind(&address, daemons[0].clone()) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("cannot listen on {}: {}", address, error);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{} members, control socket on {}", daemons.len(), server.address());
+
+    // A settings file that does not parse is reported and leaves the settings as they were
+    #[cfg(unix)]
+    if let Some(path) = settings {
+        let mut hangups = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP]).expect("cannot handle SIGHUP");
+        let reloaded = daemons.clone();
+        std::thread::spawn(move || {
+            for _ in hangups.forever() {
+                match read_settings(&path) {
+                    Ok(reload) => {
+                        reloaded.iter().for_each(|daemon| daemon.reload(&reload));
+                        println!("reloaded {}", path);
+                    }
+                    Err(error) => eprintln!("{}", error),
+                }
+            }
+        });
+    }
+
+    // Shut down through the control socket
+    daemons[0].wait();
+    daemons.iter().for_each(Daemon::shutdown);
+    ExitCode::SUCCESS
+}
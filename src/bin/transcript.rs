@@ -0,0 +1,35 @@
+// Replays the validation rules on transcripts written by Process::with_transcript, and fails on the first invalid one
+// archipelago-transcript <transcript>...
+use std::{fs, process::ExitCode};
+use arquipelago::{verify_transcript, Transcript, ValueOrder};
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: archipelago-transcript <transcript>...");
+        return ExitCode::FAILURE;
+    }
+
+    let mut invalid = 0;
+    for path in &paths {
+        let text = fs::read_to_string(path).unwrap_or_else(|error| panic!("cannot read {}: {}", path, error));
+
+        match Transcript::parse(&text).and_then(|transcript| verify_transcript(&transcript, &ValueOrder::default()).map(|_| transcript)) {
+            Ok(transcript) => println!(
+                "{}: instance {} committed {} at rank {} ({} broadcasts, {} responses), valid",
+                path,
+                transcript.commit.instance,
+                transcript.commit.value.encode_hex(),
+                transcript.commit.rank,
+                transcript.broadcasts.len(),
+                transcript.responses.len()
+            ),
+            Err(error) => {
+                println!("{}: {}", path, error);
+                invalid += 1;
+            }
+        }
+    }
+
+    if invalid == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
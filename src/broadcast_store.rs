@@ -0,0 +1,154 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, mem::size_of, sync::Arc};
+use crate::{Broadcast, BroadcastHash, Id, MemorySize, ProposalHash, Rank, Step};
+
+// Broadcasts a process has reliably checked, and the senders of the responses answering each broadcast, checked or
+// not yet (Lines 74/75)
+// They are kept per rank, in the order they arrived, so that the broadcast responsible for a register value is found
+// without going through every broadcast, and ranks that fell below the horizon are dropped as a whole
+#[derive(Debug, Default)]
+pub struct BroadcastStore {
+    by_hash: HashMap<BroadcastHash, Arc<Broadcast>>,
+    ranks: BTreeMap<Rank, Vec<Arc<Broadcast>>>,
+    answered: HashMap<BroadcastHash, (Rank, HashSet<Id>)>,
+    // Of the stored broadcasts, kept up to date so that it can be published after every message
+    bytes: usize,
+}
+
+impl BroadcastStore {
+    pub fn new() -> BroadcastStore {
+        BroadcastStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    pub fn contains(&self, broadcast: &Broadcast) -> bool {
+        self.by_hash.contains_key(&broadcast.hash_value())
+    }
+
+    pub fn get(&self, hash: BroadcastHash) -> Option<&Arc<Broadcast>> {
+        self.by_hash.get(&hash)
+    }
+
+    // Senders of the responses answering the broadcast
+    pub fn count(&self, broadcast: &Broadcast) -> usize {
+        self.answered.get(&broadcast.hash_value()).map_or(0, |(_, senders)| senders.len())
+    }
+
+    // Records the sender of a response answering the broadcast, returns how many senders did
+    pub fn answered(&mut self, broadcast: &Broadcast, sender: Id) -> usize {
+        let (_, senders) = self.answered.entry(broadcast.hash_value()).or_insert_with(|| (broadcast.rank, HashSet::new()));
+        senders.insert(sender);
+        senders.len()
+    }
+
+    // Returns false for a broadcast that was already stored
+    pub fn insert(&mut self, broadcast: Broadcast) -> bool {
+        if self.contains(&broadcast) {
+            return false;
+        }
+
+        self.bytes += broadcast.memory_size();
+        let broadcast = Arc::new(broadcast);
+        self.ranks.entry(broadcast.rank).or_default().push(Arc::clone(&broadcast));
+        self.by_hash.insert(broadcast.hash_value(), broadcast);
+        true
+    }
+
+    // First broadcast stored of the step and rank holding the value (and the flag, for B broadcasts)
+    pub fn find(&self, step: Step, rank: Rank, value: ProposalHash, flag: Option<bool>) -> Option<Arc<Broadcast>> {
+        self.ranks
+            .get(&rank)?
+            .iter()
+            .find(|broadcast| broadcast.step == step && broadcast.value == value && (step != Step::B || broadcast.flag == flag))
+            .cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Broadcast>> {
+        self.ranks.values().flatten()
+    }
+
+    pub fn lowest_rank(&self) -> Option<Rank> {
+        self.ranks.keys().next().copied()
+    }
+
+    // Removes the broadcasts of the rank, except the ones to keep, and returns them
+    pub fn evict_rank(&mut self, rank: Rank, keep: impl Fn(&Broadcast) -> bool) -> Vec<Arc<Broadcast>> {
+        self.answered.retain(|hash, (answered_rank, _)| *answered_rank != rank || self.by_hash.get(hash).is_some_and(|broadcast| keep(broadcast)));
+        let Some(broadcasts) = self.ranks.remove(&rank) else {
+            return Vec::new();
+        };
+        let (kept, evicted): (Vec<_>, Vec<_>) = broadcasts.into_iter().partition(|broadcast| keep(broadcast));
+
+        for broadcast in &evicted {
+            self.by_hash.remove(&broadcast.hash_value());
+            self.bytes -= broadcast.memory_size();
+        }
+        if !kept.is_empty() {
+            self.ranks.insert(rank, kept);
+        }
+        evicted
+    }
+
+    // Removes the broadcasts of every rank below `rank`, except the ones to keep
+    pub fn evict_below(&mut self, rank: Rank, keep: impl Fn(&Broadcast) -> bool) -> Vec<Arc<Broadcast>> {
+        // Including the answers to broadcasts this process never stored
+        self.answered.retain(|hash, (answered_rank, _)| *answered_rank >= rank || self.by_hash.get(hash).is_some_and(|broadcast| keep(broadcast)));
+        let ranks: Vec<Rank> = self.ranks.range(..rank).map(|(rank, _)| *rank).collect();
+        ranks.into_iter().flat_map(|rank| self.evict_rank(rank, &keep)).collect()
+    }
+}
+
+// The broadcasts, and the answers recorded for them without counting their senders, so that it costs nothing to publish
+impl MemorySize for BroadcastStore {
+    fn memory_size(&self) -> usize {
+        self.bytes + self.answered.len() * size_of::<(BroadcastHash, Rank, HashSet<Id>)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+
+    fn broadcast(sender: i64, step: Step, rank: Rank, value: u64) -> Broadcast {
+        let flag = (step == Step::B).then_some(true);
+        Broadcast::new(sender, step, BlockHash::from(value), flag, rank, None)
+    }
+
+    #[test]
+    fn finds_and_evicts_per_rank() {
+        let mut store = BroadcastStore::new();
+
+        assert!(store.insert(broadcast(0, Step::R, 0, 1)));
+        assert!(!store.insert(broadcast(0, Step::R, 0, 1)));
+        store.insert(broadcast(1, Step::B, 0, 2));
+        store.insert(broadcast(1, Step::A, 1, 2));
+        store.insert(broadcast(2, Step::A, 2, 3));
+
+        assert_eq!(store.find(Step::R, 0, BlockHash::from(1), None).map(|found| found.sender), Some(0));
+        assert!(store.find(Step::B, 0, BlockHash::from(2), Some(false)).is_none());
+        assert!(store.find(Step::B, 0, BlockHash::from(2), Some(true)).is_some());
+        assert!(store.find(Step::A, 0, BlockHash::from(2), None).is_none());
+
+        // Answers are counted by sender, to broadcasts not stored as well
+        let unknown = broadcast(3, Step::B, 1, 4);
+        assert_eq!([0, 1, 1].map(|sender| store.answered(&unknown, sender)), [1, 2, 2]);
+        assert_eq!(store.count(&unknown), 2);
+
+        // The R broadcast of rank 0 is still needed
+        let evicted = store.evict_below(2, |broadcast| broadcast.step == Step::R);
+        assert_eq!(evicted.len(), 2);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.lowest_rank(), Some(0));
+        assert!(store.contains(&broadcast(0, Step::R, 0, 1)));
+        assert_eq!(store.iter().count(), 2);
+        assert_eq!(store.count(&unknown), 0);
+        assert_eq!(store.memory_size(), 2 * size_of::<Broadcast>());
+    }
+}
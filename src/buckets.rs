@@ -0,0 +1,162 @@
+use std::sync::mpsc::{Receiver, Sender};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{Config, FrontierCollector, Id, Instances, Message, PreProposal, Proposal, ProposalHash};
+
+// Accounts split into `Config::preconsensus_buckets` buckets by the leading bits of their key, a power of two
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buckets {
+    bits: u32,
+}
+
+impl Buckets {
+    // Rounded up to a power of two, up to 2^16 buckets
+    pub fn new(count: usize) -> Buckets {
+        Buckets { bits: count.max(1).next_power_of_two().trailing_zeros().min(16) }
+    }
+
+    pub fn count(&self) -> usize {
+        1 << self.bits
+    }
+
+    pub fn bucket(&self, account: &[u8; 32]) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        (u16::from_be_bytes([account[0], account[1]]) >> (16 - self.bits)) as usize
+    }
+}
+
+// A FrontierCollector per bucket, each turned into a preproposal of its own
+#[derive(Debug, Clone)]
+pub struct ShardedCollector {
+    buckets: Buckets,
+    collectors: Vec<FrontierCollector>,
+}
+
+impl ShardedCollector {
+    pub fn new(buckets: Buckets) -> ShardedCollector {
+        // Clones of a collector share its frontiers
+        ShardedCollector { buckets, collectors: (0..buckets.count()).map(|_| FrontierCollector::default()).collect() }
+    }
+
+    // Frontier of the account
+    pub fn add(&self, account: &[u8; 32], frontier: BlockHash) {
+        self.collectors[self.buckets.bucket(account)].add([frontier]);
+    }
+
+    pub fn collector(&self, bucket: usize) -> &FrontierCollector {
+        &self.collectors[bucket]
+    }
+
+    // Preproposals of the frontiers collected in each bucket, in bucket order
+    pub fn take_preproposals(&self, sender: Id) -> Vec<PreProposal> {
+        self.collectors.iter().map(|collector| collector.take_preproposal(sender)).collect()
+    }
+}
+
+// Proposals decided for each bucket of a slot, in bucket order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositeProposal {
+    pub buckets: Vec<Proposal>,
+    pub hash: ProposalHash,
+}
+
+impl CompositeProposal {
+    pub fn new(buckets: Vec<Proposal>) -> CompositeProposal {
+        let hash = buckets.iter().fold(Blake2HashBuilder::new(), |hasher, proposal| hasher.update(proposal.hash.as_bytes())).build();
+        CompositeProposal { buckets, hash }
+    }
+}
+
+// Preconsensus run per bucket: each slot decides one proposal per bucket, in instances of their own that run side by
+// side, so that no preproposal carries more than the frontiers of its bucket. Bucket b of slot s is decided in instance
+// s * buckets + b of the Instances, whose window is widened to hold every bucket of a slot
+#[derive(Debug, Clone)]
+pub struct ShardedPreconsensus {
+    buckets: Buckets,
+    instances: Instances,
+}
+
+impl ShardedPreconsensus {
+    pub fn new(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config) -> ShardedPreconsensus {
+        let buckets = Buckets::new(config.preconsensus_buckets);
+        let config = Config { instance_window: config.instance_window.max(buckets.count()), ..config };
+        ShardedPreconsensus { buckets, instances: Instances::new(id, f, senders, receiver, config) }
+    }
+
+    pub fn buckets(&self) -> Buckets {
+        self.buckets
+    }
+
+    // Takes one preproposal per bucket, None if the slot already fell behind the window
+    pub fn propose(&self, slot: u64, threshold: usize, preproposals: Vec<PreProposal>) -> Option<CompositeProposal> {
+        assert_eq!(preproposals.len(), self.buckets.count(), "one preproposal per bucket");
+        let first = slot * self.buckets.count() as u64;
+
+        let decided: Option<Vec<Proposal>> = self.instances.propose_all(first, threshold, preproposals).into_iter().collect();
+        decided.map(CompositeProposal::new)
+    }
+
+    // Union of the frontiers of the decided proposals, while the instances of the slot still run
+    pub fn frontiers(&self, slot: u64, composite: &CompositeProposal) -> Option<Vec<BlockHash>> {
+        let first = slot * self.buckets.count() as u64;
+        let mut frontiers = Vec::new();
+
+        for (instance, proposal) in (first..).zip(&composite.buckets) {
+            let page = self.instances.process(instance)?.proposal_frontiers(proposal.hash, None, usize::MAX)?;
+            frontiers.extend(page.frontiers);
+        }
+        Some(frontiers)
+    }
+
+    pub fn stop(&self) {
+        self.instances.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc::channel, thread};
+    use super::*;
+
+    #[test]
+    fn accounts_are_bucketed_by_leading_bits() {
+        let buckets = Buckets::new(3);
+        assert_eq!(buckets.count(), 4);
+        assert_eq!([0x00, 0x3F, 0x40, 0xFF].map(|first| buckets.bucket(&[first; 32])), [0, 0, 1, 3]);
+        assert_eq!(Buckets::new(1).bucket(&[0xFF; 32]), 0);
+    }
+
+    #[test]
+    fn buckets_are_decided_side_by_side() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let config = Config { preconsensus_buckets: 4, ..Config::default() };
+        let nodes: Vec<ShardedPreconsensus> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| ShardedPreconsensus::new(id as Id, 1, senders.clone(), receiver, config))
+            .collect();
+
+        // Every node collected the same frontier of an account in each bucket
+        let accounts: Vec<[u8; 32]> = [0x00, 0x40, 0x80, 0xC0].map(|first| [first; 32]).to_vec();
+        let proposers: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .map(|(id, node)| {
+                let collector = ShardedCollector::new(node.buckets());
+                for (value, account) in accounts.iter().enumerate() {
+                    collector.add(account, BlockHash::from(value as u64));
+                }
+                assert!((0..4).all(|bucket| collector.collector(bucket).len() == 1));
+
+                let node = node.clone();
+                thread::spawn(move || node.propose(0, 3, collector.take_preproposals(id as Id)).unwrap())
+            })
+            .collect();
+        let decided: Vec<CompositeProposal> = proposers.into_iter().map(|proposer| proposer.join().unwrap()).collect();
+
+        assert!(decided.iter().all(|composite| composite.hash == decided[0].hash && composite.buckets.len() == 4));
+        assert_eq!(nodes[0].frontiers(0, &decided[0]), Some((0..4).map(BlockHash::from).collect()));
+        nodes.iter().for_each(ShardedPreconsensus::stop);
+    }
+}
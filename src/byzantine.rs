@@ -0,0 +1,197 @@
+use std::fmt::Debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rsnano_core::BlockHash;
+use crate::{Broadcast, Certificate, Id, Message, PreProposal, Process, Step};
+
+// Adversarial behaviour of a Byzantine node, applied to every message its honest core sends to each peer
+// Returns the messages that actually go out to that peer, in order
+pub trait ByzantineStrategy: Debug + Send {
+    fn tamper(&mut self, to: Id, message: Message) -> Vec<Message>;
+}
+
+// Flips a random field of every message, like the byzantine flag of Process
+#[derive(Debug)]
+pub struct FieldFlipper;
+
+impl ByzantineStrategy for FieldFlipper {
+    fn tamper(&mut self, _to: Id, mut message: Message) -> Vec<Message> {
+        Process::apply_byzantine_behavior(&mut message);
+        vec![message]
+    }
+}
+
+// Never sends anything
+#[derive(Debug)]
+pub struct Mute;
+
+impl ByzantineStrategy for Mute {
+    fn tamper(&mut self, _to: Id, _message: Message) -> Vec<Message> {
+        Vec::new()
+    }
+}
+
+// Sends its broadcasts and preproposals with a different value to each half of the committee
+#[derive(Debug)]
+pub struct Equivocator;
+
+impl Equivocator {
+    fn value_for(to: Id, value: BlockHash) -> BlockHash {
+        if to % 2 == 0 {
+            value
+        } else {
+            BlockHash::from_bytes(value.as_bytes().map(|byte| !byte))
+        }
+    }
+}
+
+impl ByzantineStrategy for Equivocator {
+    fn tamper(&mut self, to: Id, message: Message) -> Vec<Message> {
+        let message = match message {
+            Message::Broadcast(mut broadcast) => {
+                broadcast.value = Equivocator::value_for(to, broadcast.value);
+                broadcast.rehash();
+                Message::Broadcast(broadcast)
+            }
+            Message::PreProposal(preproposal) => {
+                let frontiers = preproposal.frontiers().iter().map(|frontier| Equivocator::value_for(to, *frontier)).collect();
+                Message::PreProposal(PreProposal::new(frontiers, preproposal.sender))
+            }
+            message => message,
+        };
+        vec![message]
+    }
+}
+
+// Attaches the first certificate it ever sent to all of its later broadcasts, and replays its first broadcast alongside them
+#[derive(Debug, Default)]
+pub struct StaleCertificateReplayer {
+    first: Option<Broadcast>,
+    stale: Option<Certificate>,
+}
+
+impl ByzantineStrategy for StaleCertificateReplayer {
+    fn tamper(&mut self, _to: Id, message: Message) -> Vec<Message> {
+        let Message::Broadcast(mut broadcast) = message else {
+            return vec![message];
+        };
+
+        let first = self.first.get_or_insert_with(|| broadcast.clone()).clone();
+
+        match (&self.stale, &broadcast.previous_step_responses) {
+            (Some(stale), Some(_)) => broadcast.previous_step_responses = Some(stale.clone()),
+            (None, Some(certificate)) => self.stale = Some(certificate.clone()),
+            _ => (),
+        }
+
+        vec![Message::Broadcast(first), Message::Broadcast(broadcast)]
+    }
+}
+
+// Sends its broadcasts without the certificate that justifies them
+#[derive(Debug)]
+pub struct CertificateWithholder;
+
+impl ByzantineStrategy for CertificateWithholder {
+    fn tamper(&mut self, _to: Id, message: Message) -> Vec<Message> {
+        let message = match message {
+            Message::Broadcast(mut broadcast) => {
+                broadcast.previous_step_responses = None;
+                broadcast.certificate_refs = None;
+                broadcast.certificate_hash = None;
+                Message::Broadcast(broadcast)
+            }
+            message => message,
+        };
+        vec![message]
+    }
+}
+
+// Follows every message with `count` broadcasts of random values, steps and ranks
+#[derive(Debug)]
+pub struct ValueSpammer {
+    count: usize,
+    rng: StdRng,
+}
+
+impl ValueSpammer {
+    pub fn new(count: usize, seed: u64) -> ValueSpammer {
+        ValueSpammer { count, rng: StdRng::seed_from_u64(seed) }
+    }
+}
+
+impl ByzantineStrategy for ValueSpammer {
+    fn tamper(&mut self, _to: Id, message: Message) -> Vec<Message> {
+        let sender = message.sender().unwrap_or_default();
+        let mut messages = vec![message];
+
+        for _ in 0..self.count {
+            let step = match self.rng.gen_range(0..3) {
+                0 => Step::R,
+                1 => Step::A,
+                _ => Step::B,
+            };
+            let flag = (step == Step::B).then(|| self.rng.gen_bool(0.5));
+            let value = BlockHash::from(self.rng.gen::<u64>());
+
+            messages.push(Message::Broadcast(Broadcast::new(sender, step, value, flag, self.rng.gen_range(0..3), None)));
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Id, SimConfig, Simulation};
+
+    fn preproposals(nodes: usize) -> Vec<PreProposal> {
+        (0..nodes)
+            .map(|id| PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id as Id))
+            .collect()
+    }
+
+    // f = 1 out of 4 nodes runs the strategy, returns the number of runs in which every correct node decided
+    fn assert_agreement(strategy: impl Fn(u64) -> Box<dyn ByzantineStrategy>) -> usize {
+        (0..10)
+            .filter(|&seed| {
+                let config = SimConfig { seed, ..SimConfig::default() };
+                let outcome = Simulation::new(config, preproposals(config.nodes))
+                    .with_byzantine(3, strategy(seed))
+                    .run();
+
+                assert!(outcome.agreement(), "seed {seed}: {outcome:?}");
+                outcome.decisions[..3].iter().all(Option::is_some)
+            })
+            .count()
+    }
+
+    #[test]
+    fn agreement_with_field_flipper() {
+        assert_agreement(|_| Box::new(FieldFlipper));
+    }
+
+    #[test]
+    fn agreement_with_mute_node() {
+        assert_eq!(assert_agreement(|_| Box::new(Mute)), 10);
+    }
+
+    #[test]
+    fn agreement_with_equivocator() {
+        assert_agreement(|_| Box::new(Equivocator));
+    }
+
+    #[test]
+    fn agreement_with_stale_certificate_replayer() {
+        assert_agreement(|_| Box::<StaleCertificateReplayer>::default());
+    }
+
+    #[test]
+    fn agreement_with_certificate_withholder() {
+        assert_eq!(assert_agreement(|_| Box::new(CertificateWithholder)), 10);
+    }
+
+    #[test]
+    fn agreement_with_value_spammer() {
+        assert_agreement(|seed| Box::new(ValueSpammer::new(4, seed)));
+    }
+}
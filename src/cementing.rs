@@ -0,0 +1,153 @@
+use std::{fs, io, path::{Path, PathBuf}};
+use rsnano_core::BlockHash;
+
+// Ledger the blocks of decided proposals are cemented in
+pub trait Ledger {
+    // Cements the blocks in a single transaction. Blocks that are already cemented must be accepted again: a crash
+    // between a transaction and the progress saved after it cements the batch a second time
+    fn cement(&mut self, blocks: &[BlockHash]) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CementProgress {
+    pub instance: u64,
+    // Blocks of the instance cemented so far, out of the total
+    pub cemented: usize,
+    pub total: usize,
+}
+
+impl CementProgress {
+    pub fn is_done(&self) -> bool {
+        self.cemented >= self.total
+    }
+}
+
+// Cements the blocks a decided proposal resolves to in batches of `Config::cement_batch_size`, one ledger transaction
+// each, instead of one transaction per block. The progress is saved to a file after every batch, as a single line
+// "<instance> <cemented> <total>", so that cementing an instance again after a crash resumes from the last batch saved
+#[derive(Debug)]
+pub struct Cementer<L> {
+    ledger: L,
+    batch_size: usize,
+    path: PathBuf,
+    progress: Option<CementProgress>,
+}
+
+impl<L: Ledger> Cementer<L> {
+    // Reads the progress saved at the path, if any
+    pub fn open(ledger: L, batch_size: usize, path: &Path) -> io::Result<Cementer<L>> {
+        let progress = match fs::read_to_string(path) {
+            Ok(saved) => Some(Cementer::<L>::parse(&saved).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid cementing progress {:?}", saved)))?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+
+        Ok(Cementer { ledger, batch_size: batch_size.max(1), path: path.to_path_buf(), progress })
+    }
+
+    fn parse(saved: &str) -> Option<CementProgress> {
+        let mut numbers = saved.split_whitespace();
+        let progress = CementProgress {
+            instance: numbers.next()?.parse().ok()?,
+            cemented: numbers.next()?.parse().ok()?,
+            total: numbers.next()?.parse().ok()?,
+        };
+        numbers.next().is_none().then_some(progress)
+    }
+
+    pub fn ledger(&self) -> &L {
+        &self.ledger
+    }
+
+    // Last progress saved
+    pub fn progress(&self) -> Option<CementProgress> {
+        self.progress
+    }
+
+    // Cements the blocks of the instance, which must come in the same order every time it is cemented, e.g. the sorted
+    // frontiers of `Process::proposal_frontiers`. The progress is reported after every batch
+    // Instances are cemented in order: the ones up to the last one saved are skipped
+    pub fn cement(&mut self, instance: u64, blocks: &[BlockHash], mut report: impl FnMut(CementProgress)) -> io::Result<()> {
+        let mut progress = match self.progress {
+            Some(saved) if saved.instance > instance || (saved.instance == instance && saved.is_done()) => return Ok(()),
+            Some(saved) if saved.instance == instance => CementProgress { total: blocks.len(), ..saved },
+            _ => CementProgress { instance, cemented: 0, total: blocks.len() },
+        };
+
+        while !progress.is_done() {
+            let batch = &blocks[progress.cemented..(progress.cemented + self.batch_size).min(blocks.len())];
+            self.ledger.cement(batch)?;
+
+            progress.cemented += batch.len();
+            self.save(progress)?;
+            report(progress);
+        }
+
+        // An instance without blocks is still saved as cemented
+        if self.progress != Some(progress) {
+            self.save(progress)?;
+        }
+        Ok(())
+    }
+
+    // Written aside and renamed, so that a crash leaves either the previous progress or the new one
+    fn save(&mut self, progress: CementProgress) -> io::Result<()> {
+        let written = self.path.with_extension("tmp");
+        fs::write(&written, format!("{} {} {}\n", progress.instance, progress.cemented, progress.total))?;
+        fs::rename(&written, &self.path)?;
+        self.progress = Some(progress);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use super::*;
+
+    // Fails every transaction after the first `crash_after` ones
+    #[derive(Debug, Default)]
+    struct TestLedger {
+        cemented: BTreeSet<BlockHash>,
+        transactions: usize,
+        crash_after: Option<usize>,
+    }
+
+    impl Ledger for TestLedger {
+        fn cement(&mut self, blocks: &[BlockHash]) -> io::Result<()> {
+            if self.crash_after.is_some_and(|crash_after| self.transactions >= crash_after) {
+                return Err(io::Error::other("crashed"));
+            }
+            self.cemented.extend(blocks.iter().copied());
+            self.transactions += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cementing_resumes_after_a_crash() {
+        let path = std::env::temp_dir().join(format!("archipelago-cementing-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let blocks: Vec<BlockHash> = (0..2500u64).map(BlockHash::from).collect();
+
+        let mut cementer = Cementer::open(TestLedger { crash_after: Some(2), ..TestLedger::default() }, 1000, &path).unwrap();
+        let mut reported = Vec::new();
+        assert!(cementer.cement(0, &blocks, |progress| reported.push(progress.cemented)).is_err());
+        assert_eq!(reported, vec![1000, 2000]);
+
+        // The blocks cemented before the crash are not cemented again
+        let mut cementer = Cementer::open(TestLedger::default(), 1000, &path).unwrap();
+        assert_eq!(cementer.progress(), Some(CementProgress { instance: 0, cemented: 2000, total: 2500 }));
+        cementer.cement(0, &blocks, |_| ()).unwrap();
+        assert_eq!((cementer.ledger().transactions, cementer.ledger().cemented.len()), (1, 500));
+
+        cementer.cement(0, &blocks, |_| ()).unwrap();
+        cementer.cement(1, &[], |_| ()).unwrap();
+        assert_eq!(cementer.ledger().transactions, 1);
+        assert_eq!(Cementer::open(TestLedger::default(), 1000, &path).unwrap().progress(), Some(CementProgress { instance: 1, cemented: 0, total: 0 }));
+
+        fs::write(&path, "1 2").unwrap();
+        assert!(Cementer::open(TestLedger::default(), 1000, &path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use crate::{Broadcast, BroadcastHash, Certificate, CertificateHash, Rank, Response, ResponseHash};
+
+// What a parked broadcast waits for before it can be checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Missing {
+    // Responses of a certificate by reference, fetched with GetResponses
+    Responses(Vec<ResponseHash>),
+    // Certificate sent by hash, fetched with GetCertificate
+    Certificate(CertificateHash),
+}
+
+// Resolves certificates sent by reference against the responses this process has already received, and certificates
+// sent by hash against the ones it kept
+// Broadcasts referencing unknown responses or certificates are parked until they are fetched from their sender
+#[derive(Debug, Default)]
+pub struct CertificateResolver {
+    known: HashMap<ResponseHash, Response>,
+    // Certificates to attach to broadcasts sent by hash and to serve, with the rank of their broadcast
+    certificates: HashMap<CertificateHash, (Rank, Certificate)>,
+    parked: HashMap<BroadcastHash, Broadcast>,
+}
+
+impl CertificateResolver {
+    pub fn new() -> CertificateResolver {
+        CertificateResolver::default()
+    }
+
+    // Returns true if the response was not known yet
+    pub fn record(&mut self, response: &Response) -> bool {
+        let hash = response.hash_value();
+        if self.known.contains_key(&hash) {
+            return false;
+        }
+
+        self.known.insert(hash, response.clone());
+        true
+    }
+
+    // Keeps the certificate of a broadcast, e.g. one this process sent by hash
+    pub fn keep(&mut self, broadcast: &Broadcast) {
+        if let Some(certificate) = &broadcast.previous_step_responses {
+            self.certificates.entry(Broadcast::certificate_hash(certificate)).or_insert_with(|| (broadcast.rank, certificate.clone()));
+        }
+    }
+
+    // Attaches the referenced responses or certificate, or parks the broadcast and returns what to fetch
+    pub fn resolve(&mut self, mut broadcast: Broadcast) -> Result<Broadcast, Missing> {
+        if let Some(hash) = broadcast.certificate_hash.filter(|_| broadcast.is_lazy()) {
+            let Some((_, certificate)) = self.certificates.get(&hash) else {
+                self.parked.insert(broadcast.hash_value(), broadcast);
+                return Err(Missing::Certificate(hash));
+            };
+
+            broadcast.previous_step_responses = Some(certificate.clone());
+            return Ok(broadcast);
+        }
+
+        let Some(refs) = broadcast.certificate_refs.as_ref().filter(|_| broadcast.is_by_reference()) else {
+            return Ok(broadcast);
+        };
+
+        let missing: Vec<ResponseHash> = refs.iter()
+            .filter(|hash| !self.known.contains_key(hash))
+            .copied()
+            .collect();
+
+        if !missing.is_empty() {
+            self.parked.insert(broadcast.hash_value(), broadcast);
+            return Err(Missing::Responses(missing));
+        }
+
+        let responses = refs.iter().map(|hash| self.known[hash].clone()).collect();
+        broadcast.resolve(responses);
+        Ok(broadcast)
+    }
+
+    // Known responses among the requested ones
+    pub fn serve(&self, hashes: &[ResponseHash]) -> Vec<Response> {
+        hashes.iter().filter_map(|hash| self.known.get(hash).cloned()).collect()
+    }
+
+    pub fn certificate(&self, hash: CertificateHash) -> Option<Certificate> {
+        self.certificates.get(&hash).map(|(_, certificate)| certificate.clone())
+    }
+
+    // Records fetched responses and returns the parked broadcasts that can now be resolved
+    pub fn receive(&mut self, responses: Vec<Response>) -> Vec<Broadcast> {
+        for response in &responses {
+            self.record(response);
+        }
+
+        let ready: Vec<BroadcastHash> = self.parked
+            .iter()
+            .filter(|(_, broadcast)| {
+                broadcast.is_by_reference() && broadcast.certificate_refs.iter().flatten().all(|hash| self.known.contains_key(hash))
+            })
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        let ready: Vec<Broadcast> = ready.into_iter()
+            .filter_map(|hash| self.parked.remove(&hash))
+            .collect();
+
+        ready.into_iter()
+            .filter_map(|broadcast| self.resolve(broadcast).ok())
+            .collect()
+    }
+
+    // Keeps a fetched certificate if a parked broadcast was sent with its hash, and returns those broadcasts resolved
+    // Certificates nobody waits for are dropped, so that peers cannot fill the resolver with them
+    pub fn receive_certificate(&mut self, certificate: Certificate) -> Vec<Broadcast> {
+        let hash = Broadcast::certificate_hash(&certificate);
+        let ready: Vec<BroadcastHash> = self.parked
+            .iter()
+            .filter(|(_, broadcast)| broadcast.is_lazy() && broadcast.certificate_hash == Some(hash))
+            .map(|(hash, _)| *hash)
+            .collect();
+        let Some(rank) = ready.first().map(|first| self.parked[first].rank) else {
+            return Vec::new();
+        };
+
+        self.certificates.insert(hash, (rank, certificate));
+        let ready: Vec<Broadcast> = ready.into_iter()
+            .filter_map(|hash| self.parked.remove(&hash))
+            .collect();
+
+        ready.into_iter()
+            .filter_map(|broadcast| self.resolve(broadcast).ok())
+            .collect()
+    }
+
+    // Takes a parked broadcast back, e.g. once f+1 processes answered it and it no longer needs its certificate
+    pub fn unpark(&mut self, hash: BroadcastHash) -> Option<Broadcast> {
+        self.parked.remove(&hash)
+    }
+
+    pub fn evict_rank(&mut self, rank: Rank) {
+        self.known.retain(|_, response| response.rank != rank);
+        self.certificates.retain(|_, (certificate_rank, _)| *certificate_rank != rank);
+        self.parked.retain(|_, broadcast| broadcast.rank != rank);
+    }
+
+    pub fn parked(&self) -> usize {
+        self.parked.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use std::sync::Arc;
+    use super::*;
+    use crate::{CertificateResponses, Step};
+
+    #[test]
+    fn parks_until_missing_responses_arrive() {
+        let mut resolver = CertificateResolver::new();
+        let response1 = Response::new(1, Step::B, 0, Vec::new());
+        let response2 = Response::new(2, Step::B, 0, Vec::new());
+        let broadcast = Broadcast::new(0, Step::R, BlockHash::from(1), None, 1, Some(vec![response1.clone(), response2.clone()].into()));
+
+        resolver.record(&response1);
+
+        let missing = resolver.resolve(broadcast.clone().into_reference()).unwrap_err();
+        assert_eq!(missing, Missing::Responses(vec![response2.hash_value()]));
+        assert_eq!(resolver.parked(), 1);
+
+        let ready = resolver.receive(vec![response2]);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].previous_step_responses, broadcast.previous_step_responses);
+        assert_eq!(resolver.parked(), 0);
+    }
+
+    #[test]
+    fn lazy_broadcasts_wait_for_their_certificate() {
+        let mut resolver = CertificateResolver::new();
+        let certificate: CertificateResponses = (1..4).map(|sender| Response::new(sender, Step::B, 0, Vec::new())).collect();
+        let broadcast = Broadcast::new(0, Step::R, BlockHash::from(1), None, 1, Some(certificate.clone()));
+        let lazy = broadcast.clone().into_lazy();
+
+        assert_eq!(resolver.resolve(lazy.clone()), Err(Missing::Certificate(Broadcast::certificate_hash(&certificate))));
+        assert!(resolver.receive_certificate(Arc::new(certificate[..2].into())).is_empty());
+        let ready = resolver.receive_certificate(Arc::new(certificate.clone()));
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].previous_step_responses, broadcast.previous_step_responses);
+
+        // Kept certificates resolve broadcasts sent with their hash right away, and are served
+        let other = Broadcast::new(2, Step::R, BlockHash::from(1), None, 1, Some(certificate.clone())).into_lazy();
+        assert_eq!(resolver.resolve(other).unwrap().previous_step_responses, broadcast.previous_step_responses);
+        assert_eq!(resolver.certificate(lazy.certificate_hash.unwrap()), Some(Arc::new(certificate)));
+        resolver.evict_rank(1);
+        assert_eq!(resolver.certificate(lazy.certificate_hash.unwrap()), None);
+    }
+
+    #[test]
+    fn passes_through_full_certificates() {
+        let mut resolver = CertificateResolver::new();
+        let broadcast = Broadcast::new(0, Step::R, BlockHash::from(1), None, 0, None);
+
+        assert_eq!(resolver.resolve(broadcast.clone()).unwrap(), broadcast);
+    }
+
+    #[test]
+    fn serves_known_responses_only() {
+        let mut resolver = CertificateResolver::new();
+        let response = Response::new(1, Step::A, 0, Vec::new());
+        resolver.record(&response);
+
+        assert_eq!(resolver.serve(&[response.hash_value(), 42.into()]), vec![response]);
+    }
+}
@@ -0,0 +1,161 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rsnano_core::BlockHash;
+use crate::{FaultAction, History, Id, PreProposal, ProposalHash, Regression, ScriptedFault, SimConfig, Time};
+
+// Disruption of the cluster during one consensus instance
+// Nodes are simulated cores, not OS processes, and the events are faults of the simulated network: a killed node loses
+// everything sent to or by it until it restarts with its state intact, a paused node gets everything that was sent to
+// it once it resumes, like one stopped with SIGSTOP would. Nothing is signalled or restarted for real
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChaosEvent {
+    Kill { node: Id, at: Time, restart: Time },
+    Pause { node: Id, at: Time, resume: Time },
+    // The minority cannot reach the rest of the cluster until the partition heals
+    Partition { minority: Vec<Id>, at: Time, heal: Time },
+}
+
+impl ChaosEvent {
+    pub(crate) fn faults(&self, nodes: usize) -> Vec<ScriptedFault> {
+        let isolate = |node: Id, start: Time, end: Time, action: FaultAction| {
+            vec![
+                ScriptedFault { from: Some(node), to: None, start, end, action },
+                ScriptedFault { from: None, to: Some(node), start, end, action },
+            ]
+        };
+
+        match self {
+            ChaosEvent::Kill { node, at, restart } => isolate(*node, *at, *restart, FaultAction::Drop),
+            ChaosEvent::Pause { node, at, resume } => isolate(*node, *at, *resume, FaultAction::Hold),
+            ChaosEvent::Partition { minority, at, heal } => {
+                let majority: Vec<Id> = (0..nodes as Id).filter(|node| !minority.contains(node)).collect();
+
+                minority
+                    .iter()
+                    .flat_map(|&a| majority.iter().flat_map(move |&b| [(a, b), (b, a)]))
+                    .map(|(from, to)| ScriptedFault { from: Some(from), to: Some(to), start: *at, end: *heal, action: FaultAction::Drop })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChaosConfig {
+    pub sim: SimConfig,
+    // Consensus instances run one after the other, each deciding one entry of the log
+    pub slots: usize,
+    pub events_per_slot: usize,
+    // Events start and end within this window of each instance
+    pub window: Time,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig { sim: SimConfig::default(), slots: 20, events_per_slot: 2, window: 50_000 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChaosReport {
+    // Decided log of each node, None where the node did not decide in time
+    pub logs: Vec<Vec<Option<ProposalHash>>>,
+    pub events: Vec<Vec<ChaosEvent>>,
+    // Proposals and commits of every slot, with the certificates the commits were decided on
+    pub history: History,
+    // Inputs of each slot, to record the ones that failed in the regression corpus
+    pub slots: Vec<Regression>,
+}
+
+impl ChaosReport {
+    // First slot in which two nodes decided differently
+    pub fn divergence(&self) -> Option<usize> {
+        (0..self.events.len()).find(|&slot| {
+            let mut decided = self.logs.iter().filter_map(|log| log[slot]);
+            decided.next().is_some_and(|first| decided.any(|decision| decision != first))
+        })
+    }
+
+    // Number of slots some node did not decide
+    pub fn undecided(&self) -> usize {
+        (0..self.events.len()).filter(|&slot| self.logs.iter().any(|log| log[slot].is_none())).count()
+    }
+}
+
+pub fn random_events(rng: &mut impl Rng, config: &ChaosConfig) -> Vec<ChaosEvent> {
+    let nodes = config.sim.nodes;
+
+    (0..config.events_per_slot)
+        .map(|_| {
+            let at = rng.gen_range(0..config.window);
+            let end = rng.gen_range(at..=config.window);
+            let node = rng.gen_range(0..nodes) as Id;
+
+            match rng.gen_range(0..3) {
+                0 => ChaosEvent::Kill { node, at, restart: end },
+                1 => ChaosEvent::Pause { node, at, resume: end },
+                _ => {
+                    let mut ids: Vec<Id> = (0..nodes as Id).collect();
+                    ids.shuffle(rng);
+                    ids.truncate(rng.gen_range(1..=nodes / 2));
+                    ChaosEvent::Partition { minority: ids, at, heal: end }
+                }
+            }
+        })
+        .collect()
+}
+
+// Runs the instances of the log one after the other, every node submitting new values to each of them
+pub fn run_chaos(config: ChaosConfig) -> ChaosReport {
+    let mut rng = StdRng::seed_from_u64(config.sim.seed);
+    let mut logs = vec![Vec::with_capacity(config.slots); config.sim.nodes];
+    let mut events = Vec::with_capacity(config.slots);
+    let mut history = History::new(config.sim.nodes, config.sim.f);
+    let mut slots = Vec::with_capacity(config.slots);
+
+    for slot in 0..config.slots {
+        let slot_events = random_events(&mut rng, &config);
+        let script = slot_events.iter().flat_map(|event| event.faults(config.sim.nodes)).collect();
+
+        let preproposals = (0..config.sim.nodes)
+            .map(|id| PreProposal::new(vec![BlockHash::from(rng.gen::<u64>()), BlockHash::from(slot as u64)], id as Id))
+            .collect();
+
+        let sim = SimConfig { seed: rng.gen(), ..config.sim };
+        let regression = Regression::new(&format!("chaos-{}-{}-{}", config.sim.seed, config.sim.nodes, slot), sim, preproposals).with_script(script);
+        let (outcome, simulation) = regression.run();
+        history.entries.extend(simulation.history(slot));
+        slots.push(regression);
+
+        for (log, decision) in logs.iter_mut().zip(outcome.decisions) {
+            log.push(decision);
+        }
+        events.push(slot_events);
+    }
+
+    ChaosReport { logs, events, history, slots }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_cuts_every_link_across() {
+        let faults = ChaosEvent::Partition { minority: vec![1], at: 0, heal: 10 }.faults(4);
+
+        assert_eq!(faults.len(), 6);
+        assert!(faults.iter().all(|fault| (fault.from == Some(1)) != (fault.to == Some(1))));
+    }
+
+    #[test]
+    fn logs_never_diverge() {
+        for seed in 0..3 {
+            let config = ChaosConfig { sim: SimConfig { seed, ..SimConfig::default() }, slots: 10, ..ChaosConfig::default() };
+            let report = run_chaos(config);
+
+            assert_eq!(report.divergence(), None, "seed {seed}: {report:?}");
+            assert_eq!(report.history.check(), Vec::new(), "seed {seed}");
+            assert!(report.logs.iter().all(|log| log.len() == 10));
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::{collections::{BTreeMap, BTreeSet}, fs, io::{self, BufRead, BufReader, Write}, net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream}, path::PathBuf, process::{Child, Command, Stdio}, thread, time::{Duration, Instant}};
+use log::{debug, warn};
+use ed25519_dalek::SigningKey;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rsnano_core::BlockHash;
+use crate::{diverging_instance, key_file, max_faults, parse_decided_log, Config, Id, ProposalHash};
+
+// How long a control command may take: a paused member does not answer at all
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(500);
+// How long a member that was started may take to answer on its control socket
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+// After the last disruption healed, how long the members get to decide the instances the others reached
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    // The archipelago-daemon binary the members run
+    pub daemon: PathBuf,
+    // Where the decided logs of the members go
+    pub dir: PathBuf,
+    pub nodes: usize,
+    pub duration: Duration,
+    pub seed: u64,
+    // A disruption starts about this often while fewer than f members are disrupted, and lasts up to `max_disruption`
+    pub disruption_interval: Duration,
+    pub max_disruption: Duration,
+    // A value is submitted this often, to a member picked at random
+    pub submission_interval: Duration,
+}
+
+impl ClusterConfig {
+    pub fn new(daemon: PathBuf, dir: PathBuf) -> ClusterConfig {
+        ClusterConfig {
+            daemon,
+            dir,
+            nodes: 4,
+            duration: Duration::from_secs(60),
+            seed: 0,
+            disruption_interval: Duration::from_secs(2),
+            max_disruption: Duration::from_secs(3),
+            submission_interval: Duration::from_millis(20),
+        }
+    }
+}
+
+// What happens to the processes of the members for a while
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Disruption {
+    // SIGKILL, then started again on the same decided log
+    Kill(Id),
+    // SIGSTOP, then SIGCONT
+    Pause(Id),
+    // The members cannot reach the rest of the cluster, each side dropping the other through its control socket
+    Partition(Vec<Id>),
+}
+
+impl Disruption {
+    pub fn members(&self) -> Vec<Id> {
+        match self {
+            Disruption::Kill(member) | Disruption::Pause(member) => vec![*member],
+            Disruption::Partition(members) => members.clone(),
+        }
+    }
+
+    // Of members not disrupted yet, so that no more than f are at once and the others keep deciding
+    pub fn random(rng: &mut impl Rng, nodes: usize, disrupted: &BTreeSet<Id>) -> Option<Disruption> {
+        let mut free: Vec<Id> = (0..nodes as Id).filter(|member| !disrupted.contains(member)).collect();
+        let room = max_faults(nodes).saturating_sub(disrupted.len());
+        if room == 0 || free.is_empty() {
+            return None;
+        }
+
+        free.shuffle(rng);
+        Some(match rng.gen_range(0..3) {
+            0 => Disruption::Kill(free[0]),
+            1 => Disruption::Pause(free[0]),
+            _ => Disruption::Partition(free[..rng.gen_range(1..=room.min(free.len()))].to_vec()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClusterReport {
+    // With how long after the start each one began
+    pub disruptions: Vec<(Duration, Disruption)>,
+    pub submitted: u64,
+    // Members restarted because they fell more than the instance window behind, which they cannot catch up on
+    pub lagging_restarts: u64,
+    // Decided log of each member
+    pub logs: Vec<BTreeMap<u64, ProposalHash>>,
+    // Lowest instance two members decided differently
+    pub divergence: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Member {
+    id: Id,
+    address: SocketAddr,
+    control: SocketAddr,
+    log: PathBuf,
+    // Key file the connections of the member are authenticated with
+    keys: PathBuf,
+    child: Option<Child>,
+}
+
+// A committee of archipelago-daemon processes on this machine, each a member over TCP with a control socket of its own,
+// whose processes are killed and started again, paused with SIGSTOP and cut off from each other while values keep being
+// submitted to them, see run. Their decided logs are compared after every step
+#[derive(Debug)]
+pub struct Cluster {
+    config: ClusterConfig,
+    members: Vec<Member>,
+    rng: StdRng,
+}
+
+impl Cluster {
+    pub fn launch(config: ClusterConfig) -> io::Result<Cluster> {
+        fs::create_dir_all(&config.dir)?;
+        // Ports the system picks, free again once the listeners are dropped
+        let ports = (0..2 * config.nodes).map(|_| TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()).collect::<io::Result<Vec<_>>>()?;
+        // Fresh for every launch, and not drawn from the seeded rng so that the disruptions of a seed stay the same
+        let keys: Vec<SigningKey> = (0..config.nodes).map(|_| SigningKey::from_bytes(&rand::random())).collect();
+        let committee: Vec<_> = keys.iter().map(SigningKey::verifying_key).collect();
+        let members = (0..config.nodes)
+            .map(|id| {
+                let log = config.dir.join(format!("decided-{}.log", id));
+                if log.exists() {
+                    fs::remove_file(&log)?;
+                }
+                let keys_path = config.dir.join(format!("keys-{}", id));
+                fs::write(&keys_path, key_file(&keys[id], &committee))?;
+                Ok(Member { id: id as Id, address: ports[id], control: ports[config.nodes + id], log, keys: keys_path, child: None })
+            })
+            .collect::<io::Result<_>>()?;
+
+        let mut cluster = Cluster { rng: StdRng::seed_from_u64(config.seed), config, members };
+        for id in 0..cluster.members.len() {
+            cluster.start(id as Id, 0)?;
+        }
+        Ok(cluster)
+    }
+
+    // Disrupts the members while submitting values to them for the configured duration, then heals every disruption
+    // and lets them settle. Stops at the first divergence of the decided logs
+    pub fn run(&mut self) -> io::Result<ClusterReport> {
+        let mut report = ClusterReport::default();
+        let started = Instant::now();
+        let mut active: Vec<(Instant, Disruption)> = Vec::new();
+        let mut next_disruption = started;
+
+        while started.elapsed() < self.config.duration && report.divergence.is_none() {
+            let now = Instant::now();
+            let (over, ongoing): (Vec<_>, Vec<_>) = active.into_iter().partition(|(end, _)| *end <= now);
+            active = ongoing;
+            for (_, disruption) in over {
+                self.heal(&disruption)?;
+            }
+
+            if now >= next_disruption {
+                let disrupted: BTreeSet<Id> = active.iter().flat_map(|(_, disruption)| disruption.members()).collect();
+                if let Some(disruption) = Disruption::random(&mut self.rng, self.members.len(), &disrupted) {
+                    self.disrupt(&disruption)?;
+                    let length = self.rng.gen_range(Duration::ZERO..=self.config.max_disruption);
+                    report.disruptions.push((started.elapsed(), disruption.clone()));
+                    active.push((now + length, disruption));
+                }
+                next_disruption = now + self.rng.gen_range(Duration::ZERO..=2 * self.config.disruption_interval);
+            }
+
+            if self.submit() {
+                report.submitted += 1;
+            }
+
+            let disrupted: BTreeSet<Id> = active.iter().flat_map(|(_, disruption)| disruption.members()).collect();
+            report.lagging_restarts += self.restart_lagging(&disrupted)?;
+            report.divergence = diverging_instance(&self.logs()?);
+            thread::sleep(self.config.submission_interval);
+        }
+
+        for (_, disruption) in active {
+            self.heal(&disruption)?;
+        }
+        if report.divergence.is_none() {
+            self.settle()?;
+        }
+        report.logs = self.logs()?;
+        report.divergence = diverging_instance(&report.logs);
+        Ok(report)
+    }
+
+    pub fn logs(&self) -> io::Result<Vec<BTreeMap<u64, ProposalHash>>> {
+        self.members
+            .iter()
+            .map(|member| match fs::read_to_string(&member.log) {
+                Ok(text) => parse_decided_log(&text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+                Err(error) => Err(error),
+            })
+            .collect()
+    }
+
+    fn disrupt(&mut self, disruption: &Disruption) -> io::Result<()> {
+        debug!("disrupting {:?}", disruption);
+        match disruption {
+            Disruption::Kill(member) => {
+                if let Some(mut child) = self.members[*member as usize].child.take() {
+                    child.kill()?;
+                    child.wait()?;
+                }
+            }
+            Disruption::Pause(member) => self.signal(*member, "-STOP")?,
+            Disruption::Partition(minority) => {
+                for (a, b) in self.across(minority) {
+                    let _ = self.command(a, &format!("disconnect {}", b));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn heal(&mut self, disruption: &Disruption) -> io::Result<()> {
+        debug!("healing {:?}", disruption);
+        match disruption {
+            // Past the instances the others may have started: it would wait forever in one whose first messages it
+            // missed. The ones it skips are decided without it
+            Disruption::Kill(member) => {
+                let furthest = self.instances().into_iter().filter(|(other, _)| other != member).map(|(_, instance)| instance).max();
+                let resume = furthest.map_or(0, |instance| instance + Config::default().instance_window as u64);
+                self.start(*member, resume)?;
+            }
+            Disruption::Pause(member) => self.signal(*member, "-CONT")?,
+            Disruption::Partition(minority) => {
+                for (a, b) in self.across(minority) {
+                    let _ = self.command(a, &format!("add {} {}", b, self.members[b as usize].address));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Both directions of every link between the members and the others
+    fn across(&self, minority: &[Id]) -> Vec<(Id, Id)> {
+        let majority: Vec<Id> = self.members.iter().map(|member| member.id).filter(|member| !minority.contains(member)).collect();
+        minority.iter().flat_map(|&a| majority.iter().flat_map(move |&b| [(a, b), (b, a)])).collect()
+    }
+
+    // Members more than the instance window behind a quorum never decide again: the instances they are in were stopped
+    // everywhere else. They are started again ahead of the others. A member that was started ahead of the others is not
+    // a quorum on its own, so it does not make them lag
+    fn restart_lagging(&mut self, disrupted: &BTreeSet<Id>) -> io::Result<u64> {
+        let instances = self.instances();
+        let mut reached: Vec<u64> = instances.values().copied().collect();
+        reached.sort_unstable_by(|a, b| b.cmp(a));
+        let Some(&quorum) = reached.get(self.members.len() - max_faults(self.members.len()) - 1) else {
+            return Ok(0);
+        };
+        let window = Config::default().instance_window as u64;
+        let lagging: Vec<Id> = instances.iter().filter(|(member, instance)| !disrupted.contains(member) && **instance + window < quorum).map(|(member, _)| *member).collect();
+
+        for member in &lagging {
+            warn!("member {} is more than {} instances behind, restarting it", member, window);
+            self.disrupt(&Disruption::Kill(*member))?;
+            self.heal(&Disruption::Kill(*member))?;
+        }
+        Ok(lagging.len() as u64)
+    }
+
+    // Waits for every member to reach the instance the furthest one was in
+    fn settle(&mut self) -> io::Result<()> {
+        let target = self.instances().values().max().copied().unwrap_or(0);
+        let deadline = Instant::now() + SETTLE_TIMEOUT;
+
+        while Instant::now() < deadline {
+            let instances = self.instances();
+            if instances.len() == self.members.len() && instances.values().all(|instance| *instance >= target) {
+                return Ok(());
+            }
+            // Instances only start with submissions
+            self.submit();
+            self.restart_lagging(&BTreeSet::new())?;
+            thread::sleep(self.config.submission_interval);
+        }
+        warn!("members did not all reach instance {} within {:?}", target, SETTLE_TIMEOUT);
+        Ok(())
+    }
+
+    // Instance each member that answers proposes in next
+    fn instances(&self) -> BTreeMap<Id, u64> {
+        self.members
+            .iter()
+            .filter_map(|member| {
+                let status = self.command(member.id, "status").ok()?;
+                let instance = status.strip_prefix(r#"{"instance":"#)?.split(',').next()?.parse().ok()?;
+                Some((member.id, instance))
+            })
+            .collect()
+    }
+
+    // A random value to a random member, false if it was not taken
+    fn submit(&mut self) -> bool {
+        let member = self.rng.gen_range(0..self.members.len()) as Id;
+        let value = BlockHash::from(self.rng.gen::<u64>());
+        self.command(member, &format!("submit {}", value.encode_hex())).is_ok_and(|answer| answer == r#"{"ok":true}"#)
+    }
+
+    fn start(&mut self, member: Id, resume: u64) -> io::Result<()> {
+        let addresses: Vec<String> = self.members.iter().map(|member| member.address.to_string()).collect();
+        let entry = &self.members[member as usize];
+        let child = Command::new(&self.config.daemon)
+            .arg("member")
+            .arg(member.to_string())
+            .arg(addresses.join(","))
+            .arg(&entry.keys)
+            .arg(entry.control.to_string())
+            .arg(&entry.log)
+            .arg(resume.to_string())
+            .stdout(Stdio::null())
+            .spawn()?;
+        self.members[member as usize].child = Some(child);
+
+        let deadline = Instant::now() + START_TIMEOUT;
+        while self.command(member, "status").is_err() {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, format!("member {} did not start", member)));
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        Ok(())
+    }
+
+    fn signal(&self, member: Id, signal: &str) -> io::Result<()> {
+        let Some(child) = &self.members[member as usize].child else {
+            return Ok(());
+        };
+        let status = Command::new("kill").arg(signal).arg(child.id().to_string()).status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("kill {} {} failed", signal, child.id())));
+        }
+        Ok(())
+    }
+
+    // One line over a connection of its own, see control::execute
+    fn command(&self, member: Id, command: &str) -> io::Result<String> {
+        let stream = TcpStream::connect_timeout(&self.members[member as usize].control, CONTROL_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+        writeln!(&stream, "{}", command)?;
+
+        let mut answer = String::new();
+        BufReader::new(stream).read_line(&mut answer)?;
+        Ok(answer.trim_end().to_string())
+    }
+}
+
+// Paused members are resumed first, so that they exit
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for member in 0..self.members.len() {
+            let _ = self.signal(member as Id, "-CONT");
+            if let Some(mut child) = self.members[member].child.take() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disruptions_leave_the_others_a_quorum() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let disruption = Disruption::random(&mut rng, 7, &BTreeSet::from([3])).unwrap();
+            assert!(!disruption.members().contains(&3) && disruption.members().len() <= 1, "{disruption:?}");
+        }
+        assert_eq!(Disruption::random(&mut rng, 4, &BTreeSet::from([0])), None);
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::{BTreeSet, HashMap}, sync::{Arc, Mutex}};
+use rsnano_core::{BlockHash, PublicKey};
+use crate::{Id, PreProposal, Process, Vote, VoteError};
+
+// Confirmed frontiers waiting for the next preproposal of this process, shared by the ones adding to it
+#[derive(Debug, Clone, Default)]
+pub struct FrontierCollector {
+    frontiers: Arc<Mutex<BTreeSet<BlockHash>>>,
+}
+
+impl FrontierCollector {
+    pub fn add(&self, frontiers: impl IntoIterator<Item = BlockHash>) {
+        self.frontiers.lock().unwrap().extend(frontiers);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frontiers.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Preproposal of the frontiers collected so far, which the collector starts over without
+    pub fn take_preproposal(&self, sender: Id) -> PreProposal {
+        let frontiers = std::mem::take(&mut *self.frontiers.lock().unwrap());
+        PreProposal::new(frontiers.into_iter().collect(), sender)
+    }
+}
+
+// Glue between the vote processor of an rsnano node and a process: `on_vote` takes each vote the node processed (e.g.
+// from an observer of its vote processor, rsnano_node is not a dependency of this crate), adds the final votes of
+// committee members to the vote cache of the process and hands the frontiers they confirm to the collector
+#[derive(Debug, Clone)]
+pub struct VoteAdapter {
+    process: Process,
+    // Committee members by their rsnano account key
+    voters: HashMap<PublicKey, Id>,
+    collector: FrontierCollector,
+}
+
+impl VoteAdapter {
+    // The voters must be the ones of `Process::with_voters`
+    pub fn new(process: Process, voters: &HashMap<Id, PublicKey>, collector: FrontierCollector) -> VoteAdapter {
+        let voters = voters.iter().map(|(voter, key)| (*key, *voter)).collect();
+        VoteAdapter { process, voters, collector }
+    }
+
+    pub fn collector(&self) -> &FrontierCollector {
+        &self.collector
+    }
+
+    // Returns the number of frontiers the vote confirmed. Votes of keys outside the committee are ignored, since a node
+    // processes the votes of every representative
+    pub fn on_vote(&self, vote: &rsnano_core::Vote) -> Result<usize, VoteError> {
+        let Some(voter) = self.voters.get(&vote.voting_account) else {
+            return Ok(0);
+        };
+
+        let confirmed = self.process.add_vote(&Vote::from_nano(*voter, vote.clone()))?;
+        let count = confirmed.len();
+        self.collector.add(confirmed);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use rsnano_core::{PrivateKey, RawKey};
+    use super::*;
+
+    #[test]
+    fn final_votes_of_the_node_confirm_frontiers() {
+        let key = |id: Id| PrivateKey::from(RawKey::from_bytes([id as u8 + 1; 32]));
+        let voters: HashMap<Id, PublicKey> = (0..4).map(|id| (id, key(id).public_key())).collect();
+        let (sender, receiver) = channel();
+        let mut process = Process::new(0, 1, vec![sender], receiver, false).with_voters(voters.clone());
+        let adapter = VoteAdapter::new(process.clone(), &voters, FrontierCollector::default());
+
+        let frontiers = vec![BlockHash::from(1), BlockHash::from(2)];
+        let on_vote = |vote: Vote| adapter.on_vote(&vote.to_nano());
+        // A representative outside the committee, a non-final vote and a forged one change nothing
+        assert_eq!(on_vote(Vote::new_final(4, &key(4), frontiers.clone())), Ok(0));
+        assert_eq!(on_vote(Vote::new(0, &key(0), 1, frontiers.clone())), Ok(0));
+        let forged = Vote { hashes: frontiers.clone(), ..Vote::new_final(0, &key(0), vec![]) };
+        assert_eq!(on_vote(forged), Err(VoteError::InvalidSignature));
+
+        assert_eq!(on_vote(Vote::new_final(0, &key(0), frontiers.clone())), Ok(0));
+        assert_eq!(on_vote(Vote::new_final(1, &key(1), frontiers.clone())), Ok(0));
+        assert_eq!(on_vote(Vote::new_final(2, &key(2), frontiers[..1].to_vec())), Ok(1));
+        assert_eq!(on_vote(Vote::new_final(3, &key(3), frontiers.clone())), Ok(1));
+
+        let preproposal = adapter.collector().take_preproposal(0);
+        assert_eq!(preproposal, PreProposal::new(frontiers, 0));
+        assert!(adapter.collector().is_empty());
+        process.stop();
+    }
+}
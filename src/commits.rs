@@ -0,0 +1,246 @@
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, io, mem::size_of, ops::Range};
+use rsnano_core::BlockHash;
+use crate::{AuditLog, CertificateResponses, DecidedChannel, Id, Identities, MemorySize, ProposalHash, Rank};
+
+// A decision with the B answers it was committed on (Lines 56/57), so that any process can check it
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct CommitRecord {
+    // Position among the commits of the process, from 0 like in the audit log
+    pub instance: u64,
+    pub rank: Rank,
+    pub value: ProposalHash,
+    pub certificate: CertificateResponses,
+    // Set on checkpoints only, see CommitLog::checkpoint
+    pub committee: Option<Committee>,
+}
+
+// Committee a commit was decided by. Checkpoints carry it, since a process that joined after a reconfiguration has
+// other keys on record than the ones that signed their certificates
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Committee {
+    pub f: usize,
+    // Ed25519 public key of each member, none if the committee does not sign its responses
+    pub members: BTreeMap<Id, [u8; 32]>,
+    // Identities::configuration_hash every response of the committee carries, if it was pinned
+    pub configuration: Option<BlockHash>,
+}
+
+impl Committee {
+    // The one of a process, as it holds it now
+    pub fn of(f: usize, identities: &Identities) -> Committee {
+        let members = identities.committee().into_iter().map(|(member, key)| (member, key.to_bytes())).collect();
+        Committee { f, members, configuration: identities.pinned() }
+    }
+}
+
+// Commits of instances this process did not witness, asked from the committee with Process::backfill. The instance
+// of a commit is not covered by its certificate, so a commit is only taken once f+1 processes sent the same value for
+// its instance
+#[derive(Debug, Default)]
+struct Backfill {
+    wanted: Range<u64>,
+    offered: HashMap<(u64, ProposalHash), HashSet<Id>>,
+    found: BTreeMap<u64, CommitRecord>,
+}
+
+// Commits of a process: each one is appended to the audit log, if any, and published to the decided watches
+// The most recent ones are kept, to send to processes that come back after a restart and announce what they missed
+#[derive(Debug)]
+pub struct CommitLog {
+    recent: VecDeque<CommitRecord>,
+    capacity: usize,
+    next: u64,
+    audit: Option<AuditLog>,
+    decided: DecidedChannel,
+    // A process taking part again after a rejoin neither answers nor votes before this instance, see Rejoin
+    silent_until: u64,
+    backfill: Backfill,
+    // f and keys of the committee of this process, see CommitLog::with_committee
+    identities: Option<(usize, Identities)>,
+    // Of the latest commit
+    committee: Option<Committee>,
+}
+
+impl CommitLog {
+    pub fn new(capacity: usize) -> CommitLog {
+        CommitLog {
+            recent: VecDeque::new(),
+            capacity,
+            next: 0,
+            audit: None,
+            decided: DecidedChannel::default(),
+            silent_until: 0,
+            backfill: Backfill::default(),
+            identities: None,
+            committee: None,
+        }
+    }
+
+    // Commits are recorded as decided by the committee the identities hold at the time, which a reconfiguration
+    // changes later on
+    pub fn with_committee(mut self, f: usize, identities: Identities) -> CommitLog {
+        self.identities = Some((f, identities));
+        self
+    }
+
+    // Continues from the last commit of the log, persisted before a restart
+    pub fn resume(&mut self, audit: AuditLog) {
+        if let Some(last) = audit.last() {
+            self.next = last.instance + 1;
+            self.decided.resume(self.next, last.rank, last.value);
+        }
+        self.audit = Some(audit);
+    }
+
+    // Starts a log without commits from a checkpoint of the committee, so that a new member does not fetch the
+    // commits before it. The audit log, if any, only gets the commits recorded after it
+    pub fn install(&mut self, checkpoint: CommitRecord) {
+        self.next = checkpoint.instance + 1;
+        self.decided.resume(self.next, checkpoint.rank, checkpoint.value);
+        self.committee = checkpoint.committee.clone();
+        self.recent.push_back(checkpoint);
+    }
+
+    // Instance of the next commit, which is also how many this process has seen
+    pub fn next_instance(&self) -> u64 {
+        self.next
+    }
+
+    pub fn silent_until(&mut self, instance: u64) {
+        self.silent_until = instance;
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.next < self.silent_until
+    }
+
+    pub fn decided(&self) -> &DecidedChannel {
+        &self.decided
+    }
+
+    // The commit is recorded and published even if the audit log cannot be written
+    pub fn record(&mut self, rank: Rank, value: ProposalHash, certificate: CertificateResponses) -> io::Result<()> {
+        let appended = match &mut self.audit {
+            Some(audit) => audit.append(value, rank, &certificate).map(|_| ()),
+            None => Ok(()),
+        };
+
+        self.recent.push_back(CommitRecord { instance: self.next, rank, value, certificate, committee: None });
+        self.committee = self.identities.as_ref().map(|(f, identities)| Committee::of(*f, identities));
+        if self.recent.len() > self.capacity {
+            self.recent.pop_front();
+        }
+        self.next += 1;
+        self.decided.publish(rank, value);
+        appended
+    }
+
+    // Instance that committed the value, if its commit is still kept
+    pub fn instance_of(&self, value: &ProposalHash) -> Option<u64> {
+        self.recent.iter().find(|commit| commit.value == *value).map(|commit| commit.instance)
+    }
+
+    // The latest commit with the committee that decided it, for a process without commits to start from (Process::join)
+    // None before the first commit recorded with a committee or installed
+    pub fn checkpoint(&self) -> Option<CommitRecord> {
+        Some(CommitRecord { committee: Some(self.committee.clone()?), ..self.recent.back()?.clone() })
+    }
+
+    // Kept commits from the instance on, in order
+    pub fn since(&self, instance: u64) -> Vec<CommitRecord> {
+        self.recent.iter().filter(|commit| commit.instance >= instance).cloned().collect()
+    }
+
+    // Kept commits of the instances, in order. Only the last Config::retained_commits are kept: the audit log has the
+    // digests of the older certificates, not the certificates themselves
+    pub fn range(&self, instances: Range<u64>) -> Vec<CommitRecord> {
+        self.recent.iter().filter(|commit| instances.contains(&commit.instance)).cloned().collect()
+    }
+
+    // Takes the commits of the instances the committee sends from now on, forgetting the ones of an earlier backfill
+    pub fn want(&mut self, instances: Range<u64>) {
+        self.backfill = Backfill { wanted: instances, ..Backfill::default() };
+    }
+
+    pub fn wants(&self, instance: u64) -> bool {
+        self.backfill.wanted.contains(&instance) && !self.backfill.found.contains_key(&instance)
+    }
+
+    // A commit of a wanted instance, whose certificate the caller checked
+    pub fn offer(&mut self, sender: Id, commit: CommitRecord, f: usize) {
+        if !self.wants(commit.instance) {
+            return;
+        }
+
+        let offered = self.backfill.offered.entry((commit.instance, commit.value)).or_default();
+        offered.insert(sender);
+        if offered.len() > f {
+            self.backfill.offered.retain(|(instance, _), _| *instance != commit.instance);
+            self.backfill.found.insert(commit.instance, commit);
+        }
+    }
+
+    // Instances of the backfill taken so far
+    pub fn backfilled(&self) -> impl Iterator<Item = u64> + '_ {
+        self.backfill.found.keys().copied()
+    }
+
+    // Ends the backfill with the commits taken, in order
+    pub fn finish_backfill(&mut self) -> Vec<CommitRecord> {
+        std::mem::take(&mut self.backfill).found.into_values().collect()
+    }
+}
+
+// The commits kept for the processes that come back, with their certificates
+impl MemorySize for CommitLog {
+    fn memory_size(&self) -> usize {
+        self.recent.iter().map(|commit| size_of::<CommitRecord>() + commit.certificate.iter().map(MemorySize::memory_size).sum::<usize>()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_from_the_audit_log() {
+        let path = std::env::temp_dir().join(format!("archipelago-commits-{}.jsonl", std::process::id()));
+
+        let mut log = CommitLog::new(2);
+        log.resume(AuditLog::open(&path).unwrap());
+        for value in 1..=3u64 {
+            log.record(value as Rank, BlockHash::from(value), CertificateResponses::new()).unwrap();
+        }
+        assert_eq!(log.since(0).iter().map(|commit| commit.instance).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(log.since(2).len(), 1);
+
+        // After a restart, numbering and the decided watches go on from the last commit
+        let mut restarted = CommitLog::new(2);
+        restarted.resume(AuditLog::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restarted.next_instance(), 3);
+        assert!(restarted.since(0).is_empty());
+        assert_eq!(restarted.decided().subscribe().borrow().map(|decided| (decided.instance, decided.value)), Some((3, BlockHash::from(3))));
+    }
+
+    #[test]
+    fn backfilled_commits_need_f_plus_one_senders() {
+        let commit = |instance: u64, value: u64| CommitRecord { instance, rank: 0, value: BlockHash::from(value), certificate: CertificateResponses::new(), committee: None };
+        let mut log = CommitLog::new(4);
+        (1..=3u64).for_each(|value| log.record(0, BlockHash::from(value), CertificateResponses::new()).unwrap());
+        assert_eq!(log.range(1..5).iter().map(|commit| commit.instance).collect::<Vec<_>>(), vec![1, 2]);
+
+        log.want(10..12);
+        log.offer(0, commit(10, 1), 1);
+        log.offer(1, commit(10, 2), 1);
+        log.offer(2, commit(12, 3), 1);
+        log.offer(3, commit(12, 3), 1);
+        assert_eq!(log.backfilled().count(), 0);
+
+        log.offer(2, commit(10, 1), 1);
+        log.offer(3, commit(11, 4), 1);
+        assert!(!log.wants(10) && log.wants(11) && !log.wants(12));
+        assert_eq!(log.finish_backfill(), vec![commit(10, 1)]);
+        assert!(!log.wants(11));
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+use crate::{BatchConfig, OverflowPolicy, Rank, StatsdConfig, VoteGossip};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    pub batch: BatchConfig,
+    // Number of threads validating certificates before messages reach the run loop, 0 validates on the run loop itself
+    pub validation_workers: usize,
+    // Approximate number of bytes the run loop may hold before evicting state, unbounded if None
+    pub memory_budget: Option<usize>,
+    // Responses that may wait for a quorum at once, past it the senders holding the most lose their lowest ranks
+    pub max_pending_responses: Option<usize>,
+    // Preproposals kept at most, besides the ones referenced by proposals not decided yet, unbounded if None
+    pub preproposal_capacity: Option<usize>,
+    // Processes announce the hashes of the preproposals they hold this often, for the ones that started late to fetch
+    // the preproposals they missed, never if None
+    pub preproposal_digest_interval: Option<Duration>,
+    // Timers are moved by up to this fraction of their period either way, drawn from the jitter source of the process
+    pub timer_jitter: f64,
+    // Broadcasts carry the hashes of their certificate responses, receivers fetch the ones they have not seen
+    pub certificates_by_reference: bool,
+    // Broadcasts carry the hash of their certificate, receivers ask the sender for the certificate unless f+1
+    // processes already answered the broadcast
+    pub lazy_certificates: bool,
+    // A and B responses name the broadcasts justifying their values by header, without the certificates of those
+    // broadcasts, which receivers never look at
+    pub aggregated_responses: bool,
+    // Broadcasts go to the coordinator of their step and rank, which rotates and relays them to the other processes,
+    // instead of to every process. A broadcast no quorum answered within this long is sent to every process directly.
+    // All-to-all if None
+    pub coordinated_broadcasts: Option<Duration>,
+    // Proposers advertise the value they are about to start rank 0 with in a pre-vote, and start with the most frequent
+    // value of the 2f+1 pre-votes they wait up to this long for instead, so that fewer ranks end in an adopt. No
+    // pre-votes if None
+    pub pre_vote_timeout: Option<Duration>,
+    // A B step whose first 2f+1 answers do not commit waits up to this long for further answers, and commits instead of
+    // adopting as soon as 2f+1 of all the answers hold only the same true pair. Processes keep up to n-f responses per
+    // step and rank for it. Adopts right away if None
+    pub fast_commit_window: Option<Duration>,
+    // Broadcasts and responses carry the configuration hash of the committee (f and the id, key and weight of each
+    // member), and ones carrying another are dropped, see Identities::pin. Off by default: members that pin and members
+    // that do not drop each other's messages, so it is turned on for the whole committee at once
+    pub pin_committee: bool,
+    // Messages each peer's writer thread may have queued before further ones are dropped, 0 sends on the caller's thread
+    pub outbound_queue_capacity: usize,
+    // Message dropped once a peer's queue is full
+    pub overflow_policy: OverflowPolicy,
+    // Queued messages are sent by priority, broadcasts and responses ahead of preproposals and votes
+    pub prioritize_traffic: bool,
+    // How final votes are relayed through the committee
+    pub vote_gossip: VoteGossip,
+    // A proposer that does not move to another step for this long is reported as stalled, never if None
+    pub stall_timeout: Option<Duration>,
+    // A proposer that goes through more ranks than this without committing raises an alert, never if None
+    pub stall_ranks: Option<Rank>,
+    // Processes broadcasting more than this many ranks below the highest R certificate a process knows are sent it,
+    // so that they can catch up, never if None
+    pub pacemaker_gap: Option<Rank>,
+    // Broadcasts more than this many ranks below the highest quorum are dropped and no longer answered, unless pending
+    // responses or the R register still refer to them, kept forever if None
+    pub broadcast_horizon: Option<Rank>,
+    // Latest commits kept to send to processes that announce themselves after a restart
+    pub retained_commits: usize,
+    // Blocks a Cementer cements per ledger transaction
+    pub cement_batch_size: usize,
+    // Submitted values a Daemon holds before further submissions are refused, or wait for room
+    pub submission_capacity: usize,
+    // A Daemon without submissions starts the next instance this long after the last one, which decides the bottom
+    // value unless another member has values to propose. Idle daemons wait for submissions or other members if None
+    pub empty_round_interval: Option<Duration>,
+    // Consensus instances an Instances runs at once, from the lowest one it has not decided
+    pub instance_window: usize,
+    // An instance an Instances has not decided this long after proposing in it is abandoned, and the value proposed
+    // again in a later instance. Waits until it decides if None
+    pub instance_deadline: Option<Duration>,
+    // Account buckets a ShardedPreconsensus decides each slot in, rounded up to a power of two
+    pub preconsensus_buckets: usize,
+    // Statsd daemon the metrics are pushed to, none are pushed if None
+    pub statsd: Option<StatsdConfig>,
+    // Test hook: A steps of the ranks below this never report unanimity, so their B steps adopt and the ranks after them
+    // (certificates of B answers, R steps with certificates) run deterministically. Must be the same on the whole committee
+    // Ignored unless FORCED_ADOPT, read it through Config::forced_adopt_ranks()
+    pub forced_adopt_ranks: Rank,
+}
+
+// Whether the forced adopt test hook is built in: in the tests of the crate, and with the `forced-adopt` feature for the
+// simulations of other crates. Without it, no setting or transcript makes a process validate as if A steps adopted
+pub const FORCED_ADOPT: bool = cfg!(any(test, feature = "forced-adopt"));
+
+impl Config {
+    // Ranks whose A steps are forced to adopt, always 0 without the test hook
+    pub fn forced_adopt_ranks(&self) -> Rank {
+        if FORCED_ADOPT {
+            self.forced_adopt_ranks
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            batch: BatchConfig::default(),
+            validation_workers: 0,
+            memory_budget: None,
+            max_pending_responses: Some(1 << 16),
+            preproposal_capacity: Some(1024),
+            preproposal_digest_interval: Some(Duration::from_secs(1)),
+            timer_jitter: 0.2,
+            certificates_by_reference: false,
+            lazy_certificates: false,
+            aggregated_responses: false,
+            coordinated_broadcasts: None,
+            fast_commit_window: None,
+            pin_committee: false,
+            pre_vote_timeout: None,
+            outbound_queue_capacity: 1024,
+            overflow_policy: OverflowPolicy::DropNewest,
+            prioritize_traffic: true,
+            vote_gossip: VoteGossip::Direct,
+            stall_timeout: Some(Duration::from_secs(10)),
+            stall_ranks: Some(3),
+            pacemaker_gap: Some(2),
+            broadcast_horizon: Some(16),
+            retained_commits: 128,
+            cement_batch_size: 1024,
+            submission_capacity: 1024,
+            empty_round_interval: None,
+            instance_window: 4,
+            instance_deadline: None,
+            preconsensus_buckets: 1,
+            statsd: None,
+            forced_adopt_ranks: 0,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::{collections::{BTreeSet, HashMap}, fmt::Debug};
+use rsnano_core::BlockHash;
+
+// Client of the driver, as opposed to a member of the committee
+pub type SubmitterId = u64;
+
+// A batch of frontiers a client wants decided in a slot
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Submission {
+    pub submitter: SubmitterId,
+    pub frontiers: Vec<BlockHash>,
+}
+
+impl Submission {
+    pub fn new(submitter: SubmitterId, frontiers: Vec<BlockHash>) -> Submission {
+        Submission { submitter, frontiers }
+    }
+}
+
+// Which value to propose for a slot several clients submitted different values for, see Instances::propose_resolved
+// Submissions come in the order they arrived. A slot without submissions proposes no frontiers
+pub trait ConflictPolicy: Send + Sync + Debug {
+    fn resolve(&self, submissions: Vec<Submission>) -> Vec<BlockHash>;
+}
+
+// The first submission wins
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstCome;
+
+impl ConflictPolicy for FirstCome {
+    fn resolve(&self, submissions: Vec<Submission>) -> Vec<BlockHash> {
+        submissions.into_iter().next().map_or_else(Vec::new, |submission| submission.frontiers)
+    }
+}
+
+// The submission of the submitter with the highest priority wins, the first one among equals. Submitters without a
+// priority come last
+#[derive(Debug, Clone, Default)]
+pub struct SubmitterPriority {
+    priorities: HashMap<SubmitterId, u32>,
+}
+
+impl SubmitterPriority {
+    pub fn new(priorities: HashMap<SubmitterId, u32>) -> SubmitterPriority {
+        SubmitterPriority { priorities }
+    }
+
+    fn priority(&self, submitter: SubmitterId) -> Option<u32> {
+        self.priorities.get(&submitter).copied()
+    }
+}
+
+impl ConflictPolicy for SubmitterPriority {
+    fn resolve(&self, submissions: Vec<Submission>) -> Vec<BlockHash> {
+        // max_by_key keeps the last of equals
+        submissions
+            .into_iter()
+            .rev()
+            .max_by_key(|submission| self.priority(submission.submitter))
+            .map_or_else(Vec::new, |submission| submission.frontiers)
+    }
+}
+
+// Frontier batches are sets, so submissions merge into their union. A submission that would take the union past the
+// capacity is left out whole, so that every merged submission is decided with all of its frontiers
+#[derive(Debug, Clone, Copy)]
+pub struct Merge {
+    capacity: usize,
+}
+
+impl Merge {
+    pub fn new(capacity: usize) -> Merge {
+        Merge { capacity }
+    }
+}
+
+impl Default for Merge {
+    fn default() -> Self {
+        Merge::new(usize::MAX)
+    }
+}
+
+impl ConflictPolicy for Merge {
+    fn resolve(&self, submissions: Vec<Submission>) -> Vec<BlockHash> {
+        let mut merged = BTreeSet::new();
+        for submission in submissions {
+            let fresh: BTreeSet<BlockHash> = submission.frontiers.into_iter().filter(|frontier| !merged.contains(frontier)).collect();
+            if merged.len() + fresh.len() <= self.capacity {
+                merged.extend(fresh);
+            }
+        }
+        merged.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submissions() -> Vec<Submission> {
+        vec![
+            Submission::new(1, vec![BlockHash::from(1), BlockHash::from(2)]),
+            Submission::new(2, vec![BlockHash::from(2), BlockHash::from(3)]),
+            Submission::new(3, vec![BlockHash::from(4), BlockHash::from(5), BlockHash::from(6)]),
+        ]
+    }
+
+    #[test]
+    fn policies_pick_or_merge_the_submissions_of_a_slot() {
+        assert_eq!(FirstCome.resolve(submissions()), vec![BlockHash::from(1), BlockHash::from(2)]);
+        assert_eq!(FirstCome.resolve(Vec::new()), vec![]);
+
+        let priority = SubmitterPriority::new(HashMap::from([(2, 5), (3, 5)]));
+        assert_eq!(priority.resolve(submissions()), vec![BlockHash::from(2), BlockHash::from(3)]);
+        assert_eq!(SubmitterPriority::default().resolve(submissions()), FirstCome.resolve(submissions()));
+
+        let all: Vec<BlockHash> = (1..=6).map(BlockHash::from).collect();
+        assert_eq!(Merge::default().resolve(submissions()), all);
+        // The third submission does not fit next to the first two
+        assert_eq!(Merge::new(4).resolve(submissions()), all[..3].to_vec());
+    }
+}
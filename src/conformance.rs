@@ -0,0 +1,198 @@
+use std::{fmt::Write, sync::Arc};
+use rsnano_core::BlockHash;
+use crate::{AValue, BValue, Broadcast, Id, Identities, Message, PreProposal, Process, Proposal, RValue, Rank, Response, State, Step, Value, ValueOrder};
+
+// Golden vectors checked by the tests, regenerate with `cargo run --example conformance > vectors/conformance.txt`
+pub const GOLDEN_VECTORS: &str = include_str!("../vectors/conformance.txt");
+
+// A message together with the outcome every implementation of the validators must reach
+// The canonical encoding lists each field and hash, so that another implementation (or a refactor) that
+// hashes or validates differently produces a different file
+#[derive(Debug, Clone)]
+pub struct Vector {
+    pub name: &'static str,
+    pub f: usize,
+    pub message: Message,
+    pub accept: bool,
+}
+
+impl Vector {
+    fn new(name: &'static str, f: usize, message: Message, accept: bool) -> Vector {
+        Vector { name, f, message, accept }
+    }
+
+    // Outcome of the stateless validation the run loop and the validation workers perform
+    pub fn check(&self) -> bool {
+        Process::validate_message(&self.message, self.f, 0, &ValueOrder::default(), &Identities::default())
+    }
+
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        let outcome = if self.accept { "accept" } else { "reject" };
+        writeln!(out, "vector {} {} f={}", self.name, outcome, self.f).unwrap();
+        encode_message(&mut out, &self.message);
+        out
+    }
+}
+
+fn hex(value: &BlockHash) -> String {
+    value.as_bytes().iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn encode_broadcast(out: &mut String, indent: &str, broadcast: &Broadcast) {
+    let flag = broadcast.flag.map_or("none".to_string(), |flag| flag.to_string());
+    writeln!(
+        out,
+        "{}broadcast sender={} step={:?} rank={} value={} flag={} hash={}",
+        indent, broadcast.sender, broadcast.step, broadcast.rank, hex(&broadcast.value), flag, hex(&broadcast.hash_value())
+    ).unwrap();
+
+    if let Some(certificate) = &broadcast.previous_step_responses {
+        for response in certificate.iter() {
+            encode_response(out, &format!("{}  ", indent), response);
+        }
+    }
+}
+
+fn encode_response(out: &mut String, indent: &str, response: &Response) {
+    writeln!(
+        out,
+        "{}response sender={} step={:?} rank={} answers={} hash={}",
+        indent, response.sender, response.step, response.rank, hex(&response.answers), hex(&response.hash_value())
+    ).unwrap();
+
+    for state in &response.state {
+        let value = match state.value {
+            Value::RValue(r_value) => format!("r rank={} value={}", r_value.rank, hex(&r_value.value)),
+            Value::AValue(a_value) => format!("a value={}", hex(&a_value.0)),
+            Value::BValue(b_value) => format!("b flag={} value={}", b_value.flag, hex(&b_value.value)),
+        };
+        writeln!(out, "{}  state {} broadcast={}", indent, value, hex(&state.broadcast.hash_value())).unwrap();
+    }
+}
+
+fn encode_message(out: &mut String, message: &Message) {
+    match message {
+        Message::Broadcast(broadcast) => encode_broadcast(out, "", broadcast),
+        Message::Response(response) => encode_response(out, "", response),
+        Message::PreProposal(preproposal) => {
+            let frontiers: Vec<String> = preproposal.frontiers().iter().map(hex).collect();
+            writeln!(out, "preproposal sender={} frontiers={} hash={}", preproposal.sender, frontiers.join(","), hex(&preproposal.hash())).unwrap();
+        }
+        Message::Proposal(proposal) => {
+            let preproposals: Vec<String> = proposal.preproposals.iter().map(hex).collect();
+            writeln!(out, "proposal sender={} preproposals={} hash={}", proposal.sender, preproposals.join(","), hex(&proposal.hash)).unwrap();
+        }
+        message => writeln!(out, "{:?}", message).unwrap(),
+    }
+}
+
+fn broadcast(step: Step, value: u64, flag: Option<bool>, rank: Rank) -> Arc<Broadcast> {
+    Arc::new(Broadcast::new(0, step, BlockHash::from(value), flag, rank, None))
+}
+
+fn r_answer(sender: Id, rank: Rank, value: u64) -> Response {
+    let state = State::new(Value::RValue(RValue::new(rank, BlockHash::from(value))), broadcast(Step::R, value, None, rank));
+    Response::new(sender, Step::R, rank, vec![state])
+}
+
+fn a_answer(sender: Id, rank: Rank, values: &[u64]) -> Response {
+    let states: Vec<State> = values
+        .iter()
+        .map(|&value| State::new(Value::AValue(AValue(BlockHash::from(value))), broadcast(Step::A, value, None, rank)))
+        .collect();
+    Response::new(sender, Step::A, rank, states)
+}
+
+fn b_answer(sender: Id, rank: Rank, pairs: &[(bool, u64)]) -> Response {
+    let states: Vec<State> = pairs
+        .iter()
+        .map(|&(flag, value)| State::new(Value::BValue(BValue::new(BlockHash::from(value), flag)), broadcast(Step::B, value, Some(flag), rank)))
+        .collect();
+    Response::new(sender, Step::B, rank, states)
+}
+
+fn with_certificate(step: Step, value: u64, flag: Option<bool>, rank: Rank, certificate: Vec<Response>) -> Message {
+    Message::Broadcast(Broadcast::new(1, step, BlockHash::from(value), flag, rank, Some(certificate.into())))
+}
+
+pub fn vectors() -> Vec<Vector> {
+    let r_answers = |count: Id, value| (0..count).map(|sender| r_answer(sender, 0, value)).collect::<Vec<_>>();
+    let a_answers = |values: &[u64]| (0..3).map(|sender| a_answer(sender, 0, values)).collect::<Vec<_>>();
+    let b_answers = |pairs: &[(bool, u64)]| (0..3).map(|sender| b_answer(sender, 0, pairs)).collect::<Vec<_>>();
+
+    let mut wrong_step = r_answers(3, 5);
+    wrong_step[2].step = Step::B;
+
+    let mut mismatched_state = r_answer(2, 0, 5);
+    mismatched_state.rank = 1;
+
+    vec![
+        Vector::new("r_rank_0_needs_no_certificate", 1, Message::Broadcast((*broadcast(Step::R, 5, None, 0)).clone()), true),
+        Vector::new("r_rank_1_without_certificate", 1, Message::Broadcast((*broadcast(Step::R, 5, None, 1)).clone()), false),
+        Vector::new("a_with_quorum_of_r_answers", 1, with_certificate(Step::A, 5, None, 0, r_answers(3, 5)), true),
+        Vector::new("a_with_larger_committee", 2, with_certificate(Step::A, 5, None, 0, r_answers(5, 5)), true),
+        Vector::new("a_below_quorum", 1, with_certificate(Step::A, 5, None, 0, r_answers(2, 5)), false),
+        Vector::new("a_below_quorum_of_larger_committee", 2, with_certificate(Step::A, 5, None, 0, r_answers(4, 5)), false),
+        Vector::new("a_with_other_value", 1, with_certificate(Step::A, 6, None, 0, r_answers(3, 5)), false),
+        Vector::new("a_with_flag", 1, with_certificate(Step::A, 5, Some(true), 0, r_answers(3, 5)), false),
+        Vector::new("a_with_wrong_step_answer", 1, with_certificate(Step::A, 5, None, 0, wrong_step), false),
+        Vector::new("a_without_r_values", 1, with_certificate(Step::A, 5, None, 0, (0..3).map(|sender| Response::new(sender, Step::R, 0, Vec::new())).collect()), false),
+        Vector::new("b_true_on_unanimous_a_answers", 1, with_certificate(Step::B, 5, Some(true), 0, a_answers(&[5])), true),
+        Vector::new("b_false_on_unanimous_a_answers", 1, with_certificate(Step::B, 5, Some(false), 0, a_answers(&[5])), false),
+        Vector::new("b_false_max_on_mixed_a_answers", 1, with_certificate(Step::B, 6, Some(false), 0, a_answers(&[5, 6])), true),
+        Vector::new("b_true_on_mixed_a_answers", 1, with_certificate(Step::B, 6, Some(true), 0, a_answers(&[5, 6])), false),
+        Vector::new("b_without_flag", 1, with_certificate(Step::B, 5, None, 0, a_answers(&[5])), false),
+        Vector::new("r_adopting_true_pair", 1, with_certificate(Step::R, 5, None, 1, b_answers(&[(true, 5), (false, 6)])), true),
+        Vector::new("r_adopting_max_false_pair", 1, with_certificate(Step::R, 6, None, 1, b_answers(&[(false, 6)])), true),
+        Vector::new("r_ignoring_true_pair", 1, with_certificate(Step::R, 6, None, 1, b_answers(&[(true, 5), (false, 6)])), false),
+        // A quorum of answers holding only ⟨true, v⟩ commits, there is nothing to adopt at the next rank
+        Vector::new("r_after_commit", 1, with_certificate(Step::R, 5, None, 1, b_answers(&[(true, 5)])), false),
+        Vector::new("response_r_answer", 1, Message::Response(r_answer(0, 0, 5)), true),
+        Vector::new("response_with_state_of_other_rank", 1, Message::Response(mismatched_state), false),
+        Vector::new("response_b_answer_with_two_pairs", 1, Message::Response(b_answer(0, 0, &[(true, 5), (false, 6)])), true),
+        Vector::new("preproposal", 1, Message::PreProposal(PreProposal::new(vec![BlockHash::from(2), BlockHash::from(1)], 0)), true),
+        Vector::new(
+            "proposal",
+            1,
+            Message::Proposal(Proposal::new((0..3).map(|id| PreProposal::new(vec![BlockHash::from(id as u64)], id).hash()).collect(), 0)),
+            true,
+        ),
+    ]
+}
+
+pub fn encode_vectors(vectors: &[Vector]) -> String {
+    vectors.iter().map(Vector::encode).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validators_reach_expected_outcomes() {
+        for vector in vectors() {
+            assert_eq!(vector.check(), vector.accept, "{}", vector.encode());
+        }
+    }
+
+    #[test]
+    fn encoding_matches_golden_vectors() {
+        let encoded = encode_vectors(&vectors());
+
+        for (line, (expected, actual)) in GOLDEN_VECTORS.lines().zip(encoded.lines()).enumerate() {
+            assert_eq!(expected, actual, "line {} of vectors/conformance.txt", line + 1);
+        }
+        assert_eq!(GOLDEN_VECTORS.lines().count(), encoded.lines().count());
+    }
+
+    #[test]
+    fn broadcasts_of_other_senders_share_the_hash_but_differ() {
+        let value = BlockHash::from(5);
+        let (own, other) = (Broadcast::new(0, Step::R, value, None, 0, None), Broadcast::new(1, Step::R, value, None, 0, None));
+
+        assert_eq!(own.hash_value(), other.hash_value());
+        assert_ne!(own, other);
+        assert_ne!(own.hash_value(), Broadcast::new(0, Step::R, value, None, 1, None).hash_value());
+    }
+}
@@ -0,0 +1,263 @@
+use std::{fmt, io::{self, BufRead, BufReader, Write}, net::{SocketAddr, TcpListener}, str::FromStr, thread, time::Duration};
+#[cfg(unix)]
+use std::{os::unix::net::UnixListener, path::PathBuf};
+use rsnano_core::BlockHash;
+use crate::{BanError, Daemon, Id, Reload, SubmitError};
+
+// Where the control socket of a daemon listens: `unix:<path>` or a TCP address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for ControlAddress {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<ControlAddress, String> {
+        #[cfg(unix)]
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok(ControlAddress::Unix(PathBuf::from(path)));
+        }
+        address.parse().map(ControlAddress::Tcp).map_err(|_| format!("invalid control address {:?}", address))
+    }
+}
+
+impl fmt::Display for ControlAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlAddress::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            ControlAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// Line protocol of the control socket, for supervision tooling that does not link this crate. Each command is a line,
+// answered by a line holding a JSON object:
+//   submit <hex value> [ms]  {"ok":true}, or {"error":"saturated"} if the queue is still full after waiting the
+//                       milliseconds given (none by default), or {"error":"draining"}
+//   status              {"instance":3,"pending":0,"rejected":0,"decided":3,"last_decided":"<hex>","draining":false,"drained":false,"stopped":false,"mismatched_peers":0,"suspected_peers":0}
+//   peers               {"peers":[{"id":1,"state":"connected","address":null,"sent_messages":12,"sent_bytes":..,"received_messages":..,"received_bytes":..,"invalid":0,"duplicates":0,"rate_limited":0,"version":"0.1.0","mismatches":[],"suspicion":0.0}]},
+//                       the version and mismatches ("version", "features", "committee") being the ones of the latest hello of the peer,
+//                       the suspicion its current score, see Suspicion
+//   add <id> <address>  {"ok":true}, the peer is known at the address and connected again if it was not
+//   disconnect <id>     {"ok":true}, messages to and from the peer are dropped until it is added again
+//   ban <id> <seconds>  {"ok":true}, the same until the ban expires, or {"error":"no evidence"} if the daemon received
+//                       no equivocation of the peer signed by its key on record
+//   reload <key>=<value>...  {"ok":true}, or {"error":"invalid settings"}, see Reload for the settings
+//   drain               {"ok":true}, further submissions are refused and status reports drained once the pending ones are decided
+//   shutdown            {"ok":true}, then the daemon stops and closes the connection
+pub fn execute(daemon: &Daemon, command: &str) -> String {
+    let mut words = command.split_whitespace();
+
+    match (words.next(), words.next()) {
+        (Some("submit"), Some(value)) => match (BlockHash::decode_hex(value), words.next().map_or(Ok(0), str::parse)) {
+            (Ok(value), Ok(wait)) => match daemon.submit_within(value, Duration::from_millis(wait)) {
+                Ok(()) => r#"{"ok":true}"#.to_string(),
+                Err(SubmitError::Saturated) => r#"{"error":"saturated"}"#.to_string(),
+                Err(SubmitError::Draining) => r#"{"error":"draining"}"#.to_string(),
+                Err(SubmitError::Stopped) => r#"{"error":"stopped"}"#.to_string(),
+            },
+            _ => r#"{"error":"invalid value"}"#.to_string(),
+        },
+        (Some("status"), None) => {
+            let status = daemon.status();
+            let last_decided = status.last_decided.map_or("null".to_string(), |value| format!("\"{}\"", value.encode_hex()));
+            format!(
+                r#"{{"instance":{},"pending":{},"rejected":{},"decided":{},"last_decided":{},"draining":{},"drained":{},"stopped":{},"mismatched_peers":{},"suspected_peers":{}}}"#,
+                status.instance, status.pending, status.rejected, status.decided, last_decided, status.draining, status.drained, status.stopped, status.mismatched_peers, status.suspected_peers
+            )
+        }
+        (Some("peers"), None) => {
+            let mut stats = daemon.peer_stats();
+            let table = daemon.peer_table();
+            let telemetry = daemon.peer_telemetry();
+            let suspicion = daemon.peer_suspicion();
+            for peer in table.keys().chain(telemetry.keys()).chain(suspicion.keys()) {
+                stats.entry(*peer).or_default();
+            }
+
+            let peers: Vec<String> = stats
+                .into_iter()
+                .map(|(peer, traffic)| {
+                    let entry = table.get(&peer).cloned().unwrap_or_default();
+                    let address = entry.address.map_or("null".to_string(), |address| format!("{:?}", address));
+                    let (version, mismatches) = match telemetry.get(&peer) {
+                        Some(telemetry) => (format!("{:?}", telemetry.version), telemetry.mismatches.iter().map(|mismatch| format!("{:?}", mismatch.name())).collect::<Vec<_>>().join(",")),
+                        None => ("null".to_string(), String::new()),
+                    };
+                    format!(
+                        r#"{{"id":{},"state":"{}","address":{},"sent_messages":{},"sent_bytes":{},"received_messages":{},"received_bytes":{},"invalid":{},"duplicates":{},"rate_limited":{},"version":{},"mismatches":[{}],"suspicion":{:.1}}}"#,
+                        peer, entry.state.name(), address, traffic.sent_messages, traffic.sent_bytes, traffic.received_messages, traffic.received_bytes, traffic.invalid, traffic.duplicates, traffic.rate_limited, version, mismatches,
+                        suspicion.get(&peer).copied().unwrap_or_default()
+                    )
+                })
+                .collect();
+            format!(r#"{{"peers":[{}]}}"#, peers.join(","))
+        }
+        (Some("add"), Some(peer)) => match (peer.parse::<Id>(), words.next()) {
+            (Ok(peer), Some(address)) => {
+                daemon.add_peer(peer, address.to_string());
+                r#"{"ok":true}"#.to_string()
+            }
+            _ => r#"{"error":"invalid peer"}"#.to_string(),
+        },
+        (Some("disconnect"), Some(peer)) => match peer.parse::<Id>() {
+            Ok(peer) => {
+                daemon.disconnect(peer);
+                r#"{"ok":true}"#.to_string()
+            }
+            Err(_) => r#"{"error":"invalid peer"}"#.to_string(),
+        },
+        (Some("ban"), Some(peer)) => match (peer.parse::<Id>(), words.next().map(str::parse::<u64>)) {
+            (Ok(peer), Some(Ok(seconds))) => match daemon.ban(peer, Duration::from_secs(seconds)) {
+                Ok(()) => r#"{"ok":true}"#.to_string(),
+                Err(BanError::NoEvidence) => r#"{"error":"no evidence"}"#.to_string(),
+                Err(BanError::InvalidEvidence) => r#"{"error":"invalid evidence"}"#.to_string(),
+            },
+            _ => r#"{"error":"invalid peer"}"#.to_string(),
+        },
+        (Some("reload"), Some(setting)) => match Reload::parse(&[setting].into_iter().chain(words).collect::<Vec<_>>().join("\n")) {
+            Ok(reload) => {
+                daemon.reload(&reload);
+                r#"{"ok":true}"#.to_string()
+            }
+            Err(_) => r#"{"error":"invalid settings"}"#.to_string(),
+        },
+        (Some("drain"), None) => {
+            daemon.drain();
+            r#"{"ok":true}"#.to_string()
+        }
+        (Some("shutdown"), None) => {
+            daemon.shutdown();
+            r#"{"ok":true}"#.to_string()
+        }
+        _ => r#"{"error":"unknown command"}"#.to_string(),
+    }
+}
+
+// Answers commands until the connection is closed or the daemon is shut down
+fn serve(reader: impl BufRead, mut writer: impl Write, daemon: &Daemon) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        writeln!(writer, "{}", execute(daemon, &line))?;
+        writer.flush()?;
+        if daemon.status().stopped {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Control socket of a daemon, each connection is served by a thread of its own
+#[derive(Debug)]
+pub struct ControlServer {
+    address: ControlAddress,
+}
+
+impl ControlServer {
+    pub fn bind(address: &ControlAddress, daemon: Daemon) -> io::Result<ControlServer> {
+        let bound = match address {
+            ControlAddress::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+                let bound = ControlAddress::Tcp(listener.local_addr()?);
+
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        let daemon = daemon.clone();
+                        thread::spawn(move || {
+                            let reader = BufReader::new(stream.try_clone()?);
+                            serve(reader, stream, &daemon)
+                        });
+                    }
+                });
+                bound
+            }
+            #[cfg(unix)]
+            ControlAddress::Unix(path) => {
+                let listener = UnixListener::bind(path)?;
+
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        let daemon = daemon.clone();
+                        thread::spawn(move || {
+                            let reader = BufReader::new(stream.try_clone()?);
+                            serve(reader, stream, &daemon)
+                        });
+                    }
+                });
+                address.clone()
+            }
+        };
+
+        Ok(ControlServer { address: bound })
+    }
+
+    // The address it listens on, with the port picked by the system if the one asked for was 0
+    pub fn address(&self) -> &ControlAddress {
+        &self.address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpStream, sync::mpsc::channel, time::{Duration, Instant}};
+    use super::*;
+    use crate::{Config, Id, VERSION};
+
+    #[test]
+    fn daemons_are_driven_over_the_control_socket() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let daemons: Vec<Daemon> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| Daemon::spawn(id as Id, 1, senders.clone(), receiver, Config::default()))
+            .collect();
+        let server = ControlServer::bind(&"127.0.0.1:0".parse().unwrap(), daemons[0].clone()).unwrap();
+        let ControlAddress::Tcp(address) = server.address() else {
+            panic!("not a TCP address");
+        };
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut writer = stream;
+        let mut command = |command: &str| {
+            writeln!(writer, "{}", command).unwrap();
+            lines.next().unwrap().unwrap()
+        };
+
+        assert_eq!(command(&format!("submit {}", BlockHash::from(1).encode_hex())), r#"{"ok":true}"#);
+        assert_eq!(command("submit xyz"), r#"{"error":"invalid value"}"#);
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !command("status").contains(r#""decided":1"#) {
+            assert!(Instant::now() < deadline, "no decision");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(command("peers").starts_with(r#"{"peers":[{"id":0,"state":"connected","address":null,"#));
+        // The other daemons said hello when their first instance started, and run the same as this one
+        assert!(command("peers").contains(&format!(r#""version":"{}","mismatches":[],"suspicion":0.0}}"#, VERSION)));
+        assert!(command("status").contains(r#""mismatched_peers":0,"suspected_peers":0"#));
+        assert_eq!(command("ban 3 60"), r#"{"error":"no evidence"}"#);
+        assert_eq!(command("disconnect 3"), r#"{"ok":true}"#);
+        assert!(command("peers").contains(r#"{"id":3,"state":"disconnected","address":null,"#));
+        assert_eq!(command("add 3 127.0.0.1:7075"), r#"{"ok":true}"#);
+        assert!(command("peers").contains(r#"{"id":3,"state":"connected","address":"127.0.0.1:7075","#));
+
+        assert_eq!(command("reload max_batch_size=4 stall_timeout_ms=none"), r#"{"ok":true}"#);
+        assert_eq!(command("reload instance_window=8"), r#"{"error":"invalid settings"}"#);
+
+        assert_eq!(command("drain"), r#"{"ok":true}"#);
+        assert_eq!(command(&format!("submit {}", BlockHash::from(2).encode_hex())), r#"{"error":"draining"}"#);
+        assert!(command("status").contains(r#""drained":true"#));
+        assert_eq!(command("restart"), r#"{"error":"unknown command"}"#);
+        assert_eq!(command("shutdown"), r#"{"ok":true}"#);
+        assert!(daemons[0].status().stopped);
+        daemons.iter().for_each(Daemon::shutdown);
+    }
+}
@@ -0,0 +1,424 @@
+use std::{collections::BTreeMap, fs::{self, File, OpenOptions}, io::{self, Write}, path::Path, sync::{mpsc::{Receiver, Sender}, Arc, Condvar, Mutex}, thread, time::{Duration, Instant}};
+use log::warn;
+use rsnano_core::BlockHash;
+use crate::{BanError, Config, Id, Instances, Message, PeerEntry, PeerStats, PreProposal, ProposalHash, Reload, Telemetry};
+
+// The proposer checks this often whether another member started the next instance
+const INSTANCE_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitError {
+    // The submission queue is full until the next instance starts
+    Saturated,
+    Draining,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DaemonStatus {
+    // Instance the daemon proposes in next
+    pub instance: u64,
+    // Submitted values not proposed yet
+    pub pending: usize,
+    // Submissions refused because the queue was full
+    pub rejected: u64,
+    pub decided: u64,
+    pub last_decided: Option<ProposalHash>,
+    pub draining: bool,
+    // Draining and nothing left to propose
+    pub drained: bool,
+    pub stopped: bool,
+    // Peers whose latest hello differs from this daemon in version, features or committee
+    pub mismatched_peers: usize,
+    // Peers whose suspicion score reached the threshold, see Suspicion
+    pub suspected_peers: usize,
+}
+
+// Decisions of a daemon, one `<instance> <hex value>` line each, kept across restarts so that the logs of the members of
+// a cluster can be compared entry by entry, see Cluster
+#[derive(Debug)]
+pub struct DecidedLog {
+    file: File,
+    decided: BTreeMap<u64, ProposalHash>,
+}
+
+impl DecidedLog {
+    // Appends to the log at the path, which must parse if it exists
+    pub fn open(path: &Path) -> io::Result<DecidedLog> {
+        let decided = match fs::read_to_string(path) {
+            Ok(text) => parse_decided_log(&text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(error),
+        };
+
+        Ok(DecidedLog { file: OpenOptions::new().create(true).append(true).open(path)?, decided })
+    }
+
+    // One past the highest instance in the log
+    pub fn next_instance(&self) -> u64 {
+        self.decided.last_key_value().map_or(0, |(instance, _)| instance + 1)
+    }
+
+    // In a single write, so that a daemon killed meanwhile leaves the line whole or out
+    fn append(&mut self, instance: u64, value: ProposalHash) -> io::Result<()> {
+        self.decided.insert(instance, value);
+        self.file.write_all(format!("{} {}\n", instance, value.encode_hex()).as_bytes())
+    }
+}
+
+pub fn parse_decided_log(text: &str) -> Result<BTreeMap<u64, ProposalHash>, String> {
+    let mut decided = BTreeMap::new();
+
+    for (line, text) in text.lines().enumerate().filter(|(_, text)| !text.trim().is_empty()) {
+        let entry = text.split_once(' ').and_then(|(instance, value)| Some((instance.parse().ok()?, BlockHash::decode_hex(value.trim()).ok()?)));
+        let Some((instance, value)) = entry else {
+            return Err(format!("line {}: invalid entry {:?}", line + 1, text));
+        };
+        if decided.insert(instance, value).is_some_and(|previous| previous != value) {
+            return Err(format!("line {}: instance {} decided twice", line + 1, instance));
+        }
+    }
+    Ok(decided)
+}
+
+// Lowest instance two of the logs decided differently
+pub fn diverging_instance(logs: &[BTreeMap<u64, ProposalHash>]) -> Option<u64> {
+    let mut first: BTreeMap<u64, ProposalHash> = BTreeMap::new();
+
+    logs.iter()
+        .flat_map(|log| log.iter())
+        .filter(|(instance, value)| *first.entry(**instance).or_insert(**value) != **value)
+        .map(|(instance, _)| *instance)
+        .min()
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: Vec<BlockHash>,
+    capacity: usize,
+    proposing: bool,
+    status: DaemonStatus,
+}
+
+// A committee member running as a service: values submitted to it are preproposed in the next instance, one instance
+// after the other. A member without submissions still proposes in an instance once another member started it, or starts
+// one itself every Config::empty_round_interval
+#[derive(Debug, Clone)]
+pub struct Daemon {
+    id: Id,
+    threshold: usize,
+    empty_round_interval: Option<Duration>,
+    instances: Instances,
+    state: Arc<(Mutex<State>, Condvar)>,
+    log: Option<Arc<Mutex<DecidedLog>>>,
+}
+
+impl Daemon {
+    pub fn spawn(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config) -> Daemon {
+        Daemon::start(id, f, senders, receiver, config, None, 0)
+    }
+
+    // A daemon that appends its decisions to the log and, after a restart, goes on from the instance after the last one
+    // in it, or from `resume` if the committee is further: the instances it missed are skipped rather than caught up on
+    pub fn spawn_logged(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config, log: DecidedLog, resume: u64) -> Daemon {
+        let first = log.next_instance().max(resume);
+        Daemon::start(id, f, senders, receiver, config, Some(log), first)
+    }
+
+    fn start(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config, log: Option<DecidedLog>, first: u64) -> Daemon {
+        let mut state = State { capacity: config.submission_capacity.max(1), ..State::default() };
+        state.status.instance = first;
+        let daemon = Daemon {
+            id,
+            threshold: 2 * f + 1,
+            empty_round_interval: config.empty_round_interval,
+            instances: Instances::new(id, f, senders, receiver, config),
+            state: Arc::new((Mutex::new(state), Condvar::new())),
+            log: log.map(|log| Arc::new(Mutex::new(log))),
+        };
+        daemon.instances.resume(first);
+
+        let proposer = daemon.clone();
+        thread::spawn(move || proposer.propose());
+        daemon
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    // Refused right away if the queue is full
+    pub fn submit(&self, value: BlockHash) -> Result<(), SubmitError> {
+        self.submit_within(value, Duration::ZERO)
+    }
+
+    // Waits up to the timeout for room in the queue, which the next instance makes by proposing the values in it
+    pub fn submit_within(&self, value: BlockHash, timeout: Duration) -> Result<(), SubmitError> {
+        let (lock, condvar) = &*self.state;
+        let deadline = Instant::now() + timeout;
+        let mut state = lock.lock().unwrap();
+
+        while state.pending.len() >= state.capacity && !state.status.stopped {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|remaining| !remaining.is_zero()) else {
+                state.status.rejected += 1;
+                return Err(SubmitError::Saturated);
+            };
+            state = condvar.wait_timeout(state, remaining).unwrap().0;
+        }
+
+        if state.status.stopped {
+            return Err(SubmitError::Stopped);
+        }
+        if state.status.draining {
+            return Err(SubmitError::Draining);
+        }
+        state.pending.push(value);
+        condvar.notify_all();
+        Ok(())
+    }
+
+    pub fn status(&self) -> DaemonStatus {
+        let state = self.state.0.lock().unwrap();
+        DaemonStatus {
+            pending: state.pending.len(),
+            drained: state.status.draining && state.pending.is_empty() && !state.proposing,
+            mismatched_peers: self.instances.peer_table().telemetry().values().filter(|peer| !peer.mismatches.is_empty()).count(),
+            suspected_peers: self.instances.peer_table().suspicion().suspected().len(),
+            ..state.status
+        }
+    }
+
+    // Traffic of the instances still running
+    pub fn peer_stats(&self) -> BTreeMap<Id, PeerStats> {
+        self.instances.peer_stats()
+    }
+
+    // Submissions already queued stay queued when the capacity goes down. The log level is the one of the whole program
+    pub fn reload(&self, reload: &Reload) {
+        if let Some(capacity) = reload.submission_capacity {
+            let (lock, condvar) = &*self.state;
+            lock.lock().unwrap().capacity = capacity.max(1);
+            condvar.notify_all();
+        }
+        if let Some(level) = reload.log_level {
+            log::set_max_level(level);
+        }
+        self.instances.reload(reload);
+    }
+
+    // Peers the operator added, disconnected or banned
+    pub fn peer_table(&self) -> BTreeMap<Id, PeerEntry> {
+        self.instances.peer_table().entries()
+    }
+
+    // Latest hello of each peer, with what it differs from this daemon in
+    pub fn peer_telemetry(&self) -> BTreeMap<Id, Telemetry> {
+        self.instances.peer_table().telemetry()
+    }
+
+    // Suspicion score of each peer with an offense
+    pub fn peer_suspicion(&self) -> BTreeMap<Id, f64> {
+        self.instances.peer_table().suspicion().scores()
+    }
+
+    pub fn add_peer(&self, peer: Id, address: String) {
+        self.instances.peer_table().add(peer, address);
+    }
+
+    pub fn disconnect(&self, peer: Id) {
+        self.instances.peer_table().disconnect(peer);
+    }
+
+    // Only for an equivocation of the peer one of the instances received
+    pub fn ban(&self, peer: Id, duration: Duration) -> Result<(), BanError> {
+        let table = self.instances.peer_table();
+        let evidence = table.evidence(peer).ok_or(BanError::NoEvidence)?;
+        table.ban(peer, duration, &evidence)
+    }
+
+    // Refuses further submissions, the pending ones are still proposed
+    pub fn drain(&self) {
+        self.state.0.lock().unwrap().status.draining = true;
+    }
+
+    pub fn shutdown(&self) {
+        let (lock, condvar) = &*self.state;
+        lock.lock().unwrap().status.stopped = true;
+        condvar.notify_all();
+        self.instances.stop();
+    }
+
+    // Waits for the daemon to be shut down
+    pub fn wait(&self) {
+        let (lock, condvar) = &*self.state;
+        let _stopped = condvar.wait_while(lock.lock().unwrap(), |state| !state.status.stopped).unwrap();
+    }
+
+    fn propose(&self) {
+        let (lock, condvar) = &*self.state;
+
+        loop {
+            let idle_since = Instant::now();
+            let (instance, values) = {
+                let mut state = lock.lock().unwrap();
+                loop {
+                    if state.status.stopped {
+                        return;
+                    }
+                    // Proposes nothing, which decides the bottom value unless another member has values
+                    let empty_round = !state.status.draining && self.empty_round_interval.is_some_and(|interval| idle_since.elapsed() >= interval);
+                    if !state.pending.is_empty() || empty_round || self.instances.process(state.status.instance).is_some() {
+                        break;
+                    }
+                    state = condvar.wait_timeout(state, INSTANCE_POLL).unwrap().0;
+                }
+
+                state.proposing = true;
+                condvar.notify_all();
+                (state.status.instance, std::mem::take(&mut state.pending))
+            };
+
+            let proposal = self.instances.propose(instance, self.threshold, PreProposal::new(values, self.id));
+            if let (Some(decided), Some(proposal)) = (&self.log, &proposal) {
+                if let Err(error) = decided.lock().unwrap().append(instance, proposal.hash) {
+                    warn!("{}: cannot append the decision of instance {} to the log: {}", self.id, instance, error);
+                }
+            }
+
+            let mut state = lock.lock().unwrap();
+            state.proposing = false;
+            state.status.instance = instance + 1;
+            if let Some(proposal) = proposal.filter(|_| !state.status.stopped) {
+                state.status.decided += 1;
+                state.status.last_decided = Some(proposal.hash);
+            }
+            condvar.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc::channel, time::Instant};
+    use crate::bottom_value;
+    use super::*;
+
+    #[test]
+    fn submitted_values_are_decided_until_drained() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let daemons: Vec<Daemon> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| Daemon::spawn(id as Id, 1, senders.clone(), receiver, Config::default()))
+            .collect();
+
+        // Only the first member gets submissions, the others follow it into each instance
+        daemons[0].submit(BlockHash::from(1)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while daemons.iter().any(|daemon| daemon.status().decided == 0) {
+            assert!(Instant::now() < deadline, "no decision");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(daemons.iter().all(|daemon| daemon.status().last_decided == daemons[0].status().last_decided));
+
+        daemons[0].drain();
+        assert_eq!(daemons[0].submit(BlockHash::from(2)), Err(SubmitError::Draining));
+        assert!(daemons[0].status().drained);
+
+        daemons.iter().for_each(Daemon::shutdown);
+        daemons[0].wait();
+        assert_eq!(daemons[0].submit(BlockHash::from(2)), Err(SubmitError::Stopped));
+    }
+
+    #[test]
+    fn idle_committees_decide_the_bottom_value() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let config = Config { empty_round_interval: Some(Duration::from_millis(50)), ..Config::default() };
+        let daemons: Vec<Daemon> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| Daemon::spawn(id as Id, 1, senders.clone(), receiver, config))
+            .collect();
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while daemons.iter().any(|daemon| daemon.status().decided == 0) {
+            assert!(Instant::now() < deadline, "no decision");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(daemons.iter().all(|daemon| daemon.status().last_decided == Some(bottom_value())));
+        daemons.iter().for_each(Daemon::shutdown);
+    }
+
+    #[test]
+    fn full_queues_refuse_submissions() {
+        let (_sender, receiver) = channel();
+        // Without peers, the first instance never decides and the queue is never emptied again
+        let daemon = Daemon::spawn(0, 0, vec![], receiver, Config { submission_capacity: 2, ..Config::default() });
+        daemon.submit(BlockHash::from(1)).unwrap();
+        while daemon.status().pending > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        daemon.submit(BlockHash::from(2)).unwrap();
+        daemon.submit(BlockHash::from(3)).unwrap();
+        assert_eq!(daemon.submit(BlockHash::from(4)), Err(SubmitError::Saturated));
+        assert_eq!(daemon.submit_within(BlockHash::from(4), Duration::from_millis(20)), Err(SubmitError::Saturated));
+
+        let status = daemon.status();
+        assert_eq!((status.pending, status.rejected), (2, 2));
+        daemon.shutdown();
+    }
+
+    fn wait_for_decisions(daemon: &Daemon, decided: u64) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while daemon.status().decided < decided {
+            assert!(Instant::now() < deadline, "no decision");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn logged_daemons_go_on_after_their_last_decision() {
+        let path = std::env::temp_dir().join(format!("archipelago-decided-{}.log", std::process::id()));
+        // A committee of one, which decides on its own
+        let alone = |resume| {
+            let (sender, receiver) = channel();
+            Daemon::spawn_logged(0, 0, vec![sender], receiver, Config::default(), DecidedLog::open(&path).unwrap(), resume)
+        };
+
+        let daemon = alone(0);
+        daemon.submit(BlockHash::from(1)).unwrap();
+        wait_for_decisions(&daemon, 1);
+        daemon.shutdown();
+
+        let daemon = alone(0);
+        assert_eq!(daemon.status().instance, 1);
+        daemon.submit(BlockHash::from(2)).unwrap();
+        wait_for_decisions(&daemon, 1);
+        daemon.shutdown();
+
+        // The committee moved on while it was down
+        let daemon = alone(5);
+        assert_eq!(daemon.status().instance, 5);
+        daemon.shutdown();
+
+        let decided = parse_decided_log(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(decided.keys().copied().collect::<Vec<_>>(), vec![0, 1]);
+        assert_ne!(decided[&0], decided[&1]);
+    }
+
+    #[test]
+    fn logs_diverge_where_two_members_decided_differently() {
+        let log = |entries: &[(u64, u64)]| {
+            let text: String = entries.iter().map(|(instance, value)| format!("{} {}\n", instance, BlockHash::from(*value).encode_hex())).collect();
+            parse_decided_log(&text)
+        };
+        let logs = vec![log(&[(0, 1), (1, 2)]).unwrap(), log(&[(1, 2), (2, 3)]).unwrap(), log(&[(0, 1), (2, 4)]).unwrap()];
+
+        assert_eq!(diverging_instance(&logs[..2]), None);
+        assert_eq!(diverging_instance(&logs), Some(2));
+        assert_eq!(log(&[(0, 1), (0, 2)]), Err("line 2: instance 0 decided twice".to_string()));
+        assert!(parse_decided_log("0\n").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::{sync::{Arc, Condvar, Mutex}, time::{Duration, Instant}};
+use crate::{ProposalHash, Rank};
+
+// A commit of the process, `instance` counts them from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decided {
+    pub instance: u64,
+    pub rank: Rank,
+    pub value: ProposalHash,
+}
+
+// Holds the latest commit of a process, like a tokio watch channel: a consumer that only cares about the latest
+// decision reads it without draining every decision before it
+#[derive(Debug, Clone, Default)]
+pub struct DecidedChannel {
+    latest: Arc<(Mutex<Option<Decided>>, Condvar)>,
+}
+
+impl DecidedChannel {
+    pub fn publish(&self, rank: Rank, value: ProposalHash) {
+        let (lock, condvar) = &*self.latest;
+        let mut latest = lock.lock().unwrap();
+        let instance = latest.map_or(1, |decided| decided.instance + 1);
+
+        *latest = Some(Decided { instance, rank, value });
+        condvar.notify_all();
+    }
+
+    // Sets the latest decision without waking the watches, e.g. the last one persisted before a restart
+    pub fn resume(&self, instance: u64, rank: Rank, value: ProposalHash) {
+        *self.latest.0.lock().unwrap() = Some(Decided { instance, rank, value });
+    }
+
+    // The watch starts out having seen the current decision, if any
+    pub fn subscribe(&self) -> DecidedWatch {
+        let seen = self.latest.0.lock().unwrap().map_or(0, |decided| decided.instance);
+        DecidedWatch { latest: Arc::clone(&self.latest), seen }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DecidedWatch {
+    latest: Arc<(Mutex<Option<Decided>>, Condvar)>,
+    seen: u64,
+}
+
+impl DecidedWatch {
+    // Latest decision, without marking it as seen
+    pub fn borrow(&self) -> Option<Decided> {
+        *self.latest.0.lock().unwrap()
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.borrow().is_some_and(|decided| decided.instance > self.seen)
+    }
+
+    pub fn borrow_and_update(&mut self) -> Option<Decided> {
+        let latest = self.borrow();
+        self.seen = latest.map_or(self.seen, |decided| decided.instance);
+        latest
+    }
+
+    // Waits until a decision this watch has not seen is published, and marks it as seen
+    // Decisions published in between are skipped, None if there is none before the timeout
+    pub fn changed(&mut self, timeout: Duration) -> Option<Decided> {
+        let deadline = Instant::now() + timeout;
+        let (lock, condvar) = &*self.latest;
+        let mut latest = lock.lock().unwrap();
+
+        loop {
+            if let Some(decided) = latest.filter(|decided| decided.instance > self.seen) {
+                self.seen = decided.instance;
+                return Some(decided);
+            }
+
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            latest = condvar.wait_timeout(latest, remaining).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use rsnano_core::BlockHash;
+    use super::*;
+
+    #[test]
+    fn watches_only_see_the_latest_decision() {
+        let channel = DecidedChannel::default();
+        let mut watch = channel.subscribe();
+        assert_eq!(watch.borrow(), None);
+        assert_eq!(watch.changed(Duration::from_millis(10)), None);
+
+        channel.publish(0, BlockHash::from(1));
+        channel.publish(2, BlockHash::from(2));
+        assert!(watch.has_changed());
+        assert_eq!(watch.changed(Duration::ZERO), Some(Decided { instance: 2, rank: 2, value: BlockHash::from(2) }));
+        assert!(!watch.has_changed());
+
+        // Subscribing later does not replay the current decision
+        assert!(!channel.subscribe().has_changed());
+
+        let publisher = channel.clone();
+        let handle = thread::spawn(move || publisher.publish(3, BlockHash::from(3)));
+        assert_eq!(watch.changed(Duration::from_secs(5)).map(|decided| decided.instance), Some(3));
+        handle.join().unwrap();
+    }
+}
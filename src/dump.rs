@@ -0,0 +1,194 @@
+use std::{collections::BTreeMap, fmt};
+use crate::{AValue, BValue, BroadcastHash, CertificateHash, Id, ProposalHash, RValue, Rank, Response, ResponseHash, Step, Value};
+
+// Deterministic snapshot of the run loop of a process, to attach to bug reports or look at when a node is wedged
+// Everything is ordered (by rank, then step, then sender), so two dumps of the same state are identical
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDump {
+    pub id: Id,
+    // Broadcasts below this rank have been evicted and are no longer answered
+    pub evicted_below: Rank,
+    pub preproposals: Vec<Id>,
+    pub proposals: Vec<Id>,
+    pub broadcasts: Vec<BroadcastDump>,
+    // Senders of the reliably checked responses per step and rank
+    pub responses: Vec<(Step, Rank, Vec<Id>)>,
+    pub pending: Vec<PendingDump>,
+    pub r: RValue,
+    pub a: BTreeMap<Rank, Vec<AValue>>,
+    pub b: BTreeMap<Rank, Vec<BValue>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastDump {
+    pub sender: Id,
+    pub step: Step,
+    pub rank: Rank,
+    pub value: ProposalHash,
+    pub flag: Option<bool>,
+    pub hash: BroadcastHash,
+}
+
+// Responses waiting for 2f+1 answers justified by the same broadcasts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingDump {
+    pub broadcasts: Vec<BroadcastHash>,
+    pub responses: Vec<(Id, Step, Rank)>,
+}
+
+fn step_order(step: Step) -> u8 {
+    step as u8
+}
+
+impl StateDump {
+    // Puts every list in its canonical order
+    pub fn sorted(mut self) -> StateDump {
+        self.preproposals.sort();
+        self.proposals.sort();
+        self.broadcasts.sort_by_key(|broadcast| (broadcast.rank, step_order(broadcast.step), broadcast.sender, broadcast.hash));
+        for (_, _, senders) in &mut self.responses {
+            senders.sort();
+        }
+        self.responses.sort_by_key(|(step, rank, _)| (*rank, step_order(*step)));
+        for pending in &mut self.pending {
+            pending.broadcasts.sort();
+            pending.responses.sort_by_key(|(sender, step, rank)| (*rank, step_order(*step), *sender));
+        }
+        self.pending.sort_by(|x, y| x.broadcasts.cmp(&y.broadcasts));
+        for values in self.a.values_mut() {
+            values.sort();
+        }
+        for values in self.b.values_mut() {
+            values.sort_by_key(|pair| (pair.flag, pair.value));
+        }
+        self
+    }
+}
+
+impl fmt::Display for StateDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "process {} evicted_below={}", self.id, self.evicted_below)?;
+        writeln!(f, "preproposals {:?}", self.preproposals)?;
+        writeln!(f, "proposals {:?}", self.proposals)?;
+
+        for broadcast in &self.broadcasts {
+            let flag = broadcast.flag.map_or("none".to_string(), |flag| flag.to_string());
+            writeln!(
+                f,
+                "broadcast {:?} rank={} sender={} value={} flag={} hash={}",
+                broadcast.step, broadcast.rank, broadcast.sender, broadcast.value.encode_hex(), flag, broadcast.hash.encode_hex()
+            )?;
+        }
+        for (step, rank, senders) in &self.responses {
+            writeln!(f, "responses {:?} rank={} senders={:?}", step, rank, senders)?;
+        }
+        for pending in &self.pending {
+            let broadcasts: Vec<String> = pending.broadcasts.iter().map(BroadcastHash::encode_hex).collect();
+            let responses: Vec<String> = pending.responses.iter().map(|(sender, step, rank)| format!("{}:{:?}:{}", sender, step, rank)).collect();
+            writeln!(f, "pending broadcasts={} responses={}", broadcasts.join(","), responses.join(","))?;
+        }
+
+        writeln!(f, "register R rank={} value={}", self.r.rank, self.r.value.encode_hex())?;
+        for (rank, values) in &self.a {
+            let values: Vec<String> = values.iter().map(|value| value.0.encode_hex()).collect();
+            writeln!(f, "register A rank={} values={}", rank, values.join(","))?;
+        }
+        for (rank, pairs) in &self.b {
+            let pairs: Vec<String> = pairs.iter().map(|pair| format!("{}:{}", pair.flag, pair.value.encode_hex())).collect();
+            writeln!(f, "register B rank={} pairs={}", rank, pairs.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+// Responses a process holds for a step and rank of an instance, to diff against the view of another process when one
+// of them waits for a quorum the other has. Responses are ordered by sender, their states by justifying broadcast
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseGroup {
+    pub instance: u64,
+    pub step: Step,
+    pub rank: Rank,
+    pub responses: Vec<ResponseSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseSummary {
+    pub sender: Id,
+    pub hash: ResponseHash,
+    // Broadcast the response answers
+    pub answers: BroadcastHash,
+    pub states: Vec<StateSummary>,
+}
+
+// A value of a response with the broadcast justifying it, and how that broadcast carried its certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateSummary {
+    pub value: Value,
+    pub broadcast: BroadcastHash,
+    pub broadcast_sender: Id,
+    // Senders of the responses of the certificate, if it came with them
+    pub certificate: Vec<Id>,
+    pub certificate_refs: usize,
+    pub certificate_hash: Option<CertificateHash>,
+}
+
+impl ResponseSummary {
+    pub fn new(response: &Response) -> ResponseSummary {
+        let mut states: Vec<StateSummary> = response
+            .state
+            .iter()
+            .map(|state| {
+                let broadcast = &state.broadcast;
+                let mut certificate: Vec<Id> = broadcast.previous_step_responses.iter().flat_map(|responses| responses.iter().map(|response| response.sender)).collect();
+                certificate.sort();
+                StateSummary {
+                    value: state.value.clone(),
+                    broadcast: broadcast.hash_value(),
+                    broadcast_sender: broadcast.sender,
+                    certificate,
+                    certificate_refs: broadcast.certificate_refs.as_ref().map_or(0, Vec::len),
+                    certificate_hash: broadcast.certificate_hash,
+                }
+            })
+            .collect();
+        states.sort_by_key(|state| (state.broadcast, state.broadcast_sender));
+
+        ResponseSummary { sender: response.sender, hash: response.hash_value(), answers: response.answers, states }
+    }
+}
+
+impl ResponseGroup {
+    pub fn new(instance: u64, step: Step, rank: Rank, responses: &[Response]) -> ResponseGroup {
+        let mut responses: Vec<ResponseSummary> = responses.iter().map(ResponseSummary::new).collect();
+        responses.sort_by_key(|response| (response.sender, response.hash));
+        ResponseGroup { instance, step, rank, responses }
+    }
+
+    // Canonical order of the groups: by instance, then rank, then step
+    pub fn sort(groups: &mut [ResponseGroup]) {
+        groups.sort_by_key(|group| (group.instance, group.rank, step_order(group.step)));
+    }
+}
+
+impl fmt::Display for ResponseGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instance {} {:?} rank={} responses={}", self.instance, self.step, self.rank, self.responses.len())?;
+        for response in &self.responses {
+            writeln!(f, "  response sender={} hash={} answers={}", response.sender, response.hash.encode_hex(), response.answers.encode_hex())?;
+            for state in &response.states {
+                let value = match &state.value {
+                    Value::RValue(r_value) => format!("r rank={} value={}", r_value.rank, r_value.value.encode_hex()),
+                    Value::AValue(a_value) => format!("a value={}", a_value.0.encode_hex()),
+                    Value::BValue(b_value) => format!("b flag={} value={}", b_value.flag, b_value.value.encode_hex()),
+                };
+                let hash = state.certificate_hash.map_or("none".to_string(), |hash| hash.encode_hex());
+                writeln!(
+                    f,
+                    "    state {} broadcast={} sender={} certificate={:?} refs={} certificate_hash={}",
+                    value, state.broadcast.encode_hex(), state.broadcast_sender, state.certificate, state.certificate_refs, hash
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use rand::Rng;
+use crate::{Id, Time};
+
+// Extra delay added on top of the base latency of the simulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delay {
+    Fixed(Time),
+    Uniform(Time, Time),
+    Exponential { mean: Time },
+}
+
+impl Default for Delay {
+    fn default() -> Self {
+        Delay::Fixed(0)
+    }
+}
+
+impl Delay {
+    pub fn sample(&self, rng: &mut impl Rng) -> Time {
+        match *self {
+            Delay::Fixed(delay) => delay,
+            Delay::Uniform(min, max) => rng.gen_range(min..=max),
+            Delay::Exponential { mean } => {
+                let uniform: f64 = rng.gen();
+                (-(mean as f64) * (1.0 - uniform).ln()) as Time
+            }
+        }
+    }
+}
+
+// Faults applied independently to every message sent over a link
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkFaults {
+    pub drop_rate: f64,
+    pub duplicate_rate: f64,
+    // Probability that a message is held back long enough to be overtaken by later ones
+    pub reorder_rate: f64,
+    pub delay: Delay,
+}
+
+// Extra delay on every link of a slow node, added to the faults of the link
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PeerDelay {
+    // Messages sent to the node
+    pub inbound: Delay,
+    // Messages sent by the node
+    pub outbound: Delay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    // Messages are lost
+    Drop,
+    // Messages are delivered once the fault ends, like a partition that heals
+    Hold,
+}
+
+// Applies to the messages sent from `from` to `to` (any node if None) between `start` and `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptedFault {
+    pub from: Option<Id>,
+    pub to: Option<Id>,
+    pub start: Time,
+    pub end: Time,
+    pub action: FaultAction,
+}
+
+impl ScriptedFault {
+    pub fn applies(&self, from: Id, to: Id, time: Time) -> bool {
+        self.from.is_none_or(|node| node == from)
+            && self.to.is_none_or(|node| node == to)
+            && (self.start..self.end).contains(&time)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    default: LinkFaults,
+    links: HashMap<(Id, Id), LinkFaults>,
+    peers: HashMap<Id, PeerDelay>,
+    script: Vec<ScriptedFault>,
+}
+
+impl Faults {
+    pub fn new(default: LinkFaults) -> Faults {
+        Faults { default, ..Faults::default() }
+    }
+
+    pub fn with_link(mut self, from: Id, to: Id, faults: LinkFaults) -> Faults {
+        self.links.insert((from, to), faults);
+        self
+    }
+
+    // The node stays correct, it is only slow to send and receive
+    pub fn with_slow_peer(mut self, node: Id, delay: PeerDelay) -> Faults {
+        self.peers.insert(node, delay);
+        self
+    }
+
+    pub fn with_scripted(mut self, fault: ScriptedFault) -> Faults {
+        self.script.push(fault);
+        self
+    }
+
+    pub fn link(&self, from: Id, to: Id) -> &LinkFaults {
+        self.links.get(&(from, to)).unwrap_or(&self.default)
+    }
+
+    // Delay added by the slow peers at both ends of the link
+    pub fn peer_delay(&self, from: Id, to: Id, rng: &mut impl Rng) -> Time {
+        let outbound = self.peers.get(&from).map_or(0, |delay| delay.outbound.sample(rng));
+        let inbound = self.peers.get(&to).map_or(0, |delay| delay.inbound.sample(rng));
+        outbound + inbound
+    }
+
+    // The first scripted fault covering a message sent at `time`
+    pub fn scripted(&self, from: Id, to: Id, time: Time) -> Option<&ScriptedFault> {
+        self.script.iter().find(|fault| fault.applies(from, to, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use super::*;
+
+    #[test]
+    fn link_overrides_default() {
+        let lossy = LinkFaults { drop_rate: 1.0, ..LinkFaults::default() };
+        let faults = Faults::default().with_link(2, 0, lossy);
+
+        assert_eq!(faults.link(2, 0), &lossy);
+        assert_eq!(faults.link(0, 2), &LinkFaults::default());
+    }
+
+    #[test]
+    fn scripted_fault_window() {
+        let faults = Faults::default().with_scripted(ScriptedFault { from: Some(2), to: None, start: 3, end: 5, action: FaultAction::Drop });
+
+        assert!(faults.scripted(2, 0, 2).is_none());
+        assert!(faults.scripted(2, 1, 3).is_some());
+        assert!(faults.scripted(1, 2, 4).is_none());
+        assert!(faults.scripted(2, 2, 5).is_none());
+    }
+
+    #[test]
+    fn slow_peer_delays_both_directions() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let faults = Faults::default()
+            .with_slow_peer(1, PeerDelay { inbound: Delay::Fixed(5), outbound: Delay::Fixed(7) })
+            .with_slow_peer(2, PeerDelay { inbound: Delay::Fixed(100), ..PeerDelay::default() });
+
+        assert_eq!(faults.peer_delay(1, 0, &mut rng), 7);
+        assert_eq!(faults.peer_delay(0, 1, &mut rng), 5);
+        assert_eq!(faults.peer_delay(1, 2, &mut rng), 107);
+        assert_eq!(faults.peer_delay(0, 3, &mut rng), 0);
+    }
+
+    #[test]
+    fn delays_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            assert!((10..=20).contains(&Delay::Uniform(10, 20).sample(&mut rng)));
+        }
+        assert_eq!(Delay::Fixed(7).sample(&mut rng), 7);
+    }
+}
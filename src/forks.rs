@@ -0,0 +1,175 @@
+use std::{fmt::Debug, io};
+use rsnano_core::BlockHash;
+
+// What fork resolution needs of a local ledger, in which each account has a chain of blocks ending at its head
+pub trait LedgerView {
+    type Account: Clone + Debug + PartialEq;
+
+    // Account of a block the ledger knows of, applied or not. None if it does not know the block
+    fn account(&self, block: &BlockHash) -> Option<Self::Account>;
+
+    // Last block applied on the chain of the account, None if it has none
+    fn head(&self, account: &Self::Account) -> Option<BlockHash>;
+
+    // Whether the ancestor is the block itself or one of the blocks before it on the chain of its account
+    fn is_ancestor(&self, ancestor: &BlockHash, block: &BlockHash) -> bool;
+
+    // Whether the block has no successor on the chain of its account. Ledgers that keep successors can answer without
+    // looking up the account
+    fn is_head(&self, block: &BlockHash) -> bool {
+        self.account(block).and_then(|account| self.head(&account)) == Some(*block)
+    }
+
+    // Rolls back the blocks of the account that are not on the chain of the frontier
+    fn roll_back(&mut self, account: &Self::Account, frontier: &BlockHash) -> io::Result<()>;
+
+    // Applies the blocks of the account up to the frontier
+    fn advance(&mut self, account: &Self::Account, frontier: &BlockHash) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForkAction<A> {
+    // The local head is on a losing fork: it is rolled back, then the account advances to the frontier
+    RollBack { account: A, head: BlockHash, frontier: BlockHash },
+    // The local head is behind the frontier on the same chain
+    Advance { account: A, frontier: BlockHash },
+    // The ledger does not know the frontier, which has to be fetched before it can be applied
+    Missing(BlockHash),
+}
+
+// Actions bringing the ledger to the decided frontiers, in their order. Accounts already at or past their frontier on
+// the same chain need none: the blocks after it were not decided yet, but they do not conflict
+pub fn fork_diff<V: LedgerView>(view: &V, frontiers: &[BlockHash]) -> Vec<ForkAction<V::Account>> {
+    let mut actions = Vec::new();
+
+    for frontier in frontiers {
+        let Some(account) = view.account(frontier) else {
+            actions.push(ForkAction::Missing(*frontier));
+            continue;
+        };
+
+        match view.head(&account) {
+            Some(head) if view.is_ancestor(frontier, &head) => (),
+            Some(head) if !view.is_ancestor(&head, frontier) => actions.push(ForkAction::RollBack { account, head, frontier: *frontier }),
+            _ => actions.push(ForkAction::Advance { account, frontier: *frontier }),
+        }
+    }
+    actions
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ForkResolution {
+    // Heads of losing forks that were rolled back
+    pub rolled_back: Vec<BlockHash>,
+    // Frontiers applied, the ones of the rolled back accounts included
+    pub advanced: Vec<BlockHash>,
+    // Frontiers the ledger does not know yet, left for the caller to fetch and resolve again
+    pub missing: Vec<BlockHash>,
+}
+
+// Drives the ledger to the frontiers of a decided proposal, e.g. the ones of `Process::proposal_frontiers`
+// Stops at the first ledger error, resolving the same frontiers again picks up from where it stopped
+pub fn resolve_forks<V: LedgerView>(view: &mut V, frontiers: &[BlockHash]) -> io::Result<ForkResolution> {
+    let mut resolution = ForkResolution::default();
+
+    for action in fork_diff(view, frontiers) {
+        match action {
+            ForkAction::RollBack { account, head, frontier } => {
+                view.roll_back(&account, &frontier)?;
+                resolution.rolled_back.push(head);
+                view.advance(&account, &frontier)?;
+                resolution.advanced.push(frontier);
+            }
+            ForkAction::Advance { account, frontier } => {
+                view.advance(&account, &frontier)?;
+                resolution.advanced.push(frontier);
+            }
+            ForkAction::Missing(frontier) => resolution.missing.push(frontier),
+        }
+    }
+    Ok(resolution)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use super::*;
+
+    // Chains of applied blocks per account, and the chain each known block ends
+    #[derive(Debug, Default)]
+    struct TestLedger {
+        chains: BTreeMap<u8, Vec<BlockHash>>,
+        known: HashMap<BlockHash, (u8, Vec<BlockHash>)>,
+    }
+
+    impl TestLedger {
+        fn know(&mut self, account: u8, chain: &[u64]) {
+            let chain: Vec<BlockHash> = chain.iter().copied().map(BlockHash::from).collect();
+            self.known.insert(*chain.last().unwrap(), (account, chain));
+        }
+
+        fn apply(&mut self, account: u8, chain: &[u64]) {
+            self.know(account, chain);
+            self.chains.insert(account, chain.iter().copied().map(BlockHash::from).collect());
+        }
+    }
+
+    impl LedgerView for TestLedger {
+        type Account = u8;
+
+        fn account(&self, block: &BlockHash) -> Option<u8> {
+            self.known.get(block).map(|(account, _)| *account)
+        }
+
+        fn head(&self, account: &u8) -> Option<BlockHash> {
+            self.chains.get(account).and_then(|chain| chain.last()).copied()
+        }
+
+        fn is_ancestor(&self, ancestor: &BlockHash, block: &BlockHash) -> bool {
+            self.known.get(block).is_some_and(|(_, chain)| chain.contains(ancestor))
+        }
+
+        fn roll_back(&mut self, account: &u8, frontier: &BlockHash) -> io::Result<()> {
+            let winning = &self.known[frontier].1;
+            let chain = self.chains.entry(*account).or_default();
+            let common = chain.iter().zip(winning).take_while(|(local, winning)| local == winning).count();
+            chain.truncate(common);
+            Ok(())
+        }
+
+        fn advance(&mut self, account: &u8, frontier: &BlockHash) -> io::Result<()> {
+            self.chains.insert(*account, self.known[frontier].1.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn losing_forks_are_rolled_back_to_the_decided_frontiers() {
+        let mut ledger = TestLedger::default();
+        // Account 0 is on a losing fork of its second block, 1 is behind, 2 is ahead and 3 is up to date
+        ledger.apply(0, &[1, 2, 3]);
+        ledger.know(0, &[1, 4]);
+        ledger.apply(1, &[10]);
+        ledger.know(1, &[10, 11]);
+        ledger.apply(2, &[20, 21]);
+        ledger.know(2, &[20]);
+        ledger.apply(3, &[30]);
+
+        let frontiers: Vec<BlockHash> = [4, 11, 20, 30, 40].map(BlockHash::from).to_vec();
+        assert_eq!(fork_diff(&ledger, &frontiers), vec![
+            ForkAction::RollBack { account: 0, head: BlockHash::from(3), frontier: BlockHash::from(4) },
+            ForkAction::Advance { account: 1, frontier: BlockHash::from(11) },
+            ForkAction::Missing(BlockHash::from(40)),
+        ]);
+
+        let resolution = resolve_forks(&mut ledger, &frontiers).unwrap();
+        assert_eq!(resolution, ForkResolution {
+            rolled_back: vec![BlockHash::from(3)],
+            advanced: vec![BlockHash::from(4), BlockHash::from(11)],
+            missing: vec![BlockHash::from(40)],
+        });
+        assert_eq!(ledger.chains[&0], vec![BlockHash::from(1), BlockHash::from(4)]);
+        assert_eq!(ledger.head(&2), Some(BlockHash::from(21)));
+        assert_eq!(fork_diff(&ledger, &frontiers), vec![ForkAction::Missing(BlockHash::from(40))]);
+    }
+}
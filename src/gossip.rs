@@ -0,0 +1,224 @@
+use std::{collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque}, hash::{Hash, Hasher}, sync::{atomic::{AtomicU64, Ordering}, mpsc::{channel, Receiver, Sender}, Arc, Mutex}, thread};
+use crate::{decode_message, encode_message, Id, Message, WireError};
+
+// Topics of the consensus messages everyone receives
+pub const BROADCAST_TOPIC: &str = "archipelago/broadcast";
+pub const RESPONSE_TOPIC: &str = "archipelago/response";
+
+// Publications remembered to publish each broadcast and response once, however many peers the process sends it to
+const RECENT_PUBLICATIONS: usize = 1024;
+
+// Identity of a node on the overlay, e.g. the bytes of a libp2p PeerId
+pub type OverlayPeer = Vec<u8>;
+
+// The topic of the messages meant for a single member: requests, answers and acknowledged batches
+pub fn member_topic(member: Id) -> String {
+    format!("archipelago/member/{}", member)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Publication {
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipError {
+    // Not a topic the process subscribes to
+    Topic(String),
+    // The source of the message is not a member of the committee
+    UnknownPeer,
+    Decode(WireError),
+    // The message claims another sender than the member that published it, or is not of the kind of its topic
+    Impersonation,
+    // The process is gone
+    Closed,
+}
+
+// Runs a process on a publish-subscribe overlay such as libp2p gossipsub: broadcasts and responses are published on
+// BROADCAST_TOPIC and RESPONSE_TOPIC, everything else on the member topic of its receiver, in the wire encoding. The
+// overlay itself stays with the caller, whose event loop publishes the publications of `new` and hands every message
+// of the topics to `deliver`, with the overlay peer that signed it. Like TcpTransport, the bridge hands out the
+// senders and receiver Process::new_with_config takes
+#[derive(Debug, Clone)]
+pub struct GossipBridge {
+    id: Id,
+    members: Arc<HashMap<OverlayPeer, Id>>,
+    inbound: Sender<Message>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl GossipBridge {
+    // `members` has the overlay peer of every member by id
+    pub fn new(id: Id, members: Vec<OverlayPeer>) -> (GossipBridge, Vec<Sender<Message>>, Receiver<Message>, Receiver<Publication>) {
+        let (inbound, receiver) = channel();
+        let (publisher, publications) = channel();
+        let recent = Arc::new(Mutex::new((VecDeque::new(), HashSet::new())));
+
+        let senders = (0..members.len() as Id)
+            .map(|member| {
+                // Messages to itself do not go through the overlay
+                if member == id {
+                    return inbound.clone();
+                }
+                let (sender, outbound) = channel();
+                let (publisher, recent) = (publisher.clone(), Arc::clone(&recent));
+                thread::spawn(move || {
+                    for message in outbound {
+                        for publication in GossipBridge::publications(member, message) {
+                            if GossipBridge::fresh(&recent, &publication) && publisher.send(publication).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+                sender
+            })
+            .collect();
+
+        let members = members.into_iter().enumerate().map(|(member, peer)| (peer, member as Id)).collect();
+        (GossipBridge { id, members: Arc::new(members), inbound, rejected: Arc::default() }, senders, receiver, publications)
+    }
+
+    // Topics to subscribe to
+    pub fn topics(&self) -> Vec<String> {
+        vec![BROADCAST_TOPIC.to_string(), RESPONSE_TOPIC.to_string(), member_topic(self.id)]
+    }
+
+    pub fn member(&self, peer: &[u8]) -> Option<Id> {
+        self.members.get(peer).copied()
+    }
+
+    // A message of a subscribed topic, published by `source`
+    pub fn deliver(&self, source: &[u8], topic: &str, data: &[u8]) -> Result<(), GossipError> {
+        let delivered = self.check(source, topic, data).and_then(|message| self.inbound.send(message).map_err(|_| GossipError::Closed));
+        if matches!(delivered, Err(GossipError::UnknownPeer | GossipError::Decode(_) | GossipError::Impersonation)) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        delivered
+    }
+
+    // Messages delivered by the overlay that were not the members' own
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn check(&self, source: &[u8], topic: &str, data: &[u8]) -> Result<Message, GossipError> {
+        if !self.topics().iter().any(|subscribed| subscribed == topic) {
+            return Err(GossipError::Topic(topic.to_string()));
+        }
+        let member = self.member(source).ok_or(GossipError::UnknownPeer)?;
+        let message = decode_message(data).map_err(GossipError::Decode)?;
+
+        let kind = match &message {
+            Message::Broadcast(_) => BROADCAST_TOPIC,
+            Message::Response(_) => RESPONSE_TOPIC,
+            _ => "",
+        };
+        let own_topic = topic == kind || (kind.is_empty() && topic == member_topic(self.id));
+        if !own_topic || message.sender() != Some(member) {
+            return Err(GossipError::Impersonation);
+        }
+        Ok(message)
+    }
+
+    // Batches are split so that their broadcasts and responses go out on their topics, sequenced batches are
+    // acknowledged by their receiver and stay whole
+    fn publications(member: Id, message: Message) -> Vec<Publication> {
+        let publication = |message: &Message| {
+            let topic = match message {
+                Message::Broadcast(_) => BROADCAST_TOPIC.to_string(),
+                Message::Response(_) => RESPONSE_TOPIC.to_string(),
+                _ => member_topic(member),
+            };
+            Publication { topic, data: encode_message(message) }
+        };
+        match message {
+            Message::Batch(messages) => messages.iter().map(publication).collect(),
+            message => vec![publication(&message)],
+        }
+    }
+
+    // A broadcast or response sent to every peer is published the first time only
+    fn fresh(recent: &Mutex<(VecDeque<u64>, HashSet<u64>)>, publication: &Publication) -> bool {
+        if publication.topic != BROADCAST_TOPIC && publication.topic != RESPONSE_TOPIC {
+            return true;
+        }
+        let mut state = DefaultHasher::new();
+        publication.hash(&mut state);
+        let digest = state.finish();
+
+        let mut recent = recent.lock().unwrap();
+        let (order, seen) = &mut *recent;
+        if !seen.insert(digest) {
+            return false;
+        }
+        order.push_back(digest);
+        if order.len() > RECENT_PUBLICATIONS {
+            if let Some(oldest) = order.pop_front() {
+                seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Broadcast, Config, PreProposal, Process, Step};
+
+    #[test]
+    fn processes_reach_consensus_over_an_overlay() {
+        let f = 1;
+        let peers: Vec<OverlayPeer> = (0..3 * f + 1).map(|member| vec![member as u8; 4]).collect();
+        let config = Config { aggregated_responses: true, ..Config::default() };
+
+        let mut bridges = Vec::new();
+        let mut handles = Vec::new();
+        let (published, overlay) = channel();
+        for id in 0..peers.len() as Id {
+            let (bridge, senders, receiver, publications) = GossipBridge::new(id, peers.clone());
+            let (published, source) = (published.clone(), peers[id as usize].clone());
+            thread::spawn(move || publications.into_iter().for_each(|publication| published.send((source.clone(), publication)).unwrap()));
+
+            let mut process = Process::new_with_config(id, f, senders, receiver, false, config);
+            let preproposal = PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id);
+            handles.push(thread::spawn(move || process.propose(2 * f + 1, preproposal, 0)));
+            bridges.push(bridge);
+        }
+
+        // Gossipsub delivers a publication to the subscribers of its topic other than its source
+        thread::spawn(move || {
+            for (source, publication) in overlay {
+                for bridge in bridges.iter().filter(|bridge| bridge.member(&source) != Some(bridge.id) && bridge.topics().contains(&publication.topic)) {
+                    let _ = bridge.deliver(&source, &publication.topic, &publication.data);
+                }
+            }
+        });
+
+        let decisions: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert!(decisions.windows(2).all(|pair| pair[0].hash == pair[1].hash), "{decisions:?}");
+    }
+
+    #[test]
+    fn messages_must_come_from_their_sender_on_their_topic() {
+        let peers: Vec<OverlayPeer> = (0..4u8).map(|member| vec![member]).collect();
+        let (bridge, _senders, receiver, _publications) = GossipBridge::new(0, peers);
+        let ack = encode_message(&Message::Ack(2, 1));
+        let broadcast = encode_message(&Message::Broadcast(Broadcast::new(2, Step::R, BlockHash::from(1), None, 0, None)));
+
+        assert_eq!(bridge.deliver(&[2], BROADCAST_TOPIC, &broadcast), Ok(()));
+        assert_eq!(bridge.deliver(&[2], &member_topic(0), &ack), Ok(()));
+        assert_eq!(receiver.try_iter().count(), 2);
+
+        assert_eq!(bridge.deliver(&[3], BROADCAST_TOPIC, &broadcast), Err(GossipError::Impersonation));
+        assert_eq!(bridge.deliver(&[2], RESPONSE_TOPIC, &broadcast), Err(GossipError::Impersonation));
+        assert_eq!(bridge.deliver(&[2], BROADCAST_TOPIC, &ack), Err(GossipError::Impersonation));
+        assert_eq!(bridge.deliver(&[9], BROADCAST_TOPIC, &broadcast), Err(GossipError::UnknownPeer));
+        assert_eq!(bridge.deliver(&[2], &member_topic(1), &ack), Err(GossipError::Topic(member_topic(1))));
+        assert_eq!(bridge.deliver(&[2], BROADCAST_TOPIC, &[0]), Err(GossipError::Decode(WireError::Truncated)));
+        assert_eq!(bridge.rejected(), 5);
+    }
+}
@@ -0,0 +1,180 @@
+use std::ops::BitOr;
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{Config, Id, Identities};
+
+// Software version of this process, as told to its peers
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Settings that change what goes on the wire, which every member of the committee has to run with alike
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Features(u32);
+
+impl Features {
+    pub const CERTIFICATES_BY_REFERENCE: Features = Features(1);
+    pub const LAZY_CERTIFICATES: Features = Features(1 << 1);
+    pub const AGGREGATED_RESPONSES: Features = Features(1 << 2);
+    pub const COORDINATED_BROADCASTS: Features = Features(1 << 3);
+    pub const ACKNOWLEDGED_BATCHES: Features = Features(1 << 4);
+    pub const PRE_VOTES: Features = Features(1 << 5);
+    pub const PINNED_COMMITTEE: Features = Features(1 << 6);
+
+    const NAMES: [(Features, &'static str); 7] = [
+        (Features::CERTIFICATES_BY_REFERENCE, "certificates_by_reference"),
+        (Features::LAZY_CERTIFICATES, "lazy_certificates"),
+        (Features::AGGREGATED_RESPONSES, "aggregated_responses"),
+        (Features::COORDINATED_BROADCASTS, "coordinated_broadcasts"),
+        (Features::ACKNOWLEDGED_BATCHES, "acknowledged_batches"),
+        (Features::PRE_VOTES, "pre_votes"),
+        (Features::PINNED_COMMITTEE, "pinned_committee"),
+    ];
+
+    pub fn of(config: &Config) -> Features {
+        [
+            (config.certificates_by_reference, Features::CERTIFICATES_BY_REFERENCE),
+            (config.lazy_certificates, Features::LAZY_CERTIFICATES),
+            (config.aggregated_responses, Features::AGGREGATED_RESPONSES),
+            (config.coordinated_broadcasts.is_some(), Features::COORDINATED_BROADCASTS),
+            (config.batch.ack_timeout.is_some(), Features::ACKNOWLEDGED_BATCHES),
+            (config.pre_vote_timeout.is_some(), Features::PRE_VOTES),
+            (config.pin_committee, Features::PINNED_COMMITTEE),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(Features::default(), |features, (_, feature)| features | feature)
+    }
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Features::NAMES.iter().filter(|(feature, _)| self.contains(*feature)).map(|(_, name)| *name).collect()
+    }
+
+    // As sent on the wire. Bits of features this release does not know are kept, so that they show as a mismatch
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u32) -> Features {
+        Features(bits)
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mismatch {
+    Version,
+    Features,
+    // Another f, committee size or member key
+    Committee,
+}
+
+impl Mismatch {
+    pub const ALL: [Mismatch; 3] = [Mismatch::Version, Mismatch::Features, Mismatch::Committee];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mismatch::Version => "version",
+            Mismatch::Features => "features",
+            Mismatch::Committee => "committee",
+        }
+    }
+}
+
+// What a process tells its peers about itself when it starts or its identity changes, so that operators see the peers
+// that run another version, other features or another committee than their own
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hello {
+    pub sender: Id,
+    pub version: String,
+    pub features: Features,
+    // See Hello::committee_hash
+    pub committee: BlockHash,
+    // The receiver sends its own hello back, answers do not ask for one
+    pub ask: bool,
+}
+
+impl Hello {
+    pub fn new(sender: Id, features: Features, committee: BlockHash) -> Hello {
+        Hello { sender, version: VERSION.to_string(), features, committee, ask: true }
+    }
+
+    pub fn answer(self) -> Hello {
+        Hello { ask: false, ..self }
+    }
+
+    // Covers f, the size of the committee and the key of each member, if any
+    pub fn committee_hash(f: usize, size: usize, identities: &Identities) -> BlockHash {
+        let mut committee: Vec<_> = identities.committee().into_iter().collect();
+        committee.sort_by_key(|(member, _)| *member);
+
+        committee
+            .iter()
+            .fold(Blake2HashBuilder::new().update(b"committee ").update((f as u64).to_le_bytes()).update((size as u64).to_le_bytes()), |hasher, (member, key)| {
+                hasher.update(member.to_le_bytes()).update(key.as_bytes())
+            })
+            .build()
+    }
+
+    // What the hello of the peer differs from the one of this process in
+    pub fn mismatches(&self, own: &Hello) -> Vec<Mismatch> {
+        [
+            (self.version != own.version, Mismatch::Version),
+            (self.features != own.features, Mismatch::Features),
+            (self.committee != own.committee, Mismatch::Committee),
+        ]
+        .into_iter()
+        .filter(|(differs, _)| *differs)
+        .map(|(_, mismatch)| mismatch)
+        .collect()
+    }
+}
+
+// Latest hello of a peer, with what it differs from this process in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Telemetry {
+    pub version: String,
+    pub features: Features,
+    pub committee: BlockHash,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Telemetry {
+    pub fn new(hello: &Hello, own: &Hello) -> Telemetry {
+        Telemetry { version: hello.version.clone(), features: hello.features, committee: hello.committee, mismatches: hello.mismatches(own) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use super::*;
+
+    #[test]
+    fn peers_differing_in_version_features_or_committee_are_mismatched() {
+        let config = Config { lazy_certificates: true, ..Config::default() };
+        let features = Features::of(&config);
+        assert_eq!(features.names(), vec!["lazy_certificates"]);
+
+        let identities = Identities::default();
+        let own = Hello::new(0, features, Hello::committee_hash(1, 4, &identities));
+        assert_eq!(Hello { sender: 1, ..own.clone() }.mismatches(&own), vec![]);
+
+        let outdated = Hello { sender: 1, version: "0.0.1".to_string(), features: Features::default(), ..own.clone() };
+        assert_eq!(outdated.mismatches(&own), vec![Mismatch::Version, Mismatch::Features]);
+
+        let key = |id: Id| SigningKey::from_bytes(&[id as u8 + 1; 32]);
+        identities.set(key(1), (0..4).map(|id| (id, key(id).verifying_key())).collect());
+        let keyed = Hello::new(1, features, Hello::committee_hash(1, 4, &identities));
+        assert_eq!(keyed.mismatches(&own), vec![Mismatch::Committee]);
+        assert_ne!(Hello::committee_hash(1, 5, &identities), keyed.committee);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{collections::BTreeSet, sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver}, Arc, Mutex, RwLock}, thread, time::Duration};
+use rsnano_core::BlockHash;
+use crate::{LedgerView, PreProposal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadCheck {
+    // The frontier is the head of its account chain
+    Head,
+    // The account already has blocks after the frontier locally
+    Superseded,
+    // The ledger does not know the frontier, has not applied it yet or is on another fork of the account
+    Behind,
+}
+
+pub fn check_head<V: LedgerView>(view: &V, frontier: &BlockHash) -> HeadCheck {
+    if view.is_head(frontier) {
+        return HeadCheck::Head;
+    }
+
+    let superseded = view.account(frontier).and_then(|account| view.head(&account)).is_some_and(|head| view.is_ancestor(frontier, &head));
+    if superseded { HeadCheck::Superseded } else { HeadCheck::Behind }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HeadValidation {
+    pub accepted: Vec<BlockHash>,
+    pub rejected: Vec<BlockHash>,
+    // Held by the validator until a recheck accepts or rejects them
+    pub deferred: Vec<BlockHash>,
+}
+
+impl HeadValidation {
+    pub fn is_empty(&self) -> bool {
+        self.accepted.is_empty() && self.rejected.is_empty() && self.deferred.is_empty()
+    }
+}
+
+// Checks proposed frontiers against the local ledger before they are counted: a frontier must be the head of its
+// account chain. Superseded frontiers are rejected, and the ones the ledger has not caught up with are deferred and
+// checked again by `recheck`, e.g. from the observer of the blocks the ledger applies
+#[derive(Debug, Default)]
+pub struct HeadValidator {
+    deferred: BTreeSet<BlockHash>,
+}
+
+impl HeadValidator {
+    pub fn deferred(&self) -> &BTreeSet<BlockHash> {
+        &self.deferred
+    }
+
+    pub fn validate<V: LedgerView>(&mut self, view: &V, frontiers: &[BlockHash]) -> HeadValidation {
+        let mut validation = HeadValidation::default();
+
+        for frontier in frontiers {
+            match check_head(view, frontier) {
+                HeadCheck::Head => validation.accepted.push(*frontier),
+                HeadCheck::Superseded => validation.rejected.push(*frontier),
+                HeadCheck::Behind => {
+                    self.deferred.insert(*frontier);
+                    validation.deferred.push(*frontier);
+                }
+            }
+        }
+        validation
+    }
+
+    pub fn validate_preproposal<V: LedgerView>(&mut self, view: &V, preproposal: &PreProposal) -> HeadValidation {
+        self.validate(view, &preproposal.frontiers().iter().copied().collect::<Vec<_>>())
+    }
+
+    // Checks the deferred frontiers again. Only the ones accepted or rejected are reported, the others stay deferred
+    pub fn recheck<V: LedgerView>(&mut self, view: &V) -> HeadValidation {
+        let deferred: Vec<BlockHash> = std::mem::take(&mut self.deferred).into_iter().collect();
+        let mut validation = self.validate(view, &deferred);
+        validation.deferred.clear();
+        validation
+    }
+
+    // Drops deferred frontiers that are no longer needed, e.g. the ones of a decided proposal
+    pub fn forget(&mut self, frontiers: &[BlockHash]) {
+        frontiers.iter().for_each(|frontier| {
+            self.deferred.remove(frontier);
+        });
+    }
+}
+
+// Rechecks the deferred frontiers of the validator every interval on a thread of its own, sending the frontiers that
+// were accepted or rejected once the ledger caught up. The thread stops with the flag or once the receiver is dropped
+pub fn spawn_rechecks<V: LedgerView + Send + Sync + 'static>(
+    validator: Arc<Mutex<HeadValidator>>,
+    view: Arc<RwLock<V>>,
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+) -> Receiver<HeadValidation> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            thread::sleep(interval);
+            let validation = validator.lock().unwrap().recheck(&*view.read().unwrap());
+            if !validation.is_empty() && sender.send(validation).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    // Applied chain of a single account, and the chain each known block ends
+    #[derive(Debug, Default)]
+    struct TestLedger {
+        applied: Vec<BlockHash>,
+        known: HashMap<BlockHash, Vec<BlockHash>>,
+    }
+
+    impl TestLedger {
+        fn know(&mut self, chain: &[u64]) {
+            let chain: Vec<BlockHash> = chain.iter().copied().map(BlockHash::from).collect();
+            self.known.insert(*chain.last().unwrap(), chain);
+        }
+
+        fn apply(&mut self, chain: &[u64]) {
+            self.know(chain);
+            self.applied = chain.iter().copied().map(BlockHash::from).collect();
+        }
+    }
+
+    impl LedgerView for TestLedger {
+        type Account = ();
+
+        fn account(&self, block: &BlockHash) -> Option<()> {
+            self.known.contains_key(block).then_some(())
+        }
+
+        fn head(&self, _: &()) -> Option<BlockHash> {
+            self.applied.last().copied()
+        }
+
+        fn is_ancestor(&self, ancestor: &BlockHash, block: &BlockHash) -> bool {
+            self.known.get(block).is_some_and(|chain| chain.contains(ancestor))
+        }
+
+        fn roll_back(&mut self, _: &(), _: &BlockHash) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn advance(&mut self, _: &(), frontier: &BlockHash) -> std::io::Result<()> {
+            self.applied = self.known[frontier].clone();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frontiers_must_be_heads_of_their_chain() {
+        let mut ledger = TestLedger::default();
+        ledger.apply(&[1, 2]);
+        ledger.know(&[1]);
+        ledger.know(&[1, 2, 3]);
+
+        let mut validator = HeadValidator::default();
+        let frontiers: Vec<BlockHash> = [1, 2, 3, 4].map(BlockHash::from).to_vec();
+        assert_eq!(validator.validate(&ledger, &frontiers), HeadValidation {
+            accepted: vec![BlockHash::from(2)],
+            rejected: vec![BlockHash::from(1)],
+            deferred: vec![BlockHash::from(3), BlockHash::from(4)],
+        });
+
+        // Once the ledger applies 3 it is accepted, and 4 is still unknown
+        let validator = Arc::new(Mutex::new(validator));
+        let ledger = Arc::new(RwLock::new(ledger));
+        let stop = Arc::new(AtomicBool::new(false));
+        let rechecks = spawn_rechecks(validator.clone(), ledger.clone(), Duration::from_millis(1), stop.clone());
+        ledger.write().unwrap().advance(&(), &BlockHash::from(3)).unwrap();
+
+        assert_eq!(rechecks.recv().unwrap(), HeadValidation { accepted: vec![BlockHash::from(3)], ..HeadValidation::default() });
+        assert_eq!(validator.lock().unwrap().deferred(), &BTreeSet::from([BlockHash::from(4)]));
+        stop.store(true, Ordering::SeqCst);
+
+        validator.lock().unwrap().forget(&[BlockHash::from(4)]);
+        assert!(validator.lock().unwrap().deferred().is_empty());
+    }
+}
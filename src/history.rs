@@ -0,0 +1,314 @@
+use std::{collections::{BTreeMap, HashSet}, fmt, sync::Arc};
+use rsnano_core::BlockHash;
+use crate::{BTally, BValue, Broadcast, Decision, Id, ProposalHash, Rank, Response, State, Step, Value};
+
+// Recorded history of a run, one line per event, so that the logs of every node can be concatenated and checked offline:
+//   history nodes=<n> f=<f>
+//   propose <node> <slot> <value>
+//   commit <node> <slot> <rank> <value> <sender>=<flag>:<value>,<flag>:<value> ...
+// A commit lists the B answers it was decided on, the certificate that every other node would accept for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub sender: Id,
+    pub pairs: Vec<BValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryEntry {
+    Propose { node: Id, slot: usize, value: ProposalHash },
+    Commit { node: Id, slot: usize, rank: Rank, value: ProposalHash, certificate: Vec<Answer> },
+}
+
+impl HistoryEntry {
+    // Keeps the B answers of a certificate of responses, as received by the committing node
+    pub fn commit(node: Id, slot: usize, rank: Rank, value: ProposalHash, responses: &[Response]) -> HistoryEntry {
+        let certificate = responses
+            .iter()
+            .map(|response| Answer {
+                sender: response.sender,
+                pairs: response.state.iter().filter_map(|state| match state.value {
+                    Value::BValue(b_value) => Some(b_value),
+                    _ => None,
+                }).collect(),
+            })
+            .collect();
+
+        HistoryEntry::Commit { node, slot, rank, value, certificate }
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryEntry::Propose { node, slot, value } => write!(f, "propose {} {} {}", node, slot, value.encode_hex()),
+            HistoryEntry::Commit { node, slot, rank, value, certificate } => {
+                write!(f, "commit {} {} {} {}", node, slot, rank, value.encode_hex())?;
+
+                for answer in certificate {
+                    let pairs: Vec<String> = answer.pairs.iter().map(|pair| format!("{}:{}", pair.flag, pair.value.encode_hex())).collect();
+                    write!(f, " {}={}", answer.sender, pairs.join(","))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct History {
+    pub nodes: usize,
+    pub f: usize,
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "history nodes={} f={}", self.nodes, self.f)?;
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(field: Option<&str>, name: &str) -> Result<T, String> {
+    let field = field.ok_or_else(|| format!("missing {}", name))?;
+    field.parse().map_err(|_| format!("invalid {} {:?}", name, field))
+}
+
+fn parse_value(field: Option<&str>) -> Result<ProposalHash, String> {
+    let field = field.ok_or("missing value")?;
+    BlockHash::decode_hex(field).map_err(|_| format!("invalid value {:?}", field))
+}
+
+fn parse_answer(field: &str) -> Result<Answer, String> {
+    let (sender, pairs) = field.split_once('=').ok_or_else(|| format!("invalid answer {:?}", field))?;
+    let pairs = pairs
+        .split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (flag, value) = pair.split_once(':').ok_or_else(|| format!("invalid pair {:?}", pair))?;
+            Ok(BValue::new(parse_value(Some(value))?, parse_number(Some(flag), "flag")?))
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(Answer { sender: parse_number(Some(sender), "sender")?, pairs })
+}
+
+impl History {
+    pub fn new(nodes: usize, f: usize) -> History {
+        History { nodes, f, entries: Vec::new() }
+    }
+
+    // Accepts the concatenated logs of several nodes, whose headers must describe the same committee
+    pub fn parse(text: &str) -> Result<History, String> {
+        let mut history: Option<History> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let mut fields = line.split_whitespace();
+
+            match fields.next() {
+                None => continue,
+                Some("history") => {
+                    let mut header = || -> Result<(usize, usize), String> {
+                        let nodes = fields.next().and_then(|field| field.strip_prefix("nodes="));
+                        let f = fields.next().and_then(|field| field.strip_prefix("f="));
+                        Ok((parse_number(nodes, "nodes")?, parse_number(f, "f")?))
+                    };
+                    let (nodes, f) = header().map_err(error)?;
+
+                    match &history {
+                        Some(history) if (history.nodes, history.f) != (nodes, f) => return Err(error("logs of different committees".to_string())),
+                        Some(_) => (),
+                        None => history = Some(History::new(nodes, f)),
+                    }
+                }
+                Some(kind) => {
+                    let history = history.as_mut().ok_or_else(|| error("missing history header".to_string()))?;
+                    let mut entry = || -> Result<HistoryEntry, String> {
+                        let node = parse_number(fields.next(), "node")?;
+                        let slot = parse_number(fields.next(), "slot")?;
+
+                        match kind {
+                            "propose" => Ok(HistoryEntry::Propose { node, slot, value: parse_value(fields.next())? }),
+                            "commit" => {
+                                let rank = parse_number(fields.next(), "rank")?;
+                                let value = parse_value(fields.next())?;
+                                let certificate = fields.by_ref().map(parse_answer).collect::<Result<_, String>>()?;
+                                Ok(HistoryEntry::Commit { node, slot, rank, value, certificate })
+                            }
+                            kind => Err(format!("unknown entry {:?}", kind)),
+                        }
+                    };
+                    history.entries.push(entry().map_err(error)?);
+                }
+            }
+        }
+
+        history.ok_or_else(|| "missing history header".to_string())
+    }
+
+    // Every violation of agreement, validity or certificate correctness, in slot order
+    pub fn check(&self) -> Vec<Violation> {
+        let mut proposed: BTreeMap<usize, HashSet<ProposalHash>> = BTreeMap::new();
+        let mut committed: BTreeMap<usize, Vec<(Id, ProposalHash)>> = BTreeMap::new();
+        let mut violations = Vec::new();
+
+        for entry in &self.entries {
+            match entry {
+                HistoryEntry::Propose { slot, value, .. } => {
+                    proposed.entry(*slot).or_default().insert(*value);
+                }
+                HistoryEntry::Commit { node, slot, rank, value, certificate } => {
+                    committed.entry(*slot).or_default().push((*node, *value));
+
+                    if let Err(reason) = self.check_certificate(*rank, *value, certificate) {
+                        violations.push(Violation::Certificate { node: *node, slot: *slot, reason });
+                    }
+                }
+            }
+        }
+
+        for (slot, commits) in committed {
+            let (first_node, first) = commits[0];
+            if let Some(&(node, value)) = commits.iter().find(|(_, value)| *value != first) {
+                violations.push(Violation::Agreement { slot, first: (first_node, first), other: (node, value) });
+            }
+
+            // Slots without recorded proposals come from logs that only keep commits
+            if let Some(values) = proposed.get(&slot) {
+                for &(node, value) in commits.iter().filter(|(_, value)| !values.contains(value)) {
+                    violations.push(Violation::Validity { node, slot, value });
+                }
+            }
+        }
+
+        violations.sort_by_key(Violation::slot);
+        violations
+    }
+
+    // Line 56/57: 2f+1 B answers of distinct members of the committee, which only hold ⟨true, value⟩
+    fn check_certificate(&self, rank: Rank, value: ProposalHash, certificate: &[Answer]) -> Result<(), String> {
+        let threshold = 2 * self.f + 1;
+        let mut senders = HashSet::new();
+        let mut tally = BTally::new(threshold);
+
+        for answer in certificate {
+            if !(0..self.nodes as Id).contains(&answer.sender) {
+                return Err(format!("answer from {}, outside of the committee", answer.sender));
+            }
+            if !senders.insert(answer.sender) {
+                return Err(format!("two answers from {}", answer.sender));
+            }
+
+            let states: Vec<State> = answer.pairs
+                .iter()
+                .map(|pair| State::new(Value::BValue(*pair), Arc::new(Broadcast::new(answer.sender, Step::B, pair.value, Some(pair.flag), rank, None))))
+                .collect();
+            tally.add(&Response::new(answer.sender, Step::B, rank, states));
+        }
+
+        if senders.len() < threshold {
+            return Err(format!("{} answers, {} needed", senders.len(), threshold));
+        }
+
+        match tally.result() {
+            Decision::Commit(committed) if committed == value => Ok(()),
+            Decision::Commit(other) => Err(format!("answers commit {}", other.encode_hex())),
+            Decision::Adopt(adopted) => Err(format!("answers only adopt {}", adopted.encode_hex())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    // Two nodes committed different values in the same slot
+    Agreement { slot: usize, first: (Id, ProposalHash), other: (Id, ProposalHash) },
+    // A node committed a value nobody proposed
+    Validity { node: Id, slot: usize, value: ProposalHash },
+    Certificate { node: Id, slot: usize, reason: String },
+}
+
+impl Violation {
+    pub fn slot(&self) -> usize {
+        match self {
+            Violation::Agreement { slot, .. } | Violation::Validity { slot, .. } | Violation::Certificate { slot, .. } => *slot,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Agreement { slot, first, other } => write!(
+                f,
+                "slot {}: {} committed {} but {} committed {}",
+                slot, first.0, first.1.encode_hex(), other.0, other.1.encode_hex()
+            ),
+            Violation::Validity { node, slot, value } => write!(f, "slot {}: {} committed {}, which was never proposed", slot, node, value.encode_hex()),
+            Violation::Certificate { node, slot, reason } => write!(f, "slot {}: invalid commit certificate of {}: {}", slot, node, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(node: Id, value: u64, certificate: &[(Id, bool, u64)]) -> HistoryEntry {
+        let certificate = certificate
+            .iter()
+            .map(|&(sender, flag, value)| Answer { sender, pairs: vec![BValue::new(BlockHash::from(value), flag)] })
+            .collect();
+        HistoryEntry::Commit { node, slot: 0, rank: 0, value: BlockHash::from(value), certificate }
+    }
+
+    fn history(entries: Vec<HistoryEntry>) -> History {
+        History { nodes: 4, f: 1, entries }
+    }
+
+    #[test]
+    fn history_round_trips_through_text() {
+        let history = history(vec![
+            HistoryEntry::Propose { node: 0, slot: 0, value: BlockHash::from(1) },
+            commit(0, 1, &[(0, true, 1), (1, true, 1), (3, true, 1)]),
+        ]);
+
+        assert_eq!(History::parse(&history.to_string()), Ok(history.clone()));
+        // The logs of several nodes are concatenated, headers included
+        assert_eq!(History::parse(&format!("{}{}", history, history)).unwrap().entries.len(), 4);
+        assert!(History::parse("history nodes=7 f=2\nhistory nodes=4 f=1").is_err());
+        assert!(History::parse("propose 0 0 00").is_err());
+    }
+
+    #[test]
+    fn valid_history_has_no_violations() {
+        let quorum = [(0, true, 1), (1, true, 1), (2, true, 1)];
+        let history = history(vec![
+            HistoryEntry::Propose { node: 0, slot: 0, value: BlockHash::from(1) },
+            commit(0, 1, &quorum),
+            commit(1, 1, &quorum),
+        ]);
+
+        assert_eq!(history.check(), Vec::new());
+    }
+
+    #[test]
+    fn violations_are_reported() {
+        let history = history(vec![
+            HistoryEntry::Propose { node: 0, slot: 0, value: BlockHash::from(1) },
+            commit(0, 1, &[(0, true, 1), (1, true, 1), (2, true, 1)]),
+            commit(1, 2, &[(0, true, 2), (1, true, 2), (2, true, 2)]),
+            commit(2, 1, &[(0, true, 1), (0, true, 1), (2, true, 1)]),
+            commit(3, 1, &[(0, true, 1), (1, false, 1), (2, true, 1)]),
+        ]);
+
+        let violations = history.check();
+        assert_eq!(violations.len(), 4, "{violations:?}");
+        assert!(violations.contains(&Violation::Agreement { slot: 0, first: (0, BlockHash::from(1)), other: (1, BlockHash::from(2)) }));
+        assert!(violations.contains(&Violation::Validity { node: 1, slot: 0, value: BlockHash::from(2) }));
+        assert!(violations.iter().any(|violation| matches!(violation, Violation::Certificate { node: 2, .. })));
+        assert!(violations.iter().any(|violation| matches!(violation, Violation::Certificate { node: 3, .. })));
+    }
+}
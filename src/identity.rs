@@ -0,0 +1,308 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::{Arc, RwLock}};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{Broadcast, Id, Response};
+
+// Digests of the certificates whose signatures were verified, forgotten oldest first
+const MAX_VERIFIED_CERTIFICATES: usize = 1 << 12;
+
+#[derive(Debug, Default)]
+struct Keys {
+    committee: HashMap<Id, VerifyingKey>,
+    own: Option<SigningKey>,
+    // Verified against this committee, and dropped with it
+    verified: HashSet<BlockHash>,
+    verified_order: VecDeque<BlockHash>,
+}
+
+// Key file of a member, e.g. for archipelago-daemon: the hex of its Ed25519 secret key on the first line, then the hex
+// of the public key of every member of the committee, one per line by id
+pub fn key_file(key: &SigningKey, committee: &[VerifyingKey]) -> String {
+    let hex = |bytes: [u8; 32]| BlockHash::from_bytes(bytes).encode_hex();
+    std::iter::once(hex(key.to_bytes())).chain(committee.iter().map(|member| hex(member.to_bytes()))).map(|line| line + "\n").collect()
+}
+
+pub fn parse_key_file(text: &str) -> Result<(SigningKey, HashMap<Id, VerifyingKey>), String> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    let bytes = |line: &str| BlockHash::decode_hex(line).map(|hash| *hash.as_bytes()).map_err(|_| format!("invalid key {:?}", line));
+    let key = SigningKey::from_bytes(&bytes(lines.next().ok_or("no secret key")?)?);
+    let committee = lines
+        .enumerate()
+        .map(|(member, line)| VerifyingKey::from_bytes(&bytes(line)?).map(|key| (member as Id, key)).map_err(|_| format!("invalid public key of {}", member)))
+        .collect::<Result<HashMap<Id, VerifyingKey>, String>>()?;
+    if committee.is_empty() {
+        return Err("no public keys".to_string());
+    }
+    Ok((key, committee))
+}
+
+// Keys binding each response to its sender (Line 77: check signatures of those messages)
+// Quorums are counted by sender, and without keys a sender is whatever id the response claims, so a single process
+// could answer under 2f+1 ids. With them, a response only counts, directly or inside a certificate, if it is signed by
+// the key of the member it claims to come from
+// Clones share the keys, so the ones set on a process apply to its run loop and validation workers
+#[derive(Debug, Clone, Default)]
+pub struct Identities {
+    keys: Arc<RwLock<Keys>>,
+    // See Identities::pin
+    pinned: Arc<RwLock<Option<BlockHash>>>,
+}
+
+impl Identities {
+    // The key this process signs its responses with, and the keys of the whole committee, this process included
+    pub fn set(&self, key: SigningKey, committee: HashMap<Id, VerifyingKey>) {
+        *self.keys.write().unwrap() = Keys { committee, own: Some(key), ..Keys::default() };
+    }
+
+    // Only verifies, e.g. the responses of a transcript
+    pub fn verifying(committee: HashMap<Id, VerifyingKey>) -> Identities {
+        let identities = Identities::default();
+        identities.keys.write().unwrap().committee = committee;
+        identities
+    }
+
+    pub fn committee(&self) -> HashMap<Id, VerifyingKey> {
+        self.keys.read().unwrap().committee.clone()
+    }
+
+    // Key the committee has on record for the member
+    pub fn key(&self, member: Id) -> Option<VerifyingKey> {
+        self.keys.read().unwrap().committee.get(&member).copied()
+    }
+
+    // Responses of the member only count under the new key from now on, see Rejoin. Certificates verified under the
+    // old one are verified again
+    pub fn rekey(&self, member: Id, key: VerifyingKey) {
+        let mut keys = self.keys.write().unwrap();
+        keys.committee.insert(member, key);
+        keys.verified.clear();
+        keys.verified_order.clear();
+    }
+
+    // Whether this process has a key of its own to sign with
+    pub fn signing(&self) -> bool {
+        self.keys.read().unwrap().own.is_some()
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.keys.read().unwrap().committee.is_empty()
+    }
+
+    // Covers f and the whole membership: the id, key and weight of every member, where a member without a key is
+    // listed as such and every member weighs 1. A member rejoining under a fresh key changes it, see Core::readmit
+    pub fn configuration_hash(f: usize, members: impl IntoIterator<Item = (Id, Option<[u8; 32]>)>) -> BlockHash {
+        let mut members: Vec<(Id, Option<[u8; 32]>)> = members.into_iter().collect();
+        members.sort_unstable_by_key(|(member, _)| *member);
+
+        members
+            .iter()
+            .fold(Blake2HashBuilder::new().update(b"configuration ").update((f as u64).to_le_bytes()), |hasher, (member, key)| {
+                let hasher = hasher.update(member.to_le_bytes());
+                let hasher = match key {
+                    Some(key) => hasher.update([1]).update(key),
+                    None => hasher.update([0]),
+                };
+                hasher.update(1u64.to_le_bytes())
+            })
+            .build()
+    }
+
+    // Configuration hash of the committee these identities hold, or of `size` members without keys while they hold none
+    pub fn configuration(&self, f: usize, size: usize) -> BlockHash {
+        let committee = self.committee();
+        if committee.is_empty() {
+            return Identities::configuration_hash(f, (0..size as Id).map(|member| (member, None)));
+        }
+        Identities::configuration_hash(f, committee.into_iter().map(|(member, key)| (member, Some(key.to_bytes()))))
+    }
+
+    // Responses signed from now on carry the configuration hash, and broadcasts and responses are only accepted if
+    // they carry the same one, so that messages of another committee are not replayed into this one (e.g. during and
+    // after a reconfiguration). Signatures cover the hash
+    pub fn pin(&self, configuration: BlockHash) {
+        *self.pinned.write().unwrap() = Some(configuration);
+    }
+
+    pub fn pinned(&self) -> Option<BlockHash> {
+        *self.pinned.read().unwrap()
+    }
+
+    // Whether a message carrying the configuration hash belongs to the committee, always true while none is pinned
+    pub fn accepts(&self, configuration: Option<BlockHash>) -> bool {
+        self.pinned().is_none_or(|pinned| configuration == Some(pinned))
+    }
+
+    // Responses are left unsigned while no key is set
+    pub fn sign(&self, mut response: Response) -> Response {
+        response.committee = self.pinned();
+        if let Some(key) = &self.keys.read().unwrap().own {
+            response.signature = Some(key.sign(response.digest().as_bytes()).to_bytes());
+        }
+        response
+    }
+
+    // Broadcasts are left unsigned while no key is set. Only an equivocation needs their signatures, see Equivocation
+    pub fn sign_broadcast(&self, mut broadcast: Broadcast) -> Broadcast {
+        broadcast.signature = self.sign_digest(&broadcast.signing_digest());
+        broadcast
+    }
+
+    // Signature of this process over a digest other than the one of a response, None while no key is set
+    pub fn sign_digest(&self, digest: &BlockHash) -> Option<[u8; 64]> {
+        self.keys.read().unwrap().own.as_ref().map(|key| key.sign(digest.as_bytes()).to_bytes())
+    }
+
+    // Covers the signed digest and the signature of every response, in the order of the certificate, so that the same
+    // 2f+1 responses attached to several broadcasts have the same digest
+    pub fn certificate_digest(responses: &[Response]) -> BlockHash {
+        responses
+            .iter()
+            .fold(Blake2HashBuilder::new().update(b"certificate "), |hasher, response| {
+                let hasher = hasher.update(response.digest().as_bytes());
+                match &response.signature {
+                    Some(signature) => hasher.update([1]).update(signature),
+                    None => hasher.update([0]),
+                }
+            })
+            .build()
+    }
+
+    // Whether the signatures of the certificate with this digest were already verified, so that a certificate
+    // attached to several broadcasts is only verified once
+    pub fn verified_certificate(&self, digest: &BlockHash) -> bool {
+        self.keys.read().unwrap().verified.contains(digest)
+    }
+
+    pub fn remember_certificate(&self, digest: BlockHash) {
+        let mut keys = self.keys.write().unwrap();
+        if !keys.verified.insert(digest) {
+            return;
+        }
+        keys.verified_order.push_back(digest);
+        if keys.verified_order.len() > MAX_VERIFIED_CERTIFICATES {
+            let oldest = keys.verified_order.pop_front().unwrap();
+            keys.verified.remove(&oldest);
+        }
+    }
+
+    // Signed by the key on record for its sender, never true without one
+    pub fn verify_broadcast(&self, broadcast: &Broadcast) -> bool {
+        match (self.key(broadcast.sender), &broadcast.signature) {
+            (Some(key), Some(signature)) => key.verify(broadcast.signing_digest().as_bytes(), &Signature::from_bytes(signature)).is_ok(),
+            _ => false,
+        }
+    }
+
+    // Always true while no committee is set, unless the response belongs to another configuration
+    pub fn verify(&self, response: &Response) -> bool {
+        if !self.accepts(response.committee) {
+            return false;
+        }
+        let keys = self.keys.read().unwrap();
+        if keys.committee.is_empty() {
+            return true;
+        }
+
+        match (keys.committee.get(&response.sender), &response.signature) {
+            (Some(key), Some(signature)) => key.verify(response.digest().as_bytes(), &Signature::from_bytes(signature)).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Step;
+
+    fn key(id: Id) -> SigningKey {
+        SigningKey::from_bytes(&[id as u8 + 1; 32])
+    }
+
+    fn identities(id: Id) -> Identities {
+        let identities = Identities::default();
+        identities.set(key(id), (0..4).map(|id| (id, key(id).verifying_key())).collect());
+        identities
+    }
+
+    #[test]
+    fn responses_only_verify_under_the_key_of_their_sender() {
+        let response = Response::new(1, Step::R, 0, vec![]);
+        assert!(Identities::default().verify(&response));
+        assert!(!identities(0).verify(&response));
+
+        let signed = identities(1).sign(response.clone());
+        assert!(identities(0).verify(&signed));
+        assert_eq!(signed.hash_value(), response.hash_value());
+
+        // Signed by 1 but claiming to come from 2, or changed after signing
+        assert!(!identities(0).verify(&Response { sender: 2, ..signed.clone() }));
+        assert!(!identities(0).verify(&Response { rank: 1, ..signed.clone() }));
+        assert!(!identities(0).verify(&signed.clone().answering(1.into())));
+        assert!(!identities(0).verify(&identities(5).sign(Response::new(5, Step::R, 0, vec![]))));
+    }
+
+    #[test]
+    fn certificates_are_remembered_by_their_signatures() {
+        let certificate: Vec<Response> = (1..4).map(|id| identities(id).sign(Response::new(id, Step::R, 0, vec![]))).collect();
+        let digest = Identities::certificate_digest(&certificate);
+
+        let identities = identities(0);
+        assert!(!identities.verified_certificate(&digest));
+        identities.remember_certificate(digest);
+        assert!(identities.verified_certificate(&digest));
+
+        // Another signature, or the same responses unsigned, is another certificate
+        let mut forged = certificate.clone();
+        forged[0].signature = Some([0; 64]);
+        assert!(!identities.verified_certificate(&Identities::certificate_digest(&forged)));
+        forged[0].signature = None;
+        assert!(!identities.verified_certificate(&Identities::certificate_digest(&forged)));
+
+        // A new committee verifies again, as does a member under a new key
+        identities.set(key(0), (0..4).map(|id| (id, key(id).verifying_key())).collect());
+        assert!(!identities.verified_certificate(&digest));
+        identities.remember_certificate(digest);
+        identities.rekey(1, key(9).verifying_key());
+        assert!(!identities.verified_certificate(&digest));
+    }
+
+    #[test]
+    fn key_files_hold_the_key_of_the_member_and_the_ones_of_the_committee() {
+        let committee: Vec<VerifyingKey> = (0..4).map(|id| key(id).verifying_key()).collect();
+        let (own, keys) = parse_key_file(&key_file(&key(2), &committee)).unwrap();
+        assert_eq!(own.to_bytes(), key(2).to_bytes());
+        assert_eq!(keys, (0..4).map(|id| (id, key(id).verifying_key())).collect());
+
+        assert!(parse_key_file("").is_err());
+        assert!(parse_key_file(&key_file(&key(2), &[])).is_err());
+        assert!(parse_key_file("not hex\n").is_err());
+    }
+
+    #[test]
+    fn pinned_processes_only_accept_their_own_configuration() {
+        let configuration = identities(0).configuration(1, 4);
+        let members = |ids: &[Id]| ids.iter().map(|id| (*id, Some(key(*id).verifying_key().to_bytes()))).collect::<Vec<_>>();
+        assert_eq!(configuration, Identities::configuration_hash(1, members(&[3, 1, 2, 0])));
+        assert_ne!(configuration, Identities::configuration_hash(1, members(&[0, 1, 2, 3, 4])));
+        assert_ne!(configuration, Identities::configuration_hash(2, members(&[0, 1, 2, 3])));
+        // Keys are part of the membership
+        assert_ne!(configuration, Identities::default().configuration(1, 4));
+        let rekeyed = identities(0);
+        rekeyed.rekey(2, key(9).verifying_key());
+        assert_ne!(configuration, rekeyed.configuration(1, 4));
+
+        let (signer, verifier) = (identities(1), identities(0));
+        signer.pin(configuration);
+        verifier.pin(configuration);
+        let signed = signer.sign(Response::new(1, Step::R, 0, vec![]));
+        assert_eq!(signed.committee, Some(configuration));
+        assert!(verifier.verify(&signed));
+
+        // Replayed into another configuration, or stripped of the hash the signature covers
+        verifier.pin(Identities::configuration_hash(1, members(&[0, 1, 2, 3, 4])));
+        assert!(!verifier.verify(&signed));
+        assert!(!identities(0).verify(&Response { committee: None, ..signed.clone() }));
+        assert!(Identities::default().accepts(None) && Identities::default().accepts(Some(configuration)));
+    }
+}
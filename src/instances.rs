@@ -0,0 +1,411 @@
+use std::{cmp::Reverse, collections::{BTreeMap, BTreeSet, BinaryHeap}, sync::{mpsc::{channel, Receiver, Sender}, Arc, Condvar, Mutex}, thread, time::Instant};
+use log::debug;
+use crate::{ConflictPolicy, Config, Id, LiveConfig, Message, PeerQueues, PeerStats, PeerTable, PreProposal, Process, Proposal, Reload, ResponseGroup, Submission};
+
+// Messages for instances past the window are held until it reaches them, up to this many
+const MAX_EARLY_MESSAGES: usize = 1 << 16;
+
+#[derive(Debug, Default)]
+struct Window {
+    // Lowest instance this process has not decided
+    base: u64,
+    // Instances above the base that already decided
+    decided: BTreeSet<u64>,
+    // Instances given up past their deadline, no longer started
+    abandoned: BTreeSet<u64>,
+    // One past the highest instance this process proposed in, where abandoned values are proposed again
+    next: u64,
+    // Of the instances proposed in, earliest first
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    stopped: bool,
+    running: BTreeMap<u64, (Process, Sender<Message>)>,
+    early: BTreeMap<u64, Vec<Message>>,
+    early_count: usize,
+}
+
+// How the proposer of an instance came back
+enum Outcome {
+    Decided(Proposal),
+    // Given up past its deadline before its process committed
+    Abandoned,
+}
+
+// Consensus instances run side by side, each by a Process of its own so that they share no state. Their messages go
+// over the same peer queues, wrapped in Message::Instance, and are handed to the process of their instance on arrival
+// Up to `Config::instance_window` instances from the lowest undecided one run at once: proposing further ahead waits
+// for it to decide, and messages for them are held until then. Decided instances keep answering while they are within
+// the window below it, for the peers that have not decided them yet
+// With `Config::instance_deadline` set, an instance not decided by then is abandoned locally, as if it had decided, so
+// that it does not hold the window back, and the value is proposed again in a later instance
+#[derive(Debug, Clone)]
+pub struct Instances {
+    id: Id,
+    f: usize,
+    size: u64,
+    peers: PeerQueues,
+    // Instances start with the settings as reloaded so far
+    config: LiveConfig,
+    window: Arc<(Mutex<Window>, Condvar)>,
+}
+
+impl Instances {
+    pub fn new(id: Id, f: usize, senders: Vec<Sender<Message>>, receiver: Receiver<Message>, config: Config) -> Instances {
+        let instances = Instances {
+            id,
+            f,
+            size: config.instance_window.max(1) as u64,
+            peers: PeerQueues::spawn(senders, config.outbound_queue_capacity, config.overflow_policy, config.prioritize_traffic),
+            config: LiveConfig::new(config),
+            window: Arc::default(),
+        };
+
+        let router = instances.clone();
+        thread::spawn(move || router.route(receiver));
+        if config.instance_deadline.is_some() {
+            let expirer = instances.clone();
+            thread::spawn(move || expirer.expire());
+        }
+        instances
+    }
+
+    // Lowest instance this process has not decided
+    pub fn base(&self) -> u64 {
+        self.window.0.lock().unwrap().base
+    }
+
+    // Process of a running instance, e.g. for its stats
+    pub fn process(&self, instance: u64) -> Option<Process> {
+        self.window.0.lock().unwrap().running.get(&instance).map(|(process, _)| process.clone())
+    }
+
+    // Traffic of the running instances, added up
+    pub fn peer_stats(&self) -> BTreeMap<Id, PeerStats> {
+        let mut stats: BTreeMap<Id, PeerStats> = BTreeMap::new();
+        for (process, _) in self.window.0.lock().unwrap().running.values() {
+            for (peer, traffic) in process.peer_stats() {
+                *stats.entry(peer).or_default() += traffic;
+            }
+        }
+        stats
+    }
+
+    // Applies to the running instances and the ones started later
+    pub fn reload(&self, reload: &Reload) {
+        self.config.reload(reload);
+        for (process, _) in self.window.0.lock().unwrap().running.values() {
+            process.reload(reload);
+        }
+    }
+
+    // Of the peer queues every instance sends over
+    pub fn peer_table(&self) -> Arc<PeerTable> {
+        self.peers.table()
+    }
+
+    // Stops once every peer is gone
+    fn route(&self, receiver: Receiver<Message>) {
+        for message in receiver {
+            match message {
+                Message::Instance(instance, message) => self.deliver(instance, *message),
+                message => debug!("{}: dropped a message of no instance from {:?}", self.id, message.sender()),
+            }
+        }
+    }
+
+    fn deliver(&self, instance: u64, message: Message) {
+        let mut window = self.window.0.lock().unwrap();
+
+        if instance >= window.base + self.size {
+            if window.early_count < MAX_EARLY_MESSAGES {
+                window.early.entry(instance).or_default().push(message);
+                window.early_count += 1;
+            }
+            return;
+        }
+
+        if let Some(sender) = self.start(&mut window, instance) {
+            let _ = sender.send(message);
+        }
+    }
+
+    // Sender to the process of the instance, started on its first message or proposal
+    // None once the instance fell more than the window below the base, or was abandoned
+    fn start<'a>(&self, window: &'a mut Window, instance: u64) -> Option<&'a Sender<Message>> {
+        if instance + self.size < window.base || window.abandoned.contains(&instance) {
+            return None;
+        }
+
+        let (_, sender) = window.running.entry(instance).or_insert_with(|| {
+            let (sender, receiver) = channel();
+            let process = Process::new_with_peers(self.id, self.f, self.peers.for_instance(instance), receiver, false, self.config.get());
+            (process, sender)
+        });
+        Some(sender)
+    }
+
+    // Waits for the instance to be within the window, then for it to decide
+    // If it is abandoned first, the value is proposed again in the next instance this process has not proposed in and
+    // the proposal decided there is returned. None if it already fell behind the window
+    pub fn propose(&self, mut instance: u64, threshold: usize, value: PreProposal) -> Option<Proposal> {
+        loop {
+            match self.run(instance, threshold, value.clone())? {
+                Outcome::Decided(proposal) => return Some(proposal),
+                Outcome::Abandoned => {
+                    let mut window = self.window.0.lock().unwrap();
+                    instance = window.next;
+                    window.next += 1;
+                }
+            }
+        }
+    }
+
+    fn run(&self, instance: u64, threshold: usize, value: PreProposal) -> Option<Outcome> {
+        let (lock, condvar) = &*self.window;
+        let mut process = {
+            let mut window = condvar.wait_while(lock.lock().unwrap(), |window| instance >= window.base + self.size).unwrap();
+            window.next = window.next.max(instance + 1);
+            self.start(&mut window, instance)?;
+            if let Some(deadline) = self.config.get().instance_deadline {
+                window.deadlines.push(Reverse((Instant::now() + deadline, instance)));
+                condvar.notify_all();
+            }
+            window.running[&instance].0.clone()
+        };
+
+        let proposal = process.propose(threshold, value, 0);
+
+        // A process that committed decided its instance, whether or not it was abandoned while waiting for the proposal
+        let mut window = lock.lock().unwrap();
+        if process.decided_watch().borrow().is_none() && window.abandoned.contains(&instance) {
+            return Some(Outcome::Abandoned);
+        }
+        self.decided(&mut window, instance);
+        Some(Outcome::Decided(proposal))
+    }
+
+    // Abandons the instances past their deadline, until stopped
+    fn expire(&self) {
+        let (lock, condvar) = &*self.window;
+        let mut window = lock.lock().unwrap();
+
+        while !window.stopped {
+            let now = Instant::now();
+            window = match window.deadlines.peek().copied() {
+                Some(Reverse((deadline, instance))) if deadline <= now => {
+                    window.deadlines.pop();
+                    self.abandon(&mut window, instance);
+                    window
+                }
+                Some(Reverse((deadline, _))) => condvar.wait_timeout(window, deadline - now).unwrap().0,
+                None => condvar.wait(window).unwrap(),
+            };
+        }
+    }
+
+    // Gives the instance up locally unless it decided or committed: stops its process, which frees its responses,
+    // tallies and queued messages, drops the messages held for it and moves the window past it as if it had decided
+    fn abandon(&self, window: &mut Window, instance: u64) {
+        let committed = window.running.get(&instance).is_some_and(|(process, _)| process.decided_watch().borrow().is_some());
+        if instance < window.base || window.decided.contains(&instance) || committed {
+            return;
+        }
+
+        debug!("{}: abandoned instance {} past its deadline", self.id, instance);
+        window.abandoned.insert(instance);
+        if let Some((mut process, _)) = window.running.remove(&instance) {
+            process.stop();
+        }
+        if let Some(messages) = window.early.remove(&instance) {
+            window.early_count -= messages.len();
+        }
+        self.decided(window, instance);
+    }
+
+    // Proposes the values in consecutive instances from `first`, as many at once as the window allows
+    pub fn propose_all(&self, first: u64, threshold: usize, values: Vec<PreProposal>) -> Vec<Option<Proposal>> {
+        // Abandoned values go past all of them
+        {
+            let mut window = self.window.0.lock().unwrap();
+            window.next = window.next.max(first + values.len() as u64);
+        }
+
+        thread::scope(|scope| {
+            let proposers: Vec<_> = values
+                .into_iter()
+                .zip(first..)
+                .map(|(value, instance)| scope.spawn(move || self.propose(instance, threshold, value)))
+                .collect();
+
+            proposers.into_iter().map(|proposer| proposer.join().unwrap()).collect()
+        })
+    }
+
+    // Proposes in consecutive instances from `first` what the policy resolves the submissions of each one to
+    pub fn propose_resolved(&self, first: u64, threshold: usize, submissions: Vec<Vec<Submission>>, policy: &dyn ConflictPolicy) -> Vec<Option<Proposal>> {
+        let values = submissions.into_iter().map(|submissions| PreProposal::new(policy.resolve(submissions), self.id)).collect();
+        self.propose_all(first, threshold, values)
+    }
+
+    // Responses of every running instance, see Process::snapshot_responses
+    pub fn snapshot_responses(&self) -> Vec<ResponseGroup> {
+        let running: Vec<(u64, Process)> = self.window.0.lock().unwrap().running.iter().map(|(instance, (process, _))| (*instance, process.clone())).collect();
+        running.into_iter().flat_map(|(instance, process)| process.response_groups(instance)).collect()
+    }
+
+    // Skips the instances below `base`, e.g. the ones a member that restarted without its state missed: they are
+    // neither started nor proposed in, and the window starts from it
+    pub fn resume(&self, base: u64) {
+        let mut window = self.window.0.lock().unwrap();
+        if base <= window.base {
+            return;
+        }
+
+        window.base = base;
+        window.next = window.next.max(base);
+        window.decided = window.decided.split_off(&base);
+        self.advance(&mut window);
+    }
+
+    fn decided(&self, window: &mut Window, instance: u64) {
+        // Abandoned instances may still have committed
+        if instance < window.base || !window.decided.insert(instance) {
+            return;
+        }
+        self.advance(window);
+    }
+
+    // Moves the window past the instances decided from the base on, stopping the ones that fell behind it and handing
+    // the held messages to the ones it reached
+    fn advance(&self, window: &mut Window) {
+        while let Some(base) = window.decided.first().copied().filter(|decided| *decided == window.base) {
+            window.decided.remove(&base);
+            window.base += 1;
+        }
+
+        let kept = window.running.split_off(&window.base.saturating_sub(self.size));
+        for (_, (mut process, _)) in std::mem::replace(&mut window.running, kept) {
+            process.stop();
+        }
+        window.abandoned = window.abandoned.split_off(&window.base.saturating_sub(self.size));
+
+        let later = window.early.split_off(&(window.base + self.size));
+        for (instance, messages) in std::mem::replace(&mut window.early, later) {
+            window.early_count -= messages.len();
+            if let Some(sender) = self.start(window, instance) {
+                for message in messages {
+                    let _ = sender.send(message);
+                }
+            }
+        }
+
+        self.window.1.notify_all();
+    }
+
+    pub fn stop(&self) {
+        let mut window = self.window.0.lock().unwrap();
+        window.stopped = true;
+        for (process, _) in window.running.values_mut() {
+            process.stop();
+        }
+        self.window.1.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rsnano_core::BlockHash;
+    use super::*;
+
+    #[test]
+    fn instances_decide_side_by_side() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let config = Config { instance_window: 2, ..Config::default() };
+        let instances: Vec<Instances> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| Instances::new(id as Id, 1, senders.clone(), receiver, config))
+            .collect();
+
+        let proposers: Vec<_> = instances
+            .iter()
+            .enumerate()
+            .map(|(id, instances)| {
+                let instances = instances.clone();
+                let values = (0..5).map(|instance| PreProposal::new(vec![BlockHash::from(10 * instance + id as u64)], id as Id)).collect();
+                thread::spawn(move || instances.propose_all(0, 3, values))
+            })
+            .collect();
+        let decided: Vec<Vec<Option<Proposal>>> = proposers.into_iter().map(|proposer| proposer.join().unwrap()).collect();
+
+        for instance in 0..5 {
+            let hash = decided[0][instance].as_ref().unwrap().hash;
+            assert!(decided.iter().all(|decided| decided[instance].as_ref().map(|proposal| proposal.hash) == Some(hash)));
+        }
+        assert!(instances.iter().all(|instances| instances.base() == 5));
+        // Instances more than the window below the base were stopped and no longer answer
+        assert!(instances[0].process(2).is_none() && instances[0].process(3).is_some());
+        assert_eq!(instances[0].propose(0, 3, PreProposal::new(vec![], 0)), None);
+        instances.iter().for_each(Instances::stop);
+    }
+
+    #[test]
+    fn stuck_instances_are_abandoned_and_their_value_decided_later() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        // Only 0 gives up on its instances
+        let config = |id| Config { instance_deadline: (id == 0).then_some(Duration::from_secs(1)), ..Config::default() };
+        let instances: Vec<Instances> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| Instances::new(id as Id, 1, senders.clone(), receiver, config(id)))
+            .collect();
+        let values: Vec<PreProposal> = (0..3).map(|id| PreProposal::new(vec![BlockHash::from(id + 1)], id as Id)).collect();
+
+        // Only 0 proposes in instance 0, which never gets 2f+1 preproposals, while 1 and 2 wait for a third in instance 1
+        let proposers: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(id, value)| {
+                let instances = instances[id].clone();
+                let value = value.clone();
+                thread::spawn(move || instances.propose(if id == 0 { 0 } else { 1 }, 3, value))
+            })
+            .collect();
+        let decided: Vec<Proposal> = proposers.into_iter().map(|proposer| proposer.join().unwrap().unwrap()).collect();
+
+        assert!(decided.iter().all(|proposal| proposal.hash == decided[0].hash));
+        assert!(decided[0].preproposals.contains(&values[0].hash()));
+        // 0 moved past both instances and no longer runs the abandoned one
+        assert_eq!(instances[0].base(), 2);
+        assert!(instances[0].process(0).is_none());
+        assert_eq!(instances[0].propose(0, 3, values[0].clone()), None);
+        instances.iter().for_each(Instances::stop);
+    }
+
+    #[test]
+    fn resumed_instances_start_from_the_base_given() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..4).map(|_| channel()).unzip();
+        let instances: Vec<Instances> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| Instances::new(id as Id, 1, senders.clone(), receiver, Config::default()))
+            .collect();
+        instances.iter().for_each(|instances| instances.resume(10));
+        instances[0].resume(3);
+
+        let proposers: Vec<_> = instances
+            .iter()
+            .enumerate()
+            .map(|(id, instances)| {
+                let instances = instances.clone();
+                thread::spawn(move || instances.propose(10, 3, PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id as Id)))
+            })
+            .collect();
+        let decided: Vec<Proposal> = proposers.into_iter().map(|proposer| proposer.join().unwrap().unwrap()).collect();
+
+        assert!(decided.iter().all(|proposal| proposal.hash == decided[0].hash));
+        assert!(instances.iter().all(|instances| instances.base() == 11));
+        assert_eq!(instances[0].propose(2, 3, PreProposal::new(vec![], 0)), None);
+        instances.iter().for_each(Instances::stop);
+    }
+}
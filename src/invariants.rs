@@ -0,0 +1,97 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::{Broadcast, BroadcastHash, ProposalHash, Rank, Registers, Step};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantViolation {
+    // The process sent two different broadcasts for the same step and rank
+    Equivocation { step: Step, rank: Rank },
+    // The rank of the process went down
+    RankDecreased { from: Rank, to: Rank },
+    // More than two values in the A or B set of a rank
+    OversizedSet { step: Step, rank: Rank, len: usize },
+    // Two different values committed in the same instance
+    ConflictingCommit { instance: u64, first: ProposalHash, second: ProposalHash },
+}
+
+// Properties a process keeps after every transition, whatever messages it handled and in whatever order. Core::handle
+// and the simulator check them in debug builds, and so do the fuzz targets driving them
+#[derive(Debug, Default)]
+pub struct Invariants {
+    sent: HashMap<(Step, Rank), BroadcastHash>,
+    rank: Option<Rank>,
+    committed: BTreeMap<u64, ProposalHash>,
+}
+
+impl Invariants {
+    // A broadcast the process itself sends, as opposed to one it relays
+    pub fn sent(&mut self, broadcast: &Broadcast) -> Result<(), InvariantViolation> {
+        let (step, rank) = (broadcast.step, broadcast.rank);
+        let first = *self.sent.entry((step, rank)).or_insert(broadcast.hash_value());
+        if first == broadcast.hash_value() { Ok(()) } else { Err(InvariantViolation::Equivocation { step, rank }) }
+    }
+
+    pub fn rank(&mut self, rank: Rank) -> Result<(), InvariantViolation> {
+        match self.rank {
+            Some(from) if rank < from => Err(InvariantViolation::RankDecreased { from, to: rank }),
+            _ => {
+                self.rank = Some(rank);
+                Ok(())
+            }
+        }
+    }
+
+    // The R register only grows, and A and B sets hold two values at most
+    pub fn registers(&mut self, registers: &Registers) -> Result<(), InvariantViolation> {
+        self.rank(registers.r().rank)?;
+
+        let a_sets = registers.a_sets().into_iter().map(|(rank, set)| (Step::A, rank, set.len()));
+        let b_sets = registers.b_sets().into_iter().map(|(rank, set)| (Step::B, rank, set.len()));
+        match a_sets.chain(b_sets).find(|(_, _, len)| *len > 2) {
+            Some((step, rank, len)) => Err(InvariantViolation::OversizedSet { step, rank, len }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn committed(&mut self, instance: u64, value: ProposalHash) -> Result<(), InvariantViolation> {
+        let first = *self.committed.entry(instance).or_insert(value);
+        if first == value { Ok(()) } else { Err(InvariantViolation::ConflictingCommit { instance, first, second: value }) }
+    }
+
+    // Instance after the last one checked, the commits from there on are the new ones
+    pub fn next_instance(&self) -> u64 {
+        self.committed.last_key_value().map_or(0, |(instance, _)| instance + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{AValue, BValue, RValue};
+
+    #[test]
+    fn violations_are_reported_once_they_happen() {
+        let mut invariants = Invariants::default();
+
+        let broadcast = Broadcast::new(0, Step::A, BlockHash::from(1), None, 2, None);
+        assert_eq!(invariants.sent(&broadcast), Ok(()));
+        assert_eq!(invariants.sent(&broadcast), Ok(()));
+        assert_eq!(invariants.sent(&Broadcast::new(0, Step::A, BlockHash::from(2), None, 2, None)), Err(InvariantViolation::Equivocation { step: Step::A, rank: 2 }));
+
+        let registers = Registers::new();
+        registers.update_r(RValue::new(3, BlockHash::from(1)));
+        for value in 1..=3 {
+            registers.update_a(0, AValue(BlockHash::from(value)));
+            registers.update_b(0, BValue::new(BlockHash::from(value), value == 1));
+        }
+        assert_eq!(invariants.registers(&registers), Ok(()));
+        assert_eq!(invariants.rank(2), Err(InvariantViolation::RankDecreased { from: 3, to: 2 }));
+
+        assert_eq!(invariants.committed(4, BlockHash::from(1)), Ok(()));
+        assert_eq!(invariants.next_instance(), 5);
+        assert_eq!(
+            invariants.committed(4, BlockHash::from(2)),
+            Err(InvariantViolation::ConflictingCommit { instance: 4, first: BlockHash::from(1), second: BlockHash::from(2) })
+        );
+    }
+}
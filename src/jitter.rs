@@ -0,0 +1,68 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Random delays added to protocol timers, so that an adversary watching the network cannot tell when a process acts
+// next from when it acted before. Drawn from a CSPRNG (StdRng, ChaCha) seeded from the OS per node, or from a seed to
+// replay the same delays in tests
+// Clones share the generator, so the seed set on a process applies to its proposer and run loop
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl Default for Jitter {
+    fn default() -> Jitter {
+        Jitter { rng: Arc::new(Mutex::new(StdRng::from_entropy())) }
+    }
+}
+
+impl Jitter {
+    pub fn seeded(seed: u64) -> Jitter {
+        Jitter { rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))) }
+    }
+
+    // Every clone draws from the seed from now on
+    pub fn reseed(&self, seed: u64) {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+    }
+
+    // Uniform below the bound, zero for a zero bound
+    pub fn below(&self, bound: Duration) -> Duration {
+        let bound = bound.as_nanos() as u64;
+        if bound == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(self.rng.lock().unwrap().gen_range(0..bound))
+    }
+
+    // The period moved by up to the fraction of it either way, e.g. 0.2 for ±20%
+    pub fn spread(&self, period: Duration, fraction: f64) -> Duration {
+        let spread = period.mul_f64(fraction.clamp(0.0, 1.0));
+        (period + self.below(2 * spread)).saturating_sub(spread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_sources_replay_the_same_delays() {
+        let period = Duration::from_millis(100);
+        let delays = |jitter: &Jitter| (0..16).map(|_| jitter.spread(period, 0.2)).collect::<Vec<_>>();
+
+        let jitter = Jitter::seeded(7);
+        let drawn = delays(&jitter);
+        assert!(drawn.iter().all(|delay| *delay >= period.mul_f64(0.8) && *delay < period.mul_f64(1.2)));
+        assert!(drawn.iter().any(|delay| *delay != drawn[0]));
+
+        // Clones share the generator
+        let clone = jitter.clone();
+        clone.reseed(7);
+        assert_eq!(delays(&jitter), drawn);
+        assert_ne!(delays(&Jitter::default()), drawn);
+
+        assert_eq!(jitter.spread(period, 0.0), period);
+        assert_eq!(jitter.below(Duration::ZERO), Duration::ZERO);
+    }
+}
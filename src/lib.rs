@@ -1,7 +1,148 @@
 pub mod bft_archipelago;
 pub mod structs;
 pub mod preconsensus;
+pub mod preproposals;
+pub mod votes;
+pub mod collector;
+pub mod identity;
+pub mod batching;
+pub mod workers;
+pub mod config;
+pub mod response_store;
+pub mod broadcast_store;
+pub mod memory;
+pub mod pending;
+pub mod certificates;
+pub mod tally;
+pub mod peers;
+pub mod mailbox;
+pub mod accounting;
+pub mod logging;
+pub mod pool;
+pub mod sim;
+pub mod faults;
+pub mod byzantine;
+pub mod twins;
+pub mod registers;
+pub mod pacemaker;
+pub mod beacon;
+pub mod validity;
+pub mod jitter;
+pub mod profiling;
+pub mod invariants;
+pub mod reload;
+pub mod peer_table;
+pub mod suspicion;
+pub mod rejoin;
+pub mod handshake;
+pub mod sink;
+pub mod watchdog;
+pub mod decided;
+pub mod commits;
+pub mod cementing;
+pub mod forks;
+pub mod heads;
+pub mod instances;
+pub mod conflicts;
+pub mod buckets;
+pub mod daemon;
+pub mod control;
+pub mod dump;
+pub mod trace;
+pub mod metrics;
+pub mod stats;
+pub mod statsd;
+pub mod chaos;
+#[cfg(unix)]
+pub mod cluster;
+pub mod scenario;
+pub mod conformance;
+pub mod history;
+pub mod audit;
+pub mod light;
+pub mod transcript;
+pub mod regressions;
+pub mod wire;
+pub mod proto;
+pub mod transport;
+pub mod udp;
+pub mod gossip;
+pub mod wakeup;
+pub mod proposing;
+#[cfg(feature = "scalability")]
+pub mod scalability;
+mod sync;
 
 pub use bft_archipelago::*;
 pub use structs::*;
-pub use preconsensus::*;
\ No newline at end of file
+pub use preconsensus::*;
+pub use preproposals::*;
+pub use votes::*;
+pub use collector::*;
+pub use identity::*;
+pub use batching::*;
+pub use workers::*;
+pub use config::*;
+pub use response_store::*;
+pub use broadcast_store::*;
+pub use memory::*;
+pub use pending::*;
+pub use certificates::*;
+pub use tally::*;
+pub use peers::*;
+pub use mailbox::*;
+pub use accounting::*;
+pub use logging::*;
+pub use pool::*;
+pub use sim::*;
+pub use faults::*;
+pub use byzantine::*;
+pub use twins::*;
+pub use registers::*;
+pub use pacemaker::*;
+pub use beacon::*;
+pub use validity::*;
+pub use jitter::*;
+pub use profiling::*;
+pub use invariants::*;
+pub use reload::*;
+pub use peer_table::*;
+pub use suspicion::*;
+pub use rejoin::*;
+pub use handshake::*;
+pub use sink::*;
+pub use watchdog::*;
+pub use decided::*;
+pub use commits::*;
+pub use cementing::*;
+pub use forks::*;
+pub use heads::*;
+pub use instances::*;
+pub use conflicts::*;
+pub use buckets::*;
+pub use daemon::*;
+pub use control::*;
+pub use dump::*;
+pub use trace::*;
+pub use metrics::*;
+pub use stats::*;
+pub use statsd::*;
+pub use chaos::*;
+#[cfg(unix)]
+pub use cluster::*;
+pub use scenario::*;
+pub use conformance::*;
+pub use history::*;
+pub use audit::*;
+pub use light::*;
+pub use transcript::*;
+pub use regressions::*;
+pub use wire::*;
+pub use proto::*;
+pub use transport::*;
+pub use udp::*;
+pub use gossip::*;
+pub use wakeup::*;
+pub use proposing::*;
+#[cfg(feature = "scalability")]
+pub use scalability::*;
\ No newline at end of file
@@ -0,0 +1,170 @@
+use rsnano_core::BlockHash;
+use crate::{certificate_digest, verify_audit_log, AuditEntry, CommitRecord, Committee, Core, FrontierDecodeError, PreProposal, PreProposalHash, Proposal, ProposalHash, ValueOrder};
+
+// Checks for light clients, e.g. wallets and services that want to know that a block was in a committed proposal
+// without running a member: pure functions of their inputs, without networking or threads
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightError {
+    // The committee has no keys or no configuration hash, so any answers claiming to be of its members would do
+    Committee,
+    // The answers do not commit the value, or are not signed by the committee
+    Certificate,
+    // The audit log is broken at some line, see verify_audit_log
+    Log(String),
+    // The log has no entry for the instance of the commit, or one of another value, rank or certificate
+    NotLogged,
+    // The preproposal hashes of the proof do not hash to the proposal
+    Proposal,
+    // The frontiers of the proof do not hash to the preproposal they stand for
+    PreProposal,
+    Encoding(FrontierDecodeError),
+    // The frontiers of the proof do not contain the block
+    NotAFrontier,
+}
+
+// The answers of the commit are 2f+1 B answers of its rank, signed by the committee and carrying its configuration
+// hash, that commit its value. The committee is the one the client trusts, e.g. the one of a checkpoint it checked
+pub fn verify_commit(commit: &CommitRecord, committee: &Committee, order: &ValueOrder) -> Result<(), LightError> {
+    if committee.members.is_empty() || committee.configuration.is_none() {
+        return Err(LightError::Committee);
+    }
+    Core::check_checkpoint(commit, committee, order).then_some(()).ok_or(LightError::Certificate)
+}
+
+// Entries of an audit log whose hash chain holds
+pub fn verify_log(text: &str) -> Result<Vec<AuditEntry>, LightError> {
+    verify_audit_log(text).map_err(LightError::Log)
+}
+
+// The commit is the one the log recorded for its instance
+pub fn verify_logged(commit: &CommitRecord, entries: &[AuditEntry]) -> Result<(), LightError> {
+    entries
+        .iter()
+        .find(|entry| entry.instance == commit.instance)
+        .filter(|entry| entry.value == commit.value && entry.rank == commit.rank && entry.certificate == certificate_digest(&commit.certificate))
+        .map(|_| ())
+        .ok_or(LightError::NotLogged)
+}
+
+// That a block is a frontier of a proposal, by listing the preproposal hashes the proposal hashes and every frontier of
+// the preproposal holding the block, in the encoding of encode_frontiers. Preproposal digests hash their frontiers in a
+// row rather than as a Merkle tree, so there is no path to a committed root: a listing is O(n) in the preproposals of
+// the proposal and the frontiers of the preproposal, as is checking it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontierListing {
+    pub preproposals: Vec<PreProposalHash>,
+    // Position of the preproposal holding the block
+    pub index: usize,
+    pub frontiers: Vec<u8>,
+}
+
+impl FrontierListing {
+    // None if the preproposal is not part of the proposal
+    pub fn new(proposal: &Proposal, preproposal: &PreProposal) -> Option<FrontierListing> {
+        let index = proposal.preproposals.iter().position(|hash| *hash == preproposal.hash())?;
+        Some(FrontierListing { preproposals: proposal.preproposals.clone(), index, frontiers: preproposal.encoded_frontiers() })
+    }
+
+    pub fn verify(&self, block: BlockHash, proposal: ProposalHash) -> Result<(), LightError> {
+        if Proposal::new(self.preproposals.clone(), 0).hash != proposal {
+            return Err(LightError::Proposal);
+        }
+
+        let preproposal = PreProposal::from_encoded(&self.frontiers, 0).map_err(LightError::Encoding)?;
+        if self.preproposals.get(self.index) != Some(&preproposal.hash()) {
+            return Err(LightError::PreProposal);
+        }
+        if !preproposal.frontiers().contains(&block) {
+            return Err(LightError::NotAFrontier);
+        }
+        Ok(())
+    }
+}
+
+// Block X was in the proposal committed by the commit, whose certificate holds
+pub fn verify_inclusion(block: BlockHash, listing: &FrontierListing, commit: &CommitRecord, committee: &Committee, order: &ValueOrder) -> Result<(), LightError> {
+    verify_commit(commit, committee, order)?;
+    listing.verify(block, commit.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+    use ed25519_dalek::SigningKey;
+    use super::*;
+    use crate::{AuditLog, BValue, Broadcast, Id, Identities, Response, State, Step, Value};
+
+    fn key(id: Id) -> SigningKey {
+        SigningKey::from_bytes(&[id as u8 + 1; 32])
+    }
+
+    // Four members signing with their keys, pinned to their configuration
+    fn committee() -> Committee {
+        let members = (0..4).map(|id| (id, key(id).verifying_key().to_bytes())).collect::<BTreeMap<_, _>>();
+        let configuration = Identities::configuration_hash(1, members.iter().map(|(member, key)| (*member, Some(*key))));
+        Committee { f: 1, members, configuration: Some(configuration) }
+    }
+
+    fn commit(value: ProposalHash) -> CommitRecord {
+        let committee = committee();
+        let broadcast = Arc::new(Broadcast::new(0, Step::B, value, Some(true), 2, None));
+        let certificate = (0..3)
+            .map(|sender| {
+                let identities = Identities::default();
+                identities.set(key(sender), (0..4).map(|id| (id, key(id).verifying_key())).collect());
+                identities.pin(committee.configuration.unwrap());
+                identities.sign(Response::new(sender, Step::B, 2, vec![State::new(Value::BValue(BValue::new(value, true)), broadcast.clone())]))
+            })
+            .collect();
+        CommitRecord { instance: 0, rank: 2, value, certificate, committee: None }
+    }
+
+    #[test]
+    fn blocks_are_listed_frontiers_of_committed_proposals() {
+        let preproposals: Vec<PreProposal> = (0..3u64).map(|id| PreProposal::new(vec![BlockHash::from(10 * id + 1), BlockHash::from(10 * id + 2)], id as Id)).collect();
+        let proposal = Proposal::new(preproposals.iter().map(PreProposal::hash).collect(), 1);
+        let listing = FrontierListing::new(&proposal, &preproposals[1]).unwrap();
+        let (order, committee) = (ValueOrder::default(), committee());
+
+        let commit = commit(proposal.hash);
+        assert_eq!(verify_inclusion(BlockHash::from(12), &listing, &commit, &committee, &order), Ok(()));
+        assert_eq!(listing.verify(BlockHash::from(1), proposal.hash), Err(LightError::NotAFrontier));
+        assert_eq!(listing.verify(BlockHash::from(12), BlockHash::from(7)), Err(LightError::Proposal));
+        assert_eq!(FrontierListing { index: 0, ..listing.clone() }.verify(BlockHash::from(12), proposal.hash), Err(LightError::PreProposal));
+    }
+
+    #[test]
+    fn commits_are_only_taken_from_a_signed_and_pinned_committee() {
+        let (order, committee) = (ValueOrder::default(), committee());
+        let commit = commit(BlockHash::from(5));
+        assert_eq!(verify_commit(&commit, &committee, &order), Ok(()));
+
+        // Without keys or a configuration hash any answers would commit
+        let empty = Committee { members: BTreeMap::new(), ..committee.clone() };
+        assert_eq!(verify_commit(&commit, &empty, &order), Err(LightError::Committee));
+        let unpinned = Committee { configuration: None, ..committee.clone() };
+        assert_eq!(verify_commit(&commit, &unpinned, &order), Err(LightError::Committee));
+
+        let mut unsupported = commit.clone();
+        unsupported.certificate.truncate(2);
+        assert_eq!(verify_commit(&unsupported, &committee, &order), Err(LightError::Certificate));
+        let mut unsigned = commit.clone();
+        unsigned.certificate[0].signature = None;
+        assert_eq!(verify_commit(&unsigned, &committee, &order), Err(LightError::Certificate));
+        let other = Committee { members: (0..4).map(|id| (id, key(id + 4).verifying_key().to_bytes())).collect(), ..committee.clone() };
+        assert_eq!(verify_commit(&commit, &other, &order), Err(LightError::Certificate));
+    }
+
+    #[test]
+    fn commits_are_checked_against_the_audit_log() {
+        let commit = commit(BlockHash::from(5));
+        let mut log = AuditLog::new(None);
+        let entry = log.append(commit.value, commit.rank, &commit.certificate).unwrap().clone();
+
+        let entries = verify_log(&format!("{}\n", entry)).unwrap();
+        assert_eq!(verify_logged(&commit, &entries), Ok(()));
+        assert_eq!(verify_logged(&CommitRecord { rank: 3, ..commit.clone() }, &entries), Err(LightError::NotLogged));
+        assert!(matches!(verify_log(&format!("{}\n", entry).replace("\"rank\":2", "\"rank\":3")), Err(LightError::Log(_))));
+    }
+}
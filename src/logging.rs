@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Lets through the first event and then one out of every `every` events, so that repetitive
+// events in the hot paths (one per message) show up in the logs without flooding them
+#[derive(Debug)]
+pub struct LogSampler {
+    every: u64,
+    seen: AtomicU64,
+}
+
+impl LogSampler {
+    pub const fn new(every: u64) -> LogSampler {
+        LogSampler { every, seen: AtomicU64::new(0) }
+    }
+
+    pub fn sample(&self) -> bool {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed);
+        self.every <= 1 || seen.is_multiple_of(self.every)
+    }
+
+    // Number of events offered to the sampler, logged or not
+    pub fn seen(&self) -> u64 {
+        self.seen.load(Ordering::Relaxed)
+    }
+}
+
+// Logs at the given level only if it is enabled and the sampler lets the event through
+// The arguments are not evaluated (nor formatted) otherwise
+#[macro_export]
+macro_rules! sampled {
+    ($sampler:expr, $level:expr, $($arg:tt)+) => {
+        if log::log_enabled!($level) && $sampler.sample() {
+            log::log!($level, $($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_first_and_every_nth_event() {
+        let sampler = LogSampler::new(3);
+
+        let sampled: Vec<bool> = (0..7).map(|_| sampler.sample()).collect();
+
+        assert_eq!(sampled, vec![true, false, false, true, false, false, true]);
+        assert_eq!(sampler.seen(), 7);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{collections::VecDeque, sync::{atomic::{AtomicUsize, Ordering}, Arc, Condvar, Mutex}};
+use crate::Message;
+
+// A prioritized mailbox hands out a message next once more than this many were pushed after it, whatever its priority,
+// so that a steady stream of steps and answers does not starve acks, hellos and commits
+const MAX_AGE: u64 = 64;
+
+// What a full mailbox drops to make room for a new message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // The new message, queued ones are sent in full
+    #[default]
+    DropNewest,
+    // The message queued the longest, which is the most likely to be stale by the time it is sent
+    DropOldest,
+    // The oldest of the queued messages of the lowest priority, or the new one if none is below it
+    DropLowestPriority,
+}
+
+impl OverflowPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            OverflowPolicy::DropNewest => "drop_newest",
+            OverflowPolicy::DropOldest => "drop_oldest",
+            OverflowPolicy::DropLowestPriority => "drop_lowest_priority",
+        }
+    }
+}
+
+// How much losing a message costs the receiver, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    // Votes and commits for restarted processes, which are sent again or fetched later
+    Background,
+    // Preproposals, proposals and the responses of certificates sent by reference
+    Dissemination,
+    // Responses, each one counts towards the quorum that completes a step
+    Answer,
+    // Broadcasts, which start a step and are not sent again
+    Step,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [Priority::Background, Priority::Dissemination, Priority::Answer, Priority::Step];
+
+    pub fn of(message: &Message) -> Priority {
+        match message {
+            Message::Broadcast(_) => Priority::Step,
+            Message::Response(_) => Priority::Answer,
+            Message::PreProposal(_) | Message::Proposal(_) | Message::PreVote(..) | Message::GetPreProposals(..) | Message::GetResponses(..) | Message::Responses(..) | Message::GetCertificate(..) | Message::Certificate(..) => Priority::Dissemination,
+            Message::PreProposalDigest(..) | Message::Vote(_) | Message::VoteHashes(..) | Message::GetVotes(..) | Message::Announce(..) | Message::Commits(..) | Message::GetCertificates(..) | Message::Certificates(..) | Message::Ack(..) | Message::Hello(_) | Message::Rejoin(_) => Priority::Background,
+            Message::Batch(messages) | Message::Sequenced(_, _, messages) => messages.iter().map(Priority::of).max().unwrap_or(Priority::Background),
+            Message::Instance(_, message) => Priority::of(message),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Background => "background",
+            Priority::Dissemination => "dissemination",
+            Priority::Answer => "answer",
+            Priority::Step => "step",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pushed {
+    Queued,
+    // Queued in place of this message, which was dropped to make room
+    Replaced(Message),
+    // The mailbox was full and the message was dropped
+    Dropped(Message),
+    // The writer thread is gone
+    Closed(Message),
+}
+
+// Queued messages by priority, each numbered in the order it was pushed
+#[derive(Debug, Default)]
+struct Slots {
+    classes: [VecDeque<(u64, Message)>; 4],
+    pushed: u64,
+    closed: bool,
+}
+
+impl Slots {
+    fn len(&self) -> usize {
+        self.classes.iter().map(VecDeque::len).sum()
+    }
+
+    // Class of the message pushed first
+    fn oldest(&self) -> Option<usize> {
+        self.oldest_pushed().map(|(_, class)| class)
+    }
+
+    fn oldest_pushed(&self) -> Option<(u64, usize)> {
+        (0..self.classes.len()).filter_map(|class| self.classes[class].front().map(|(pushed, _)| (*pushed, class))).min()
+    }
+
+    // Class of the message queued at the highest priority, unless the message pushed first is too old to wait longer
+    fn next(&self) -> Option<usize> {
+        match self.oldest_pushed() {
+            Some((number, class)) if self.pushed - number - 1 > MAX_AGE => Some(class),
+            _ => (0..self.classes.len()).rev().find(|class| !self.classes[*class].is_empty()),
+        }
+    }
+}
+
+// Bounded queue of the messages to one peer, drained by its writer thread
+// Prioritized, it hands out the messages of the highest priority queued first, so that large preproposals queued
+// ahead cannot hold back the broadcasts and responses completing a step, up to MAX_AGE. Otherwise in the order they
+// were pushed
+#[derive(Debug)]
+pub struct Mailbox {
+    slots: Mutex<Slots>,
+    available: Condvar,
+    capacity: AtomicUsize,
+    policy: OverflowPolicy,
+    prioritized: bool,
+}
+
+impl Mailbox {
+    pub fn new(capacity: usize, policy: OverflowPolicy, prioritized: bool) -> Mailbox {
+        Mailbox { slots: Mutex::default(), available: Condvar::new(), capacity: AtomicUsize::new(capacity.max(1)), policy, prioritized }
+    }
+
+    // A lower capacity drops no queued message, only the ones pushed while the mailbox holds too many
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity.max(1), Ordering::Relaxed);
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&self, message: Message) -> Pushed {
+        let mut slots = self.slots.lock().unwrap();
+        if slots.closed {
+            return Pushed::Closed(message);
+        }
+
+        let priority = Priority::of(&message);
+        let pushed = if slots.len() < self.capacity.load(Ordering::Relaxed) {
+            Pushed::Queued
+        } else {
+            let class = match self.policy {
+                OverflowPolicy::DropNewest => None,
+                OverflowPolicy::DropOldest => slots.oldest(),
+                OverflowPolicy::DropLowestPriority => (0..priority as usize).find(|class| !slots.classes[*class].is_empty()),
+            };
+
+            match class.and_then(|class| slots.classes[class].pop_front()) {
+                Some((_, replaced)) => Pushed::Replaced(replaced),
+                None => return Pushed::Dropped(message),
+            }
+        };
+
+        let number = slots.pushed;
+        slots.pushed += 1;
+        slots.classes[priority as usize].push_back((number, message));
+        self.available.notify_one();
+        pushed
+    }
+
+    // Waits for the next message, None once the mailbox is closed and drained
+    pub fn pop(&self) -> Option<Message> {
+        let slots = self.slots.lock().unwrap();
+        let mut slots = self.available.wait_while(slots, |slots| slots.len() == 0 && !slots.closed).unwrap();
+        let class = match self.prioritized {
+            true => slots.next(),
+            false => slots.oldest(),
+        };
+        class.and_then(|class| slots.classes[class].pop_front()).map(|(_, message)| message)
+    }
+
+    // Messages already queued are still handed out
+    pub fn close(&self) {
+        self.slots.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+}
+
+// Side of a mailbox the process pushes to, closing it once every handle is dropped so the writer thread stops
+#[derive(Debug)]
+pub struct MailboxSender(Arc<Mailbox>);
+
+impl MailboxSender {
+    pub fn new(mailbox: Arc<Mailbox>) -> MailboxSender {
+        MailboxSender(mailbox)
+    }
+
+    pub fn push(&self, message: Message) -> Pushed {
+        self.0.push(message)
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        self.0.set_capacity(capacity);
+    }
+}
+
+impl Drop for MailboxSender {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Broadcast, PreProposal, Step, Vote};
+
+    fn broadcast(rank: i64) -> Message {
+        Message::Broadcast(Broadcast::new(0, Step::R, BlockHash::from(1), None, rank, None))
+    }
+
+    fn vote() -> Message {
+        Message::Vote(Vote { voter: 0, key: [0; 32], timestamp: 0, hashes: vec![], signature: [0; 64] })
+    }
+
+    #[test]
+    fn full_mailboxes_shed_by_policy() {
+        let filled = |policy| {
+            let mailbox = Mailbox::new(2, policy, false);
+            assert_eq!(mailbox.push(vote()), Pushed::Queued);
+            assert_eq!(mailbox.push(broadcast(0)), Pushed::Queued);
+            mailbox
+        };
+
+        let newest = filled(OverflowPolicy::DropNewest);
+        assert_eq!(newest.push(broadcast(1)), Pushed::Dropped(broadcast(1)));
+
+        let oldest = filled(OverflowPolicy::DropOldest);
+        assert_eq!(oldest.push(broadcast(1)), Pushed::Replaced(vote()));
+        assert_eq!(oldest.pop(), Some(broadcast(0)));
+
+        // The vote goes before any broadcast, after which nothing queued is worth less than a preproposal
+        let lowest = filled(OverflowPolicy::DropLowestPriority);
+        assert_eq!(lowest.push(broadcast(1)), Pushed::Replaced(vote()));
+        let preproposal = Message::PreProposal(PreProposal::new(vec![], 0));
+        assert_eq!(lowest.push(preproposal.clone()), Pushed::Dropped(preproposal));
+
+        lowest.close();
+        assert_eq!(lowest.push(vote()), Pushed::Closed(vote()));
+        assert_eq!((lowest.pop(), lowest.pop(), lowest.pop()), (Some(broadcast(0)), Some(broadcast(1)), None));
+    }
+
+    #[test]
+    fn prioritized_mailboxes_hand_out_steps_first() {
+        let mailbox = Mailbox::new(8, OverflowPolicy::DropNewest, true);
+        let preproposal = Message::PreProposal(PreProposal::new(vec![BlockHash::from(1); 1024], 0));
+        for message in [vote(), preproposal.clone(), broadcast(0), preproposal.clone(), broadcast(1)] {
+            assert_eq!(mailbox.push(message), Pushed::Queued);
+        }
+
+        let popped: Vec<Message> = (0..5).filter_map(|_| mailbox.pop()).collect();
+        assert_eq!(popped, vec![broadcast(0), broadcast(1), preproposal.clone(), preproposal, vote()]);
+    }
+
+    #[test]
+    fn prioritized_mailboxes_hand_out_aged_messages() {
+        let mailbox = Mailbox::new(1024, OverflowPolicy::DropNewest, true);
+        mailbox.push(vote());
+        for rank in 0..MAX_AGE as i64 {
+            mailbox.push(broadcast(rank));
+        }
+        assert_eq!(mailbox.pop(), Some(broadcast(0)));
+
+        // Once more than MAX_AGE messages were pushed after the vote, it goes first
+        mailbox.push(broadcast(MAX_AGE as i64));
+        assert_eq!(mailbox.pop(), Some(vote()));
+        assert_eq!(mailbox.pop(), Some(broadcast(1)));
+    }
+}
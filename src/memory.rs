@@ -0,0 +1,218 @@
+use std::{collections::BTreeMap, mem::size_of, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+use rsnano_core::BlockHash;
+use crate::{Broadcast, CertificateHash, CertificateResponses, CommitRecord, Message, PreProposalHash, Rank, Response, ResponseHash, State, VoteHash};
+
+// Approximate number of bytes a message keeps alive, used to enforce the memory budget
+pub trait MemorySize {
+    fn memory_size(&self) -> usize;
+}
+
+impl MemorySize for Response {
+    fn memory_size(&self) -> usize {
+        // The broadcasts of the states are shared with the broadcasts map, only the pointers are counted
+        // States are stored inline unless a response carries more of them than usual
+        let spilled = if self.state.spilled() { self.state.capacity() * size_of::<State>() } else { 0 };
+
+        size_of::<Response>() + spilled
+    }
+}
+
+impl MemorySize for Broadcast {
+    fn memory_size(&self) -> usize {
+        let certificate = self.previous_step_responses
+            .as_ref()
+            .map_or(0, |responses| {
+                let spilled = if responses.spilled() { responses.capacity() * size_of::<Response>() } else { 0 };
+                let inline = size_of::<CertificateResponses>();
+                let states: usize = responses.iter().map(|r| r.memory_size() - size_of::<Response>()).sum();
+
+                inline + spilled + states
+            });
+
+        size_of::<Broadcast>() + certificate
+    }
+}
+
+// There is no wire encoding yet, so this also stands for the size of a message on the network
+impl MemorySize for Message {
+    fn memory_size(&self) -> usize {
+        let payload = match self {
+            Message::Broadcast(broadcast) => broadcast.memory_size(),
+            Message::Response(response) => response.memory_size(),
+            Message::PreProposal(preproposal) => preproposal.frontiers().len() * size_of::<BlockHash>(),
+            Message::Proposal(proposal) => proposal.preproposals.len() * size_of::<PreProposalHash>(),
+            Message::PreProposalDigest(_, hashes) | Message::GetPreProposals(_, hashes) => hashes.len() * size_of::<PreProposalHash>(),
+            Message::Batch(messages) | Message::Sequenced(_, _, messages) => messages.iter().map(MemorySize::memory_size).sum(),
+            Message::GetResponses(_, hashes) => hashes.len() * size_of::<ResponseHash>(),
+            Message::GetCertificate(..) => size_of::<CertificateHash>(),
+            Message::Certificate(_, responses) => responses.iter().map(MemorySize::memory_size).sum(),
+            Message::Responses(_, responses) => responses.iter().map(MemorySize::memory_size).sum(),
+            Message::Vote(vote) => vote.hashes.len() * size_of::<BlockHash>(),
+            Message::VoteHashes(_, hashes) | Message::GetVotes(_, hashes) => hashes.len() * size_of::<VoteHash>(),
+            Message::PreVote(..) | Message::Announce(..) | Message::GetCertificates(..) | Message::Ack(..) | Message::Hello(_) | Message::Rejoin(_) => 0,
+            Message::Instance(_, message) => message.memory_size(),
+            Message::Commits(_, commits) | Message::Certificates(_, commits) => commits.iter().map(|commit| size_of::<CommitRecord>() + commit.certificate.iter().map(MemorySize::memory_size).sum::<usize>()).sum(),
+        };
+
+        size_of::<Message>() + payload
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryMetrics {
+    held_bytes: AtomicUsize,
+    evictions: AtomicUsize,
+    evicted_bytes: AtomicUsize,
+    // Held by the stores of the run loop, published after each message it handles
+    broadcast_bytes: AtomicUsize,
+    pending_bytes: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryUsage {
+    pub held_bytes: usize,
+    pub evictions: usize,
+    pub evicted_bytes: usize,
+}
+
+// Approximate bytes held by each subsystem of a process, for capacity planning and to spot the one that leaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryBreakdown {
+    pub broadcasts: usize,
+    pub responses: usize,
+    pub pending_responses: usize,
+    pub preproposals: usize,
+    pub commit_log: usize,
+}
+
+impl MemoryBreakdown {
+    pub fn total(&self) -> usize {
+        self.broadcasts + self.responses + self.pending_responses + self.preproposals + self.commit_log
+    }
+
+    pub fn subsystems(&self) -> [(&'static str, usize); 5] {
+        [
+            ("broadcasts", self.broadcasts),
+            ("responses", self.responses),
+            ("pending_responses", self.pending_responses),
+            ("preproposals", self.preproposals),
+            ("commit_log", self.commit_log),
+        ]
+    }
+}
+
+impl MemoryMetrics {
+    pub fn held_by_run_loop(&self, broadcasts: usize, pending: usize) {
+        self.broadcast_bytes.store(broadcasts, Ordering::Relaxed);
+        self.pending_bytes.store(pending, Ordering::Relaxed);
+    }
+
+    // Bytes of the broadcasts map and of the pending responses
+    pub fn run_loop(&self) -> (usize, usize) {
+        (self.broadcast_bytes.load(Ordering::Relaxed), self.pending_bytes.load(Ordering::Relaxed))
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            held_bytes: self.held_bytes.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            evicted_bytes: self.evicted_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Bytes held by the run loop (broadcasts, stored and pending responses) per rank
+#[derive(Debug)]
+pub struct MemoryTracker {
+    budget: Option<usize>,
+    held: BTreeMap<Rank, usize>,
+    total: usize,
+    metrics: Arc<MemoryMetrics>,
+}
+
+impl MemoryTracker {
+    pub fn new(budget: Option<usize>, metrics: Arc<MemoryMetrics>) -> MemoryTracker {
+        MemoryTracker { budget, held: BTreeMap::new(), total: 0, metrics }
+    }
+
+    pub fn add(&mut self, rank: Rank, bytes: usize) {
+        *self.held.entry(rank).or_insert(0) += bytes;
+        self.total += bytes;
+        self.metrics.held_bytes.store(self.total, Ordering::Relaxed);
+    }
+
+    pub fn evict(&mut self, rank: Rank, bytes: usize) {
+        let Some(held) = self.held.get_mut(&rank) else {
+            return;
+        };
+
+        let bytes = bytes.min(*held);
+        *held -= bytes;
+        if *held == 0 {
+            self.held.remove(&rank);
+        }
+        self.record_eviction(bytes);
+    }
+
+    pub fn evict_rank(&mut self, rank: Rank) {
+        if let Some(bytes) = self.held.remove(&rank) {
+            self.record_eviction(bytes);
+        }
+    }
+
+    fn record_eviction(&mut self, bytes: usize) {
+        self.total -= bytes;
+        self.metrics.held_bytes.store(self.total, Ordering::Relaxed);
+        self.metrics.evictions.fetch_add(1, Ordering::Relaxed);
+        self.metrics.evicted_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn over_budget(&self) -> bool {
+        self.budget.is_some_and(|budget| self.total > budget)
+    }
+
+    pub fn lowest_rank(&self) -> Option<Rank> {
+        self.held.keys().next().copied()
+    }
+
+    pub fn highest_rank(&self) -> Option<Rank> {
+        self.held.keys().next_back().copied()
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_bytes_per_rank() {
+        let metrics = Arc::new(MemoryMetrics::default());
+        let mut tracker = MemoryTracker::new(Some(100), metrics.clone());
+
+        tracker.add(0, 60);
+        tracker.add(1, 30);
+        assert!(!tracker.over_budget());
+
+        tracker.add(2, 20);
+        assert!(tracker.over_budget());
+        assert_eq!(tracker.lowest_rank(), Some(0));
+        assert_eq!(tracker.highest_rank(), Some(2));
+
+        tracker.evict_rank(0);
+        assert!(!tracker.over_budget());
+        assert_eq!(tracker.lowest_rank(), Some(1));
+        assert_eq!(metrics.usage(), MemoryUsage { held_bytes: 50, evictions: 1, evicted_bytes: 60 });
+    }
+
+    #[test]
+    fn unbounded_without_budget() {
+        let mut tracker = MemoryTracker::new(None, Arc::new(MemoryMetrics::default()));
+
+        tracker.add(0, usize::MAX / 2);
+        assert!(!tracker.over_budget());
+    }
+}
@@ -0,0 +1,179 @@
+use std::{io, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+use crate::Step;
+
+// Bucket i counts the durations of i significant bits in microseconds, below 2^i µs, the last one also everything longer
+const BUCKETS: usize = 32;
+
+// Lock-free log-scale histogram of durations, recorded by the proposer and read from any thread
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1);
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+            buckets: self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: Duration,
+    pub buckets: Vec<u64>,
+}
+
+impl HistogramSnapshot {
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.sum / self.count as u32)
+    }
+
+    // Upper bound of the bucket holding the q-quantile, so within a factor of two of the exact value
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        self.buckets.iter().enumerate().find_map(|(bucket, &count)| {
+            seen += count;
+            (seen >= rank).then(|| Duration::from_micros(1 << bucket))
+        })
+    }
+}
+
+// Time the proposer waits in each step for 2f+1 responses, and from proposing to committing
+#[derive(Debug, Default)]
+pub struct LatencyMetrics {
+    r: Histogram,
+    a: Histogram,
+    b: Histogram,
+    commit: Histogram,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Latencies {
+    pub r: HistogramSnapshot,
+    pub a: HistogramSnapshot,
+    pub b: HistogramSnapshot,
+    pub commit: HistogramSnapshot,
+}
+
+impl LatencyMetrics {
+    pub fn record_step(&self, step: Step, duration: Duration) {
+        match step {
+            Step::R => self.r.record(duration),
+            Step::A => self.a.record(duration),
+            Step::B => self.b.record(duration),
+        }
+    }
+
+    pub fn record_commit(&self, duration: Duration) {
+        self.commit.record(duration);
+    }
+
+    pub fn latencies(&self) -> Latencies {
+        Latencies {
+            r: self.r.snapshot(),
+            a: self.a.snapshot(),
+            b: self.b.snapshot(),
+            commit: self.commit.snapshot(),
+        }
+    }
+}
+
+impl Latencies {
+    pub fn step(&self, step: Step) -> &HistogramSnapshot {
+        match step {
+            Step::R => &self.r,
+            Step::A => &self.a,
+            Step::B => &self.b,
+        }
+    }
+}
+
+// Where the metrics of a process are pushed to, all as gauges: counters are cumulative and histograms summarized
+pub trait MetricsSink: Send {
+    fn gauge(&mut self, name: &str, value: f64);
+
+    // Sends what was buffered since the last flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Keeps the gauges in memory, for tests and for callers formatting them themselves
+impl MetricsSink for Vec<(String, f64)> {
+    fn gauge(&mut self, name: &str, value: f64) {
+        self.push((name.to_string(), value));
+    }
+}
+
+impl HistogramSnapshot {
+    pub fn report(&self, name: &str, sink: &mut dyn MetricsSink) {
+        let millis = |duration: Option<Duration>| duration.map_or(0.0, |duration| duration.as_secs_f64() * 1000.0);
+
+        sink.gauge(&format!("{}.count", name), self.count as f64);
+        sink.gauge(&format!("{}.mean_ms", name), millis(self.mean()));
+        sink.gauge(&format!("{}.p50_ms", name), millis(self.quantile(0.5)));
+        sink.gauge(&format!("{}.p99_ms", name), millis(self.quantile(0.99)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantiles_are_bucket_upper_bounds() {
+        let histogram = Histogram::default();
+
+        for micros in [0, 3, 5, 6, 7, 900] {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 6);
+        assert_eq!(snapshot.mean(), Some(Duration::from_nanos(921_000 / 6)));
+        assert_eq!(snapshot.quantile(0.0), Some(Duration::from_micros(1)));
+        assert_eq!(snapshot.quantile(0.5), Some(Duration::from_micros(8)));
+        assert_eq!(snapshot.quantile(1.0), Some(Duration::from_micros(1024)));
+        assert_eq!(HistogramSnapshot::default().quantile(0.5), None);
+    }
+
+    #[test]
+    fn longest_durations_share_the_last_bucket() {
+        let histogram = Histogram::default();
+
+        histogram.record(Duration::from_secs(3600));
+        histogram.record(Duration::MAX);
+
+        assert_eq!(histogram.snapshot().buckets[BUCKETS - 1], 2);
+    }
+
+    #[test]
+    fn steps_are_recorded_separately() {
+        let metrics = LatencyMetrics::default();
+
+        metrics.record_step(Step::A, Duration::from_millis(2));
+        metrics.record_commit(Duration::from_millis(5));
+
+        let latencies = metrics.latencies();
+        assert_eq!(latencies.step(Step::A).count, 1);
+        assert_eq!(latencies.step(Step::R).count, 0);
+        assert_eq!(latencies.commit.sum, Duration::from_millis(5));
+    }
+}
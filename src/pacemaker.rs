@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use crate::{Broadcast, CertificateResponses, RValue, Rank, Step};
+
+// Highest rank a process has seen a reliable R broadcast of. The certificate of that broadcast, the 2f+1 B answers
+// of the rank before adopting its value, lets any process start that rank right away (Line 17) instead of going
+// through the ranks before it, and is sent to the processes found lagging too far behind
+#[derive(Debug, Default)]
+pub struct Pacemaker {
+    highest: Option<Arc<Broadcast>>,
+}
+
+impl Pacemaker {
+    // The broadcast must have been reliably checked, returns true if it is the highest so far
+    pub fn observe(&mut self, broadcast: &Broadcast) -> bool {
+        if broadcast.step != Step::R || broadcast.previous_step_responses.is_none() || broadcast.rank <= self.highest_rank() {
+            return false;
+        }
+
+        self.highest = Some(Arc::new(broadcast.clone()));
+        true
+    }
+
+    pub fn highest_rank(&self) -> Rank {
+        self.highest.as_ref().map_or(0, |broadcast| broadcast.rank)
+    }
+
+    pub fn highest(&self) -> Option<&Arc<Broadcast>> {
+        self.highest.as_ref()
+    }
+
+    // Value and certificate to start the highest rank with, if it is above the rank a process is about to start
+    pub fn catch_up(&self, rank: Rank) -> Option<(RValue, CertificateResponses)> {
+        let highest = self.highest.as_ref().filter(|highest| highest.rank > rank)?;
+        let certificate = highest.previous_step_responses.as_ref()?;
+
+        Some((RValue::new(highest.rank, highest.value), CertificateResponses::clone(certificate)))
+    }
+
+    // Whether a process broadcasting in this rank is more than `gap` ranks behind
+    pub fn lagging(&self, rank: Rank, gap: Rank) -> bool {
+        rank + gap < self.highest_rank()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+
+    fn r_broadcast(rank: Rank, value: u64) -> Broadcast {
+        Broadcast::new(1, Step::R, BlockHash::from(value), None, rank, Some(CertificateResponses::new()))
+    }
+
+    #[test]
+    fn keeps_the_highest_certified_rank() {
+        let mut pacemaker = Pacemaker::default();
+
+        assert!(!pacemaker.observe(&Broadcast::new(1, Step::R, BlockHash::from(1), None, 0, None)));
+        assert!(pacemaker.observe(&r_broadcast(3, 1)));
+        assert!(!pacemaker.observe(&r_broadcast(2, 2)));
+        assert!(!pacemaker.observe(&Broadcast::new(1, Step::A, BlockHash::from(3), None, 5, Some(CertificateResponses::new()))));
+
+        assert_eq!(pacemaker.highest_rank(), 3);
+        assert_eq!(pacemaker.catch_up(1).map(|(r_value, _)| r_value), Some(RValue::new(3, BlockHash::from(1))));
+        assert_eq!(pacemaker.catch_up(3), None);
+        assert!(pacemaker.lagging(0, 2));
+        assert!(!pacemaker.lagging(1, 2));
+    }
+}
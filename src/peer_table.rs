@@ -0,0 +1,270 @@
+use std::{collections::{BTreeMap, VecDeque}, sync::{Arc, Mutex, RwLock}, time::{Duration, Instant}};
+use crate::{Broadcast, Hello, Id, Identities, Mismatch, ProposalHash, Rejoin, Suspicion, Telemetry};
+
+// Equivocations kept as evidence, the oldest is dropped first
+const MAX_EQUIVOCATIONS: usize = 64;
+
+// Two broadcasts of the same sender, step and rank with different hashes, both signed by the key the committee has on
+// record for the sender, kept by header. Only `new` makes one, so any equivocation is evidence against its sender
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Equivocation {
+    first: Broadcast,
+    second: Broadcast,
+}
+
+impl Equivocation {
+    // None unless the broadcasts conflict and the identities verify both, see Identities::verify_broadcast
+    pub fn new(first: &Broadcast, second: &Broadcast, identities: &Identities) -> Option<Equivocation> {
+        let conflicting = first.sender == second.sender && first.step == second.step && first.rank == second.rank && first.hash_value() != second.hash_value();
+        (conflicting && identities.verify_broadcast(first) && identities.verify_broadcast(second)).then(|| Equivocation { first: first.header(), second: second.header() })
+    }
+
+    pub fn sender(&self) -> Id {
+        self.first.sender
+    }
+
+    pub fn broadcasts(&self) -> (&Broadcast, &Broadcast) {
+        (&self.first, &self.second)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PeerState {
+    #[default]
+    Connected,
+    // Until it is added again
+    Disconnected,
+    Banned { until: Instant },
+}
+
+impl PeerState {
+    pub fn name(self) -> &'static str {
+        match self {
+            PeerState::Connected => "connected",
+            PeerState::Disconnected => "disconnected",
+            PeerState::Banned { .. } => "banned",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerEntry {
+    // Where the peer can be reached, as given by the operator
+    pub address: Option<String>,
+    pub state: PeerState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BanError {
+    // No equivocation of the peer was received
+    NoEvidence,
+    // The evidence is an equivocation of another peer
+    InvalidEvidence,
+}
+
+// Peers of the transport as the operator left them, shared by the queues of every instance sending over it
+// Messages to and from a disconnected peer, or a banned one until its ban expires, are dropped. Peers are connected
+// until told otherwise
+#[derive(Debug, Default)]
+pub struct PeerTable {
+    peers: RwLock<BTreeMap<Id, PeerEntry>>,
+    equivocations: Mutex<VecDeque<Equivocation>>,
+    // Latest request of each banned peer to be taken back, until the committee commits its reconfiguration
+    held: Mutex<BTreeMap<Id, Rejoin>>,
+    telemetry: RwLock<BTreeMap<Id, Telemetry>>,
+    // Last hello sent to the peers, by any instance
+    said: Mutex<Option<Hello>>,
+    suspicion: Arc<Suspicion>,
+}
+
+impl PeerTable {
+    // Known at this address from now on, which also lifts a disconnection or a ban
+    pub fn add(&self, peer: Id, address: String) {
+        *self.peers.write().unwrap().entry(peer).or_default() = PeerEntry { address: Some(address), state: PeerState::Connected };
+    }
+
+    pub fn disconnect(&self, peer: Id) {
+        self.peers.write().unwrap().entry(peer).or_default().state = PeerState::Disconnected;
+    }
+
+    pub fn ban(&self, peer: Id, duration: Duration, evidence: &Equivocation) -> Result<(), BanError> {
+        if evidence.sender() != peer {
+            return Err(BanError::InvalidEvidence);
+        }
+        self.peers.write().unwrap().entry(peer).or_default().state = PeerState::Banned { until: Instant::now() + duration };
+        Ok(())
+    }
+
+    // Lifts the ban of a peer the committee took back, see Rejoin. False if it was not banned
+    pub fn readmit(&self, peer: Id) -> bool {
+        if !matches!(self.state(peer), PeerState::Banned { .. }) {
+            return false;
+        }
+        self.peers.write().unwrap().entry(peer).or_default().state = PeerState::Connected;
+        self.held.lock().unwrap().remove(&peer);
+        true
+    }
+
+    // Keeps the request of a banned peer for the commit of its reconfiguration, replacing an earlier one. The peer is
+    // no more admitted for it. False if the peer is not banned
+    pub fn hold(&self, rejoin: Rejoin) -> bool {
+        if !matches!(self.state(rejoin.sender), PeerState::Banned { .. }) {
+            return false;
+        }
+        self.held.lock().unwrap().insert(rejoin.sender, rejoin);
+        true
+    }
+
+    // The held request the committed value is the reconfiguration of
+    pub fn take_held(&self, value: &ProposalHash) -> Option<Rejoin> {
+        let mut held = self.held.lock().unwrap();
+        let peer = held.values().find(|rejoin| rejoin.reconfiguration() == *value)?.sender;
+        held.remove(&peer)
+    }
+
+    pub fn admits(&self, peer: Id) -> bool {
+        self.state(peer) == PeerState::Connected
+    }
+
+    // Expired bans are reported as connected
+    pub fn state(&self, peer: Id) -> PeerState {
+        match self.peers.read().unwrap().get(&peer).map(|entry| entry.state) {
+            Some(PeerState::Banned { until }) if until <= Instant::now() => PeerState::Connected,
+            state => state.unwrap_or_default(),
+        }
+    }
+
+    // Peers the operator changed, by id
+    pub fn entries(&self) -> BTreeMap<Id, PeerEntry> {
+        let peers = self.peers.read().unwrap().clone();
+        peers.into_iter().map(|(peer, entry)| (peer, PeerEntry { state: self.state(peer), ..entry })).collect()
+    }
+
+    pub fn equivocation(&self, evidence: Equivocation) {
+        let mut equivocations = self.equivocations.lock().unwrap();
+        equivocations.push_back(evidence);
+        if equivocations.len() > MAX_EQUIVOCATIONS {
+            equivocations.pop_front();
+        }
+    }
+
+    // Records the hello of the peer, and returns what it differs from this process in
+    pub fn hello(&self, hello: &Hello, own: &Hello) -> Vec<Mismatch> {
+        let telemetry = Telemetry::new(hello, own);
+        let mismatches = telemetry.mismatches.clone();
+        self.telemetry.write().unwrap().insert(hello.sender, telemetry);
+        mismatches
+    }
+
+    // Whether the hello is news to some of the peers, in which case it is taken as sent: it is not the last one sent,
+    // or some peer never said hello back
+    pub fn say_hello(&self, own: &Hello, peers: usize) -> bool {
+        let heard = self.telemetry.read().unwrap();
+        let mut said = self.said.lock().unwrap();
+        let due = said.as_ref() != Some(own) || (0..peers as Id).any(|peer| peer != own.sender && !heard.contains_key(&peer));
+        *said = Some(own.clone());
+        due
+    }
+
+    // Latest hello of each peer that sent one
+    pub fn telemetry(&self) -> BTreeMap<Id, Telemetry> {
+        self.telemetry.read().unwrap().clone()
+    }
+
+    // Offenses of the peers noticed by any instance
+    pub fn suspicion(&self) -> Arc<Suspicion> {
+        Arc::clone(&self.suspicion)
+    }
+
+    // Latest equivocation of the peer received
+    pub fn evidence(&self, peer: Id) -> Option<Equivocation> {
+        self.equivocations.lock().unwrap().iter().rev().find(|evidence| evidence.sender() == peer).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Features, RejoinGrounds, Step};
+
+    #[test]
+    fn equivocations_are_signed_by_the_key_of_their_sender() {
+        let key = |id: Id| SigningKey::from_bytes(&[id as u8 + 1; 32]);
+        let identities = Identities::verifying((0..4).map(|id| (id, key(id).verifying_key())).collect());
+        let signed = |signer: Id, step: Step, value: u64| {
+            let signing = Identities::default();
+            signing.set(key(signer), identities.committee());
+            signing.sign_broadcast(Broadcast::new(1, step, BlockHash::from(value), None, 1, None))
+        };
+
+        let first = signed(1, Step::R, 1);
+        assert_eq!(Equivocation::new(&first, &signed(1, Step::R, 2), &identities).map(|evidence| evidence.sender()), Some(1));
+        // The same broadcast twice, one of another step, or one unsigned or signed by another member
+        assert_eq!(Equivocation::new(&first, &signed(1, Step::R, 1), &identities), None);
+        assert_eq!(Equivocation::new(&first, &signed(1, Step::A, 2), &identities), None);
+        assert_eq!(Equivocation::new(&first, &Broadcast { signature: None, ..signed(1, Step::R, 2) }, &identities), None);
+        assert_eq!(Equivocation::new(&first, &signed(2, Step::R, 2), &identities), None);
+        // Without the key on record
+        assert_eq!(Equivocation::new(&first, &signed(1, Step::R, 2), &Identities::default()), None);
+    }
+
+    #[test]
+    fn banned_and_disconnected_peers_are_not_admitted() {
+        let table = PeerTable::default();
+        let key = |id: Id| SigningKey::from_bytes(&[id as u8 + 1; 32]);
+        let identities = Identities::verifying((0..4).map(|id| (id, key(id).verifying_key())).collect());
+        let equivocation = |sender: Id| {
+            let signing = Identities::default();
+            signing.set(key(sender), identities.committee());
+            let broadcast = |value| signing.sign_broadcast(Broadcast::new(sender, Step::R, BlockHash::from(value), None, 1, None));
+            Equivocation::new(&broadcast(1), &broadcast(2), &identities).unwrap()
+        };
+
+        assert!(table.admits(1));
+        assert_eq!(table.evidence(1), None);
+        assert_eq!(table.ban(1, Duration::from_secs(60), &equivocation(2)), Err(BanError::InvalidEvidence));
+
+        table.equivocation(equivocation(1));
+        let evidence = table.evidence(1).unwrap();
+        assert_eq!(table.ban(1, Duration::from_secs(60), &evidence), Ok(()));
+        assert!(!table.admits(1));
+        assert!(table.readmit(1));
+        assert!(table.admits(1));
+        assert!(!table.readmit(1));
+        assert_eq!(table.ban(1, Duration::ZERO, &evidence), Ok(()));
+        assert!(table.admits(1));
+
+        // Requests to be taken back are held for banned peers only, and taken by the value of their reconfiguration
+        let rejoin = Rejoin::new(1, RejoinGrounds::Unban, 5, &key(1));
+        assert!(!table.hold(rejoin.clone()));
+        table.ban(1, Duration::from_secs(60), &evidence).unwrap();
+        assert!(table.hold(rejoin.clone()));
+        assert!(!table.admits(1));
+        assert_eq!(table.take_held(&Rejoin::value(2, RejoinGrounds::Unban)), None);
+        assert_eq!(table.take_held(&rejoin.reconfiguration()), Some(rejoin.clone()));
+        assert_eq!(table.take_held(&rejoin.reconfiguration()), None);
+
+        table.disconnect(2);
+        assert_eq!(table.state(2), PeerState::Disconnected);
+        table.add(2, "10.0.0.2:7075".to_string());
+        assert_eq!(table.entries()[&2], PeerEntry { address: Some("10.0.0.2:7075".to_string()), state: PeerState::Connected });
+    }
+
+    #[test]
+    fn hellos_are_only_sent_again_when_news_to_some_peer() {
+        let table = PeerTable::default();
+        let own = Hello::new(0, Features::default(), BlockHash::from(1));
+
+        assert!(table.say_hello(&own, 3));
+        assert!(table.say_hello(&own, 3));
+        assert_eq!(table.hello(&Hello::new(1, Features::LAZY_CERTIFICATES, BlockHash::from(1)).answer(), &own), vec![Mismatch::Features]);
+        assert_eq!(table.hello(&Hello::new(2, Features::default(), BlockHash::from(1)), &own), vec![]);
+        assert!(!table.say_hello(&own, 3));
+
+        let rekeyed = Hello::new(0, Features::default(), BlockHash::from(2));
+        assert!(table.say_hello(&rekeyed, 3));
+        assert_eq!(table.telemetry()[&1].mismatches, vec![Mismatch::Features]);
+    }
+}
@@ -0,0 +1,194 @@
+use std::{sync::{mpsc::Sender, Arc}, thread};
+use log::{warn, Level};
+use crate::{Id, LogSampler, Mailbox, MailboxSender, MemorySize, Message, OverflowPolicy, PeerAccounting, PeerTable, Priority, Pushed};
+
+static DROPPED_MESSAGES: LogSampler = LogSampler::new(100);
+
+#[derive(Debug, Clone)]
+enum PeerQueue {
+    // Sent on the calling thread
+    Direct(Sender<Message>),
+    // Sent by the writer thread of the peer
+    Queued(Arc<MailboxSender>),
+}
+
+// Outbound messages of a process, one queue per peer
+// Each queued peer has its own writer thread, so a slow peer only fills its own queue instead of
+// stalling the sends to every other peer. Once a peer's queue is full, the overflow policy picks the message dropped,
+// which is counted by priority
+#[derive(Debug, Clone)]
+pub struct PeerQueues {
+    queues: Vec<PeerQueue>,
+    accounting: Arc<PeerAccounting>,
+    // Shared by every clone, whatever instance or accounting it is for
+    table: Arc<PeerTable>,
+    // Messages are wrapped in Message::Instance with it, if any
+    instance: Option<u64>,
+}
+
+impl PeerQueues {
+    // A capacity of 0 sends every message directly on the calling thread
+    // Prioritized queues send the messages completing a step ahead of the ones queued before them, see Mailbox
+    pub fn spawn(senders: Vec<Sender<Message>>, capacity: usize, policy: OverflowPolicy, prioritized: bool) -> PeerQueues {
+        if capacity == 0 {
+            return PeerQueues::direct(senders);
+        }
+
+        let queues = senders
+            .into_iter()
+            .map(|sender| {
+                let mailbox = Arc::new(Mailbox::new(capacity, policy, prioritized));
+                let writer = Arc::clone(&mailbox);
+
+                // Stops once every handle of the queue is dropped or the peer is gone
+                thread::spawn(move || {
+                    while let Some(message) = writer.pop() {
+                        if sender.send(message).is_err() {
+                            writer.close();
+                            break;
+                        }
+                    }
+                });
+
+                PeerQueue::Queued(Arc::new(MailboxSender::new(mailbox)))
+            })
+            .collect();
+
+        PeerQueues::with_queues(queues)
+    }
+
+    pub fn direct(senders: Vec<Sender<Message>>) -> PeerQueues {
+        PeerQueues::with_queues(senders.into_iter().map(PeerQueue::Direct).collect())
+    }
+
+    fn with_queues(queues: Vec<PeerQueue>) -> PeerQueues {
+        PeerQueues { queues, accounting: Arc::default(), table: Arc::default(), instance: None }
+    }
+
+    // Counts sent and dropped messages in the accounting of the process instead of the queues' own
+    pub fn with_accounting(mut self, accounting: Arc<PeerAccounting>) -> PeerQueues {
+        self.accounting = accounting;
+        self
+    }
+
+    // Same queues, for the messages of one consensus instance
+    pub fn for_instance(&self, instance: u64) -> PeerQueues {
+        PeerQueues { instance: Some(instance), ..self.clone() }
+    }
+
+    // Messages to and from the peers disconnected or banned in it are dropped
+    pub fn table(&self) -> Arc<PeerTable> {
+        Arc::clone(&self.table)
+    }
+
+    pub fn send(&self, peer: usize, message: Message) {
+        let Some(queue) = self.queues.get(peer).filter(|_| self.table.admits(peer as Id)) else {
+            return;
+        };
+        let message = match self.instance {
+            Some(instance) => Message::Instance(instance, Box::new(message)),
+            None => message,
+        };
+        let bytes = message.memory_size();
+
+        match queue {
+            PeerQueue::Direct(sender) => match sender.send(message) {
+                Ok(()) => self.accounting.sent(peer as Id, bytes),
+                Err(e) => warn!("Failed to send message: {}", e),
+            },
+            PeerQueue::Queued(queue) => match queue.push(message) {
+                Pushed::Queued => self.accounting.sent(peer as Id, bytes),
+                Pushed::Replaced(shed) => {
+                    self.accounting.sent(peer as Id, bytes);
+                    self.shed(peer, &shed);
+                }
+                Pushed::Dropped(shed) => self.shed(peer, &shed),
+                Pushed::Closed(_) => warn!("Failed to send message: peer {} disconnected", peer),
+            },
+        }
+    }
+
+    fn shed(&self, peer: usize, message: &Message) {
+        self.accounting.shed(peer as Id, Priority::of(message));
+        crate::sampled!(DROPPED_MESSAGES, Level::Warn, "Outbound queue of peer {} is full, dropping a {} message", peer, Priority::of(message).name());
+    }
+
+    pub fn send_to(&self, peer: Id, message: Message) {
+        if let Ok(peer) = usize::try_from(peer) {
+            self.send(peer, message);
+        }
+    }
+
+    pub fn send_all(&self, message: &Message) {
+        for peer in 0..self.queues.len() {
+            self.send(peer, message.clone());
+        }
+    }
+
+    // Queues spawned with a capacity of 0 stay direct
+    pub fn set_capacity(&self, capacity: usize) {
+        for queue in &self.queues {
+            if let PeerQueue::Queued(queue) = queue {
+                queue.set_capacity(capacity);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    // Number of messages dropped because the queue of each peer was full
+    pub fn dropped(&self) -> Vec<usize> {
+        (0..self.queues.len()).map(|peer| self.accounting.peer(peer as Id).rate_limited).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc::channel, time::Duration};
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Broadcast, Step};
+
+    fn message(rank: i64) -> Message {
+        Message::Broadcast(Broadcast::new(0, Step::R, BlockHash::from(1), None, rank, None))
+    }
+
+    #[test]
+    fn writer_threads_forward_in_order() {
+        let (sender1, receiver1) = channel();
+        let (sender2, receiver2) = channel();
+        let peers = PeerQueues::spawn(vec![sender1, sender2], 4, OverflowPolicy::DropNewest, false);
+
+        peers.send_all(&message(0));
+        peers.send_to(1, message(1));
+
+        assert_eq!(receiver1.recv_timeout(Duration::from_secs(5)).unwrap(), message(0));
+        assert_eq!(receiver2.recv_timeout(Duration::from_secs(5)).unwrap(), message(0));
+        assert_eq!(receiver2.recv_timeout(Duration::from_secs(5)).unwrap(), message(1));
+        assert_eq!(peers.dropped(), vec![0, 0]);
+    }
+
+    #[test]
+    fn full_queue_drops_only_for_that_peer() {
+        let (sender, receiver) = channel();
+        let queue = MailboxSender::new(Arc::new(Mailbox::new(1, OverflowPolicy::DropNewest, false)));
+        // Peer 0 is never drained, peer 1 is forwarded directly
+        let peers = PeerQueues::with_queues(vec![PeerQueue::Queued(Arc::new(queue)), PeerQueue::Direct(sender)]);
+
+        for rank in 0..3 {
+            peers.send_all(&message(rank));
+        }
+
+        assert_eq!(peers.dropped(), vec![2, 0]);
+        assert_eq!(peers.accounting.peer(0).shed[Priority::Step as usize], 2);
+        assert_eq!(receiver.try_iter().count(), 3);
+        assert_eq!(peers.accounting.peer(0).sent_messages, 1);
+        assert_eq!(peers.accounting.peer(1).sent_bytes, 3 * message(0).memory_size());
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::{BroadcastHash, Id, MemorySize, Rank, Response, ResponseHash};
+
+// Responses waiting for 2f+1 answers justified by the same broadcasts, keyed by the hashes of those broadcasts
+// The keys come from the senders, so a sender can make up as many as it likes: past the capacity, the lowest-rank
+// response of the sender holding the most is evicted, which leaves the few pending responses of correct senders alone
+#[derive(Debug, Default)]
+pub struct PendingResponses {
+    responses: HashMap<BTreeSet<BroadcastHash>, HashSet<Response>>,
+    // Where the pending responses of each sender are, lowest rank first
+    senders: BTreeMap<Id, BTreeSet<(Rank, BTreeSet<BroadcastHash>, ResponseHash)>>,
+    // Pending responses justified by each broadcast, which must not be dropped while they wait
+    references: HashMap<BroadcastHash, usize>,
+    len: usize,
+    // Of the pending responses
+    bytes: usize,
+    capacity: Option<usize>,
+}
+
+impl PendingResponses {
+    pub fn new(capacity: Option<usize>) -> PendingResponses {
+        PendingResponses { capacity, ..PendingResponses::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, broadcasts: &BTreeSet<BroadcastHash>) -> Option<&HashSet<Response>> {
+        self.responses.get(broadcasts)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&BTreeSet<BroadcastHash>, &HashSet<Response>)> {
+        self.responses.iter()
+    }
+
+    // Returns false for a response that was already pending
+    pub fn insert(&mut self, broadcasts: BTreeSet<BroadcastHash>, response: Response) -> bool {
+        let index = (response.rank, broadcasts.clone(), response.hash_value());
+        let (sender, size) = (response.sender, response.memory_size());
+
+        if !self.responses.entry(broadcasts).or_default().insert(response) {
+            return false;
+        }
+        for hash in &index.1 {
+            *self.references.entry(*hash).or_default() += 1;
+        }
+        self.senders.entry(sender).or_default().insert(index);
+        self.len += 1;
+        self.bytes += size;
+        true
+    }
+
+    pub fn is_referenced(&self, broadcast: BroadcastHash) -> bool {
+        self.references.contains_key(&broadcast)
+    }
+
+    // Responses evicted to get back under the capacity
+    pub fn evict_over_capacity(&mut self) -> Vec<Response> {
+        let mut evicted = Vec::new();
+
+        while self.capacity.is_some_and(|capacity| self.len > capacity) {
+            let Some(sender) = self.senders.iter().max_by_key(|(_, pending)| pending.len()).map(|(sender, _)| *sender) else {
+                break;
+            };
+            let (_, broadcasts, hash) = self.senders.get_mut(&sender).and_then(|pending| pending.pop_first()).unwrap();
+
+            evicted.extend(self.remove(sender, &broadcasts, hash));
+        }
+        evicted
+    }
+
+    // Removes every group holding a response of the rank
+    pub fn evict_rank(&mut self, rank: Rank) {
+        let keys: Vec<_> = self.responses
+            .iter()
+            .filter(|(_, pending)| pending.iter().any(|response| response.rank == rank))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in keys {
+            self.remove_group(&key);
+        }
+    }
+
+    // Removes the groups below the rank that reached a quorum, whose responses are in the response store already,
+    // and returns their responses
+    pub fn evict_quorums_below(&mut self, rank: Rank, threshold: usize) -> Vec<Response> {
+        let keys: Vec<_> = self.responses
+            .iter()
+            .filter(|(_, pending)| pending.len() >= threshold && pending.iter().all(|response| response.rank < rank))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        keys.into_iter().filter_map(|key| self.remove_group(&key)).flatten().collect()
+    }
+
+    // Removes the group furthest from a quorum
+    pub fn evict_smallest(&mut self) -> Option<HashSet<Response>> {
+        let key = self.responses.iter().min_by_key(|(_, pending)| pending.len()).map(|(key, _)| key.clone())?;
+        self.remove_group(&key)
+    }
+
+    fn remove(&mut self, sender: Id, broadcasts: &BTreeSet<BroadcastHash>, hash: ResponseHash) -> Option<Response> {
+        let group = self.responses.get_mut(broadcasts)?;
+        let response = group.iter().find(|response| response.sender == sender && response.hash_value() == hash)?.clone();
+
+        group.remove(&response);
+        if group.is_empty() {
+            self.responses.remove(broadcasts);
+        }
+        self.release(broadcasts, 1);
+        if self.senders.get(&sender).is_some_and(|pending| pending.is_empty()) {
+            self.senders.remove(&sender);
+        }
+        self.len -= 1;
+        self.bytes -= response.memory_size();
+        Some(response)
+    }
+
+    fn remove_group(&mut self, broadcasts: &BTreeSet<BroadcastHash>) -> Option<HashSet<Response>> {
+        let group = self.responses.remove(broadcasts)?;
+
+        for response in &group {
+            let index = (response.rank, broadcasts.clone(), response.hash_value());
+
+            if let Some(pending) = self.senders.get_mut(&response.sender) {
+                pending.remove(&index);
+                if pending.is_empty() {
+                    self.senders.remove(&response.sender);
+                }
+            }
+        }
+        self.release(broadcasts, group.len());
+        self.len -= group.len();
+        self.bytes -= group.iter().map(MemorySize::memory_size).sum::<usize>();
+        Some(group)
+    }
+
+    fn release(&mut self, broadcasts: &BTreeSet<BroadcastHash>, count: usize) {
+        for hash in broadcasts {
+            if let Some(references) = self.references.get_mut(hash) {
+                *references -= count;
+                if *references == 0 {
+                    self.references.remove(hash);
+                }
+            }
+        }
+    }
+}
+
+impl MemorySize for PendingResponses {
+    fn memory_size(&self) -> usize {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Step;
+
+    fn response(sender: Id, rank: Rank) -> Response {
+        Response::new(sender, Step::R, rank, vec![])
+    }
+
+    fn key(hash: u64) -> BTreeSet<BroadcastHash> {
+        BTreeSet::from([hash.into()])
+    }
+
+    #[test]
+    fn evicts_the_lowest_ranks_of_the_flooding_sender() {
+        let mut pending = PendingResponses::new(Some(4));
+
+        assert!(pending.insert(key(1), response(0, 0)));
+        assert!(pending.insert(key(1), response(1, 0)));
+        assert!(!pending.insert(key(1), response(1, 0)));
+        for rank in [5, 3, 4] {
+            pending.insert(key(100 + rank as u64), response(3, rank));
+        }
+
+        let evicted = pending.evict_over_capacity();
+        assert_eq!(evicted, vec![response(3, 3)]);
+        assert_eq!(pending.len(), 4);
+        assert_eq!(pending.get(&key(1)).map(HashSet::len), Some(2));
+        assert!(pending.get(&key(103)).is_none());
+    }
+
+    #[test]
+    fn removing_groups_keeps_the_senders_index() {
+        let mut pending = PendingResponses::new(Some(2));
+
+        pending.insert(key(1), response(0, 0));
+        pending.insert(key(2), response(0, 1));
+        pending.insert(key(2), response(1, 1));
+        pending.evict_rank(1);
+        assert_eq!(pending.len(), 1);
+
+        pending.insert(key(3), response(2, 2));
+        pending.insert(key(3), response(1, 2));
+        assert_eq!(pending.evict_smallest().map(|group| group.len()), Some(1));
+        assert_eq!(pending.evict_over_capacity(), vec![]);
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending.memory_size(), 2 * std::mem::size_of::<Response>());
+    }
+
+    #[test]
+    fn quorums_below_the_horizon_release_their_broadcasts() {
+        let mut pending = PendingResponses::new(None);
+
+        for sender in 0..3 {
+            pending.insert(key(1), response(sender, 0));
+        }
+        pending.insert(key(2), response(0, 0));
+        pending.insert(key(3), response(0, 4));
+        assert!(pending.is_referenced(1.into()));
+
+        assert_eq!(pending.evict_quorums_below(4, 3).len(), 3);
+        assert!(!pending.is_referenced(1.into()));
+        // Still waiting for a quorum
+        assert!(pending.is_referenced(2.into()));
+        assert_eq!(pending.len(), 2);
+
+        pending.evict_rank(0);
+        assert!(!pending.is_referenced(2.into()));
+        assert!(pending.is_referenced(3.into()));
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Mutex};
+use crate::Message;
+
+// Buffers larger than this are dropped instead of being kept in the pool
+const MAX_BUFFER_CAPACITY: usize = 4096;
+
+// Batch buffers travel from the outbox of a process to the run loop of its peers, which hands them back once unbatched
+pub static MESSAGE_BUFFERS: BufferPool<Message> = BufferPool::new(1024);
+
+// Recycles emptied Vecs so that hot paths reuse their allocations across steps and instances
+#[derive(Debug)]
+pub struct BufferPool<T> {
+    buffers: Mutex<Vec<Vec<T>>>,
+    max_buffers: usize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl PoolStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+impl<T> BufferPool<T> {
+    pub const fn new(max_buffers: usize) -> BufferPool<T> {
+        BufferPool {
+            buffers: Mutex::new(Vec::new()),
+            max_buffers,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    // An empty buffer, reusing a pooled allocation if there is one
+    pub fn take(&self) -> Vec<T> {
+        match self.buffers.lock().unwrap().pop() {
+            Some(buffer) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn give(&self, mut buffer: Vec<T>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_BUFFER_CAPACITY {
+            return;
+        }
+
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_returned_buffers() {
+        let pool: BufferPool<u64> = BufferPool::new(1);
+
+        let mut buffer = pool.take();
+        buffer.extend([1, 2, 3]);
+        let capacity = buffer.capacity();
+        pool.give(buffer);
+        // The pool is full, this one is dropped
+        pool.give(vec![4]);
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), capacity);
+        assert!(pool.take().capacity() == 0);
+        assert_eq!(pool.stats(), PoolStats { hits: 1, misses: 2 });
+        assert!((pool.stats().hit_rate() - 1.0 / 3.0).abs() < f64::EPSILON);
+    }
+}
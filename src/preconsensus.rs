@@ -1,44 +1,213 @@
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, hash::{Hash, Hasher}, ops::Bound, sync::OnceLock};
 use rsnano_core::{Blake2HashBuilder, BlockHash};
 use crate::Id;
 
-const FRONTIERS_THRESHOLD: usize = 1000;
 pub type ProposalHash = BlockHash;
 pub type PreProposalHash = BlockHash;
 
 // For a preproposal to be valid:
-// - The length must be equal to FRONTIERS_THRESHOLD
+// - The length must be equal to the frontiers threshold (not checked yet)
 // - It must contain only valid final voted blocks, which means each block must have received at least 2f+1 votes
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Default)]
+// The frontiers are kept sorted and hashed on the first call to hash() after they changed, so that building a
+// preproposal one frontier at a time hashes it once rather than on every insert
+#[derive(Debug, Clone)]
 pub struct PreProposal {
-    pub frontiers: Vec<BlockHash>,
+    frontiers: BTreeSet<BlockHash>,
     pub sender: Id, 
-    pub hash: PreProposalHash
+    hash: OnceLock<PreProposalHash>
 }
 
-impl PreProposal {
-    pub fn new(frontiers: Vec<BlockHash>, sender: Id) -> PreProposal {
-        let mut hasher = Blake2HashBuilder::new();
-        for frontier in &frontiers {
-            hasher = hasher.update(frontier.as_bytes());
+// The digest follows from the frontiers, whether it was computed yet or not
+impl PartialEq for PreProposal {
+    fn eq(&self, other: &Self) -> bool {
+        self.frontiers == other.frontiers && self.sender == other.sender
+    }
+}
+
+impl Eq for PreProposal {}
+
+impl Hash for PreProposal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.frontiers.hash(state);
+        self.sender.hash(state);
+    }
+}
+
+// Frontiers in hash order from a cursor on, with the cursor to pass for the next page
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FrontierPage {
+    pub frontiers: Vec<BlockHash>,
+    // Last frontier of the page, None once there are no more
+    pub next: Option<BlockHash>,
+}
+
+impl FrontierPage {
+    // Takes up to `limit` of the frontiers, which must come in order
+    fn collect(mut frontiers: impl Iterator<Item = BlockHash>, limit: usize) -> FrontierPage {
+        let page: Vec<BlockHash> = frontiers.by_ref().take(limit.max(1)).collect();
+        let next = page.last().copied().filter(|_| frontiers.next().is_some());
+        FrontierPage { frontiers: page, next }
+    }
+}
+
+// Frontiers after the cursor, all of them without one
+fn frontiers_after(frontiers: &BTreeSet<BlockHash>, after: Option<BlockHash>) -> impl Iterator<Item = BlockHash> + '_ {
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    frontiers.range((start, Bound::Unbounded)).copied()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontierDecodeError {
+    Truncated,
+    // A frontier shares more bytes with the one before it than a different hash can
+    InvalidPrefix,
+    // Frontiers must be encoded in increasing order, each once
+    Unordered,
+    TrailingBytes,
+}
+
+// Frontier sets in sorted order, each frontier as the number of leading bytes it shares with the previous one followed
+// by the rest of its bytes, after the number of frontiers as a LEB128 varint
+// Ledger frontiers are uniformly distributed hashes, so consecutive ones only share about log256(n) leading bytes: a
+// percent or two of the set for ledger-sized ones, see `cargo bench --bench frontiers` for the sizes. Sets of hashes
+// clustered in a range, like the ones of the tests, shrink to about a quarter
+pub fn encode_frontiers(frontiers: &BTreeSet<BlockHash>) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(frontiers.len() * 32);
+    let mut count = frontiers.len() as u64;
+    while count >= 0x80 {
+        encoded.push(count as u8 | 0x80);
+        count >>= 7;
+    }
+    encoded.push(count as u8);
+
+    let mut previous: Option<&BlockHash> = None;
+    for frontier in frontiers {
+        let bytes = frontier.as_bytes();
+        let shared = previous.map_or(0, |previous| previous.as_bytes().iter().zip(bytes).take_while(|(a, b)| a == b).count());
+        encoded.push(shared as u8);
+        encoded.extend_from_slice(&bytes[shared..]);
+        previous = Some(frontier);
+    }
+    encoded
+}
+
+pub fn decode_frontiers(mut encoded: &[u8]) -> Result<BTreeSet<BlockHash>, FrontierDecodeError> {
+    let mut count = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = encoded.split_first().ok_or(FrontierDecodeError::Truncated)?;
+        encoded = rest;
+        count |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            break;
         }
-        let hash = hasher.build();
+    }
 
+    let mut frontiers = BTreeSet::new();
+    let mut previous: Option<[u8; 32]> = None;
+    for _ in 0..count {
+        let (&shared, rest) = encoded.split_first().ok_or(FrontierDecodeError::Truncated)?;
+        let shared = shared as usize;
+        if shared >= 32 || (previous.is_none() && shared > 0) {
+            return Err(FrontierDecodeError::InvalidPrefix);
+        }
+        if rest.len() < 32 - shared {
+            return Err(FrontierDecodeError::Truncated);
+        }
+
+        let mut bytes = previous.unwrap_or([0; 32]);
+        bytes[shared..].copy_from_slice(&rest[..32 - shared]);
+        encoded = &rest[32 - shared..];
+        if previous.is_some_and(|previous| bytes <= previous) {
+            return Err(FrontierDecodeError::Unordered);
+        }
+
+        frontiers.insert(BlockHash::from_bytes(bytes));
+        previous = Some(bytes);
+    }
+
+    if !encoded.is_empty() {
+        return Err(FrontierDecodeError::TrailingBytes);
+    }
+    Ok(frontiers)
+}
+
+impl Default for PreProposal {
+    fn default() -> Self {
+        PreProposal::new(Vec::new(), Id::default())
+    }
+}
+
+impl PreProposal {
+    pub fn new(frontiers: Vec<BlockHash>, sender: Id) -> PreProposal {
         PreProposal {
-            frontiers,
+            frontiers: frontiers.into_iter().collect(),
             sender,
-            hash
+            hash: OnceLock::new()
         }
     }
 
-    pub fn hash(&self) -> BlockHash {
+    fn digest(frontiers: &BTreeSet<BlockHash>) -> PreProposalHash {
         let mut hasher = Blake2HashBuilder::new();
-        let frontiers: BTreeSet<BlockHash> = self.frontiers.iter().cloned().collect();
-        for frontier in &frontiers {
+        for frontier in frontiers {
             hasher = hasher.update(frontier.as_bytes());
         }
         hasher.build()
     }
+
+    pub fn hash(&self) -> BlockHash {
+        *self.hash.get_or_init(|| PreProposal::digest(&self.frontiers))
+    }
+
+    pub fn frontiers(&self) -> &BTreeSet<BlockHash> {
+        &self.frontiers
+    }
+
+    // Returns false if the frontier was already part of the preproposal
+    pub fn insert(&mut self, frontier: BlockHash) -> bool {
+        if !self.frontiers.insert(frontier) {
+            return false;
+        }
+
+        self.hash.take();
+        true
+    }
+
+    pub fn extend(&mut self, frontiers: impl IntoIterator<Item = BlockHash>) {
+        let len = self.frontiers.len();
+        self.frontiers.extend(frontiers);
+
+        if self.frontiers.len() != len {
+            self.hash.take();
+        }
+    }
+
+    // Frontiers in the encoding of encode_frontiers, e.g. to store the preproposal
+    pub fn encoded_frontiers(&self) -> Vec<u8> {
+        encode_frontiers(&self.frontiers)
+    }
+
+    pub fn from_encoded(encoded: &[u8], sender: Id) -> Result<PreProposal, FrontierDecodeError> {
+        Ok(PreProposal { frontiers: decode_frontiers(encoded)?, sender, hash: OnceLock::new() })
+    }
+
+    // Up to `limit` frontiers after the cursor, the first ones without it
+    pub fn frontier_page(&self, after: Option<BlockHash>, limit: usize) -> FrontierPage {
+        FrontierPage::collect(frontiers_after(&self.frontiers, after), limit)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frontiers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frontiers.is_empty()
+    }
+}
+
+// The bottom value, decided in an instance where no process had anything to propose: the hash of the proposal of no
+// preproposals. It loses every comparison to another value of the same rank, see ValueOrder
+pub fn bottom_value() -> ProposalHash {
+    Proposal::empty(Id::default()).hash
 }
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Default)]
@@ -65,6 +234,11 @@ impl Proposal {
         }
     }
 
+    // Proposal of a process whose preproposals all came without frontiers, see bottom_value
+    pub fn empty(sender: Id) -> Proposal {
+        Proposal::new(Vec::new(), sender)
+    }
+
     pub fn create_proposal(preproposals: Vec<PreProposal>, sender: Id) -> Proposal {
         let mut hasher = Blake2HashBuilder::new();
         for preproposal in &preproposals {
@@ -89,7 +263,7 @@ impl Proposal {
     }
     
     /// Returns the union of all frontiers from the preproposals included in this proposal
-    fn frontiers(&self, all_preproposals: &[PreProposal], _f: usize) -> Vec<BlockHash> {
+    pub fn frontiers(&self, all_preproposals: &[PreProposal], _f: usize) -> Vec<BlockHash> {
         // Collect all frontiers from the included preproposals
         let mut all_frontiers = BTreeSet::new();
         
@@ -100,7 +274,7 @@ impl Proposal {
             }
             
             // Add each frontier block to the set
-            for frontier in &preproposal.frontiers {
+            for frontier in preproposal.frontiers() {
                 all_frontiers.insert(*frontier);
             }
         }
@@ -108,6 +282,21 @@ impl Proposal {
         // Convert to sorted vector
         all_frontiers.into_iter().collect()
     }
+
+    // A page of frontiers() without building the whole union: only the next `limit` frontiers of each preproposal
+    // after the cursor are merged
+    pub fn frontier_page(&self, all_preproposals: &[PreProposal], after: Option<BlockHash>, limit: usize) -> FrontierPage {
+        let limit = limit.max(1);
+        let mut merged = BTreeSet::new();
+
+        for preproposal in all_preproposals {
+            if self.preproposals.contains(&preproposal.hash()) {
+                merged.extend(frontiers_after(preproposal.frontiers(), after).take(limit.saturating_add(1)));
+            }
+        }
+
+        FrontierPage::collect(merged.into_iter(), limit)
+    }
 }
 
 // Goal: Guarantee that every valid proposal contains all the blocks that were committed (final voted) by at least one correct node (f+1)
@@ -116,39 +305,23 @@ impl Proposal {
 
 #[test]
 fn preproposal_hash() {
-    let preproposal = PreProposal {
-        frontiers: vec![BlockHash::from(1)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal = PreProposal::new(vec![BlockHash::from(1)], 0);
 
     assert_eq!(preproposal.hash(), BlockHash::decode_hex("33E423980C9B37D048BD5FADBD4A2AEB95146922045405ACCC2F468D0EF96988").unwrap());
 }
 
 #[test]
 fn preproposal_hash_with_unordered_frontiers() {
-    let preproposal1 = PreProposal {
-        frontiers: vec![BlockHash::from(1), BlockHash::from(2)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
-
-    let preproposal2 = PreProposal {
-        frontiers: vec![BlockHash::from(2), BlockHash::from(1)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal1 = PreProposal::new(vec![BlockHash::from(1), BlockHash::from(2)], 0);
+
+    let preproposal2 = PreProposal::new(vec![BlockHash::from(2), BlockHash::from(1)], 0);
 
     assert_eq!(preproposal1.hash(), preproposal2.hash());
 }
 
 #[test]
 fn preproposal_to_proposal() {
-    let preproposal = PreProposal {
-        frontiers: vec![BlockHash::from(1)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal = PreProposal::new(vec![BlockHash::from(1)], 0);
     
     let hash = preproposal.hash();
     let proposal = Proposal::create_proposal(vec![preproposal], 0);
@@ -158,17 +331,9 @@ fn preproposal_to_proposal() {
 
 #[test]
 fn preproposals_to_proposal() {
-    let preproposal1 = PreProposal {
-        frontiers: vec![BlockHash::from(1)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
-
-    let preproposal2 = PreProposal {
-        frontiers: vec![BlockHash::from(2)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal1 = PreProposal::new(vec![BlockHash::from(1)], 0);
+
+    let preproposal2 = PreProposal::new(vec![BlockHash::from(2)], 0);
     
     let hash1 = preproposal1.hash();
     let hash2 = preproposal2.hash();
@@ -179,11 +344,7 @@ fn preproposals_to_proposal() {
 
 #[test]
 fn proposal_hash() {
-    let preproposal = PreProposal {
-        frontiers: vec![BlockHash::from(1)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal = PreProposal::new(vec![BlockHash::from(1)], 0);
     
     let proposal = Proposal::create_proposal(vec![preproposal], 0);
 
@@ -192,17 +353,9 @@ fn proposal_hash() {
 
 #[test]
 fn proposal_hash_with_unordered_preproposals() {
-    let preproposal1 = PreProposal {
-        frontiers: vec![BlockHash::from(1)], 
-        sender: 0,
-        hash: BlockHash::default(),
-    };
-
-    let preproposal2 = PreProposal {
-        frontiers: vec![BlockHash::from(2)],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal1 = PreProposal::new(vec![BlockHash::from(1)], 0);
+
+    let preproposal2 = PreProposal::new(vec![BlockHash::from(2)], 0);
     
     let proposal1 = Proposal::create_proposal(vec![preproposal1.clone(), preproposal2.clone()], 0);
     let proposal2 = Proposal::create_proposal(vec![preproposal2, preproposal1], 0);
@@ -217,32 +370,16 @@ fn proposal_frontiers() {
     let block3 = BlockHash::from(3);
 
     // Node 1 has final voted block 2
-    let preproposal1 = PreProposal {
-        frontiers: vec![block1],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal1 = PreProposal::new(vec![block1], 0);
     
     // Node 2 has final voted block 1 
-    let preproposal2 = PreProposal {
-        frontiers: vec![block2],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal2 = PreProposal::new(vec![block2], 0);
     
     // Node 3 has confirmed block 1 and final voted block 2
-    let preproposal3 = PreProposal {
-        frontiers: vec![block1, block2], 
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let preproposal3 = PreProposal::new(vec![block1, block2], 0);
     
     // Node 4 has final voted block 1 but preproposes block 3, which is a fork of block 1, because it is byzantine
-    let _preproposal4 = PreProposal {
-        frontiers: vec![block3],
-        sender: 0,
-        hash: BlockHash::default(),
-    };
+    let _preproposal4 = PreProposal::new(vec![block3], 0);
     
     // Create a proposal that includes the preproposals from node 1, 2 and 3 (proposal from node 4 is not valid because block 3 has not received at least 2f+1 votes) 
     let preproposals = vec![
@@ -259,3 +396,105 @@ fn proposal_frontiers() {
     assert!(proposal_frontiers.contains(&block2));
 }
 
+#[test]
+fn preproposal_insert_updates_hash() {
+    let mut preproposal = PreProposal::new(vec![BlockHash::from(2)], 0);
+    let before = preproposal.hash();
+
+    assert!(preproposal.insert(BlockHash::from(1)));
+    assert_ne!(preproposal.hash(), before);
+    assert!(!preproposal.insert(BlockHash::from(2)));
+    assert_eq!(preproposal.hash(), PreProposal::new(vec![BlockHash::from(1), BlockHash::from(2)], 0).hash());
+
+    preproposal.extend(vec![BlockHash::from(3), BlockHash::from(1)]);
+    assert_eq!(preproposal.len(), 3);
+    assert_eq!(preproposal.hash(), PreProposal::new(vec![BlockHash::from(3), BlockHash::from(2), BlockHash::from(1)], 0).hash());
+}
+
+// Frontier sets of the size of a real ledger's, see benches/frontiers.rs for the timings
+#[cfg(test)]
+fn huge_frontiers(count: u64, offset: u64) -> Vec<BlockHash> {
+    (offset..offset + count).map(|value| BlockHash::from(value.wrapping_mul(0x9E37_79B9_7F4A_7C15))).collect()
+}
+
+#[test]
+fn huge_preproposal_hash_ignores_order() {
+    let frontiers = huge_frontiers(100_000, 0);
+    let mut reversed = frontiers.clone();
+    reversed.reverse();
+
+    let preproposal = PreProposal::new(frontiers, 0);
+    assert_eq!(preproposal.len(), 100_000);
+    assert_eq!(preproposal.hash(), PreProposal::new(reversed, 1).hash());
+}
+
+#[test]
+fn huge_preproposal_extend_matches_new() {
+    let mut preproposal = PreProposal::new(huge_frontiers(10_000, 0), 0);
+    preproposal.extend(huge_frontiers(90_000, 5_000));
+
+    assert_eq!(preproposal.len(), 95_000);
+    assert_eq!(preproposal.hash(), PreProposal::new(huge_frontiers(95_000, 0), 0).hash());
+}
+
+#[test]
+fn huge_preproposal_inserted_one_by_one_matches_new() {
+    let mut preproposal = PreProposal::default();
+    for frontier in huge_frontiers(100_000, 0) {
+        preproposal.insert(frontier);
+    }
+
+    assert_eq!(preproposal.hash(), PreProposal::new(huge_frontiers(100_000, 0), 0).hash());
+}
+
+#[test]
+fn frontiers_are_paged_by_cursor() {
+    let preproposals: Vec<PreProposal> = (0..3).map(|id| PreProposal::new(huge_frontiers(300, id as u64 * 100), id)).collect();
+    let proposal = Proposal::create_proposal(preproposals.clone(), 0);
+    let frontiers = proposal.frontiers(&preproposals, 1);
+
+    let mut paged = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = proposal.frontier_page(&preproposals, cursor, 64);
+        assert!(page.frontiers.len() <= 64);
+        paged.extend(page.frontiers);
+        cursor = page.next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    assert_eq!(paged, frontiers);
+
+    let page = preproposals[0].frontier_page(None, 300);
+    assert_eq!((page.frontiers.len(), page.next), (300, None));
+    assert_eq!(preproposals[0].frontier_page(page.frontiers.last().copied(), 10), FrontierPage::default());
+}
+
+#[test]
+fn frontiers_round_trip_prefix_compressed() {
+    let preproposal = PreProposal::new(huge_frontiers(10_000, 0), 3);
+    let encoded = preproposal.encoded_frontiers();
+    assert!(encoded.len() < 10_000 * 32 / 3);
+    assert_eq!(PreProposal::from_encoded(&encoded, 3), Ok(preproposal));
+    assert_eq!(PreProposal::from_encoded(&encode_frontiers(&BTreeSet::new()), 0), Ok(PreProposal::default()));
+
+    assert_eq!(decode_frontiers(&encoded[..encoded.len() - 1]), Err(FrontierDecodeError::Truncated));
+    assert_eq!(decode_frontiers(&[encoded.as_slice(), &[0]].concat()), Err(FrontierDecodeError::TrailingBytes));
+    assert_eq!(decode_frontiers(&[1, 5]), Err(FrontierDecodeError::InvalidPrefix));
+    let mut repeated = vec![2, 0];
+    repeated.extend_from_slice(BlockHash::from(1).as_bytes());
+    repeated.push(31);
+    repeated.push(1);
+    assert_eq!(decode_frontiers(&repeated), Err(FrontierDecodeError::Unordered));
+}
+
+#[test]
+fn huge_proposal_frontiers_are_the_union() {
+    let preproposals: Vec<PreProposal> = (0..3).map(|id| PreProposal::new(huge_frontiers(30_000, id as u64 * 10_000), id)).collect();
+    let proposal = Proposal::create_proposal(preproposals.clone(), 0);
+
+    let frontiers = proposal.frontiers(&preproposals, 1);
+    assert_eq!(frontiers.len(), 50_000);
+    assert!(frontiers.windows(2).all(|pair| pair[0] < pair[1]));
+}
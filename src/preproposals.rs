@@ -0,0 +1,149 @@
+use std::{collections::{HashMap, HashSet}, mem::size_of};
+use rsnano_core::BlockHash;
+use crate::{Id, MemorySize, PreProposal, PreProposalHash};
+
+// Preproposals received, one per sender, bounded to `Config::preproposal_capacity` of them: past it the least recently
+// used one goes first. Preproposals referenced by a proposal that was not decided yet are pinned and never evicted,
+// since the decided proposal resolves to their frontiers
+#[derive(Debug, Default)]
+pub struct PreProposalCache {
+    capacity: Option<usize>,
+    // Preproposal of each sender with the tick it was last used at
+    entries: HashMap<Id, (PreProposal, u64)>,
+    tick: u64,
+    pinned: HashSet<PreProposalHash>,
+    evicted: usize,
+}
+
+impl PreProposalCache {
+    // Unbounded if None
+    pub fn new(capacity: Option<usize>) -> PreProposalCache {
+        PreProposalCache { capacity, ..PreProposalCache::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Preproposals evicted so far
+    pub fn evicted(&self) -> usize {
+        self.evicted
+    }
+
+    // Without counting as a use
+    pub fn peek(&self, sender: Id) -> Option<&PreProposal> {
+        self.entries.get(&sender).map(|(preproposal, _)| preproposal)
+    }
+
+    pub fn get(&mut self, sender: Id) -> Option<&PreProposal> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(&sender).map(|(preproposal, used)| {
+            *used = tick;
+            &*preproposal
+        })
+    }
+
+    // Without counting as a use, e.g. to serve it to a peer
+    pub fn find(&self, hash: &PreProposalHash) -> Option<&PreProposal> {
+        self.values().find(|preproposal| preproposal.hash() == *hash)
+    }
+
+    pub fn senders(&self) -> impl Iterator<Item = Id> + '_ {
+        self.entries.keys().copied()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &PreProposal> {
+        self.entries.values().map(|(preproposal, _)| preproposal)
+    }
+
+    // Keeps the first preproposal of each sender, returns false if the sender already had one
+    pub fn insert(&mut self, preproposal: PreProposal) -> bool {
+        if self.entries.contains_key(&preproposal.sender) {
+            return false;
+        }
+
+        self.tick += 1;
+        self.entries.insert(preproposal.sender, (preproposal, self.tick));
+        self.evict_over_capacity();
+        true
+    }
+
+    pub fn pin(&mut self, hashes: &[PreProposalHash]) {
+        self.pinned.extend(hashes.iter().copied());
+    }
+
+    // Once a proposal is decided, the ones that lost no longer hold their preproposals
+    pub fn unpin_all(&mut self) {
+        self.pinned.clear();
+        self.evict_over_capacity();
+    }
+
+    fn evict_over_capacity(&mut self) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+
+        while self.entries.len() > capacity {
+            let oldest = self.entries
+                .iter()
+                .filter(|(_, (preproposal, _))| !self.pinned.contains(&preproposal.hash()))
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(sender, _)| *sender);
+
+            // Past the capacity until the pins are released
+            let Some(sender) = oldest else {
+                return;
+            };
+            self.entries.remove(&sender);
+            self.evicted += 1;
+        }
+    }
+}
+
+// The frontiers of the preproposals held, which is most of what they weigh
+impl MemorySize for PreProposalCache {
+    fn memory_size(&self) -> usize {
+        self.values().map(|preproposal| size_of::<(Id, PreProposal, u64)>() + preproposal.frontiers().len() * size_of::<BlockHash>()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+
+    fn preproposal(sender: Id) -> PreProposal {
+        PreProposal::new(vec![BlockHash::from(sender as u64)], sender)
+    }
+
+    #[test]
+    fn least_recently_used_unpinned_preproposals_are_evicted() {
+        let mut cache = PreProposalCache::new(Some(2));
+        assert!(cache.insert(preproposal(0)));
+        assert!(cache.insert(preproposal(1)));
+        assert!(!cache.insert(PreProposal::new(vec![], 1)));
+
+        // 0 was used after 1 was inserted, so 1 goes first
+        cache.get(0);
+        cache.insert(preproposal(2));
+        assert_eq!((cache.peek(0).is_some(), cache.peek(1).is_some(), cache.evicted()), (true, false, 1));
+
+        // 0 is pinned by a pending proposal, so 2 goes first even though it was used after it
+        cache.pin(&[preproposal(0).hash()]);
+        cache.insert(preproposal(3));
+        assert_eq!((cache.peek(0).is_some(), cache.peek(2).is_some(), cache.peek(3).is_some()), (true, false, true));
+
+        // With nothing else to evict, the cache goes past its capacity until the decision
+        cache.pin(&[preproposal(3).hash(), preproposal(4).hash()]);
+        cache.insert(preproposal(4));
+        assert_eq!(cache.len(), 3);
+        cache.unpin_all();
+        assert_eq!((cache.len(), cache.evicted()), (2, 3));
+        assert!(cache.peek(0).is_none());
+    }
+}
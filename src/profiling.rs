@@ -0,0 +1,161 @@
+use std::{collections::BTreeMap, sync::{Mutex, MutexGuard}, time::Duration};
+#[cfg(feature = "profiling")]
+use std::{collections::VecDeque, sync::Arc, time::Instant};
+
+// Reports kept, the one of the oldest instance is dropped first
+#[cfg(feature = "profiling")]
+const MAX_REPORTS: usize = 64;
+
+// Where the time of an instance goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Probe {
+    // Waiting for the commit log and transcript locks
+    LockWait,
+    // Checking broadcasts and responses, on the run loop and the validation workers
+    Validation,
+    // Hashing the certificates sent and received by reference or by hash
+    Hashing,
+    // Encoding and writing the transcript of the instance
+    Serialization,
+    // Time the oldest message of a batch waited in the outbox of the run loop before it went out
+    ChannelLatency,
+}
+
+impl Probe {
+    pub const ALL: [Probe; 5] = [Probe::LockWait, Probe::Validation, Probe::Hashing, Probe::Serialization, Probe::ChannelLatency];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Probe::LockWait => "lock_wait",
+            Probe::Validation => "validation",
+            Probe::Hashing => "hashing",
+            Probe::Serialization => "serialization",
+            Probe::ChannelLatency => "channel_latency",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Timing {
+    #[cfg(feature = "profiling")]
+    fn add(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.count as u32).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub instance: u64,
+    pub timings: BTreeMap<Probe, Timing>,
+}
+
+// Fine-grained timings of each instance, recorded with the `profiling` feature only. Without it the probes cost
+// nothing and no report is kept, so production validators only pay for them when built to investigate
+// Clones share the reports, so the run loop, the validation workers and the proposer record into the same ones
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    #[cfg(feature = "profiling")]
+    reports: Arc<Mutex<VecDeque<ProfileReport>>>,
+}
+
+impl Profiler {
+    pub const ENABLED: bool = cfg!(feature = "profiling");
+
+    // Timings recorded from now on go to the report of this instance
+    #[cfg_attr(not(feature = "profiling"), allow(unused_variables))]
+    pub fn start(&self, instance: u64) {
+        #[cfg(feature = "profiling")]
+        {
+            let mut reports = self.reports.lock().unwrap();
+            reports.push_back(ProfileReport { instance, ..ProfileReport::default() });
+            if reports.len() > MAX_REPORTS {
+                reports.pop_front();
+            }
+        }
+    }
+
+    // Timings recorded before the first instance started are reported under instance 0
+    #[cfg_attr(not(feature = "profiling"), allow(unused_variables))]
+    pub fn record(&self, probe: Probe, elapsed: Duration) {
+        #[cfg(feature = "profiling")]
+        {
+            let mut reports = self.reports.lock().unwrap();
+            if reports.is_empty() {
+                reports.push_back(ProfileReport::default());
+            }
+            reports.back_mut().unwrap().timings.entry(probe).or_default().add(elapsed);
+        }
+    }
+
+    #[cfg_attr(not(feature = "profiling"), allow(unused_variables))]
+    pub fn time<T>(&self, probe: Probe, timed: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "profiling")]
+        {
+            let started = Instant::now();
+            let result = timed();
+            self.record(probe, started.elapsed());
+            result
+        }
+        #[cfg(not(feature = "profiling"))]
+        timed()
+    }
+
+    // Locks the mutex, recording how long it waited for it as lock wait
+    pub fn lock<'a, T>(&self, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        self.time(Probe::LockWait, || mutex.lock().unwrap())
+    }
+
+    pub fn report(&self, instance: u64) -> Option<ProfileReport> {
+        self.reports().into_iter().rev().find(|report| report.instance == instance)
+    }
+
+    // Oldest instance first, empty without the `profiling` feature
+    pub fn reports(&self) -> Vec<ProfileReport> {
+        #[cfg(feature = "profiling")]
+        return self.reports.lock().unwrap().iter().cloned().collect();
+        #[cfg(not(feature = "profiling"))]
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_are_reported_per_instance() {
+        let profiler = Profiler::default();
+        let mutex = Mutex::new(());
+
+        profiler.start(1);
+        profiler.record(Probe::Validation, Duration::from_millis(2));
+        profiler.record(Probe::Validation, Duration::from_millis(4));
+        drop(profiler.lock(&mutex));
+        profiler.clone().start(2);
+        assert_eq!(profiler.time(Probe::Hashing, || 7), 7);
+
+        if !Profiler::ENABLED {
+            assert_eq!(profiler.reports(), vec![]);
+            return;
+        }
+
+        let first = profiler.report(1).unwrap();
+        assert_eq!(first.timings[&Probe::Validation], Timing { count: 2, total: Duration::from_millis(6), max: Duration::from_millis(4) });
+        assert_eq!(first.timings[&Probe::Validation].mean(), Duration::from_millis(3));
+        assert_eq!(first.timings[&Probe::LockWait].count, 1);
+        assert_eq!(profiler.report(2).unwrap().timings.keys().collect::<Vec<_>>(), vec![&Probe::Hashing]);
+        assert_eq!(profiler.report(3), None);
+    }
+}
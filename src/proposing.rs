@@ -0,0 +1,178 @@
+use std::{collections::VecDeque, future::Future, panic::{self, AssertUnwindSafe}, pin::Pin, sync::{Arc, Condvar, Mutex}, task::{Context, Poll, Waker}, thread};
+use crate::{PreProposal, Process, Proposal, Rank};
+
+// Proposer threads of this OS process, shared by every future. Proposals past it wait for one of them to decide, so
+// processes of the same committee proposing in one OS process (e.g. tests) must stay below it
+const MAX_PROPOSERS: usize = 64;
+
+static PROPOSERS: Proposers = Proposers { pool: Mutex::new(Pool { jobs: VecDeque::new(), idle: 0, threads: 0 }), ready: Condvar::new() };
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    jobs: VecDeque<Job>,
+    // Threads waiting for a job
+    idle: usize,
+    threads: usize,
+}
+
+// Threads the proposals of futures block on, started as needed up to MAX_PROPOSERS and kept for the next ones
+struct Proposers {
+    pool: Mutex<Pool>,
+    ready: Condvar,
+}
+
+impl Proposers {
+    fn run(&'static self, job: Job) {
+        let mut pool = self.pool.lock().unwrap();
+        pool.jobs.push_back(job);
+        if pool.idle >= pool.jobs.len() {
+            self.ready.notify_one();
+        } else if pool.threads < MAX_PROPOSERS {
+            pool.threads += 1;
+            thread::spawn(move || self.work());
+        }
+    }
+
+    fn work(&self) {
+        let mut pool = self.pool.lock().unwrap();
+        loop {
+            if let Some(job) = pool.jobs.pop_front() {
+                drop(pool);
+                job();
+                pool = self.pool.lock().unwrap();
+                continue;
+            }
+            pool.idle += 1;
+            pool = self.ready.wait(pool).unwrap();
+            pool.idle -= 1;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    decided: Option<thread::Result<Proposal>>,
+    waker: Option<Waker>,
+}
+
+// An instance proposed from async code: the steps still block on the condvars of the process, on one of a bounded pool
+// of proposer threads, and the future completes with the decided proposal without blocking the executor that polls it.
+// Works with any executor, the crate depends on no runtime
+#[derive(Debug)]
+pub struct Proposing {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Future for Proposing {
+    type Output = Proposal;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Proposal> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.decided.take() {
+            Some(Ok(proposal)) => Poll::Ready(proposal),
+            // The proposer panicked, the caller sees it like a join would
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                shared.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Process {
+    // Process::propose as a future, the process proposes on a proposer thread, see MAX_PROPOSERS
+    pub fn propose_async(mut self, threshold: usize, value: PreProposal, rank: Rank) -> Proposing {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let decided = Arc::clone(&shared);
+        PROPOSERS.run(Box::new(move || {
+            let proposal = panic::catch_unwind(AssertUnwindSafe(|| self.propose(threshold, value, rank)));
+            let mut decided = decided.lock().unwrap();
+            decided.decided = Some(proposal);
+            if let Some(waker) = decided.waker.take() {
+                waker.wake();
+            }
+        }));
+        Proposing { shared }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc::channel, task::Wake, thread::Thread};
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::Id;
+
+    // Tests counting the threads of the pool do not run beside other proposals
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // The smallest executor there is, polling on the calling thread
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn proposals_complete_as_futures() {
+        let _serial = SERIAL.lock().unwrap();
+        let f = 1;
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..3 * f + 1).map(|_| channel()).unzip();
+        let proposing: Vec<Proposing> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, receiver)| {
+                let process = Process::new(id as Id, f, senders.clone(), receiver, false);
+                process.propose_async(2 * f + 1, PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id as Id), 0)
+            })
+            .collect();
+
+        let decided: Vec<Proposal> = proposing.into_iter().map(block_on).collect();
+        // Proposers of the same preproposals propose the same hash, whichever of them a process found first
+        assert!(decided.windows(2).all(|pair| pair[0].hash == pair[1].hash), "{decided:?}");
+    }
+
+    #[test]
+    fn proposer_threads_are_reused() {
+        let f = 1;
+        let propose = || {
+            let (senders, receivers): (Vec<_>, Vec<_>) = (0..3 * f + 1).map(|_| channel()).unzip();
+            let proposing: Vec<Proposing> = receivers
+                .into_iter()
+                .enumerate()
+                .map(|(id, receiver)| Process::new(id as Id, f, senders.clone(), receiver, false).propose_async(2 * f + 1, PreProposal::new(vec![BlockHash::from(1)], id as Id), 0))
+                .collect();
+            proposing.into_iter().for_each(|proposing| drop(block_on(proposing)));
+            // The wakers run before the threads are back in the pool
+            loop {
+                let pool = PROPOSERS.pool.lock().unwrap();
+                if pool.idle == pool.threads {
+                    return pool.threads;
+                }
+                drop(pool);
+                thread::yield_now();
+            }
+        };
+
+        let _serial = SERIAL.lock().unwrap();
+        let threads = propose();
+        assert!((3 * f + 1..=MAX_PROPOSERS).contains(&threads));
+        assert_eq!(propose(), threads);
+    }
+}
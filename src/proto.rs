@@ -0,0 +1,642 @@
+use std::sync::Arc;
+use rsnano_core::BlockHash;
+use crate::{AValue, BValue, Broadcast, CommitRecord, Committee, Features, Hello, Message, PreProposal, Proposal, RValue, Rejoin, RejoinGrounds, Response, State, Step, TraceContext, Value, Vote};
+
+// Protocol Buffers encoding of the messages, after the schema of proto/archipelago.proto, for nodes and tooling not
+// written in Rust. Written by hand like the other encodings of the crate, rather than generated: fields at their
+// default value are left out as in proto3, unknown fields are skipped, and repeated scalars are read packed or not
+
+// Nested messages deeper than this are rejected, see the wire format
+const MAX_DEPTH: usize = 64;
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtoError {
+    Truncated,
+    // A varint longer than ten bytes
+    Overlong,
+    // Groups are not part of the schema
+    WireType(u8),
+    // A field of another wire type than the schema's
+    FieldType(u32),
+    // A required field or oneof is missing
+    Missing(&'static str),
+    // A hash, key or signature of the wrong length
+    Length(u32),
+    // An unknown step or an invalid string
+    Invalid(u32),
+    TooDeep,
+}
+
+pub fn encode_protobuf(message: &Message) -> Vec<u8> {
+    let mut writer = ProtoWriter(Vec::new());
+    writer.message(message);
+    writer.0
+}
+
+pub fn decode_protobuf(bytes: &[u8]) -> Result<Message, ProtoError> {
+    Fields::parse(bytes, 0)?.message()
+}
+
+struct ProtoWriter(Vec<u8>);
+
+impl ProtoWriter {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn optional_uint(&mut self, field: u32, value: Option<u64>) {
+        if let Some(value) = value {
+            self.key(field, VARINT);
+            self.varint(value);
+        }
+    }
+
+    fn uint(&mut self, field: u32, value: u64) {
+        self.optional_uint(field, Some(value).filter(|value| *value != 0));
+    }
+
+    // int64 as in the schema, negative values take ten bytes
+    fn int(&mut self, field: u32, value: i64) {
+        self.uint(field, value as u64);
+    }
+
+    fn bool(&mut self, field: u32, value: bool) {
+        self.uint(field, value as u64);
+    }
+
+    fn optional_fixed64(&mut self, field: u32, value: Option<u64>) {
+        if let Some(value) = value {
+            self.key(field, FIXED64);
+            self.0.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn fixed64(&mut self, field: u32, value: u64) {
+        self.optional_fixed64(field, Some(value).filter(|value| *value != 0));
+    }
+
+    fn optional_bytes(&mut self, field: u32, bytes: Option<&[u8]>) {
+        if let Some(bytes) = bytes {
+            self.key(field, LENGTH_DELIMITED);
+            self.varint(bytes.len() as u64);
+            self.0.extend_from_slice(bytes);
+        }
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.optional_bytes(field, Some(bytes).filter(|bytes| !bytes.is_empty()));
+    }
+
+    fn hashes(&mut self, field: u32, hashes: &[BlockHash]) {
+        hashes.iter().for_each(|hash| self.optional_bytes(field, Some(hash.as_bytes())));
+    }
+
+    // Always written, an empty message still tells which field of a oneof is set
+    fn nested(&mut self, field: u32, write: impl FnOnce(&mut ProtoWriter)) {
+        let mut nested = ProtoWriter(Vec::new());
+        write(&mut nested);
+        self.optional_bytes(field, Some(&nested.0));
+    }
+
+    fn step(&mut self, field: u32, step: Step) {
+        self.uint(field, match step {
+            Step::R => 0,
+            Step::A => 1,
+            Step::B => 2,
+        });
+    }
+
+    fn hashes_message(&mut self, field: u32, sender: i64, hashes: &[BlockHash]) {
+        self.nested(field, |writer| {
+            writer.int(1, sender);
+            writer.hashes(2, hashes);
+        });
+    }
+
+    fn message(&mut self, message: &Message) {
+        match message {
+            Message::Broadcast(broadcast) => self.nested(1, |writer| writer.broadcast(broadcast)),
+            Message::Response(response) => self.nested(2, |writer| writer.response(response)),
+            Message::Proposal(proposal) => self.nested(3, |writer| {
+                writer.int(1, proposal.sender);
+                writer.hashes(2, &proposal.preproposals);
+            }),
+            Message::PreProposal(preproposal) => self.nested(4, |writer| {
+                writer.int(1, preproposal.sender);
+                preproposal.frontiers().iter().for_each(|frontier| writer.optional_bytes(2, Some(frontier.as_bytes())));
+            }),
+            Message::PreVote(sender, value) => self.nested(5, |writer| {
+                writer.int(1, *sender);
+                writer.bytes(2, value.as_bytes());
+            }),
+            Message::Batch(messages) => self.nested(6, |writer| messages.iter().for_each(|message| writer.nested(1, |writer| writer.message(message)))),
+            Message::Sequenced(sender, sequence, messages) => self.nested(7, |writer| {
+                writer.int(1, *sender);
+                writer.uint(2, *sequence);
+                messages.iter().for_each(|message| writer.nested(3, |writer| writer.message(message)));
+            }),
+            Message::Ack(sender, sequence) => self.nested(8, |writer| {
+                writer.int(1, *sender);
+                writer.uint(2, *sequence);
+            }),
+            Message::GetResponses(requester, hashes) => self.nested(9, |writer| {
+                writer.int(1, *requester);
+                writer.hashes(2, hashes);
+            }),
+            Message::Responses(sender, responses) => self.nested(10, |writer| {
+                writer.int(1, *sender);
+                writer.responses(2, responses);
+            }),
+            Message::GetCertificate(requester, hash) => self.nested(11, |writer| {
+                writer.int(1, *requester);
+                writer.bytes(2, hash.as_bytes());
+            }),
+            Message::Certificate(sender, certificate) => self.nested(12, |writer| {
+                writer.int(1, *sender);
+                writer.responses(2, certificate);
+            }),
+            Message::PreProposalDigest(sender, hashes) => self.hashes_message(13, *sender, hashes),
+            Message::GetPreProposals(requester, hashes) => self.hashes_message(14, *requester, hashes),
+            Message::Vote(vote) => self.nested(15, |writer| {
+                writer.int(1, vote.voter);
+                writer.bytes(2, &vote.key);
+                writer.uint(3, vote.timestamp);
+                writer.hashes(4, &vote.hashes);
+                writer.bytes(5, &vote.signature);
+            }),
+            Message::VoteHashes(sender, hashes) => self.hashes_message(16, *sender, hashes),
+            Message::GetVotes(requester, hashes) => self.hashes_message(17, *requester, hashes),
+            Message::Announce(sender, instance) => self.nested(18, |writer| {
+                writer.int(1, *sender);
+                writer.uint(2, *instance);
+            }),
+            Message::Commits(sender, commits) => self.nested(19, |writer| writer.commits(*sender, commits)),
+            Message::GetCertificates(requester, instances) => self.nested(20, |writer| {
+                writer.int(1, *requester);
+                writer.uint(2, instances.start);
+                writer.uint(3, instances.end);
+            }),
+            Message::Certificates(sender, commits) => self.nested(21, |writer| writer.commits(*sender, commits)),
+            Message::Hello(hello) => self.nested(22, |writer| {
+                writer.int(1, hello.sender);
+                writer.bytes(2, hello.version.as_bytes());
+                writer.uint(3, u64::from(hello.features.bits()));
+                writer.bytes(4, hello.committee.as_bytes());
+                writer.bool(5, hello.ask);
+            }),
+            Message::Rejoin(rejoin) => self.nested(23, |writer| {
+                writer.int(1, rejoin.sender);
+                match rejoin.grounds {
+                    RejoinGrounds::Rekey(key) => writer.optional_bytes(2, Some(&key)),
+                    RejoinGrounds::Unban => writer.bool(3, true),
+                }
+                writer.uint(4, rejoin.resume);
+                writer.bytes(5, &rejoin.signature);
+            }),
+            Message::Instance(instance, message) => self.nested(24, |writer| {
+                writer.uint(1, *instance);
+                writer.nested(2, |writer| writer.message(message));
+            }),
+        }
+    }
+
+    fn broadcast(&mut self, broadcast: &Broadcast) {
+        self.int(1, broadcast.sender);
+        self.step(2, broadcast.step);
+        self.bytes(3, broadcast.value.as_bytes());
+        self.optional_uint(4, broadcast.flag.map(u64::from));
+        self.int(5, broadcast.rank);
+        if let Some(responses) = &broadcast.previous_step_responses {
+            self.nested(6, |writer| writer.responses(1, responses));
+        }
+        if let Some(refs) = &broadcast.certificate_refs {
+            self.nested(7, |writer| writer.hashes(1, refs));
+        }
+        self.optional_bytes(8, broadcast.certificate_hash.as_ref().map(|hash| hash.as_bytes().as_slice()));
+        if let Some(trace) = &broadcast.trace {
+            self.nested(9, |writer| {
+                writer.fixed64(1, (trace.trace_id >> 64) as u64);
+                writer.fixed64(2, trace.trace_id as u64);
+                writer.fixed64(3, trace.span_id);
+            });
+        }
+        self.optional_bytes(10, broadcast.committee.as_ref().map(|committee| committee.as_bytes().as_slice()));
+        self.optional_bytes(11, broadcast.signature.as_ref().map(|signature| signature.as_slice()));
+    }
+
+    fn responses(&mut self, field: u32, responses: &[Response]) {
+        responses.iter().for_each(|response| self.nested(field, |writer| writer.response(response)));
+    }
+
+    fn response(&mut self, response: &Response) {
+        self.int(1, response.sender);
+        self.step(2, response.step);
+        self.int(3, response.rank);
+        for state in &response.state {
+            self.nested(4, |writer| {
+                match &state.value {
+                    Value::RValue(value) => writer.nested(1, |writer| {
+                        writer.int(1, value.rank);
+                        writer.bytes(2, value.value.as_bytes());
+                    }),
+                    Value::AValue(value) => writer.nested(2, |writer| writer.bytes(1, value.0.as_bytes())),
+                    Value::BValue(value) => writer.nested(3, |writer| {
+                        writer.bytes(1, value.value.as_bytes());
+                        writer.bool(2, value.flag);
+                    }),
+                }
+                writer.nested(4, |writer| writer.broadcast(&state.broadcast));
+            });
+        }
+        self.optional_bytes(5, Some(response.answers.as_bytes().as_slice()).filter(|_| !response.answers.is_zero()));
+        self.optional_bytes(6, response.committee.as_ref().map(|committee| committee.as_bytes().as_slice()));
+        self.optional_bytes(7, response.signature.as_ref().map(|signature| signature.as_slice()));
+    }
+
+    fn commits(&mut self, sender: i64, commits: &[CommitRecord]) {
+        self.int(1, sender);
+        for commit in commits {
+            self.nested(2, |writer| {
+                writer.uint(1, commit.instance);
+                writer.int(2, commit.rank);
+                writer.bytes(3, commit.value.as_bytes());
+                writer.responses(4, &commit.certificate);
+                if let Some(committee) = &commit.committee {
+                    writer.nested(5, |writer| {
+                        writer.uint(1, committee.f as u64);
+                        for (member, key) in &committee.members {
+                            writer.nested(2, |writer| {
+                                writer.int(1, *member);
+                                writer.bytes(2, key);
+                            });
+                        }
+                        writer.optional_bytes(3, committee.configuration.as_ref().map(|configuration| configuration.as_bytes().as_slice()));
+                    });
+                }
+            });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+// Fields of a message in the order they came, nested messages are parsed as they are asked for
+struct Fields<'a> {
+    fields: Vec<(u32, Field<'a>)>,
+    depth: usize,
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, ProtoError> {
+    let mut value = 0;
+    for shift in (0..70).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(ProtoError::Truncated)?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift.min(63);
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(ProtoError::Overlong)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Result<&'a [u8], ProtoError> {
+    if bytes.len() < length {
+        return Err(ProtoError::Truncated);
+    }
+    let (taken, rest) = bytes.split_at(length);
+    *bytes = rest;
+    Ok(taken)
+}
+
+impl<'a> Fields<'a> {
+    fn parse(mut bytes: &'a [u8], depth: usize) -> Result<Fields<'a>, ProtoError> {
+        if depth > MAX_DEPTH {
+            return Err(ProtoError::TooDeep);
+        }
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes)?;
+            let field = match key as u8 & 7 {
+                VARINT => Field::Varint(read_varint(&mut bytes)?),
+                FIXED64 => Field::Fixed64(u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap())),
+                LENGTH_DELIMITED => {
+                    let length = read_varint(&mut bytes)?;
+                    Field::Bytes(take(&mut bytes, usize::try_from(length).map_err(|_| ProtoError::Truncated)?)?)
+                }
+                FIXED32 => {
+                    take(&mut bytes, 4)?;
+                    Field::Fixed32
+                }
+                wire_type => return Err(ProtoError::WireType(wire_type)),
+            };
+            fields.push(((key >> 3) as u32, field));
+        }
+        Ok(Fields { fields, depth })
+    }
+
+    fn all(&self, number: u32) -> impl Iterator<Item = Field<'a>> + '_ {
+        self.fields.iter().filter(move |(field, _)| *field == number).map(|(_, field)| *field)
+    }
+
+    // The last one wins, as in proto3
+    fn last(&self, number: u32) -> Option<Field<'a>> {
+        self.all(number).last()
+    }
+
+    fn optional_uint(&self, number: u32) -> Result<Option<u64>, ProtoError> {
+        match self.last(number) {
+            None => Ok(None),
+            Some(Field::Varint(value)) => Ok(Some(value)),
+            Some(_) => Err(ProtoError::FieldType(number)),
+        }
+    }
+
+    fn uint(&self, number: u32) -> Result<u64, ProtoError> {
+        self.optional_uint(number).map(Option::unwrap_or_default)
+    }
+
+    fn int(&self, number: u32) -> Result<i64, ProtoError> {
+        self.uint(number).map(|value| value as i64)
+    }
+
+    fn bool(&self, number: u32) -> Result<bool, ProtoError> {
+        self.uint(number).map(|value| value != 0)
+    }
+
+    fn optional_fixed64(&self, number: u32) -> Result<Option<u64>, ProtoError> {
+        match self.last(number) {
+            None => Ok(None),
+            Some(Field::Fixed64(value)) => Ok(Some(value)),
+            Some(_) => Err(ProtoError::FieldType(number)),
+        }
+    }
+
+    fn fixed64(&self, number: u32) -> Result<u64, ProtoError> {
+        self.optional_fixed64(number).map(Option::unwrap_or_default)
+    }
+
+    fn repeated_bytes(&self, number: u32) -> Result<Vec<&'a [u8]>, ProtoError> {
+        self.all(number)
+            .map(|field| match field {
+                Field::Bytes(bytes) => Ok(bytes),
+                _ => Err(ProtoError::FieldType(number)),
+            })
+            .collect()
+    }
+
+    fn optional_bytes(&self, number: u32) -> Result<Option<&'a [u8]>, ProtoError> {
+        Ok(self.repeated_bytes(number)?.pop())
+    }
+
+    fn bytes(&self, number: u32) -> Result<&'a [u8], ProtoError> {
+        self.optional_bytes(number).map(Option::unwrap_or_default)
+    }
+
+    fn array<const N: usize>(&self, bytes: &[u8], number: u32) -> Result<[u8; N], ProtoError> {
+        bytes.try_into().map_err(|_| ProtoError::Length(number))
+    }
+
+    fn hash(&self, number: u32) -> Result<BlockHash, ProtoError> {
+        self.array(self.bytes(number)?, number).map(BlockHash::from_bytes)
+    }
+
+    fn optional_hash(&self, number: u32) -> Result<Option<BlockHash>, ProtoError> {
+        self.optional_bytes(number)?.map(|bytes| self.array(bytes, number).map(BlockHash::from_bytes)).transpose()
+    }
+
+    fn hashes(&self, number: u32) -> Result<Vec<BlockHash>, ProtoError> {
+        self.repeated_bytes(number)?.into_iter().map(|bytes| self.array(bytes, number).map(BlockHash::from_bytes)).collect()
+    }
+
+    fn nested(&self, number: u32) -> Result<Option<Fields<'a>>, ProtoError> {
+        self.optional_bytes(number)?.map(|bytes| Fields::parse(bytes, self.depth + 1)).transpose()
+    }
+
+    fn required(&self, number: u32, name: &'static str) -> Result<Fields<'a>, ProtoError> {
+        self.nested(number)?.ok_or(ProtoError::Missing(name))
+    }
+
+    fn repeated(&self, number: u32) -> Result<Vec<Fields<'a>>, ProtoError> {
+        self.repeated_bytes(number)?.into_iter().map(|bytes| Fields::parse(bytes, self.depth + 1)).collect()
+    }
+
+    fn step(&self, number: u32) -> Result<Step, ProtoError> {
+        match self.uint(number)? {
+            0 => Ok(Step::R),
+            1 => Ok(Step::A),
+            2 => Ok(Step::B),
+            _ => Err(ProtoError::Invalid(number)),
+        }
+    }
+
+    fn messages(&self, number: u32) -> Result<Vec<Message>, ProtoError> {
+        self.repeated(number)?.iter().map(Fields::message).collect()
+    }
+
+    fn responses(&self, number: u32) -> Result<Vec<Response>, ProtoError> {
+        self.repeated(number)?.iter().map(Fields::response).collect()
+    }
+
+    fn commits(&self) -> Result<(i64, Vec<CommitRecord>), ProtoError> {
+        let commits = self.repeated(2)?
+            .iter()
+            .map(|commit| {
+                Ok(CommitRecord {
+                    instance: commit.uint(1)?,
+                    rank: commit.int(2)?,
+                    value: commit.hash(3)?,
+                    certificate: commit.responses(4)?.into(),
+                    committee: commit.nested(5)?.map(|committee| committee.committee()).transpose()?,
+                })
+            })
+            .collect::<Result<_, ProtoError>>()?;
+        Ok((self.int(1)?, commits))
+    }
+
+    fn committee(&self) -> Result<Committee, ProtoError> {
+        let members = self.repeated(2)?
+            .iter()
+            .map(|member| Ok((member.int(1)?, member.array(member.bytes(2)?, 2)?)))
+            .collect::<Result<_, ProtoError>>()?;
+        Ok(Committee { f: self.uint(1)? as usize, members, configuration: self.optional_hash(3)? })
+    }
+
+    fn hashes_message(&self) -> Result<(i64, Vec<BlockHash>), ProtoError> {
+        Ok((self.int(1)?, self.hashes(2)?))
+    }
+
+    // The set field of the oneof of a Message
+    fn message(&self) -> Result<Message, ProtoError> {
+        let (number, kind) = self
+            .fields
+            .iter()
+            .rev()
+            .find(|(number, _)| (1..=24).contains(number))
+            .map(|(number, _)| (*number, self.required(*number, "message")))
+            .ok_or(ProtoError::Missing("message"))?;
+        let kind = kind?;
+
+        Ok(match number {
+            1 => Message::Broadcast(kind.broadcast()?),
+            2 => Message::Response(kind.response()?),
+            3 => Message::Proposal(Proposal::new(kind.hashes(2)?, kind.int(1)?)),
+            4 => Message::PreProposal(PreProposal::new(kind.hashes(2)?, kind.int(1)?)),
+            5 => Message::PreVote(kind.int(1)?, kind.hash(2)?),
+            6 => Message::Batch(kind.messages(1)?),
+            7 => Message::Sequenced(kind.int(1)?, kind.uint(2)?, kind.messages(3)?),
+            8 => Message::Ack(kind.int(1)?, kind.uint(2)?),
+            9 => Message::GetResponses(kind.int(1)?, kind.hashes(2)?),
+            10 => Message::Responses(kind.int(1)?, kind.responses(2)?),
+            11 => Message::GetCertificate(kind.int(1)?, kind.hash(2)?),
+            12 => Message::Certificate(kind.int(1)?, Arc::new(kind.responses(2)?.into())),
+            13 => kind.hashes_message().map(|(sender, hashes)| Message::PreProposalDigest(sender, hashes))?,
+            14 => kind.hashes_message().map(|(requester, hashes)| Message::GetPreProposals(requester, hashes))?,
+            15 => Message::Vote(Vote {
+                voter: kind.int(1)?,
+                key: kind.array(kind.bytes(2)?, 2)?,
+                timestamp: kind.uint(3)?,
+                hashes: kind.hashes(4)?,
+                signature: kind.array(kind.bytes(5)?, 5)?,
+            }),
+            16 => kind.hashes_message().map(|(sender, hashes)| Message::VoteHashes(sender, hashes))?,
+            17 => kind.hashes_message().map(|(requester, hashes)| Message::GetVotes(requester, hashes))?,
+            18 => Message::Announce(kind.int(1)?, kind.uint(2)?),
+            19 => kind.commits().map(|(sender, commits)| Message::Commits(sender, commits))?,
+            20 => Message::GetCertificates(kind.int(1)?, kind.uint(2)?..kind.uint(3)?),
+            21 => kind.commits().map(|(sender, commits)| Message::Certificates(sender, commits))?,
+            22 => Message::Hello(Hello {
+                sender: kind.int(1)?,
+                version: String::from_utf8(kind.bytes(2)?.to_vec()).map_err(|_| ProtoError::Invalid(2))?,
+                features: Features::from_bits(u32::try_from(kind.uint(3)?).map_err(|_| ProtoError::Invalid(3))?),
+                committee: kind.hash(4)?,
+                ask: kind.bool(5)?,
+            }),
+            23 => {
+                // The last field of the oneof that came wins
+                let grounds = match kind.fields.iter().rev().find(|(number, _)| *number == 2 || *number == 3) {
+                    Some((2, _)) => RejoinGrounds::Rekey(kind.array(kind.bytes(2)?, 2)?),
+                    Some(_) => RejoinGrounds::Unban,
+                    None => return Err(ProtoError::Missing("grounds")),
+                };
+                Message::Rejoin(Rejoin { sender: kind.int(1)?, grounds, resume: kind.uint(4)?, signature: kind.array(kind.bytes(5)?, 5)? })
+            }
+            _ => Message::Instance(kind.uint(1)?, Box::new(kind.required(2, "message")?.message()?)),
+        })
+    }
+
+    // The hash is computed from the fields rather than read
+    fn broadcast(&self) -> Result<Broadcast, ProtoError> {
+        let flag = self.optional_uint(4)?.map(|flag| flag != 0);
+        let certificate = self.nested(6)?.map(|certificate| certificate.responses(1)).transpose()?;
+        let mut broadcast = Broadcast::new(self.int(1)?, self.step(2)?, self.hash(3)?, flag, self.int(5)?, certificate.map(Into::into));
+        broadcast.certificate_refs = self.nested(7)?.map(|refs| refs.hashes(1)).transpose()?;
+        broadcast.certificate_hash = self.optional_hash(8)?;
+        broadcast.trace = self
+            .nested(9)?
+            .map(|trace| Ok::<_, ProtoError>(TraceContext { trace_id: ((trace.fixed64(1)? as u128) << 64) | trace.fixed64(2)? as u128, span_id: trace.fixed64(3)? }))
+            .transpose()?;
+        let mut broadcast = broadcast.pinned(self.optional_hash(10)?);
+        broadcast.signature = self.optional_bytes(11)?.map(|signature| self.array(signature, 11)).transpose()?;
+        Ok(broadcast)
+    }
+
+    fn response(&self) -> Result<Response, ProtoError> {
+        let states = self.repeated(4)?.iter().map(Fields::state).collect::<Result<Vec<State>, ProtoError>>()?;
+        let mut response = Response::new(self.int(1)?, self.step(2)?, self.int(3)?, states).answering(self.optional_hash(5)?.unwrap_or_else(BlockHash::zero));
+        response.committee = self.optional_hash(6)?;
+        response.signature = self.optional_bytes(7)?.map(|signature| self.array(signature, 7)).transpose()?;
+        Ok(response)
+    }
+
+    fn state(&self) -> Result<State, ProtoError> {
+        let value = match self.fields.iter().rev().find(|(number, _)| (1..=3).contains(number)) {
+            Some((1, _)) => {
+                let value = self.required(1, "value")?;
+                Value::RValue(RValue::new(value.int(1)?, value.hash(2)?))
+            }
+            Some((2, _)) => Value::AValue(AValue(self.required(2, "value")?.hash(1)?)),
+            Some(_) => {
+                let value = self.required(3, "value")?;
+                Value::BValue(BValue::new(value.hash(1)?, value.bool(2)?))
+            }
+            None => return Err(ProtoError::Missing("value")),
+        };
+        Ok(State::new(value, Arc::new(self.required(4, "broadcast")?.broadcast()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::{encode_message, CertificateResponses};
+
+    #[test]
+    fn messages_survive_the_protobuf_encoding() {
+        let header = Arc::new(Broadcast::new(1, Step::B, BlockHash::from(3), Some(false), 2, None));
+        let mut response = Response::new(2, Step::B, 2, vec![State::new(Value::BValue(BValue::new(BlockHash::from(3), false)), header.clone())]).answering(header.hash);
+        response.signature = Some([4; 64]);
+        let mut broadcast = Broadcast::new(0, Step::R, BlockHash::from(3), None, 3, Some(vec![response.clone(), response.clone()].into())).pinned(Some(BlockHash::from(8)));
+        broadcast.trace = Some(TraceContext { trace_id: u128::MAX - 5, span_id: 6 });
+
+        let messages = vec![
+            Message::Broadcast(broadcast.clone()),
+            Message::Instance(1, Box::new(Message::Batch(vec![Message::Broadcast(broadcast.into_reference()), Message::Response(response.clone()), Message::Ack(-1, 0)]))),
+            Message::PreProposal(PreProposal::new(vec![BlockHash::from(2), BlockHash::from(1)], 3)),
+            Message::Certificates(1, vec![CommitRecord { instance: 4, rank: 2, value: BlockHash::from(3), certificate: vec![response].into(), committee: None }]),
+            Message::Commits(1, vec![CommitRecord {
+                instance: 5,
+                rank: 0,
+                value: BlockHash::from(3),
+                certificate: CertificateResponses::new(),
+                committee: Some(Committee { f: 1, members: BTreeMap::from([(0, [1; 32]), (4, [2; 32])]), configuration: Some(BlockHash::from(9)) }),
+            }]),
+            Message::GetResponses(0, vec![BlockHash::from(1), BlockHash::from(2), BlockHash::from_bytes([0xff; 32])]),
+            Message::Rejoin(Rejoin { sender: 2, grounds: RejoinGrounds::Unban, resume: 6, signature: [7; 64] }),
+            Message::Hello(Hello { sender: 0, version: "0.1.0".to_string(), features: Features::AGGREGATED_RESPONSES, committee: BlockHash::from(4), ask: false }),
+        ];
+
+        for message in messages {
+            let decoded = decode_protobuf(&encode_protobuf(&message)).unwrap();
+            assert_eq!(decoded, message);
+            // Fields the equality of broadcasts leaves out survive too
+            assert_eq!(encode_message(&decoded), encode_message(&message));
+        }
+    }
+
+    #[test]
+    fn protobuf_decoding_follows_proto3() {
+        // Ack { sender: 3, sequence: 5 } with an unknown field 9 and the sequence given twice
+        let ack = [0x42, 0x0b, 0x08, 0x03, 0x10, 0x04, 0x48, 0x01, 0x10, 0x05, 0x4a, 0x00];
+        assert_eq!(decode_protobuf(&ack[..ack.len() - 2]), Err(ProtoError::Truncated));
+        let ack = [&[0x42, 0x0a][..], &ack[2..]].concat();
+        assert_eq!(decode_protobuf(&ack), Ok(Message::Ack(3, 5)));
+
+        // GetResponses with a hash cut to a single byte
+        let truncated = [0x4a, 0x05, 0x08, 0x01, 0x12, 0x01, 7];
+        assert_eq!(decode_protobuf(&truncated), Err(ProtoError::Length(2)));
+
+        assert_eq!(decode_protobuf(&[]), Err(ProtoError::Missing("message")));
+        assert_eq!(decode_protobuf(&[0x2a, 0x02, 0x12, 0x00]), Err(ProtoError::Length(2)));
+        assert_eq!(decode_protobuf(&[0x0b]), Err(ProtoError::WireType(3)));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use rsnano_core::BlockHash;
+use crate::{sync::RwLock, AValue, BValue, RValue, Rank, ValueOrder};
+
+// In the first step of rank i, each process:
+// 1) Broadcasts its rank i, value v and an optional certificate containing responses of step B and rank i-1 from 2f+1 processes (if i > 0)
+// 2) Waits valid responses from 2f+1 processes
+// 3) Keeps the maximum RValue v'
+// 4) Returns (i, v')
+type R = RwLock<RValue>;
+
+// In the second step of rank i, each process:
+// 1) Broadcasts its rank i, value v and a certificate containing responses of step R and rank i from 2f+1 processes
+// 2) Waits valid responses from 2f+1 processes
+// 3) Keeps the the greatest AValue or the two greatest if there are different values
+// 4) According to the received AResponses, it returns:
+// - (true, v) if there is only one Avalue v
+// - (false, max(v)), otherwise
+type A = RwLock<HashMap<Rank, Vec<AValue>>>;
+
+// In the third step of rank i, each process:
+// 1) Broadcasts its rank i, value v, a boolean flag and a certificate containing responses of step R and rank i from 2f+1 processes
+// 2) Waits valid responses from 2f+1 processes
+// 3) Keeps the (true, v) pair and the highest (false, pair), if there is one
+// 4) According to the received BResponses, it returns:
+// - (commit, v) if there are at least 2f+1 (commit, v)
+// - (adopt, v) if there is at least 1 (commit, v)
+// - (adopt, max(v)) otherwise
+type B = RwLock<HashMap<Rank, Vec<BValue>>>;
+
+// Registers R, A and B of a process
+// Each update is a single critical section that returns a snapshot of the register, so that the response
+// built from it is never computed from a register another thread changed in between
+#[derive(Debug, Default)]
+pub struct Registers {
+    r: R,
+    a: A,
+    b: B,
+    // Order the max() and min() of the updates are taken in
+    order: ValueOrder,
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers::default()
+    }
+
+    pub fn ordered(order: ValueOrder) -> Registers {
+        Registers { order, ..Registers::default() }
+    }
+
+    pub fn order(&self) -> &ValueOrder {
+        &self.order
+    }
+
+    pub fn r(&self) -> RValue {
+        *self.r.read().unwrap()
+    }
+
+    pub fn a(&self, rank: Rank) -> Vec<AValue> {
+        self.a.read().unwrap().get(&rank).cloned().unwrap_or_default()
+    }
+
+    pub fn b(&self, rank: Rank) -> Vec<BValue> {
+        self.b.read().unwrap().get(&rank).cloned().unwrap_or_default()
+    }
+
+    pub fn a_sets(&self) -> BTreeMap<Rank, Vec<AValue>> {
+        self.a.read().unwrap().iter().map(|(rank, values)| (*rank, values.clone())).collect()
+    }
+
+    pub fn b_sets(&self) -> BTreeMap<Rank, Vec<BValue>> {
+        self.b.read().unwrap().iter().map(|(rank, pairs)| (*rank, pairs.clone())).collect()
+    }
+
+    // Line 27: R ← max(⟨j, v⟩, R)
+    pub fn update_r(&self, r_value: RValue) -> RValue {
+        let mut r = self.r.write().unwrap();
+        // The zero value of a register nothing was written to is below anything, whatever the order
+        if r.value == BlockHash::zero() || self.order.r_key(r_value) > self.order.r_key(*r) {
+            *r = r_value;
+        }
+        *r
+    }
+
+    pub fn update_a(&self, rank: Rank, a_value: AValue) -> Vec<AValue> {
+        let mut a = self.a.write().unwrap();
+        let a_set = a.entry(rank).or_default();
+
+        // Line 43: if v /∈ A[j] and |A[j]| < 2
+        if !a_set.contains(&a_value) && a_set.len() < 2 {
+            // Line 44: add v to A[j]
+            a_set.push(a_value);
+        // Line 45: v > max(A[j])
+        } else if self.order.greater(rank, a_value.0, self.order.max(rank, a_set.iter(), |a_value| a_value.0).unwrap().0) {
+            let min = *a_set.iter().min_by_key(|a_value| self.order.key(rank, a_value.0)).unwrap();
+            if let Some(index) = a_set.iter().position(|&x| x == min) {
+                // Line 46: min(A[j]) ← v
+                a_set[index] = a_value;
+            }
+        }
+
+        a_set.clone()
+    }
+
+    pub fn update_b(&self, rank: Rank, b_value: BValue) -> Vec<BValue> {
+        let mut b = self.b.write().unwrap();
+        let b_values = b.entry(rank).or_default();
+        let len = b_values.len();
+
+        // Line 63: m ← max(B[j][0].v, B[j][1].v)
+        let m = self.order.max(rank, b_values.iter().take(2), |b_value| b_value.value).map(|b_value| b_value.value);
+
+        if len < 2 {
+            // Line 64: if |B[j]| < 2 then add ⟨bool, v⟩ to B[j]
+            b_values.push(b_value);
+        }
+        else {
+            let contains_flag_value = b_values.iter().any(|value| value == &b_value);
+
+            // Lines 65/66: else if(flag ∧ ⟨flag, v⟩ ∈/ B[j] ∨ ¬flag ∧ v > m) then
+            if (b_value.flag && !contains_flag_value) || (!b_value.flag && m.is_some_and(|m| self.order.greater(rank, b_value.value, m))) {
+                // Line 67: B[j][0] ← ⟨flag, v⟩
+                b_values[0] = b_value;
+            }
+        }
+
+        b_values.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_register_keeps_the_two_greatest_values() {
+        let registers = Registers::new();
+
+        for value in [2, 1, 3, 1] {
+            registers.update_a(0, AValue(BlockHash::from(value)));
+        }
+
+        let mut a = registers.a(0);
+        a.sort();
+        assert_eq!(a, vec![AValue(BlockHash::from(2)), AValue(BlockHash::from(3))]);
+        assert!(registers.a(1).is_empty());
+    }
+}
+
+// Run with RUSTFLAGS="--cfg loom" cargo test --release --lib loom_tests
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{sync::Arc, thread};
+    use super::*;
+
+    // Two broadcasts answered concurrently: neither update is lost and each sees at least its own value
+    #[test]
+    fn loom_r_updates_are_not_lost() {
+        loom::model(|| {
+            let registers = Arc::new(Registers::new());
+            let other = Arc::clone(&registers);
+            let low = RValue::new(0, BlockHash::from(1));
+            let high = RValue::new(1, BlockHash::from(1));
+
+            let writer = thread::spawn(move || other.update_r(low));
+            let seen = registers.update_r(high);
+
+            assert!(writer.join().unwrap() >= low);
+            assert_eq!(seen, high);
+            assert_eq!(registers.r(), high);
+        });
+    }
+
+    #[test]
+    fn loom_a_register_never_exceeds_two_values() {
+        loom::model(|| {
+            let registers = Arc::new(Registers::new());
+            let writers: Vec<_> = (1..=3)
+                .map(|value| {
+                    let registers = Arc::clone(&registers);
+                    thread::spawn(move || registers.update_a(0, AValue(BlockHash::from(value))))
+                })
+                .collect();
+
+            for writer in writers {
+                assert!(writer.join().unwrap().len() <= 2);
+            }
+
+            // The greatest value always makes it into A[0], whatever the order of the updates
+            let a = registers.a(0);
+            assert_eq!(a.len(), 2);
+            assert!(a.contains(&AValue(BlockHash::from(3))));
+        });
+    }
+
+    #[test]
+    fn loom_b_register_keeps_the_true_pair() {
+        loom::model(|| {
+            let registers = Arc::new(Registers::new());
+            let other = Arc::clone(&registers);
+            let commit = BValue::new(BlockHash::from(1), true);
+            let adopt = BValue::new(BlockHash::from(2), false);
+
+            let writer = thread::spawn(move || other.update_b(0, commit));
+            let seen = registers.update_b(0, adopt);
+
+            assert!(writer.join().unwrap().contains(&commit));
+            assert!(seen.contains(&adopt));
+
+            let mut b = registers.b(0);
+            b.sort_by_key(|b_value| b_value.flag);
+            assert_eq!(b, vec![adopt, commit]);
+        });
+    }
+}
@@ -0,0 +1,289 @@
+use std::{fmt, fs::OpenOptions, io::Write, path::Path};
+use rsnano_core::BlockHash;
+use crate::{Delay, FaultAction, Faults, Id, LinkFaults, PreProposal, ScriptedFault, SimConfig, SimOutcome, Simulation};
+
+// Committed corpus of failing schedules, replayed by the regression tests
+pub const CORPUS: &str = include_str!("../regressions/schedules.txt");
+
+pub fn corpus_path() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/regressions/schedules.txt"))
+}
+
+// Everything a simulation depends on, so that a failing run is replayed exactly from one line of the corpus:
+// the simulator is deterministic given its configuration, faults and preproposals, and the delivery schedule
+// of a fuzzed run is the list of indices passed to Simulation::step_pending before it ran on its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub name: String,
+    pub config: SimConfig,
+    pub link: LinkFaults,
+    pub script: Vec<ScriptedFault>,
+    pub preproposals: Vec<PreProposal>,
+    pub schedule: Vec<usize>,
+    // Whether every correct node must decide, which only holds for runs without lasting faults
+    pub live: bool,
+}
+
+impl Regression {
+    pub fn new(name: &str, config: SimConfig, preproposals: Vec<PreProposal>) -> Regression {
+        Regression {
+            name: name.to_string(),
+            config,
+            link: LinkFaults::default(),
+            script: Vec::new(),
+            preproposals,
+            schedule: Vec::new(),
+            live: false,
+        }
+    }
+
+    pub fn with_link(mut self, link: LinkFaults) -> Regression {
+        self.link = link;
+        self
+    }
+
+    pub fn with_script(mut self, script: Vec<ScriptedFault>) -> Regression {
+        self.script = script;
+        self
+    }
+
+    pub fn with_schedule(mut self, schedule: Vec<usize>) -> Regression {
+        self.schedule = schedule;
+        self
+    }
+
+    pub fn live(mut self) -> Regression {
+        self.live = true;
+        self
+    }
+
+    pub fn run(&self) -> (SimOutcome, Simulation) {
+        let faults = self.script.iter().fold(Faults::new(self.link), |faults, fault| faults.with_scripted(*fault));
+        let mut simulation = Simulation::with_faults(self.config, self.preproposals.clone(), faults);
+
+        for &index in &self.schedule {
+            simulation.step_pending(index);
+        }
+        (simulation.run(), simulation)
+    }
+
+    // Agreement, validity and, for live runs, termination
+    pub fn check(&self) -> Result<(), String> {
+        let (outcome, simulation) = self.run();
+        let proposals = simulation.proposals();
+
+        if !outcome.agreement() {
+            return Err(format!("{}: correct nodes disagree, {:?}", self.name, outcome.decisions));
+        }
+        if let Some(decision) = outcome.decisions.iter().flatten().find(|decision| !proposals.contains(decision)) {
+            return Err(format!("{}: {:?} was never proposed", self.name, decision));
+        }
+        if self.live && !outcome.all_decided() {
+            return Err(format!("{}: not every correct node decided, {:?}", self.name, outcome.decisions));
+        }
+        Ok(())
+    }
+
+    // Same as check, but a failing regression is first appended to the corpus, to be committed with the fix
+    pub fn check_or_record(&self) -> Result<(), String> {
+        self.check().inspect_err(|_| {
+            if let Err(error) = self.record(corpus_path()) {
+                eprintln!("cannot record {}: {}", self.name, error);
+            }
+        })
+    }
+
+    pub fn record(&self, path: &Path) -> std::io::Result<()> {
+        let line = self.to_string();
+
+        if std::fs::read_to_string(path).is_ok_and(|corpus| corpus.lines().any(|recorded| recorded == line)) {
+            return Ok(());
+        }
+        writeln!(OpenOptions::new().create(true).append(true).open(path)?, "{}", line)
+    }
+
+    pub fn parse(line: &str) -> Result<Regression, String> {
+        let mut regression = Regression::new("", SimConfig::default(), Vec::new());
+        let config = &mut regression.config;
+
+        for field in line.split_whitespace() {
+            let (key, value) = field.split_once('=').ok_or_else(|| format!("invalid field {:?}", field))?;
+            let invalid = || format!("invalid {} {:?}", key, value);
+
+            match key {
+                "name" => regression.name = value.to_string(),
+                "nodes" => config.nodes = value.parse().map_err(|_| invalid())?,
+                "f" => config.f = value.parse().map_err(|_| invalid())?,
+                "seed" => config.seed = value.parse().map_err(|_| invalid())?,
+                "latency" => {
+                    let (min, max) = value.split_once("..").ok_or_else(invalid)?;
+                    config.min_latency = min.parse().map_err(|_| invalid())?;
+                    config.max_latency = max.parse().map_err(|_| invalid())?;
+                }
+                "time_limit" => config.time_limit = value.parse().map_err(|_| invalid())?,
+                "by_reference" => config.config.certificates_by_reference = value.parse().map_err(|_| invalid())?,
+                "forced_adopt_ranks" => config.config.forced_adopt_ranks = value.parse().map_err(|_| invalid())?,
+                "drop" => regression.link.drop_rate = value.parse().map_err(|_| invalid())?,
+                "duplicate" => regression.link.duplicate_rate = value.parse().map_err(|_| invalid())?,
+                "reorder" => regression.link.reorder_rate = value.parse().map_err(|_| invalid())?,
+                "delay" => regression.link.delay = parse_delay(value).ok_or_else(invalid)?,
+                "script" => regression.script = list(value, ';').map(parse_fault).collect::<Option<_>>().ok_or_else(invalid)?,
+                "preproposals" => {
+                    regression.preproposals = list(value, ';')
+                        .enumerate()
+                        .map(|(id, frontiers)| {
+                            let frontiers = list(frontiers, ',').map(|frontier| BlockHash::decode_hex(frontier).ok()).collect::<Option<_>>()?;
+                            Some(PreProposal::new(frontiers, id as Id))
+                        })
+                        .collect::<Option<_>>()
+                        .ok_or_else(invalid)?;
+                }
+                "schedule" => regression.schedule = list(value, ',').map(str::parse).collect::<Result<_, _>>().map_err(|_| invalid())?,
+                "live" => regression.live = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown field {:?}", key)),
+            }
+        }
+
+        if regression.preproposals.len() != regression.config.nodes {
+            return Err(format!("{}: one preproposal per node", regression.name));
+        }
+        Ok(regression)
+    }
+}
+
+fn list(value: &str, separator: char) -> impl Iterator<Item = &str> {
+    value.split(separator).filter(|item| !item.is_empty())
+}
+
+fn parse_delay(value: &str) -> Option<Delay> {
+    let parts: Vec<&str> = value.split(':').collect();
+    let number = |index: usize| parts.get(index)?.parse().ok();
+
+    match parts[0] {
+        "fixed" => Some(Delay::Fixed(number(1)?)),
+        "uniform" => Some(Delay::Uniform(number(1)?, number(2)?)),
+        "exponential" => Some(Delay::Exponential { mean: number(1)? }),
+        _ => None,
+    }
+}
+
+// <from>:<to>:<start>:<end>:<drop|hold>, * standing for any node
+fn parse_fault(value: &str) -> Option<ScriptedFault> {
+    let parts: Vec<&str> = value.split(':').collect();
+    let [from, to, start, end, action] = parts[..] else {
+        return None;
+    };
+    let node = |part: &str| if part == "*" { Some(None) } else { part.parse().ok().map(Some) };
+
+    Some(ScriptedFault {
+        from: node(from)?,
+        to: node(to)?,
+        start: start.parse().ok()?,
+        end: end.parse().ok()?,
+        action: match action {
+            "drop" => FaultAction::Drop,
+            "hold" => FaultAction::Hold,
+            _ => return None,
+        },
+    })
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = &self.config;
+        let node = |node: Option<Id>| node.map_or("*".to_string(), |node| node.to_string());
+        let delay = match self.link.delay {
+            Delay::Fixed(delay) => format!("fixed:{}", delay),
+            Delay::Uniform(min, max) => format!("uniform:{}:{}", min, max),
+            Delay::Exponential { mean } => format!("exponential:{}", mean),
+        };
+        let script: Vec<String> = self.script
+            .iter()
+            .map(|fault| {
+                let action = match fault.action {
+                    FaultAction::Drop => "drop",
+                    FaultAction::Hold => "hold",
+                };
+                format!("{}:{}:{}:{}:{}", node(fault.from), node(fault.to), fault.start, fault.end, action)
+            })
+            .collect();
+        let preproposals: Vec<String> = self.preproposals
+            .iter()
+            .map(|preproposal| preproposal.frontiers().iter().map(BlockHash::encode_hex).collect::<Vec<_>>().join(","))
+            .collect();
+        let schedule: Vec<String> = self.schedule.iter().map(usize::to_string).collect();
+
+        write!(
+            f,
+            "name={} nodes={} f={} seed={} latency={}..{} time_limit={} by_reference={} forced_adopt_ranks={} drop={} duplicate={} reorder={} delay={} script={} preproposals={} schedule={} live={}",
+            self.name,
+            config.nodes,
+            config.f,
+            config.seed,
+            config.min_latency,
+            config.max_latency,
+            config.time_limit,
+            config.config.certificates_by_reference,
+            config.config.forced_adopt_ranks,
+            self.link.drop_rate,
+            self.link.duplicate_rate,
+            self.link.reorder_rate,
+            delay,
+            script.join(";"),
+            preproposals.join(";"),
+            schedule.join(","),
+            self.live
+        )
+    }
+}
+
+// Regressions of the corpus, comments and blank lines skipped
+pub fn corpus() -> Result<Vec<Regression>, String> {
+    CORPUS
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(Regression::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_schedules_still_pass() {
+        for regression in corpus().unwrap() {
+            regression.check().unwrap();
+        }
+    }
+
+    #[test]
+    fn regression_round_trips_through_text() {
+        let preproposals = (0..4).map(|id| PreProposal::new(vec![BlockHash::from(id as u64), BlockHash::from(9)], id)).collect();
+        let regression = Regression::new("lossy", SimConfig { seed: 3, ..SimConfig::default() }, preproposals)
+            .with_link(LinkFaults { drop_rate: 0.05, delay: Delay::Uniform(1, 2), ..LinkFaults::default() })
+            .with_script(vec![ScriptedFault { from: Some(2), to: None, start: 0, end: 10, action: FaultAction::Hold }])
+            .with_schedule(vec![3, 0, 1])
+            .live();
+
+        assert_eq!(Regression::parse(&regression.to_string()), Ok(regression.clone()));
+        assert!(Regression::parse("name=short nodes=4").is_err());
+    }
+
+    #[test]
+    fn failing_regression_is_recorded_once() {
+        let path = std::env::temp_dir().join(format!("archipelago-regressions-{}.txt", std::process::id()));
+        let preproposals = (0..4).map(|id| PreProposal::new(vec![BlockHash::from(id as u64)], id)).collect();
+        // Nothing is ever delivered, so nobody decides
+        let mute = ScriptedFault { from: None, to: None, start: 0, end: u64::MAX, action: FaultAction::Drop };
+        let regression = Regression::new("muted", SimConfig::default(), preproposals).with_script(vec![mute]).live();
+
+        assert!(regression.check().is_err());
+        regression.record(&path).unwrap();
+        regression.record(&path).unwrap();
+
+        let recorded = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recorded.lines().map(Regression::parse).collect::<Result<Vec<_>, _>>(), Ok(vec![regression]));
+    }
+}
@@ -0,0 +1,126 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{Id, Identities, PeerTable, ProposalHash};
+
+// Reconfiguration the committee commits to take back a member it banned for equivocation, see Rejoin::value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejoinGrounds {
+    // The member comes back under this key, which the request is signed with
+    Rekey([u8; 32]),
+    // The member comes back under the key on record, which the request is signed with
+    Unban,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejoinError {
+    // The fresh key is the one on record, or no key is on record for an unban
+    StaleKey,
+    InvalidSignature,
+}
+
+// Sent by a member banned for equivocation to be taken back, before it announces itself to catch up from a checkpoint
+// of the committee (Process::rejoin). The members hold it until they commit its reconfiguration value, and take the
+// member back at that instance, so that they all move to its key together. It neither answers nor votes before the
+// resume instance
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rejoin {
+    pub sender: Id,
+    pub grounds: RejoinGrounds,
+    pub resume: u64,
+    pub signature: [u8; 64],
+}
+
+impl Rejoin {
+    pub fn new(sender: Id, grounds: RejoinGrounds, resume: u64, key: &SigningKey) -> Rejoin {
+        let signature = key.sign(Rejoin::digest(sender, grounds, resume).as_bytes()).to_bytes();
+        Rejoin { sender, grounds, resume, signature }
+    }
+
+    fn digest(sender: Id, grounds: RejoinGrounds, resume: u64) -> BlockHash {
+        let hasher = Blake2HashBuilder::new().update(b"rejoin ").update(sender.to_le_bytes());
+        let hasher = match grounds {
+            RejoinGrounds::Rekey(key) => hasher.update([0]).update(key),
+            RejoinGrounds::Unban => hasher.update([1]),
+        };
+        hasher.update(resume.to_le_bytes()).build()
+    }
+
+    // Value the committee commits to take the member back on these grounds
+    pub fn value(member: Id, grounds: RejoinGrounds) -> ProposalHash {
+        match grounds {
+            RejoinGrounds::Rekey(key) => Blake2HashBuilder::new().update(b"rekey ").update(member.to_le_bytes()).update(key).build(),
+            RejoinGrounds::Unban => Blake2HashBuilder::new().update(b"unban ").update(member.to_le_bytes()).build(),
+        }
+    }
+
+    pub fn reconfiguration(&self) -> ProposalHash {
+        Rejoin::value(self.sender, self.grounds)
+    }
+
+    // Checks the signature against the key of the grounds, the one on record for an unban
+    pub fn verify(&self, on_record: Option<&VerifyingKey>) -> Result<(), RejoinError> {
+        let key = match self.grounds {
+            RejoinGrounds::Rekey(key) if on_record.is_some_and(|on_record| on_record.to_bytes() == key) => return Err(RejoinError::StaleKey),
+            RejoinGrounds::Rekey(key) => VerifyingKey::from_bytes(&key).map_err(|_| RejoinError::InvalidSignature)?,
+            RejoinGrounds::Unban => *on_record.ok_or(RejoinError::StaleKey)?,
+        };
+
+        key.verify(Rejoin::digest(self.sender, self.grounds, self.resume).as_bytes(), &Signature::from_bytes(&self.signature))
+            .map_err(|_| RejoinError::InvalidSignature)
+    }
+
+    // Takes the member back at the instance that committed its reconfiguration: under its fresh key, which is part of
+    // the pinned configuration, or under the one on record
+    pub fn apply(&self, table: &PeerTable, identities: &Identities, f: usize) {
+        if let RejoinGrounds::Rekey(key) = self.grounds {
+            // Without a committee, responses are not checked against any key
+            if let Some(key) = VerifyingKey::from_bytes(&key).ok().filter(|_| identities.enabled()) {
+                identities.rekey(self.sender, key);
+                if identities.pinned().is_some() {
+                    identities.pin(identities.configuration(f, 0));
+                }
+            }
+        }
+        table.readmit(self.sender);
+    }
+
+    // Takes back the member whose held request the committed value is the reconfiguration of, if any
+    pub fn reconfigure(value: ProposalHash, table: &PeerTable, identities: &Identities, f: usize) -> Option<Id> {
+        let rejoin = table.take_held(&value)?;
+        rejoin.apply(table, identities, f);
+        Some(rejoin.sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[test]
+    fn rejoins_are_signed_with_the_key_of_their_grounds() {
+        let (old, fresh) = (key(1), key(2));
+        let on_record = old.verifying_key();
+
+        let rekeyed = Rejoin::new(3, RejoinGrounds::Rekey(fresh.verifying_key().to_bytes()), 10, &fresh);
+        assert_eq!(rekeyed.verify(Some(&on_record)), Ok(()));
+        assert_eq!(rekeyed.verify(None), Ok(()));
+        assert_eq!(Rejoin { resume: 11, ..rekeyed.clone() }.verify(Some(&on_record)), Err(RejoinError::InvalidSignature));
+
+        let stale = Rejoin::new(3, RejoinGrounds::Rekey(on_record.to_bytes()), 10, &old);
+        assert_eq!(stale.verify(Some(&on_record)), Err(RejoinError::StaleKey));
+
+        let unbanned = Rejoin::new(3, RejoinGrounds::Unban, 10, &old);
+        assert_eq!(unbanned.verify(Some(&on_record)), Ok(()));
+        assert_eq!(unbanned.verify(None), Err(RejoinError::StaleKey));
+        assert_eq!(Rejoin::new(3, RejoinGrounds::Unban, 10, &fresh).verify(Some(&on_record)), Err(RejoinError::InvalidSignature));
+
+        // The committee commits to the member and to the key it comes back under
+        assert_ne!(rekeyed.reconfiguration(), Rejoin::value(3, RejoinGrounds::Rekey(key(4).verifying_key().to_bytes())));
+        assert_ne!(rekeyed.reconfiguration(), Rejoin::value(4, rekeyed.grounds));
+        assert_ne!(unbanned.reconfiguration(), Rejoin::value(4, RejoinGrounds::Unban));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{sync::{atomic::{AtomicU64, Ordering}, Arc, RwLock}, time::Duration};
+use log::LevelFilter;
+use crate::Config;
+
+// Settings that can change while a process runs, without restarting it or dropping the instances in flight. The ones
+// left at None keep their value
+// Read from `key=value` lines, `#` starting a comment:
+//   stall_timeout_ms         milliseconds, or none to never report stalls
+//   max_batch_size           messages coalesced per peer at most
+//   max_batch_delay_us       microseconds the oldest queued message may wait for a batch
+//   outbound_queue_capacity  messages each peer may have queued before further ones are dropped, at least 1
+//   submission_capacity      submitted values a daemon holds before refusing further ones
+//   log_level                off, error, warn, info, debug or trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Reload {
+    pub stall_timeout: Option<Option<Duration>>,
+    pub max_batch_size: Option<usize>,
+    pub max_batch_delay: Option<Duration>,
+    pub outbound_queue_capacity: Option<usize>,
+    pub submission_capacity: Option<usize>,
+    pub log_level: Option<LevelFilter>,
+}
+
+impl Reload {
+    pub fn parse(text: &str) -> Result<Reload, String> {
+        let mut reload = Reload::default();
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=').map(|(key, value)| (key.trim(), value.trim())) else {
+                return Err(format!("expected key=value, got {:?}", line));
+            };
+            let number = || value.parse::<u64>().map_err(|_| format!("invalid {}: {:?}", key, value));
+
+            match key {
+                "stall_timeout_ms" if value == "none" => reload.stall_timeout = Some(None),
+                "stall_timeout_ms" => reload.stall_timeout = Some(Some(Duration::from_millis(number()?))),
+                "max_batch_size" => reload.max_batch_size = Some(number()? as usize),
+                "max_batch_delay_us" => reload.max_batch_delay = Some(Duration::from_micros(number()?)),
+                "outbound_queue_capacity" => reload.outbound_queue_capacity = Some(number()? as usize),
+                "submission_capacity" => reload.submission_capacity = Some(number()? as usize),
+                "log_level" => reload.log_level = Some(value.parse().map_err(|_| format!("invalid log_level: {:?}", value))?),
+                _ => return Err(format!("unknown setting {:?}", key)),
+            }
+        }
+        Ok(reload)
+    }
+
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(stall_timeout) = self.stall_timeout {
+            config.stall_timeout = stall_timeout;
+        }
+        if let Some(max_batch_size) = self.max_batch_size {
+            config.batch.max_batch_size = max_batch_size.max(1);
+        }
+        if let Some(max_delay) = self.max_batch_delay {
+            config.batch.max_delay = max_delay;
+        }
+        if let Some(capacity) = self.outbound_queue_capacity {
+            config.outbound_queue_capacity = capacity.max(1);
+        }
+        if let Some(capacity) = self.submission_capacity {
+            config.submission_capacity = capacity;
+        }
+    }
+}
+
+// Config of a process with the reloads applied so far
+// Clones share it, so the run loop and the watchdog of the process see the values reloaded through any of its handles
+#[derive(Debug, Clone, Default)]
+pub struct LiveConfig {
+    config: Arc<RwLock<Config>>,
+    generation: Arc<AtomicU64>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config) -> LiveConfig {
+        LiveConfig { config: Arc::new(RwLock::new(config)), generation: Arc::default() }
+    }
+
+    pub fn get(&self) -> Config {
+        *self.config.read().unwrap()
+    }
+
+    pub fn reload(&self, reload: &Reload) {
+        reload.apply(&mut self.config.write().unwrap());
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    // The config if it was reloaded since the generation seen, which moves on to the current one
+    pub fn changed(&self, seen: &mut u64) -> Option<Config> {
+        let generation = self.generation.load(Ordering::Acquire);
+        if generation == *seen {
+            return None;
+        }
+        *seen = generation;
+        Some(self.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloads_change_only_the_settings_given() {
+        let reload = Reload::parse("# tuned\nstall_timeout_ms = none\n\nmax_batch_size=0\nlog_level = debug # for now\n").unwrap();
+        assert_eq!(reload, Reload { stall_timeout: Some(None), max_batch_size: Some(0), log_level: Some(LevelFilter::Debug), ..Reload::default() });
+
+        let live = LiveConfig::new(Config::default());
+        let mut seen = 0;
+        assert_eq!(live.changed(&mut seen), None);
+
+        live.clone().reload(&reload);
+        let config = live.changed(&mut seen).unwrap();
+        assert_eq!((config.stall_timeout, config.batch.max_batch_size), (None, 1));
+        assert_eq!(config.batch.max_delay, Config::default().batch.max_delay);
+        assert_eq!(live.changed(&mut seen), None);
+
+        assert!(Reload::parse("stall_timeout_ms = soon").is_err());
+        assert!(Reload::parse("instance_window = 8").is_err());
+        assert!(Reload::parse("log_level").is_err());
+    }
+}
@@ -0,0 +1,432 @@
+use std::{collections::{HashMap, HashSet}, sync::atomic::Ordering, time::{Duration, Instant}};
+use crate::{sync::{AtomicBool, Condvar, Mutex, MutexGuard}, CertificateResponses, Id, MemorySize, Rank, Response, ResponseHash, Step};
+
+const SHARDS: usize = 8;
+
+type Shard = HashMap<(Step, Rank), HashMap<Id, Response>>;
+
+// Each process receives 2f+1 responses per step and rank, n-f with fast commits
+// Responses are sharded by rank so that proposers waiting on a quorum and the run loop inserting
+// responses of other ranks do not contend, and waiters are woken up instead of spinning
+#[derive(Debug)]
+pub struct ResponseStore {
+    shards: Vec<(Mutex<Shard>, Condvar)>,
+    // Waiters return None once the store is closed, see Process::stop
+    closed: AtomicBool,
+}
+
+impl Default for ResponseStore {
+    fn default() -> Self {
+        ResponseStore::new()
+    }
+}
+
+impl ResponseStore {
+    pub fn new() -> ResponseStore {
+        let shards = (0..SHARDS).map(|_| (Mutex::new(HashMap::new()), Condvar::new())).collect();
+        ResponseStore { shards, closed: AtomicBool::new(false) }
+    }
+
+    // Wakes up every waiter, and has later waits return right away
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        for (lock, condvar) in &self.shards {
+            let _shard = lock.lock().unwrap();
+            condvar.notify_all();
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    fn shard(&self, rank: Rank) -> &(Mutex<Shard>, Condvar) {
+        &self.shards[rank.rem_euclid(SHARDS as i64) as usize]
+    }
+
+    // Keeps the first response of each sender, up to threshold responses per step and rank
+    pub fn insert(&self, response: Response, threshold: usize) -> bool {
+        let (lock, condvar) = self.shard(response.rank);
+        let mut shard = lock.lock().unwrap();
+        let entry = shard.entry((response.step, response.rank)).or_default();
+
+        if entry.contains_key(&response.sender) || entry.len() >= threshold {
+            return false;
+        }
+
+        entry.insert(response.sender, response);
+        condvar.notify_all();
+        true
+    }
+
+    // Replaces the stored response of the sender if `replaces` holds for it, and returns the replaced one. For answers
+    // that a sender sent before the stored one, but that only became reliable after it
+    pub fn replace(&self, response: Response, replaces: impl FnOnce(&Response) -> bool) -> Option<Response> {
+        let (lock, condvar) = self.shard(response.rank);
+        let mut shard = lock.lock().unwrap();
+        let stored = shard.get_mut(&(response.step, response.rank))?.get_mut(&response.sender)?;
+
+        if *stored == response || !replaces(stored) {
+            return None;
+        }
+
+        let replaced = std::mem::replace(stored, response);
+        condvar.notify_all();
+        Some(replaced)
+    }
+
+    pub fn count(&self, step: Step, rank: Rank) -> usize {
+        let (lock, _) = self.shard(rank);
+        lock.lock().unwrap().get(&(step, rank)).map_or(0, |responses| responses.len())
+    }
+
+    pub fn get(&self, step: Step, rank: Rank) -> Option<Vec<Response>> {
+        let (lock, _) = self.shard(rank);
+        lock.lock().unwrap().get(&(step, rank)).map(|responses| responses.values().cloned().collect())
+    }
+
+    // Senders of the responses stored for the step and rank
+    pub fn senders(&self, step: Step, rank: Rank) -> Vec<Id> {
+        let (lock, _) = self.shard(rank);
+        lock.lock().unwrap().get(&(step, rank)).map_or_else(Vec::new, |responses| responses.keys().copied().collect())
+    }
+
+    // Highest rank for which some step has gathered threshold responses
+    pub fn latest_quorum_rank(&self, threshold: usize) -> Option<Rank> {
+        self.shards
+            .iter()
+            .filter_map(|(lock, _)| {
+                let shard = lock.lock().unwrap();
+                shard.iter()
+                    .filter(|(_, responses)| responses.len() >= threshold)
+                    .map(|((_, rank), _)| *rank)
+                    .max()
+            })
+            .max()
+    }
+
+    // Steps and ranks with stored responses, together with their senders
+    pub fn all_senders(&self) -> Vec<(Step, Rank, Vec<Id>)> {
+        self.shards
+            .iter()
+            .flat_map(|(lock, _)| {
+                let shard = lock.lock().unwrap();
+                shard.iter().map(|((step, rank), responses)| (*step, *rank, responses.keys().copied().collect())).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    // Every stored response by step and rank, for reports rather than the run loop
+    pub fn snapshot(&self) -> Vec<(Step, Rank, Vec<Response>)> {
+        self.shards
+            .iter()
+            .flat_map(|(lock, _)| {
+                let shard = lock.lock().unwrap();
+                shard.iter().map(|((step, rank), responses)| (*step, *rank, responses.values().cloned().collect())).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    pub fn evict_rank(&self, rank: Rank) {
+        let (lock, _) = self.shard(rank);
+        lock.lock().unwrap().retain(|(_, r), _| *r != rank);
+    }
+
+    // Blocks until `ready` holds for the number of responses of the given step and rank, and returns with the shard
+    // locked. None once the store is closed
+    fn wait_until(&self, step: Step, rank: Rank, ready: impl Fn(usize) -> bool) -> Option<MutexGuard<'_, Shard>> {
+        let (lock, condvar) = self.shard(rank);
+        let mut shard = lock.lock().unwrap();
+
+        while !ready(shard.get(&(step, rank)).map_or(0, |responses| responses.len())) {
+            if self.is_closed() {
+                return None;
+            }
+            shard = condvar.wait(shard).unwrap();
+        }
+        Some(shard)
+    }
+
+    // Blocks until threshold responses of the given step and rank have been stored, None once the store is closed
+    pub fn wait_for_quorum(&self, step: Step, rank: Rank, threshold: usize) -> Option<CertificateResponses> {
+        let shard = self.wait_until(step, rank, |count| count >= threshold)?;
+
+        Some(shard[&(step, rank)].values().cloned().collect())
+    }
+
+    // Same as wait_for_quorum, but hands every response to on_response as soon as it is stored
+    // The lock is released while on_response runs, responses are returned in arrival order
+    pub fn wait_for_quorum_with(
+        &self,
+        step: Step,
+        rank: Rank,
+        threshold: usize,
+        mut on_response: impl FnMut(&Response),
+    ) -> Option<CertificateResponses> {
+        let mut seen = HashSet::new();
+        let mut certificate = CertificateResponses::with_capacity(threshold);
+
+        while certificate.len() < threshold {
+            let fresh: Vec<Response> = {
+                let shard = self.wait_until(step, rank, |count| count > seen.len())?;
+
+                shard[&(step, rank)]
+                    .iter()
+                    .filter(|(sender, _)| !seen.contains(*sender))
+                    .map(|(_, response)| response.clone())
+                    .collect()
+            };
+
+            for response in fresh {
+                seen.insert(response.sender);
+                on_response(&response);
+                certificate.push(response);
+            }
+        }
+
+        Some(certificate)
+    }
+
+    // Hands on_response the responses of the step and rank stored besides the ones seen, replacements included, until
+    // it returns true, the timeout passes or the store is closed. Returns the seen responses with the ones handed over
+    // if it returned true, one per sender
+    pub fn wait_for_more(
+        &self,
+        step: Step,
+        rank: Rank,
+        seen: &CertificateResponses,
+        timeout: Duration,
+        mut on_response: impl FnMut(&Response) -> bool,
+    ) -> Option<CertificateResponses> {
+        let deadline = Instant::now() + timeout;
+        let (lock, condvar) = self.shard(rank);
+        let mut hashes: HashSet<ResponseHash> = seen.iter().map(Response::hash_value).collect();
+        let mut certificate = seen.clone();
+
+        loop {
+            let fresh: Vec<Response> = {
+                let mut shard = lock.lock().unwrap();
+                loop {
+                    let fresh: Vec<Response> = shard.get(&(step, rank))
+                        .into_iter()
+                        .flat_map(HashMap::values)
+                        .filter(|response| !hashes.contains(&response.hash_value()))
+                        .cloned()
+                        .collect();
+                    if !fresh.is_empty() {
+                        break fresh;
+                    }
+                    if self.is_closed() {
+                        return None;
+                    }
+                    let remaining = deadline.checked_duration_since(Instant::now()).filter(|remaining| !remaining.is_zero())?;
+                    shard = condvar.wait_timeout(shard, remaining).unwrap().0;
+                }
+            };
+
+            for response in fresh {
+                hashes.insert(response.hash_value());
+                let done = on_response(&response);
+                certificate.retain(|kept| kept.sender != response.sender);
+                certificate.push(response);
+                if done {
+                    return Some(certificate);
+                }
+            }
+        }
+    }
+}
+
+// Goes through every shard, for reports rather than the run loop
+impl MemorySize for ResponseStore {
+    fn memory_size(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|(lock, _)| lock.lock().unwrap().values().flat_map(HashMap::values).map(MemorySize::memory_size).sum::<usize>())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+    use super::*;
+
+    #[test]
+    fn keeps_one_response_per_sender_up_to_threshold() {
+        let store = ResponseStore::new();
+
+        assert!(store.insert(Response::new(0, Step::R, 0, Vec::new()), 2));
+        assert!(!store.insert(Response::new(0, Step::R, 0, Vec::new()), 2));
+        assert!(store.insert(Response::new(1, Step::R, 0, Vec::new()), 2));
+        assert!(!store.insert(Response::new(2, Step::R, 0, Vec::new()), 2));
+
+        assert_eq!(store.count(Step::R, 0), 2);
+        assert_eq!(store.count(Step::A, 0), 0);
+        assert!(store.get(Step::A, 0).is_none());
+    }
+
+    #[test]
+    fn evicts_stale_ranks() {
+        let store = ResponseStore::new();
+
+        for rank in 0..3 {
+            store.insert(Response::new(0, Step::R, rank, Vec::new()), 1);
+        }
+        store.insert(Response::new(0, Step::A, 5, Vec::new()), 2);
+        assert_eq!(store.latest_quorum_rank(1), Some(5));
+        assert_eq!(store.latest_quorum_rank(2), None);
+
+        store.evict_rank(0);
+        assert!(store.get(Step::R, 0).is_none());
+        assert_eq!(store.count(Step::R, 1), 1);
+    }
+
+    #[test]
+    fn wakes_up_waiter_on_quorum() {
+        let store = Arc::new(ResponseStore::new());
+        let store_clone = Arc::clone(&store);
+
+        let waiter = thread::spawn(move || store_clone.wait_for_quorum(Step::B, 3, 3).unwrap());
+
+        for sender in 0..3 {
+            store.insert(Response::new(sender, Step::B, 3, Vec::new()), 3);
+        }
+
+        assert_eq!(waiter.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn wakes_up_waiters_once_closed() {
+        let store = Arc::new(ResponseStore::new());
+        let store_clone = Arc::clone(&store);
+        store.insert(Response::new(0, Step::A, 1, Vec::new()), 3);
+
+        let waiter = thread::spawn(move || store_clone.wait_for_quorum_with(Step::A, 1, 3, |_| ()));
+        store.close();
+
+        assert_eq!(waiter.join().unwrap(), None);
+        assert_eq!(store.wait_for_quorum(Step::B, 0, 1), None);
+    }
+
+    #[test]
+    fn hands_out_responses_while_waiting() {
+        let store = Arc::new(ResponseStore::new());
+        let store_clone = Arc::clone(&store);
+
+        store.insert(Response::new(0, Step::A, 1, Vec::new()), 3);
+
+        let waiter = thread::spawn(move || {
+            let mut senders = Vec::new();
+            let certificate = store_clone.wait_for_quorum_with(Step::A, 1, 3, |response| senders.push(response.sender)).unwrap();
+            (senders, certificate)
+        });
+
+        for sender in 1..3 {
+            store.insert(Response::new(sender, Step::A, 1, Vec::new()), 3);
+        }
+
+        let (mut senders, certificate) = waiter.join().unwrap();
+        assert_eq!(certificate.iter().map(|response| response.sender).collect::<Vec<_>>(), senders);
+        senders.sort();
+        assert_eq!(senders, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn waits_for_more_responses_until_done_or_timed_out() {
+        let store = ResponseStore::new();
+        for sender in 0..4 {
+            store.insert(Response::new(sender, Step::B, 2, Vec::new()), 4);
+        }
+        let seen = store.wait_for_quorum(Step::B, 2, 3).unwrap().into_iter().take(3).collect();
+
+        let mut handed_out = Vec::new();
+        let certificate = store.wait_for_more(Step::B, 2, &seen, Duration::from_secs(10), |response| {
+            handed_out.push(response.sender);
+            true
+        });
+        assert_eq!(certificate.map(|certificate| certificate.len()), Some(4));
+        assert_eq!(handed_out.len(), 1);
+
+        let all = store.wait_for_quorum(Step::B, 2, 4).unwrap();
+        assert_eq!(store.wait_for_more(Step::B, 2, &all, Duration::from_millis(10), |_| true), None);
+    }
+
+    #[test]
+    fn hands_replaced_responses_over_once_per_sender() {
+        let store = ResponseStore::new();
+        for sender in 0..3 {
+            store.insert(Response::new(sender, Step::B, 0, Vec::new()), 4);
+        }
+        let seen = store.wait_for_quorum(Step::B, 0, 3).unwrap();
+
+        let earlier = Response::new(1, Step::B, 0, Vec::new()).answering(7.into());
+        assert!(store.replace(earlier.clone(), |_| false).is_none());
+        assert_eq!(store.replace(earlier.clone(), |_| true), Some(Response::new(1, Step::B, 0, Vec::new())));
+        assert!(store.replace(earlier.clone(), |_| true).is_none());
+        assert!(store.replace(Response::new(3, Step::B, 0, Vec::new()), |_| true).is_none());
+
+        let certificate = store.wait_for_more(Step::B, 0, &seen, Duration::from_secs(10), |response| {
+            assert_eq!(*response, earlier);
+            true
+        });
+        let mut senders: Vec<Id> = certificate.unwrap().iter().map(|response| response.sender).collect();
+        senders.sort();
+        assert_eq!(senders, vec![0, 1, 2]);
+    }
+}
+
+// Run with RUSTFLAGS="--cfg loom" cargo test --release --lib loom_tests
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{sync::Arc, thread};
+    use super::*;
+
+    // The run loop stores the responses while the proposer waits for the quorum: the proposer is always woken up
+    // and never sees a response twice, whatever the interleaving
+    #[test]
+    fn loom_proposer_wakes_up_on_quorum() {
+        loom::model(|| {
+            let store = Arc::new(ResponseStore::new());
+            let run_loop = Arc::clone(&store);
+
+            let inserter = thread::spawn(move || {
+                for sender in 0..2 {
+                    run_loop.insert(Response::new(sender, Step::R, 0, Vec::new()), 2);
+                }
+            });
+
+            let mut handed_out = Vec::new();
+            let certificate = store.wait_for_quorum_with(Step::R, 0, 2, |response| handed_out.push(response.sender)).unwrap();
+            inserter.join().unwrap();
+
+            handed_out.sort();
+            assert_eq!(handed_out, vec![0, 1]);
+            assert_eq!(certificate.len(), 2);
+        });
+    }
+
+    // Concurrent inserts of the same sender keep a single response, and never go past the threshold
+    #[test]
+    fn loom_concurrent_inserts_respect_threshold() {
+        loom::model(|| {
+            let store = Arc::new(ResponseStore::new());
+            let inserters: Vec<_> = [0, 0, 1]
+                .into_iter()
+                .map(|sender| {
+                    let store = Arc::clone(&store);
+                    thread::spawn(move || store.insert(Response::new(sender, Step::A, 0, Vec::new()), 1))
+                })
+                .collect();
+
+            let inserted = inserters
+                .into_iter()
+                .map(|inserter| inserter.join().unwrap())
+                .filter(|&inserted| inserted)
+                .count();
+
+            assert_eq!(inserted, 1);
+            assert_eq!(store.wait_for_quorum(Step::A, 0, 1).map(|certificate| certificate.len()), Some(1));
+        });
+    }
+}
@@ -0,0 +1,83 @@
+use rsnano_core::BlockHash;
+use crate::{max_faults, Id, PreProposal, SimConfig, Simulation, Time};
+
+pub const CSV_HEADER: &str = "nodes,f,seed,decided,latency_us,messages,messages_per_node,max_held_bytes,mean_held_bytes";
+
+// Cost of one simulated consensus instance for a committee of `nodes` processes tolerating `f` faults
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalabilityRow {
+    pub nodes: usize,
+    pub f: usize,
+    pub seed: u64,
+    pub decided: bool,
+    // Virtual time at which the last node decided
+    pub latency: Time,
+    pub messages: usize,
+    // Bytes held by the run loops when the instance ends, nothing is evicted without a memory budget
+    pub max_held_bytes: usize,
+    pub mean_held_bytes: usize,
+}
+
+impl ScalabilityRow {
+    pub fn csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{:.1},{},{}",
+            self.nodes,
+            self.f,
+            self.seed,
+            self.decided,
+            self.latency,
+            self.messages,
+            self.messages as f64 / self.nodes as f64,
+            self.max_held_bytes,
+            self.mean_held_bytes
+        )
+    }
+}
+
+// Every node proposes a frontier of its own and one shared with its neighbour
+pub fn measure(config: SimConfig) -> ScalabilityRow {
+    let preproposals = (0..config.nodes)
+        .map(|id| PreProposal::new(vec![BlockHash::from(id as u64 + 1), BlockHash::from(id as u64 + 2)], id as Id))
+        .collect();
+
+    let mut simulation = Simulation::new(config, preproposals);
+    let outcome = simulation.run();
+    let held: Vec<usize> = simulation.memory_usage().iter().map(|usage| usage.held_bytes).collect();
+
+    ScalabilityRow {
+        nodes: config.nodes,
+        f: config.f,
+        seed: config.seed,
+        decided: outcome.all_decided(),
+        latency: outcome.time,
+        messages: outcome.delivered,
+        max_held_bytes: held.iter().copied().max().unwrap_or(0),
+        mean_held_bytes: held.iter().sum::<usize>() / held.len().max(1),
+    }
+}
+
+// One row per committee size and seed, with the largest f each committee tolerates
+pub fn sweep(sizes: &[usize], seeds: u64, base: SimConfig) -> Vec<ScalabilityRow> {
+    sizes
+        .iter()
+        .flat_map(|&nodes| (0..seeds).map(move |seed| SimConfig { nodes, f: max_faults(nodes), seed, ..base }))
+        .map(measure)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn larger_committees_send_more_messages() {
+        let rows = sweep(&[4, 10], 1, SimConfig::default());
+
+        assert!(rows.iter().all(|row| row.decided), "{rows:?}");
+        assert_eq!(rows.iter().map(|row| row.f).collect::<Vec<_>>(), vec![1, 3]);
+        assert!(rows[1].messages > rows[0].messages);
+        assert!(rows[1].max_held_bytes > rows[0].max_held_bytes);
+        assert_eq!(rows[0].csv().split(',').count(), CSV_HEADER.split(',').count());
+    }
+}
@@ -0,0 +1,257 @@
+use std::{collections::BTreeMap, fmt, ops::Range, sync::Arc};
+use rsnano_core::BlockHash;
+use crate::{max_faults, ByzantineStrategy, ChaosEvent, Config, Faults, Id, LinkFaults, PeerDelay, PreProposal, Regression, ScriptedFault, SimConfig, SimOutcome, Simulation, Time};
+
+// What a scenario must end in. Correct nodes that decide always agree on a proposed value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    // Every correct node decides
+    Decision,
+    // Nothing more, e.g. with a fault that lasts past the time limit
+    Safety,
+    // No correct node decides
+    Stall,
+}
+
+// Builds the strategy of a byzantine node afresh for each run, strategies keep state
+type StrategyFactory = Arc<dyn Fn() -> Box<dyn ByzantineStrategy> + Send + Sync>;
+
+// A simulation declared in code: the committee, its byzantine nodes, a timeline of network events, what each node
+// submits and the outcome the run must end in. A scenario is only a description, so the same one runs under any
+// number of seeds, in tests and benchmarks alike
+#[derive(Clone)]
+pub struct Scenario {
+    name: String,
+    sim: SimConfig,
+    byzantine: Vec<(Id, StrategyFactory)>,
+    link: LinkFaults,
+    slow: Vec<(Id, PeerDelay)>,
+    events: Vec<ChaosEvent>,
+    script: Vec<ScriptedFault>,
+    submissions: BTreeMap<Id, Vec<BlockHash>>,
+    expected: Expected,
+}
+
+impl fmt::Debug for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scenario")
+            .field("name", &self.name)
+            .field("sim", &self.sim)
+            .field("byzantine", &self.byzantine.iter().map(|(node, strategy)| (*node, strategy())).collect::<Vec<_>>())
+            .field("link", &self.link)
+            .field("slow", &self.slow)
+            .field("events", &self.events)
+            .field("script", &self.script)
+            .field("submissions", &self.submissions)
+            .field("expected", &self.expected)
+            .finish()
+    }
+}
+
+impl Scenario {
+    // The largest f the committee tolerates, every node decides by default
+    pub fn new(name: &str, nodes: usize) -> Scenario {
+        Scenario {
+            name: name.to_string(),
+            sim: SimConfig { nodes, f: max_faults(nodes), ..SimConfig::default() },
+            byzantine: Vec::new(),
+            link: LinkFaults::default(),
+            slow: Vec::new(),
+            events: Vec::new(),
+            script: Vec::new(),
+            submissions: BTreeMap::new(),
+            expected: Expected::Decision,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Scenario {
+        self.sim.seed = seed;
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Scenario {
+        self.sim.config = config;
+        self
+    }
+
+    pub fn latency(mut self, min: Time, max: Time) -> Scenario {
+        self.sim.min_latency = min;
+        self.sim.max_latency = max;
+        self
+    }
+
+    pub fn time_limit(mut self, limit: Time) -> Scenario {
+        self.sim.time_limit = limit;
+        self
+    }
+
+    // E.g. `.byzantine(3, || Box::new(Equivocator))`
+    pub fn byzantine(mut self, node: Id, strategy: impl Fn() -> Box<dyn ByzantineStrategy> + Send + Sync + 'static) -> Scenario {
+        self.byzantine.push((node, Arc::new(strategy)));
+        self
+    }
+
+    // Faults of every link
+    pub fn links(mut self, link: LinkFaults) -> Scenario {
+        self.link = link;
+        self
+    }
+
+    pub fn slow(mut self, node: Id, delay: PeerDelay) -> Scenario {
+        self.slow.push((node, delay));
+        self
+    }
+
+    // The node loses everything sent to or by it in the meantime
+    pub fn kill(self, node: Id, at: Time, restart: Time) -> Scenario {
+        self.event(ChaosEvent::Kill { node, at, restart })
+    }
+
+    // The node gets everything sent to it in the meantime once it resumes
+    pub fn pause(self, node: Id, at: Time, resume: Time) -> Scenario {
+        self.event(ChaosEvent::Pause { node, at, resume })
+    }
+
+    pub fn partition(self, minority: &[Id], at: Time, heal: Time) -> Scenario {
+        self.event(ChaosEvent::Partition { minority: minority.to_vec(), at, heal })
+    }
+
+    pub fn event(mut self, event: ChaosEvent) -> Scenario {
+        self.events.push(event);
+        self
+    }
+
+    // Any other disruption of the links
+    pub fn fault(mut self, fault: ScriptedFault) -> Scenario {
+        self.script.push(fault);
+        self
+    }
+
+    // Frontiers the node proposes, the ones of nodes without a submission are their id + 1
+    pub fn submit(mut self, node: Id, frontiers: &[BlockHash]) -> Scenario {
+        self.submissions.insert(node, frontiers.to_vec());
+        self
+    }
+
+    pub fn expect(mut self, expected: Expected) -> Scenario {
+        self.expected = expected;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn preproposals(&self) -> Vec<PreProposal> {
+        (0..self.sim.nodes as Id)
+            .map(|node| {
+                let frontiers = self.submissions.get(&node).cloned().unwrap_or_else(|| vec![BlockHash::from(node as u64 + 1)]);
+                PreProposal::new(frontiers, node)
+            })
+            .collect()
+    }
+
+    // Scripted faults of the timeline, events first
+    fn faults(&self) -> Vec<ScriptedFault> {
+        self.events.iter().flat_map(|event| event.faults(self.sim.nodes)).chain(self.script.iter().copied()).collect()
+    }
+
+    // The simulation of the scenario under the seed, not run yet
+    pub fn simulation(&self, seed: u64) -> Simulation {
+        let faults = self.slow.iter().fold(Faults::new(self.link), |faults, (node, delay)| faults.with_slow_peer(*node, *delay));
+        let faults = self.faults().into_iter().fold(faults, |faults, fault| faults.with_scripted(fault));
+
+        self.byzantine.iter().fold(Simulation::with_faults(SimConfig { seed, ..self.sim }, self.preproposals(), faults), |simulation, (node, strategy)| {
+            simulation.with_byzantine(*node, strategy())
+        })
+    }
+
+    // Runs the scenario under its own seed and checks the outcome against the expected one
+    pub fn run(&self) -> Result<SimOutcome, String> {
+        self.run_seed(self.sim.seed)
+    }
+
+    pub fn run_seed(&self, seed: u64) -> Result<SimOutcome, String> {
+        let mut simulation = self.simulation(seed);
+        let outcome = simulation.run();
+        let proposals = simulation.proposals();
+        let failed = |what: &str| Err(format!("{} (seed {}): {}, {:?}", self.name, seed, what, outcome.decisions));
+
+        if !outcome.agreement() {
+            return failed("correct nodes disagree");
+        }
+        if outcome.decisions.iter().flatten().any(|decision| !proposals.contains(decision)) {
+            return failed("a value that was never proposed was decided");
+        }
+        // Byzantine nodes never count as decided
+        let undecided = outcome.decisions.iter().enumerate().any(|(node, decision)| {
+            decision.is_none() && !self.byzantine.iter().any(|(byzantine, _)| *byzantine == node as Id)
+        });
+        match self.expected {
+            Expected::Decision if undecided => failed("not every correct node decided"),
+            Expected::Stall if outcome.decisions.iter().any(Option::is_some) => failed("a correct node decided"),
+            _ => Ok(outcome),
+        }
+    }
+
+    // Stops at the first seed whose outcome is not the expected one
+    pub fn run_seeds(&self, seeds: Range<u64>) -> Result<Vec<SimOutcome>, String> {
+        seeds.map(|seed| self.run_seed(seed)).collect()
+    }
+
+    // The line of the regression corpus replaying the scenario under the seed, if it has neither byzantine nor slow
+    // nodes, which the corpus cannot describe. Stalls are recorded as safety only
+    pub fn regression(&self, seed: u64) -> Option<Regression> {
+        if !self.byzantine.is_empty() || !self.slow.is_empty() {
+            return None;
+        }
+
+        let regression = Regression::new(&format!("{}-{}", self.name, seed), SimConfig { seed, ..self.sim }, self.preproposals())
+            .with_link(self.link)
+            .with_script(self.faults());
+        Some(if self.expected == Expected::Decision { regression.live() } else { regression })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CertificateWithholder, Delay, Equivocator, FaultAction, Mute};
+
+    #[test]
+    fn declared_scenarios_end_as_expected() {
+        let paused = Scenario::new("paused", 4)
+            .byzantine(3, || Box::new(CertificateWithholder))
+            .pause(2, 0, 100_000)
+            .submit(0, &[BlockHash::from(7), BlockHash::from(8)]);
+        let outcomes = paused.run_seeds(0..5).unwrap();
+        assert!(outcomes.iter().all(|outcome| outcome.time >= 100_000), "{outcomes:?}");
+
+        Scenario::new("equivocating", 4).byzantine(3, || Box::new(Equivocator)).partition(&[0], 0, 50_000).expect(Expected::Safety).run_seeds(0..5).unwrap();
+
+        Scenario::new("slow-and-lossy", 7)
+            .slow(1, PeerDelay { inbound: Delay::Fixed(50_000), ..PeerDelay::default() })
+            .links(LinkFaults { duplicate_rate: 0.1, ..LinkFaults::default() })
+            .byzantine(6, || Box::new(Mute))
+            .run_seeds(0..3)
+            .unwrap();
+
+        let silenced = Scenario::new("silenced", 4)
+            .fault(ScriptedFault { from: None, to: None, start: 0, end: Time::MAX, action: FaultAction::Drop })
+            .expect(Expected::Stall);
+        assert!(silenced.run().is_ok());
+        assert!(silenced.clone().expect(Expected::Decision).run().is_err());
+    }
+
+    #[test]
+    fn scenarios_without_byzantine_nodes_become_regressions() {
+        let scenario = Scenario::new("paused", 4).pause(1, 0, 50_000).seed(4);
+        let regression = scenario.regression(4).unwrap();
+
+        assert_eq!(regression.script.len(), 2);
+        assert!(regression.live);
+        assert_eq!(regression.check(), Ok(()));
+        assert_eq!(regression.run().0, scenario.run().unwrap());
+        assert!(scenario.byzantine(0, || Box::new(Mute)).regression(4).is_none());
+    }
+}
@@ -0,0 +1,978 @@
+use std::{cmp::Ordering, collections::{BTreeMap, BinaryHeap, HashMap}, hash::{DefaultHasher, Hash, Hasher}, sync::{mpsc::{channel, Receiver}, Arc}};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use crate::{ATally, BTally, BatchConfig, Broadcast, ByzantineStrategy, CertificateResponses, Config, ConsensusStats, Core, Decision, FaultAction, Faults, HistoryEntry, Id, Invariants, LinkFaults, MemoryUsage, Message, Outbox, PeerQueues, PeerStats, PreProposal, Proposal, ProposalHash, RTally, RValue, RandomnessBeacon, Rank, StateDump, Step};
+
+// Virtual time, in microseconds
+pub type Time = u64;
+
+// Runs the protocol cores of a committee on a virtual clock, on a single thread
+// Every source of nondeterminism (delivery order and latencies) comes from a RNG seeded with `seed`,
+// so a failing schedule is replayed exactly by running the simulation again with the same seed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimConfig {
+    pub nodes: usize,
+    pub f: usize,
+    pub seed: u64,
+    pub min_latency: Time,
+    pub max_latency: Time,
+    // The simulation gives up once the clock passes this time
+    pub time_limit: Time,
+    pub config: Config,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            nodes: 4,
+            f: 1,
+            seed: 0,
+            min_latency: 1_000,
+            max_latency: 10_000,
+            time_limit: 60_000_000,
+            config: Config { batch: BatchConfig::disabled(), ..Config::default() },
+        }
+    }
+}
+
+// Local clock of a node: `offset` microseconds ahead of (or behind) the simulation, running `drift_ppm` parts per
+// million faster (or slower). Nothing in the protocol reads clocks, only the stall detection does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Clock {
+    pub offset: i64,
+    pub drift_ppm: i64,
+}
+
+impl Clock {
+    pub fn local(&self, now: Time) -> Time {
+        let drifted = now as i128 * (1_000_000 + self.drift_ppm as i128) / 1_000_000;
+        (drifted + self.offset as i128).max(0) as Time
+    }
+}
+
+// Largest f such that nodes >= 3f+1
+pub fn max_faults(nodes: usize) -> usize {
+    nodes.saturating_sub(1) / 3
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimOutcome {
+    pub decisions: Vec<Option<ProposalHash>>,
+    pub time: Time,
+    pub delivered: usize,
+    pub dropped: usize,
+    // Digest of every delivery (time, link and message), equal for two runs with the same schedule
+    pub trace: u64,
+}
+
+impl SimOutcome {
+    pub fn all_decided(&self) -> bool {
+        self.decisions.iter().all(Option::is_some)
+    }
+
+    pub fn agreement(&self) -> bool {
+        let mut decided = self.decisions.iter().flatten();
+        match decided.next() {
+            Some(first) => decided.all(|decision| decision == first),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Event {
+    time: Time,
+    seq: u64,
+    from: Id,
+    to: usize,
+    message: Message,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Earliest event first, ties broken by scheduling order
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+// Where the proposer of a node stands, Lines 15-60 without blocking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    PreProposal,
+    R(Rank),
+    A(Rank),
+    B(Rank),
+    Decided(ProposalHash),
+}
+
+impl Phase {
+    fn rank(&self) -> Option<Rank> {
+        match *self {
+            Phase::R(rank) | Phase::A(rank) | Phase::B(rank) => Some(rank),
+            Phase::PreProposal | Phase::Decided(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    core: Core,
+    outbox: Outbox,
+    // Messages sent by this node to each peer, drained by the simulator
+    links: Vec<Receiver<Message>>,
+    phase: Phase,
+    byzantine: Option<Box<dyn ByzantineStrategy>>,
+    // Instance of an identity that runs twice, which makes the identity faulty
+    twin: bool,
+    // Messages only flow between instances of the same partition
+    partition: usize,
+    proposal: Option<ProposalHash>,
+    // Rank and B answers of the commit
+    certificate: Option<(Rank, CertificateResponses)>,
+    clock: Clock,
+    // Local time at which the proposer entered its current phase
+    phase_since: Time,
+    // Of the proposer, the ones of the run loop are checked by the core
+    invariants: Invariants,
+}
+
+impl Node {
+    fn new(id: Id, config: &SimConfig, preproposal: PreProposal) -> Node {
+        let (senders, links): (Vec<_>, Vec<_>) = (0..config.nodes).map(|_| channel()).unzip();
+
+        let core = Core::new(id, config.f, false, config.config);
+        let mut outbox = Outbox::new(PeerQueues::direct(senders).with_accounting(core.peer_accounting()), BatchConfig::disabled());
+        outbox.push(Message::PreProposal(preproposal));
+
+        Node {
+            core,
+            outbox,
+            links,
+            phase: Phase::PreProposal,
+            byzantine: None,
+            twin: false,
+            partition: 0,
+            proposal: None,
+            certificate: None,
+            clock: Clock::default(),
+            phase_since: 0,
+            invariants: Invariants::default(),
+        }
+    }
+
+    fn correct(&self) -> bool {
+        self.byzantine.is_none() && !self.twin
+    }
+
+    fn broadcast(&mut self, broadcast: Broadcast, by_reference: bool) {
+        let broadcast = self.core.identities().sign_broadcast(if by_reference { broadcast.into_reference() } else { broadcast });
+        debug_assert_eq!(self.invariants.sent(&broadcast), Ok(()), "{}: {:?}", self.core.id(), broadcast);
+        self.outbox.push(Message::Broadcast(broadcast));
+    }
+
+    fn quorum(&self, step: Step, rank: Rank, threshold: usize) -> Option<CertificateResponses> {
+        if self.core.responses().count(step, rank) < threshold {
+            return None;
+        }
+
+        let mut responses = self.core.responses().get(step, rank)?;
+        responses.sort_by_key(|response| response.sender);
+        Some(responses.into())
+    }
+
+    // Moves the proposer forward as far as the responses received so far allow
+    fn advance(&mut self, threshold: usize, by_reference: bool, forced_adopt_ranks: Rank) {
+        let id = self.core.id();
+
+        loop {
+            let next = match self.phase {
+                Phase::PreProposal => {
+                    let preproposals = self.core.preproposals();
+                    if preproposals.len() < threshold {
+                        return;
+                    }
+
+                    let proposal = Proposal::create_proposal(preproposals, id);
+                    self.proposal = Some(proposal.hash);
+                    self.outbox.push(Message::Proposal(proposal.clone()));
+                    self.broadcast(Broadcast::new(id, Step::R, proposal.hash, None, 0, None), by_reference);
+                    Phase::R(0)
+                }
+                Phase::R(rank) => {
+                    let Some(certificate) = self.quorum(Step::R, rank, threshold) else {
+                        return;
+                    };
+
+                    let mut tally = RTally::default().ordered(self.core.value_order());
+                    certificate.iter().for_each(|response| tally.add(response));
+                    let r_value = tally.result();
+
+                    self.broadcast(Broadcast::new(id, Step::A, r_value.value, None, r_value.rank, Some(certificate)), by_reference);
+                    Phase::A(r_value.rank)
+                }
+                Phase::A(rank) => {
+                    let Some(certificate) = self.quorum(Step::A, rank, threshold) else {
+                        return;
+                    };
+
+                    let mut tally = ATally::new(threshold).forcing_adopt(rank < forced_adopt_ranks).ordered(self.core.value_order());
+                    certificate.iter().for_each(|response| tally.add(response));
+                    let (flag, value) = tally.result();
+
+                    self.broadcast(Broadcast::new(id, Step::B, value, Some(flag), rank, Some(certificate)), by_reference);
+                    Phase::B(rank)
+                }
+                Phase::B(rank) => {
+                    let Some(certificate) = self.quorum(Step::B, rank, threshold) else {
+                        return;
+                    };
+
+                    let mut tally = BTally::new(threshold).ordered(self.core.value_order());
+                    certificate.iter().for_each(|response| tally.add(response));
+
+                    match tally.result() {
+                        Decision::Commit(value) => {
+                            self.core.consensus_metrics().decided(0, rank);
+                            self.certificate = Some((rank, certificate));
+                            Phase::Decided(value)
+                        }
+                        Decision::Adopt(value) => {
+                            self.core.consensus_metrics().adopted();
+                            // A rank whose certificate the pacemaker knows is started right away, instead of the next one
+                            let (r_value, certificate) = self.core.catch_up(rank + 1).unwrap_or((RValue::new(rank + 1, value), certificate));
+                            self.broadcast(Broadcast::new(id, Step::R, r_value.value, None, r_value.rank, Some(certificate)), by_reference);
+                            Phase::R(r_value.rank)
+                        }
+                    }
+                }
+                Phase::Decided(_) => return,
+            };
+
+            // A proposer never goes back to a lower rank, nor decides another value
+            let checked = match next {
+                Phase::Decided(value) => self.invariants.committed(0, value),
+                next => next.rank().map_or(Ok(()), |rank| self.invariants.rank(rank)),
+            };
+            debug_assert_eq!(checked, Ok(()), "{}: {:?} after {:?}", id, next, self.phase);
+            self.phase = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Simulation {
+    config: SimConfig,
+    nodes: Vec<Node>,
+    events: BinaryHeap<Event>,
+    faults: Faults,
+    rng: StdRng,
+    now: Time,
+    seq: u64,
+    delivered: usize,
+    dropped: usize,
+    trace: DefaultHasher,
+    // The preproposals are only sent on the first step, once the Byzantine nodes are known
+    started: bool,
+}
+
+impl Simulation {
+    // Node i proposes preproposals[i]
+    pub fn new(config: SimConfig, preproposals: Vec<PreProposal>) -> Simulation {
+        Simulation::with_faults(config, preproposals, Faults::default())
+    }
+
+    pub fn with_faults(config: SimConfig, preproposals: Vec<PreProposal>, faults: Faults) -> Simulation {
+        assert_eq!(preproposals.len(), config.nodes, "one preproposal per node");
+
+        let nodes = preproposals
+            .into_iter()
+            .enumerate()
+            .map(|(id, preproposal)| Node::new(id as Id, &config, preproposal))
+            .collect();
+
+        Simulation {
+            config,
+            nodes,
+            events: BinaryHeap::new(),
+            faults,
+            rng: StdRng::seed_from_u64(config.seed),
+            now: 0,
+            seq: 0,
+            delivered: 0,
+            dropped: 0,
+            trace: DefaultHasher::new(),
+            started: false,
+        }
+    }
+
+    // The node keeps running an honest core, but everything it sends goes through the strategy
+    pub fn with_byzantine(mut self, node: Id, strategy: Box<dyn ByzantineStrategy>) -> Simulation {
+        self.nodes[node as usize].byzantine = Some(strategy);
+        self
+    }
+
+    // Adds a second instance of `node` that starts from a different preproposal, as instance number nodes + k for the k-th twin
+    // Messages sent to the identity reach every instance in the sender's partition
+    pub fn with_twin(mut self, node: Id, preproposal: PreProposal) -> Simulation {
+        let mut twin = Node::new(node, &self.config, preproposal);
+        twin.twin = true;
+        self.nodes[node as usize].twin = true;
+        self.nodes.push(twin);
+        self
+    }
+
+    // Splits the instances into partitions that cannot reach each other, instances not listed stay in the first one
+    pub fn with_partitions(mut self, partitions: &[Vec<usize>]) -> Simulation {
+        for (partition, instances) in partitions.iter().enumerate() {
+            for &instance in instances {
+                self.nodes[instance].partition = partition;
+            }
+        }
+        self
+    }
+
+    // Every instance signs its responses, with a key derived from its id (twins share theirs), and only counts signed ones
+    pub fn with_identities(self) -> Simulation {
+        let key = |id: Id| SigningKey::from_bytes(&[id as u8 + 1; 32]);
+        let committee: HashMap<Id, VerifyingKey> = (0..self.config.nodes as Id).map(|id| (id, key(id).verifying_key())).collect();
+
+        for node in &self.nodes {
+            node.core.identities().set(key(node.core.id()), committee.clone());
+        }
+        self
+    }
+
+    // Every instance, twins included, orders values by the same beacon
+    pub fn with_beacon(self, beacon: Arc<dyn RandomnessBeacon>) -> Simulation {
+        for node in &self.nodes {
+            node.core.value_order().set_beacon(Arc::clone(&beacon));
+        }
+        self
+    }
+
+    // Also moves the start of the node's current phase to its local time, as a node that just booted would see it
+    pub fn with_clock(mut self, node: Id, clock: Clock) -> Simulation {
+        let node = &mut self.nodes[node as usize];
+        node.clock = clock;
+        node.phase_since = clock.local(self.now);
+        self
+    }
+
+    // Correct nodes that, by their own clock read at simulation time `at`, have been waiting in the same phase for
+    // at least `timeout`, the way Progress::stalled reports a real process
+    pub fn stalled(&self, at: Time, timeout: Time) -> Vec<Id> {
+        self.nodes
+            .iter()
+            .filter(|node| node.correct() && !matches!(node.phase, Phase::Decided(_)))
+            .filter(|node| node.clock.local(at).saturating_sub(node.phase_since) >= timeout)
+            .map(|node| node.core.id())
+            .collect()
+    }
+
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
+    // Delivers the next message, returns false once there is nothing left to deliver or the time limit is reached
+    pub fn step(&mut self) -> bool {
+        self.start();
+
+        let Some(event) = self.events.pop() else {
+            return false;
+        };
+
+        if event.time > self.config.time_limit {
+            return false;
+        }
+
+        self.deliver(event);
+        true
+    }
+
+    // Messages sent but not delivered yet
+    pub fn pending(&mut self) -> usize {
+        self.start();
+        self.events.len()
+    }
+
+    // Delivers the pending message number `index` (in an arbitrary but reproducible order) ahead of the others,
+    // which lets a fuzzer pick any asynchronous schedule instead of the ones the latencies produce
+    pub fn step_pending(&mut self, index: usize) -> bool {
+        self.start();
+
+        if index >= self.events.len() {
+            return false;
+        }
+
+        let mut events = std::mem::take(&mut self.events).into_vec();
+        let event = events.swap_remove(index);
+        self.events = events.into();
+
+        self.deliver(Event { time: event.time.max(self.now), ..event });
+        true
+    }
+
+    fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            for node in 0..self.nodes.len() {
+                self.schedule_sent(node);
+            }
+        }
+    }
+
+    fn deliver(&mut self, event: Event) {
+        self.now = event.time;
+        self.delivered += 1;
+        (event.time, event.from, event.to, &event.message).hash(&mut self.trace);
+
+        let threshold = 2 * self.config.f + 1;
+        let Config { certificates_by_reference, .. } = self.config.config;
+        let forced_adopt_ranks = self.config.config.forced_adopt_ranks();
+        let node = &mut self.nodes[event.to];
+        let phase = node.phase;
+        node.core.handle(event.message, &mut node.outbox);
+        node.advance(threshold, certificates_by_reference, forced_adopt_ranks);
+        node.outbox.flush();
+
+        if node.phase != phase {
+            node.phase_since = node.clock.local(self.now);
+        }
+
+        // Checked on every delivery in debug builds, so that fuzzed schedules stop at the first violation
+        debug_assert!(self.outcome().agreement(), "correct nodes decided different values: {:?}", self.decisions());
+
+        self.schedule_sent(event.to);
+    }
+
+    // Runs until every correct node has decided, nothing is left to deliver or the time limit is reached
+    pub fn run(&mut self) -> SimOutcome {
+        while !self.correct_nodes_decided() && self.step() {}
+        self.outcome()
+    }
+
+    // Decisions of the correct instances, None for the Byzantine ones and the twins
+    pub fn decisions(&self) -> Vec<Option<ProposalHash>> {
+        self.nodes
+            .iter()
+            .map(|node| match node.phase {
+                Phase::Decided(value) if node.correct() => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Values proposed so far, by any instance
+    pub fn proposals(&self) -> Vec<ProposalHash> {
+        self.nodes.iter().filter_map(|node| node.proposal).collect()
+    }
+
+    // Proposals of every instance and commits of the correct ones, as the `slot` entries of a history
+    pub fn history(&self, slot: usize) -> Vec<HistoryEntry> {
+        let proposals = self.nodes
+            .iter()
+            .filter_map(|node| node.proposal.map(|value| HistoryEntry::Propose { node: node.core.id(), slot, value }));
+
+        let commits = self.nodes.iter().filter(|node| node.correct()).filter_map(|node| match (node.phase, &node.certificate) {
+            (Phase::Decided(value), Some((rank, certificate))) => Some(HistoryEntry::commit(node.core.id(), slot, *rank, value, certificate)),
+            _ => None,
+        });
+
+        proposals.chain(commits).collect()
+    }
+
+    pub fn dump_state(&self, node: usize) -> StateDump {
+        self.nodes[node].core.dump_state()
+    }
+
+    // Bytes held by the run loop of each instance
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        self.nodes.iter().map(|node| node.core.memory_usage()).collect()
+    }
+
+    // Traffic of each instance with its peers
+    pub fn peer_stats(&self) -> Vec<BTreeMap<Id, PeerStats>> {
+        self.nodes.iter().map(|node| node.core.peer_accounting().stats()).collect()
+    }
+
+    // Consensus counters of each instance
+    pub fn stats(&self) -> Vec<ConsensusStats> {
+        self.nodes.iter().map(|node| node.core.consensus_metrics().stats()).collect()
+    }
+
+    fn correct_nodes_decided(&self) -> bool {
+        self.nodes
+            .iter()
+            .all(|node| !node.correct() || matches!(node.phase, Phase::Decided(_)))
+    }
+
+    pub fn outcome(&self) -> SimOutcome {
+        SimOutcome {
+            decisions: self.decisions(),
+            time: self.now,
+            delivered: self.delivered,
+            dropped: self.dropped,
+            trace: self.trace.finish(),
+        }
+    }
+
+    // Hands every message the node has sent since the last call to its strategy, if it is Byzantine, and schedules it
+    fn schedule_sent(&mut self, from: usize) {
+        for to_id in 0..self.config.nodes as Id {
+            while let Ok(message) = self.nodes[from].links[to_id as usize].try_recv() {
+                let messages = match self.nodes[from].byzantine.as_mut() {
+                    Some(strategy) => strategy.tamper(to_id, message),
+                    None => vec![message],
+                };
+
+                let recipients: Vec<usize> = (0..self.nodes.len())
+                    .filter(|&to| self.nodes[to].core.id() == to_id && self.nodes[to].partition == self.nodes[from].partition)
+                    .collect();
+
+                for message in messages {
+                    for &to in &recipients {
+                        self.schedule(from, to, message.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // Applies the faults of the link and picks a latency for the message
+    fn schedule(&mut self, from: usize, to: usize, message: Message) {
+        let (from, to_id) = (self.nodes[from].core.id(), self.nodes[to].core.id());
+
+        let sent = match self.faults.scripted(from, to_id, self.now).map(|fault| (fault.action, fault.end)) {
+            Some((FaultAction::Drop, _)) => {
+                self.dropped += 1;
+                return;
+            }
+            Some((FaultAction::Hold, end)) => end,
+            None => self.now,
+        };
+
+        let link = *self.faults.link(from, to_id);
+        if self.rng.gen_bool(link.drop_rate) {
+            self.dropped += 1;
+            return;
+        }
+
+        if self.rng.gen_bool(link.duplicate_rate) {
+            let time = sent.saturating_add(self.latency(from, to_id, &link));
+            self.push(Event { time, seq: 0, from, to, message: message.clone() });
+        }
+
+        let time = sent.saturating_add(self.latency(from, to_id, &link));
+        self.push(Event { time, seq: 0, from, to, message });
+    }
+
+    fn latency(&mut self, from: Id, to: Id, link: &LinkFaults) -> Time {
+        let mut latency = self.rng.gen_range(self.config.min_latency..=self.config.max_latency)
+            + link.delay.sample(&mut self.rng)
+            + self.faults.peer_delay(from, to, &mut self.rng);
+
+        if self.rng.gen_bool(link.reorder_rate) {
+            latency += self.config.max_latency;
+        }
+        latency
+    }
+
+    fn push(&mut self, mut event: Event) {
+        self.seq += 1;
+        event.seq = self.seq;
+        self.events.push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+    use proptest::prelude::*;
+    use crate::{Delay, FieldFlipper, History, PeerDelay, Regression, ScriptedFault, SeededBeacon};
+
+    fn preproposals(nodes: usize) -> Vec<PreProposal> {
+        (0..nodes)
+            .map(|id| PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id as Id))
+            .collect()
+    }
+
+    #[test]
+    fn all_nodes_decide_the_same_value() {
+        for seed in 0..20 {
+            let config = SimConfig { seed, ..SimConfig::default() };
+            Regression::new("all_nodes_decide_the_same_value", config, preproposals(config.nodes)).live().check_or_record().unwrap();
+        }
+    }
+
+    #[test]
+    fn recorded_history_passes_the_checker() {
+        let config = SimConfig { seed: 1, ..SimConfig::default() };
+        let mut simulation = Simulation::new(config, preproposals(config.nodes));
+        simulation.run();
+
+        let history = History { nodes: config.nodes, f: config.f, entries: simulation.history(0) };
+        assert_eq!(history.entries.iter().filter(|entry| matches!(entry, HistoryEntry::Commit { .. })).count(), 4);
+        assert_eq!(history.check(), Vec::new());
+    }
+
+    fn skewed_clocks(nodes: usize) -> Vec<Clock> {
+        // Up to a second apart and 200ppm of drift, far beyond what NTP leaves
+        [Clock { offset: 1_000_000, drift_ppm: 200 }, Clock { offset: -1_000_000, drift_ppm: -200 }, Clock { offset: 300_000, drift_ppm: 50 }]
+            .into_iter()
+            .cycle()
+            .take(nodes)
+            .collect()
+    }
+
+    #[test]
+    fn clock_skew_does_not_change_decisions() {
+        for seed in 0..10 {
+            let config = SimConfig { nodes: 7, f: 2, seed, ..SimConfig::default() };
+            let mut unskewed = Simulation::new(config, preproposals(7));
+            let expected = unskewed.run();
+
+            let mut simulation = skewed_clocks(7)
+                .into_iter()
+                .enumerate()
+                .fold(Simulation::new(config, preproposals(7)), |simulation, (node, clock)| simulation.with_clock(node as Id, clock));
+
+            // No node waits long enough in a phase to be reported, whatever its clock says
+            while !simulation.correct_nodes_decided() && simulation.step() {
+                assert_eq!(simulation.stalled(simulation.now(), 100_000), Vec::<Id>::new(), "seed {seed}");
+            }
+
+            // Same decisions at the same ranks, the rank never escalates because of a clock
+            assert_eq!(simulation.outcome(), expected, "seed {seed}");
+            let ranks = |simulation: &Simulation| simulation.nodes.iter().map(|node| node.certificate.as_ref().map(|(rank, _)| *rank)).collect::<Vec<_>>();
+            assert_eq!(ranks(&simulation), ranks(&unskewed), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn stall_is_measured_on_the_local_clock() {
+        // Without nodes 2 and 3 no quorum can form until the hold ends
+        let hold = |node| ScriptedFault { from: Some(node), to: None, start: 0, end: 5_000_000, action: FaultAction::Hold };
+        let faults = Faults::default().with_scripted(hold(2)).with_scripted(hold(3));
+        let config = SimConfig { seed: 1, time_limit: 1_000_000, ..SimConfig::default() };
+
+        let mut simulation = Simulation::with_faults(config, preproposals(4), faults)
+            .with_clock(0, Clock { offset: 0, drift_ppm: 500_000 })
+            .with_clock(1, Clock { offset: 7_000_000, drift_ppm: 0 });
+        simulation.run();
+
+        // Node 0's clock runs 50% fast, so it is the only one past a 1.2s timeout after a second
+        assert_eq!(simulation.stalled(1_000_000, 1_200_000), vec![0]);
+        assert_eq!(simulation.stalled(2_000_000, 1_200_000), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn state_dumps_are_deterministic() {
+        let config = SimConfig { seed: 5, ..SimConfig::default() };
+        let dumps = || {
+            let mut simulation = Simulation::new(config, preproposals(config.nodes));
+            simulation.run();
+            (0..config.nodes).map(|node| simulation.dump_state(node).to_string()).collect::<Vec<_>>()
+        };
+
+        let first = dumps();
+        assert_eq!(first, dumps());
+        for dump in &first {
+            assert!(dump.contains("responses B rank=0 senders="), "{dump}");
+            assert!(dump.contains("register B rank=0 pairs=true:"), "{dump}");
+        }
+    }
+
+    #[test]
+    fn huge_preproposals_decide() {
+        // 10k frontiers per node, half of them shared with the next node
+        let preproposals = (0..4u64)
+            .map(|id| PreProposal::new((id * 5_000..id * 5_000 + 10_000).map(BlockHash::from).collect(), id as Id))
+            .collect();
+        let outcome = Simulation::new(SimConfig::default(), preproposals).run();
+
+        assert!(outcome.all_decided(), "{outcome:?}");
+        assert!(outcome.agreement());
+    }
+
+    #[test]
+    fn any_delivery_order_decides() {
+        let config = SimConfig { seed: 2, ..SimConfig::default() };
+        let mut simulation = Simulation::new(config, preproposals(config.nodes));
+
+        // Always delivers the most recently sent message first, whatever its latency
+        while !simulation.correct_nodes_decided() && simulation.pending() > 0 {
+            let last = simulation.pending() - 1;
+            assert!(simulation.step_pending(last));
+        }
+
+        assert!(simulation.outcome().all_decided());
+        assert!(simulation.outcome().agreement());
+    }
+
+    #[test]
+    fn same_seed_replays_same_schedule() {
+        let config = SimConfig { nodes: 7, f: 2, seed: 42, ..SimConfig::default() };
+
+        let first = Simulation::new(config, preproposals(7)).run();
+        let second = Simulation::new(config, preproposals(7)).run();
+        let other = Simulation::new(SimConfig { seed: 43, ..config }, preproposals(7)).run();
+
+        assert_eq!(first, second);
+        assert_ne!(first.trace, other.trace);
+    }
+
+    #[test]
+    fn agreement_holds_on_lossy_links() {
+        let link = LinkFaults { drop_rate: 0.05, duplicate_rate: 0.1, reorder_rate: 0.2, delay: Delay::Exponential { mean: 2_000 } };
+
+        for seed in 0..20 {
+            let config = SimConfig { seed, time_limit: 5_000_000, ..SimConfig::default() };
+            Regression::new("agreement_holds_on_lossy_links", config, preproposals(config.nodes)).with_link(link).check_or_record().unwrap();
+        }
+    }
+
+    #[test]
+    fn duplicated_and_reordered_messages_do_not_prevent_decisions() {
+        let faults = Faults::new(LinkFaults { duplicate_rate: 0.3, reorder_rate: 0.3, ..LinkFaults::default() });
+
+        for seed in 0..10 {
+            let config = SimConfig { seed, ..SimConfig::default() };
+            let outcome = Simulation::with_faults(config, preproposals(config.nodes), faults.clone()).run();
+
+            assert!(outcome.all_decided(), "seed {seed}: {outcome:?}");
+            assert!(outcome.agreement(), "seed {seed}: {outcome:?}");
+        }
+    }
+
+    #[test]
+    fn peer_stats_single_out_the_broken_peer() {
+        let faults = Faults::new(LinkFaults { duplicate_rate: 0.3, ..LinkFaults::default() });
+        let config = SimConfig { seed: 4, ..SimConfig::default() };
+        let mut simulation = Simulation::with_faults(config, preproposals(config.nodes), faults).with_byzantine(3, Box::new(FieldFlipper));
+        simulation.run();
+
+        for stats in &simulation.peer_stats()[..3] {
+            assert!(stats[&3].invalid > 0, "{stats:?}");
+            assert!((0..3).all(|peer| stats[&peer].invalid == 0), "{stats:?}");
+            assert!(stats.values().map(|peer| peer.duplicates).sum::<usize>() > 0, "{stats:?}");
+            assert!(stats.values().all(|peer| peer.sent_messages > 0 && peer.received_bytes > 0), "{stats:?}");
+        }
+    }
+
+    #[test]
+    fn stats_count_decisions_and_equivocations() {
+        let config = SimConfig { seed: 2, ..SimConfig::default() };
+        let twin = PreProposal::new(vec![BlockHash::from(100)], 3);
+        let mut simulation = Simulation::new(config, preproposals(config.nodes)).with_twin(3, twin).with_identities();
+        simulation.run();
+
+        for stats in &simulation.stats()[..3] {
+            assert_eq!(stats.instances_decided, 1, "{stats:?}");
+            assert!(stats.adopts < stats.ranks_to_commit, "{stats:?}");
+            assert!(stats.equivocations > 0, "{stats:?}");
+            assert_eq!(stats.rejected_broadcasts(), 0, "{stats:?}");
+        }
+        // The twins sign with the same key, so their broadcasts are kept as evidence to ban them
+        let evidence = simulation.nodes[0].core.peer_table().evidence(3).unwrap();
+        let (first, second) = evidence.broadcasts();
+        assert!(first.step == second.step && first.rank == second.rank && first.hash_value() != second.hash_value(), "{evidence:?}");
+    }
+
+    #[test]
+    fn beacon_ordered_ranks_agree_and_check() {
+        for seed in 0..10 {
+            let config = SimConfig { seed, config: Config { forced_adopt_ranks: 3, ..SimConfig::default().config }, ..SimConfig::default() };
+            let mut simulation = Simulation::new(config, preproposals(config.nodes)).with_beacon(Arc::new(SeededBeacon(seed)));
+            let outcome = simulation.run();
+
+            assert!(outcome.all_decided() && outcome.agreement(), "seed {seed}: {outcome:?}");
+            // Certificates built in the beacon's order are justified in it too
+            assert!(simulation.stats().iter().all(|stats| stats.rejected_broadcasts() == 0), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn signed_responses_decide() {
+        let config = SimConfig { seed: 4, config: Config { forced_adopt_ranks: 2, ..SimConfig::default().config }, ..SimConfig::default() };
+        let mut simulation = Simulation::new(config, preproposals(config.nodes)).with_identities();
+        let outcome = simulation.run();
+
+        assert!(outcome.all_decided() && outcome.agreement(), "{outcome:?}");
+        assert!(simulation.stats().iter().all(|stats| stats.rejected_broadcasts() == 0));
+        assert!(simulation.peer_stats().iter().all(|stats| stats.values().all(|peer| peer.invalid == 0)));
+    }
+
+    #[test]
+    fn broadcasts_below_the_horizon_are_dropped() {
+        let defaults = SimConfig::default();
+        let config = SimConfig { seed: 1, config: Config { forced_adopt_ranks: 24, broadcast_horizon: Some(4), ..defaults.config }, ..defaults };
+        let mut simulation = Simulation::new(config, preproposals(config.nodes));
+        let outcome = simulation.run();
+
+        assert!(outcome.all_decided() && outcome.agreement(), "{outcome:?}");
+        for node in &simulation.nodes {
+            let dump = node.core.dump_state();
+            assert!(dump.evicted_below >= 20, "{}", dump.evicted_below);
+            // Only the broadcast of the R register outlives its rank
+            let old: Vec<_> = dump.broadcasts.iter().filter(|broadcast| broadcast.rank < dump.evicted_below).collect();
+            assert!(old.len() <= 1 && old.iter().all(|broadcast| broadcast.step == Step::R), "{old:?}");
+            assert!(dump.broadcasts.len() <= 4 * 3 * 6, "{}", dump.broadcasts.len());
+        }
+    }
+
+    #[test]
+    fn liveness_resumes_after_partition_heals() {
+        // Node 2 is cut off from everyone (itself included) for the first 200ms
+        let partition = ScriptedFault { from: Some(2), to: None, start: 0, end: 200_000, action: FaultAction::Hold };
+        let faults = Faults::default()
+            .with_scripted(partition)
+            .with_scripted(ScriptedFault { from: None, to: Some(2), ..partition });
+
+        let config = SimConfig { seed: 7, ..SimConfig::default() };
+        let outcome = Simulation::with_faults(config, preproposals(config.nodes), faults).run();
+
+        assert!(outcome.all_decided(), "{outcome:?}");
+        assert!(outcome.agreement());
+        assert!(outcome.time >= 200_000);
+    }
+
+    #[test]
+    fn pacemaker_lets_a_lagging_node_skip_ranks() {
+        // Node 2 hears nothing for the first 200ms, while the others adopt through the forced ranks without it
+        let deaf = ScriptedFault { from: None, to: Some(2), start: 0, end: 200_000, action: FaultAction::Hold };
+        let defaults = SimConfig::default();
+        let config = SimConfig { seed: 5, config: Config { forced_adopt_ranks: 6, ..defaults.config }, ..defaults };
+
+        let mut simulation = Simulation::with_faults(config, preproposals(config.nodes), Faults::default().with_scripted(deaf));
+        let outcome = simulation.run();
+
+        assert!(outcome.all_decided() && outcome.agreement(), "{outcome:?}");
+        // Without the certificates of the higher ranks, it would adopt in each of the forced ranks
+        let stats = simulation.stats();
+        assert!(stats[2].adopts < 6, "{stats:?}");
+        assert_eq!(stats[0].adopts, 6);
+    }
+
+    #[test]
+    fn correct_nodes_decide_while_one_node_is_muted() {
+        let mute = ScriptedFault { from: Some(2), to: None, start: 0, end: Time::MAX, action: FaultAction::Drop };
+        let config = SimConfig { seed: 3, time_limit: 5_000_000, ..SimConfig::default() };
+
+        let outcome = Simulation::with_faults(config, preproposals(config.nodes), Faults::default().with_scripted(mute)).run();
+
+        assert!(outcome.agreement());
+        assert!([0, 1, 3].iter().all(|&node| outcome.decisions[node].is_some()), "{outcome:?}");
+        assert!(outcome.dropped > 0);
+    }
+
+    #[test]
+    fn forced_adopt_decides_at_later_ranks() {
+        for seed in 0..10 {
+            let config = SimConfig { seed, config: Config { forced_adopt_ranks: 2, ..SimConfig::default().config }, ..SimConfig::default() };
+            let mut simulation = Simulation::new(config, preproposals(config.nodes));
+            let outcome = simulation.run();
+
+            assert!(outcome.all_decided(), "seed {seed}: {outcome:?}");
+            assert!(outcome.agreement(), "seed {seed}: {outcome:?}");
+            // Every node went through the R steps of ranks 1 and 2, with certificates of B answers
+            for node in &simulation.nodes {
+                assert_eq!(node.core.responses().count(Step::R, 2), 3, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn slow_node_does_not_block_the_others() {
+        let slow = PeerDelay { inbound: Delay::Fixed(500_000), outbound: Delay::Fixed(500_000) };
+        let config = SimConfig { seed: 5, ..SimConfig::default() };
+        let mut simulation = Simulation::with_faults(config, preproposals(config.nodes), Faults::default().with_slow_peer(2, slow));
+
+        // The other three form a quorum on their own and decide before any message of the slow node arrives
+        while [0, 1, 3].iter().any(|&node| simulation.decisions()[node].is_none()) {
+            assert!(simulation.step(), "{:?}", simulation.outcome());
+        }
+        assert!(simulation.now() < 500_000);
+
+        // Its messages are still answered and accepted, so it decides the same value once they arrive
+        let outcome = simulation.run();
+        assert!(outcome.all_decided(), "{outcome:?}");
+        assert!(outcome.agreement(), "{outcome:?}");
+    }
+
+    #[test]
+    fn slow_node_with_variable_delay_decides() {
+        let slow = PeerDelay { inbound: Delay::Exponential { mean: 50_000 }, outbound: Delay::Uniform(10_000, 100_000) };
+
+        for seed in 0..10 {
+            let config = SimConfig { nodes: 7, f: 2, seed, ..SimConfig::default() };
+            let faults = Faults::default().with_slow_peer(seed as Id % 7, slow);
+            let outcome = Simulation::with_faults(config, preproposals(config.nodes), faults).run();
+
+            assert!(outcome.all_decided(), "seed {seed}: {outcome:?}");
+            assert!(outcome.agreement(), "seed {seed}: {outcome:?}");
+        }
+    }
+
+    fn schedule() -> impl Strategy<Value = (SimConfig, Faults)> {
+        (1usize..=2, any::<u64>(), 0..5_000u64, 0..20_000u64, 0.0..0.1f64, 0.0..0.3f64, 0.0..0.5f64)
+            .prop_map(|(f, seed, min_latency, spread, drop_rate, duplicate_rate, reorder_rate)| {
+                let config = SimConfig {
+                    nodes: 3 * f + 1,
+                    f,
+                    seed,
+                    min_latency,
+                    max_latency: min_latency + spread,
+                    time_limit: 5_000_000,
+                    ..SimConfig::default()
+                };
+                let faults = Faults::new(LinkFaults { drop_rate, duplicate_rate, reorder_rate, delay: Delay::default() });
+
+                (config, faults)
+            })
+    }
+
+    fn values(nodes: usize) -> impl Strategy<Value = Vec<PreProposal>> {
+        proptest::collection::vec(proptest::collection::vec(any::<u64>(), 1..4), nodes).prop_map(|values| {
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(id, frontiers)| PreProposal::new(frontiers.into_iter().map(BlockHash::from).collect(), id as Id))
+                .collect()
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn agreement_and_validity((config, faults, preproposals) in schedule().prop_flat_map(|(config, faults)| {
+            (Just(config), Just(faults), values(config.nodes))
+        })) {
+            let mut simulation = Simulation::with_faults(config, preproposals, faults);
+            let outcome = simulation.run();
+            let proposals = simulation.proposals();
+
+            // (a) no two correct processes commit different values
+            prop_assert!(outcome.agreement(), "{:?}", outcome);
+            // (b) any committed value was proposed by some process
+            for decision in outcome.decisions.iter().flatten() {
+                prop_assert!(proposals.contains(decision), "{:?} not in {:?}", decision, proposals);
+            }
+        }
+    }
+}
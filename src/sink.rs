@@ -0,0 +1,219 @@
+use std::{fmt, fs::{File, OpenOptions}, io::{self, Write}, net::{SocketAddr, TcpStream}, path::Path, sync::{atomic::{AtomicU64, Ordering}, mpsc::{sync_channel, SyncSender, TrySendError}, Arc, Mutex}, thread};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use log::warn;
+
+// Where the lines of the audit stream go, one JSON object per line without the newline
+pub trait Sink: Send + fmt::Debug {
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+
+    // Makes the lines written so far durable, or at least sent
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Keeps the lines in memory, for tests and for callers forwarding them themselves
+impl Sink for Arc<Mutex<Vec<String>>> {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.lock().unwrap().push(line.to_string());
+        Ok(())
+    }
+}
+
+// Appends to a file, flushed after every line
+#[derive(Debug)]
+pub struct FileSink(File);
+
+impl FileSink {
+    pub fn append(path: &Path) -> io::Result<FileSink> {
+        OpenOptions::new().create(true).append(true).open(path).map(FileSink)
+    }
+}
+
+impl Sink for FileSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.0, "{}", line)?;
+        self.0.flush()
+    }
+}
+
+// Sends each line to the local syslog daemon as an RFC 3164 message of the local0 facility, at info severity
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SyslogSink {
+    socket: UnixDatagram,
+    tag: String,
+}
+
+#[cfg(unix)]
+impl SyslogSink {
+    // Usually /dev/log
+    pub fn connect(path: &Path, tag: &str) -> io::Result<SyslogSink> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(SyslogSink { socket, tag: tag.to_string() })
+    }
+}
+
+#[cfg(unix)]
+impl Sink for SyslogSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        // local0 (16) * 8 + info (6)
+        self.socket.send(format!("<134>{}: {}", self.tag, line).as_bytes()).map(|_| ())
+    }
+}
+
+// Streams the lines over TCP to a log collector, connecting again once for a line that fails to go out
+#[derive(Debug)]
+pub struct CollectorSink {
+    address: SocketAddr,
+    stream: Option<TcpStream>,
+}
+
+impl CollectorSink {
+    pub fn connect(address: SocketAddr) -> io::Result<CollectorSink> {
+        Ok(CollectorSink { address, stream: Some(TcpStream::connect(address)?) })
+    }
+
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self.stream.insert(TcpStream::connect(self.address)?),
+        };
+        let sent = writeln!(stream, "{}", line);
+        if sent.is_err() {
+            self.stream = None;
+        }
+        sent
+    }
+}
+
+impl Sink for CollectorSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.send(line).or_else(|_| self.send(line))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.as_mut().map_or(Ok(()), Write::flush)
+    }
+}
+
+// What a QueuedSink does with a line once its queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    // Waits for room, slowing down the commits of the process to the pace of the sink
+    #[default]
+    Block,
+    // Drops the line, counted in `dropped`, so that a slow sink never holds back consensus. Receivers notice the gap
+    // as a broken chain
+    Drop,
+}
+
+// Writes to another sink on a thread of its own, through a queue of bounded capacity
+// Write errors of the sink are logged, since the line was already accepted
+#[derive(Debug)]
+pub struct QueuedSink {
+    queue: SyncSender<String>,
+    backpressure: Backpressure,
+    dropped: Arc<AtomicU64>,
+}
+
+impl QueuedSink {
+    pub fn spawn(mut sink: impl Sink + 'static, capacity: usize, backpressure: Backpressure) -> QueuedSink {
+        let (queue, lines) = sync_channel::<String>(capacity.max(1));
+
+        // Stops once the QueuedSink is dropped and the queue drained
+        thread::spawn(move || {
+            for line in lines {
+                if let Err(error) = sink.write_line(&line).and_then(|_| sink.flush()) {
+                    warn!("cannot write to {:?}: {}", sink, error);
+                }
+            }
+        });
+
+        QueuedSink { queue, backpressure, dropped: Arc::default() }
+    }
+
+    // Lines dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Sink for QueuedSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "sink thread stopped");
+
+        match self.backpressure {
+            Backpressure::Block => self.queue.send(line.to_string()).map_err(|_| closed()),
+            Backpressure::Drop => match self.queue.try_send(line.to_string()) {
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+                sent => sent.map_err(|_| closed()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader}, net::TcpListener, sync::mpsc::{channel, Receiver}, time::{Duration, Instant}};
+    use super::*;
+
+    // Writes a line only once it is let through
+    #[derive(Debug)]
+    struct Gated(Receiver<()>, Arc<Mutex<Vec<String>>>);
+
+    impl Sink for Gated {
+        fn write_line(&mut self, line: &str) -> io::Result<()> {
+            self.0.recv().map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            self.1.write_line(line)
+        }
+    }
+
+    fn wait_for(lines: &Arc<Mutex<Vec<String>>>, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while lines.lock().unwrap().len() < count {
+            assert!(Instant::now() < deadline, "{:?}", lines);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn full_queues_block_or_drop() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let (gate, gated) = channel();
+        let mut dropping = QueuedSink::spawn(Gated(gated, written.clone()), 1, Backpressure::Drop);
+
+        // One line held by the writer, one queued, the others dropped
+        for line in ["a", "b", "c", "d"] {
+            dropping.write_line(line).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        (0..4).for_each(|_| gate.send(()).unwrap());
+        wait_for(&written, 2);
+        assert_eq!(*written.lock().unwrap(), vec!["a", "b"]);
+        assert_eq!(dropping.dropped(), 2);
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut blocking = QueuedSink::spawn(written.clone(), 1, Backpressure::Block);
+        (0..100).for_each(|line| blocking.write_line(&line.to_string()).unwrap());
+        wait_for(&written, 100);
+        assert_eq!(blocking.dropped(), 0);
+    }
+
+    #[test]
+    fn collectors_receive_the_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sink = CollectorSink::connect(listener.local_addr().unwrap()).unwrap();
+        sink.write_line(r#"{"instance":0}"#).unwrap();
+        sink.flush().unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let line = BufReader::new(stream).lines().next().unwrap().unwrap();
+        assert_eq!(line, r#"{"instance":0}"#);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{collections::BTreeMap, sync::{atomic::{AtomicU64, Ordering}, Mutex}};
+use crate::Rank;
+
+// Why a broadcast was not answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Rejection {
+    // Its rank was evicted before it arrived
+    Stale,
+    // It, or a response of its certificate, carries the configuration hash of another committee, see Identities::pin
+    ForeignCommittee,
+    // Neither a certificate nor references to one
+    MissingCertificate,
+    // More responses than members
+    OversizedCertificate,
+    // Fewer than 2f+1 responses
+    SmallCertificate,
+    // A response that does not answer the previous step and rank
+    InvalidEntry,
+    // A response whose signature does not check
+    InvalidSignature,
+    // A flag on an R or A broadcast, or none on a B broadcast
+    InvalidFlag,
+    // The value (and flag) do not follow from the certificate
+    UnjustifiedValue,
+    // The validity predicate of the application does not hold for the value, see ValidityPredicate
+    InvalidValue,
+}
+
+impl Rejection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rejection::Stale => "stale",
+            Rejection::ForeignCommittee => "foreign_committee",
+            Rejection::MissingCertificate => "missing_certificate",
+            Rejection::OversizedCertificate => "oversized_certificate",
+            Rejection::SmallCertificate => "small_certificate",
+            Rejection::InvalidEntry => "invalid_entry",
+            Rejection::InvalidSignature => "invalid_signature",
+            Rejection::InvalidFlag => "invalid_flag",
+            Rejection::UnjustifiedValue => "unjustified_value",
+            Rejection::InvalidValue => "invalid_value",
+        }
+    }
+
+    pub fn stage(&self) -> RejectionStage {
+        match self {
+            Rejection::InvalidSignature => RejectionStage::Signature,
+            Rejection::UnjustifiedValue | Rejection::InvalidValue => RejectionStage::Certificate,
+            _ => RejectionStage::Structure,
+        }
+    }
+}
+
+// Stages of the receive pipeline, in the order they run: each costs more than the one before, so that a bogus message
+// is dropped as cheaply as possible
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RejectionStage {
+    // Sizes, steps, ranks and flags
+    Structure,
+    Signature,
+    // The values a certificate justifies
+    Certificate,
+}
+
+impl RejectionStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RejectionStage::Structure => "structure",
+            RejectionStage::Signature => "signature",
+            RejectionStage::Certificate => "certificate",
+        }
+    }
+}
+
+// Cumulative counters of a process, updated by its proposer and its run loop
+#[derive(Debug, Default)]
+pub struct ConsensusMetrics {
+    instances_decided: AtomicU64,
+    // Ranks run by the decided instances, the deciding one included
+    ranks_to_commit: AtomicU64,
+    adopts: AtomicU64,
+    fast_commits: AtomicU64,
+    equivocations: AtomicU64,
+    rejected: Mutex<BTreeMap<Rejection, u64>>,
+    dropped: Mutex<BTreeMap<RejectionStage, u64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConsensusStats {
+    pub instances_decided: u64,
+    pub ranks_to_commit: u64,
+    pub adopts: u64,
+    // Commits on answers after the first 2f+1 did not commit, see Config::fast_commit_window
+    pub fast_commits: u64,
+    // Senders seen with two different preproposals, or broadcasts of the same step and rank
+    pub equivocations: u64,
+    pub rejected: BTreeMap<Rejection, u64>,
+    // Invalid messages of any kind by the stage that dropped them, rejected broadcasts included
+    pub dropped: BTreeMap<RejectionStage, u64>,
+}
+
+impl ConsensusMetrics {
+    // An instance proposed at rank `first` and committed at rank `decided`
+    pub fn decided(&self, first: Rank, decided: Rank) {
+        self.instances_decided.fetch_add(1, Ordering::Relaxed);
+        self.ranks_to_commit.fetch_add(decided.saturating_sub(first) as u64 + 1, Ordering::Relaxed);
+    }
+
+    pub fn adopted(&self) {
+        self.adopts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn fast_committed(&self) {
+        self.fast_commits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn equivocation(&self) {
+        self.equivocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self, reason: Rejection) {
+        *self.rejected.lock().unwrap().entry(reason).or_default() += 1;
+        self.dropped(reason.stage());
+    }
+
+    pub fn dropped(&self, stage: RejectionStage) {
+        *self.dropped.lock().unwrap().entry(stage).or_default() += 1;
+    }
+
+    pub fn stats(&self) -> ConsensusStats {
+        ConsensusStats {
+            instances_decided: self.instances_decided.load(Ordering::Relaxed),
+            ranks_to_commit: self.ranks_to_commit.load(Ordering::Relaxed),
+            adopts: self.adopts.load(Ordering::Relaxed),
+            fast_commits: self.fast_commits.load(Ordering::Relaxed),
+            equivocations: self.equivocations.load(Ordering::Relaxed),
+            rejected: self.rejected.lock().unwrap().clone(),
+            dropped: self.dropped.lock().unwrap().clone(),
+        }
+    }
+}
+
+impl ConsensusStats {
+    // 1.0 when every instance commits in the rank it started in
+    pub fn average_ranks_to_commit(&self) -> Option<f64> {
+        (self.instances_decided > 0).then(|| self.ranks_to_commit as f64 / self.instances_decided as f64)
+    }
+
+    pub fn rejected_broadcasts(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_ranks_over_decided_instances() {
+        let metrics = ConsensusMetrics::default();
+        assert_eq!(metrics.stats().average_ranks_to_commit(), None);
+
+        metrics.decided(0, 0);
+        metrics.decided(2, 4);
+        metrics.adopted();
+        metrics.adopted();
+        metrics.rejected(Rejection::Stale);
+        metrics.rejected(Rejection::InvalidFlag);
+        metrics.rejected(Rejection::Stale);
+        metrics.dropped(RejectionStage::Signature);
+
+        let stats = metrics.stats();
+        assert_eq!((stats.instances_decided, stats.ranks_to_commit, stats.adopts), (2, 4, 2));
+        assert_eq!(stats.average_ranks_to_commit(), Some(2.0));
+        assert_eq!(stats.rejected.get(&Rejection::Stale), Some(&2));
+        assert_eq!(stats.rejected_broadcasts(), 3);
+        assert_eq!(stats.dropped, BTreeMap::from([(RejectionStage::Structure, 3), (RejectionStage::Signature, 1)]));
+    }
+}
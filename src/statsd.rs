@@ -0,0 +1,87 @@
+use std::{io, mem, net::{SocketAddr, UdpSocket}, time::Duration};
+use crate::MetricsSink;
+
+// Lines are packed into datagrams of at most this many bytes, which are not fragmented on common networks
+const MAX_DATAGRAM: usize = 512;
+
+// Pushes the metrics of a process to a statsd daemon every interval, for deployments that do not scrape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsdConfig {
+    pub address: SocketAddr,
+    pub interval: Duration,
+}
+
+impl StatsdConfig {
+    pub fn new(address: SocketAddr) -> StatsdConfig {
+        StatsdConfig { address, interval: Duration::from_secs(10) }
+    }
+}
+
+// Sends each gauge as a `<prefix>.<name>:<value>|g` line over UDP, where losing a datagram only loses a sample
+#[derive(Debug)]
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    datagrams: Vec<String>,
+}
+
+impl StatsdSink {
+    pub fn connect(address: SocketAddr, prefix: &str) -> io::Result<StatsdSink> {
+        let local: SocketAddr = if address.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+
+        Ok(StatsdSink { socket, prefix: prefix.to_string(), datagrams: Vec::new() })
+    }
+}
+
+impl MetricsSink for StatsdSink {
+    fn gauge(&mut self, name: &str, value: f64) {
+        let line = format!("{}.{}:{}|g", self.prefix, name, value);
+
+        match self.datagrams.last_mut() {
+            Some(datagram) if datagram.len() + 1 + line.len() <= MAX_DATAGRAM => {
+                datagram.push('\n');
+                datagram.push_str(&line);
+            }
+            _ => self.datagrams.push(line),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for datagram in mem::take(&mut self.datagrams) {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_gauges_into_datagrams() {
+        let daemon = UdpSocket::bind("127.0.0.1:0").unwrap();
+        daemon.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut sink = StatsdSink::connect(daemon.local_addr().unwrap(), "archipelago.3").unwrap();
+
+        for peer in 0..40 {
+            sink.gauge(&format!("peer.{}.sent_messages", peer), peer as f64);
+        }
+        sink.gauge("latency.commit.mean_ms", 1.5);
+        sink.flush().unwrap();
+
+        let mut lines = Vec::new();
+        let mut buffer = [0; 2 * MAX_DATAGRAM];
+        while lines.len() < 41 {
+            let length = daemon.recv(&mut buffer).unwrap();
+            assert!(length <= MAX_DATAGRAM);
+            lines.extend(String::from_utf8(buffer[..length].to_vec()).unwrap().lines().map(str::to_string));
+        }
+
+        assert_eq!(lines[0], "archipelago.3.peer.0.sent_messages:0|g");
+        assert_eq!(lines[39], "archipelago.3.peer.39.sent_messages:39|g");
+        assert_eq!(lines[40], "archipelago.3.latency.commit.mean_ms:1.5|g");
+    }
+}
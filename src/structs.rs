@@ -1,10 +1,22 @@
-use std::{cmp::Ordering, hash::{DefaultHasher, Hash, Hasher}};
-use rsnano_core::BlockHash;
-use crate::{PreProposal, Proposal, ProposalHash, Decision::{Commit, Adopt}};
+use std::{cmp::Ordering, hash::{Hash, Hasher}, ops::Range, sync::Arc};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use smallvec::SmallVec;
+use crate::{CommitRecord, Hello, PreProposal, PreProposalHash, Proposal, ProposalHash, Rejoin, TraceContext, Vote, VoteHash, Decision::{Commit, Adopt}};
 
 pub type Id = i64;
 pub type Rank = i64;
-pub type BroadcastHash = u64;
+pub type BroadcastHash = BlockHash;
+pub type ResponseHash = BlockHash;
+pub type CertificateHash = BlockHash;
+
+// R and B responses carry a single state, A responses at most two
+pub type States = SmallVec<[State; 2]>;
+
+// 2f+1 responses, kept inline for committees of up to 7 processes
+pub type CertificateResponses = SmallVec<[Response; 7]>;
+
+// 2f+1 responses of the previous step, shared so that cloning a broadcast does not copy them
+pub type Certificate = Arc<CertificateResponses>;
 
 // Extract the sender (header) from the content of the message
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
@@ -12,56 +24,307 @@ pub enum Message {
     Broadcast(Broadcast),
     Response(Response),
     Proposal(Proposal),
-    PreProposal(PreProposal)
+    PreProposal(PreProposal),
+    // Value the sender is about to start rank 0 with, see Config::pre_vote_timeout
+    PreVote(Id, ProposalHash),
+    // Messages coalesced by the outbox of the sender
+    Batch(Vec<Message>),
+    // Messages coalesced by the outbox of the sender under its next sequence number for the receiver, see
+    // BatchConfig::ack_timeout
+    Sequenced(Id, u64, Vec<Message>),
+    // Every sequenced batch of the receiver up to this sequence number reached the sender
+    Ack(Id, u64),
+    // Asks the sender of a broadcast whose certificate is by reference for the responses the requester has not seen
+    GetResponses(Id, Vec<ResponseHash>),
+    // Answer to GetResponses
+    Responses(Id, Vec<Response>),
+    // Asks the sender of a broadcast whose certificate is sent by hash for the certificate
+    GetCertificate(Id, CertificateHash),
+    // Answer to GetCertificate
+    Certificate(Id, Certificate),
+    // Hashes of the preproposals the sender holds, announced periodically for the processes that started late
+    PreProposalDigest(Id, Vec<PreProposalHash>),
+    // Asks the sender of a digest for the preproposals the requester does not hold, answered with the preproposals
+    GetPreProposals(Id, Vec<PreProposalHash>),
+    // Final votes for frontiers, aggregated into the preproposals
+    Vote(Vote),
+    // Hashes of votes the sender relays, see VoteGossip::RelayByHash
+    VoteHashes(Id, Vec<VoteHash>),
+    // Asks the sender of VoteHashes for the votes the requester has not seen, answered with the votes
+    GetVotes(Id, Vec<VoteHash>),
+    // Sent by a process coming back after a restart, with the instance of the next commit it has not seen
+    Announce(Id, u64),
+    // Answer to Announce, the commits of the sender from that instance on
+    Commits(Id, Vec<CommitRecord>),
+    // Asks for the commits of the instances with their certificates, see Process::backfill
+    GetCertificates(Id, Range<u64>),
+    // Answer to GetCertificates, the ones of those commits the sender kept
+    Certificates(Id, Vec<CommitRecord>),
+    // Version, features and committee of the sender, answered with the ones of the receiver if asked for
+    Hello(Hello),
+    // Sent by a member banned for equivocation to be taken back, see Rejoin
+    Rejoin(Rejoin),
+    // A message of one of the consensus instances run side by side, see Instances
+    Instance(u64, Box<Message>),
+}
+
+impl Message {
+    pub fn into_messages(self) -> Vec<Message> {
+        match self {
+            Message::Batch(messages) => messages,
+            // The header goes first, without the messages, so that whoever unbatches them can still acknowledge them
+            Message::Sequenced(sender, sequence, mut messages) => {
+                messages.insert(0, Message::Sequenced(sender, sequence, Vec::new()));
+                messages
+            }
+            message => vec![message],
+        }
+    }
+
+    pub fn sender(&self) -> Option<Id> {
+        match self {
+            Message::Broadcast(broadcast) => Some(broadcast.sender),
+            Message::Response(response) => Some(response.sender),
+            Message::Proposal(proposal) => Some(proposal.sender),
+            Message::PreProposal(preproposal) => Some(preproposal.sender),
+            Message::PreVote(sender, _) => Some(*sender),
+            Message::Batch(messages) => messages.first().and_then(Message::sender),
+            Message::Sequenced(sender, ..) => Some(*sender),
+            Message::Ack(sender, _) => Some(*sender),
+            Message::GetResponses(requester, _) => Some(*requester),
+            Message::Responses(responder, _) => Some(*responder),
+            Message::GetCertificate(requester, _) => Some(*requester),
+            Message::Certificate(responder, _) => Some(*responder),
+            Message::PreProposalDigest(sender, _) => Some(*sender),
+            Message::GetPreProposals(requester, _) => Some(*requester),
+            Message::Vote(vote) => Some(vote.voter),
+            Message::VoteHashes(sender, _) => Some(*sender),
+            Message::GetVotes(requester, _) => Some(*requester),
+            Message::Announce(sender, _) => Some(*sender),
+            Message::Commits(sender, _) => Some(*sender),
+            Message::GetCertificates(requester, _) => Some(*requester),
+            Message::Certificates(responder, _) => Some(*responder),
+            Message::Hello(hello) => Some(hello.sender),
+            Message::Rejoin(rejoin) => Some(rejoin.sender),
+            Message::Instance(_, message) => message.sender(),
+        }
+    }
 }
 
 // A process only sends one broadcast per step and rank
-#[derive(Debug, Clone, Eq, PartialEq)]
+// The hash is computed once at construction and names the broadcast in answers and certificate references. It leaves
+// the sender out, so that the answers to broadcasts of the same value by different senders count together (Line 91).
+// Equality compares the fields instead, the sender included, so that broadcasts whose hashes match still differ
+#[derive(Debug, Clone)]
 pub struct Broadcast {
     pub sender: Id,
     pub step: Step,
     pub value: ProposalHash,
     pub flag: Option<bool>,
     pub rank: Rank,
-    pub previous_step_responses: Option<Vec<Response>>
+    pub previous_step_responses: Option<Certificate>,
+    // Hashes of the certificate responses, sent instead of the responses themselves
+    pub certificate_refs: Option<Vec<ResponseHash>>,
+    // Hash of the certificate, sent instead of the certificate itself
+    pub certificate_hash: Option<CertificateHash>,
+    // Not part of the hash, a broadcast is the same whatever trace it was sent in
+    pub trace: Option<TraceContext>,
+    // Configuration of the committee the broadcast was sent in, see Identities::pin. Part of the hash if set
+    pub committee: Option<BlockHash>,
+    pub hash: BroadcastHash,
+    // Ed25519 signature of the sender over signing_digest, see Identities::sign_broadcast. Not part of the hash
+    pub signature: Option<[u8; 64]>,
 }
 
 impl Hash for Broadcast {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.step.hash(state);
-        self.value.hash(state);
-        self.flag.hash(state);
-        self.rank.hash(state);
+        self.hash.hash(state);
     }
 }
 
+// Never walks the certificates, which the hash does not cover either
+impl PartialEq for Broadcast {
+    fn eq(&self, other: &Self) -> bool {
+        self.sender == other.sender
+            && self.step == other.step
+            && self.value == other.value
+            && self.flag == other.flag
+            && self.rank == other.rank
+            && self.committee == other.committee
+    }
+}
+
+impl Eq for Broadcast {}
+
 impl Broadcast {
-    pub fn new(sender: Id, step: Step, value: ProposalHash, flag: Option<bool>, rank: Rank, previous_step_responses: Option<Vec<Response>>) -> Broadcast {
-        Broadcast { sender, step, value, flag, rank, previous_step_responses }
+    pub fn new(sender: Id, step: Step, value: ProposalHash, flag: Option<bool>, rank: Rank, previous_step_responses: Option<CertificateResponses>) -> Broadcast {
+        let hash = Broadcast::compute_hash(step, value, flag, rank, None);
+        let previous_step_responses = previous_step_responses.map(Arc::new);
+        Broadcast { sender, step, value, flag, rank, previous_step_responses, certificate_refs: None, certificate_hash: None, trace: None, committee: None, hash, signature: None }
+    }
+
+    pub fn pinned(mut self, committee: Option<BlockHash>) -> Broadcast {
+        self.committee = committee;
+        self.rehash();
+        self
+    }
+
+    pub fn with_trace(mut self, trace: Option<TraceContext>) -> Broadcast {
+        self.trace = trace;
+        self
+    }
+
+    // Replaces the certificate by the hashes of its responses
+    pub fn into_reference(mut self) -> Broadcast {
+        if let Some(responses) = self.previous_step_responses.take() {
+            self.certificate_refs = Some(responses.iter().map(Response::hash_value).collect());
+        }
+        self
+    }
+
+    // A certificate by reference still has to be resolved before the broadcast can be checked
+    pub fn is_by_reference(&self) -> bool {
+        self.previous_step_responses.is_none() && self.certificate_refs.is_some()
+    }
+
+    // What a response needs of the broadcast justifying its value: its sender and the fields its hash covers, without
+    // the certificate and so without the responses nested in it
+    pub fn header(&self) -> Broadcast {
+        Broadcast { previous_step_responses: None, certificate_refs: None, certificate_hash: None, trace: None, ..self.clone() }
+    }
+
+    // Replaces the certificate by its hash, receivers that do not have it ask the sender for it
+    pub fn into_lazy(mut self) -> Broadcast {
+        if let Some(responses) = self.previous_step_responses.take() {
+            self.certificate_hash = Some(Broadcast::certificate_hash(&responses));
+        }
+        self
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        self.previous_step_responses.is_none() && self.certificate_hash.is_some()
+    }
+
+    // Hashes of the responses, in the order of the certificate
+    pub fn certificate_hash(responses: &[Response]) -> CertificateHash {
+        let hasher = responses.iter().fold(Blake2HashBuilder::new().update(b"certificate "), |hasher, response| hasher.update(response.hash_value().as_bytes()));
+        hasher.build()
+    }
+
+    // Attaches the responses a certificate by reference points to, in the order of the references
+    pub fn resolve(&mut self, responses: CertificateResponses) {
+        self.previous_step_responses = Some(Arc::new(responses));
     }
 
     pub fn hash_value(&self) -> BroadcastHash {
-        let mut state = DefaultHasher::new();
-        self.step.hash(&mut state);
-        self.value.hash(&mut state);
-        self.flag.hash(&mut state);
-        self.rank.hash(&mut state);
-        state.finish()
+        self.hash
+    }
+
+    // The hash with the sender, which the hash leaves out
+    pub fn signing_digest(&self) -> BlockHash {
+        Blake2HashBuilder::new().update(b"signed broadcast ").update(self.sender.to_le_bytes()).update(self.hash.as_bytes()).build()
+    }
+
+    // Must be called after mutating step, value, flag or rank in place
+    pub fn rehash(&mut self) {
+        self.hash = Broadcast::compute_hash(self.step, self.value, self.flag, self.rank, self.committee);
+    }
+
+    // Blake2b over a fixed encoding of the fields, so that every release and implementation computes the same hash
+    // Broadcasts of unpinned committees hash as they did before pinning existed
+    fn compute_hash(step: Step, value: ProposalHash, flag: Option<bool>, rank: Rank, committee: Option<BlockHash>) -> BroadcastHash {
+        let step = match step {
+            Step::R => 0u8,
+            Step::A => 1,
+            Step::B => 2,
+        };
+        let flag = match flag {
+            None => 0u8,
+            Some(false) => 1,
+            Some(true) => 2,
+        };
+        let mut hasher = Blake2HashBuilder::new()
+            .update(b"broadcast ")
+            .update([step])
+            .update(value.as_bytes())
+            .update([flag])
+            .update(rank.to_le_bytes());
+        if let Some(committee) = &committee {
+            hasher = hasher.update(committee.as_bytes());
+        }
+        hasher.build()
     }
 }
 
 // Page 25 of the technical report: "Since processes can only ever send one B-answer to each process..."
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Response {
     pub sender: Id,
     pub step: Step, 
     pub rank: Rank,
-    pub state: Vec<State>,
+    pub state: States,
+    // Hash of the broadcast the response answers, zero if it answers none (e.g. in tests)
+    pub answers: BroadcastHash,
+    // Configuration of the committee the response was sent in, see Identities::pin
+    pub committee: Option<BlockHash>,
+    // Ed25519 signature of the sender over the rest of the response, see Identities
+    pub signature: Option<[u8; 64]>,
+}
+
+// The signature is left out, so that the hash of an answer does not depend on whether it is signed
+impl Hash for Response {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sender.hash(state);
+        self.step.hash(state);
+        self.rank.hash(state);
+        self.state.hash(state);
+        self.answers.hash(state);
+        if let Some(committee) = &self.committee {
+            committee.hash(state);
+        }
+    }
 }
 
 impl Response {
-    pub fn new(sender: Id, step: Step, rank: Rank, state: Vec<State>) -> Self {
-        Self { sender, step, rank, state }
+    pub fn new(sender: Id, step: Step, rank: Rank, state: impl Into<States>) -> Self {
+        Self { sender, step, rank, state: state.into(), answers: BlockHash::zero(), committee: None, signature: None }
+    }
+
+    pub fn answering(mut self, broadcast: BroadcastHash) -> Self {
+        self.answers = broadcast;
+        self
+    }
+
+    pub fn hash_value(&self) -> ResponseHash {
+        self.digest()
+    }
+
+    // Blake2b over the fixed encoding of the response without its signature, which the signature covers
+    pub fn digest(&self) -> BlockHash {
+        let step = match self.step {
+            Step::R => 0u8,
+            Step::A => 1,
+            Step::B => 2,
+        };
+        let mut hasher = Blake2HashBuilder::new()
+            .update(b"response ")
+            .update(self.sender.to_le_bytes())
+            .update([step])
+            .update(self.rank.to_le_bytes())
+            .update(self.answers.as_bytes());
+        if let Some(committee) = &self.committee {
+            hasher = hasher.update(committee.as_bytes());
+        }
+
+        for state in &self.state {
+            hasher = match state.value {
+                Value::RValue(r_value) => hasher.update(r_value.rank.to_le_bytes()).update(r_value.value.as_bytes()),
+                Value::AValue(a_value) => hasher.update(a_value.0.as_bytes()),
+                Value::BValue(b_value) => hasher.update(b_value.value.as_bytes()).update([b_value.flag as u8]),
+            };
+            hasher = hasher.update(state.broadcast.hash_value().as_bytes());
+        }
+        hasher.build()
     }
 }
 
@@ -79,14 +342,15 @@ pub enum Value {
     BValue(BValue),
 }
 
+// The broadcast is shared with the broadcasts map of the process that answered it
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct State {
     pub value: Value,
-    pub broadcast: Broadcast,
+    pub broadcast: Arc<Broadcast>,
 }
 
 impl State {
-    pub fn new(value: Value, broadcast: Broadcast) -> Self {
+    pub fn new(value: Value, broadcast: Arc<Broadcast>) -> Self {
         Self { value, broadcast }
     }
 }
@@ -137,11 +401,11 @@ impl Ord for RValue {
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct RState {
     pub r_value: RValue, 
-    pub broadcast: Broadcast,
+    pub broadcast: Arc<Broadcast>,
 }
 
 impl RState {
-    pub fn new(r_value: RValue, broadcast: Broadcast) -> RState {
+    pub fn new(r_value: RValue, broadcast: Arc<Broadcast>) -> RState {
         RState { r_value, broadcast}
     }
 }
@@ -149,11 +413,11 @@ impl RState {
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct AState {
     pub a_value: AValue, 
-    pub broadcast: Broadcast,
+    pub broadcast: Arc<Broadcast>,
 }
 
 impl AState {
-    pub fn new(a_value: AValue, broadcast: Broadcast) -> AState {
+    pub fn new(a_value: AValue, broadcast: Arc<Broadcast>) -> AState {
         AState { a_value, broadcast }
     }
 }
@@ -161,11 +425,11 @@ impl AState {
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct BState {
     pub b_value: BValue, 
-    pub broadcast: Broadcast,
+    pub broadcast: Arc<Broadcast>,
 }
 
 impl BState {
-    pub fn new(b_value: BValue, broadcast: Broadcast) -> BState {
+    pub fn new(b_value: BValue, broadcast: Arc<Broadcast>) -> BState {
         BState { b_value, broadcast }
     }
 }   
@@ -185,4 +449,51 @@ impl Decision {
     }
 }
 
-        
\ No newline at end of file
+        
+#[test]
+fn broadcast_equality_ignores_certificate() {
+    let response = Response::new(1, Step::B, 0, Vec::new());
+    let broadcast1 = Broadcast::new(0, Step::R, BlockHash::from(1), None, 1, None);
+    let broadcast2 = Broadcast::new(0, Step::R, BlockHash::from(1), None, 1, Some(vec![response].into()));
+
+    assert_eq!(broadcast1, broadcast2);
+    assert_eq!(broadcast1.hash_value(), broadcast2.hash_value());
+}
+
+#[test]
+fn broadcast_rehash_after_mutation() {
+    let mut broadcast = Broadcast::new(0, Step::R, BlockHash::from(1), None, 0, None);
+    let original = broadcast.hash_value();
+
+    broadcast.rank = 1;
+    broadcast.rehash();
+
+    assert_ne!(broadcast.hash_value(), original);
+    assert_eq!(broadcast, Broadcast::new(0, Step::R, BlockHash::from(1), None, 1, None));
+}
+
+#[test]
+fn broadcast_by_reference_resolves_to_same_certificate() {
+    let response1 = Response::new(1, Step::B, 0, Vec::new());
+    let response2 = Response::new(2, Step::B, 0, Vec::new());
+    let broadcast = Broadcast::new(0, Step::R, BlockHash::from(1), None, 1, Some(vec![response1.clone(), response2.clone()].into()));
+
+    let mut by_reference = broadcast.clone().into_reference();
+    assert!(by_reference.is_by_reference());
+    assert_eq!(by_reference.certificate_refs, Some(vec![response1.hash_value(), response2.hash_value()]));
+
+    by_reference.resolve(vec![response1, response2].into());
+    assert!(!by_reference.is_by_reference());
+    assert_eq!(by_reference.previous_step_responses, broadcast.previous_step_responses);
+}
+
+#[test]
+fn response_states_and_certificates_stay_inline() {
+    let broadcast = Arc::new(Broadcast::new(0, Step::A, BlockHash::from(1), None, 0, None));
+    let states: States = (0..2).map(|_| State::new(Value::AValue(AValue(BlockHash::from(1))), broadcast.clone())).collect();
+    let response = Response::new(1, Step::A, 0, states);
+    assert!(!response.state.spilled());
+
+    let certificate: CertificateResponses = (0..7).map(|_| response.clone()).collect();
+    assert!(!certificate.spilled());
+}
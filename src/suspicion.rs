@@ -0,0 +1,93 @@
+use std::{collections::BTreeMap, sync::Mutex, time::{Duration, Instant}};
+use crate::Id;
+
+// Scores halve this often without further offenses
+const HALF_LIFE: Duration = Duration::from_secs(60);
+// Peers scoring at least this much are suspected
+const SUSPECT_SCORE: f64 = 10.0;
+
+// What made a peer more suspect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Offense {
+    // A message of the peer failed validation
+    Invalid,
+    // The peer did not do in time what it had to, e.g. relay the broadcasts it coordinates
+    Timeout,
+    // A proposer stalled without a message of the peer
+    Silent,
+}
+
+impl Offense {
+    fn weight(self) -> f64 {
+        match self {
+            Offense::Invalid => 1.0,
+            Offense::Timeout => 2.0,
+            Offense::Silent => 2.0,
+        }
+    }
+}
+
+// Suspicion score of each peer, which decays exponentially so that old offenses are forgotten. An input for operators
+// and reconfiguration policies, nothing is dropped or banned for it
+#[derive(Debug, Default)]
+pub struct Suspicion {
+    scores: Mutex<BTreeMap<Id, (f64, Instant)>>,
+}
+
+fn decayed(score: f64, since: Instant, now: Instant) -> f64 {
+    score * 0.5f64.powf(now.saturating_duration_since(since).as_secs_f64() / HALF_LIFE.as_secs_f64())
+}
+
+impl Suspicion {
+    pub fn record(&self, peer: Id, offense: Offense) {
+        self.record_at(peer, offense, Instant::now());
+    }
+
+    fn record_at(&self, peer: Id, offense: Offense, now: Instant) {
+        let mut scores = self.scores.lock().unwrap();
+        let (score, since) = scores.entry(peer).or_insert((0.0, now));
+        *score = decayed(*score, *since, now) + offense.weight();
+        *since = now;
+    }
+
+    pub fn score(&self, peer: Id) -> f64 {
+        self.scores_at(Instant::now()).get(&peer).copied().unwrap_or_default()
+    }
+
+    // Current score of every peer with an offense
+    pub fn scores(&self) -> BTreeMap<Id, f64> {
+        self.scores_at(Instant::now())
+    }
+
+    fn scores_at(&self, now: Instant) -> BTreeMap<Id, f64> {
+        self.scores.lock().unwrap().iter().map(|(peer, (score, since))| (*peer, decayed(*score, *since, now))).collect()
+    }
+
+    pub fn suspected(&self) -> Vec<Id> {
+        self.scores().into_iter().filter(|(_, score)| *score >= SUSPECT_SCORE).map(|(peer, _)| peer).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_add_up_and_decay() {
+        let suspicion = Suspicion::default();
+        let start = Instant::now();
+
+        (0..4).for_each(|_| suspicion.record_at(1, Offense::Timeout, start));
+        suspicion.record_at(1, Offense::Invalid, start);
+        suspicion.record_at(2, Offense::Invalid, start);
+        assert_eq!(suspicion.scores_at(start), BTreeMap::from([(1, 9.0), (2, 1.0)]));
+
+        suspicion.record_at(1, Offense::Timeout, start);
+        assert_eq!(suspicion.suspected(), vec![1]);
+
+        let later = suspicion.scores_at(start + HALF_LIFE);
+        assert!((later[&1] - 5.5).abs() < 1e-9 && (later[&2] - 0.5).abs() < 1e-9);
+        suspicion.record_at(1, Offense::Silent, start + HALF_LIFE);
+        assert!((suspicion.scores_at(start + 2 * HALF_LIFE)[&1] - 3.75).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,6 @@
+// Locks shared between the run loop and the proposer threads
+// Under `--cfg loom` they are replaced by the loom model checker's, which explores every interleaving of the loom tests
+#[cfg(loom)]
+pub(crate) use loom::sync::{atomic::AtomicBool, Condvar, Mutex, MutexGuard, RwLock};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{atomic::AtomicBool, Condvar, Mutex, MutexGuard, RwLock};
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use rsnano_core::BlockHash;
+use crate::{AValue, BValue, Decision, ProposalHash, RValue, Response, Value, ValueOrder};
+
+// Running results of a step, updated one response at a time so the proposer can fold
+// responses while it is still waiting for the quorum, and the certificate checks can replay them
+
+fn first_value<T>(response: &Response, extract: impl Fn(&Value) -> Option<T>) -> Option<T> {
+    response.state.iter().find_map(|state| extract(&state.value))
+}
+
+// The value every state of the response holds, None if the response is empty or holds different values
+fn only_value<T: PartialEq>(values: &[T]) -> Option<&T> {
+    let first = values.first()?;
+    values.iter().all(|value| value == first).then_some(first)
+}
+
+fn b_values(response: &Response) -> Vec<BValue> {
+    response.state.iter()
+        .filter_map(|state| match state.value {
+            Value::BValue(b_value) => Some(b_value),
+            _ => None,
+        })
+        .collect()
+}
+
+// The value of a B answer that only holds ⟨true, val⟩, the answers that count towards a commit
+pub fn only_true(response: &Response) -> Option<ProposalHash> {
+    only_value(&b_values(response)).filter(|b_value| b_value.flag).map(|b_value| b_value.value)
+}
+
+#[derive(Debug, Default)]
+pub struct RTally {
+    max: Option<RValue>,
+    order: ValueOrder,
+}
+
+impl RTally {
+    pub fn ordered(mut self, order: ValueOrder) -> RTally {
+        self.order = order;
+        self
+    }
+
+    pub fn add(&mut self, response: &Response) {
+        // Line 20: R ← union of all valid Rs received
+        let r_value = first_value(response, |value| match value {
+            Value::RValue(r_value) => Some(*r_value),
+            _ => None,
+        });
+
+        // Line 21: ⟨i’,v’⟩ ← max(R)
+        self.max = self.max.into_iter().chain(r_value).max_by_key(|r_value| self.order.r_key(*r_value));
+    }
+
+    // None while no response carried an R value, which a well-formed quorum never does
+    pub fn value(&self) -> Option<RValue> {
+        self.max
+    }
+
+    pub fn result(&self) -> RValue {
+        self.max.unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub struct ATally {
+    threshold: usize,
+    counts: HashMap<AValue, usize>,
+    max: Option<AValue>,
+    unanimous: Option<AValue>,
+    forced_adopt: bool,
+    order: ValueOrder,
+}
+
+impl ATally {
+    pub fn new(threshold: usize) -> ATally {
+        ATally { threshold, counts: HashMap::new(), max: None, unanimous: None, forced_adopt: false, order: ValueOrder::default() }
+    }
+
+    pub fn ordered(mut self, order: ValueOrder) -> ATally {
+        self.order = order;
+        self
+    }
+
+    // Ignores unanimity, see Config::forced_adopt_ranks
+    pub fn forcing_adopt(mut self, forced: bool) -> ATally {
+        self.forced_adopt = forced;
+        self
+    }
+
+    pub fn add(&mut self, response: &Response) {
+        // Line 36: S ← union of all A[i]s received
+        let a_values: Vec<AValue> = response.state.iter()
+            .filter_map(|state| match state.value {
+                Value::AValue(a_value) => Some(a_value),
+                _ => None,
+            })
+            .collect();
+
+        // Only answers containing a single value count towards the 2f+1 of Line 37
+        if let Some(&a_value) = only_value(&a_values) {
+            let count = self.counts.entry(a_value).or_insert(0);
+            *count += 1;
+            if *count >= self.threshold {
+                self.unanimous.get_or_insert(a_value);
+            }
+        }
+        self.max = self.order.max(response.rank, self.max.into_iter().chain(a_values), |a_value| a_value.0);
+    }
+
+    pub fn result(&self) -> (bool, ProposalHash) {
+        // Line 37/38/39: if (S contains at least 2f+1 A-answers containing only val) return ⟨true, val⟩
+        if let Some(value) = self.unanimous.filter(|_| !self.forced_adopt) {
+            return (true, value.0);
+        }
+
+        // Line 40: else return ⟨false, max(S)⟩
+        (false, self.max.map_or(BlockHash::zero(), |value| value.0))
+    }
+}
+
+#[derive(Debug)]
+pub struct BTally {
+    threshold: usize,
+    first_true: Option<ProposalHash>,
+    // Answers whose B[i] only holds ⟨true, val⟩, per val
+    true_counts: HashMap<ProposalHash, usize>,
+    committed: Option<ProposalHash>,
+    max: Option<ProposalHash>,
+    order: ValueOrder,
+}
+
+impl BTally {
+    pub fn new(threshold: usize) -> BTally {
+        BTally {
+            threshold,
+            first_true: None,
+            true_counts: HashMap::new(),
+            committed: None,
+            max: None,
+            order: ValueOrder::default(),
+        }
+    }
+
+    pub fn ordered(mut self, order: ValueOrder) -> BTally {
+        self.order = order;
+        self
+    }
+
+    pub fn add(&mut self, response: &Response) {
+        // Line 55: S ← array with all B[i]s received
+        let b_values = b_values(response);
+
+        if let Some(b_value) = b_values.iter().find(|b_value| b_value.flag) {
+            self.first_true.get_or_insert(b_value.value);
+        }
+
+        // An answer that also holds a false pair does not count towards a commit, otherwise a process could commit
+        // a value that the answers of the same processes, received by another process before the true pair, never held
+        if let Some(value) = only_true(response) {
+            let count = self.true_counts.entry(value).or_insert(0);
+            *count += 1;
+            if *count >= self.threshold {
+                self.committed.get_or_insert(value);
+            }
+        }
+        self.max = self.order.max(response.rank, self.max.into_iter().chain(b_values.iter().map(|b_value| b_value.value)), |value| *value);
+    }
+
+    pub fn result(&self) -> Decision {
+        // Line 56/57: if |{⟨true, val⟩ ∈ S}| ≥ 2f + 1 return ⟨commit, val⟩
+        if let Some(value) = self.committed {
+            return Decision::Commit(value);
+        }
+
+        match self.first_true {
+            // Line 58/59: else if |{⟨true, val⟩ ∈ S}| ≥ 1 return ⟨adopt, val⟩
+            Some(value) => Decision::Adopt(value),
+            // Line 60: else return ⟨adopt, max(S)⟩
+            None => Decision::Adopt(self.max.unwrap()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::{Broadcast, SeededBeacon, State, Step};
+
+    fn response(sender: i64, value: Value) -> Response {
+        let broadcast = Arc::new(Broadcast::new(sender, Step::R, BlockHash::zero(), None, 0, None));
+        Response::new(sender, Step::R, 0, vec![State::new(value, broadcast)])
+    }
+
+    #[test]
+    fn a_tally_needs_unanimous_quorum() {
+        let mut tally = ATally::new(3);
+
+        tally.add(&response(0, Value::AValue(AValue(BlockHash::from(1)))));
+        tally.add(&response(1, Value::AValue(AValue(BlockHash::from(2)))));
+        tally.add(&response(2, Value::AValue(AValue(BlockHash::from(1)))));
+        assert_eq!(tally.result(), (false, BlockHash::from(2)));
+
+        let mut tally = ATally::new(2);
+        tally.add(&response(0, Value::AValue(AValue(BlockHash::from(1)))));
+        tally.add(&response(1, Value::AValue(AValue(BlockHash::from(1)))));
+        assert_eq!(tally.result(), (true, BlockHash::from(1)));
+    }
+
+    #[test]
+    fn a_tally_forcing_adopt_returns_max() {
+        let mut tally = ATally::new(2).forcing_adopt(true);
+
+        tally.add(&response(0, Value::AValue(AValue(BlockHash::from(1)))));
+        tally.add(&response(1, Value::AValue(AValue(BlockHash::from(1)))));
+        tally.add(&response(2, Value::AValue(AValue(BlockHash::from(2)))));
+        assert_eq!(tally.result(), (false, BlockHash::from(2)));
+    }
+
+    #[test]
+    fn tallies_take_the_max_in_the_beacon_order() {
+        let order = ValueOrder::new(Arc::new(SeededBeacon(1)));
+        let values = [BlockHash::from(1), BlockHash::from(2)];
+        let max = order.max(0, values, |value| *value).unwrap();
+
+        let mut a_tally = ATally::new(3).ordered(order.clone());
+        let mut b_tally = BTally::new(3).ordered(order.clone());
+        let mut r_tally = RTally::default().ordered(order);
+        for (sender, value) in values.into_iter().enumerate() {
+            a_tally.add(&response(sender as i64, Value::AValue(AValue(value))));
+            b_tally.add(&response(sender as i64, Value::BValue(BValue::new(value, false))));
+            r_tally.add(&response(sender as i64, Value::RValue(RValue::new(0, value))));
+        }
+
+        assert_eq!(a_tally.result(), (false, max));
+        assert_eq!(b_tally.result(), Decision::Adopt(max));
+        assert_eq!(r_tally.result(), RValue::new(0, max));
+    }
+
+    #[test]
+    fn b_tally_commits_or_adopts() {
+        let mut tally = BTally::new(2);
+
+        tally.add(&response(0, Value::BValue(BValue::new(BlockHash::from(3), false))));
+        assert_eq!(tally.result(), Decision::Adopt(BlockHash::from(3)));
+
+        tally.add(&response(1, Value::BValue(BValue::new(BlockHash::from(1), true))));
+        assert_eq!(tally.result(), Decision::Adopt(BlockHash::from(1)));
+
+        tally.add(&response(2, Value::BValue(BValue::new(BlockHash::from(1), true))));
+        assert_eq!(tally.result(), Decision::Commit(BlockHash::from(1)));
+    }
+
+    #[test]
+    fn b_tally_does_not_commit_on_mixed_answers() {
+        let broadcast = Arc::new(Broadcast::new(0, Step::B, BlockHash::zero(), Some(true), 0, None));
+        let mixed = |sender| Response::new(sender, Step::B, 0, vec![
+            State::new(Value::BValue(BValue::new(BlockHash::from(1), true)), broadcast.clone()),
+            State::new(Value::BValue(BValue::new(BlockHash::from(2), false)), broadcast.clone()),
+        ]);
+
+        let mut tally = BTally::new(2);
+        tally.add(&mixed(0));
+        tally.add(&mixed(1));
+        assert_eq!(tally.result(), Decision::Adopt(BlockHash::from(1)));
+
+        tally.add(&response(2, Value::BValue(BValue::new(BlockHash::from(1), true))));
+        tally.add(&response(3, Value::BValue(BValue::new(BlockHash::from(1), true))));
+        assert_eq!(tally.result(), Decision::Commit(BlockHash::from(1)));
+    }
+
+    #[test]
+    fn a_tally_does_not_count_mixed_answers_towards_unanimity() {
+        let broadcast = Arc::new(Broadcast::new(0, Step::A, BlockHash::zero(), None, 0, None));
+        let mixed = |sender| Response::new(sender, Step::A, 0, vec![
+            State::new(Value::AValue(AValue(BlockHash::from(1))), broadcast.clone()),
+            State::new(Value::AValue(AValue(BlockHash::from(2))), broadcast.clone()),
+        ]);
+
+        let mut tally = ATally::new(2);
+        tally.add(&mixed(0));
+        tally.add(&mixed(1));
+        assert_eq!(tally.result(), (false, BlockHash::from(2)));
+
+        tally.add(&response(2, Value::AValue(AValue(BlockHash::from(1)))));
+        tally.add(&response(3, Value::AValue(AValue(BlockHash::from(1)))));
+        assert_eq!(tally.result(), (true, BlockHash::from(1)));
+    }
+}
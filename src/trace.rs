@@ -0,0 +1,159 @@
+#[cfg(feature = "otel")]
+use opentelemetry::{global, trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, Tracer}, Context, KeyValue};
+use crate::{Broadcast, Id, Rank, Step};
+
+#[cfg(feature = "otel")]
+const TRACER: &str = "archipelago";
+
+// Trace and span ids of the step a broadcast was sent in, so that the processes answering it add their spans
+// to the proposer's trace, which then shows an instance end-to-end across the committee
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+#[cfg(feature = "otel")]
+impl TraceContext {
+    fn of(context: &Context) -> Option<TraceContext> {
+        let span = context.span();
+        let span_context = span.span_context();
+
+        span_context.is_valid().then(|| TraceContext {
+            trace_id: u128::from_be_bytes(span_context.trace_id().to_bytes()),
+            span_id: u64::from_be_bytes(span_context.span_id().to_bytes()),
+        })
+    }
+
+    fn remote(self) -> Context {
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(self.trace_id.to_be_bytes()),
+            SpanId::from_bytes(self.span_id.to_be_bytes()),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        Context::new().with_remote_span_context(span_context)
+    }
+}
+
+// Spans of the instance a proposer is running: the instance, then one per rank and one per step of the rank
+// Spans go to the global tracer provider (an OTLP pipeline installed by the application), and are only
+// recorded with the otel feature, without it broadcasts carry no context
+#[derive(Debug, Clone, Default)]
+pub struct InstanceTrace {
+    #[cfg(feature = "otel")]
+    instance: Option<Context>,
+    #[cfg(feature = "otel")]
+    rank: Option<(Rank, Context)>,
+    #[cfg(feature = "otel")]
+    step: Option<Context>,
+}
+
+impl InstanceTrace {
+    #[cfg(feature = "otel")]
+    fn child(parent: &Context, name: String, attributes: Vec<KeyValue>) -> Context {
+        let tracer = global::tracer(TRACER);
+        parent.with_span(tracer.span_builder(name).with_attributes(attributes).start_with_context(&tracer, parent))
+    }
+
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    pub fn start(&mut self, id: Id) {
+        #[cfg(feature = "otel")]
+        {
+            self.finish(None);
+            self.instance = Some(InstanceTrace::child(&Context::new(), "instance".to_string(), vec![KeyValue::new("node", id)]));
+        }
+    }
+
+    // Ends the span of the previous step (and rank, once it changes) and returns the context to send with the step's broadcast
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    pub fn step(&mut self, id: Id, step: Step, rank: Rank) -> Option<TraceContext> {
+        #[cfg(feature = "otel")]
+        {
+            let instance = self.instance.as_ref()?;
+
+            if let Some(step) = self.step.take() {
+                step.span().end();
+            }
+            if self.rank.as_ref().is_none_or(|(current, _)| *current != rank) {
+                if let Some((_, previous)) = self.rank.take() {
+                    previous.span().end();
+                }
+                let span = InstanceTrace::child(instance, format!("rank {}", rank), vec![KeyValue::new("node", id), KeyValue::new("rank", rank)]);
+                self.rank = Some((rank, span));
+            }
+
+            let (_, rank_span) = self.rank.as_ref()?;
+            let attributes = vec![KeyValue::new("node", id), KeyValue::new("rank", rank), KeyValue::new("step", format!("{:?}", step))];
+            let step = self.step.insert(InstanceTrace::child(rank_span, format!("{:?} step", step), attributes));
+
+            TraceContext::of(step)
+        }
+        #[cfg(not(feature = "otel"))]
+        None
+    }
+
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    pub fn finish(&mut self, decided: Option<Rank>) {
+        #[cfg(feature = "otel")]
+        {
+            let spans = [self.step.take(), self.rank.take().map(|(_, rank)| rank), self.instance.take()];
+
+            for span in spans.into_iter().flatten() {
+                if let Some(rank) = decided {
+                    span.span().set_attribute(KeyValue::new("decided_rank", rank));
+                }
+                span.span().end();
+            }
+        }
+    }
+}
+
+// Runs the answer to a broadcast in a span of the broadcast's trace, if it carries one
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn traced_answer<T>(id: Id, broadcast: &Broadcast, answer: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "otel")]
+    if let Some(trace) = broadcast.trace {
+        let attributes = vec![KeyValue::new("node", id), KeyValue::new("sender", broadcast.sender), KeyValue::new("rank", broadcast.rank)];
+        let span = InstanceTrace::child(&trace.remote(), format!("answer {:?}", broadcast.step), attributes);
+        let answered = answer();
+
+        span.span().end();
+        return answered;
+    }
+
+    answer()
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use opentelemetry_sdk::{testing::trace::InMemorySpanExporter, trace::TracerProvider};
+    use super::*;
+
+    #[test]
+    fn answers_join_the_trace_of_the_step() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        global::set_tracer_provider(provider);
+
+        let mut trace = InstanceTrace::default();
+        trace.start(7);
+        let r = trace.step(7, Step::R, 0).unwrap();
+        let a = trace.step(7, Step::A, 0).unwrap();
+        let broadcast = Broadcast::new(7, Step::A, Default::default(), None, 0, None).with_trace(Some(a));
+        assert_eq!(traced_answer(8, &broadcast, || 42), 42);
+        trace.step(7, Step::R, 1);
+        trace.finish(Some(1));
+
+        // Other tests may record spans concurrently, only the ones of this trace are checked
+        let spans: Vec<_> = exporter.get_finished_spans().unwrap().into_iter().filter(|span| u128::from_be_bytes(span.span_context.trace_id().to_bytes()) == r.trace_id).collect();
+        let names = |name: &str| spans.iter().filter(|span| span.name == name).count();
+
+        assert_eq!(r.trace_id, a.trace_id);
+        assert_eq!((names("instance"), names("rank 0"), names("rank 1"), names("R step"), names("A step")), (1, 1, 1, 2, 1));
+
+        let answer = spans.iter().find(|span| span.name == "answer A").unwrap();
+        assert_eq!(u64::from_be_bytes(answer.parent_span_id.to_bytes()), a.span_id);
+    }
+}
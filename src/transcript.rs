@@ -0,0 +1,512 @@
+use std::{collections::{BTreeMap, HashMap}, fmt::Write, fs, io, path::PathBuf, str::FromStr, sync::Arc};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{AValue, BValue, Broadcast, BroadcastHash, BroadcastStore, CommitRecord, Core, Id, Identities, FORCED_ADOPT, Process, RValue, Rank, Response, ResponseHash, State, Step, Value, ValueOrder};
+
+// Complete record of one instance as a process saw it: every broadcast and response its run loop accepted and the
+// commit with its certificate, signed by the process. `verify_transcript` replays the validation rules on it, so that
+// a third party can check the instance without trusting whoever hands the transcript over
+// The canonical encoding is what the signature covers, one item per line and every list sorted:
+//   transcript recorder=<id> f=<f> forced_adopt_ranks=<ranks>
+//   key member=<id> key=<hex>
+//   broadcast sender=<id> step=<R|A|B> rank=<rank> value=<hex> flag=<none|true|false> certificate=<none|responses>
+//     response sender=<id> step=<step> rank=<rank> answers=<hash> signature=<none|hex> states=<states>
+//       state value=<r:rank:hex|a:hex|b:flag:hex> broadcast=<sender>:<step>:<rank>:<hex>:<flag>
+//   response ...
+//   commit instance=<n> rank=<rank> value=<hex> certificate=<responses>
+//   signed signature=<hex>
+// Broadcasts and responses of a pinned committee end with committee=<hex>, and so do the broadcasts of their states
+// with :<hex>, see Identities::pin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    pub recorder: Id,
+    pub f: usize,
+    pub forced_adopt_ranks: Rank,
+    // The verifier takes the keys from the transcript, they are to be compared with the ones of the committee
+    pub committee: BTreeMap<Id, VerifyingKey>,
+    pub broadcasts: Vec<Broadcast>,
+    pub responses: Vec<Response>,
+    pub commit: CommitRecord,
+    // Of the recorder, over the digest of the rest
+    pub signature: Option<[u8; 64]>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn unhex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != 2 * N || !text.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (byte, digits) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn flag_text(flag: Option<bool>) -> String {
+    flag.map_or("none".to_string(), |flag| flag.to_string())
+}
+
+// Nothing for messages of an unpinned committee
+fn committee_text(committee: Option<BlockHash>, prefix: &str) -> String {
+    committee.map_or(String::new(), |committee| format!("{}{}", prefix, hex(committee.as_bytes())))
+}
+
+fn broadcast_order(broadcast: &Broadcast) -> (Rank, u8, Id, BroadcastHash) {
+    (broadcast.rank, broadcast.step as u8, broadcast.sender, broadcast.hash_value())
+}
+
+fn response_order(response: &Response) -> (Rank, u8, Id, ResponseHash) {
+    (response.rank, response.step as u8, response.sender, response.hash_value())
+}
+
+fn encode_response(out: &mut String, indent: &str, response: &Response) {
+    let signature = response.signature.map_or("none".to_string(), |signature| hex(&signature));
+    writeln!(
+        out,
+        "{}response sender={} step={:?} rank={} answers={} signature={} states={}{}",
+        indent, response.sender, response.step, response.rank, hex(response.answers.as_bytes()), signature, response.state.len(), committee_text(response.committee, " committee=")
+    ).unwrap();
+
+    for state in &response.state {
+        let value = match state.value {
+            Value::RValue(r_value) => format!("r:{}:{}", r_value.rank, hex(r_value.value.as_bytes())),
+            Value::AValue(a_value) => format!("a:{}", hex(a_value.0.as_bytes())),
+            Value::BValue(b_value) => format!("b:{}:{}", b_value.flag, hex(b_value.value.as_bytes())),
+        };
+        let broadcast = &state.broadcast;
+        writeln!(
+            out,
+            "{}  state value={} broadcast={}:{:?}:{}:{}:{}{}",
+            indent, value, broadcast.sender, broadcast.step, broadcast.rank, hex(broadcast.value.as_bytes()), flag_text(broadcast.flag), committee_text(broadcast.committee, ":")
+        ).unwrap();
+    }
+}
+
+impl Transcript {
+    // Lines the signature covers
+    fn body(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "transcript recorder={} f={} forced_adopt_ranks={}", self.recorder, self.f, self.forced_adopt_ranks).unwrap();
+
+        for (member, key) in &self.committee {
+            writeln!(out, "key member={} key={}", member, hex(key.as_bytes())).unwrap();
+        }
+
+        let mut broadcasts: Vec<&Broadcast> = self.broadcasts.iter().collect();
+        broadcasts.sort_by_key(|broadcast| broadcast_order(broadcast));
+        broadcasts.dedup_by_key(|broadcast| broadcast_order(broadcast));
+        for broadcast in broadcasts {
+            let certificate = broadcast.previous_step_responses.as_ref().map_or("none".to_string(), |certificate| certificate.len().to_string());
+            writeln!(
+                out,
+                "broadcast sender={} step={:?} rank={} value={} flag={} certificate={}{}",
+                broadcast.sender, broadcast.step, broadcast.rank, hex(broadcast.value.as_bytes()), flag_text(broadcast.flag), certificate, committee_text(broadcast.committee, " committee=")
+            ).unwrap();
+
+            for response in broadcast.previous_step_responses.iter().flat_map(|certificate| certificate.iter()) {
+                encode_response(&mut out, "  ", response);
+            }
+        }
+
+        let mut responses: Vec<&Response> = self.responses.iter().collect();
+        responses.sort_by_key(|response| response_order(response));
+        responses.dedup_by_key(|response| response_order(response));
+        for response in responses {
+            encode_response(&mut out, "", response);
+        }
+
+        let commit = &self.commit;
+        writeln!(
+            out,
+            "commit instance={} rank={} value={} certificate={}",
+            commit.instance, commit.rank, hex(commit.value.as_bytes()), commit.certificate.len()
+        ).unwrap();
+        for response in &commit.certificate {
+            encode_response(&mut out, "  ", response);
+        }
+        out
+    }
+
+    pub fn digest(&self) -> BlockHash {
+        Blake2HashBuilder::new().update(b"transcript ").update(self.body().as_bytes()).build()
+    }
+
+    pub fn encode(&self) -> String {
+        let mut out = self.body();
+        if let Some(signature) = &self.signature {
+            writeln!(out, "signed signature={}", hex(signature)).unwrap();
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Transcript, String> {
+        let mut lines = Lines::new(text);
+
+        let header = lines.take("transcript")?;
+        let mut committee = BTreeMap::new();
+        while lines.peek() == Some("key") {
+            let fields = lines.take("key")?;
+            let key = unhex(fields.get("key")?).and_then(|key| VerifyingKey::from_bytes(&key).ok()).ok_or_else(|| fields.error("invalid key"))?;
+            committee.insert(fields.number("member")?, key);
+        }
+
+        let mut broadcasts = Vec::new();
+        while lines.peek() == Some("broadcast") {
+            broadcasts.push(lines.broadcast()?);
+        }
+
+        let mut responses = Vec::new();
+        while lines.peek() == Some("response") {
+            responses.push(lines.response()?);
+        }
+
+        let fields = lines.take("commit")?;
+        let certificate = lines.certificate(fields.number("certificate")?)?;
+        let commit = CommitRecord { instance: fields.number("instance")?, rank: fields.number("rank")?, value: fields.hash("value")?, certificate: certificate.into(), committee: None };
+
+        let signature = match lines.peek() {
+            Some("signed") => {
+                let fields = lines.take("signed")?;
+                Some(unhex(fields.get("signature")?).ok_or_else(|| fields.error("invalid signature"))?)
+            }
+            _ => None,
+        };
+        lines.end()?;
+
+        Ok(Transcript {
+            recorder: header.number("recorder")?,
+            f: header.number("f")?,
+            forced_adopt_ranks: header.number("forced_adopt_ranks")?,
+            committee,
+            broadcasts,
+            responses,
+            commit,
+            signature,
+        })
+    }
+}
+
+// Fields of a line, with its number for the errors
+struct Fields<'a> {
+    line: usize,
+    fields: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Fields<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line, message)
+    }
+
+    fn get(&self, name: &str) -> Result<&'a str, String> {
+        self.fields.get(name).copied().ok_or_else(|| self.error(&format!("missing {}", name)))
+    }
+
+    fn number<T: FromStr>(&self, name: &str) -> Result<T, String> {
+        self.get(name)?.parse().map_err(|_| self.error(&format!("invalid {}", name)))
+    }
+
+    fn hash(&self, name: &str) -> Result<BlockHash, String> {
+        unhex(self.get(name)?).map(BlockHash::from_bytes).ok_or_else(|| self.error(&format!("invalid {}", name)))
+    }
+
+    fn optional_hash(&self, name: &str) -> Result<Option<BlockHash>, String> {
+        self.fields.contains_key(name).then(|| self.hash(name)).transpose()
+    }
+
+    fn step(&self, name: &str) -> Result<Step, String> {
+        parse_step(self.get(name)?).ok_or_else(|| self.error(&format!("invalid {}", name)))
+    }
+
+    fn flag(&self, name: &str) -> Result<Option<bool>, String> {
+        parse_flag(self.get(name)?).ok_or_else(|| self.error(&format!("invalid {}", name)))
+    }
+}
+
+fn parse_step(text: &str) -> Option<Step> {
+    match text {
+        "R" => Some(Step::R),
+        "A" => Some(Step::A),
+        "B" => Some(Step::B),
+        _ => None,
+    }
+}
+
+fn parse_flag(text: &str) -> Option<Option<bool>> {
+    match text {
+        "none" => Some(None),
+        flag => flag.parse().ok().map(Some),
+    }
+}
+
+// Non-empty lines of a transcript, numbered from 1
+struct Lines<'a> {
+    lines: Vec<(usize, &'a str)>,
+    next: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Lines<'a> {
+        let lines = text.lines().enumerate().map(|(line, text)| (line + 1, text.trim())).filter(|(_, text)| !text.is_empty()).collect();
+        Lines { lines, next: 0 }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.lines.get(self.next).and_then(|(_, text)| text.split_whitespace().next())
+    }
+
+    fn take(&mut self, keyword: &str) -> Result<Fields<'a>, String> {
+        let Some(&(line, text)) = self.lines.get(self.next) else {
+            return Err(format!("missing {} at the end", keyword));
+        };
+        let mut tokens = text.split_whitespace();
+        if tokens.next() != Some(keyword) {
+            return Err(format!("line {}: expected {}", line, keyword));
+        }
+
+        let fields = tokens
+            .map(|token| token.split_once('=').ok_or_else(|| format!("line {}: invalid field {:?}", line, token)))
+            .collect::<Result<_, _>>()?;
+        self.next += 1;
+        Ok(Fields { line, fields })
+    }
+
+    fn end(&self) -> Result<(), String> {
+        match self.lines.get(self.next) {
+            Some((line, _)) => Err(format!("line {}: unexpected {}", line, self.peek().unwrap_or_default())),
+            None => Ok(()),
+        }
+    }
+
+    fn certificate(&mut self, responses: usize) -> Result<Vec<Response>, String> {
+        (0..responses).map(|_| self.response()).collect()
+    }
+
+    fn broadcast(&mut self) -> Result<Broadcast, String> {
+        let fields = self.take("broadcast")?;
+        let certificate = match fields.get("certificate")? {
+            "none" => None,
+            _ => Some(self.certificate(fields.number("certificate")?)?.into()),
+        };
+        let broadcast = Broadcast::new(fields.number("sender")?, fields.step("step")?, fields.hash("value")?, fields.flag("flag")?, fields.number("rank")?, certificate);
+        Ok(broadcast.pinned(fields.optional_hash("committee")?))
+    }
+
+    fn response(&mut self) -> Result<Response, String> {
+        let fields = self.take("response")?;
+        let answers = fields.hash("answers")?;
+        let signature = match fields.get("signature")? {
+            "none" => None,
+            signature => Some(unhex(signature).ok_or_else(|| fields.error("invalid signature"))?),
+        };
+
+        let states: Vec<State> = (0..fields.number::<usize>("states")?).map(|_| self.state()).collect::<Result<_, _>>()?;
+        let mut response = Response::new(fields.number("sender")?, fields.step("step")?, fields.number("rank")?, states).answering(answers);
+        response.committee = fields.optional_hash("committee")?;
+        response.signature = signature;
+        Ok(response)
+    }
+
+    fn state(&mut self) -> Result<State, String> {
+        let fields = self.take("state")?;
+        let invalid = |name: &str| fields.error(&format!("invalid {}", name));
+        let hash = |text: &str| unhex(text).map(BlockHash::from_bytes);
+
+        let value = match fields.get("value")?.split(':').collect::<Vec<_>>()[..] {
+            ["r", rank, value] => Value::RValue(RValue::new(rank.parse().map_err(|_| invalid("value"))?, hash(value).ok_or_else(|| invalid("value"))?)),
+            ["a", value] => Value::AValue(AValue(hash(value).ok_or_else(|| invalid("value"))?)),
+            ["b", flag, value] => Value::BValue(BValue::new(hash(value).ok_or_else(|| invalid("value"))?, flag.parse().map_err(|_| invalid("value"))?)),
+            _ => return Err(invalid("value")),
+        };
+
+        let parts = fields.get("broadcast")?.split(':').collect::<Vec<_>>();
+        let committee = match parts[..] {
+            [_, _, _, _, _] => None,
+            [_, _, _, _, _, committee] => Some(hash(committee).ok_or_else(|| invalid("broadcast"))?),
+            _ => return Err(invalid("broadcast")),
+        };
+        let [sender, step, rank, broadcast_value, flag, ..] = parts[..] else {
+            return Err(invalid("broadcast"));
+        };
+        let broadcast = Broadcast::new(
+            sender.parse().map_err(|_| invalid("broadcast"))?,
+            parse_step(step).ok_or_else(|| invalid("broadcast"))?,
+            hash(broadcast_value).ok_or_else(|| invalid("broadcast"))?,
+            parse_flag(flag).ok_or_else(|| invalid("broadcast"))?,
+            rank.parse().map_err(|_| invalid("broadcast"))?,
+            None,
+        )
+        .pinned(committee);
+        Ok(State::new(value, Arc::new(broadcast)))
+    }
+}
+
+// Checks the signature of the recorder, then replays on the transcript what the run loop checks before accepting a
+// message: certificates of the broadcasts, structure and signatures of the responses, the broadcasts their entries
+// answer, and that the commit certificate commits the value. Values are ordered as without a beacon unless the
+// order holds the beacon of the committee
+pub fn verify_transcript(transcript: &Transcript, order: &ValueOrder) -> Result<(), String> {
+    let key = transcript.committee.get(&transcript.recorder).ok_or("the recorder is not in the committee")?;
+    let signature = transcript.signature.ok_or("the transcript is not signed")?;
+    key.verify(transcript.digest().as_bytes(), &Signature::from_bytes(&signature)).map_err(|_| "the signature does not match the transcript")?;
+
+    if transcript.forced_adopt_ranks > 0 && !FORCED_ADOPT {
+        return Err("the transcript was recorded with forced adopts, which this build does not replay".to_string());
+    }
+
+    let identities = Identities::verifying(transcript.committee.iter().map(|(member, key)| (*member, *key)).collect());
+    let mut broadcasts = BroadcastStore::new();
+    transcript.broadcasts.iter().for_each(|broadcast| {
+        broadcasts.insert(broadcast.clone());
+    });
+
+    for broadcast in &transcript.broadcasts {
+        let rejection = Process::broadcast_rejection(broadcast, transcript.f, transcript.forced_adopt_ranks, order, &identities)
+            .or_else(|| Process::answers_rejection(broadcast, &broadcasts));
+
+        if let Some(reason) = rejection {
+            return Err(format!("{:?} broadcast of rank {} from {}: {:?}", broadcast.step, broadcast.rank, broadcast.sender, reason));
+        }
+    }
+
+    for response in &transcript.responses {
+        if !Process::validate_response(response) || Process::answers_other_broadcast(response, &broadcasts) {
+            return Err(format!("{:?} response of rank {} from {}: invalid entry", response.step, response.rank, response.sender));
+        }
+        if !identities.verify(response) {
+            return Err(format!("{:?} response of rank {} from {}: invalid signature", response.step, response.rank, response.sender));
+        }
+    }
+
+    if !Core::check_commit(&transcript.commit, transcript.f, order, &identities) {
+        return Err(format!("the certificate does not commit the value at rank {}", transcript.commit.rank));
+    }
+    Ok(())
+}
+
+// What the run loop accepted since the recording started, see `Process::with_transcript`
+#[derive(Debug, Default)]
+pub struct TranscriptRecorder {
+    path: Option<PathBuf>,
+    broadcasts: HashMap<(Id, BroadcastHash), Broadcast>,
+    responses: HashMap<(Id, ResponseHash), Response>,
+}
+
+impl TranscriptRecorder {
+    // Records until the next commit, which writes the transcript to the path
+    pub fn start(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    pub fn recording(&self) -> bool {
+        self.path.is_some()
+    }
+
+    // Kept with its resolved certificate, the trace it was sent in is left out
+    pub fn broadcast(&mut self, broadcast: &Broadcast) {
+        if self.recording() {
+            let broadcast = Broadcast { certificate_refs: None, certificate_hash: None, trace: None, ..broadcast.clone() };
+            self.broadcasts.entry((broadcast.sender, broadcast.hash_value())).or_insert(broadcast);
+        }
+    }
+
+    pub fn response(&mut self, response: &Response) {
+        if self.recording() {
+            self.responses.entry((response.sender, response.hash_value())).or_insert_with(|| response.clone());
+        }
+    }
+
+    // Signs the transcript of the commit with the key of the process, if it has one, and writes it. Recording stops
+    // even if it cannot be written
+    pub fn finish(&mut self, recorder: Id, f: usize, forced_adopt_ranks: Rank, identities: &Identities, commit: CommitRecord) -> io::Result<Option<Transcript>> {
+        let Some(path) = self.path.take() else {
+            return Ok(None);
+        };
+
+        let mut transcript = Transcript {
+            recorder,
+            f,
+            forced_adopt_ranks,
+            committee: identities.committee().into_iter().collect(),
+            broadcasts: std::mem::take(&mut self.broadcasts).into_values().collect(),
+            responses: std::mem::take(&mut self.responses).into_values().collect(),
+            commit,
+            signature: None,
+        };
+        transcript.signature = identities.sign_digest(&transcript.digest());
+
+        fs::write(path, transcript.encode())?;
+        Ok(Some(transcript))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use crate::CertificateResponses;
+    use super::*;
+
+    fn key(id: Id) -> SigningKey {
+        SigningKey::from_bytes(&[id as u8 + 1; 32])
+    }
+
+    fn identities(id: Id) -> Identities {
+        let identities = Identities::default();
+        identities.set(key(id), (0..4).map(|id| (id, key(id).verifying_key())).collect());
+        identities
+    }
+
+    fn answer(sender: Id, step: Step, value: Value, broadcast: &Broadcast) -> Response {
+        let response = Response::new(sender, step, 0, vec![State::new(value, Arc::new(broadcast.clone()))]).answering(broadcast.hash_value());
+        identities(sender).sign(response)
+    }
+
+    #[test]
+    fn recorded_transcripts_verify_until_tampered_with() {
+        let value = BlockHash::from(5);
+        let r = Broadcast::new(0, Step::R, value, None, 0, None);
+        let r_answers: CertificateResponses = (0..3).map(|sender| answer(sender, Step::R, Value::RValue(RValue::new(0, value)), &r)).collect();
+        let a = Broadcast::new(1, Step::A, value, None, 0, Some(r_answers.clone()));
+        let b = Broadcast::new(2, Step::B, value, Some(true), 0, None);
+        let b_answers: CertificateResponses = (0..3).map(|sender| answer(sender, Step::B, Value::BValue(BValue::new(value, true)), &b)).collect();
+
+        let path = std::env::temp_dir().join(format!("archipelago-transcript-{}.txt", std::process::id()));
+        let mut recorder = TranscriptRecorder::default();
+        recorder.broadcast(&r);
+        recorder.start(path.clone());
+        [&r, &a, &r].into_iter().for_each(|broadcast| recorder.broadcast(broadcast));
+        r_answers.iter().for_each(|response| recorder.response(response));
+
+        let commit = CommitRecord { instance: 0, rank: 0, value, certificate: b_answers, committee: None };
+        let recorded = recorder.finish(0, 1, 0, &identities(0), commit).unwrap().unwrap();
+        assert!(!recorder.recording());
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let transcript = Transcript::parse(&text).unwrap();
+        assert_eq!(transcript.encode(), text);
+        assert_eq!((transcript.broadcasts.len(), transcript.responses.len(), transcript.digest()), (2, 3, recorded.digest()));
+        assert_eq!(verify_transcript(&transcript, &ValueOrder::default()), Ok(()));
+
+        // Edited after signing
+        let edited = text.replacen("rank=0", "rank=1", 1);
+        assert_eq!(verify_transcript(&Transcript::parse(&edited).unwrap(), &ValueOrder::default()), Err("the signature does not match the transcript".to_string()));
+
+        // Signed by the recorder, but holding a response its sender did not sign
+        let mut forged = transcript.clone();
+        forged.responses[0] = Response { sender: 3, ..forged.responses[0].clone() };
+        forged.signature = identities(0).sign_digest(&forged.digest());
+        assert!(verify_transcript(&forged, &ValueOrder::default()).unwrap_err().ends_with("from 3: invalid signature"));
+
+        // Re-signed with a certificate that does not commit the value
+        let mut uncommitted = transcript.clone();
+        uncommitted.commit.certificate.pop();
+        uncommitted.signature = identities(0).sign_digest(&uncommitted.digest());
+        assert_eq!(verify_transcript(&uncommitted, &ValueOrder::default()), Err("the certificate does not commit the value at rank 0".to_string()));
+        assert!(Transcript::parse(&text.replace("commit", "comit")).unwrap_err().ends_with("expected commit"));
+    }
+}
@@ -0,0 +1,410 @@
+use std::{collections::BTreeMap, io::{self, BufReader, Read, Write}, net::{SocketAddr, TcpListener, TcpStream}, ops::RangeInclusive, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, mpsc::{channel, Receiver, Sender}, Arc, Mutex}, thread, time::{Duration, Instant}};
+use ed25519_dalek::{Signature, Verifier};
+use log::{debug, warn};
+use crate::{handshake_digest, negotiate, read_frame, read_handshake, read_handshake_answer, read_handshake_challenge, write_frame, write_handshake, write_handshake_answer, write_handshake_challenge, Id, Identities, Message, SUPPORTED_WIRE_VERSIONS};
+
+// How long connecting to a peer may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+// After a failed attempt, messages to the peer are dropped without connecting again for this long, so that an
+// unreachable peer does not hold up the ones queued behind it by a connect timeout each
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(200);
+// A peer without a wire version in common is tried again after this long, in case it was upgraded meanwhile
+const INCOMPATIBLE_INTERVAL: Duration = Duration::from_secs(5);
+// Accepted connections open at once, per member of the committee: each member dials one, and the ones it dials again
+// after a failure are open until the reads of the earlier ones fail. Connections past it are closed right away, so that
+// whoever can reach the port cannot tie up a thread per connection
+const CONNECTIONS_PER_MEMBER: usize = 4;
+
+// The version negotiated with each peer, or the versions a peer without one in common supports
+type Versions = Arc<Mutex<BTreeMap<Id, Result<u16, RangeInclusive<u16>>>>>;
+
+// Carries the messages of a process to the other members of the committee and back over TCP, as length-prefixed frames
+// of the wire encoding. It hands out the senders and receiver Process::new_with_config takes, so that a process runs
+// the same whether its peers are threads or other machines: the sender of each peer feeds a thread writing to a
+// connection of its own, opened on first use and again after a failure, and every accepted connection gets a thread
+// reading into the receiver. Messages to a peer that cannot be reached are dropped, like a lossy link, and counted;
+// BatchConfig::ack_timeout resends them. Every connection opens with a handshake in which the dialing end names the wire
+// versions it supports and the accepting end picks the highest one both support, or answers with its own versions
+// and closes the connection if there is none, so that a peer of an incompatible release is refused and reported
+// instead of sending frames the other end misreads. The accepting end first has the dialing end sign a nonce with the
+// key the committee has on record for the member it claims to be, and closes the connection if it does not, so that
+// frames are only taken from members
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    address: SocketAddr,
+    dropped: Arc<AtomicU64>,
+    versions: Versions,
+}
+
+impl TcpTransport {
+    // Listens on the address of the process in `addresses`, which has the address of every member by id. The
+    // identities hold the key of the process and the ones of the committee, see Identities::set
+    pub fn bind(id: Id, addresses: &[SocketAddr], identities: &Identities) -> io::Result<(TcpTransport, Vec<Sender<Message>>, Receiver<Message>)> {
+        TcpTransport::listen(id, TcpListener::bind(addresses[id as usize])?, addresses, identities)
+    }
+
+    // On a listener bound beforehand, e.g. to a port the system picked. Its own entry of `addresses` is not used
+    pub fn listen(id: Id, listener: TcpListener, addresses: &[SocketAddr], identities: &Identities) -> io::Result<(TcpTransport, Vec<Sender<Message>>, Receiver<Message>)> {
+        TcpTransport::listen_supporting(id, listener, addresses, SUPPORTED_WIRE_VERSIONS, identities)
+    }
+
+    // Negotiating only the wire versions in `supported`, a subset of SUPPORTED_WIRE_VERSIONS, e.g. to keep speaking
+    // the previous version until the whole committee runs a release that knows the new one
+    pub fn listen_supporting(
+        id: Id,
+        listener: TcpListener,
+        addresses: &[SocketAddr],
+        supported: RangeInclusive<u16>,
+        identities: &Identities,
+    ) -> io::Result<(TcpTransport, Vec<Sender<Message>>, Receiver<Message>)> {
+        if supported.is_empty() || !SUPPORTED_WIRE_VERSIONS.contains(supported.start()) || !SUPPORTED_WIRE_VERSIONS.contains(supported.end()) {
+            let message = format!("wire versions {:?} are not within the ones this release supports, {:?}", supported, SUPPORTED_WIRE_VERSIONS);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        if !identities.signing() || (0..addresses.len() as Id).any(|member| identities.key(member).is_none()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "connections are authenticated with the key of this process and the ones of every member"));
+        }
+        let address = listener.local_addr()?;
+        let (inbound, receiver) = channel();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let versions = Versions::default();
+
+        let (accepted, accepting, negotiated, authenticating) = (inbound.clone(), supported.clone(), Arc::clone(&versions), identities.clone());
+        let (open, capacity) = (Arc::new(AtomicUsize::new(0)), CONNECTIONS_PER_MEMBER * addresses.len());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if open.fetch_add(1, Ordering::Relaxed) >= capacity {
+                    open.fetch_sub(1, Ordering::Relaxed);
+                    debug!("closed the connection from {:?}: {} connections are open", stream.peer_addr().ok(), capacity);
+                    continue;
+                }
+                let (inbound, supported, versions, identities, open) = (accepted.clone(), accepting.clone(), Arc::clone(&negotiated), authenticating.clone(), Arc::clone(&open));
+                thread::spawn(move || {
+                    TcpTransport::read(id, stream, inbound, supported, versions, &identities);
+                    open.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+
+        let senders = addresses
+            .iter()
+            .enumerate()
+            .map(|(peer, address)| {
+                // Messages to itself do not leave the process
+                if peer as Id == id {
+                    return inbound.clone();
+                }
+                let (sender, outbound) = channel();
+                let mut connection = Connection {
+                    id,
+                    identities: identities.clone(),
+                    peer: peer as Id,
+                    address: *address,
+                    supported: supported.clone(),
+                    versions: Arc::clone(&versions),
+                    stream: None,
+                    retry_at: Instant::now(),
+                };
+                let dropped = Arc::clone(&dropped);
+                thread::spawn(move || {
+                    for message in outbound {
+                        // A connection that broke since the last message is opened again once
+                        if connection.send(&message).or_else(|_| connection.send(&message)).is_err() {
+                            dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+                sender
+            })
+            .collect();
+
+        Ok((TcpTransport { address, dropped, versions }, senders, receiver))
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Messages that did not reach their peer, because it could not be connected to or the connection failed
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // Outcome of the last handshake with each peer that connected or was connected to: the negotiated wire version,
+    // or the versions the peer supports if none is common
+    pub fn versions(&self) -> BTreeMap<Id, Result<u16, RangeInclusive<u16>>> {
+        self.versions.lock().unwrap().clone()
+    }
+
+    // Until the peer closes the connection, sends something that is not a frame, or the process is gone
+    fn read(id: Id, mut stream: TcpStream, inbound: Sender<Message>, supported: RangeInclusive<u16>, versions: Versions, identities: &Identities) {
+        let peer = stream.peer_addr().ok();
+        let _ = stream.set_nodelay(true);
+
+        // A peer that does not complete the handshake in time does not hold the thread
+        let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+        let (sender, offered) = match read_handshake(&mut stream) {
+            Ok(handshake) => handshake,
+            Err(error) => {
+                debug!("connection from {:?} without a handshake: {}", peer, error);
+                return;
+            }
+        };
+        if let Err(error) = TcpTransport::authenticate(id, &mut stream, sender, &offered, identities) {
+            warn!("refused the connection from {:?} claiming to be {}: {}", peer, sender, error);
+            return;
+        }
+        let answer = negotiate(&supported, &offered).ok_or_else(|| supported.clone());
+        versions.lock().unwrap().insert(sender, answer.clone().map_err(|_| offered.clone()));
+        if write_handshake_answer(&mut stream, &answer).is_err() || stream.set_read_timeout(None).is_err() {
+            return;
+        }
+        let Ok(version) = answer else {
+            warn!("refused {} at {:?}: it supports wire versions {:?}, this process {:?}", sender, peer, offered, supported);
+            return;
+        };
+
+        let mut reader = BufReader::new(stream);
+        loop {
+            match read_frame(&mut reader, version) {
+                Ok(message) => {
+                    if inbound.send(message).is_err() {
+                        return;
+                    }
+                }
+                Err(error) => {
+                    debug!("connection from {:?} closed: {}", peer, error);
+                    return;
+                }
+            }
+        }
+    }
+
+    // The dialing end signs a fresh nonce with the key on record for the member it claims to be, another than this one
+    fn authenticate(id: Id, stream: &mut TcpStream, sender: Id, offered: &RangeInclusive<u16>, identities: &Identities) -> io::Result<()> {
+        let key = identities.key(sender).filter(|_| sender != id).ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "not a member"))?;
+        let nonce: [u8; 32] = rand::random();
+        write_handshake_challenge(stream, &nonce)?;
+        let mut signature = [0; 64];
+        stream.read_exact(&mut signature)?;
+        key.verify(handshake_digest(&nonce, sender, id, offered).as_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| io::Error::new(io::ErrorKind::PermissionDenied, "invalid signature"))
+    }
+}
+
+// Outgoing connection to a peer
+#[derive(Debug)]
+struct Connection {
+    id: Id,
+    identities: Identities,
+    peer: Id,
+    address: SocketAddr,
+    supported: RangeInclusive<u16>,
+    versions: Versions,
+    // With the wire version negotiated on it
+    stream: Option<(TcpStream, u16)>,
+    retry_at: Instant,
+}
+
+impl Connection {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        let (stream, version) = match &mut self.stream {
+            Some((stream, version)) => (stream, *version),
+            None if Instant::now() < self.retry_at => return Err(io::ErrorKind::NotConnected.into()),
+            None => {
+                self.retry_at = Instant::now() + RECONNECT_INTERVAL;
+                let connected = self.connect()?;
+                let (stream, version) = self.stream.insert(connected);
+                (stream, *version)
+            }
+        };
+
+        let sent = write_frame(stream, version, message);
+        if sent.is_err() {
+            self.stream = None;
+        }
+        sent
+    }
+
+    fn connect(&mut self) -> io::Result<(TcpStream, u16)> {
+        let mut stream = TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        write_handshake(&mut stream, self.id, &self.supported)?;
+        let nonce = read_handshake_challenge(&mut stream)?;
+        let signature = self.identities.sign_digest(&handshake_digest(&nonce, self.id, self.peer, &self.supported)).ok_or(io::ErrorKind::PermissionDenied)?;
+        stream.write_all(&signature)?;
+        let answer = read_handshake_answer(&mut stream)?;
+        stream.set_read_timeout(None)?;
+
+        self.versions.lock().unwrap().insert(self.peer, answer.clone());
+        match answer {
+            Ok(version) if self.supported.contains(&version) => Ok((stream, version)),
+            Ok(version) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("peer {} picked wire version {}", self.peer, version))),
+            Err(versions) => {
+                warn!("peer {} refused the connection: it supports wire versions {:?}, this process {:?}", self.peer, versions, self.supported);
+                self.retry_at = Instant::now() + INCOMPATIBLE_INTERVAL;
+                Err(io::Error::new(io::ErrorKind::Unsupported, "no wire version in common"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::Ipv4Addr};
+    use ed25519_dalek::SigningKey;
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Config, PreProposal, Process, WIRE_VERSION};
+
+    fn key(member: Id) -> SigningKey {
+        SigningKey::from_bytes(&[member as u8 + 1; 32])
+    }
+
+    // Of a member of a committee of `count`
+    fn identities(id: Id, count: usize) -> Identities {
+        let identities = Identities::default();
+        identities.set(key(id), (0..count as Id).map(|member| (member, key(member).verifying_key())).collect::<HashMap<_, _>>());
+        identities
+    }
+
+    fn listeners(count: usize) -> (Vec<TcpListener>, Vec<SocketAddr>) {
+        let listeners: Vec<TcpListener> = (0..count).map(|_| TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).collect();
+        let addresses = listeners.iter().map(|listener| listener.local_addr().unwrap()).collect();
+        (listeners, addresses)
+    }
+
+    #[test]
+    fn processes_reach_consensus_over_tcp() {
+        let f = 1;
+        let (listeners, addresses) = listeners(3 * f + 1);
+        let config = Config { aggregated_responses: true, ..Config::default() };
+
+        let handles: Vec<_> = listeners
+            .into_iter()
+            .enumerate()
+            .map(|(id, listener)| {
+                let (_, senders, receiver) = TcpTransport::listen(id as Id, listener, &addresses, &identities(id as Id, addresses.len())).unwrap();
+                let mut process = Process::new_with_config(id as Id, f, senders, receiver, false, config);
+                let preproposal = PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id as Id);
+                thread::spawn(move || process.propose(2 * f + 1, preproposal, 0))
+            })
+            .collect();
+
+        let decisions: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert!(decisions.windows(2).all(|pair| pair[0].hash == pair[1].hash), "{decisions:?}");
+    }
+
+    #[test]
+    fn messages_to_unreachable_peers_are_dropped_until_they_listen() {
+        let (mut listeners, addresses) = listeners(2);
+        let peer = listeners.pop().unwrap();
+        let (transport, senders, _receiver) = TcpTransport::listen(0, listeners.pop().unwrap(), &addresses, &identities(0, addresses.len())).unwrap();
+        drop(peer);
+
+        senders[1].send(Message::Ack(0, 1)).unwrap();
+        let started = Instant::now();
+        while transport.dropped() == 0 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (_, _, receiver) = TcpTransport::listen(1, TcpListener::bind(addresses[1]).unwrap(), &addresses, &identities(1, addresses.len())).unwrap();
+        thread::sleep(RECONNECT_INTERVAL);
+        senders[1].send(Message::Ack(0, 2)).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(Message::Ack(0, 2)));
+        assert_eq!(transport.dropped(), 1);
+    }
+
+    #[test]
+    fn peers_of_the_previous_wire_version_are_spoken_to_in_it() {
+        let (mut listeners, addresses) = listeners(2);
+        let old = listeners.pop().unwrap();
+        let (transport, senders, _receiver) = TcpTransport::listen(0, listeners.pop().unwrap(), &addresses, &identities(0, addresses.len())).unwrap();
+        let (_, _, old_receiver) = TcpTransport::listen_supporting(1, old, &addresses, 1..=1, &identities(1, addresses.len())).unwrap();
+
+        senders[1].send(Message::Ack(0, 1)).unwrap();
+        assert_eq!(old_receiver.recv_timeout(Duration::from_secs(5)), Ok(Message::Ack(0, 1)));
+        assert_eq!(transport.versions()[&1], Ok(1));
+    }
+
+    #[test]
+    fn peers_without_a_common_wire_version_are_refused() {
+        let (mut listeners, addresses) = listeners(3);
+        let old = listeners.pop().unwrap();
+        let peer = listeners.pop().unwrap();
+        // A release that no longer speaks version 1
+        let (transport, senders, _receiver) = TcpTransport::listen_supporting(0, listeners.pop().unwrap(), &addresses, 2..=WIRE_VERSION, &identities(0, addresses.len())).unwrap();
+        let (_, _, receiver) = TcpTransport::listen(1, peer, &addresses, &identities(1, addresses.len())).unwrap();
+        let (old, _, old_receiver) = TcpTransport::listen_supporting(2, old, &addresses, 1..=1, &identities(2, addresses.len())).unwrap();
+
+        senders[1].send(Message::Ack(0, 1)).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(Message::Ack(0, 1)));
+        senders[2].send(Message::Ack(0, 1)).unwrap();
+        assert!(old_receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+        assert_eq!(transport.dropped(), 1);
+        let versions = transport.versions();
+        assert_eq!(versions[&1], Ok(WIRE_VERSION));
+        assert_eq!(versions[&2], Err(1..=1));
+        assert_eq!(old.versions()[&0], Err(2..=WIRE_VERSION));
+    }
+
+    #[test]
+    fn versions_this_release_does_not_speak_are_not_listened_for() {
+        let (mut listeners, addresses) = listeners(1);
+        let refused = TcpTransport::listen_supporting(0, listeners.pop().unwrap(), &addresses, 0..=WIRE_VERSION, &identities(0, addresses.len())).map(|_| ());
+        assert_eq!(refused.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn processes_that_cannot_authenticate_are_not_listened_for() {
+        let (mut listeners, addresses) = listeners(2);
+        let verifying = Identities::verifying((0..2).map(|member| (member, key(member).verifying_key())).collect());
+        let refused = TcpTransport::listen(0, listeners.pop().unwrap(), &addresses, &verifying).map(|_| ());
+        assert_eq!(refused.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let refused = TcpTransport::listen(0, listeners.pop().unwrap(), &addresses, &identities(0, 1)).map(|_| ());
+        assert_eq!(refused.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn handshakes_not_signed_by_the_claimed_member_are_refused() {
+        let (mut listeners, addresses) = listeners(3);
+        listeners.truncate(1);
+        let (_, _, receiver) = TcpTransport::listen(0, listeners.pop().unwrap(), &addresses, &identities(0, 3)).unwrap();
+
+        // Member 2 signing for member 1, then a process claiming to be the listener itself
+        for (claimed, signer) in [(1, 2), (0, 0)] {
+            let mut stream = TcpStream::connect(addresses[0]).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            write_handshake(&mut stream, claimed, &SUPPORTED_WIRE_VERSIONS).unwrap();
+            if let Ok(nonce) = read_handshake_challenge(&mut stream) {
+                let signature = identities(signer, 3).sign_digest(&handshake_digest(&nonce, claimed, 0, &SUPPORTED_WIRE_VERSIONS)).unwrap();
+                stream.write_all(&signature).unwrap();
+            }
+            assert!(read_handshake_answer(&mut stream).is_err());
+        }
+
+        let mut stream = TcpStream::connect(addresses[0]).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        write_handshake(&mut stream, 1, &SUPPORTED_WIRE_VERSIONS).unwrap();
+        let nonce = read_handshake_challenge(&mut stream).unwrap();
+        stream.write_all(&identities(1, 3).sign_digest(&handshake_digest(&nonce, 1, 0, &SUPPORTED_WIRE_VERSIONS)).unwrap()).unwrap();
+        assert_eq!(read_handshake_answer(&mut stream).unwrap(), Ok(WIRE_VERSION));
+        write_frame(&mut stream, WIRE_VERSION, &Message::Ack(1, 1)).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(Message::Ack(1, 1)));
+    }
+
+    #[test]
+    fn connections_past_the_cap_are_closed_on_accept() {
+        let (mut listeners, addresses) = listeners(1);
+        let (_, _, _receiver) = TcpTransport::listen(0, listeners.pop().unwrap(), &addresses, &identities(0, 1)).unwrap();
+
+        // Held open without a handshake
+        let _open: Vec<TcpStream> = (0..CONNECTIONS_PER_MEMBER).map(|_| TcpStream::connect(addresses[0]).unwrap()).collect();
+        let mut closed = TcpStream::connect(addresses[0]).unwrap();
+        closed.set_read_timeout(Some(CONNECT_TIMEOUT / 2)).unwrap();
+        assert_eq!(closed.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
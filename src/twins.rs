@@ -0,0 +1,97 @@
+use crate::{Id, PreProposal, SimConfig, SimOutcome, Simulation};
+
+// Twins: the f faulty identities are each run by two honest instances that start from different preproposals,
+// so they equivocate the way a node with a leaked key would, while both follow the protocol.
+// Every way of splitting the instances in two partitions is simulated, since equivocation only hurts when the twins
+// talk to different parts of the committee
+#[derive(Debug, Clone)]
+pub struct TwinsScenario {
+    pub config: SimConfig,
+    pub preproposals: Vec<PreProposal>,
+    // Identities to twin, with the preproposal of their second instance
+    pub twins: Vec<(Id, PreProposal)>,
+}
+
+impl TwinsScenario {
+    pub fn instances(&self) -> usize {
+        self.config.nodes + self.twins.len()
+    }
+
+    // Every split of the instances into two partitions, including the one where nobody is cut off
+    pub fn partitions(&self) -> Vec<Vec<Vec<usize>>> {
+        let instances = self.instances();
+
+        // The last instance always stays in the first partition, so that each split is only listed once
+        (0..1usize << (instances - 1))
+            .map(|mask| {
+                let (second, first): (Vec<usize>, Vec<usize>) = (0..instances).partition(|instance| mask >> instance & 1 == 1);
+                vec![first, second]
+            })
+            .collect()
+    }
+
+    pub fn run(&self, partitions: &[Vec<usize>]) -> SimOutcome {
+        let simulation = self.twins
+            .iter()
+            .fold(Simulation::new(self.config, self.preproposals.clone()), |simulation, (node, preproposal)| {
+                simulation.with_twin(*node, preproposal.clone())
+            });
+
+        simulation.with_partitions(partitions).run()
+    }
+
+    // Runs every partition and returns the outcomes in which two correct instances decided differently
+    pub fn violations(&self) -> Vec<(Vec<Vec<usize>>, SimOutcome)> {
+        self.partitions()
+            .into_iter()
+            .map(|partitions| {
+                let outcome = self.run(&partitions);
+                (partitions, outcome)
+            })
+            .filter(|(_, outcome)| !outcome.agreement())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsnano_core::BlockHash;
+    use super::*;
+
+    fn scenario(seed: u64) -> TwinsScenario {
+        let config = SimConfig { seed, time_limit: 2_000_000, ..SimConfig::default() };
+        let preproposals = (0..config.nodes)
+            .map(|id| PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id as Id))
+            .collect();
+
+        TwinsScenario { config, preproposals, twins: vec![(3, PreProposal::new(vec![BlockHash::from(100)], 3))] }
+    }
+
+    #[test]
+    fn lists_each_split_once() {
+        let partitions = scenario(0).partitions();
+
+        assert_eq!(partitions.len(), 16);
+        assert_eq!(partitions[0], vec![vec![0, 1, 2, 3, 4], vec![]]);
+        assert!(partitions.iter().all(|split| split[0].contains(&4)));
+    }
+
+    #[test]
+    fn agreement_holds_with_twins() {
+        for seed in 0..5 {
+            let violations = scenario(seed).violations();
+
+            assert!(violations.is_empty(), "seed {seed}: {violations:?}");
+        }
+    }
+
+    #[test]
+    fn majority_partition_decides_despite_twin() {
+        // 0, 1 and the first instance of 3 form a quorum, 2 is left with the twin of 3
+        let outcome = scenario(1).run(&[vec![0, 1, 3], vec![2, 4]]);
+
+        assert!(outcome.agreement());
+        assert!([0, 1].iter().all(|&node| outcome.decisions[node].is_some()), "{outcome:?}");
+        assert_eq!(outcome.decisions[2], None);
+    }
+}
@@ -0,0 +1,313 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, io, net::{SocketAddr, UdpSocket}, sync::{atomic::{AtomicU64, Ordering}, mpsc::{channel, Receiver, RecvTimeoutError, Sender}, Arc, Mutex}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use log::{debug, warn};
+use crate::{decode_message, encode_message, Id, Message};
+
+// Largest UDP payload over IPv4
+const MAX_DATAGRAM: usize = 65_507;
+// Kind, sender, session and sequence number
+const HEADER: usize = 1 + 8 + 8 + 8;
+const DATA: u8 = 0;
+const ACK: u8 = 1;
+// A datagram without an acknowledgement is sent again after this long, until it is acknowledged
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(50);
+// Datagrams awaiting an acknowledgement per peer. Past it the oldest one is given up on, so that a peer that is gone
+// does not take the memory of the process with it
+const MAX_UNACKED: usize = 4096;
+// Sequence numbers a receiver remembers above the ones it got all of, the ones below a gap this wide are given up on
+const RECEIVE_WINDOW: usize = 4 * MAX_UNACKED;
+
+// Carries the messages of a process over UDP, for clusters on a LAN where a lost datagram is rarer and cheaper to
+// resend than the round trips and head-of-line blocking of TCP. Every message travels in a datagram of its own, with the
+// sender's sequence number for the receiver: the receiver acknowledges each datagram and drops the ones it already
+// delivered, and the sender sends again those not acknowledged in time. Messages are delivered in the order they
+// arrive, which the protocol does not depend on. Like TcpTransport, it hands out the senders and receiver
+// Process::new_with_config takes
+#[derive(Debug, Clone)]
+pub struct UdpTransport {
+    address: SocketAddr,
+    dropped: Arc<AtomicU64>,
+    retransmitted: Arc<AtomicU64>,
+}
+
+// Datagrams sent to a peer and not acknowledged yet
+#[derive(Debug, Default)]
+struct Unacked {
+    next: u64,
+    datagrams: BTreeMap<u64, (Vec<u8>, Instant)>,
+}
+
+// Sequence numbers received from a peer, in the session they were sent in
+#[derive(Debug, Default)]
+struct Received {
+    session: u64,
+    // Every sequence number below it was received
+    below: u64,
+    above: BTreeSet<u64>,
+}
+
+impl Received {
+    // Whether the datagram was not received before. A peer that restarted sends under a later session, the datagrams
+    // of its earlier sessions that are still around are dropped rather than taken for a restart
+    fn first(&mut self, session: u64, sequence: u64) -> bool {
+        if session < self.session {
+            return false;
+        }
+        if session > self.session {
+            *self = Received { session, ..Received::default() };
+        }
+        if sequence < self.below || !self.above.insert(sequence) {
+            return false;
+        }
+        if self.above.len() > RECEIVE_WINDOW {
+            self.below = *self.above.first().unwrap();
+        }
+        while self.above.remove(&self.below) {
+            self.below += 1;
+        }
+        true
+    }
+}
+
+fn header(kind: u8, sender: Id, session: u64, sequence: u64) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER);
+    datagram.push(kind);
+    datagram.extend_from_slice(&sender.to_le_bytes());
+    datagram.extend_from_slice(&session.to_le_bytes());
+    datagram.extend_from_slice(&sequence.to_le_bytes());
+    datagram
+}
+
+fn parse_header(datagram: &[u8]) -> Option<(u8, Id, u64, u64)> {
+    let field = |at: usize| datagram.get(at..at + 8).map(|bytes| bytes.try_into().unwrap());
+    Some((*datagram.first()?, Id::from_le_bytes(field(1)?), u64::from_le_bytes(field(9)?), u64::from_le_bytes(field(17)?)))
+}
+
+impl UdpTransport {
+    // Binds the address of the process in `addresses`, which has the address of every member by id
+    pub fn bind(id: Id, addresses: &[SocketAddr]) -> io::Result<(UdpTransport, Vec<Sender<Message>>, Receiver<Message>)> {
+        UdpTransport::on_socket(id, UdpSocket::bind(addresses[id as usize])?, addresses)
+    }
+
+    // On a socket bound beforehand, e.g. to a port the system picked. Its own entry of `addresses` is not used
+    pub fn on_socket(id: Id, socket: UdpSocket, addresses: &[SocketAddr]) -> io::Result<(UdpTransport, Vec<Sender<Message>>, Receiver<Message>)> {
+        let address = socket.local_addr()?;
+        let socket = Arc::new(socket);
+        let (inbound, receiver) = channel();
+        let transport = UdpTransport { address, dropped: Arc::default(), retransmitted: Arc::default() };
+        // Tells the datagrams of this run from the ones of an earlier run under the same id
+        let session = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+        let unacked: Vec<Arc<Mutex<Unacked>>> = addresses.iter().map(|_| Arc::default()).collect();
+
+        let (reader, acked, loopback) = (Arc::clone(&socket), unacked.clone(), inbound.clone());
+        let peers: HashMap<Id, SocketAddr> = addresses.iter().enumerate().map(|(peer, address)| (peer as Id, *address)).collect();
+        thread::spawn(move || UdpTransport::read(id, session, &reader, &peers, &acked, &loopback));
+
+        let senders = addresses
+            .iter()
+            .enumerate()
+            .map(|(peer, address)| {
+                // Messages to itself do not leave the process
+                if peer as Id == id {
+                    return inbound.clone();
+                }
+                let (sender, outbound) = channel();
+                let (socket, unacked, address, transport) = (Arc::clone(&socket), Arc::clone(&unacked[peer]), *address, transport.clone());
+                thread::spawn(move || transport.write(id, session, &socket, address, &unacked, outbound));
+                sender
+            })
+            .collect();
+
+        Ok((transport, senders, receiver))
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Messages that never reached their peer: too long for a datagram, or given up on unacknowledged
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // Datagrams sent again for want of an acknowledgement
+    pub fn retransmitted(&self) -> u64 {
+        self.retransmitted.load(Ordering::Relaxed)
+    }
+
+    // Until the process drops its sender
+    fn write(&self, id: Id, session: u64, socket: &UdpSocket, address: SocketAddr, unacked: &Mutex<Unacked>, outbound: Receiver<Message>) {
+        loop {
+            match outbound.recv_timeout(RETRANSMIT_TIMEOUT) {
+                Ok(message) => self.datagrams(message).into_iter().for_each(|payload| self.send(id, session, socket, address, unacked, payload)),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let now = Instant::now();
+            let mut unacked = unacked.lock().unwrap();
+            for (datagram, sent) in unacked.datagrams.values_mut().filter(|(_, sent)| now.duration_since(*sent) >= RETRANSMIT_TIMEOUT) {
+                let _ = socket.send_to(datagram, address);
+                *sent = now;
+                self.retransmitted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn send(&self, id: Id, session: u64, socket: &UdpSocket, address: SocketAddr, unacked: &Mutex<Unacked>, payload: Vec<u8>) {
+        let mut unacked = unacked.lock().unwrap();
+        let sequence = unacked.next;
+        unacked.next += 1;
+
+        let mut datagram = header(DATA, id, session, sequence);
+        datagram.extend_from_slice(&payload);
+        // A failed send is a lost datagram, sent again like one
+        let _ = socket.send_to(&datagram, address);
+        unacked.datagrams.insert(sequence, (datagram, Instant::now()));
+
+        if unacked.datagrams.len() > MAX_UNACKED {
+            unacked.datagrams.pop_first();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Payloads of the message, whose batches are split if they do not fit a datagram
+    fn datagrams(&self, message: Message) -> Vec<Vec<u8>> {
+        let encoded = encode_message(&message);
+        if HEADER + encoded.len() <= MAX_DATAGRAM {
+            return vec![encoded];
+        }
+        match message {
+            Message::Batch(messages) => messages.into_iter().flat_map(|message| self.datagrams(message)).collect(),
+            message => {
+                warn!("dropped a message of {} bytes, too long for a datagram: {:?}", encoded.len(), message.sender());
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Vec::new()
+            }
+        }
+    }
+
+    // Acknowledges the datagrams of the members and delivers each message once, until the process is gone
+    fn read(id: Id, session: u64, socket: &UdpSocket, peers: &HashMap<Id, SocketAddr>, unacked: &[Arc<Mutex<Unacked>>], inbound: &Sender<Message>) {
+        let mut received: HashMap<Id, Received> = HashMap::new();
+        let mut buffer = vec![0; MAX_DATAGRAM];
+
+        loop {
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                // Some platforms report an earlier datagram that was refused here
+                Err(error) if matches!(error.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused) => continue,
+                Err(error) => {
+                    warn!("{}: cannot receive datagrams: {}", id, error);
+                    return;
+                }
+            };
+            let datagram = &buffer[..length];
+
+            // Datagrams must come from the address of the member they name
+            let Some((kind, sender, peer_session, sequence)) = parse_header(datagram).filter(|(_, sender, ..)| peers.get(sender) == Some(&from)) else {
+                debug!("{}: dropped a datagram from {}", id, from);
+                continue;
+            };
+
+            match kind {
+                ACK if peer_session == session => {
+                    if let Some(unacked) = unacked.get(sender as usize) {
+                        unacked.lock().unwrap().datagrams.remove(&sequence);
+                    }
+                }
+                DATA => {
+                    let _ = socket.send_to(&header(ACK, id, peer_session, sequence), from);
+                    if !received.entry(sender).or_default().first(peer_session, sequence) {
+                        continue;
+                    }
+                    match decode_message(&datagram[HEADER..]) {
+                        Ok(message) => {
+                            if inbound.send(message).is_err() {
+                                return;
+                            }
+                        }
+                        Err(error) => debug!("{}: cannot decode a datagram of {}: {:?}", id, sender, error),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Config, PreProposal, Process};
+
+    fn sockets(count: usize) -> (Vec<UdpSocket>, Vec<SocketAddr>) {
+        let sockets: Vec<UdpSocket> = (0..count).map(|_| UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).collect();
+        let addresses = sockets.iter().map(|socket| socket.local_addr().unwrap()).collect();
+        (sockets, addresses)
+    }
+
+    #[test]
+    fn processes_reach_consensus_over_udp() {
+        let f = 1;
+        let (sockets, addresses) = sockets(3 * f + 1);
+        let config = Config { aggregated_responses: true, ..Config::default() };
+
+        let handles: Vec<_> = sockets
+            .into_iter()
+            .enumerate()
+            .map(|(id, socket)| {
+                let (_, senders, receiver) = UdpTransport::on_socket(id as Id, socket, &addresses).unwrap();
+                let mut process = Process::new_with_config(id as Id, f, senders, receiver, false, config);
+                let preproposal = PreProposal::new(vec![BlockHash::from(id as u64 + 1)], id as Id);
+                thread::spawn(move || process.propose(2 * f + 1, preproposal, 0))
+            })
+            .collect();
+
+        let decisions: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert!(decisions.windows(2).all(|pair| pair[0].hash == pair[1].hash), "{decisions:?}");
+    }
+
+    #[test]
+    fn lost_datagrams_are_sent_again_and_delivered_once() {
+        let (mut sockets, addresses) = sockets(2);
+        // Nothing listens on the address of the peer at first, so the first datagram is lost
+        drop(sockets.pop());
+        let (transport, senders, _receiver) = UdpTransport::on_socket(0, sockets.pop().unwrap(), &addresses).unwrap();
+        senders[1].send(Message::Ack(0, 1)).unwrap();
+        thread::sleep(RETRANSMIT_TIMEOUT);
+
+        let (_, _, receiver) = UdpTransport::bind(1, &addresses).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(Message::Ack(0, 1)));
+        assert!(transport.retransmitted() > 0);
+        // Acknowledged, so not sent again
+        thread::sleep(4 * RETRANSMIT_TIMEOUT);
+        assert_eq!(receiver.try_recv().ok(), None);
+        assert_eq!(transport.dropped(), 0);
+    }
+
+    #[test]
+    fn duplicates_are_delivered_once_per_session() {
+        let mut received = Received::default();
+        assert!(received.first(1, 0));
+        assert!(received.first(1, 2));
+        assert!(!received.first(1, 0) && !received.first(1, 2));
+        assert!(received.first(1, 1));
+        assert_eq!((received.below, received.above.len()), (3, 0));
+        // A restarted peer numbers its datagrams from 0 again
+        assert!(received.first(2, 0));
+    }
+
+    #[test]
+    fn datagrams_of_earlier_sessions_are_dropped() {
+        let mut received = Received::default();
+        assert!(received.first(2, 0) && received.first(2, 1));
+        // Retransmitted by the peer before it restarted, they neither get through nor reset the session
+        assert!(!received.first(1, 5));
+        assert!(!received.first(1, 0));
+        assert!(!received.first(2, 1));
+        assert!(received.first(2, 2));
+        assert_eq!((received.session, received.below), (2, 3));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fmt::Debug, sync::{Arc, RwLock}};
+use crate::{ProposalHash, Wakeup};
+
+// Rule of the application on the values that may be decided (external validity), e.g. that a proposal hash resolves
+// to a known, fully validated proposal. Broadcasts of other values are rejected, so correct processes neither answer,
+// adopt nor commit them
+// It must eventually hold at every correct process for the values correct processes propose, or their instances
+// never decide. A predicate that starts to hold for a value on its own, e.g. once the application validated its
+// proposal, is followed by Validity::changed, which wakes a proposer waiting to commit it
+pub trait ValidityPredicate: Send + Sync + Debug {
+    fn valid(&self, value: ProposalHash) -> bool;
+}
+
+// Clones share the predicate, so the one set on a process applies to its proposer and run loop
+#[derive(Debug, Clone, Default)]
+pub struct Validity {
+    predicate: Arc<RwLock<Option<Arc<dyn ValidityPredicate>>>>,
+    // Of the proposer, see Process::validity
+    wakeup: Arc<Wakeup>,
+}
+
+impl Validity {
+    pub fn notifying(wakeup: Arc<Wakeup>) -> Validity {
+        Validity { predicate: Arc::default(), wakeup }
+    }
+
+    pub fn set(&self, predicate: Arc<dyn ValidityPredicate>) {
+        *self.predicate.write().unwrap() = Some(predicate);
+        self.changed();
+    }
+
+    // The predicate may hold for values it did not hold for before
+    pub fn changed(&self) {
+        self.wakeup.notify();
+    }
+
+    // Every value is valid without a predicate
+    pub fn holds(&self, value: ProposalHash) -> bool {
+        self.predicate.read().unwrap().as_ref().is_none_or(|predicate| predicate.valid(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::{AtomicBool, Ordering}, thread, time::Duration};
+    use rsnano_core::BlockHash;
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Validated(AtomicBool);
+
+    impl ValidityPredicate for Validated {
+        fn valid(&self, _: ProposalHash) -> bool {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn changes_wake_up_the_proposer() {
+        let wakeup = Arc::new(Wakeup::default());
+        let validity = Validity::notifying(Arc::clone(&wakeup));
+        let validated = Arc::new(Validated::default());
+        validity.set(Arc::clone(&validated) as Arc<dyn ValidityPredicate>);
+
+        let changed = validity.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            validated.0.store(true, Ordering::Relaxed);
+            changed.changed();
+        });
+        // Without a deadline, only the notification ends the wait
+        assert!(wakeup.wait_until(None, || validity.holds(BlockHash::from(1))));
+        handle.join().unwrap();
+    }
+}
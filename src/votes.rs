@@ -0,0 +1,263 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use rsnano_core::{validate_message, Blake2HashBuilder, BlockHash, PrivateKey, PublicKey, Signature};
+use crate::Id;
+
+// Timestamp of the final votes, as in rsnano: a representative only sends one for a block and never changes it
+pub const FINAL_VOTE_TIMESTAMP: u64 = u64::MAX;
+
+// Hashes a vote may carry, as in rsnano
+pub const MAX_VOTE_HASHES: usize = 255;
+
+// Valid votes kept to answer GetVotes, the oldest ones are dropped first
+const MAX_STORED_VOTES: usize = 1 << 14;
+
+pub type VoteHash = BlockHash;
+
+// How final votes spread past the committee members their voter sent them to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoteGossip {
+    // Only the voter sends its vote
+    #[default]
+    Direct,
+    // Processes also relay the votes that were new to them
+    Relay,
+    // Processes relay the hashes of the votes that were new to them, and peers missing one ask for it, like Nano's
+    // vote-by-hash. Peers that already have the vote only cost a hash
+    RelayByHash,
+}
+
+// Vote of a representative for frontiers, an rsnano vote (rsnano_core::Vote) with the committee member that sent it:
+// signed with Ed25519-Blake2b over the hash of "vote ", the hashes and the timestamp
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Vote {
+    pub voter: Id,
+    // Account key of the voter
+    pub key: [u8; 32],
+    pub timestamp: u64,
+    pub hashes: Vec<BlockHash>,
+    pub signature: [u8; 64],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteError {
+    InvalidSignature,
+    // The voter is not part of the committee
+    UnknownVoter,
+    // The vote is signed by another key than the voter's
+    WrongKey,
+    // More than MAX_VOTE_HASHES hashes
+    TooManyHashes,
+}
+
+impl Vote {
+    // rsnano keeps the duration of a vote in the lowest 4 bits of its timestamp, which are 0 here
+    pub fn new(voter: Id, key: &PrivateKey, timestamp: u64, hashes: Vec<BlockHash>) -> Vote {
+        Vote::from_nano(voter, rsnano_core::Vote::new(key, timestamp, 0, hashes))
+    }
+
+    pub fn new_final(voter: Id, key: &PrivateKey, hashes: Vec<BlockHash>) -> Vote {
+        Vote::from_nano(voter, rsnano_core::Vote::new_final(key, hashes))
+    }
+
+    pub fn from_nano(voter: Id, vote: rsnano_core::Vote) -> Vote {
+        Vote { voter, key: *vote.voting_account.as_bytes(), timestamp: vote.timestamp, hashes: vote.hashes, signature: *vote.signature.as_bytes() }
+    }
+
+    pub fn to_nano(&self) -> rsnano_core::Vote {
+        rsnano_core::Vote {
+            timestamp: self.timestamp,
+            voting_account: PublicKey::from_bytes(self.key),
+            signature: Signature::from_bytes(self.signature),
+            hashes: self.hashes.clone(),
+        }
+    }
+
+    // The hash of rsnano_core::Vote, without copying the hashes into one
+    fn digest(timestamp: u64, hashes: &[BlockHash]) -> BlockHash {
+        let hasher = hashes.iter().fold(Blake2HashBuilder::new().update(b"vote "), |hasher, hash| hasher.update(hash.as_bytes()));
+        hasher.update(timestamp.to_le_bytes()).build()
+    }
+
+    // Covers what is signed as well as the signature, so that a vote altered on the way does not take the hash of the
+    // one it was made from
+    pub fn hash(&self) -> VoteHash {
+        let digest = Vote::digest(self.timestamp, &self.hashes);
+        Blake2HashBuilder::new().update(digest.as_bytes()).update(self.key).update(self.signature).build()
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.timestamp == FINAL_VOTE_TIMESTAMP
+    }
+
+    // Checks the signature against the key the vote carries, whoever that key belongs to
+    pub fn verify(&self) -> bool {
+        let digest = Vote::digest(self.timestamp, &self.hashes);
+        validate_message(&PublicKey::from_bytes(self.key), digest.as_bytes(), &Signature::from_bytes(self.signature)).is_ok()
+    }
+}
+
+// Final votes received per frontier, from which a process builds its preproposal:
+// only frontiers final voted by 2f+1 members of the committee may be preproposed
+#[derive(Debug, Default)]
+pub struct VoteCache {
+    voters: HashMap<Id, [u8; 32]>,
+    votes: HashMap<BlockHash, BTreeSet<Id>>,
+    stored: HashMap<VoteHash, Vote>,
+    stored_order: VecDeque<VoteHash>,
+}
+
+impl VoteCache {
+    // Keys of the members of the committee, votes of anyone else are rejected
+    pub fn set_voters(&mut self, voters: HashMap<Id, PublicKey>) {
+        self.voters = voters.into_iter().map(|(voter, key)| (voter, *key.as_bytes())).collect();
+    }
+
+    // Returns the frontiers the vote was the first final vote of its voter for
+    // Signatures that were already verified (by the validation pool) are not verified again, and neither are the ones of
+    // votes already stored: the signature is only checked once the cheaper checks passed
+    pub fn add(&mut self, vote: &Vote, verified: bool) -> Result<Vec<BlockHash>, VoteError> {
+        match self.voters.get(&vote.voter) {
+            None => return Err(VoteError::UnknownVoter),
+            Some(key) if *key != vote.key => return Err(VoteError::WrongKey),
+            Some(_) => (),
+        }
+        if vote.hashes.len() > MAX_VOTE_HASHES {
+            return Err(VoteError::TooManyHashes);
+        }
+        // The hash covers the signature, so a stored vote with the same hash is the same valid vote
+        if vote.is_final() && self.contains(&vote.hash()) {
+            return Ok(Vec::new());
+        }
+        if !verified && !vote.verify() {
+            return Err(VoteError::InvalidSignature);
+        }
+        if !vote.is_final() {
+            return Ok(Vec::new());
+        }
+        self.store(vote);
+
+        Ok(vote.hashes.iter().filter(|hash| self.votes.entry(**hash).or_default().insert(vote.voter)).copied().collect())
+    }
+
+    fn store(&mut self, vote: &Vote) {
+        let hash = vote.hash();
+        if self.stored.insert(hash, vote.clone()).is_some() {
+            return;
+        }
+
+        self.stored_order.push_back(hash);
+        if self.stored_order.len() > MAX_STORED_VOTES {
+            if let Some(oldest) = self.stored_order.pop_front() {
+                self.stored.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn contains(&self, hash: &VoteHash) -> bool {
+        self.stored.contains_key(hash)
+    }
+
+    // Stored votes among the hashes, for the peers that asked for them
+    pub fn get(&self, hashes: &[VoteHash]) -> Vec<Vote> {
+        hashes.iter().filter_map(|hash| self.stored.get(hash)).cloned().collect()
+    }
+
+    pub fn votes(&self, frontier: &BlockHash) -> usize {
+        self.votes.get(frontier).map_or(0, BTreeSet::len)
+    }
+
+    // Frontiers with at least `threshold` final votes, in order
+    pub fn confirmed(&self, threshold: usize) -> Vec<BlockHash> {
+        let mut confirmed: Vec<BlockHash> = self.votes.iter().filter(|(_, voters)| voters.len() >= threshold).map(|(frontier, _)| *frontier).collect();
+        confirmed.sort();
+        confirmed
+    }
+
+    // Drops the votes of frontiers that no longer need to be preproposed
+    pub fn forget(&mut self, frontiers: &[BlockHash]) {
+        for frontier in frontiers {
+            self.votes.remove(frontier);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rsnano_core::RawKey;
+
+    fn key(voter: Id) -> PrivateKey {
+        PrivateKey::from(RawKey::from_bytes([voter as u8 + 1; 32]))
+    }
+
+    fn cache(voters: Id) -> VoteCache {
+        let mut cache = VoteCache::default();
+        cache.set_voters((0..voters).map(|voter| (voter, key(voter).public_key())).collect());
+        cache
+    }
+
+    #[test]
+    fn votes_are_the_ones_of_rsnano() {
+        // Of the dev network genesis account
+        let key = PrivateKey::from(RawKey::decode_hex("34F0A37AAD20F4A260F0A5B3CB3D7FB50673212263E58A380BC10474BB039CE4").unwrap());
+        assert_eq!(key.public_key().encode_hex(), "B0311EA55708D6A53C75CDBF88300259C6D018522FE3D4D0A242E431F9E8B6D0");
+
+        let vote = Vote::new_final(0, &key, vec![BlockHash::from_bytes([1; 32]), BlockHash::from_bytes([2; 32])]);
+        let hash = "9D0B66E503E42335CA519A0BD70F147BBEDF5733686DE452B60603A85D8F054B";
+        let signature = "AC62BE47A0E72701091F818634A526A5B4313A288A2F58176A5800F321AC7F8946B38B8832FCB271708BFD580F8BCCBB403098CE2494EC31FF89A2CBEB7A7201";
+        assert_eq!(Vote::digest(vote.timestamp, &vote.hashes).encode_hex(), hash);
+        assert_eq!(vote.to_nano().hash().encode_hex(), hash);
+        assert_eq!(vote.signature.iter().map(|byte| format!("{:02X}", byte)).collect::<String>(), signature);
+        assert!(vote.verify() && vote.to_nano().validate().is_ok());
+        assert_eq!(Vote::from_nano(0, vote.to_nano()), vote);
+    }
+
+    #[test]
+    fn signatures_cover_hashes_and_timestamp() {
+        let vote = Vote::new_final(0, &key(0), vec![BlockHash::from(1), BlockHash::from(2)]);
+        assert!(vote.verify());
+        assert!(vote.is_final());
+
+        assert!(!Vote { hashes: vec![BlockHash::from(1)], ..vote.clone() }.verify());
+        assert!(!Vote { timestamp: 7, ..vote.clone() }.verify());
+        assert!(!Vote { key: *key(1).public_key().as_bytes(), ..vote }.verify());
+    }
+
+    #[test]
+    fn aggregates_final_votes_of_the_committee() {
+        let mut cache = cache(4);
+        let frontiers = vec![BlockHash::from(1), BlockHash::from(2)];
+
+        for voter in 0..3 {
+            assert_eq!(cache.add(&Vote::new_final(voter, &key(voter), frontiers[..1].to_vec()), false), Ok(frontiers[..1].to_vec()));
+        }
+        assert_eq!(cache.add(&Vote::new_final(0, &key(0), frontiers.clone()), false), Ok(frontiers[1..].to_vec()));
+        assert_eq!(cache.add(&Vote::new(3, &key(3), 1, frontiers.clone()), false), Ok(vec![]));
+
+        assert_eq!(cache.add(&Vote::new_final(4, &key(4), frontiers.clone()), false), Err(VoteError::UnknownVoter));
+        assert_eq!(cache.add(&Vote::new_final(3, &key(2), frontiers.clone()), false), Err(VoteError::WrongKey));
+        let forged = Vote { hashes: frontiers.clone(), ..Vote::new_final(3, &key(3), vec![]) };
+        assert_eq!(cache.add(&forged, false), Err(VoteError::InvalidSignature));
+        let oversized = Vote::new_final(3, &key(3), (0..=MAX_VOTE_HASHES as u64).map(BlockHash::from).collect());
+        assert_eq!(cache.add(&oversized, false), Err(VoteError::TooManyHashes));
+
+        assert_eq!((cache.votes(&frontiers[0]), cache.votes(&frontiers[1])), (3, 1));
+        assert_eq!(cache.confirmed(3), frontiers[..1].to_vec());
+        cache.forget(&frontiers[..1]);
+        assert_eq!(cache.confirmed(1), frontiers[1..].to_vec());
+    }
+
+    #[test]
+    fn valid_final_votes_are_kept_by_hash() {
+        let mut cache = cache(2);
+        let vote = Vote::new_final(0, &key(0), vec![BlockHash::from(1)]);
+        let forged = Vote { hashes: vec![BlockHash::from(2)], ..vote.clone() };
+        assert_ne!(vote.hash(), Vote::new_final(1, &key(1), vote.hashes.clone()).hash());
+
+        cache.add(&vote, false).unwrap();
+        assert!(cache.add(&forged, false).is_err());
+        assert!(cache.contains(&vote.hash()));
+        assert_eq!(cache.get(&[forged.hash(), vote.hash()]), vec![vote]);
+    }
+}
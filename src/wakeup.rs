@@ -0,0 +1,72 @@
+use std::time::Instant;
+use crate::sync::{Condvar, Mutex};
+
+// Wakes the proposer when the run loop stored something it may be waiting for (a preproposal, proposal, pre-vote or
+// backfilled commits) or the process is stopped, instead of having it poll. Responses have condvars of their own, see
+// ResponseStore
+#[derive(Debug)]
+pub struct Wakeup {
+    // Notifications so far, so that one sent while the waiter checks its condition is not missed
+    generation: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl Default for Wakeup {
+    fn default() -> Self {
+        Wakeup { generation: Mutex::new(0), condvar: Condvar::new() }
+    }
+}
+
+impl Wakeup {
+    pub fn notify(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.condvar.notify_all();
+    }
+
+    // Blocks until `ready` holds or the deadline passes, and returns whether it holds. `ready` is checked again after
+    // every notification, without the lock held
+    pub fn wait_until(&self, deadline: Option<Instant>, mut ready: impl FnMut() -> bool) -> bool {
+        loop {
+            let seen = *self.generation.lock().unwrap();
+            if ready() {
+                return true;
+            }
+
+            let mut generation = self.generation.lock().unwrap();
+            while *generation == seen {
+                generation = match deadline {
+                    Some(deadline) => match deadline.checked_duration_since(Instant::now()).filter(|remaining| !remaining.is_zero()) {
+                        Some(remaining) => self.condvar.wait_timeout(generation, remaining).unwrap().0,
+                        None => return ready(),
+                    },
+                    None => self.condvar.wait(generation).unwrap(),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::Duration};
+    use super::*;
+
+    #[test]
+    fn waiters_wake_up_on_notifications_and_deadlines() {
+        let wakeup = Arc::new(Wakeup::default());
+        let flag = Arc::new(AtomicBool::new(false));
+
+        let (notifier, set) = (Arc::clone(&wakeup), Arc::clone(&flag));
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            set.store(true, Ordering::Relaxed);
+            notifier.notify();
+        });
+        assert!(wakeup.wait_until(None, || flag.load(Ordering::Relaxed)));
+        handle.join().unwrap();
+
+        let started = Instant::now();
+        assert!(!wakeup.wait_until(Some(started + Duration::from_millis(20)), || false));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+}
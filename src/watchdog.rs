@@ -0,0 +1,274 @@
+use std::{fmt, sync::{mpsc::{channel, Receiver, Sender}, Mutex}, time::{Duration, Instant}};
+use log::warn;
+use crate::{Id, Latencies, LatencyMetrics, Rank, Step};
+
+// Where the proposer of a process currently is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    // Not proposing
+    Idle,
+    // Waiting for 2f+1 preproposals
+    PreProposal,
+    // Waiting for 2f+1 responses of the step and rank
+    Step(Step, Rank),
+    Decided(Rank),
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Idle => write!(f, "idle"),
+            Stage::PreProposal => write!(f, "preproposal step"),
+            Stage::Step(step, rank) => write!(f, "{:?} step of rank {}", step, rank),
+            Stage::Decided(rank) => write!(f, "decided at rank {}", rank),
+        }
+    }
+}
+
+// Step transitions of the proposer, read by the watchdog
+// The time spent in each step and from proposing to deciding is recorded on every transition
+#[derive(Debug)]
+pub struct Progress {
+    current: Mutex<(Stage, Instant)>,
+    proposed: Mutex<Option<Instant>>,
+    // First and current rank of the instance being proposed
+    ranks: Mutex<Option<(Rank, Rank)>>,
+    latencies: LatencyMetrics,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Progress::new()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress {
+            current: Mutex::new((Stage::Idle, Instant::now())),
+            proposed: Mutex::new(None),
+            ranks: Mutex::new(None),
+            latencies: LatencyMetrics::default(),
+        }
+    }
+
+    pub fn enter(&self, stage: Stage) {
+        let now = Instant::now();
+        let (previous, since) = std::mem::replace(&mut *self.current.lock().unwrap(), (stage, now));
+
+        if let Stage::Step(step, _) = previous {
+            self.latencies.record_step(step, now - since);
+        }
+        match stage {
+            Stage::PreProposal => *self.proposed.lock().unwrap() = Some(now),
+            Stage::Step(_, rank) => {
+                let mut ranks = self.ranks.lock().unwrap();
+                *ranks = Some((ranks.map_or(rank, |(first, _)| first), rank));
+            }
+            Stage::Decided(_) => {
+                *self.ranks.lock().unwrap() = None;
+                if let Some(proposed) = self.proposed.lock().unwrap().take() {
+                    self.latencies.record_commit(now - proposed);
+                }
+            }
+            Stage::Idle => (),
+        }
+    }
+
+    // Gauge of the ranks the current instance went through without committing, 0 when not proposing
+    pub fn ranks_since_commit(&self) -> Rank {
+        self.ranks.lock().unwrap().map_or(0, |(first, current)| current - first)
+    }
+
+    pub fn latencies(&self) -> Latencies {
+        self.latencies.latencies()
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.current.lock().unwrap().0
+    }
+
+    // The stage the proposer has been waiting in for at least `timeout`, if any
+    // Idle and decided processes are not waiting for anything and never stall
+    pub fn stalled(&self, timeout: Duration) -> Option<(Stage, Duration)> {
+        let (stage, since) = *self.current.lock().unwrap();
+        let elapsed = since.elapsed();
+
+        match stage {
+            Stage::Idle | Stage::Decided(_) => None,
+            _ if elapsed < timeout => None,
+            stage => Some((stage, elapsed)),
+        }
+    }
+}
+
+// What a stalled process was waiting for, and who it was waiting on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StallReport {
+    pub id: Id,
+    pub stage: Stage,
+    pub elapsed: Duration,
+    pub threshold: usize,
+    // Senders of the preproposals or responses received in the stage
+    pub received: Vec<Id>,
+    // Members of the committee that have not sent theirs, some of which are faulty or unreachable
+    pub missing: Vec<Id>,
+}
+
+impl StallReport {
+    pub fn new(id: Id, stage: Stage, elapsed: Duration, threshold: usize, mut received: Vec<Id>, nodes: usize) -> StallReport {
+        received.sort();
+        let missing = (0..nodes as Id).filter(|node| !received.contains(node)).collect();
+
+        StallReport { id, stage, elapsed, threshold, received, missing }
+    }
+}
+
+// Early warnings for monitoring systems, logged and delivered to every subscriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alert {
+    Stalled(StallReport),
+    // The proposer went through more ranks than the threshold without committing, the committee is failing to converge
+    RanksWithoutCommit { id: Id, rank: Rank, ranks: Rank, threshold: Rank },
+    // The suspicion score of the peer, rounded down, reached the threshold, see Suspicion
+    Suspected { id: Id, peer: Id, score: u64 },
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::Stalled(report) => write!(f, "{}", report),
+            Alert::RanksWithoutCommit { id, rank, ranks, threshold } => {
+                write!(f, "{}: {} ranks without committing at rank {}, more than {}", id, ranks, rank, threshold)
+            }
+            Alert::Suspected { id, peer, score } => write!(f, "{}: suspects {} with a score of {}", id, peer, score),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Alerts {
+    subscribers: Mutex<Vec<Sender<Alert>>>,
+}
+
+impl Alerts {
+    pub fn subscribe(&self) -> Receiver<Alert> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    // Subscribers that dropped their receiver are forgotten
+    pub fn emit(&self, alert: Alert) {
+        warn!("{}", alert);
+        self.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(alert.clone()).is_ok());
+    }
+}
+
+impl fmt::Display for StallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: stalled for {:.1?} in {} with {}/{} answers from {:?}, missing {:?}",
+            self.id,
+            self.elapsed,
+            self.stage,
+            self.received.len(),
+            self.threshold,
+            self.received,
+            self.missing
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn only_waiting_stages_stall() {
+        let progress = Progress::new();
+        let timeout = Duration::from_millis(5);
+
+        thread::sleep(timeout);
+        assert_eq!(progress.stalled(timeout), None);
+
+        progress.enter(Stage::Step(Step::A, 2));
+        assert_eq!(progress.stalled(timeout), None);
+
+        thread::sleep(timeout);
+        assert_eq!(progress.stalled(timeout).map(|(stage, _)| stage), Some(Stage::Step(Step::A, 2)));
+
+        progress.enter(Stage::Decided(2));
+        thread::sleep(timeout);
+        assert_eq!(progress.stalled(timeout), None);
+    }
+
+    #[test]
+    fn transitions_record_latencies() {
+        let progress = Progress::new();
+        let wait = Duration::from_millis(2);
+
+        progress.enter(Stage::PreProposal);
+        for step in [Step::R, Step::A, Step::B] {
+            progress.enter(Stage::Step(step, 0));
+            thread::sleep(wait);
+        }
+        progress.enter(Stage::Decided(0));
+
+        let latencies = progress.latencies();
+        for step in [Step::R, Step::A, Step::B] {
+            assert_eq!(latencies.step(step).count, 1);
+            assert!(latencies.step(step).sum >= wait);
+        }
+        assert_eq!(latencies.commit.count, 1);
+        assert!(latencies.commit.sum >= 3 * wait);
+    }
+
+    #[test]
+    fn gauge_counts_ranks_of_the_instance() {
+        let progress = Progress::new();
+
+        progress.enter(Stage::PreProposal);
+        assert_eq!(progress.ranks_since_commit(), 0);
+
+        for rank in 4..7 {
+            for step in [Step::R, Step::A, Step::B] {
+                progress.enter(Stage::Step(step, rank));
+            }
+        }
+        assert_eq!(progress.ranks_since_commit(), 2);
+
+        progress.enter(Stage::Decided(6));
+        assert_eq!(progress.ranks_since_commit(), 0);
+
+        progress.enter(Stage::PreProposal);
+        progress.enter(Stage::Step(Step::R, 0));
+        assert_eq!(progress.ranks_since_commit(), 0);
+    }
+
+    #[test]
+    fn alerts_reach_every_subscriber() {
+        let alerts = Alerts::default();
+        let first = alerts.subscribe();
+        let dropped = alerts.subscribe();
+        drop(dropped);
+
+        let alert = Alert::RanksWithoutCommit { id: 1, rank: 7, ranks: 4, threshold: 3 };
+        alerts.emit(alert.clone());
+
+        assert_eq!(first.try_recv(), Ok(alert));
+        assert_eq!(alerts.subscribers.lock().unwrap().len(), 1);
+        assert_eq!(first.try_recv().map_err(|_| ()), Err(()));
+    }
+
+    #[test]
+    fn report_lists_missing_senders() {
+        let report = StallReport::new(1, Stage::Step(Step::B, 0), Duration::from_secs(3), 3, vec![2, 1], 4);
+
+        assert_eq!(report.received, vec![1, 2]);
+        assert_eq!(report.missing, vec![0, 3]);
+        assert_eq!(report.to_string(), "1: stalled for 3.0s in B step of rank 0 with 2/3 answers from [1, 2], missing [0, 3]");
+    }
+}
@@ -0,0 +1,822 @@
+use std::{io::{self, Read, Write}, ops::RangeInclusive, sync::Arc};
+use rsnano_core::{Blake2HashBuilder, BlockHash};
+use crate::{AValue, BValue, Broadcast, CommitRecord, Committee, Features, FrontierDecodeError, Hello, Id, Message, PreProposal, Proposal, RValue, Rejoin, RejoinGrounds, Response, State, Step, TraceContext, Value, Vote};
+
+// Binary encoding of the messages exchanged between machines: integers little-endian, hashes as their 32 bytes,
+// lists after their length as a u32 and options after a 0 or 1 byte. The hashes identifying broadcasts and responses
+// are computed again by the receiver rather than sent, the ones pointing at other messages (answers, certificate
+// references and hashes) are sent as they are, see Broadcast::compute_hash and Response::hash_value.
+// Every encoding starts with the version of the format it is in, as a u16
+
+// Version messages are encoded in, and the ones this release decodes. A change to the format bumps the version and
+// keeps decoding the previous one, so that a committee can be upgraded a process at a time
+// Version 2 added the committee of checkpoints to commits, which version 1 decodes without and encodes leaving out
+// Version 3 sends the frontiers of preproposals as a list of hashes, which versions 1 and 2 prefix-compressed (see
+// encode_frontiers): that only saved about 1.5% of a preproposal of 100 000 ledger frontiers, which are uniformly
+// distributed, for a decoding pass over every preproposal received
+// Version 4 added the signature of broadcasts, which earlier versions decode without and encode leaving out
+// Version 5 dropped the instance of the unban of a rejoin, members being taken back at whichever instance commits it,
+// which earlier versions decode ignoring and encode as 0
+pub const WIRE_VERSION: u16 = 5;
+pub const SUPPORTED_WIRE_VERSIONS: RangeInclusive<u16> = 1..=WIRE_VERSION;
+
+// Opens the handshake of every connection
+const HANDSHAKE_MAGIC: [u8; 4] = *b"ARCH";
+
+// Longest frame a receiver accepts, so that a peer cannot make it allocate more
+pub const MAX_FRAME: usize = 64 << 20;
+// Batches and instances nest messages, and responses nest the broadcasts they justify
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireError {
+    Truncated,
+    // A tag that names no message, step, value or option
+    InvalidTag(u8),
+    InvalidFrontiers(FrontierDecodeError),
+    InvalidVersion,
+    TooDeep,
+    TrailingBytes,
+    // An encoding in a version of the format this release does not decode
+    UnsupportedWireVersion(u16),
+}
+
+pub fn encode_message(message: &Message) -> Vec<u8> {
+    encode_message_as(message, WIRE_VERSION)
+}
+
+// In a version negotiated with the receiver, which must be a supported one
+pub fn encode_message_as(message: &Message, version: u16) -> Vec<u8> {
+    assert!(SUPPORTED_WIRE_VERSIONS.contains(&version), "wire version {} is not supported", version);
+    let mut writer = Writer { bytes: version.to_le_bytes().to_vec(), version };
+    writer.message(message);
+    writer.bytes
+}
+
+pub fn decode_message(bytes: &[u8]) -> Result<Message, WireError> {
+    let mut reader = Reader { bytes, depth: 0, version: 0 };
+    reader.version = u16::from_le_bytes(reader.array()?);
+    if !SUPPORTED_WIRE_VERSIONS.contains(&reader.version) {
+        return Err(WireError::UnsupportedWireVersion(reader.version));
+    }
+    let message = reader.message()?;
+    if !reader.bytes.is_empty() {
+        return Err(WireError::TrailingBytes);
+    }
+    Ok(message)
+}
+
+// The encoding in the version after its length as a u32, in a single write
+pub fn write_frame(writer: &mut impl Write, version: u16, message: &Message) -> io::Result<()> {
+    let encoded = encode_message_as(message, version);
+    if encoded.len() > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "message longer than a frame"));
+    }
+    let mut frame = Vec::with_capacity(4 + encoded.len());
+    frame.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&encoded);
+    writer.write_all(&frame)
+}
+
+// Only in the version negotiated for the connection
+pub fn read_frame(reader: &mut impl Read, version: u16) -> io::Result<Message> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+    }
+    let mut encoded = vec![0; length];
+    reader.read_exact(&mut encoded)?;
+    if let Some(found) = encoded.get(..2).map(|found| u16::from_le_bytes([found[0], found[1]])).filter(|found| *found != version) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame in wire version {} on a connection of version {}", found, version)));
+    }
+    decode_message(&encoded).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", error)))
+}
+
+// Highest version both ends support
+pub fn negotiate(own: &RangeInclusive<u16>, peer: &RangeInclusive<u16>) -> Option<u16> {
+    let highest = *own.end().min(peer.end());
+    (highest >= *own.start().max(peer.start())).then_some(highest)
+}
+
+// Sent by the end that opened the connection: its id and the versions it supports
+pub fn write_handshake(writer: &mut impl Write, id: Id, versions: &RangeInclusive<u16>) -> io::Result<()> {
+    let mut handshake = HANDSHAKE_MAGIC.to_vec();
+    handshake.extend_from_slice(&id.to_le_bytes());
+    handshake.extend_from_slice(&versions.start().to_le_bytes());
+    handshake.extend_from_slice(&versions.end().to_le_bytes());
+    writer.write_all(&handshake)
+}
+
+pub fn read_handshake(reader: &mut impl Read) -> io::Result<(Id, RangeInclusive<u16>)> {
+    let mut handshake = [0; 16];
+    reader.read_exact(&mut handshake)?;
+    if handshake[..4] != HANDSHAKE_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a handshake"));
+    }
+    let field = |at: usize| u16::from_le_bytes([handshake[at], handshake[at + 1]]);
+    Ok((Id::from_le_bytes(handshake[4..12].try_into().unwrap()), field(12)..=field(14)))
+}
+
+// Answer of the end that accepted the connection to the handshake: a nonce for the dialing end to sign with its key,
+// see handshake_digest, so that a member is only taken for itself and a handshake cannot be replayed
+pub fn write_handshake_challenge(writer: &mut impl Write, nonce: &[u8; 32]) -> io::Result<()> {
+    let mut challenge = HANDSHAKE_MAGIC.to_vec();
+    challenge.extend_from_slice(nonce);
+    writer.write_all(&challenge)
+}
+
+pub fn read_handshake_challenge(reader: &mut impl Read) -> io::Result<[u8; 32]> {
+    let mut challenge = [0; 36];
+    reader.read_exact(&mut challenge)?;
+    if challenge[..4] != HANDSHAKE_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a handshake challenge"));
+    }
+    Ok(challenge[4..].try_into().unwrap())
+}
+
+// Signed by the dialing end, and sent as the 64 bytes of the signature: the nonce, both ends and the versions offered
+pub fn handshake_digest(nonce: &[u8; 32], dialer: Id, acceptor: Id, versions: &RangeInclusive<u16>) -> BlockHash {
+    Blake2HashBuilder::new()
+        .update(b"handshake ")
+        .update(nonce)
+        .update(dialer.to_le_bytes())
+        .update(acceptor.to_le_bytes())
+        .update(versions.start().to_le_bytes())
+        .update(versions.end().to_le_bytes())
+        .build()
+}
+
+// The version picked by the end that accepted the connection, or the versions it supports if none is common, after
+// which it closes the connection
+pub fn write_handshake_answer(writer: &mut impl Write, answer: &Result<u16, RangeInclusive<u16>>) -> io::Result<()> {
+    let mut encoded = HANDSHAKE_MAGIC.to_vec();
+    match answer {
+        Ok(version) => {
+            encoded.push(0);
+            encoded.extend_from_slice(&version.to_le_bytes());
+            encoded.extend_from_slice(&version.to_le_bytes());
+        }
+        Err(versions) => {
+            encoded.push(1);
+            encoded.extend_from_slice(&versions.start().to_le_bytes());
+            encoded.extend_from_slice(&versions.end().to_le_bytes());
+        }
+    }
+    writer.write_all(&encoded)
+}
+
+pub fn read_handshake_answer(reader: &mut impl Read) -> io::Result<Result<u16, RangeInclusive<u16>>> {
+    let mut answer = [0; 9];
+    reader.read_exact(&mut answer)?;
+    let field = |at: usize| u16::from_le_bytes([answer[at], answer[at + 1]]);
+    match (answer[..4] == HANDSHAKE_MAGIC, answer[4]) {
+        (true, 0) => Ok(Ok(field(5))),
+        (true, 1) => Ok(Err(field(5)..=field(7))),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "not a handshake answer")),
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    // Of the encoding, see WIRE_VERSION
+    version: u16,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn hash(&mut self, hash: &BlockHash) {
+        self.raw(hash.as_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.raw(bytes);
+    }
+
+    fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Writer, T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            write(self, value);
+        }
+    }
+
+    fn list<T>(&mut self, items: &[T], mut write: impl FnMut(&mut Writer, &T)) {
+        self.u32(items.len() as u32);
+        items.iter().for_each(|item| write(self, item));
+    }
+
+    fn step(&mut self, step: Step) {
+        self.u8(match step {
+            Step::R => 0,
+            Step::A => 1,
+            Step::B => 2,
+        });
+    }
+
+    fn message(&mut self, message: &Message) {
+        match message {
+            Message::Broadcast(broadcast) => {
+                self.u8(0);
+                self.broadcast(broadcast);
+            }
+            Message::Response(response) => {
+                self.u8(1);
+                self.response(response);
+            }
+            Message::Proposal(proposal) => {
+                self.u8(2);
+                self.i64(proposal.sender);
+                self.list(&proposal.preproposals, Writer::hash);
+            }
+            Message::PreProposal(preproposal) => {
+                self.u8(3);
+                self.i64(preproposal.sender);
+                if self.version >= 3 {
+                    self.list(&preproposal.frontiers().iter().collect::<Vec<_>>(), |writer, frontier| writer.hash(frontier));
+                } else {
+                    self.bytes(&preproposal.encoded_frontiers());
+                }
+            }
+            Message::PreVote(sender, value) => {
+                self.u8(4);
+                self.i64(*sender);
+                self.hash(value);
+            }
+            Message::Batch(messages) => {
+                self.u8(5);
+                self.list(messages, Writer::message);
+            }
+            Message::Sequenced(sender, sequence, messages) => {
+                self.u8(6);
+                self.i64(*sender);
+                self.u64(*sequence);
+                self.list(messages, Writer::message);
+            }
+            Message::Ack(sender, sequence) => {
+                self.u8(7);
+                self.i64(*sender);
+                self.u64(*sequence);
+            }
+            Message::GetResponses(requester, hashes) => {
+                self.u8(8);
+                self.i64(*requester);
+                self.list(hashes, |writer, hash| writer.hash(hash));
+            }
+            Message::Responses(responder, responses) => {
+                self.u8(9);
+                self.i64(*responder);
+                self.list(responses, Writer::response);
+            }
+            Message::GetCertificate(requester, hash) => {
+                self.u8(10);
+                self.i64(*requester);
+                self.hash(hash);
+            }
+            Message::Certificate(responder, certificate) => {
+                self.u8(11);
+                self.i64(*responder);
+                self.list(certificate, Writer::response);
+            }
+            Message::PreProposalDigest(sender, hashes) => {
+                self.u8(12);
+                self.i64(*sender);
+                self.list(hashes, Writer::hash);
+            }
+            Message::GetPreProposals(requester, hashes) => {
+                self.u8(13);
+                self.i64(*requester);
+                self.list(hashes, Writer::hash);
+            }
+            Message::Vote(vote) => {
+                self.u8(14);
+                self.i64(vote.voter);
+                self.raw(&vote.key);
+                self.u64(vote.timestamp);
+                self.list(&vote.hashes, Writer::hash);
+                self.raw(&vote.signature);
+            }
+            Message::VoteHashes(sender, hashes) => {
+                self.u8(15);
+                self.i64(*sender);
+                self.list(hashes, Writer::hash);
+            }
+            Message::GetVotes(requester, hashes) => {
+                self.u8(16);
+                self.i64(*requester);
+                self.list(hashes, Writer::hash);
+            }
+            Message::Announce(sender, instance) => {
+                self.u8(17);
+                self.i64(*sender);
+                self.u64(*instance);
+            }
+            Message::Commits(sender, commits) => {
+                self.u8(18);
+                self.i64(*sender);
+                self.list(commits, Writer::commit);
+            }
+            Message::GetCertificates(requester, instances) => {
+                self.u8(19);
+                self.i64(*requester);
+                self.u64(instances.start);
+                self.u64(instances.end);
+            }
+            Message::Certificates(responder, commits) => {
+                self.u8(20);
+                self.i64(*responder);
+                self.list(commits, Writer::commit);
+            }
+            Message::Hello(hello) => {
+                self.u8(21);
+                self.i64(hello.sender);
+                self.bytes(hello.version.as_bytes());
+                self.u32(hello.features.bits());
+                self.hash(&hello.committee);
+                self.bool(hello.ask);
+            }
+            Message::Rejoin(rejoin) => {
+                self.u8(22);
+                self.i64(rejoin.sender);
+                match rejoin.grounds {
+                    RejoinGrounds::Rekey(key) => {
+                        self.u8(0);
+                        self.raw(&key);
+                    }
+                    RejoinGrounds::Unban => {
+                        self.u8(1);
+                        if self.version < 5 {
+                            self.u64(0);
+                        }
+                    }
+                }
+                self.u64(rejoin.resume);
+                self.raw(&rejoin.signature);
+            }
+            Message::Instance(instance, message) => {
+                self.u8(23);
+                self.u64(*instance);
+                self.message(message);
+            }
+        }
+    }
+
+    fn broadcast(&mut self, broadcast: &Broadcast) {
+        self.i64(broadcast.sender);
+        self.step(broadcast.step);
+        self.hash(&broadcast.value);
+        self.option(broadcast.flag, Writer::bool);
+        self.i64(broadcast.rank);
+        self.option(broadcast.previous_step_responses.as_deref(), |writer, responses| writer.list(responses, Writer::response));
+        self.option(broadcast.certificate_refs.as_ref(), |writer, refs| writer.list(refs, |writer, hash| writer.hash(hash)));
+        self.option(broadcast.certificate_hash.as_ref(), Writer::hash);
+        self.option(broadcast.trace.as_ref(), |writer, trace| {
+            writer.u128(trace.trace_id);
+            writer.u64(trace.span_id);
+        });
+        self.option(broadcast.committee.as_ref(), Writer::hash);
+        if self.version >= 4 {
+            self.option(broadcast.signature.as_ref(), |writer, signature| writer.raw(signature));
+        }
+    }
+
+    fn response(&mut self, response: &Response) {
+        self.i64(response.sender);
+        self.step(response.step);
+        self.i64(response.rank);
+        self.list(&response.state, |writer, state| {
+            match &state.value {
+                Value::RValue(value) => {
+                    writer.u8(0);
+                    writer.i64(value.rank);
+                    writer.hash(&value.value);
+                }
+                Value::AValue(value) => {
+                    writer.u8(1);
+                    writer.hash(&value.0);
+                }
+                Value::BValue(value) => {
+                    writer.u8(2);
+                    writer.hash(&value.value);
+                    writer.bool(value.flag);
+                }
+            }
+            writer.broadcast(&state.broadcast);
+        });
+        self.hash(&response.answers);
+        self.option(response.committee.as_ref(), Writer::hash);
+        self.option(response.signature.as_ref(), |writer, signature| writer.raw(signature));
+    }
+
+    fn commit(&mut self, commit: &CommitRecord) {
+        self.u64(commit.instance);
+        self.i64(commit.rank);
+        self.hash(&commit.value);
+        self.list(&commit.certificate, Writer::response);
+        if self.version >= 2 {
+            self.option(commit.committee.as_ref(), Writer::committee);
+        }
+    }
+
+    fn committee(&mut self, committee: &Committee) {
+        self.u64(committee.f as u64);
+        self.list(&committee.members.iter().collect::<Vec<_>>(), |writer, (member, key)| {
+            writer.i64(**member);
+            writer.raw(*key);
+        });
+        self.option(committee.configuration.as_ref(), Writer::hash);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    depth: usize,
+    // Of the encoding, see WIRE_VERSION
+    version: u16,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], WireError> {
+        if self.bytes.len() < length {
+            return Err(WireError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, WireError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, WireError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Result<i64, WireError> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    fn u128(&mut self) -> Result<u128, WireError> {
+        self.array().map(u128::from_le_bytes)
+    }
+
+    fn bool(&mut self) -> Result<bool, WireError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(WireError::InvalidTag(tag)),
+        }
+    }
+
+    fn hash(&mut self) -> Result<BlockHash, WireError> {
+        self.array().map(BlockHash::from_bytes)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], WireError> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, WireError>) -> Result<Option<T>, WireError> {
+        if self.bool()? { read(self).map(Some) } else { Ok(None) }
+    }
+
+    // Every item takes at least a byte, so a length past the rest of the frame is rejected before allocating for it
+    fn list<T>(&mut self, mut read: impl FnMut(&mut Self) -> Result<T, WireError>) -> Result<Vec<T>, WireError> {
+        let length = self.u32()? as usize;
+        if length > self.bytes.len() {
+            return Err(WireError::Truncated);
+        }
+        (0..length).map(|_| read(self)).collect()
+    }
+
+    fn nested<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, WireError>) -> Result<T, WireError> {
+        if self.depth == MAX_DEPTH {
+            return Err(WireError::TooDeep);
+        }
+        self.depth += 1;
+        let read = read(self);
+        self.depth -= 1;
+        read
+    }
+
+    fn step(&mut self) -> Result<Step, WireError> {
+        match self.u8()? {
+            0 => Ok(Step::R),
+            1 => Ok(Step::A),
+            2 => Ok(Step::B),
+            tag => Err(WireError::InvalidTag(tag)),
+        }
+    }
+
+    fn message(&mut self) -> Result<Message, WireError> {
+        self.nested(|reader| {
+            Ok(match reader.u8()? {
+                0 => Message::Broadcast(reader.broadcast()?),
+                1 => Message::Response(reader.response()?),
+                2 => {
+                    let sender = reader.i64()?;
+                    Message::Proposal(Proposal::new(reader.list(Reader::hash)?, sender))
+                }
+                3 => {
+                    let sender = reader.i64()?;
+                    if reader.version >= 3 {
+                        Message::PreProposal(PreProposal::new(reader.list(Reader::hash)?, sender))
+                    } else {
+                        Message::PreProposal(PreProposal::from_encoded(reader.bytes()?, sender).map_err(WireError::InvalidFrontiers)?)
+                    }
+                }
+                4 => Message::PreVote(reader.i64()?, reader.hash()?),
+                5 => Message::Batch(reader.list(Reader::message)?),
+                6 => Message::Sequenced(reader.i64()?, reader.u64()?, reader.list(Reader::message)?),
+                7 => Message::Ack(reader.i64()?, reader.u64()?),
+                8 => Message::GetResponses(reader.i64()?, reader.list(Reader::hash)?),
+                9 => Message::Responses(reader.i64()?, reader.list(Reader::response)?),
+                10 => Message::GetCertificate(reader.i64()?, reader.hash()?),
+                11 => Message::Certificate(reader.i64()?, Arc::new(reader.list(Reader::response)?.into())),
+                12 => Message::PreProposalDigest(reader.i64()?, reader.list(Reader::hash)?),
+                13 => Message::GetPreProposals(reader.i64()?, reader.list(Reader::hash)?),
+                14 => Message::Vote(Vote {
+                    voter: reader.i64()?,
+                    key: reader.array()?,
+                    timestamp: reader.u64()?,
+                    hashes: reader.list(Reader::hash)?,
+                    signature: reader.array()?,
+                }),
+                15 => Message::VoteHashes(reader.i64()?, reader.list(Reader::hash)?),
+                16 => Message::GetVotes(reader.i64()?, reader.list(Reader::hash)?),
+                17 => Message::Announce(reader.i64()?, reader.u64()?),
+                18 => Message::Commits(reader.i64()?, reader.list(Reader::commit)?),
+                19 => Message::GetCertificates(reader.i64()?, reader.u64()?..reader.u64()?),
+                20 => Message::Certificates(reader.i64()?, reader.list(Reader::commit)?),
+                21 => Message::Hello(Hello {
+                    sender: reader.i64()?,
+                    version: String::from_utf8(reader.bytes()?.to_vec()).map_err(|_| WireError::InvalidVersion)?,
+                    features: Features::from_bits(reader.u32()?),
+                    committee: reader.hash()?,
+                    ask: reader.bool()?,
+                }),
+                22 => {
+                    let sender = reader.i64()?;
+                    let grounds = match reader.u8()? {
+                        0 => RejoinGrounds::Rekey(reader.array()?),
+                        1 if reader.version < 5 => reader.u64().map(|_| RejoinGrounds::Unban)?,
+                        1 => RejoinGrounds::Unban,
+                        tag => return Err(WireError::InvalidTag(tag)),
+                    };
+                    Message::Rejoin(Rejoin { sender, grounds, resume: reader.u64()?, signature: reader.array()? })
+                }
+                23 => Message::Instance(reader.u64()?, Box::new(reader.message()?)),
+                tag => return Err(WireError::InvalidTag(tag)),
+            })
+        })
+    }
+
+    // The hash is computed from the fields rather than read
+    fn broadcast(&mut self) -> Result<Broadcast, WireError> {
+        self.nested(|reader| {
+            let (sender, step, value, flag, rank) = (reader.i64()?, reader.step()?, reader.hash()?, reader.option(Reader::bool)?, reader.i64()?);
+            let certificate = reader.option(|reader| reader.list(Reader::response))?;
+            let mut broadcast = Broadcast::new(sender, step, value, flag, rank, certificate.map(Into::into));
+            broadcast.certificate_refs = reader.option(|reader| reader.list(Reader::hash))?;
+            broadcast.certificate_hash = reader.option(Reader::hash)?;
+            broadcast.trace = reader.option(|reader| Ok(TraceContext { trace_id: reader.u128()?, span_id: reader.u64()? }))?;
+            let mut broadcast = broadcast.pinned(reader.option(Reader::hash)?);
+            if reader.version >= 4 {
+                broadcast.signature = reader.option(Reader::array)?;
+            }
+            Ok(broadcast)
+        })
+    }
+
+    fn response(&mut self) -> Result<Response, WireError> {
+        let (sender, step, rank) = (self.i64()?, self.step()?, self.i64()?);
+        let states = self.list(|reader| {
+            let value = match reader.u8()? {
+                0 => Value::RValue(RValue::new(reader.i64()?, reader.hash()?)),
+                1 => Value::AValue(AValue(reader.hash()?)),
+                2 => Value::BValue(BValue::new(reader.hash()?, reader.bool()?)),
+                tag => return Err(WireError::InvalidTag(tag)),
+            };
+            Ok(State::new(value, Arc::new(reader.broadcast()?)))
+        })?;
+        let mut response = Response::new(sender, step, rank, states).answering(self.hash()?);
+        response.committee = self.option(Reader::hash)?;
+        response.signature = self.option(Reader::array)?;
+        Ok(response)
+    }
+
+    fn commit(&mut self) -> Result<CommitRecord, WireError> {
+        Ok(CommitRecord {
+            instance: self.u64()?,
+            rank: self.i64()?,
+            value: self.hash()?,
+            certificate: self.list(Reader::response)?.into(),
+            committee: if self.version >= 2 { self.option(Reader::committee)? } else { None },
+        })
+    }
+
+    fn committee(&mut self) -> Result<Committee, WireError> {
+        Ok(Committee {
+            f: self.u64()? as usize,
+            members: self.list(|reader| Ok((reader.i64()?, reader.array()?)))?.into_iter().collect(),
+            configuration: self.option(Reader::hash)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::CertificateResponses;
+
+    fn broadcast() -> Broadcast {
+        let header = Arc::new(Broadcast::new(1, Step::A, BlockHash::from(3), None, 2, None));
+        let certificate = (0..3)
+            .map(|sender| {
+                let mut response = Response::new(sender, Step::A, 2, vec![State::new(Value::AValue(AValue(BlockHash::from(3))), header.clone())]).answering(header.hash);
+                response.signature = Some([sender as u8; 64]);
+                response
+            })
+            .collect();
+        let mut broadcast = Broadcast::new(0, Step::B, BlockHash::from(3), Some(true), 2, Some(certificate)).pinned(Some(BlockHash::from(9)));
+        broadcast.trace = Some(TraceContext { trace_id: 7, span_id: 8 });
+        broadcast.signature = Some([4; 64]);
+        broadcast
+    }
+
+    #[test]
+    fn messages_survive_the_encoding() {
+        let broadcast = broadcast();
+        let messages = vec![
+            Message::Instance(4, Box::new(Message::Batch(vec![Message::Broadcast(broadcast.clone()), Message::Broadcast(broadcast.clone().into_reference())]))),
+            Message::Certificate(2, broadcast.previous_step_responses.clone().unwrap()),
+            Message::PreProposal(PreProposal::new(vec![BlockHash::from(1), BlockHash::from(2)], 3)),
+            Message::Proposal(Proposal::new(vec![BlockHash::from(5)], 1)),
+            Message::GetCertificates(0, 3..9),
+            Message::Hello(Hello { sender: 1, version: "0.1.0".to_string(), features: Features::PINNED_COMMITTEE | Features::PRE_VOTES, committee: BlockHash::from(4), ask: true }),
+            Message::Rejoin(Rejoin { sender: 2, grounds: RejoinGrounds::Rekey([5; 32]), resume: 6, signature: [7; 64] }),
+            Message::Commits(3, vec![CommitRecord {
+                instance: 4,
+                rank: 2,
+                value: BlockHash::from(3),
+                certificate: (*broadcast.previous_step_responses.clone().unwrap()).clone(),
+                committee: Some(Committee { f: 1, members: BTreeMap::from([(0, [1; 32]), (1, [2; 32])]), configuration: Some(BlockHash::from(9)) }),
+            }]),
+        ];
+
+        for message in messages {
+            let decoded = decode_message(&encode_message(&message)).unwrap();
+            assert_eq!(decoded, message);
+            assert_eq!(encode_message(&decoded), encode_message(&message));
+        }
+
+        let mut frames = Vec::new();
+        write_frame(&mut frames, WIRE_VERSION, &Message::Broadcast(broadcast.clone())).unwrap();
+        write_frame(&mut frames, WIRE_VERSION, &Message::Ack(1, 2)).unwrap();
+        let mut reader = frames.as_slice();
+        assert_eq!(read_frame(&mut reader, WIRE_VERSION).unwrap(), Message::Broadcast(broadcast));
+        assert_eq!(read_frame(&mut reader, WIRE_VERSION).unwrap(), Message::Ack(1, 2));
+    }
+
+    #[test]
+    fn malformed_encodings_are_rejected() {
+        let encoded = encode_message(&Message::Broadcast(broadcast()));
+        assert_eq!(decode_message(&encoded[..encoded.len() - 1]), Err(WireError::Truncated));
+        assert_eq!(decode_message(&[encoded.as_slice(), &[0]].concat()), Err(WireError::TrailingBytes));
+        assert_eq!(decode_message(&[2, 0, 24]), Err(WireError::InvalidTag(24)));
+        // A list claiming more items than there are bytes left
+        assert_eq!(decode_message(&[2, 0, 5, 255, 255, 255, 255]), Err(WireError::Truncated));
+        assert_eq!(decode_message(&[6, 0, 7]), Err(WireError::UnsupportedWireVersion(6)));
+        assert_eq!(decode_message(&[0, 0, 7]), Err(WireError::UnsupportedWireVersion(0)));
+
+        assert_eq!(decode_message(&[2]), Err(WireError::Truncated));
+
+        let nested = (0..MAX_DEPTH).fold(Message::Ack(0, 0), |message, _| Message::Instance(0, Box::new(message)));
+        assert_eq!(decode_message(&encode_message(&nested)), Err(WireError::TooDeep));
+    }
+
+    #[test]
+    fn version_1_encodings_are_decoded_and_sent() {
+        // A Commits message as a release of version 1 encodes it: the commit ends with its certificate
+        let mut old = vec![1, 0, 18];
+        old.extend_from_slice(&3i64.to_le_bytes());
+        old.extend_from_slice(&1u32.to_le_bytes());
+        old.extend_from_slice(&4u64.to_le_bytes());
+        old.extend_from_slice(&2i64.to_le_bytes());
+        old.extend_from_slice(BlockHash::from(3).as_bytes());
+        old.extend_from_slice(&0u32.to_le_bytes());
+        let commit = CommitRecord { instance: 4, rank: 2, value: BlockHash::from(3), certificate: CertificateResponses::new(), committee: None };
+        assert_eq!(decode_message(&old), Ok(Message::Commits(3, vec![commit.clone()])));
+
+        // The committee of a checkpoint is left out for a peer of version 1
+        let committee = Committee { f: 1, members: BTreeMap::from([(0, [1; 32])]), configuration: Some(BlockHash::from(9)) };
+        let checkpoint = CommitRecord { committee: Some(committee), ..commit.clone() };
+        assert_eq!(encode_message_as(&Message::Commits(3, vec![checkpoint.clone()]), 1), old);
+        assert_eq!(decode_message(&encode_message(&Message::Commits(3, vec![checkpoint.clone()]))), Ok(Message::Commits(3, vec![checkpoint])));
+
+        // Messages that did not change are the same in both versions but for the version
+        let broadcast = Message::Broadcast(broadcast());
+        let pre_vote = Message::PreVote(1, BlockHash::from(2));
+        assert_eq!(encode_message_as(&pre_vote, 1)[2..], encode_message(&pre_vote)[2..]);
+        let mut frames = Vec::new();
+        write_frame(&mut frames, 1, &broadcast).unwrap();
+        assert_eq!(read_frame(&mut frames.as_slice(), 1).unwrap(), broadcast);
+        // A frame in another version than the one of the connection, even a supported one
+        assert_eq!(read_frame(&mut frames.as_slice(), WIRE_VERSION).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn broadcasts_are_signed_from_version_4() {
+        let signature = |encoded: Vec<u8>| match decode_message(&encoded) {
+            Ok(Message::Broadcast(broadcast)) => broadcast.signature,
+            decoded => panic!("{decoded:?}"),
+        };
+
+        assert_eq!(signature(encode_message(&Message::Broadcast(broadcast()))), Some([4; 64]));
+        assert_eq!(signature(encode_message_as(&Message::Broadcast(broadcast()), 3)), None);
+    }
+
+    #[test]
+    fn version_4_unbans_carry_an_instance() {
+        let unban = Message::Rejoin(Rejoin { sender: 2, grounds: RejoinGrounds::Unban, resume: 6, signature: [7; 64] });
+        let encoded = encode_message_as(&unban, 4);
+        assert_eq!(encoded.len(), encode_message(&unban).len() + 8);
+        assert_eq!(decode_message(&encoded), Ok(unban));
+    }
+
+    #[test]
+    fn version_2_preproposals_are_prefix_compressed() {
+        let preproposal = Message::PreProposal(PreProposal::new((1..=3).map(BlockHash::from).collect(), 3));
+
+        // Tag, sender and the number of frontiers, then the frontiers as they are
+        assert_eq!(encode_message(&preproposal).len(), 2 + 1 + 8 + 4 + 3 * 32);
+        let old = encode_message_as(&preproposal, 2);
+        assert!(old.len() < 2 + 1 + 8 + 4 + 3 * 32);
+        assert_eq!(decode_message(&old), Ok(preproposal));
+    }
+
+    #[test]
+    fn handshakes_settle_on_the_highest_common_version() {
+        assert_eq!(negotiate(&(1..=3), &(2..=5)), Some(3));
+        assert_eq!(negotiate(&(1..=1), &(1..=4)), Some(1));
+        assert_eq!(negotiate(&(1..=2), &(3..=4)), None);
+
+        let mut exchanged = Vec::new();
+        write_handshake(&mut exchanged, 3, &(1..=2)).unwrap();
+        write_handshake_challenge(&mut exchanged, &[9; 32]).unwrap();
+        write_handshake_answer(&mut exchanged, &Ok(2)).unwrap();
+        write_handshake_answer(&mut exchanged, &Err(3..=4)).unwrap();
+        let mut reader = exchanged.as_slice();
+        assert_eq!(read_handshake(&mut reader).unwrap(), (3, 1..=2));
+        assert_eq!(read_handshake_challenge(&mut reader).unwrap(), [9; 32]);
+        assert_eq!(read_handshake_answer(&mut reader).unwrap(), Ok(2));
+        assert_eq!(read_handshake_answer(&mut reader).unwrap(), Err(3..=4));
+        // A peer of a release without handshakes sends a frame right away
+        assert!(read_handshake(&mut [0u8; 16].as_slice()).is_err());
+
+        // The signature covers the nonce, both ends and the versions
+        let digest = handshake_digest(&[9; 32], 3, 0, &(1..=2));
+        assert_ne!(digest, handshake_digest(&[8; 32], 3, 0, &(1..=2)));
+        assert_ne!(digest, handshake_digest(&[9; 32], 3, 1, &(1..=2)));
+        assert_ne!(digest, handshake_digest(&[9; 32], 3, 0, &(1..=1)));
+    }
+}
@@ -0,0 +1,135 @@
+use std::{sync::{mpsc::{channel, Receiver, Sender}, Arc}, thread};
+use crate::{ConsensusMetrics, Identities, MemorySize, Message, PeerAccounting, Probe, Process, Profiler, Rank, ValueOrder};
+
+// What the workers check messages against, the same as the run loop of the process
+#[derive(Debug, Clone, Default)]
+pub struct ValidationRules {
+    pub f: usize,
+    pub forced_adopt_ranks: Rank,
+    pub order: ValueOrder,
+    pub identities: Identities,
+}
+
+impl ValidationRules {
+    pub fn new(f: usize) -> ValidationRules {
+        ValidationRules { f, ..ValidationRules::default() }
+    }
+}
+
+// Validates inbound messages on a pool of threads before they reach the run loop
+// All messages of a sender are handled by the same worker, so their relative order is preserved
+pub struct ValidationPool;
+
+impl ValidationPool {
+    // Returns the receiver of the messages that passed validation, the others are counted as received and invalid,
+    // and rejected broadcasts by reason
+    pub fn spawn(
+        workers: usize,
+        rules: ValidationRules,
+        accounting: Arc<PeerAccounting>,
+        consensus: Arc<ConsensusMetrics>,
+        profiler: Profiler,
+        receiver: Receiver<Message>,
+    ) -> Receiver<Message> {
+        let (verified_sender, verified_receiver) = channel();
+        let mut worker_senders: Vec<Sender<Message>> = Vec::new();
+
+        for _ in 0..workers.max(1) {
+            let (worker_sender, worker_receiver) = channel::<Message>();
+            let verified_sender = verified_sender.clone();
+            let accounting = Arc::clone(&accounting);
+            let consensus = Arc::clone(&consensus);
+            let rules = rules.clone();
+            let profiler = profiler.clone();
+            worker_senders.push(worker_sender);
+
+            thread::spawn(move || {
+                for message in worker_receiver {
+                    let is_valid = profiler.time(Probe::Validation, || match &message {
+                        Message::Broadcast(broadcast) => Process::broadcast_rejection(broadcast, rules.f, rules.forced_adopt_ranks, &rules.order, &rules.identities)
+                            .inspect(|reason| consensus.rejected(*reason))
+                            .is_none(),
+                        message => Process::message_rejection(message, rules.f, rules.forced_adopt_ranks, &rules.order, &rules.identities)
+                            .inspect(|stage| consensus.dropped(*stage))
+                            .is_none(),
+                    });
+
+                    if !is_valid {
+                        if let Some(sender) = message.sender() {
+                            accounting.received(sender, message.memory_size());
+                            accounting.invalid(sender);
+                        }
+                    } else if verified_sender.send(message).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        thread::spawn(move || {
+            for received in receiver {
+                for message in received.into_messages() {
+                    let worker = message.sender().unwrap_or(0).rem_euclid(worker_senders.len() as i64) as usize;
+
+                    if worker_senders[worker].send(message).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        verified_receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rsnano_core::BlockHash;
+    use super::*;
+    use crate::{Broadcast, Rejection, Step};
+
+    #[test]
+    fn drops_invalid_broadcasts() {
+        let (sender, receiver) = channel();
+        let accounting = Arc::new(PeerAccounting::default());
+        let consensus = Arc::new(ConsensusMetrics::default());
+        let verified = ValidationPool::spawn(2, ValidationRules::new(1), Arc::clone(&accounting), Arc::clone(&consensus), Profiler::default(), receiver);
+
+        let invalid = Broadcast::new(0, Step::A, BlockHash::from(1), None, 0, None);
+        let valid = Broadcast::new(0, Step::R, BlockHash::from(1), None, 0, None);
+
+        sender.send(Message::Broadcast(invalid)).unwrap();
+        sender.send(Message::Broadcast(valid.clone())).unwrap();
+
+        assert_eq!(verified.recv_timeout(Duration::from_secs(5)).unwrap(), Message::Broadcast(valid));
+        assert!(verified.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(accounting.peer(0).invalid, 1);
+        assert_eq!(consensus.stats().rejected, [(Rejection::MissingCertificate, 1)].into());
+    }
+
+    #[test]
+    fn preserves_per_sender_order() {
+        let (sender, receiver) = channel();
+        let verified = ValidationPool::spawn(4, ValidationRules::new(1), Arc::default(), Arc::default(), Profiler::default(), receiver);
+
+        for value in 0..100 {
+            let batch = (0..4)
+                .map(|id| Message::Broadcast(Broadcast::new(id, Step::R, BlockHash::from(value), None, 0, None)))
+                .collect();
+            sender.send(Message::Batch(batch)).unwrap();
+        }
+
+        let mut next = [0u64; 4];
+        for _ in 0..400 {
+            match verified.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Message::Broadcast(broadcast) => {
+                    let id = broadcast.sender as usize;
+                    assert_eq!(broadcast.value, BlockHash::from(next[id]));
+                    next[id] += 1;
+                }
+                message => panic!("Unexpected message {:?}", message),
+            }
+        }
+    }
+}